@@ -0,0 +1,3 @@
+-- Presence privacy: who can see this user's live status
+ALTER TABLE users ADD COLUMN IF NOT EXISTS presence_privacy TEXT NOT NULL DEFAULT 'everyone'
+    CHECK (presence_privacy IN ('everyone', 'friends_only'));
@@ -0,0 +1,10 @@
+-- Expiring custom status: cleared by a worker once expires_at passes
+ALTER TABLE users ADD COLUMN IF NOT EXISTS custom_status_expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_users_custom_status_expires
+    ON users(custom_status_expires_at) WHERE custom_status_expires_at IS NOT NULL;
+
+-- Last-seen: recorded when a user's last connection closes (if the instance
+-- enables it), never for users who hide it
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS hide_last_seen BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,44 @@
+-- Multi-device E2EE: each device has its own identity key, signed prekey and
+-- one-time prekeys, and sender key distributions are addressed per device.
+-- The key columns on `users` keep mirroring the user's primary (oldest)
+-- device for clients that predate devices.
+
+CREATE TABLE IF NOT EXISTS devices (
+    id                  UUID PRIMARY KEY,
+    user_id             UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name                TEXT,
+    identity_key        BYTEA NOT NULL,
+    signed_prekey       BYTEA NOT NULL,
+    signed_prekey_sig   BYTEA NOT NULL,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at          TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_devices_user ON devices(user_id, created_at);
+
+-- Every existing user gets a primary device holding their current keys
+INSERT INTO devices (id, user_id, identity_key, signed_prekey, signed_prekey_sig, created_at, updated_at)
+SELECT gen_random_uuid(), id, identity_key, signed_prekey, signed_prekey_sig, created_at, updated_at
+FROM users;
+
+-- One-time prekeys belong to a device
+ALTER TABLE prekeys ADD COLUMN device_id UUID REFERENCES devices(id) ON DELETE CASCADE;
+UPDATE prekeys p SET device_id = d.id FROM devices d WHERE d.user_id = p.user_id;
+ALTER TABLE prekeys ALTER COLUMN device_id SET NOT NULL;
+
+DROP INDEX IF EXISTS idx_prekeys_user_unused;
+CREATE INDEX idx_prekeys_device_unused ON prekeys(device_id, used) WHERE used = false;
+
+-- SKDMs are encrypted to one device of the recipient
+ALTER TABLE sender_key_distributions ADD COLUMN to_device_id UUID REFERENCES devices(id) ON DELETE CASCADE;
+UPDATE sender_key_distributions s SET to_device_id = d.id FROM devices d WHERE d.user_id = s.to_user_id;
+ALTER TABLE sender_key_distributions ALTER COLUMN to_device_id SET NOT NULL;
+
+ALTER TABLE sender_key_distributions
+    DROP CONSTRAINT IF EXISTS sender_key_distributions_channel_id_from_user_id_to_user_id_key;
+ALTER TABLE sender_key_distributions
+    ADD CONSTRAINT sender_key_distributions_device_unique
+    UNIQUE (channel_id, from_user_id, to_device_id, distribution_id);
+
+DROP INDEX IF EXISTS idx_skdm_to_user_channel;
+CREATE INDEX idx_skdm_to_device_channel ON sender_key_distributions(to_device_id, channel_id);
@@ -0,0 +1,3 @@
+-- Signal-style last-resort prekey: handed out in place of a one-time prekey
+-- once a device has run out, and never consumed.
+ALTER TABLE devices ADD COLUMN last_resort_prekey BYTEA;
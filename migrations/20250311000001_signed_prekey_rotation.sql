@@ -0,0 +1,16 @@
+-- Signed prekeys carry an ID and creation time, and the key a rotation
+-- replaces is retained until a grace deadline so X3DH handshakes started
+-- against it can still complete.
+
+ALTER TABLE devices ADD COLUMN signed_prekey_id INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE devices ADD COLUMN signed_prekey_created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+ALTER TABLE devices ADD COLUMN previous_signed_prekey_id INTEGER;
+ALTER TABLE devices ADD COLUMN previous_signed_prekey BYTEA;
+ALTER TABLE devices ADD COLUMN previous_signed_prekey_sig BYTEA;
+ALTER TABLE devices ADD COLUMN previous_signed_prekey_expires_at TIMESTAMPTZ;
+
+-- Existing keys were last written when the device was
+UPDATE devices SET signed_prekey_created_at = updated_at;
+
+CREATE INDEX idx_devices_previous_spk_expiry ON devices(previous_signed_prekey_expires_at)
+    WHERE previous_signed_prekey_expires_at IS NOT NULL;
@@ -0,0 +1,16 @@
+-- Append-only key transparency log of identity key publications. `seq` is
+-- the entry's position in the Merkle tree; `prev_hash` is the leaf hash of
+-- the same user's previous entry, chaining each user's key history. Rows are
+-- never updated or deleted, so user_id/device_id carry no foreign keys.
+
+CREATE TABLE IF NOT EXISTS key_transparency_log (
+    seq             BIGINT PRIMARY KEY,
+    user_id         UUID NOT NULL,
+    device_id       UUID NOT NULL,
+    identity_key    BYTEA NOT NULL,
+    prev_hash       BYTEA,
+    leaf_hash       BYTEA NOT NULL,
+    published_at    TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_key_transparency_log_user ON key_transparency_log(user_id, seq);
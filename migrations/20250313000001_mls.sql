@@ -0,0 +1,52 @@
+-- MLS (RFC 9420) delivery service. Channels opt in by creating an MLS group;
+-- the server stores opaque KeyPackages, Welcomes and handshake messages and
+-- orders each group's handshakes, but never sees group secrets.
+
+CREATE TABLE IF NOT EXISTS mls_key_packages (
+    id              UUID PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id       UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    key_package     BYTEA NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_mls_key_packages_device ON mls_key_packages(device_id, created_at);
+
+-- A channel in MLS mode. `epoch` is the group's current epoch (bumped by each
+-- accepted commit); `next_seq` numbers the channel's handshake messages.
+CREATE TABLE IF NOT EXISTS mls_groups (
+    channel_id      UUID PRIMARY KEY REFERENCES channels(id) ON DELETE CASCADE,
+    epoch           BIGINT NOT NULL DEFAULT 0,
+    next_seq        BIGINT NOT NULL DEFAULT 0,
+    created_by      UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS mls_handshake_messages (
+    channel_id          UUID NOT NULL REFERENCES mls_groups(channel_id) ON DELETE CASCADE,
+    seq                 BIGINT NOT NULL,
+    epoch               BIGINT NOT NULL,   -- epoch the message was sent in
+    kind                TEXT NOT NULL,     -- 'commit' or 'proposal'
+    -- The log must stay gapless for members catching up, so deleting a sender
+    -- only forgets who sent the message
+    sender_user_id      UUID REFERENCES users(id) ON DELETE SET NULL,
+    sender_device_id    UUID REFERENCES devices(id) ON DELETE SET NULL,
+    message             BYTEA NOT NULL,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (channel_id, seq)
+);
+
+-- One row per device a commit's Welcome is addressed to; deleted once acknowledged
+CREATE TABLE IF NOT EXISTS mls_welcomes (
+    id              UUID PRIMARY KEY,
+    channel_id      UUID NOT NULL REFERENCES mls_groups(channel_id) ON DELETE CASCADE,
+    to_user_id      UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    to_device_id    UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    epoch           BIGINT NOT NULL,   -- epoch the Welcome joins
+    welcome         BYTEA NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_mls_welcomes_device ON mls_welcomes(to_device_id, created_at);
@@ -0,0 +1,17 @@
+-- Per-contact identity verification state, synced across a user's devices.
+-- `encrypted_state` is an opaque blob encrypted client-side (like key backups).
+-- `verified_keys_hash` fingerprints the contact's identity keys as they were
+-- verified, so the server can flag later key changes; NULL when the state
+-- records no verification.
+
+CREATE TABLE IF NOT EXISTS verification_states (
+    user_id             UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    contact_id          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    encrypted_state     BYTEA NOT NULL,
+    nonce               BYTEA NOT NULL,
+    verified_keys_hash  BYTEA,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at          TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    PRIMARY KEY (user_id, contact_id)
+);
@@ -0,0 +1,48 @@
+-- Versioned key backups with independently wrapped key slots and
+-- incremental per-session entries.
+--
+-- key_backups now holds one row per backup version instead of one per user.
+-- `version` is assigned by the server and only ever grows; older versions are
+-- pruned down to key_backup_versions_retained. A version is either a
+-- passphrase-encrypted monolithic blob (`salt` set, the original format) or a
+-- manifest encrypted under a random backup key, which one or more key slots
+-- wrap. Per-session entries are encrypted under the same backup key.
+
+ALTER TABLE key_backups DROP CONSTRAINT IF EXISTS key_backups_user_id_key;
+ALTER TABLE key_backups ADD CONSTRAINT key_backups_user_version UNIQUE (user_id, version);
+ALTER TABLE key_backups ALTER COLUMN salt DROP NOT NULL;
+
+CREATE TABLE IF NOT EXISTS key_backup_slots (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    backup_id       UUID NOT NULL REFERENCES key_backups(id) ON DELETE CASCADE,
+    kind            TEXT NOT NULL CHECK (kind IN ('passphrase', 'recovery_key', 'passkey_prf')),
+    wrapped_key     BYTEA NOT NULL,
+    nonce           BYTEA NOT NULL,
+    salt            BYTEA,          -- Argon2id salt (passphrase) or PRF input (passkey_prf)
+    credential_id   BYTEA,          -- WebAuthn credential ID (passkey_prf only)
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_key_backup_slots_backup ON key_backup_slots(backup_id);
+
+CREATE TABLE IF NOT EXISTS key_backup_sessions (
+    backup_id       UUID NOT NULL REFERENCES key_backups(id) ON DELETE CASCADE,
+    session_id      TEXT NOT NULL,
+    encrypted_data  BYTEA NOT NULL,
+    nonce           BYTEA NOT NULL,
+    updated_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (backup_id, session_id)
+);
+
+-- Every download of backup material, shown to the owner.
+CREATE TABLE IF NOT EXISTS key_backup_access_log (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    version         INT NOT NULL,
+    action          TEXT NOT NULL,  -- 'download' or 'sessions'
+    ip_address      TEXT,
+    device_name     TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_key_backup_access_log_user ON key_backup_access_log(user_id, created_at DESC);
@@ -0,0 +1,6 @@
+-- Sealed-sender messages store no sender_id. Edits and deletes are
+-- authorized by a per-message capability token instead: the sender keeps the
+-- token and the server only stores its SHA-256.
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS capability_hash BYTEA;
+
+CREATE INDEX IF NOT EXISTS idx_messages_capability ON messages(capability_hash) WHERE capability_hash IS NOT NULL;
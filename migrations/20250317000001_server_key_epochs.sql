@@ -0,0 +1,23 @@
+-- Server key epochs for encrypted server and channel metadata.
+--
+-- Clients used to share one server key out of band, so a removed member kept
+-- reading every later metadata update. `key_epoch` is the server's current
+-- epoch; rotating uploads the new key wrapped for each remaining member and
+-- bumps it. Each metadata blob records the epoch it was encrypted under, and
+-- wraps for old epochs are kept so earlier blobs stay readable. Epoch 0 is the
+-- original out-of-band key and has no wraps.
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS key_epoch INT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS meta_epoch INT NOT NULL DEFAULT 0;
+ALTER TABLE channels ADD COLUMN IF NOT EXISTS meta_epoch INT NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS server_key_wraps (
+    server_id       UUID NOT NULL REFERENCES servers(id) ON DELETE CASCADE,
+    epoch           INT NOT NULL,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    encrypted_key   BYTEA NOT NULL,     -- server key wrapped to the member's identity key
+    distributed_by  UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (server_id, epoch, user_id)
+);
+
+CREATE INDEX idx_server_key_wraps_user ON server_key_wraps(server_id, user_id);
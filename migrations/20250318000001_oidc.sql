@@ -0,0 +1,28 @@
+-- OpenID Connect single sign-on.
+--
+-- oidc_identities links an issuer-scoped subject to a Haven account: either
+-- the account was provisioned on first SSO login, or its owner linked it.
+-- oidc_login_states holds in-flight authorization requests (state, nonce and
+-- PKCE verifier) until the callback consumes them.
+
+CREATE TABLE IF NOT EXISTS oidc_identities (
+    id              UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    issuer          TEXT NOT NULL,
+    subject         TEXT NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_login_at   TIMESTAMPTZ,
+    UNIQUE (issuer, subject),
+    UNIQUE (user_id, issuer)
+);
+
+CREATE TABLE IF NOT EXISTS oidc_login_states (
+    state           TEXT PRIMARY KEY,
+    code_verifier   TEXT NOT NULL,
+    nonce           TEXT NOT NULL,
+    link_user_id    UUID REFERENCES users(id) ON DELETE CASCADE,  -- set when linking, not logging in
+    expires_at      TIMESTAMPTZ NOT NULL,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_oidc_login_states_expires ON oidc_login_states(expires_at);
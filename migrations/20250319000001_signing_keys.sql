@@ -0,0 +1,21 @@
+-- Asymmetric access-token signing keys and versioned email hash keys.
+--
+-- jwt_signing_keys is the keyring every instance signs and verifies with.
+-- The private half is PKCS#8, encrypted with the storage key. A key signs
+-- from activates_at (it is published in the JWKS before that) and is dropped
+-- after retires_at, once every token it signed has expired.
+
+CREATE TABLE IF NOT EXISTS jwt_signing_keys (
+    kid             TEXT PRIMARY KEY,
+    algorithm       TEXT NOT NULL,          -- 'EdDSA' or 'ES256'
+    private_key     BYTEA NOT NULL,
+    public_key      BYTEA NOT NULL,         -- raw Ed25519 key or uncompressed P-256 point
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    activates_at    TIMESTAMPTZ NOT NULL,
+    retires_at      TIMESTAMPTZ
+);
+
+CREATE INDEX idx_jwt_signing_keys_activates ON jwt_signing_keys(activates_at);
+
+-- Which email hash key made users.email_hash. NULL: the legacy jwt_secret.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_hash_key_id TEXT;
@@ -0,0 +1,25 @@
+-- Per-user security event history and the devices each user has signed in from.
+--
+-- login_devices holds the parsed device name ("Firefox on Linux") of every
+-- successful sign-in, so a login from a device name not seen before is
+-- flagged as a new_device_login security event.
+
+CREATE TABLE IF NOT EXISTS security_events (
+    id              UUID PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind            TEXT NOT NULL,          -- 'new_device_login', 'account_locked', 'password_changed'
+    device_name     TEXT,
+    ip_address      TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_security_events_user ON security_events(user_id, created_at DESC);
+CREATE INDEX idx_security_events_created ON security_events(created_at);
+
+CREATE TABLE IF NOT EXISTS login_devices (
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_name     TEXT NOT NULL,
+    first_seen_at   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen_at    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, device_name)
+);
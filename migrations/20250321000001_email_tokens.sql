@@ -0,0 +1,25 @@
+-- Opt-in email verification and password reset.
+--
+-- Users still store only an HMAC hash of their address. A verification token
+-- carries the hash of the address it was sent to; confirming it marks that
+-- hash as the user's verified email. Password resets are only sent to
+-- verified addresses. Tokens are stored as SHA-256 hashes and consumed on use.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMPTZ;
+
+-- One account per verified address (under the current email hash key)
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_verified_email
+    ON users(email_hash) WHERE email_verified_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS email_tokens (
+    token_hash          TEXT PRIMARY KEY,
+    user_id             UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose             TEXT NOT NULL,      -- 'verify_email', 'password_reset'
+    email_hash          TEXT,               -- verify_email: the address being verified
+    email_hash_key_id   TEXT,
+    expires_at          TIMESTAMPTZ NOT NULL,
+    created_at          TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_email_tokens_user ON email_tokens(user_id, purpose);
+CREATE INDEX idx_email_tokens_expires ON email_tokens(expires_at);
@@ -0,0 +1,23 @@
+-- QR-code device linking.
+--
+-- A new client starts a link with an ephemeral X25519 public key and shows
+-- it in a QR code. A signed-in session that scans the code approves the
+-- link, leaving a provisioning message encrypted to that key. The new
+-- client, polling with a secret only it holds, then collects the message
+-- and a fresh session. Rows are short-lived and deleted once collected.
+
+CREATE TABLE IF NOT EXISTS device_links (
+    id                      UUID PRIMARY KEY,
+    token_hash              TEXT NOT NULL,          -- SHA-256 of the new client's polling secret
+    ephemeral_public_key    BYTEA NOT NULL,
+    device_name             TEXT,
+    ip_address              TEXT,
+    approved_by             UUID REFERENCES users(id) ON DELETE CASCADE,
+    approver_public_key     BYTEA,
+    provisioning_message    BYTEA,                  -- opaque to the server
+    approved_at             TIMESTAMPTZ,
+    expires_at              TIMESTAMPTZ NOT NULL,
+    created_at              TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_device_links_expires ON device_links(expires_at);
@@ -0,0 +1,21 @@
+-- Personal API tokens for scripts and integrations.
+--
+-- A user creates a named token with a set of scopes ('messages:read',
+-- 'messages:send', 'server:<uuid>:manage') and an optional expiry. The
+-- plaintext is shown once; only its SHA-256 hash is stored. token_prefix
+-- keeps the first characters so the user can tell tokens apart.
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id              UUID PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name            TEXT NOT NULL,
+    token_hash      TEXT NOT NULL UNIQUE,
+    token_prefix    TEXT NOT NULL,
+    scopes          TEXT NOT NULL,          -- space-separated, like OAuth scopes
+    expires_at      TIMESTAMPTZ,            -- NULL: never expires
+    last_used_at    TIMESTAMPTZ,
+    last_used_ip    TEXT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_tokens_user ON api_tokens(user_id, created_at DESC);
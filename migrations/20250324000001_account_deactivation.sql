@@ -0,0 +1,12 @@
+-- Account deactivation and scheduled deletion.
+--
+-- A deactivated account has its profile hidden and its sessions revoked;
+-- signing in again reactivates it. Deletion deactivates the account and
+-- sets deletion_scheduled_at to the end of a grace period, after which a
+-- background worker erases it. Signing in before then cancels the deletion.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deactivated_at TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_scheduled_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_users_deletion_scheduled
+    ON users(deletion_scheduled_at) WHERE deletion_scheduled_at IS NOT NULL;
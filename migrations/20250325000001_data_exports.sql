@@ -0,0 +1,20 @@
+-- Personal data exports.
+--
+-- A user asks for an archive of everything the server holds about them; a
+-- background job assembles it into Storage under storage_key and marks the
+-- row 'ready'. The archive can be downloaded until expires_at, after which
+-- a worker deletes it along with the row.
+
+CREATE TABLE IF NOT EXISTS data_exports (
+    id              UUID PRIMARY KEY,
+    user_id         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status          TEXT NOT NULL DEFAULT 'pending',  -- 'pending', 'ready', 'failed'
+    storage_key     TEXT,                   -- set once the archive is stored
+    size_bytes      BIGINT,
+    created_at      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at    TIMESTAMPTZ,
+    expires_at      TIMESTAMPTZ             -- set once ready
+);
+
+CREATE INDEX idx_data_exports_user ON data_exports(user_id, created_at DESC);
+CREATE INDEX idx_data_exports_expires ON data_exports(expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,5 @@
+-- Store OIDC issuers without a trailing slash, the form the server now
+-- looks them up and unlinks them in. Identities linked before this were
+-- stored exactly as the provider's discovery document spelled the issuer.
+
+UPDATE oidc_identities SET issuer = RTRIM(issuer, '/') WHERE issuer LIKE '%/';
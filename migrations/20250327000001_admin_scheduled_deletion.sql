@@ -0,0 +1,7 @@
+-- Deletions scheduled by an instance admin.
+--
+-- A user's own deletion request is cancelled by signing in again during the
+-- grace period. One scheduled by an admin is moderation, so sign-in is
+-- refused until the account is erased.
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deletion_requested_by_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,35 @@
+-- Stored Merkle tree nodes for the key transparency log.
+--
+-- Node (level, idx) is the hash of the complete subtree over leaves
+-- idx * 2^level .. (idx + 1) * 2^level; level 0 holds the leaf hashes.
+-- Appending an entry stores its leaf and every subtree it completes, so
+-- tree heads and audit paths are read from O(log n) nodes instead of
+-- rehashing the whole log.
+
+CREATE TABLE IF NOT EXISTS key_transparency_nodes (
+    level           SMALLINT NOT NULL,
+    idx             BIGINT NOT NULL,
+    hash            BYTEA NOT NULL,
+    PRIMARY KEY (level, idx)
+);
+
+-- Backfill from the existing log, one level at a time
+INSERT INTO key_transparency_nodes (level, idx, hash)
+SELECT 0, seq, leaf_hash FROM key_transparency_log
+ON CONFLICT DO NOTHING;
+
+DO $$
+DECLARE
+    lvl SMALLINT := 1;
+BEGIN
+    LOOP
+        INSERT INTO key_transparency_nodes (level, idx, hash)
+        SELECT lvl, l.idx / 2, sha256('\x01'::bytea || l.hash || r.hash)
+        FROM key_transparency_nodes l
+        JOIN key_transparency_nodes r ON r.level = l.level AND r.idx = l.idx + 1
+        WHERE l.level = lvl - 1 AND l.idx % 2 = 0
+        ON CONFLICT DO NOTHING;
+        EXIT WHEN NOT FOUND;
+        lvl := lvl + 1;
+    END LOOP;
+END $$;
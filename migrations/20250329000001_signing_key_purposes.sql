@@ -0,0 +1,10 @@
+-- Keyrings for the server's other signatures.
+--
+-- jwt_signing_keys now holds one keyring per purpose: 'access_token' for
+-- JWTs and 'tree_head' for key transparency tree heads, which were signed
+-- with a key derived from jwt_secret. Each purpose rotates on its own.
+
+ALTER TABLE jwt_signing_keys ADD COLUMN IF NOT EXISTS purpose TEXT NOT NULL DEFAULT 'access_token';
+
+DROP INDEX IF EXISTS idx_jwt_signing_keys_activates;
+CREATE INDEX idx_jwt_signing_keys_purpose ON jwt_signing_keys(purpose, activates_at);
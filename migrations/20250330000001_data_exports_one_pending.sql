@@ -0,0 +1,19 @@
+-- At most one pending export per user.
+--
+-- The application checked for a pending export before inserting one, which
+-- let two concurrent requests both start a job. The partial unique index
+-- makes the insert itself the check.
+
+UPDATE data_exports SET status = 'failed', completed_at = NOW(), expires_at = NOW()
+WHERE status = 'pending'
+  AND id NOT IN (
+      SELECT DISTINCT ON (user_id) id FROM data_exports
+      WHERE status = 'pending'
+      ORDER BY user_id, created_at DESC
+  );
+
+CREATE UNIQUE INDEX idx_data_exports_one_pending ON data_exports(user_id) WHERE status = 'pending';
+
+-- Archives are now sealed in chunks; ones stored whole before this can't be
+-- streamed back, so let the purge worker remove them.
+UPDATE data_exports SET expires_at = NOW() WHERE status = 'ready';
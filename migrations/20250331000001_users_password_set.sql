@@ -0,0 +1,7 @@
+-- Whether the account's owner has ever chosen a password.
+--
+-- Accounts provisioned through SSO get a random password nobody knows. Until
+-- the owner sets one (for example by resetting it by email), unlinking their
+-- only SSO identity would leave no way to sign in.
+
+ALTER TABLE users ADD COLUMN password_set BOOLEAN NOT NULL DEFAULT TRUE;
@@ -84,6 +84,26 @@ describe("auth", () => {
     expect(api.currentAccessToken).toBe("at-1");
   });
 
+  it("login solves a PoW challenge when the server asks for one", async () => {
+    fetchMock
+      .mockResolvedValueOnce(
+        mockResponse({ pow_required: true, challenge: { challenge: "login-challenge", difficulty: 0 } }),
+      )
+      .mockResolvedValueOnce(
+        mockResponse({ access_token: "at-2", refresh_token: "rt-2", user: { id: "uuid-1", username: "alice" } }),
+      );
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    await api.login({ username: "alice", password: "secret" });
+
+    expect(fetchMock).toHaveBeenCalledTimes(2);
+    const retry = JSON.parse(fetchMock.mock.calls[1][1].body);
+    expect(retry.username).toBe("alice");
+    expect(retry.pow_challenge).toBe("login-challenge");
+    expect(typeof retry.pow_nonce).toBe("string");
+    expect(api.currentAccessToken).toBe("at-2");
+  });
+
   it("register sends POST and sets tokens", async () => {
     // First call: getChallenge()
     const challengeResponse = { challenge: "test-challenge", difficulty: 0 };
@@ -127,6 +147,129 @@ describe("auth", () => {
 
     expect(api.currentAccessToken).toBeNull();
   });
+
+  it("oidcCallback sets tokens on login but not on link", async () => {
+    fetchMock
+      .mockResolvedValueOnce(mockResponse({
+        access_token: "at-sso",
+        refresh_token: "rt-sso",
+        user: { id: "uuid-3", username: "carol" },
+      }))
+      .mockResolvedValueOnce(mockResponse({ linked: true, issuer: "https://idp", subject: "s1" }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    await api.oidcCallback({ code: "c1", state: "st1" });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/oidc/callback");
+    expect(opts.method).toBe("POST");
+    expect(JSON.parse(opts.body)).toEqual({ code: "c1", state: "st1" });
+    expect(api.currentAccessToken).toBe("at-sso");
+
+    const linked = await api.oidcCallback({ code: "c2", state: "st2" });
+    expect("linked" in linked && linked.linked).toBe(true);
+    expect(api.currentAccessToken).toBe("at-sso");
+  });
+
+  it("pollDeviceLink sets tokens only once linked", async () => {
+    fetchMock
+      .mockResolvedValueOnce(mockResponse({ pending: true }))
+      .mockResolvedValueOnce(mockResponse({
+        access_token: "at-link",
+        refresh_token: "rt-link",
+        user: { id: "uuid-4", username: "dave" },
+        approver_public_key: "apk",
+        provisioning_message: "msg",
+      }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    const pending = await api.pollDeviceLink("link-1", "secret");
+    expect(pending).toEqual({ pending: true });
+    expect(api.currentAccessToken).toBeNull();
+
+    const linked = await api.pollDeviceLink("link-1", "secret");
+    const [url, opts] = fetchMock.mock.calls[1];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/link/link-1/poll");
+    expect(JSON.parse(opts.body)).toEqual({ link_token: "secret" });
+    expect("provisioning_message" in linked && linked.provisioning_message).toBe("msg");
+    expect(api.currentAccessToken).toBe("at-link");
+  });
+
+  it("confirmPasswordReset sends the token and new password", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ message: "Password reset" }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    await api.confirmPasswordReset("tok", "new-password");
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/password-reset/confirm");
+    expect(opts.method).toBe("POST");
+    expect(JSON.parse(opts.body)).toEqual({ token: "tok", new_password: "new-password" });
+  });
+
+  it("getSecurityEvents pages with before", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse([
+      { id: "e1", kind: "new_device_login", device_name: "Firefox on Linux", ip_address: "203.0.x.x", created_at: "2025-03-20T00:00:00Z" },
+    ]));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("at", "rt");
+    const events = await api.getSecurityEvents({ limit: 20, before: "2025-03-21T00:00:00Z" });
+
+    const [url] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/security-events?limit=20&before=2025-03-21T00%3A00%3A00Z");
+    expect(events[0].kind).toBe("new_device_login");
+  });
+
+  it("createApiToken posts the name, scopes and expiry", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({
+      id: "tok-1",
+      name: "ci",
+      token_prefix: "hvn_pat_AbCd",
+      scopes: ["messages:read"],
+      expires_at: null,
+      last_used_at: null,
+      last_used_ip: null,
+      created_at: "2025-03-23T00:00:00Z",
+      token: "hvn_pat_AbCdEf",
+    }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("at", "rt");
+    const created = await api.createApiToken({ name: "ci", scopes: ["messages:read"], expires_in_days: 30 });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/tokens");
+    expect(opts.method).toBe("POST");
+    expect(JSON.parse(opts.body)).toEqual({ name: "ci", scopes: ["messages:read"], expires_in_days: 30 });
+    expect(created.token).toBe("hvn_pat_AbCdEf");
+  });
+
+  it("deleteAccount returns when the deletion takes effect", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ deletion_scheduled_at: "2025-04-23T00:00:00Z" }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("at", "rt");
+    const res = await api.deleteAccount("password");
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/auth/delete-account");
+    expect(JSON.parse(opts.body)).toEqual({ password: "password" });
+    expect(res.deletion_scheduled_at).toBe("2025-04-23T00:00:00Z");
+  });
+
+  it("requestDataExport starts an export of the current user", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ id: "e1", status: "pending", size_bytes: null }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("at", "rt");
+    const res = await api.requestDataExport();
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/users/@me/export");
+    expect(opts.method).toBe("POST");
+    expect(res.status).toBe("pending");
+  });
 });
 
 // ── Auth header ─────────────────────────────────────────
@@ -230,6 +373,20 @@ describe("servers", () => {
       encrypted_meta: "dGVzdA==",
     });
   });
+
+  it("distributeServerKeys sends PUT with epoch and wraps", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ epoch: 1, distributed: 1 }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    const wraps = [{ user_id: "u1", encrypted_key: "a2V5" }];
+    await api.distributeServerKeys("s1", { epoch: 1, wraps });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/servers/s1/key-epochs");
+    expect(opts.method).toBe("PUT");
+    expect(JSON.parse(opts.body)).toEqual({ epoch: 1, wraps });
+  });
 });
 
 // ── Channel endpoints ───────────────────────────────────
@@ -710,6 +867,18 @@ describe("DMs", () => {
     expect(url).toBe("http://localhost:8080/api/v1/users/dm-privacy");
     expect(opts.method).toBe("PUT");
   });
+
+  it("updatePresencePrivacy sends PUT", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse(null));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.updatePresencePrivacy({ presence_privacy: "friends_only" });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/users/presence-privacy");
+    expect(opts.method).toBe("PUT");
+  });
 });
 
 // ── Channel members ─────────────────────────────────────
@@ -1085,6 +1254,18 @@ describe("keys", () => {
     expect(bundle.identity_key).toBe("key1");
   });
 
+  it("getKeyTransparencyLog sends GET", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ tree_head: { tree_size: 1 }, entries: [] }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    const log = await api.getKeyTransparencyLog("u1");
+
+    const [url] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/users/u1/keys/log");
+    expect(log.tree_head.tree_size).toBe(1);
+  });
+
   it("uploadPreKeys sends POST", async () => {
     fetchMock.mockResolvedValueOnce(mockResponse(null));
 
@@ -1109,6 +1290,47 @@ describe("keys", () => {
     expect(result.count).toBe(42);
   });
 
+  it("uploadLastResortPreKey sends PUT", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse(null));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.uploadLastResortPreKey({ prekey: "lr" });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/keys/last-resort");
+    expect(opts.method).toBe("PUT");
+  });
+
+  it("registerDevice sends POST", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ id: "d2", name: "Laptop" }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    const device = await api.registerDevice({
+      name: "Laptop",
+      identity_key: "ik",
+      signed_prekey: "spk",
+      signed_prekey_signature: "sig",
+    });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/keys/devices");
+    expect(opts.method).toBe("POST");
+    expect(device.id).toBe("d2");
+  });
+
+  it("getSenderKeys selects a device", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse([]));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.getSenderKeys("ch1", "d2");
+
+    const [url] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/channels/ch1/sender-keys?device_id=d2");
+  });
+
   it("updateKeys sends PUT", async () => {
     fetchMock.mockResolvedValueOnce(mockResponse(null));
 
@@ -1124,6 +1346,82 @@ describe("keys", () => {
     expect(url).toBe("http://localhost:8080/api/v1/keys/identity");
     expect(opts.method).toBe("PUT");
   });
+
+  it("updateKeys forwards the signed prekey ID", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse(null));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.updateKeys({
+      identity_key: "key",
+      signed_prekey: "rotated-spk",
+      signed_prekey_signature: "sig",
+      signed_prekey_id: 7,
+    });
+
+    const [, opts] = fetchMock.mock.calls[0];
+    expect(JSON.parse(opts.body).signed_prekey_id).toBe(7);
+  });
+
+  it("sendMlsHandshake posts to the channel's handshake stream", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ seq: 0, epoch: 0, kind: "commit" }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    const res = await api.sendMlsHandshake("ch1", { epoch: 0, kind: "commit", message: "bXNn" });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/channels/ch1/mls/handshake");
+    expect(opts.method).toBe("POST");
+    expect(JSON.parse(opts.body).epoch).toBe(0);
+    expect(res.seq).toBe(0);
+  });
+
+  it("uploadBackupSessions targets a backup version", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ ok: true, count: 1 }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.uploadBackupSessions(3, [
+      { session_id: "peer-a", encrypted_data: "ZGF0YQ==", nonce: "bm9uY2U=" },
+    ]);
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/keys/backup/sessions");
+    expect(opts.method).toBe("PUT");
+    const body = JSON.parse(opts.body);
+    expect(body.version).toBe(3);
+    expect(body.sessions[0].session_id).toBe("peer-a");
+  });
+
+  it("uploadVerificationState sends PUT", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse({ contact_id: "u2", verified: true, verified_key_changed: false }));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    const res = await api.uploadVerificationState("u2", {
+      encrypted_state: "c3RhdGU=",
+      nonce: "bm9uY2U=",
+      verified_identity_keys: ["key1"],
+    });
+
+    const [url, opts] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/keys/verifications/u2");
+    expect(opts.method).toBe("PUT");
+    expect(JSON.parse(opts.body).verified_identity_keys).toEqual(["key1"]);
+    expect(res.verified).toBe(true);
+  });
+
+  it("getMlsHandshakes passes the cursor", async () => {
+    fetchMock.mockResolvedValueOnce(mockResponse([]));
+
+    const api = new HavenApi({ baseUrl: "http://localhost:8080" });
+    api.setTokens("tok", "rt");
+    await api.getMlsHandshakes("ch1", { after: 4 });
+
+    const [url] = fetchMock.mock.calls[0];
+    expect(url).toBe("http://localhost:8080/api/v1/channels/ch1/mls/handshake?after=4");
+  });
 });
 
 // ── Messages ────────────────────────────────────────────
@@ -12,7 +12,13 @@ import type {
   TotpVerifyRequest,
   KeyBundle,
   UploadPreKeysRequest,
+  DeviceResponse,
+  RegisterDeviceRequest,
+  UploadLastResortPreKeyRequest,
   PreKeyCountResponse,
+  SenderCertificate,
+  SignedTreeHead,
+  KeyTransparencyLog,
   CreateServerRequest,
   ServerResponse,
   CreateChannelRequest,
@@ -29,6 +35,12 @@ import type {
   DistributeSenderKeyRequest,
   SenderKeyDistributionResponse,
   ChannelMemberKeyInfo,
+  UploadKeyPackagesRequest,
+  ClaimedKeyPackages,
+  MlsGroupResponse,
+  SendMlsHandshakeRequest,
+  MlsHandshakeResponse,
+  MlsWelcomeResponse,
   PresenceEntry,
   UpdateKeysRequest,
   ReactionGroup,
@@ -51,6 +63,7 @@ import type {
   FriendRequestBody,
   DmRequestAction,
   UpdateDmPrivacyRequest,
+  UpdatePresencePrivacyRequest,
   ChangePasswordRequest,
   CreateGroupDmRequest,
   ChannelMemberInfo,
@@ -61,8 +74,17 @@ import type {
   VoiceTokenResponse,
   VoiceParticipant,
   UploadKeyBackupRequest,
+  UploadKeyBackupResponse,
+  KeyBackupSlotRequest,
+  KeyBackupSlot,
+  KeyBackupVersionInfo,
+  BackupSessionEntry,
+  BackupSessionResponse,
+  KeyBackupAccess,
   KeyBackupResponse,
   KeyBackupStatusResponse,
+  UploadVerificationStateRequest,
+  VerificationStateResponse,
   CustomEmojiResponse,
   PowChallengeResponse,
   AuditLogEntry,
@@ -71,12 +93,22 @@ import type {
   ReadStateResponse,
   ChannelUnreadInfo,
   AdminStats,
+  SigningKeyPurpose,
+  SigningKeyResponse,
+  EmailHashKeyUsage,
   AdminUserResponse,
   SetAdminRequest,
   InviteRequiredResponse,
   RegistrationInviteResponse,
   GifSearchResponse,
   SessionResponse,
+  SecurityEventResponse,
+  ApiTokenResponse,
+  CreateApiTokenRequest,
+  CreateApiTokenResponse,
+  AccountDeletionResponse,
+  DataExportResponse,
+  EmailStatusResponse,
 } from "../types.js";
 
 export interface ApiClientOptions {
@@ -142,14 +174,64 @@ export class HavenApi {
     return res;
   }
 
+  /**
+   * Log in. While failed logins spike the server asks for a PoW solution;
+   * it is solved and the login resent automatically.
+   */
   async login(req: LoginRequest): Promise<LoginResponse> {
-    const res = await this.post<LoginResponse>("/api/v1/auth/login", req);
+    let res = await this.post<LoginResponse>("/api/v1/auth/login", req);
+    if ("pow_required" in res) {
+      const { challenge, difficulty } = res.challenge;
+      const nonce = await solvePoW(challenge, difficulty);
+      res = await this.post<LoginResponse>("/api/v1/auth/login", {
+        ...req,
+        pow_challenge: challenge,
+        pow_nonce: nonce,
+      });
+    }
     if (isLoginSuccess(res)) {
       this.setTokens(res.access_token, res.refresh_token);
     }
     return res;
   }
 
+  /** Which sign-in methods this instance accepts. */
+  async getLoginMethods(): Promise<import("../types.js").LoginMethodsResponse> {
+    return this.get("/api/v1/auth/methods");
+  }
+
+  /** Start an SSO login; redirect the browser to `authorization_url`. */
+  async oidcAuthorize(): Promise<import("../types.js").OidcAuthorizeResponse> {
+    return this.get("/api/v1/auth/oidc/authorize");
+  }
+
+  /**
+   * Finish an SSO login or link with the code and state from the redirect.
+   * A link must be finished signed in as the account that started it.
+   */
+  async oidcCallback(
+    req: import("../types.js").OidcCallbackRequest,
+  ): Promise<import("../types.js").OidcCallbackResponse> {
+    const res = await this.post<import("../types.js").OidcCallbackResponse>("/api/v1/auth/oidc/callback", req);
+    if ("access_token" in res) {
+      this.setTokens(res.access_token, res.refresh_token);
+    }
+    return res;
+  }
+
+  /** Start linking the current account to an SSO identity. */
+  async startOidcLink(): Promise<import("../types.js").OidcAuthorizeResponse> {
+    return this.post("/api/v1/auth/oidc/link", {});
+  }
+
+  async listOidcIdentities(): Promise<import("../types.js").OidcIdentity[]> {
+    return this.get("/api/v1/auth/oidc/identities");
+  }
+
+  async unlinkOidc(): Promise<void> {
+    await this.delete("/api/v1/auth/oidc/link");
+  }
+
   async refresh(): Promise<AuthResponse> {
     if (!this.refreshToken) throw new Error("No refresh token");
     const req: RefreshRequest = { refresh_token: this.refreshToken };
@@ -187,6 +269,83 @@ export class HavenApi {
     await this.delete(`/api/v1/auth/sessions/${familyId}`);
   }
 
+  async getSecurityEvents(opts?: { limit?: number; before?: string }): Promise<SecurityEventResponse[]> {
+    const params = new URLSearchParams();
+    if (opts?.limit) params.set("limit", String(opts.limit));
+    if (opts?.before) params.set("before", opts.before);
+    const qs = params.toString();
+    return this.get<SecurityEventResponse[]>(`/api/v1/auth/security-events${qs ? `?${qs}` : ""}`);
+  }
+
+  async getApiTokens(): Promise<ApiTokenResponse[]> {
+    return this.get<ApiTokenResponse[]>("/api/v1/auth/tokens");
+  }
+
+  async createApiToken(req: CreateApiTokenRequest): Promise<CreateApiTokenResponse> {
+    return this.post<CreateApiTokenResponse>("/api/v1/auth/tokens", req);
+  }
+
+  async revokeApiToken(tokenId: string): Promise<void> {
+    await this.delete(`/api/v1/auth/tokens/${tokenId}`);
+  }
+
+  // ─── Device Linking ───────────────────────────────
+
+  /** New client: start a link and show `link_uri` as a QR code. */
+  async startDeviceLink(ephemeralPublicKey: string): Promise<import("../types.js").StartDeviceLinkResponse> {
+    return this.post("/api/v1/auth/link", { ephemeral_public_key: ephemeralPublicKey });
+  }
+
+  /** New client: check whether the link was approved; signs in once it is. */
+  async pollDeviceLink(linkId: string, linkToken: string): Promise<import("../types.js").DeviceLinkPollResponse> {
+    const res = await this.post<import("../types.js").DeviceLinkPollResponse>(
+      `/api/v1/auth/link/${linkId}/poll`,
+      { link_token: linkToken },
+    );
+    if ("access_token" in res) {
+      this.setTokens(res.access_token, res.refresh_token);
+    }
+    return res;
+  }
+
+  /** Signed-in client: who is asking to be linked. */
+  async getDeviceLink(linkId: string): Promise<import("../types.js").DeviceLinkInfoResponse> {
+    return this.get(`/api/v1/auth/link/${linkId}`);
+  }
+
+  async approveDeviceLink(linkId: string, req: import("../types.js").ApproveDeviceLinkRequest): Promise<void> {
+    await this.post(`/api/v1/auth/link/${linkId}/approve`, req);
+  }
+
+  // ─── Email ────────────────────────────────────────
+
+  async getEmailStatus(): Promise<EmailStatusResponse> {
+    return this.get<EmailStatusResponse>("/api/v1/auth/email");
+  }
+
+  /** Send a verification link; the address is kept only once it's confirmed. */
+  async setEmail(email: string, password: string): Promise<void> {
+    await this.put("/api/v1/auth/email", { email, password });
+  }
+
+  async removeEmail(): Promise<void> {
+    await this.delete("/api/v1/auth/email");
+  }
+
+  /** Confirm an address with the token from its verification link. */
+  async verifyEmail(token: string): Promise<void> {
+    await this.post("/api/v1/auth/email/verify", { token });
+  }
+
+  async requestPasswordReset(email: string): Promise<void> {
+    await this.post("/api/v1/auth/password-reset", { email });
+  }
+
+  /** Set a new password with the token from a reset link. Signs out every session. */
+  async confirmPasswordReset(token: string, newPassword: string): Promise<void> {
+    await this.post("/api/v1/auth/password-reset/confirm", { token, new_password: newPassword });
+  }
+
   // ─── Users ────────────────────────────────────────
 
   async getUserByUsername(username: string): Promise<import("../types.js").UserPublic> {
@@ -199,17 +358,47 @@ export class HavenApi {
     return this.get<KeyBundle>(`/api/v1/users/${userId}/keys`);
   }
 
+  async getKeyTransparencyLog(userId: string): Promise<KeyTransparencyLog> {
+    return this.get<KeyTransparencyLog>(`/api/v1/users/${userId}/keys/log`);
+  }
+
+  async getKeyTransparencyHead(): Promise<SignedTreeHead> {
+    return this.get<SignedTreeHead>("/api/v1/keys/transparency/head");
+  }
+
   async uploadPreKeys(req: UploadPreKeysRequest): Promise<void> {
     await this.post("/api/v1/keys/prekeys", req);
   }
 
   /** Delete all unused one-time prekeys from the server (stale keys whose private keys are lost). */
-  async clearPreKeys(): Promise<void> {
-    await this.delete("/api/v1/keys/prekeys");
+  async clearPreKeys(deviceId?: string): Promise<void> {
+    await this.delete(`/api/v1/keys/prekeys${deviceQuery(deviceId)}`);
+  }
+
+  async getPreKeyCount(deviceId?: string): Promise<PreKeyCountResponse> {
+    return this.get<PreKeyCountResponse>(`/api/v1/keys/prekeys/count${deviceQuery(deviceId)}`);
+  }
+
+  /** Short-lived certificate to embed in sealed-sender messages. */
+  async getSenderCertificate(deviceId?: string): Promise<SenderCertificate> {
+    return this.get<SenderCertificate>(`/api/v1/keys/sender-certificate${deviceQuery(deviceId)}`);
   }
 
-  async getPreKeyCount(): Promise<PreKeyCountResponse> {
-    return this.get<PreKeyCountResponse>("/api/v1/keys/prekeys/count");
+  /** Set the prekey handed out once a device runs out of one-time prekeys. */
+  async uploadLastResortPreKey(req: UploadLastResortPreKeyRequest): Promise<void> {
+    await this.put("/api/v1/keys/last-resort", req);
+  }
+
+  async listDevices(): Promise<DeviceResponse[]> {
+    return this.get<DeviceResponse[]>("/api/v1/keys/devices");
+  }
+
+  async registerDevice(req: RegisterDeviceRequest): Promise<DeviceResponse> {
+    return this.post<DeviceResponse>("/api/v1/keys/devices", req);
+  }
+
+  async removeDevice(deviceId: string): Promise<void> {
+    await this.delete(`/api/v1/keys/devices/${deviceId}`);
   }
 
   async updateKeys(req: UpdateKeysRequest): Promise<void> {
@@ -218,8 +407,8 @@ export class HavenApi {
 
   // ─── Key Backup ─────────────────────────────────
 
-  async uploadKeyBackup(req: UploadKeyBackupRequest): Promise<void> {
-    await this.put("/api/v1/keys/backup", req);
+  async uploadKeyBackup(req: UploadKeyBackupRequest): Promise<UploadKeyBackupResponse> {
+    return this.put<UploadKeyBackupResponse>("/api/v1/keys/backup", req);
   }
 
   async getKeyBackup(): Promise<KeyBackupResponse> {
@@ -234,6 +423,59 @@ export class HavenApi {
     await this.delete("/api/v1/keys/backup");
   }
 
+  async listKeyBackupVersions(): Promise<KeyBackupVersionInfo[]> {
+    return this.get<KeyBackupVersionInfo[]>("/api/v1/keys/backup/versions");
+  }
+
+  async getKeyBackupVersion(version: number): Promise<KeyBackupResponse> {
+    return this.get<KeyBackupResponse>(`/api/v1/keys/backup/versions/${version}`);
+  }
+
+  async restoreKeyBackupVersion(version: number): Promise<UploadKeyBackupResponse> {
+    return this.post<UploadKeyBackupResponse>(`/api/v1/keys/backup/versions/${version}/restore`, {});
+  }
+
+  async uploadBackupSessions(version: number, sessions: BackupSessionEntry[]): Promise<void> {
+    await this.put("/api/v1/keys/backup/sessions", { version, sessions });
+  }
+
+  async getBackupSessions(version: number): Promise<BackupSessionResponse[]> {
+    return this.get<BackupSessionResponse[]>(`/api/v1/keys/backup/versions/${version}/sessions`);
+  }
+
+  async addKeyBackupSlot(req: KeyBackupSlotRequest): Promise<KeyBackupSlot> {
+    return this.post<KeyBackupSlot>("/api/v1/keys/backup/slots", req);
+  }
+
+  async deleteKeyBackupSlot(slotId: string): Promise<void> {
+    await this.delete(`/api/v1/keys/backup/slots/${slotId}`);
+  }
+
+  async getKeyBackupAccessLog(limit?: number, offset?: number): Promise<KeyBackupAccess[]> {
+    const params = new URLSearchParams();
+    if (limit !== undefined) params.set("limit", String(limit));
+    if (offset !== undefined) params.set("offset", String(offset));
+    const qs = params.toString();
+    return this.get<KeyBackupAccess[]>(`/api/v1/keys/backup/access-log${qs ? `?${qs}` : ""}`);
+  }
+
+  // ─── Verification States ────────────────────────
+
+  async listVerificationStates(): Promise<VerificationStateResponse[]> {
+    return this.get<VerificationStateResponse[]>("/api/v1/keys/verifications");
+  }
+
+  async uploadVerificationState(
+    contactId: string,
+    req: UploadVerificationStateRequest,
+  ): Promise<VerificationStateResponse> {
+    return this.put<VerificationStateResponse>(`/api/v1/keys/verifications/${contactId}`, req);
+  }
+
+  async deleteVerificationState(contactId: string): Promise<void> {
+    await this.delete(`/api/v1/keys/verifications/${contactId}`);
+  }
+
   // ─── Servers ─────────────────────────────────────
 
   async listServers(): Promise<ServerResponse[]> {
@@ -252,10 +494,21 @@ export class HavenApi {
     return this.get(`/api/v1/servers/${serverId}/members/@me/permissions`);
   }
 
-  async updateServer(serverId: string, req: { system_channel_id?: string | null; encrypted_meta?: string }): Promise<{ ok: boolean }> {
+  async updateServer(serverId: string, req: { system_channel_id?: string | null; encrypted_meta?: string; meta_epoch?: number }): Promise<{ ok: boolean }> {
     return this.patch(`/api/v1/servers/${serverId}`, req);
   }
 
+  async getServerKeyEpochs(serverId: string): Promise<import("../types.js").ServerKeyEpochsResponse> {
+    return this.get(`/api/v1/servers/${serverId}/key-epochs`);
+  }
+
+  async distributeServerKeys(
+    serverId: string,
+    req: import("../types.js").DistributeServerKeysRequest,
+  ): Promise<{ epoch: number; distributed: number }> {
+    return this.put(`/api/v1/servers/${serverId}/key-epochs`, req);
+  }
+
   async listServerChannels(serverId: string): Promise<ChannelResponse[]> {
     return this.get<ChannelResponse[]>(`/api/v1/servers/${serverId}/channels`);
   }
@@ -270,7 +523,7 @@ export class HavenApi {
     await this.post(`/api/v1/channels/${channelId}/join`, {});
   }
 
-  async updateChannel(channelId: string, req: { encrypted_meta: string }): Promise<ChannelResponse> {
+  async updateChannel(channelId: string, req: { encrypted_meta: string; meta_epoch?: number }): Promise<ChannelResponse> {
     return this.put<ChannelResponse>(`/api/v1/channels/${channelId}`, req);
   }
 
@@ -495,6 +748,10 @@ export class HavenApi {
     await this.delete(`/api/v1/servers/${serverId}`);
   }
 
+  async transferServerOwnership(serverId: string, userId: string): Promise<void> {
+    await this.put(`/api/v1/servers/${serverId}/owner`, { user_id: userId });
+  }
+
   // ─── Bans ──────────────────────────────────────────
 
   async banMember(serverId: string, userId: string, req: CreateBanRequest): Promise<BanResponse> {
@@ -539,8 +796,32 @@ export class HavenApi {
     await this.put(`/api/v1/admin/users/${userId}/admin`, body);
   }
 
-  async adminDeleteUser(userId: string): Promise<void> {
-    await this.delete(`/api/v1/admin/users/${userId}`);
+  /** Schedule a user's deletion, or erase the account now with `immediate`. */
+  async adminDeleteUser(userId: string, opts?: { immediate?: boolean }): Promise<void> {
+    const qs = opts?.immediate ? "?immediate=true" : "";
+    await this.delete(`/api/v1/admin/users/${userId}${qs}`);
+  }
+
+  async adminExportKeyTransparencyLog(after?: number, limit?: number): Promise<KeyTransparencyLog> {
+    const params = new URLSearchParams();
+    if (after !== undefined) params.set("after", String(after));
+    if (limit !== undefined) params.set("limit", String(limit));
+    const qs = params.toString();
+    return this.get<KeyTransparencyLog>(`/api/v1/admin/key-transparency${qs ? `?${qs}` : ""}`);
+  }
+
+  async listSigningKeys(): Promise<SigningKeyResponse[]> {
+    return this.get<SigningKeyResponse[]>("/api/v1/admin/signing-keys");
+  }
+
+  /** Rotate one keyring now; access tokens unless `purpose` is given. */
+  async rotateSigningKey(purpose?: SigningKeyPurpose): Promise<SigningKeyResponse[]> {
+    const qs = purpose ? `?purpose=${purpose}` : "";
+    return this.post<SigningKeyResponse[]>(`/api/v1/admin/signing-keys/rotate${qs}`, {});
+  }
+
+  async getEmailHashKeyUsage(): Promise<EmailHashKeyUsage[]> {
+    return this.get<EmailHashKeyUsage[]>("/api/v1/admin/email-hash-keys");
   }
 
   // ─── Timeouts ───────────────────────────────────────
@@ -588,9 +869,10 @@ export class HavenApi {
 
   async getSenderKeys(
     channelId: string,
+    deviceId?: string,
   ): Promise<SenderKeyDistributionResponse[]> {
     return this.get<SenderKeyDistributionResponse[]>(
-      `/api/v1/channels/${channelId}/sender-keys`,
+      `/api/v1/channels/${channelId}/sender-keys${deviceQuery(deviceId)}`,
     );
   }
 
@@ -602,6 +884,49 @@ export class HavenApi {
     );
   }
 
+  // ─── MLS ───────────────────────────────────────────
+
+  async uploadKeyPackages(req: UploadKeyPackagesRequest): Promise<{ device_id: string; total_available: number }> {
+    return this.post("/api/v1/keys/mls/key-packages", req);
+  }
+
+  async getKeyPackageCount(deviceId?: string): Promise<{ device_id: string; count: number }> {
+    return this.get(`/api/v1/keys/mls/key-packages/count${deviceQuery(deviceId)}`);
+  }
+
+  /** Claims (consumes) one KeyPackage per device of the user. */
+  async claimKeyPackages(userId: string): Promise<ClaimedKeyPackages> {
+    return this.post<ClaimedKeyPackages>(`/api/v1/users/${userId}/mls/key-packages`, {});
+  }
+
+  async createMlsGroup(channelId: string): Promise<MlsGroupResponse> {
+    return this.post<MlsGroupResponse>(`/api/v1/channels/${channelId}/mls`, {});
+  }
+
+  async getMlsGroup(channelId: string): Promise<MlsGroupResponse> {
+    return this.get<MlsGroupResponse>(`/api/v1/channels/${channelId}/mls`);
+  }
+
+  async sendMlsHandshake(channelId: string, req: SendMlsHandshakeRequest): Promise<MlsHandshakeResponse> {
+    return this.post<MlsHandshakeResponse>(`/api/v1/channels/${channelId}/mls/handshake`, req);
+  }
+
+  async getMlsHandshakes(channelId: string, opts?: { after?: number; limit?: number }): Promise<MlsHandshakeResponse[]> {
+    const params = new URLSearchParams();
+    if (opts?.after !== undefined) params.set("after", String(opts.after));
+    if (opts?.limit) params.set("limit", String(opts.limit));
+    const qs = params.toString();
+    return this.get<MlsHandshakeResponse[]>(`/api/v1/channels/${channelId}/mls/handshake${qs ? `?${qs}` : ""}`);
+  }
+
+  async getMlsWelcomes(deviceId?: string): Promise<MlsWelcomeResponse[]> {
+    return this.get<MlsWelcomeResponse[]>(`/api/v1/keys/mls/welcomes${deviceQuery(deviceId)}`);
+  }
+
+  async deleteMlsWelcome(welcomeId: string): Promise<void> {
+    await this.delete(`/api/v1/keys/mls/welcomes/${welcomeId}`);
+  }
+
   // ─── Link Previews ──────────────────────────────
 
   async fetchLinkPreview(
@@ -735,6 +1060,10 @@ export class HavenApi {
     await this.put("/api/v1/users/dm-privacy", req);
   }
 
+  async updatePresencePrivacy(req: UpdatePresencePrivacyRequest): Promise<void> {
+    await this.put("/api/v1/users/presence-privacy", req);
+  }
+
   // ─── Blocked Users ─────────────────────────────
 
   async blockUser(userId: string): Promise<void> {
@@ -844,8 +1173,26 @@ export class HavenApi {
 
   // ─── Account Deletion ──────────────────────────
 
-  async deleteAccount(password: string): Promise<void> {
-    await this.post("/api/v1/auth/delete-account", { password });
+  /** Hide the account and sign out everywhere; signing in reactivates it. */
+  async deactivateAccount(password: string): Promise<void> {
+    await this.post("/api/v1/auth/deactivate", { password });
+  }
+
+  /** Schedule the account for deletion; signing in before then cancels it. */
+  async deleteAccount(password: string): Promise<AccountDeletionResponse> {
+    return this.post<AccountDeletionResponse>("/api/v1/auth/delete-account", { password });
+  }
+
+  // ─── Data Export ───────────────────────────────
+
+  /** Start building an archive of everything the server holds about the user. */
+  async requestDataExport(): Promise<DataExportResponse> {
+    return this.post<DataExportResponse>("/api/v1/users/@me/export", {});
+  }
+
+  /** The latest export; poll until `status` is no longer "pending". */
+  async getDataExport(): Promise<DataExportResponse> {
+    return this.get<DataExportResponse>("/api/v1/users/@me/export");
   }
 
   // ─── Voice ──────────────────────────────────────
@@ -992,3 +1339,8 @@ function hasLeadingZeroBits(hash: Uint8Array, n: number): boolean {
   }
   return bits >= n;
 }
+
+/** `?device_id=` suffix selecting one of the caller's devices (primary when omitted). */
+function deviceQuery(deviceId?: string): string {
+  return deviceId ? `?device_id=${encodeURIComponent(deviceId)}` : "";
+}
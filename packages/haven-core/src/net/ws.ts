@@ -19,6 +19,12 @@ export interface HavenWsOptions {
 
   /** Base delay in ms for exponential backoff. Default: 1000. */
   reconnectBaseDelay?: number;
+
+  /**
+   * Milliseconds since the user's last input. Reported with each heartbeat
+   * so the server can mark the user idle automatically.
+   */
+  getIdleMs?: () => number;
 }
 
 type ConnectionState = "disconnected" | "connecting" | "connected";
@@ -29,7 +35,7 @@ type ConnectionState = "disconnected" | "connecting" | "connected";
  */
 export class HavenWs {
   private ws: WebSocket | null = null;
-  private options: Required<HavenWsOptions>;
+  private options: Required<Omit<HavenWsOptions, "getIdleMs">> & Pick<HavenWsOptions, "getIdleMs">;
   private listeners = new Map<string, Set<WsEventHandler<any>>>();
   private reconnectAttempts = 0;
   private reconnectTimer: ReturnType<typeof setTimeout> | null = null;
@@ -88,24 +94,27 @@ export class HavenWs {
     this.ws.send(JSON.stringify(msg));
   }
 
-  sendMessage(channelId: string, senderToken: string, encryptedBody: string, expiresAt?: string, attachmentIds?: string[], replyToId?: string): void {
+  /** Pass `capabilityHash` to send sealed: the server then stores no sender for the message. */
+  sendMessage(channelId: string, senderToken: string, encryptedBody: string, expiresAt?: string, attachmentIds?: string[], replyToId?: string, capabilityHash?: string): void {
     this.send({
       type: "SendMessage",
-      payload: { channel_id: channelId, sender_token: senderToken, encrypted_body: encryptedBody, expires_at: expiresAt, attachment_ids: attachmentIds, reply_to_id: replyToId },
+      payload: { channel_id: channelId, sender_token: senderToken, encrypted_body: encryptedBody, expires_at: expiresAt, attachment_ids: attachmentIds, reply_to_id: replyToId, capability_hash: capabilityHash },
     });
   }
 
-  editMessage(messageId: string, encryptedBody: string): void {
+  /** `capability` is required for sealed-sender messages. */
+  editMessage(messageId: string, encryptedBody: string, capability?: string): void {
     this.send({
       type: "EditMessage",
-      payload: { message_id: messageId, encrypted_body: encryptedBody },
+      payload: { message_id: messageId, encrypted_body: encryptedBody, capability },
     });
   }
 
-  deleteMessage(messageId: string): void {
+  /** `capability` is required for sealed-sender messages. */
+  deleteMessage(messageId: string, capability?: string): void {
     this.send({
       type: "DeleteMessage",
-      payload: { message_id: messageId },
+      payload: { message_id: messageId, capability },
     });
   }
 
@@ -182,13 +191,36 @@ export class HavenWs {
   private doConnect(): void {
     this.state = "connecting";
     const previousSessionId = this.sessionId;
+    const baseUrl = this.options.baseUrl.replace(/\/$/, "");
+
+    // Exchange the access token for a short-lived, single-use ticket so the
+    // JWT never ends up in the upgrade URL (and thus in proxy/access logs).
+    fetch(`${baseUrl}/api/v1/ws/ticket`, {
+      method: "POST",
+      headers: { Authorization: `Bearer ${this.options.token}` },
+    })
+      .then((res) => {
+        if (!res.ok) throw new Error(`WS ticket request failed: ${res.status}`);
+        return res.json() as Promise<{ ticket: string }>;
+      })
+      .then(({ ticket }) => {
+        if (this.closed) {
+          this.state = "disconnected";
+          return;
+        }
+        this.openSocket(baseUrl.replace(/^http/, "ws"), ticket, previousSessionId);
+      })
+      .catch(() => {
+        this.state = "disconnected";
+        if (!this.closed && this.options.autoReconnect) {
+          this.scheduleReconnect();
+        }
+      });
+  }
 
-    const wsUrl = this.options.baseUrl
-      .replace(/^http/, "ws")
-      .replace(/\/$/, "");
-
+  private openSocket(wsUrl: string, ticket: string, previousSessionId: string | null): void {
     this.ws = new WebSocket(
-      `${wsUrl}/api/v1/ws?token=${encodeURIComponent(this.options.token)}`,
+      `${wsUrl}/api/v1/ws?ticket=${encodeURIComponent(ticket)}`,
     );
 
     this.ws.onopen = () => {
@@ -228,6 +260,28 @@ export class HavenWs {
           return;
         }
 
+        if (msg.type === "Reconnect") {
+          // Server is draining — hop to another instance after the jittered
+          // delay and resume this session there.
+          this.emit(msg.type, msg);
+          if (this.reconnectTimer) clearTimeout(this.reconnectTimer);
+          this.reconnectTimer = setTimeout(() => {
+            this.reconnectTimer = null;
+            this.cleanup();
+            this.state = "disconnected";
+            if (!this.closed) this.doConnect();
+          }, msg.payload.delay_ms);
+          return;
+        }
+
+        if (msg.type === "SessionRevoked") {
+          // This login was signed out elsewhere; the server closes the socket
+          // and reconnecting with the same tokens would only be refused.
+          this.closed = true;
+          this.emit(msg.type, msg);
+          return;
+        }
+
         if (msg.type === "InvalidSession") {
           // Resume failed — this is now a fresh connection with the new session from Hello
           this.emit("_connect", {} as any);
@@ -283,7 +337,12 @@ export class HavenWs {
     this.stopPing();
     this.pingInterval = setInterval(() => {
       if (this.ws?.readyState === WebSocket.OPEN) {
-        this.send({ type: "Ping" });
+        const idleMs = this.options.getIdleMs?.();
+        this.send(
+          idleMs === undefined
+            ? { type: "Ping" }
+            : { type: "Ping", payload: { idle_ms: Math.max(0, Math.floor(idleMs)) } },
+        );
       }
     }, this.heartbeatIntervalMs);
   }
@@ -8,11 +8,14 @@ export interface RegisterRequest {
   identity_key: string;       // base64
   signed_prekey: string;      // base64
   signed_prekey_signature: string; // base64
+  signed_prekey_id?: number;  // defaults to 1
   one_time_prekeys: string[]; // base64[]
+  last_resort_prekey?: string; // base64, never consumed
   pow_challenge: string;
   pow_nonce: string;
   invite_code?: string;
-  turnstile_token?: string;
+  /** Required when the challenge names a `captcha` provider. */
+  captcha_token?: string;
 }
 
 /** Fields the caller provides — PoW fields are auto-filled by the API client */
@@ -22,30 +25,116 @@ export interface PowChallengeResponse {
   challenge: string;
   difficulty: number;
   turnstile_site_key?: string;
+  captcha?: CaptchaInfo;
+}
+
+export interface CaptchaInfo {
+  provider: "turnstile" | "hcaptcha" | "mcaptcha";
+  site_key: string;
 }
 
 export interface LoginRequest {
   username: string;
   password: string;
   totp_code?: string;
+  /** Only while the server asks for PoW on login; `login()` fills these in. */
+  pow_challenge?: string;
+  pow_nonce?: string;
 }
 
 export interface AuthResponse {
   access_token: string;
   refresh_token: string;
   user: UserPublic;
+  /** Set on registration: the device holding the registration keys. */
+  device_id?: string;
 }
 
 export interface LoginTotpRequiredResponse {
   totp_required: true;
 }
 
-export type LoginResponse = AuthResponse | LoginTotpRequiredResponse;
+/** Failed logins are spiking: solve `challenge` and send the login again. */
+export interface LoginPowRequiredResponse {
+  pow_required: true;
+  challenge: PowChallengeResponse;
+}
+
+export type LoginResponse = AuthResponse | LoginTotpRequiredResponse | LoginPowRequiredResponse;
 
 export function isLoginSuccess(res: LoginResponse): res is AuthResponse {
   return "access_token" in res;
 }
 
+// ─── Device Linking ───────────────────────────────────
+
+export interface StartDeviceLinkResponse {
+  link_id: string;
+  /** Secret for polling — keep it out of the QR code. */
+  link_token: string;
+  /** Show this as the QR code; id and key are in the fragment. */
+  link_uri: string;
+  expires_at: string;
+}
+
+export interface DeviceLinkInfoResponse {
+  link_id: string;
+  device_name: string | null;
+  ip_address: string | null;
+  created_at: string;
+  expires_at: string;
+}
+
+export interface ApproveDeviceLinkRequest {
+  /** The key from the scanned QR code (base64). */
+  ephemeral_public_key: string;
+  approver_public_key: string;
+  /** Encrypted to the new client's ephemeral key (base64). */
+  provisioning_message: string;
+}
+
+export interface DeviceLinkedResponse extends AuthResponse {
+  approver_public_key: string;
+  provisioning_message: string;
+}
+
+export type DeviceLinkPollResponse = DeviceLinkedResponse | { pending: true };
+
+export interface LoginMethodsResponse {
+  password: boolean;
+  oidc: boolean;
+}
+
+export interface OidcAuthorizeResponse {
+  /** Send the browser here; the issuer redirects back with `code` and `state`. */
+  authorization_url: string;
+  state: string;
+}
+
+export interface OidcCallbackRequest {
+  code: string;
+  state: string;
+  /** Required when the SSO identity has no account yet and one is provisioned. */
+  keys?: RegisterDeviceRequest;
+}
+
+export interface OidcLinkedResponse {
+  linked: true;
+  issuer: string;
+  subject: string;
+}
+
+export type OidcCallbackResponse = AuthResponse | OidcLinkedResponse;
+
+export interface OidcIdentity {
+  id: string;
+  user_id: string;
+  issuer: string;
+  subject: string;
+  created_at: string;
+  last_login_at: string | null;
+}
+
 export interface RefreshRequest {
   refresh_token: string;
 }
@@ -69,6 +158,81 @@ export interface SessionResponse {
   is_current: boolean;
 }
 
+export type SecurityEventKind =
+  | "new_device_login"
+  | "device_linked"
+  | "account_locked"
+  | "password_changed"
+  | "password_reset"
+  | "email_verified"
+  | "api_token_created"
+  | "account_deactivated"
+  | "account_deletion_scheduled"
+  | "account_reactivated"
+  | "data_export_requested";
+
+export interface SecurityEventResponse {
+  id: string;
+  kind: SecurityEventKind;
+  device_name: string | null;
+  /** Partially masked, e.g. "203.0.x.x". */
+  ip_address: string | null;
+  created_at: string;
+}
+
+/** "messages:read", "messages:send" or `server:${serverId}:manage`. */
+export type ApiTokenScope = "messages:read" | "messages:send" | `server:${string}:manage`;
+
+export interface CreateApiTokenRequest {
+  name: string;
+  scopes: ApiTokenScope[];
+  /** 1-365; omit for a token that never expires. */
+  expires_in_days?: number;
+}
+
+export interface ApiTokenResponse {
+  id: string;
+  name: string;
+  /** The first characters of the token, e.g. "hvn_pat_AbCd". */
+  token_prefix: string;
+  scopes: ApiTokenScope[];
+  expires_at: string | null;
+  last_used_at: string | null;
+  /** Partially masked, e.g. "203.0.x.x". */
+  last_used_ip: string | null;
+  created_at: string;
+}
+
+export interface CreateApiTokenResponse extends ApiTokenResponse {
+  /** Shown this once; send as `Authorization: Bearer <token>`. */
+  token: string;
+}
+
+export interface AccountDeletionResponse {
+  /** When the account will be erased, unless its owner signs in first. */
+  deletion_scheduled_at: string;
+}
+
+export interface DataExportResponse {
+  id: string;
+  status: "pending" | "ready" | "failed";
+  size_bytes: number | null;
+  created_at: string;
+  completed_at: string | null;
+  /** When the archive (or failed export) is deleted. */
+  expires_at: string | null;
+  /** Signed link to the tar archive, works without auth; set while ready. */
+  download_url?: string;
+}
+
+export interface EmailStatusResponse {
+  /** Whether this instance can send email (verification, password reset). */
+  email_enabled: boolean;
+  email_set: boolean;
+  verified: boolean;
+  verification_pending: boolean;
+}
+
 // ─── Users ─────────────────────────────────────────────
 
 export interface UserPublic {
@@ -80,6 +244,7 @@ export interface UserPublic {
   banner_url: string | null;
   custom_status: string | null;
   custom_status_emoji: string | null;
+  custom_status_expires_at?: string | null;
   created_at: string;
   encrypted_profile?: string | null; // base64 encrypted blob
   is_instance_admin?: boolean;
@@ -102,6 +267,7 @@ export interface UserProfileResponse {
   banner_url: string | null;
   custom_status: string | null;
   custom_status_emoji: string | null;
+  custom_status_expires_at?: string | null;
   created_at: string;
   is_blocked: boolean;
   is_friend: boolean;
@@ -112,6 +278,8 @@ export interface UserProfileResponse {
   mutual_server_count: number;
   roles?: RoleResponse[];
   encrypted_profile?: string | null; // base64 encrypted blob
+  verified: boolean;
+  verified_key_changed: boolean;
 }
 
 export interface UpdateProfileRequest {
@@ -119,6 +287,8 @@ export interface UpdateProfileRequest {
   about_me?: string | null;
   custom_status?: string | null;
   custom_status_emoji?: string | null;
+  /** When the custom status clears itself; must be in the future. */
+  custom_status_expires_at?: string | null;
   encrypted_profile?: string; // base64 encrypted blob
 }
 
@@ -158,42 +328,152 @@ export interface BlockedUserResponse {
 
 // ─── Keys ──────────────────────────────────────────────
 
+/** Bundles for all of a user's devices; top-level fields are the primary device. */
 export interface KeyBundle {
   identity_key: string;          // base64
   signed_prekey: string;         // base64
   signed_prekey_sig: string;     // base64
+  signed_prekey_id: number;
   one_time_prekey: string | null; // base64, consumed on fetch
+  last_resort: boolean;           // one_time_prekey is the last-resort prekey
+  devices: DeviceKeyBundle[];
+  verified: boolean;              // the requester verified these keys
+  verified_key_changed: boolean;  // keys changed since verification — warn before sending
+}
+
+export interface DeviceKeyBundle {
+  device_id: string;
+  identity_key: string;          // base64
+  signed_prekey: string;         // base64
+  signed_prekey_sig: string;     // base64
+  signed_prekey_id: number;
+  one_time_prekey: string | null; // base64, consumed on fetch
+  last_resort: boolean;           // one_time_prekey is the last-resort prekey
+}
+
+export interface DeviceResponse {
+  id: string;
+  name: string | null;
+  identity_key: string; // base64
+  created_at: string;
+}
+
+export interface RegisterDeviceRequest {
+  name?: string;
+  identity_key: string;            // base64
+  signed_prekey: string;           // base64
+  signed_prekey_signature: string; // base64
+  signed_prekey_id?: number;       // defaults to 1
+  one_time_prekeys?: string[];     // base64[]
+  last_resort_prekey?: string;     // base64
+}
+
+export interface UploadLastResortPreKeyRequest {
+  prekey: string;     // base64
+  device_id?: string; // primary device when omitted
 }
 
 export interface UploadPreKeysRequest {
   prekeys: string[]; // base64[]
+  device_id?: string; // primary device when omitted
 }
 
 export interface PreKeyCountResponse {
   count: number;
   needs_replenishment: boolean;
+  has_last_resort: boolean;
+  signed_prekey_id: number;
+  signed_prekey_created_at: string;
+  signed_prekey_stale: boolean;  // older than the instance's rotation schedule
+  previous_signed_prekey_id: number | null;         // replaced key still in its grace window
+  previous_signed_prekey_expires_at: string | null;
+}
+
+/** Server-signed binding of a device to its identity key, embedded in sealed-sender ciphertexts. */
+export interface SenderCertificate {
+  user_id: string;
+  device_id: string;
+  identity_key: string;  // base64
+  expires_at: string;
+  signature: string;     // base64, over "haven-sender-cert-v1" || user_id || device_id || expires_at_millis || identity_key
+  public_key: string;    // base64 Ed25519
 }
 
 export interface UpdateKeysRequest {
   identity_key: string;          // base64
   signed_prekey: string;         // base64
   signed_prekey_signature: string; // base64
+  signed_prekey_id?: number;     // current ID + 1 when omitted
+  device_id?: string;            // primary device when omitted
+}
+
+// ─── Key Transparency ────────────────────────────────
+
+export interface TransparencyLogEntry {
+  seq: number;
+  user_id: string;
+  device_id: string;
+  identity_key: string;      // base64
+  prev_hash: string | null;  // base64, leaf hash of the user's previous entry
+  leaf_hash: string;         // base64
+  published_at: string;
+  audit_path?: string[];     // base64, inclusion proof against tree_head
+}
+
+/** Ed25519-signed Merkle root over the first `tree_size` log entries. */
+export interface SignedTreeHead {
+  tree_size: number;
+  root_hash: string;  // base64
+  timestamp: string;
+  signature: string;  // base64
+  public_key: string; // base64
+}
+
+export interface KeyTransparencyLog {
+  tree_head: SignedTreeHead;
+  entries: TransparencyLogEntry[];
 }
 
 // ─── Key Backup ──────────────────────────────────────
 
+export type KeyBackupSlotKind = "passphrase" | "recovery_key" | "passkey_prf";
+
+export interface KeyBackupSlotRequest {
+  kind: KeyBackupSlotKind;
+  wrapped_key: string;      // base64, the backup key wrapped by this slot
+  nonce: string;            // base64, 24 bytes
+  salt?: string;            // base64: 16-byte Argon2id salt (passphrase) or 32-byte PRF input (passkey_prf)
+  credential_id?: string;   // base64, passkey_prf only
+}
+
+export interface KeyBackupSlot {
+  id: string;
+  kind: KeyBackupSlotKind;
+  wrapped_key: string;
+  nonce: string;
+  salt: string | null;
+  credential_id: string | null;
+  created_at: string;
+}
+
 export interface UploadKeyBackupRequest {
   encrypted_data: string;  // base64
   nonce: string;           // base64
-  salt: string;            // base64
-  version?: number;
+  salt?: string;           // base64, when encrypted directly with a passphrase
+  key_slots?: KeyBackupSlotRequest[]; // required when salt is omitted
+}
+
+export interface UploadKeyBackupResponse {
+  ok: boolean;
+  version: number;         // assigned by the server
 }
 
 export interface KeyBackupResponse {
   encrypted_data: string;  // base64
   nonce: string;           // base64
-  salt: string;            // base64
+  salt: string | null;     // base64
   version: number;
+  key_slots: KeyBackupSlot[];
   updated_at: string;
 }
 
@@ -203,6 +483,50 @@ export interface KeyBackupStatusResponse {
   updated_at: string | null;
 }
 
+export interface KeyBackupVersionInfo {
+  version: number;
+  slot_count: number;
+  session_count: number;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface BackupSessionEntry {
+  session_id: string;
+  encrypted_data: string;  // base64, under the backup key
+  nonce: string;           // base64
+}
+
+export interface BackupSessionResponse extends BackupSessionEntry {
+  updated_at: string;
+}
+
+export interface KeyBackupAccess {
+  id: string;
+  version: number;
+  action: "download" | "sessions";
+  ip_address: string | null;
+  device_name: string | null;
+  created_at: string;
+}
+
+// ─── Verification States ─────────────────────────────
+
+export interface UploadVerificationStateRequest {
+  encrypted_state: string;            // base64
+  nonce: string;                      // base64, 24 bytes
+  verified_identity_keys?: string[];  // base64, every device key verified; omit when unverified
+}
+
+export interface VerificationStateResponse {
+  contact_id: string;
+  encrypted_state: string; // base64
+  nonce: string;           // base64
+  verified: boolean;
+  verified_key_changed: boolean;
+  updated_at: string;
+}
+
 // ─── Servers ───────────────────────────────────────────
 
 export interface CreateServerRequest {
@@ -217,6 +541,33 @@ export interface ServerResponse {
   my_permissions?: string; // i64 as string for JS BigInt safety
   system_channel_id?: string; // channel where system messages (joins, etc.) are posted
   icon_url?: string;
+  key_epoch: number; // current server key epoch
+  meta_epoch: number; // server key epoch encrypted_meta is encrypted under
+}
+
+// ─── Server Key Epochs ─────────────────────────────────
+
+export interface ServerKeyWrapEntry {
+  user_id: string;
+  encrypted_key: string; // base64, server key wrapped to the member's identity key
+}
+
+/** `epoch` = key_epoch + 1 rotates (must cover every member); = key_epoch adds wraps */
+export interface DistributeServerKeysRequest {
+  epoch: number;
+  wraps: ServerKeyWrapEntry[];
+}
+
+export interface ServerKeyWrapResponse {
+  epoch: number;
+  encrypted_key: string; // base64
+  distributed_by: string | null;
+  created_at: string;
+}
+
+export interface ServerKeyEpochsResponse {
+  key_epoch: number;
+  wraps: ServerKeyWrapResponse[]; // oldest epoch first
 }
 
 // ─── Channels ──────────────────────────────────────────
@@ -227,6 +578,7 @@ export interface CreateChannelRequest {
   position?: number;
   category_id?: string | null;
   is_private?: boolean;
+  meta_epoch?: number; // server key epoch encrypted_meta is encrypted under
 }
 
 export interface ChannelResponse {
@@ -240,6 +592,7 @@ export interface ChannelResponse {
   dm_status?: string; // "active", "pending", "declined" — only for DM channels
   last_message_id?: string;
   is_private: boolean;
+  meta_epoch: number; // server key epoch of encrypted_meta (0 for DMs)
 }
 
 // ─── Channel Categories ───────────────────────────────
@@ -288,10 +641,14 @@ export interface CreateGroupDmRequest {
 
 export interface ChannelMemberInfo {
   user_id: string;
+  /** "Deleted Account" while the account awaits deletion. */
   username: string;
+  /** Null while the account is deactivated. */
   display_name: string | null;
   avatar_url: string | null;
   joined_at: string;
+  /** The account is scheduled for deletion. */
+  deleted: boolean;
 }
 
 // ─── Messages ──────────────────────────────────────────
@@ -302,6 +659,7 @@ export interface SendMessageRequest {
   encrypted_body: string;  // base64
   expires_at?: string;
   has_attachments: boolean;
+  capability_hash?: string; // base64 SHA-256 of a capability token; sealed sender, no sender stored
 }
 
 export interface MessageResponse {
@@ -334,6 +692,7 @@ export interface UploadResponse {
 export interface DistributeSenderKeyRequest {
   distributions: Array<{
     to_user_id: string;
+    to_device_id?: string; // recipient's primary device when omitted
     distribution_id: string;
     encrypted_skdm: string; // base64
   }>;
@@ -343,6 +702,7 @@ export interface SenderKeyDistributionResponse {
   id: string;
   channel_id: string;
   from_user_id: string;
+  to_device_id: string;
   distribution_id: string;
   encrypted_skdm: string; // base64
   created_at: string;
@@ -350,7 +710,61 @@ export interface SenderKeyDistributionResponse {
 
 export interface ChannelMemberKeyInfo {
   user_id: string;
-  identity_key: string; // base64
+  identity_key: string; // base64, primary device
+  devices: Array<{ device_id: string; identity_key: string }>;
+}
+
+/** Why a channel's members must distribute fresh sender keys. */
+export type SenderKeyRotationReason = "kicked" | "banned" | "left" | "permissions_changed";
+
+// ─── MLS ──────────────────────────────────────────────
+
+export interface UploadKeyPackagesRequest {
+  device_id?: string;      // primary device when omitted
+  key_packages: string[];  // base64 MLS KeyPackages
+}
+
+export interface ClaimedKeyPackages {
+  user_id: string;
+  key_packages: Array<{ device_id: string; key_package: string }>;
+}
+
+export interface MlsGroupResponse {
+  channel_id: string;
+  epoch: number;
+  created_at: string;
+}
+
+export type MlsHandshakeKind = "commit" | "proposal";
+
+export interface SendMlsHandshakeRequest {
+  device_id?: string;            // sender's primary device when omitted
+  epoch: number;                 // must be the group's current epoch (409 otherwise)
+  kind: MlsHandshakeKind;
+  message: string;               // base64 MLSMessage
+  welcome?: string;              // base64, commits only
+  welcome_device_ids?: string[];
+}
+
+export interface MlsHandshakeResponse {
+  channel_id: string;
+  seq: number;
+  epoch: number;
+  kind: MlsHandshakeKind;
+  /** null once the sender's account or device was deleted. */
+  sender_user_id: string | null;
+  sender_device_id: string | null;
+  message: string; // base64
+  created_at: string;
+}
+
+export interface MlsWelcomeResponse {
+  id: string;
+  channel_id: string;
+  to_device_id: string;
+  epoch: number;
+  welcome: string; // base64
+  created_at: string;
 }
 
 // ─── Reactions ─────────────────────────────────────────
@@ -365,9 +779,9 @@ export interface ReactionGroup {
 // ─── WebSocket ─────────────────────────────────────────
 
 export type WsClientMessage =
-  | { type: "SendMessage"; payload: { channel_id: string; sender_token: string; encrypted_body: string; expires_at?: string; attachment_ids?: string[]; reply_to_id?: string } }
-  | { type: "EditMessage"; payload: { message_id: string; encrypted_body: string } }
-  | { type: "DeleteMessage"; payload: { message_id: string } }
+  | { type: "SendMessage"; payload: { channel_id: string; sender_token: string; encrypted_body: string; expires_at?: string; attachment_ids?: string[]; reply_to_id?: string; capability_hash?: string } }
+  | { type: "EditMessage"; payload: { message_id: string; encrypted_body: string; capability?: string } }
+  | { type: "DeleteMessage"; payload: { message_id: string; capability?: string } }
   | { type: "AddReaction"; payload: { message_id: string; emoji: string } }
   | { type: "RemoveReaction"; payload: { message_id: string; emoji: string } }
   | { type: "Subscribe"; payload: { channel_id: string } }
@@ -380,9 +794,11 @@ export type WsClientMessage =
   | { type: "CallAccept"; payload: { channel_id: string } }
   | { type: "CallReject"; payload: { channel_id: string } }
   | { type: "CallEnd"; payload: { channel_id: string } }
-  | { type: "Ping" }
+  | { type: "Ping"; payload?: { idle_ms: number } }
   | { type: "MarkRead"; payload: { channel_id: string } }
-  | { type: "Resume"; payload: { session_id: string } };
+  | { type: "Resume"; payload: { session_id: string } }
+  | { type: "SubscribeMemberList"; payload: { server_id: string; group_by?: MemberListGrouping; range: [number, number] } }
+  | { type: "UnsubscribeMemberList"; payload: { server_id: string } };
 
 export type WsServerMessage =
   | { type: "NewMessage"; payload: MessageResponse }
@@ -393,10 +809,19 @@ export type WsServerMessage =
   | { type: "Error"; payload: { message: string } }
   | { type: "Pong" }
   | { type: "SenderKeysUpdated"; payload: { channel_id: string } }
+  | { type: "MlsHandshake"; payload: MlsHandshakeResponse }
+  | { type: "MlsWelcome"; payload: { channel_id: string; device_id: string } }
+  | { type: "DeviceAdded"; payload: { user_id: string; device_id: string } }
+  | { type: "DeviceRemoved"; payload: { user_id: string; device_id: string } }
+  | { type: "PrekeysLow"; payload: { device_id: string; remaining: number } }
+  | { type: "IdentityKeyChanged"; payload: { user_id: string; device_id: string; identity_key: string; log_seq: number } }
+  | { type: "SenderKeyRotationRequired"; payload: { channel_id: string; reason: SenderKeyRotationReason } }
+  | { type: "VerificationStateUpdated"; payload: { contact_id: string } }
   | { type: "MessageDeleted"; payload: { message_id: string; channel_id: string } }
   | { type: "ReactionAdded"; payload: { message_id: string; channel_id: string; sender_token: string; emoji: string } }
   | { type: "ReactionRemoved"; payload: { message_id: string; channel_id: string; sender_token: string; emoji: string } }
-  | { type: "PresenceUpdate"; payload: { user_id: string; status: string } }
+  | { type: "PresenceUpdate"; payload: { user_id: string; status: string; since?: string; last_seen?: string } }
+  | { type: "CustomStatusUpdate"; payload: { user_id: string; custom_status: string | null; custom_status_emoji: string | null; custom_status_expires_at?: string | null } }
   | { type: "FriendRequestReceived"; payload: { from_user_id: string; from_username: string; friendship_id: string } }
   | { type: "FriendRequestAccepted"; payload: { user_id: string; username: string; friendship_id: string } }
   | { type: "FriendRemoved"; payload: { user_id: string } }
@@ -415,15 +840,48 @@ export type WsServerMessage =
   | { type: "MemberTimedOut"; payload: { server_id: string; user_id: string; timed_out_until: string | null } }
   | { type: "ReadStateUpdated"; payload: { channel_id: string; last_read_at: string } }
   | { type: "ServerUpdated"; payload: { server_id: string } }
+  | { type: "ServerKeyRotated"; payload: { server_id: string; epoch: number } }
   | { type: "Hello"; payload: { session_id: string; heartbeat_interval_ms: number } }
   | { type: "Resumed"; payload: { replayed_count: number } }
-  | { type: "InvalidSession" };
+  | { type: "InvalidSession" }
+  | { type: "SessionRevoked"; payload: { family_id: string } }
+  | { type: "NewLoginDetected"; payload: { event_id: string; device_name: string | null; ip_address: string | null; created_at: string } }
+  | { type: "Reconnect"; payload: { delay_ms: number } }
+  | { type: "MemberListUpdate"; payload: { server_id: string; group_by: MemberListGrouping; member_count: number; online_count: number; groups: MemberListGroup[]; ops: MemberListOp[] } };
+
+// ─── Lazy Member List ─────────────────────────────────
+
+export type MemberListGrouping = "status" | "role";
+
+export interface MemberListGroup {
+  /** Role ID, or "online" / "offline". */
+  id: string;
+  count: number;
+}
+
+export type MemberListItem =
+  | ({ kind: "group" } & MemberListGroup)
+  | { kind: "member"; member: ServerMemberResponse; status: string };
+
+/** Applied in order; indexes are absolute positions in the full list. */
+export type MemberListOp =
+  | { op: "sync"; range: [number, number]; items: MemberListItem[] }
+  | { op: "insert"; index: number; item: MemberListItem }
+  | { op: "update"; index: number; item: MemberListItem }
+  | { op: "delete"; index: number };
 
 // ─── Presence ─────────────────────────────────────────
 
 export interface PresenceEntry {
   user_id: string;
   status: string;
+  /** When the current status started (absent when offline). */
+  since?: string;
+  /** Last disconnect, if the instance records it and the user shares it. */
+  last_seen?: string;
+  custom_status?: string;
+  custom_status_emoji?: string;
+  custom_status_expires_at?: string;
 }
 
 // ─── Admin Dashboard ─────────────────────────────────
@@ -450,6 +908,28 @@ export interface SetAdminRequest {
   is_admin: boolean;
 }
 
+/** What a signing key signs; each purpose has its own keyring. */
+export type SigningKeyPurpose = "access_token" | "tree_head" | "sender_certificate";
+
+export interface SigningKeyResponse {
+  kid: string;
+  purpose: SigningKeyPurpose;
+  algorithm: "EdDSA" | "ES256";
+  created_at: string;
+  activates_at: string;
+  retires_at: string | null;
+  /** Currently signing for its purpose */
+  signing: boolean;
+}
+
+export interface EmailHashKeyUsage {
+  /** null for hashes made with the legacy jwt_secret */
+  key_id: string | null;
+  current: boolean;
+  configured: boolean;
+  users: number;
+}
+
 // ─── Invites ──────────────────────────────────────────
 
 export interface CreateInviteRequest {
@@ -619,6 +1099,11 @@ export interface UpdateDmPrivacyRequest {
   dm_privacy: string; // "everyone", "friends_only", "server_members"
 }
 
+export interface UpdatePresencePrivacyRequest {
+  presence_privacy?: string; // "everyone", "friends_only"
+  hide_last_seen?: boolean;
+}
+
 // ─── Reports ─────────────────────────────────────────
 
 export interface CreateReportRequest {
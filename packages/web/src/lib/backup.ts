@@ -145,7 +145,6 @@ export async function uploadBackup(securityPhrase: string): Promise<void> {
     encrypted_data: toBase64(encrypted),
     nonce: toBase64(nonce),
     salt: toBase64(salt),
-    version: 1,
   });
 }
 
@@ -159,6 +158,9 @@ export async function downloadAndRestoreBackup(securityPhrase: string): Promise<
 
   const encrypted = fromBase64(response.encrypted_data);
   const nonce = fromBase64(response.nonce);
+  if (!response.salt) {
+    throw new Error("This backup is not protected by a security phrase");
+  }
   const salt = fromBase64(response.salt);
 
   const plaintext = decryptBackup(encrypted, nonce, salt, securityPhrase);
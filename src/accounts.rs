@@ -0,0 +1,206 @@
+//! Account deactivation, scheduled deletion and erasure.
+//!
+//! Deactivating an account hides its profile and revokes its sessions;
+//! signing in again reactivates it. Deleting an account deactivates it and
+//! schedules its erasure `account_deletion_grace_days` later (immediately
+//! when that is 0). Signing in during the grace window cancels the deletion.
+//! Until then, DM member lists show the account as a "Deleted Account"
+//! placeholder. A worker erases accounts whose grace period is over.
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::data_export;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::models::WsServerMessage;
+use crate::revocation;
+use crate::security_events;
+use crate::storage;
+use crate::AppState;
+
+/// Shown in place of the username of an account awaiting deletion
+pub const DELETED_ACCOUNT_NAME: &str = "Deleted Account";
+
+/// Accounts erased per worker pass
+const ERASE_BATCH: i64 = 100;
+
+/// Sign out everywhere and drop cached views of the user, after their
+/// account was deactivated.
+async fn disconnect(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    revocation::revoke_all_sessions(state, user_id).await?;
+    crate::ws::broadcast_presence(user_id, "offline", state).await;
+    crate::api::voice::cleanup_voice_state(state, user_id).await;
+    invalidate_user(state, user_id).await;
+    Ok(())
+}
+
+async fn invalidate_user(state: &AppState, user_id: Uuid) {
+    crate::cache::invalidate(state.redis.clone().as_mut(), &state.memory, &format!("haven:user:{}", user_id)).await;
+    crate::member_list::refresh_user(state, user_id).await;
+}
+
+/// Deactivate an account until its owner signs in again.
+pub async fn deactivate(state: &AppState, user_id: Uuid, headers: &HeaderMap) -> AppResult<()> {
+    queries::deactivate_user(state.db.write(), user_id, None, false).await?;
+    security_events::record(state, user_id, security_events::ACCOUNT_DEACTIVATED, headers).await?;
+    disconnect(state, user_id).await?;
+    tracing::info!("User {} deactivated their account", user_id);
+    Ok(())
+}
+
+/// Deactivate an account and schedule its erasure after the grace period,
+/// or erase it now when there is none. Returns when it is (or was) erased.
+/// A deletion scheduled `by_admin` can't be cancelled by signing in.
+pub async fn schedule_deletion(
+    state: &AppState,
+    user_id: Uuid,
+    headers: &HeaderMap,
+    by_admin: bool,
+) -> AppResult<DateTime<Utc>> {
+    let grace_days = state.config.account_deletion_grace_days;
+    if grace_days == 0 {
+        erase(state, user_id).await?;
+        return Ok(Utc::now());
+    }
+
+    let deletion_at = Utc::now() + Duration::days(i64::from(grace_days));
+    queries::deactivate_user(state.db.write(), user_id, Some(deletion_at), by_admin).await?;
+    security_events::record(state, user_id, security_events::ACCOUNT_DELETION_SCHEDULED, headers).await?;
+    disconnect(state, user_id).await?;
+    if by_admin {
+        tracing::info!("User {} was scheduled for deletion by an admin at {}", user_id, deletion_at);
+    } else {
+        tracing::info!("User {} scheduled their account for deletion at {}", user_id, deletion_at);
+    }
+    Ok(deletion_at)
+}
+
+/// Reactivate the account on sign-in, cancelling any scheduled deletion.
+/// Sign-in is refused while an admin-scheduled deletion is pending.
+pub async fn reactivate_on_login(state: &AppState, user_id: Uuid, headers: &HeaderMap) -> AppResult<()> {
+    if !queries::reactivate_user(state.db.write(), user_id).await? {
+        let user = queries::find_user_by_id(state.db.write(), user_id).await?;
+        if user.is_some_and(|u| u.deletion_requested_by_admin) {
+            return Err(AppError::Forbidden(
+                "This account has been scheduled for deletion by an administrator".into(),
+            ));
+        }
+        return Ok(());
+    }
+    security_events::record(state, user_id, security_events::ACCOUNT_REACTIVATED, headers).await?;
+    invalidate_user(state, user_id).await;
+    tracing::info!("User {} reactivated their account", user_id);
+    Ok(())
+}
+
+/// Refuse to delete an account that still owns servers.
+pub async fn require_no_owned_servers(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    let owned = queries::get_servers_owned_by(state.db.read(), user_id).await?;
+    if !owned.is_empty() {
+        return Err(AppError::Conflict(format!(
+            "Transfer ownership of your {} server(s) or delete them first",
+            owned.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Permanently erase an account: its servers, messages, reactions, stored
+/// files, data exports and the user row itself.
+pub async fn erase(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    // 1. Delete servers owned by this user (cascade removes channels, members, etc.)
+    let owned_servers = queries::get_servers_owned_by(state.db.read(), user_id).await?;
+    for server in &owned_servers {
+        // Clean up custom emoji storage for this server
+        let emojis = queries::list_server_emojis(state.db.read(), server.id).await.unwrap_or_default();
+        for emoji in &emojis {
+            let _ = state.storage.delete_blob(&emoji.storage_key).await;
+        }
+        // Delete server (CASCADE handles members, channels, emojis, etc.)
+        queries::delete_server(state.db.write(), server.id).await.ok();
+    }
+
+    // 2. Clean up message children that reference the partitioned messages table
+    //    (no FK cascade on partitioned tables in PG < 17)
+    sqlx::query("DELETE FROM attachments WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+    sqlx::query("DELETE FROM reactions WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+    sqlx::query("DELETE FROM pinned_messages WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+    sqlx::query("DELETE FROM reports WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+
+    // 3. Delete user's messages
+    sqlx::query("DELETE FROM messages WHERE sender_id = $1")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+
+    // 4. Also delete reactions by this user on other messages
+    sqlx::query("DELETE FROM reactions WHERE user_id = $1")
+        .bind(user_id)
+        .execute(state.db.write())
+        .await
+        .ok();
+
+    // 5. Revoke sessions (closing their sockets), broadcast offline, clean up voice
+    revocation::revoke_all_sessions(state, user_id).await.ok();
+    crate::ws::broadcast_presence(user_id, "offline", state).await;
+    crate::api::voice::cleanup_voice_state(state, user_id).await;
+
+    // 6. Tell remaining connections (tokens without a session) the account is gone
+    if let Some((_, conns)) = state.connections.remove(&user_id) {
+        for tx in conns {
+            let _ = tx.send(WsServerMessage::Error {
+                message: "Account deleted".into(),
+            });
+        }
+    }
+
+    // 7. Delete personal data export archives (their rows cascade below)
+    data_export::remove_all(state, user_id).await.ok();
+
+    // 8. Delete user (FK CASCADE handles server_members, channel_members,
+    //    friendships, blocks, prekeys, key_backups, sender_key_distributions, etc.)
+    queries::delete_user_account(state.db.write(), user_id).await?;
+
+    // 9. Clean up stored files (avatar, banner)
+    let avatar_key = storage::obfuscated_key(&state.storage_key, &format!("avatar:{}", user_id));
+    let banner_key = storage::obfuscated_key(&state.storage_key, &format!("banner:{}", user_id));
+    let _ = state.storage.delete_blob(&avatar_key).await;
+    let _ = state.storage.delete_blob(&banner_key).await;
+
+    // 10. Invalidate caches
+    invalidate_user(state, user_id).await;
+    Ok(())
+}
+
+/// Erase every account whose deletion grace period is over. Returns how
+/// many were erased.
+pub async fn erase_due_accounts(state: &AppState) -> AppResult<usize> {
+    let due = queries::list_users_due_for_deletion(state.db.write(), ERASE_BATCH).await?;
+    let mut erased = 0;
+    for user_id in due {
+        match erase(state, user_id).await {
+            Ok(()) => erased += 1,
+            Err(e) => tracing::error!("Failed to erase account {}: {}", user_id, e),
+        }
+    }
+    Ok(erased)
+}
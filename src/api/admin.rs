@@ -1,13 +1,15 @@
 use axum::{
     extract::{Path, Query, State},
+    http::HeaderMap,
     Json,
 };
 use uuid::Uuid;
 
+use crate::accounts;
 use crate::db::queries;
 use crate::errors::AppResult;
 use crate::middleware::AdminUser;
-use crate::models::{AdminSearchQuery, AdminStats, AdminUserResponse, SetAdminRequest};
+use crate::models::{AdminDeleteUserQuery, AdminSearchQuery, AdminStats, AdminUserResponse, SetAdminRequest};
 use crate::AppState;
 
 /// GET /api/v1/admin/stats
@@ -80,11 +82,15 @@ pub async fn set_admin(
     })))
 }
 
-/// DELETE /api/v1/admin/users/:user_id
+/// DELETE /api/v1/admin/users/:user_id?immediate=
+/// Schedule the account for deletion after the grace period, like a user's
+/// own request but without the option to cancel it by signing in, or erase
+/// it now with `immediate=true`.
 pub async fn delete_user(
     AdminUser(admin_id): AdminUser,
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
+    Query(query): Query<AdminDeleteUserQuery>,
 ) -> AppResult<Json<serde_json::Value>> {
     // Prevent self-deletion via admin panel
     if user_id == admin_id {
@@ -98,10 +104,20 @@ pub async fn delete_user(
         .await?
         .ok_or(crate::errors::AppError::NotFound("User not found".into()))?;
 
-    queries::delete_user_account(state.db.write(), user_id).await?;
+    if query.immediate || state.config.account_deletion_grace_days == 0 {
+        accounts::erase(&state, user_id).await?;
+        return Ok(Json(serde_json::json!({
+            "deleted": true,
+            "user_id": user_id,
+        })));
+    }
 
+    // No request headers: the admin's device and IP don't belong in the
+    // user's security history. Signing in won't cancel this one.
+    let deletion_scheduled_at = accounts::schedule_deletion(&state, user_id, &HeaderMap::new(), true).await?;
     Ok(Json(serde_json::json!({
-        "deleted": true,
+        "deleted": false,
+        "deletion_scheduled_at": deletion_scheduled_at,
         "user_id": user_id,
     })))
 }
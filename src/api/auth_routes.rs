@@ -1,19 +1,25 @@
 use axum::{extract::State, http::{HeaderMap, StatusCode}, Json};
 use chrono::{Duration, Utc};
-use sha2::{Digest, Sha256};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::accounts;
+use crate::api_tokens;
 use crate::auth;
+use crate::captcha::{Captcha, CaptchaProvider};
 use crate::db::queries;
+use crate::email::{self, Mailer};
 use crate::errors::{AppError, AppResult};
-use crate::middleware::AuthUser;
+use crate::login_throttle;
+use crate::middleware::{AuthUser, ClientIp};
 use crate::models::*;
-use crate::storage;
+use crate::pow;
+use crate::revocation;
+use crate::security_events;
 use crate::AppState;
 
 /// Parse a User-Agent header into a short device name like "Chrome on macOS".
-fn parse_device_name(ua: &str) -> String {
+pub(crate) fn parse_device_name(ua: &str) -> String {
     let browser = if ua.contains("Firefox") {
         "Firefox"
     } else if ua.contains("Edg/") {
@@ -42,7 +48,7 @@ fn parse_device_name(ua: &str) -> String {
 }
 
 /// Extract client IP from headers (X-Forwarded-For or X-Real-IP).
-fn extract_ip_from_headers(headers: &HeaderMap) -> Option<String> {
+pub(crate) fn extract_ip_from_headers(headers: &HeaderMap) -> Option<String> {
     headers
         .get("x-forwarded-for")
         .and_then(|v| v.to_str().ok())
@@ -56,92 +62,44 @@ fn extract_ip_from_headers(headers: &HeaderMap) -> Option<String> {
         })
 }
 
-/// Cloudflare Turnstile siteverify endpoint.
-const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
-
-/// Verify a Cloudflare Turnstile token. Returns Ok(()) on success.
-async fn verify_turnstile(secret: &str, token: &str) -> AppResult<()> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(TURNSTILE_VERIFY_URL)
-        .form(&[("secret", secret), ("response", token)])
-        .send()
-        .await
-        .map_err(|e| AppError::Internal(anyhow::anyhow!("Turnstile verification request failed: {}", e)))?;
-
-    let body: serde_json::Value = res
-        .json()
-        .await
-        .map_err(|e| AppError::Internal(anyhow::anyhow!("Turnstile response parse failed: {}", e)))?;
-
-    if body.get("success").and_then(|v| v.as_bool()) == Some(true) {
+/// Reject password registration and login when the instance only allows SSO.
+pub(crate) fn require_password_login(state: &AppState) -> AppResult<()> {
+    if state.config.password_login_enabled {
         Ok(())
     } else {
-        Err(AppError::Validation("CAPTCHA verification failed — please try again".into()))
+        Err(AppError::Forbidden("Password login is disabled on this instance — sign in with SSO".into()))
     }
 }
 
-/// Number of leading zero bits required for PoW (20 ≈ ~1M hashes, sub-second on modern hardware)
-const POW_DIFFICULTY: u32 = 20;
-/// TTL for PoW challenges in Redis (seconds)
-const POW_CHALLENGE_TTL: u64 = 300;
+/// A challenge at the difficulty this client gets right now.
+async fn new_challenge(state: &AppState, ip: std::net::IpAddr) -> PowChallengeResponse {
+    let difficulty = pow::current_difficulty(state, ip).await;
+    let challenge = pow::issue_challenge(state, difficulty).await;
 
-/// GET /api/v1/auth/challenge — generate a PoW challenge for registration
-pub async fn pow_challenge(
-    State(state): State<AppState>,
-) -> AppResult<Json<PowChallengeResponse>> {
-    // Generate a random 32-byte challenge
-    let challenge = Uuid::new_v4().to_string();
-
-    // Store challenge with TTL
-    if let Some(mut redis) = state.redis.clone() {
-        let redis_key = format!("haven:pow:{}", challenge);
-        let _: Result<(), redis::RedisError> = redis::cmd("SET")
-            .arg(&redis_key)
-            .arg("1")
-            .arg("EX")
-            .arg(POW_CHALLENGE_TTL)
-            .query_async(&mut redis)
-            .await;
-    } else {
-        let expiry = std::time::Instant::now() + std::time::Duration::from_secs(POW_CHALLENGE_TTL);
-        state.memory.pow_challenges.insert(challenge.clone(), expiry);
-    }
+    let captcha = Captcha::from_config(&state.config);
+    let turnstile_site_key = captcha
+        .as_ref()
+        .filter(|c| c.provider == CaptchaProvider::Turnstile)
+        .map(|c| c.site_key.clone());
 
-    let turnstile_site_key = if state.config.turnstile_enabled() {
-        Some(state.config.turnstile_site_key.clone())
-    } else {
-        None
-    };
-
-    Ok(Json(PowChallengeResponse {
+    PowChallengeResponse {
         challenge,
-        difficulty: POW_DIFFICULTY,
+        difficulty,
         turnstile_site_key,
-    }))
+        captcha: captcha.map(|c| CaptchaInfo {
+            provider: c.provider.as_str().to_string(),
+            site_key: c.site_key,
+        }),
+    }
 }
 
-/// Verify a Proof-of-Work solution: SHA-256(challenge + nonce) must have `difficulty` leading zero bits.
-fn verify_pow(challenge: &str, nonce: &str, difficulty: u32) -> bool {
-    let mut hasher = Sha256::new();
-    hasher.update(challenge.as_bytes());
-    hasher.update(nonce.as_bytes());
-    let hash = hasher.finalize();
-
-    // Check leading zero bits
-    let mut zero_bits = 0u32;
-    for &byte in hash.as_slice() {
-        if byte == 0 {
-            zero_bits += 8;
-        } else {
-            zero_bits += byte.leading_zeros();
-            break;
-        }
-        if zero_bits >= difficulty {
-            break;
-        }
-    }
-    zero_bits >= difficulty
+/// GET /api/v1/auth/challenge — generate a PoW challenge for registration
+/// (and for login while failed logins spike)
+pub async fn pow_challenge(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+) -> AppResult<Json<PowChallengeResponse>> {
+    Ok(Json(new_challenge(&state, ip).await))
 }
 
 /// POST /api/v1/auth/register
@@ -150,49 +108,20 @@ pub async fn register(
     headers: HeaderMap,
     Json(req): Json<RegisterRequest>,
 ) -> AppResult<Json<AuthResponse>> {
+    require_password_login(&state)?;
+
     // Validate request
     req.validate()
         .map_err(|e| AppError::Validation(e.to_string()))?;
 
-    // Verify Proof-of-Work challenge exists and consume it (single-use)
-    let challenge_valid = if let Some(mut redis) = state.redis.clone() {
-        let redis_key = format!("haven:pow:{}", req.pow_challenge);
-        let exists: Option<String> = redis::cmd("GET")
-            .arg(&redis_key)
-            .query_async(&mut redis)
-            .await
-            .unwrap_or(None);
-        if exists.is_some() {
-            let _: Result<(), redis::RedisError> = redis::cmd("DEL")
-                .arg(&redis_key)
-                .query_async(&mut redis)
-                .await;
-            true
-        } else {
-            false
-        }
-    } else {
-        // In-memory: remove and check expiry
-        state.memory.pow_challenges.remove(&req.pow_challenge)
-            .map(|(_, expiry)| std::time::Instant::now() < expiry)
-            .unwrap_or(false)
-    };
-
-    if !challenge_valid {
-        return Err(AppError::Validation(
-            "Invalid or expired PoW challenge — request a new one from /auth/challenge".into(),
-        ));
-    }
-
-    if !verify_pow(&req.pow_challenge, &req.pow_nonce, POW_DIFFICULTY) {
-        return Err(AppError::Validation("Invalid Proof-of-Work solution".into()));
-    }
+    // Verify and consume the Proof-of-Work challenge (single-use)
+    pow::redeem(&state, &req.pow_challenge, &req.pow_nonce).await?;
 
-    // Verify Cloudflare Turnstile CAPTCHA (if enabled)
-    if state.config.turnstile_enabled() {
-        let token = req.turnstile_token.as_deref()
+    // Verify the CAPTCHA (if a provider is configured)
+    if let Some(captcha) = Captcha::from_config(&state.config) {
+        let token = req.captcha_token.as_deref()
             .ok_or(AppError::Validation("CAPTCHA token required".into()))?;
-        verify_turnstile(&state.config.turnstile_secret_key, token).await?;
+        captcha.verify(token, extract_ip_from_headers(&headers).as_deref()).await?;
     }
 
     // Validate registration invite code (if invite-only mode is enabled)
@@ -223,40 +152,128 @@ pub async fn register(
     // Hash password
     let password_hash = auth::hash_password(&req.password)?;
 
-    // Hash email if provided (HMAC-SHA256 with server secret to resist rainbow tables)
-    let email_hash = req.email.as_deref().map(|e| auth::hash_email(e, &state.config.jwt_secret));
+    let keys = RegisterDeviceRequest {
+        name: None,
+        identity_key: req.identity_key,
+        signed_prekey: req.signed_prekey,
+        signed_prekey_signature: req.signed_prekey_signature,
+        signed_prekey_id: req.signed_prekey_id,
+        one_time_prekeys: req.one_time_prekeys,
+        last_resort_prekey: req.last_resort_prekey,
+    };
+    let (user, device) = create_account(
+        &state,
+        &req.username,
+        req.display_name.as_deref(),
+        req.email.as_deref(),
+        &password_hash,
+        &keys,
+    )
+    .await?;
+    pow::record(&state, pow::REGISTRATIONS).await;
+
+    // An address given at registration stays unverified until its link is followed
+    if let (Some(address), Some(mailer)) = (req.email.as_deref(), Mailer::from_config(&state.config)) {
+        let sent = match email::parse_address(address) {
+            Ok(address) => email::send_verification(&state, &mailer, user.id, address).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            tracing::warn!("No verification email for new user {}: {}", user.id, e);
+        }
+    }
+
+    // Consume registration invite and grant new invites to the new user
+    if let Some(invite) = invite_to_consume {
+        queries::consume_registration_invite(state.db.write(), invite.id, user.id).await?;
+        let _ = queries::create_registration_invites(
+            state.db.write(),
+            Some(user.id),
+            state.config.registration_invites_per_user,
+        ).await;
+    }
+
+    let (access_token, refresh_token) = start_session(&state, user.id, &headers).await?;
 
+    Ok(Json(AuthResponse {
+        access_token,
+        refresh_token,
+        user: user.into(),
+        device_id: Some(device.id),
+    }))
+}
+
+/// Create a user whose primary device holds `keys`. The first account on the
+/// instance becomes its admin. Only a keyed hash of `email` is stored
+/// (HMAC-SHA256 under the current email hash key, to resist rainbow tables).
+pub(crate) async fn create_account(
+    state: &AppState,
+    username: &str,
+    display_name: Option<&str>,
+    email: Option<&str>,
+    password_hash: &str,
+    keys: &RegisterDeviceRequest,
+) -> AppResult<(User, Device)> {
     // Decode crypto keys from base64
     let identity_key = base64::Engine::decode(
         &base64::engine::general_purpose::STANDARD,
-        &req.identity_key,
+        &keys.identity_key,
     )
     .map_err(|_| AppError::Validation("Invalid identity_key encoding".into()))?;
 
     let signed_prekey = base64::Engine::decode(
         &base64::engine::general_purpose::STANDARD,
-        &req.signed_prekey,
+        &keys.signed_prekey,
     )
     .map_err(|_| AppError::Validation("Invalid signed_prekey encoding".into()))?;
 
     let signed_prekey_sig = base64::Engine::decode(
         &base64::engine::general_purpose::STANDARD,
-        &req.signed_prekey_signature,
+        &keys.signed_prekey_signature,
     )
     .map_err(|_| AppError::Validation("Invalid signed_prekey_signature encoding".into()))?;
 
+    let signed_prekey_id =
+        crate::api::keys::validate_signed_prekey_id(keys.signed_prekey_id.unwrap_or(1))?;
+
+    let last_resort_prekey = keys
+        .last_resort_prekey
+        .as_deref()
+        .map(|k| base64::Engine::decode(&base64::engine::general_purpose::STANDARD, k))
+        .transpose()
+        .map_err(|_| AppError::Validation("Invalid last_resort_prekey encoding".into()))?;
+
+    let email_hash = email.map(|e| auth::hash_email_current(e, &state.config));
+
     // Create user
     let user = queries::create_user(
         state.db.write(),
-        &req.username,
-        req.display_name.as_deref(),
-        email_hash.as_deref(),
-        &password_hash,
+        username,
+        display_name,
+        email_hash.as_ref().map(|(hash, key_id)| (hash.as_str(), key_id.as_str())),
+        password_hash,
+        &identity_key,
+        &signed_prekey,
+        &signed_prekey_sig,
+    )
+    .await?;
+
+    // The registration keys become the user's primary device
+    let device = queries::create_device(
+        state.db.write(),
+        user.id,
+        None,
         &identity_key,
         &signed_prekey,
         &signed_prekey_sig,
+        signed_prekey_id,
     )
     .await?;
+    crate::api::transparency::publish_identity_key(state, user.id, device.id, &identity_key).await?;
+
+    if let Some(ref key) = last_resort_prekey {
+        queries::set_last_resort_prekey(state.db.write(), device.id, key).await?;
+    }
 
     // Auto-grant instance admin to the first registered user
     if queries::is_first_user(state.db.read()).await.unwrap_or(false) {
@@ -272,19 +289,9 @@ pub async fn register(
         }
     }
 
-    // Consume registration invite and grant new invites to the new user
-    if let Some(invite) = invite_to_consume {
-        queries::consume_registration_invite(state.db.write(), invite.id, user.id).await?;
-        let _ = queries::create_registration_invites(
-            state.db.write(),
-            Some(user.id),
-            state.config.registration_invites_per_user,
-        ).await;
-    }
-
     // Store one-time prekeys
-    if !req.one_time_prekeys.is_empty() {
-        let prekeys: Result<Vec<(i32, Vec<u8>)>, _> = req
+    if !keys.one_time_prekeys.is_empty() {
+        let prekeys: Result<Vec<(i32, Vec<u8>)>, _> = keys
             .one_time_prekeys
             .iter()
             .enumerate()
@@ -298,46 +305,78 @@ pub async fn register(
             })
             .collect();
 
-        queries::insert_prekeys(state.db.write(), user.id, &prekeys?).await?;
+        queries::insert_prekeys(state.db.write(), user.id, device.id, &prekeys?).await?;
     }
 
-    // Generate tokens with a new token family
+    Ok((user, device))
+}
+
+/// Issue an access token and a refresh token in a new token family, recording
+/// the session's device name and IP and flagging sign-ins from new devices.
+/// A deactivated account is reactivated.
+pub(crate) async fn start_session(
+    state: &AppState,
+    user_id: Uuid,
+    headers: &HeaderMap,
+) -> AppResult<(String, String)> {
+    // Signing in undoes a deactivation or a pending deletion, unless an
+    // admin scheduled it
+    accounts::reactivate_on_login(state, user_id, headers).await?;
+
     let family_id = Uuid::new_v4();
-    let access_token = auth::generate_access_token(user.id, &state.config)?;
+    let access_token = auth::generate_access_token(user_id, Some(family_id), &state.config, &state.jwt_keys)?;
     let refresh_token = auth::generate_refresh_token();
     let refresh_hash = auth::hash_refresh_token(&refresh_token);
 
     let device = headers.get("user-agent").and_then(|v| v.to_str().ok()).map(parse_device_name);
-    let ip = extract_ip_from_headers(&headers);
+    let ip = extract_ip_from_headers(headers);
     let expiry = Utc::now() + Duration::days(state.config.refresh_token_expiry_days);
     queries::store_refresh_token_with_metadata(
-        state.db.write(), user.id, &refresh_hash, expiry, Some(family_id),
+        state.db.write(), user_id, &refresh_hash, expiry, Some(family_id),
         device.as_deref(), ip.as_deref(),
     ).await?;
 
-    Ok(Json(AuthResponse {
-        access_token,
-        refresh_token,
-        user: user.into(),
-    }))
+    if let Err(e) = security_events::note_login(state, user_id, headers).await {
+        tracing::warn!("Failed to record login device for user {}: {}", user_id, e);
+    }
+
+    Ok((access_token, refresh_token))
 }
 
 /// POST /api/v1/auth/login
 pub async fn login(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     headers: HeaderMap,
     Json(req): Json<LoginRequest>,
 ) -> AppResult<LoginResponse> {
-    // Find user
-    let user = queries::find_user_by_username(state.db.read(), &req.username)
-        .await?
-        .ok_or(AppError::AuthError("Invalid username or password".into()))?;
-
-    // Verify password
-    if !auth::verify_password(&req.password, &user.password_hash)? {
-        return Err(AppError::AuthError("Invalid username or password".into()));
+    require_password_login(&state)?;
+
+    // A locked-out username is refused before its password is even checked
+    login_throttle::check(&state, &req.username).await?;
+
+    // While failed logins spike, every attempt costs a PoW solution
+    if pow::login_pow_required(&state).await {
+        match (req.pow_challenge.as_deref(), req.pow_nonce.as_deref()) {
+            (Some(challenge), Some(nonce)) => pow::redeem(&state, challenge, nonce).await?,
+            _ => {
+                return Ok(LoginResponse::PowRequired {
+                    pow_required: true,
+                    challenge: new_challenge(&state, ip).await,
+                });
+            }
+        }
     }
 
+    // Find user and verify password
+    let user = match queries::find_user_by_username(state.db.read(), &req.username).await? {
+        Some(user) if auth::verify_password(&req.password, &user.password_hash)? => user,
+        user => {
+            record_login_failure(&state, &req.username, user.map(|u| u.id), &headers).await;
+            return Err(AppError::AuthError("Invalid username or password".into()));
+        }
+    };
+
     // Verify TOTP if enabled
     if let Some(ref secret) = user.totp_secret {
         match req.totp_code.as_deref() {
@@ -347,33 +386,43 @@ pub async fn login(
             }
             Some(code) => {
                 if !auth::verify_totp(secret, code)? {
+                    record_login_failure(&state, &req.username, Some(user.id), &headers).await;
                     return Err(AppError::AuthError("Invalid TOTP code".into()));
                 }
             }
         }
     }
 
-    // Generate tokens with a new token family
-    let family_id = Uuid::new_v4();
-    let access_token = auth::generate_access_token(user.id, &state.config)?;
-    let refresh_token = auth::generate_refresh_token();
-    let refresh_hash = auth::hash_refresh_token(&refresh_token);
-
-    let device = headers.get("user-agent").and_then(|v| v.to_str().ok()).map(parse_device_name);
-    let ip = extract_ip_from_headers(&headers);
-    let expiry = Utc::now() + Duration::days(state.config.refresh_token_expiry_days);
-    queries::store_refresh_token_with_metadata(
-        state.db.write(), user.id, &refresh_hash, expiry, Some(family_id),
-        device.as_deref(), ip.as_deref(),
-    ).await?;
+    login_throttle::clear(&state, &req.username).await;
+    let (access_token, refresh_token) = start_session(&state, user.id, &headers).await?;
 
     Ok(LoginResponse::Success(Box::new(AuthResponse {
         access_token,
         refresh_token,
         user: user.into(),
+        device_id: None,
     })))
 }
 
+/// Count a failed login instance-wide and against the username. The first
+/// time an existing account gets locked out, that goes into its security
+/// event history.
+async fn record_login_failure(state: &AppState, username: &str, user_id: Option<Uuid>, headers: &HeaderMap) {
+    pow::record(state, pow::LOGIN_FAILURES).await;
+    let (failures, lockout) = login_throttle::record_failure(state, username).await;
+    if lockout == 0 {
+        return;
+    }
+    tracing::warn!("Login for '{}' locked for {}s after {} failures", username, lockout, failures);
+    if let Some(user_id) = user_id {
+        if failures == state.config.login_lockout_free_attempts + 1 {
+            if let Err(e) = security_events::record(state, user_id, security_events::ACCOUNT_LOCKED, headers).await {
+                tracing::warn!("Failed to record lockout for user {}: {}", user_id, e);
+            }
+        }
+    }
+}
+
 /// POST /api/v1/auth/refresh
 /// Implements token family rotation with theft detection:
 /// - If the token is valid and not revoked: rotate normally, mark old as revoked
@@ -399,9 +448,10 @@ pub async fn refresh_token(
         );
         if let Some(family_id) = stored_token.family_id {
             queries::revoke_token_family(state.db.write(), family_id).await?;
+            revocation::revoke_families(&state, stored_token.user_id, &[family_id]).await;
         }
-        // Also revoke all tokens for this user as a safety measure
-        queries::revoke_all_user_refresh_tokens(state.db.write(), stored_token.user_id).await?;
+        // Also revoke all sessions and API tokens for this user as a safety measure
+        revocation::revoke_all_credentials(&state, stored_token.user_id).await?;
         return Err(AppError::AuthError(
             "Token reuse detected — all sessions revoked for security. Please log in again.".into(),
         ));
@@ -416,7 +466,7 @@ pub async fn refresh_token(
         .ok_or(AppError::UserNotFound)?;
 
     // Generate new token in the same family
-    let access_token = auth::generate_access_token(user.id, &state.config)?;
+    let access_token = auth::generate_access_token(user.id, stored_token.family_id, &state.config, &state.jwt_keys)?;
     let new_refresh_token = auth::generate_refresh_token();
     let new_refresh_hash = auth::hash_refresh_token(&new_refresh_token);
 
@@ -434,6 +484,7 @@ pub async fn refresh_token(
         access_token,
         refresh_token: new_refresh_token,
         user: user.into(),
+        device_id: None,
     }))
 }
 
@@ -442,8 +493,9 @@ pub async fn logout(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
 ) -> AppResult<Json<serde_json::Value>> {
-    // Revoke all refresh tokens for this user
-    queries::revoke_all_user_refresh_tokens(state.db.write(), user_id).await?;
+    // Revoke every session of this user, including live access tokens, and
+    // their API tokens
+    revocation::revoke_all_credentials(&state, user_id).await?;
 
     // Broadcast offline presence and clean up voice state
     crate::ws::broadcast_presence(user_id, "offline", &state).await;
@@ -478,21 +530,146 @@ pub async fn list_sessions(
     Ok(Json(sessions))
 }
 
+/// Most personal API tokens one account may hold
+const MAX_API_TOKENS: i64 = 50;
+
+fn api_token_response(t: ApiToken) -> ApiTokenResponse {
+    ApiTokenResponse {
+        id: t.id,
+        name: t.name,
+        token_prefix: t.token_prefix,
+        scopes: t.scopes.split_whitespace().map(String::from).collect(),
+        expires_at: t.expires_at,
+        last_used_at: t.last_used_at,
+        last_used_ip: t.last_used_ip.map(|ip| mask_ip(&ip)),
+        created_at: t.created_at,
+    }
+}
+
+/// GET /api/v1/auth/tokens — list the current user's personal API tokens
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<ApiTokenResponse>>> {
+    let tokens = queries::list_api_tokens(state.db.read(), user_id).await?;
+    Ok(Json(tokens.into_iter().map(api_token_response).collect()))
+}
+
+/// POST /api/v1/auth/tokens — create a personal API token. The token itself
+/// is only in this response.
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
+    Json(req): Json<CreateApiTokenRequest>,
+) -> AppResult<(StatusCode, Json<CreateApiTokenResponse>)> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err(AppError::Validation("Token name must be 1-64 characters".into()));
+    }
+    if req.scopes.is_empty() {
+        return Err(AppError::Validation("A token needs at least one scope".into()));
+    }
+    let mut scopes: Vec<api_tokens::Scope> = Vec::new();
+    for raw in &req.scopes {
+        let scope = api_tokens::Scope::parse(raw)
+            .ok_or_else(|| AppError::Validation(format!("Unknown scope '{}'", raw)))?;
+        if let api_tokens::Scope::ManageServer(server_id) = scope {
+            if !queries::is_server_member(state.db.read(), server_id, user_id).await? {
+                return Err(AppError::Validation(format!("You are not a member of server {}", server_id)));
+            }
+        }
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    let expires_at = match req.expires_in_days {
+        Some(days) if !(1..=365).contains(&days) => {
+            return Err(AppError::Validation("expires_in_days must be 1-365".into()));
+        }
+        Some(days) => Some(Utc::now() + Duration::days(days)),
+        None => None,
+    };
+    if queries::count_api_tokens(state.db.read(), user_id).await? >= MAX_API_TOKENS {
+        return Err(AppError::Validation(format!(
+            "Maximum of {} API tokens — revoke one first",
+            MAX_API_TOKENS
+        )));
+    }
+
+    let token = api_tokens::generate_token();
+    let scopes = scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" ");
+    let record = queries::insert_api_token(
+        state.db.write(),
+        user_id,
+        name,
+        &auth::hash_refresh_token(&token),
+        &token[..api_tokens::TOKEN_PREFIX.len() + 4],
+        &scopes,
+        expires_at,
+    )
+    .await?;
+    security_events::record(&state, user_id, security_events::API_TOKEN_CREATED, &headers).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiTokenResponse {
+            info: api_token_response(record),
+            token,
+        }),
+    ))
+}
+
+/// DELETE /api/v1/auth/tokens/:token_id — revoke a personal API token
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    axum::extract::Path(token_id): axum::extract::Path<Uuid>,
+) -> AppResult<Json<serde_json::Value>> {
+    if !queries::delete_api_token(state.db.write(), user_id, token_id).await? {
+        return Err(AppError::NotFound("API token not found".into()));
+    }
+    Ok(Json(serde_json::json!({ "message": "API token revoked" })))
+}
+
+/// GET /api/v1/auth/security-events — the current user's security event
+/// history, newest first
+pub async fn list_security_events(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    axum::extract::Query(query): axum::extract::Query<SecurityEventQuery>,
+) -> AppResult<Json<Vec<SecurityEventResponse>>> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+    let events = queries::list_security_events(state.db.read(), user_id, limit, query.before).await?;
+
+    Ok(Json(
+        events
+            .into_iter()
+            .map(|e| SecurityEventResponse {
+                id: e.id,
+                kind: e.kind,
+                device_name: e.device_name,
+                ip_address: e.ip_address.map(|ip| mask_ip(&ip)),
+                created_at: e.created_at,
+            })
+            .collect(),
+    ))
+}
+
 /// DELETE /api/v1/auth/sessions/:family_id — revoke a specific session
 pub async fn revoke_session(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
     axum::extract::Path(family_id): axum::extract::Path<Uuid>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let deleted = queries::revoke_session(state.db.write(), user_id, family_id).await?;
-    if deleted == 0 {
+    if !revocation::revoke_session(&state, user_id, family_id).await? {
         return Err(AppError::NotFound("Session not found".into()));
     }
     Ok(Json(serde_json::json!({ "message": "Session revoked" })))
 }
 
 /// Partially mask an IP address for privacy (show first two octets).
-fn mask_ip(ip: &str) -> String {
+pub(crate) fn mask_ip(ip: &str) -> String {
     let parts: Vec<&str> = ip.split('.').collect();
     if parts.len() == 4 {
         format!("{}.{}.x.x", parts[0], parts[1])
@@ -559,6 +736,7 @@ pub async fn totp_verify(
 pub async fn change_password(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
     Json(req): Json<ChangePasswordRequest>,
 ) -> AppResult<Json<serde_json::Value>> {
     // Validate new password length
@@ -579,8 +757,10 @@ pub async fn change_password(
     let new_hash = auth::hash_password(&req.new_password)?;
     queries::update_user_password(state.db.write(), user_id, &new_hash).await?;
 
-    // Revoke all refresh tokens (force re-login everywhere)
-    queries::revoke_all_user_refresh_tokens(state.db.write(), user_id).await?;
+    // Revoke all sessions and API tokens (force re-login everywhere)
+    revocation::revoke_all_credentials(&state, user_id).await?;
+
+    security_events::record(&state, user_id, security_events::PASSWORD_CHANGED, &headers).await?;
 
     Ok(Json(serde_json::json!({ "message": "Password changed" })))
 }
@@ -594,13 +774,14 @@ pub async fn totp_disable(
     Ok(Json(serde_json::json!({ "message": "TOTP disabled" })))
 }
 
-/// POST /api/v1/auth/delete-account — permanently delete the user's account
-pub async fn delete_account(
+/// POST /api/v1/auth/deactivate — hide the account and sign out everywhere
+/// until the next sign-in
+pub async fn deactivate_account(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
-    Json(req): Json<DeleteAccountRequest>,
-) -> AppResult<StatusCode> {
-    // Verify password
+    headers: HeaderMap,
+    Json(req): Json<DeactivateAccountRequest>,
+) -> AppResult<Json<serde_json::Value>> {
     let user = queries::find_user_by_id(state.db.read(), user_id)
         .await?
         .ok_or(AppError::UserNotFound)?;
@@ -609,89 +790,30 @@ pub async fn delete_account(
         return Err(AppError::AuthError("Incorrect password".into()));
     }
 
-    // 1. Delete servers owned by this user (cascade removes channels, members, etc.)
-    let owned_servers = queries::get_servers_owned_by(state.db.read(), user_id).await?;
-    for server in &owned_servers {
-        // Clean up custom emoji storage for this server
-        let emojis = queries::list_server_emojis(state.db.read(), server.id).await.unwrap_or_default();
-        for emoji in &emojis {
-            let _ = state.storage.delete_blob(&emoji.storage_key).await;
-        }
-        // Delete server (CASCADE handles members, channels, emojis, etc.)
-        sqlx::query("DELETE FROM servers WHERE id = $1")
-            .bind(server.id)
-            .execute(state.db.write())
-            .await
-            .ok();
-    }
+    accounts::deactivate(&state, user_id, &headers).await?;
+    Ok(Json(serde_json::json!({ "message": "Account deactivated — sign in to reactivate it" })))
+}
 
-    // 2. Clean up message children that reference the partitioned messages table
-    //    (no FK cascade on partitioned tables in PG < 17)
-    sqlx::query("DELETE FROM attachments WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-    sqlx::query("DELETE FROM reactions WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-    sqlx::query("DELETE FROM pinned_messages WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-    sqlx::query("DELETE FROM reports WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-
-    // 3. Delete user's messages
-    sqlx::query("DELETE FROM messages WHERE sender_id = $1")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-
-    // 4. Also delete reactions by this user on other messages
-    sqlx::query("DELETE FROM reactions WHERE user_id = $1")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .ok();
-
-    // 5. Revoke tokens, broadcast offline, clean up voice
-    queries::revoke_all_user_refresh_tokens(state.db.write(), user_id).await.ok();
-    crate::ws::broadcast_presence(user_id, "offline", &state).await;
-    crate::api::voice::cleanup_voice_state(&state, user_id).await;
+/// POST /api/v1/auth/delete-account — delete the user's account after the
+/// grace period. Signing in before then cancels the deletion.
+pub async fn delete_account(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
+    Json(req): Json<DeleteAccountRequest>,
+) -> AppResult<(StatusCode, Json<AccountDeletionResponse>)> {
+    // Verify password
+    let user = queries::find_user_by_id(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
 
-    // 6. Close active WS connections
-    if let Some((_, conns)) = state.connections.remove(&user_id) {
-        for tx in conns {
-            let _ = tx.send(WsServerMessage::Error {
-                message: "Account deleted".into(),
-            });
-        }
+    if !auth::verify_password(&req.password, &user.password_hash)? {
+        return Err(AppError::AuthError("Incorrect password".into()));
     }
 
-    // 7. Delete user (FK CASCADE handles server_members, channel_members,
-    //    friendships, blocks, prekeys, key_backups, sender_key_distributions, etc.)
-    sqlx::query("DELETE FROM users WHERE id = $1")
-        .bind(user_id)
-        .execute(state.db.write())
-        .await
-        .map_err(AppError::Database)?;
-
-    // 8. Clean up stored files (avatar, banner)
-    let avatar_key = storage::obfuscated_key(&state.storage_key, &format!("avatar:{}", user_id));
-    let banner_key = storage::obfuscated_key(&state.storage_key, &format!("banner:{}", user_id));
-    let _ = state.storage.delete_blob(&avatar_key).await;
-    let _ = state.storage.delete_blob(&banner_key).await;
-
-    // 9. Invalidate caches
-    crate::cache::invalidate(state.redis.clone().as_mut(), &state.memory, &format!("haven:user:{}", user_id)).await;
+    // Servers outlive their owner only through a transfer
+    accounts::require_no_owned_servers(&state, user_id).await?;
 
-    Ok(StatusCode::NO_CONTENT)
+    let deletion_scheduled_at = accounts::schedule_deletion(&state, user_id, &headers, false).await?;
+    Ok((StatusCode::ACCEPTED, Json(AccountDeletionResponse { deletion_scheduled_at })))
 }
@@ -9,6 +9,7 @@ use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
 use crate::models::*;
 use crate::permissions;
+use crate::rekey::ChannelAccess;
 use crate::AppState;
 
 /// POST /api/v1/servers/:server_id/bans/:target_user_id
@@ -42,7 +43,10 @@ pub async fn ban_member(
     .await?;
 
     // Also kick them from the server if they are a member
+    let access = ChannelAccess::of_user(&state, server_id, target_user_id).await;
     let _ = queries::remove_server_member(state.db.write(), server_id, target_user_id).await;
+    crate::member_list::refresh(&state, server_id).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::Banned).await;
 
     // Look up username for response
     let target = queries::find_user_by_id(state.db.read(), target_user_id)
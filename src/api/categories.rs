@@ -186,5 +186,6 @@ pub async fn set_channel_category(
         dm_status: updated.dm_status,
         last_message_id: None,
         is_private: updated.is_private,
+        meta_epoch: updated.meta_epoch,
     }))
 }
@@ -38,6 +38,8 @@ pub async fn create_channel(
         return Err(AppError::Validation("encrypted_meta exceeds maximum size (8KB)".into()));
     }
 
+    crate::api::server_keys::require_current_epoch(&state, server_id, req.meta_epoch).await?;
+
     let channel_type = req.channel_type.as_deref().unwrap_or("text");
     let position = req.position.unwrap_or(0);
     let is_private = req.is_private.unwrap_or(false);
@@ -80,6 +82,7 @@ pub async fn create_channel(
         dm_status: channel.dm_status,
         last_message_id: None,
         is_private: channel.is_private,
+        meta_epoch: channel.meta_epoch,
     }))
 }
 
@@ -135,6 +138,7 @@ pub async fn create_dm(
             dm_status: existing.dm_status,
             last_message_id: None,
             is_private: false,
+            meta_epoch: existing.meta_epoch,
         }));
     }
 
@@ -184,7 +188,7 @@ pub async fn create_dm(
 
     // If pending, notify the target user via WS
     if dm_status == "pending" {
-        send_to_user(&state, req.target_user_id, WsServerMessage::DmRequestReceived {
+        crate::pubsub::send_to_user(&state, req.target_user_id, &WsServerMessage::DmRequestReceived {
             channel_id: channel.id,
             from_user_id: user_id,
         }).await;
@@ -204,6 +208,7 @@ pub async fn create_dm(
         dm_status: Some(dm_status.to_string()),
         last_message_id: None,
         is_private: false,
+        meta_epoch: channel.meta_epoch,
     }))
 }
 
@@ -230,6 +235,7 @@ pub async fn list_dm_channels(
             dm_status: ch.dm_status,
             last_message_id: None,
             is_private: ch.is_private,
+            meta_epoch: ch.meta_epoch,
         })
         .collect();
     Ok(Json(responses))
@@ -269,6 +275,8 @@ pub async fn update_channel(
         return Err(AppError::Validation("encrypted_meta exceeds maximum size (8KB)".into()));
     }
 
+    crate::api::server_keys::require_current_epoch(&state, server_id, req.meta_epoch).await?;
+
     let updated = queries::update_channel_meta(state.db.write(), channel_id, &encrypted_meta).await?;
 
     // Audit log
@@ -293,6 +301,7 @@ pub async fn update_channel(
         dm_status: updated.dm_status,
         last_message_id: None,
         is_private: updated.is_private,
+        meta_epoch: updated.meta_epoch,
     }))
 }
 
@@ -440,6 +449,7 @@ pub async fn create_group_dm(
         dm_status: Some("active".to_string()),
         last_message_id: None,
         is_private: false,
+        meta_epoch: channel.meta_epoch,
     }))
 }
 
@@ -477,6 +487,9 @@ pub async fn leave_channel(
     if remaining.is_empty() {
         queries::delete_channel(state.db.write(), channel_id).await?;
     } else {
+        crate::rekey::rekey_channel(&state, channel_id, &[user_id], SenderKeyRotationReason::Left)
+            .await;
+
         // Insert system message about the user leaving
         let body = serde_json::json!({
             "event": "member_left",
@@ -547,16 +560,9 @@ pub struct CreateDmRequest {
 #[derive(Debug, serde::Deserialize)]
 pub struct UpdateChannelRequest {
     pub encrypted_meta: String, // base64
-}
-
-/// Send a WS message to a specific user (all their connections + Redis pub/sub).
-async fn send_to_user(state: &AppState, user_id: Uuid, msg: WsServerMessage) {
-    if let Some(conns) = state.connections.get(&user_id) {
-        for tx in conns.iter() {
-            let _ = tx.send(msg.clone());
-        }
-    }
-    crate::pubsub::publish_user_event(state.redis.clone().as_mut(), user_id, &msg).await;
+    /// Server key epoch the client encrypted `encrypted_meta` under
+    #[serde(default)]
+    pub meta_epoch: Option<i32>,
 }
 
 /// PUT /api/v1/channels/:channel_id/read-state
@@ -579,12 +585,7 @@ pub async fn mark_channel_read(
         channel_id,
         last_read_at: read_state.last_read_at,
     };
-    if let Some(conns) = state.connections.get(&user_id) {
-        for conn in conns.iter() {
-            let _ = conn.send(sync_msg.clone());
-        }
-    }
-    crate::pubsub::publish_user_event(state.redis.clone().as_mut(), user_id, &sync_msg).await;
+    crate::pubsub::send_to_user(&state, user_id, &sync_msg).await;
 
     Ok(Json(read_state))
 }
@@ -0,0 +1,165 @@
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::api::auth_routes::{extract_ip_from_headers, mask_ip, parse_device_name, start_session};
+use crate::api::{b64, decode_b64};
+use crate::auth;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::AuthUser;
+use crate::models::*;
+use crate::security_events;
+use crate::AppState;
+
+/// How long a new client's link request can be approved and collected
+const LINK_TTL_SECS: i64 = 300;
+const MAX_PROVISIONING_SIZE: usize = 64 * 1024; // 64 KB
+
+/// Decode a base64 X25519 public key.
+fn decode_key(value: &str, field: &str) -> AppResult<Vec<u8>> {
+    let key = decode_b64(value, field)?;
+    if key.len() != 32 {
+        return Err(AppError::Validation(format!("{} must be 32 bytes", field)));
+    }
+    Ok(key)
+}
+
+/// POST /api/v1/auth/link
+/// Start linking a new client. It shows `link_uri` as a QR code and polls
+/// with `link_token` until a signed-in session approves.
+pub async fn start_device_link(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<StartDeviceLinkRequest>,
+) -> AppResult<Json<StartDeviceLinkResponse>> {
+    let ephemeral_public_key = decode_key(&req.ephemeral_public_key, "ephemeral_public_key")?;
+
+    let link_id = Uuid::new_v4();
+    // Same shape as refresh tokens: 48 random bytes, stored as a SHA-256 hash
+    let link_token = auth::generate_refresh_token();
+    let expires_at = Utc::now() + Duration::seconds(LINK_TTL_SECS);
+    let device = parse_device_name(headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or(""));
+    let ip = extract_ip_from_headers(&headers);
+    queries::insert_device_link(
+        state.db.write(),
+        link_id,
+        &auth::hash_refresh_token(&link_token),
+        &ephemeral_public_key,
+        Some(&device),
+        ip.as_deref(),
+        expires_at,
+    )
+    .await?;
+
+    // The key travels in the fragment, so opening the link never sends it anywhere
+    let key = base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, &ephemeral_public_key);
+    let link_uri = format!("{}/link-device#id={}&key={}", state.config.public_base_url(), link_id, key);
+
+    Ok(Json(StartDeviceLinkResponse {
+        link_id,
+        link_token,
+        link_uri,
+        expires_at,
+    }))
+}
+
+/// GET /api/v1/auth/link/:link_id
+/// Which client is asking to be linked, shown before approving.
+pub async fn get_device_link(
+    State(state): State<AppState>,
+    AuthUser(_user_id): AuthUser,
+    Path(link_id): Path<Uuid>,
+) -> AppResult<Json<DeviceLinkInfoResponse>> {
+    let link = queries::find_pending_device_link(state.db.write(), link_id)
+        .await?
+        .ok_or(AppError::NotFound("Link request not found or expired".into()))?;
+
+    Ok(Json(DeviceLinkInfoResponse {
+        link_id: link.id,
+        device_name: link.device_name,
+        ip_address: link.ip_address.map(|ip| mask_ip(&ip)),
+        created_at: link.created_at,
+        expires_at: link.expires_at,
+    }))
+}
+
+/// POST /api/v1/auth/link/:link_id/approve
+/// Sign the scanned client in to this account, handing it a provisioning
+/// message encrypted to its ephemeral key. The server can't read it.
+pub async fn approve_device_link(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(link_id): Path<Uuid>,
+    Json(req): Json<ApproveDeviceLinkRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let ephemeral_public_key = decode_key(&req.ephemeral_public_key, "ephemeral_public_key")?;
+    let approver_public_key = decode_key(&req.approver_public_key, "approver_public_key")?;
+    let provisioning_message = decode_b64(&req.provisioning_message, "provisioning_message")?;
+    if provisioning_message.is_empty() || provisioning_message.len() > MAX_PROVISIONING_SIZE {
+        return Err(AppError::Validation("provisioning_message must be 1 byte to 64 KB".into()));
+    }
+
+    // The key must be the one in the QR code, so a session can't approve a
+    // link it never scanned
+    let approved = queries::approve_device_link(
+        state.db.write(),
+        link_id,
+        &ephemeral_public_key,
+        user_id,
+        &approver_public_key,
+        &provisioning_message,
+    )
+    .await?;
+    if !approved {
+        return Err(AppError::NotFound("Link request not found, expired or already approved".into()));
+    }
+
+    tracing::info!("User {} approved device link {}", user_id, link_id);
+    Ok(Json(serde_json::json!({ "message": "Device link approved" })))
+}
+
+/// POST /api/v1/auth/link/:link_id/poll
+/// `{ pending: true }` until the link is approved; then, once, a new session
+/// for the approving account and the provisioning message.
+pub async fn poll_device_link(
+    State(state): State<AppState>,
+    Path(link_id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(req): Json<PollDeviceLinkRequest>,
+) -> AppResult<DeviceLinkPollResponse> {
+    let token_hash = auth::hash_refresh_token(&req.link_token);
+
+    let Some(link) = queries::take_approved_device_link(state.db.write(), link_id, &token_hash).await? else {
+        if queries::device_link_is_pending(state.db.write(), link_id, &token_hash).await? {
+            return Ok(DeviceLinkPollResponse::Pending { pending: true });
+        }
+        return Err(AppError::NotFound("Link request not found or expired".into()));
+    };
+    let (Some(user_id), Some(approver_public_key), Some(provisioning_message)) =
+        (link.approved_by, link.approver_public_key, link.provisioning_message)
+    else {
+        return Err(AppError::NotFound("Link request not found or expired".into()));
+    };
+
+    let user = queries::find_user_by_id(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    let (access_token, refresh_token) = start_session(&state, user.id, &headers).await?;
+    security_events::record(&state, user.id, security_events::DEVICE_LINKED, &headers).await?;
+
+    Ok(DeviceLinkPollResponse::Linked(Box::new(DeviceLinkResult {
+        auth: AuthResponse {
+            access_token,
+            refresh_token,
+            user: user.into(),
+            device_id: None,
+        },
+        approver_public_key: b64(&approver_public_key),
+        provisioning_message: b64(&provisioning_message),
+    })))
+}
@@ -0,0 +1,171 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use lettre::Address;
+
+use crate::api::auth_routes::require_password_login;
+use crate::auth;
+use crate::db::queries;
+use crate::email::{self, Mailer};
+use crate::errors::{AppError, AppResult};
+use crate::login_throttle;
+use crate::middleware::AuthUser;
+use crate::models::*;
+use crate::revocation;
+use crate::security_events;
+use crate::AppState;
+
+fn mailer(state: &AppState) -> AppResult<Mailer> {
+    Mailer::from_config(&state.config)
+        .ok_or_else(|| AppError::NotFound("Email is not configured on this instance".into()))
+}
+
+/// GET /api/v1/auth/email
+pub async fn email_status(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<EmailStatusResponse>> {
+    let user = queries::find_user_by_id(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    let verification_pending =
+        queries::has_pending_email_token(state.db.read(), user_id, email::VERIFY_EMAIL).await?;
+
+    Ok(Json(EmailStatusResponse {
+        email_enabled: state.config.email_enabled(),
+        email_set: user.email_hash.is_some(),
+        verified: user.email_verified_at.is_some(),
+        verification_pending,
+    }))
+}
+
+/// PUT /api/v1/auth/email
+/// Send a verification link to a new address. The account's email changes
+/// only once the link is followed.
+pub async fn set_email(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<SetEmailRequest>,
+) -> AppResult<(StatusCode, Json<serde_json::Value>)> {
+    let mailer = mailer(&state)?;
+    let user = queries::find_user_by_id(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    if !auth::verify_password(&req.password, &user.password_hash)? {
+        return Err(AppError::AuthError("Incorrect password".into()));
+    }
+
+    let address = email::parse_address(&req.email)?;
+    email::send_verification(&state, &mailer, user_id, address).await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "message": "Verification email sent" })),
+    ))
+}
+
+/// DELETE /api/v1/auth/email — forget the account's email (hash)
+pub async fn remove_email(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<serde_json::Value>> {
+    queries::set_user_email_hash(state.db.write(), user_id, None, false).await?;
+    queries::delete_email_tokens(state.db.write(), user_id, email::VERIFY_EMAIL).await?;
+    queries::delete_email_tokens(state.db.write(), user_id, email::PASSWORD_RESET).await?;
+    Ok(Json(serde_json::json!({ "message": "Email removed" })))
+}
+
+/// POST /api/v1/auth/email/verify
+/// Confirm an address from the emailed link. Works without a session, since
+/// the link may be opened on another device.
+pub async fn verify_email(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<VerifyEmailRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let token = email::redeem_token(&state, &req.token, email::VERIFY_EMAIL).await?;
+    let (Some(hash), Some(key_id)) = (token.email_hash.as_deref(), token.email_hash_key_id.as_deref()) else {
+        return Err(AppError::Validation("Invalid or expired link — request a new one".into()));
+    };
+
+    if let Some(other) = queries::find_user_by_verified_email(state.db.read(), &[hash.to_string()]).await? {
+        if other.id != token.user_id {
+            return Err(AppError::Conflict("This email is already verified on another account".into()));
+        }
+    }
+    queries::set_user_email_hash(state.db.write(), token.user_id, Some((hash, key_id)), true).await?;
+    security_events::record(&state, token.user_id, security_events::EMAIL_VERIFIED, &headers).await?;
+
+    Ok(Json(serde_json::json!({ "message": "Email verified" })))
+}
+
+/// POST /api/v1/auth/password-reset
+/// Email a reset link if the address is verified on some account. The
+/// response is the same either way.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(req): Json<PasswordResetRequest>,
+) -> AppResult<(StatusCode, Json<serde_json::Value>)> {
+    require_password_login(&state)?;
+    let mailer = mailer(&state)?;
+    let address = email::parse_address(&req.email)?;
+
+    let candidates = auth::email_hash_candidates(address.as_ref(), &state.config);
+    if let Some(user) = queries::find_user_by_verified_email(state.db.read(), &candidates).await? {
+        // Finish in the background, so neither the response time nor an
+        // error gives away that the account exists
+        tokio::spawn(async move {
+            if let Err(e) = send_reset_link(&state, &mailer, &user, address).await {
+                tracing::warn!("Failed to send password reset to user {}: {}", user.id, e);
+            }
+        });
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "message": "If that address is verified on an account, a reset link is on its way"
+        })),
+    ))
+}
+
+async fn send_reset_link(state: &AppState, mailer: &Mailer, user: &User, address: Address) -> AppResult<()> {
+    // The address is at hand, so move a hash made before a key rotation
+    // to the current key
+    let (hash, key_id) = auth::hash_email_current(address.as_ref(), &state.config);
+    if user.email_hash.as_deref() != Some(hash.as_str()) {
+        queries::rehash_user_email(state.db.write(), user.id, &hash, &key_id).await?;
+    }
+    email::send_password_reset(state, mailer, user.id, &user.username, address).await
+}
+
+/// POST /api/v1/auth/password-reset/confirm
+/// Set a new password from an emailed reset link, sign out every session and
+/// revoke every API token.
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<PasswordResetConfirmRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    require_password_login(&state)?;
+    if req.new_password.len() < 8 || req.new_password.len() > 128 {
+        return Err(AppError::Validation("New password must be 8-128 characters".into()));
+    }
+
+    let token = email::redeem_token(&state, &req.token, email::PASSWORD_RESET).await?;
+    let user = queries::find_user_by_id(state.db.read(), token.user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+
+    let new_hash = auth::hash_password(&req.new_password)?;
+    queries::update_user_password(state.db.write(), user.id, &new_hash).await?;
+
+    // Whoever held the old password loses every session and API token
+    revocation::revoke_all_credentials(&state, user.id).await?;
+    login_throttle::clear(&state, &user.username).await;
+    security_events::record(&state, user.id, security_events::PASSWORD_RESET, &headers).await?;
+
+    Ok(Json(serde_json::json!({ "message": "Password reset — sign in with your new password" })))
+}
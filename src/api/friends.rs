@@ -29,9 +29,10 @@ pub async fn send_friend_request(
     AuthUser(user_id): AuthUser,
     Json(req): Json<FriendRequestBody>,
 ) -> AppResult<Json<FriendResponse>> {
-    // Find target user
+    // Find target user (deactivated accounts can't be found)
     let target = queries::find_user_by_username(state.db.read(), &req.username)
         .await?
+        .filter(|u| u.deactivated_at.is_none())
         .ok_or(AppError::UserNotFound)?;
 
     if target.id == user_id {
@@ -56,7 +57,7 @@ pub async fn send_friend_request(
                 .ok_or(AppError::UserNotFound)?;
 
             // Notify the original requester that we accepted
-            send_to_user(&state, target.id, WsServerMessage::FriendRequestAccepted {
+            crate::pubsub::send_to_user(&state, target.id, &WsServerMessage::FriendRequestAccepted {
                 user_id,
                 username: queries::find_user_by_id(state.db.read(), user_id)
                     .await?
@@ -87,7 +88,7 @@ pub async fn send_friend_request(
         .ok_or(AppError::UserNotFound)?;
 
     // Notify the target via WS
-    send_to_user(&state, target.id, WsServerMessage::FriendRequestReceived {
+    crate::pubsub::send_to_user(&state, target.id, &WsServerMessage::FriendRequestReceived {
         from_user_id: user_id,
         from_username: requester_user.username.clone(),
         friendship_id: friendship.id,
@@ -134,7 +135,7 @@ pub async fn accept_friend_request(
         .ok_or(AppError::UserNotFound)?;
 
     // Notify the requester
-    send_to_user(&state, accepted.requester_id, WsServerMessage::FriendRequestAccepted {
+    crate::pubsub::send_to_user(&state, accepted.requester_id, &WsServerMessage::FriendRequestAccepted {
         user_id,
         username: accepter.username,
         friendship_id: accepted.id,
@@ -199,7 +200,7 @@ pub async fn remove_friend(
     queries::delete_friendship(state.db.write(), friendship_id).await?;
 
     // Notify the other user
-    send_to_user(&state, other_user_id, WsServerMessage::FriendRemoved {
+    crate::pubsub::send_to_user(&state, other_user_id, &WsServerMessage::FriendRemoved {
         user_id,
     }).await;
 
@@ -229,6 +230,7 @@ pub async fn list_dm_requests(
             dm_status: ch.dm_status,
             last_message_id: None,
             is_private: false,
+            meta_epoch: ch.meta_epoch,
         })
         .collect();
     Ok(Json(responses))
@@ -290,12 +292,40 @@ pub async fn update_dm_privacy(
     Ok(Json(serde_json::json!({ "dm_privacy": req.dm_privacy })))
 }
 
-/// Send a WS message to a specific user (all their connections + Redis pub/sub).
-async fn send_to_user(state: &AppState, user_id: Uuid, msg: WsServerMessage) {
-    if let Some(conns) = state.connections.get(&user_id) {
-        for tx in conns.iter() {
-            let _ = tx.send(msg.clone());
+/// PUT /api/v1/users/presence-privacy
+pub async fn update_presence_privacy(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<UpdatePresencePrivacyRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    if req.presence_privacy.is_none() && req.hide_last_seen.is_none() {
+        return Err(AppError::Validation(
+            "Provide presence_privacy and/or hide_last_seen".into(),
+        ));
+    }
+    if let Some(ref privacy) = req.presence_privacy {
+        match privacy.as_str() {
+            "everyone" | "friends_only" => {}
+            _ => {
+                return Err(AppError::Validation(
+                    "presence_privacy must be 'everyone' or 'friends_only'".into(),
+                ));
+            }
         }
     }
-    crate::pubsub::publish_user_event(state.redis.clone().as_mut(), user_id, &msg).await;
+
+    if let Some(hide) = req.hide_last_seen {
+        queries::update_hide_last_seen(state.db.write(), user_id, hide).await?;
+    }
+    if let Some(ref privacy) = req.presence_privacy {
+        queries::update_presence_privacy(state.db.write(), user_id, privacy).await?;
+        crate::ws::republish_presence(&state, user_id).await;
+    }
+
+    let (presence_privacy, hide_last_seen) =
+        queries::get_presence_settings(state.db.read(), user_id).await?;
+    Ok(Json(serde_json::json!({
+        "presence_privacy": presence_privacy,
+        "hide_last_seen": hide_last_seen,
+    })))
 }
@@ -11,6 +11,7 @@ use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
 use crate::models::*;
 use crate::permissions;
+use crate::rekey::ChannelAccess;
 use crate::AppState;
 
 /// POST /api/v1/servers/:server_id/invites
@@ -136,6 +137,7 @@ pub async fn join_by_invite(
     // Add user to the server
     let member_role = b"member";
     queries::add_server_member(state.db.write(), invite.server_id, user_id, member_role).await?;
+    crate::member_list::refresh(&state, invite.server_id).await;
 
     // Add user to all server channels (single bulk INSERT)
     queries::add_channel_members_bulk(state.db.write(), invite.server_id, user_id).await?;
@@ -184,6 +186,8 @@ pub async fn join_by_invite(
         my_permissions: Some(perms.to_string()),
         system_channel_id: server.system_channel_id,
         icon_url: server.icon_url.clone(),
+        key_epoch: server.key_epoch,
+        meta_epoch: server.meta_epoch,
     }))
 }
 
@@ -230,7 +234,10 @@ pub async fn kick_member(
         .map(|u| u.display_name.as_deref().unwrap_or(&u.username))
         .unwrap_or("Unknown");
 
+    let access = ChannelAccess::of_user(&state, server_id, target_user_id).await;
     queries::remove_server_member(state.db.write(), server_id, target_user_id).await?;
+    crate::member_list::refresh(&state, server_id).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::Kicked).await;
 
     // Insert system message in the first server channel
     let channels = queries::get_server_channels(state.db.read(), server_id).await?;
@@ -1,5 +1,12 @@
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    Json,
+};
+use uuid::Uuid;
 
+use crate::api::{b64, decode_b64};
+use crate::api::auth_routes::{extract_ip_from_headers, parse_device_name};
 use crate::db::queries;
 use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
@@ -7,35 +14,169 @@ use crate::models::*;
 use crate::AppState;
 
 const MAX_BACKUP_SIZE: usize = 512 * 1024; // 512 KB
+const MAX_SESSION_SIZE: usize = 64 * 1024; // 64 KB
+const MAX_SESSIONS_PER_UPLOAD: usize = 100;
+const MAX_SESSIONS_PER_VERSION: i64 = 10_000;
+const MAX_SESSION_ID_LEN: usize = 128;
+const MAX_KEY_SLOTS: usize = 16;
+const MAX_WRAPPED_KEY_SIZE: usize = 256;
+const MAX_CREDENTIAL_ID_SIZE: usize = 1024;
+
+fn decode_nonce(value: &str) -> AppResult<Vec<u8>> {
+    let nonce = decode_b64(value, "nonce")?;
+    if nonce.len() != 24 {
+        return Err(AppError::Validation("Nonce must be 24 bytes".into()));
+    }
+    Ok(nonce)
+}
+
+/// Validate a key slot. Passphrase slots carry a 16-byte Argon2id salt,
+/// passkey slots a credential ID and a 32-byte PRF input.
+fn decode_slot(req: &KeyBackupSlotRequest) -> AppResult<NewKeyBackupSlot> {
+    let wrapped_key = decode_b64(&req.wrapped_key, "wrapped_key")?;
+    if wrapped_key.is_empty() || wrapped_key.len() > MAX_WRAPPED_KEY_SIZE {
+        return Err(AppError::Validation(format!(
+            "wrapped_key must be 1-{} bytes",
+            MAX_WRAPPED_KEY_SIZE
+        )));
+    }
+    let nonce = decode_nonce(&req.nonce)?;
+    let salt = req.salt.as_deref().map(|s| decode_b64(s, "salt")).transpose()?;
+    let credential_id = req
+        .credential_id
+        .as_deref()
+        .map(|c| decode_b64(c, "credential_id"))
+        .transpose()?;
+
+    match req.kind {
+        KeyBackupSlotKind::Passphrase => {
+            if salt.as_ref().is_none_or(|s| s.len() != 16) {
+                return Err(AppError::Validation(
+                    "Passphrase slots need a 16-byte salt".into(),
+                ));
+            }
+            if credential_id.is_some() {
+                return Err(AppError::Validation(
+                    "Only passkey slots have a credential_id".into(),
+                ));
+            }
+        }
+        KeyBackupSlotKind::RecoveryKey => {
+            if salt.is_some() || credential_id.is_some() {
+                return Err(AppError::Validation(
+                    "Recovery key slots take no salt or credential_id".into(),
+                ));
+            }
+        }
+        KeyBackupSlotKind::PasskeyPrf => {
+            if salt.as_ref().is_none_or(|s| s.len() != 32) {
+                return Err(AppError::Validation(
+                    "Passkey slots need a 32-byte PRF salt".into(),
+                ));
+            }
+            if credential_id
+                .as_ref()
+                .is_none_or(|c| c.is_empty() || c.len() > MAX_CREDENTIAL_ID_SIZE)
+            {
+                return Err(AppError::Validation(
+                    "Passkey slots need a credential_id".into(),
+                ));
+            }
+        }
+    }
+
+    Ok(NewKeyBackupSlot {
+        kind: req.kind,
+        wrapped_key,
+        nonce,
+        salt,
+        credential_id,
+    })
+}
+
+fn slot_response(slot: KeyBackupSlot) -> KeyBackupSlotResponse {
+    KeyBackupSlotResponse {
+        id: slot.id,
+        kind: slot.kind,
+        wrapped_key: b64(&slot.wrapped_key),
+        nonce: b64(&slot.nonce),
+        salt: slot.salt.as_deref().map(b64),
+        credential_id: slot.credential_id.as_deref().map(b64),
+        created_at: slot.created_at,
+    }
+}
+
+async fn backup_response(state: &AppState, backup: KeyBackup) -> AppResult<KeyBackupResponse> {
+    let slots = queries::get_key_backup_slots(state.db.read(), backup.id).await?;
+    Ok(KeyBackupResponse {
+        encrypted_data: b64(&backup.encrypted_data),
+        nonce: b64(&backup.nonce),
+        salt: backup.salt.as_deref().map(b64),
+        version: backup.version,
+        key_slots: slots.into_iter().map(slot_response).collect(),
+        updated_at: backup.updated_at,
+    })
+}
+
+/// Record a download of backup material before handing it out.
+async fn record_access(
+    state: &AppState,
+    user_id: Uuid,
+    version: i32,
+    action: &str,
+    headers: &HeaderMap,
+) -> AppResult<()> {
+    let device_name = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .map(parse_device_name);
+    let ip = extract_ip_from_headers(headers);
+    queries::insert_key_backup_access(
+        state.db.write(),
+        user_id,
+        version,
+        action,
+        ip.as_deref(),
+        device_name.as_deref(),
+    )
+    .await
+}
+
+async fn current_backup(state: &AppState, user_id: Uuid) -> AppResult<KeyBackup> {
+    queries::get_key_backup(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::NotFound("No key backup found".into()))
+}
+
+async fn backup_version(state: &AppState, user_id: Uuid, version: i32) -> AppResult<KeyBackup> {
+    queries::get_key_backup_version(state.db.read(), user_id, version)
+        .await?
+        .ok_or(AppError::NotFound(format!(
+            "Backup version {} not found",
+            version
+        )))
+}
+
+/// Drop versions beyond `key_backup_versions_retained`.
+async fn prune_versions(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    let keep = state.config.key_backup_versions_retained.max(1) as i64;
+    queries::prune_key_backup_versions(state.db.write(), user_id, keep).await?;
+    Ok(())
+}
 
 /// PUT /api/v1/keys/backup
+/// Store a new backup version. Earlier versions are kept (up to
+/// `key_backup_versions_retained`) so a bad upload can be rolled back.
 pub async fn upload_key_backup(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
     Json(req): Json<UploadKeyBackupRequest>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let encrypted_data = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.encrypted_data,
-    )
-    .map_err(|_| AppError::Validation("Invalid encrypted_data encoding".into()))?;
-
-    let nonce = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.nonce,
-    )
-    .map_err(|_| AppError::Validation("Invalid nonce encoding".into()))?;
+    let encrypted_data = decode_b64(&req.encrypted_data, "encrypted_data")?;
+    let nonce = decode_nonce(&req.nonce)?;
+    let salt = req.salt.as_deref().map(|s| decode_b64(s, "salt")).transpose()?;
 
-    let salt = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.salt,
-    )
-    .map_err(|_| AppError::Validation("Invalid salt encoding".into()))?;
-
-    if nonce.len() != 24 {
-        return Err(AppError::Validation("Nonce must be 24 bytes".into()));
-    }
-    if salt.len() != 16 {
+    if salt.as_ref().is_some_and(|s| s.len() != 16) {
         return Err(AppError::Validation("Salt must be 16 bytes".into()));
     }
     if encrypted_data.len() > MAX_BACKUP_SIZE {
@@ -44,47 +185,49 @@ pub async fn upload_key_backup(
             MAX_BACKUP_SIZE / 1024
         )));
     }
+    if salt.is_none() && req.key_slots.is_empty() {
+        return Err(AppError::Validation(
+            "A backup needs a salt or at least one key slot".into(),
+        ));
+    }
+    if req.key_slots.len() > MAX_KEY_SLOTS {
+        return Err(AppError::Validation(format!(
+            "At most {} key slots",
+            MAX_KEY_SLOTS
+        )));
+    }
+    let slots = req
+        .key_slots
+        .iter()
+        .map(decode_slot)
+        .collect::<AppResult<Vec<_>>>()?;
 
-    let version = req.version.unwrap_or(1);
-
-    queries::upsert_key_backup(
+    let backup = queries::create_key_backup_version(
         state.db.write(),
         user_id,
         &encrypted_data,
         &nonce,
-        &salt,
-        version,
+        salt.as_deref(),
+        &slots,
     )
     .await?;
+    prune_versions(&state, user_id).await?;
 
-    Ok(Json(serde_json::json!({ "ok": true })))
+    Ok(Json(
+        serde_json::json!({ "ok": true, "version": backup.version }),
+    ))
 }
 
 /// GET /api/v1/keys/backup
+/// The current backup version and its key slots. Recorded in the access log.
 pub async fn get_key_backup(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
 ) -> AppResult<Json<KeyBackupResponse>> {
-    let backup = queries::get_key_backup(state.db.read(), user_id)
-        .await?
-        .ok_or(AppError::NotFound("No key backup found".into()))?;
-
-    Ok(Json(KeyBackupResponse {
-        encrypted_data: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &backup.encrypted_data,
-        ),
-        nonce: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &backup.nonce,
-        ),
-        salt: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &backup.salt,
-        ),
-        version: backup.version,
-        updated_at: backup.updated_at,
-    }))
+    let backup = current_backup(&state, user_id).await?;
+    record_access(&state, user_id, backup.version, "download", &headers).await?;
+    Ok(Json(backup_response(&state, backup).await?))
 }
 
 /// GET /api/v1/keys/backup/status
@@ -102,6 +245,7 @@ pub async fn get_key_backup_status(
 }
 
 /// DELETE /api/v1/keys/backup
+/// Deletes every retained version.
 pub async fn delete_key_backup(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
@@ -109,3 +253,202 @@ pub async fn delete_key_backup(
     queries::delete_key_backup(state.db.write(), user_id).await?;
     Ok(Json(serde_json::json!({ "ok": true })))
 }
+
+/// GET /api/v1/keys/backup/versions
+/// Retained versions, newest (current) first.
+pub async fn list_key_backup_versions(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<KeyBackupVersionInfo>>> {
+    let versions = queries::list_key_backup_versions(state.db.read(), user_id).await?;
+    Ok(Json(versions))
+}
+
+/// GET /api/v1/keys/backup/versions/:version
+/// A specific retained version. Recorded in the access log.
+pub async fn get_key_backup_version(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(version): Path<i32>,
+    headers: HeaderMap,
+) -> AppResult<Json<KeyBackupResponse>> {
+    let backup = backup_version(&state, user_id, version).await?;
+    record_access(&state, user_id, backup.version, "download", &headers).await?;
+    Ok(Json(backup_response(&state, backup).await?))
+}
+
+/// POST /api/v1/keys/backup/versions/:version/restore
+/// Roll back: copy an older version, with its slots and sessions, into a new
+/// current version. The versions in between stay retained.
+pub async fn restore_key_backup_version(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(version): Path<i32>,
+) -> AppResult<Json<serde_json::Value>> {
+    let backup = backup_version(&state, user_id, version).await?;
+    let current = current_backup(&state, user_id).await?;
+    if current.id == backup.id {
+        return Err(AppError::Validation(format!(
+            "Version {} is already the current backup",
+            version
+        )));
+    }
+
+    let restored = queries::restore_key_backup_version(state.db.write(), &backup).await?;
+    prune_versions(&state, user_id).await?;
+
+    Ok(Json(
+        serde_json::json!({ "ok": true, "version": restored.version }),
+    ))
+}
+
+/// POST /api/v1/keys/backup/slots
+/// Add a key slot to the current version, e.g. after enrolling a passkey.
+pub async fn add_key_backup_slot(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<KeyBackupSlotRequest>,
+) -> AppResult<Json<KeyBackupSlotResponse>> {
+    let slot = decode_slot(&req)?;
+    let backup = current_backup(&state, user_id).await?;
+    let existing = queries::get_key_backup_slots(state.db.read(), backup.id).await?;
+    if existing.len() >= MAX_KEY_SLOTS {
+        return Err(AppError::Validation(format!(
+            "At most {} key slots",
+            MAX_KEY_SLOTS
+        )));
+    }
+
+    let saved = queries::add_key_backup_slot(state.db.write(), backup.id, &slot).await?;
+    Ok(Json(slot_response(saved)))
+}
+
+/// DELETE /api/v1/keys/backup/slots/:slot_id
+/// Remove a key slot from the current version. The last slot of a backup
+/// without a passphrase salt can't be removed, since nothing could unlock it.
+pub async fn delete_key_backup_slot(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(slot_id): Path<Uuid>,
+) -> AppResult<Json<serde_json::Value>> {
+    let backup = current_backup(&state, user_id).await?;
+    let slots = queries::get_key_backup_slots(state.db.read(), backup.id).await?;
+    if !slots.iter().any(|s| s.id == slot_id) {
+        return Err(AppError::NotFound("Key slot not found".into()));
+    }
+    if backup.salt.is_none() && slots.len() == 1 {
+        return Err(AppError::Validation(
+            "Cannot remove the only key slot of a backup".into(),
+        ));
+    }
+
+    queries::delete_key_backup_slot(state.db.write(), backup.id, slot_id).await?;
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+/// PUT /api/v1/keys/backup/sessions
+/// Add or replace individual session entries in the current version, so
+/// clients back up each session as it changes instead of re-uploading
+/// everything.
+pub async fn upload_backup_sessions(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<UploadBackupSessionsRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    if req.sessions.is_empty() || req.sessions.len() > MAX_SESSIONS_PER_UPLOAD {
+        return Err(AppError::Validation(format!(
+            "Upload 1-{} sessions at a time",
+            MAX_SESSIONS_PER_UPLOAD
+        )));
+    }
+
+    let mut entries = Vec::with_capacity(req.sessions.len());
+    for entry in &req.sessions {
+        if entry.session_id.is_empty() || entry.session_id.len() > MAX_SESSION_ID_LEN {
+            return Err(AppError::Validation(format!(
+                "session_id must be 1-{} characters",
+                MAX_SESSION_ID_LEN
+            )));
+        }
+        let encrypted_data = decode_b64(&entry.encrypted_data, "encrypted_data")?;
+        if encrypted_data.len() > MAX_SESSION_SIZE {
+            return Err(AppError::Validation(format!(
+                "Session entry too large (max {}KB)",
+                MAX_SESSION_SIZE / 1024
+            )));
+        }
+        entries.push((
+            entry.session_id.as_str(),
+            encrypted_data,
+            decode_nonce(&entry.nonce)?,
+        ));
+    }
+
+    let backup = current_backup(&state, user_id).await?;
+    if backup.version != req.version {
+        return Err(AppError::Conflict(format!(
+            "Backup version {} is not current (current is {})",
+            req.version, backup.version
+        )));
+    }
+    let count = queries::count_key_backup_sessions(state.db.read(), backup.id).await?;
+    if count + entries.len() as i64 > MAX_SESSIONS_PER_VERSION {
+        return Err(AppError::Validation(format!(
+            "A backup holds at most {} sessions",
+            MAX_SESSIONS_PER_VERSION
+        )));
+    }
+
+    for (session_id, encrypted_data, nonce) in &entries {
+        queries::upsert_key_backup_session(
+            state.db.write(),
+            backup.id,
+            session_id,
+            encrypted_data,
+            nonce,
+        )
+        .await?;
+    }
+
+    Ok(Json(
+        serde_json::json!({ "ok": true, "count": entries.len() }),
+    ))
+}
+
+/// GET /api/v1/keys/backup/versions/:version/sessions
+/// Every session entry of a version. Recorded in the access log.
+pub async fn get_backup_sessions(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(version): Path<i32>,
+    headers: HeaderMap,
+) -> AppResult<Json<Vec<BackupSessionResponse>>> {
+    let backup = backup_version(&state, user_id, version).await?;
+    record_access(&state, user_id, backup.version, "sessions", &headers).await?;
+
+    let sessions = queries::get_key_backup_sessions(state.db.read(), backup.id).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|s| BackupSessionResponse {
+                session_id: s.session_id,
+                encrypted_data: b64(&s.encrypted_data),
+                nonce: b64(&s.nonce),
+                updated_at: s.updated_at,
+            })
+            .collect(),
+    ))
+}
+
+/// GET /api/v1/keys/backup/access-log?limit=&offset=
+/// Downloads of the caller's backup, newest first.
+pub async fn get_key_backup_access_log(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<PaginationQuery>,
+) -> AppResult<Json<Vec<KeyBackupAccess>>> {
+    let (limit, offset) = query.resolve();
+    let entries =
+        queries::get_key_backup_access_log(state.db.read(), user_id, limit, offset).await?;
+    Ok(Json(entries))
+}
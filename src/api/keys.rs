@@ -1,68 +1,164 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Json,
 };
 use uuid::Uuid;
 
+use crate::api::{b64, decode_b64};
 use crate::db::queries;
 use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
 use crate::models::*;
+use crate::api::transparency::publish_identity_key;
+use crate::pubsub;
 use crate::AppState;
 
+/// Upper bound on E2EE devices per account.
+const MAX_DEVICES: usize = 16;
+
+/// Owners get `PrekeysLow` when a device drops below this many one-time prekeys.
+const PREKEY_LOW_THRESHOLD: i64 = 10;
+
+/// Decode base64 one-time prekeys, numbering them from `start_id`.
+fn decode_prekeys(prekeys: &[String], start_id: i32) -> AppResult<Vec<(i32, Vec<u8>)>> {
+    prekeys
+        .iter()
+        .enumerate()
+        .map(|(i, key_b64)| {
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, key_b64)
+                .map(|bytes| (start_id + i as i32, bytes))
+                .map_err(|_| AppError::Validation(format!("Invalid prekey encoding at index {}", i)))
+        })
+        .collect()
+}
+
+/// Signed prekey IDs are client-chosen but must be non-negative.
+pub(crate) fn validate_signed_prekey_id(id: i32) -> AppResult<i32> {
+    if id < 0 {
+        return Err(AppError::Validation("signed_prekey_id must be non-negative".into()));
+    }
+    Ok(id)
+}
+
+/// Resolve one of the caller's devices; the primary device when `device_id` is None.
+pub(crate) async fn resolve_device(state: &AppState, user_id: Uuid, device_id: Option<Uuid>) -> AppResult<Device> {
+    let device = match device_id {
+        Some(id) => queries::find_device(state.db.read(), id)
+            .await?
+            .filter(|d| d.user_id == user_id),
+        None => queries::find_primary_device(state.db.read(), user_id).await?,
+    };
+    device.ok_or(AppError::NotFound("Device not found".into()))
+}
+
 /// GET /api/v1/users/:user_id/keys
-/// Fetch a user's key bundle for establishing an E2EE session (X3DH).
-/// Consumes one one-time prekey atomically.
+/// Fetch key bundles for all of a user's devices for establishing E2EE
+/// sessions (X3DH). Consumes one one-time prekey per device atomically;
+/// devices that ran out hand out their last-resort prekey instead.
+/// `verified_key_changed` warns that keys the requester verified have changed.
+/// Fetches of someone else's bundle are rate limited per requester/target.
 pub async fn get_key_bundle(
     State(state): State<AppState>,
-    AuthUser(_requester_id): AuthUser,
+    AuthUser(requester_id): AuthUser,
     Path(user_id): Path<Uuid>,
 ) -> AppResult<Json<KeyBundle>> {
+    if requester_id != user_id && !state.key_bundle_limiter.check((requester_id, user_id)) {
+        return Err(AppError::RateLimited);
+    }
+
     // Fetch the target user
     let user = queries::find_user_by_id(state.db.read(), user_id)
         .await?
         .ok_or(AppError::UserNotFound)?;
 
-    // Try to consume a one-time prekey
-    let one_time_prekey = queries::consume_prekey(state.db.write(), user_id).await?;
-
-    let bundle = KeyBundle {
-        identity_key: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &user.identity_key,
-        ),
-        signed_prekey: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &user.signed_prekey,
-        ),
-        signed_prekey_sig: base64::Engine::encode(
-            &base64::engine::general_purpose::STANDARD,
-            &user.signed_prekey_sig,
-        ),
-        one_time_prekey: one_time_prekey.map(|pk| {
-            base64::Engine::encode(
-                &base64::engine::general_purpose::STANDARD,
-                &pk.public_key,
-            )
-        }),
-    };
+    let mut devices = Vec::new();
+    for device in queries::list_devices(state.db.read(), user_id).await? {
+        // Try to consume a one-time prekey
+        let one_time_prekey = queries::consume_prekey(state.db.write(), device.id).await?;
 
-    // Log a warning if prekeys are running low
-    if let Ok(remaining) = queries::count_unused_prekeys(state.db.read(), user_id).await {
-        if remaining < 10 {
-            tracing::warn!(
-                "User {} has only {} prekeys remaining. Client should replenish.",
-                user_id,
-                remaining
-            );
+        if one_time_prekey.is_some() {
+            match queries::count_unused_prekeys(state.db.read(), device.id).await {
+                // Notify on every fetch below the threshold, at most once per
+                // cooldown window; running out entirely gets its own notice
+                Ok(remaining)
+                    if remaining < PREKEY_LOW_THRESHOLD
+                        && state.prekeys_low_cooldown.check((device.id, remaining == 0)) =>
+                {
+                    tracing::warn!(
+                        "Device {} of user {} has only {} prekeys remaining. Client should replenish.",
+                        device.id,
+                        user_id,
+                        remaining
+                    );
+                    pubsub::send_to_user(
+                        &state,
+                        user_id,
+                        &WsServerMessage::PrekeysLow { device_id: device.id, remaining },
+                    )
+                    .await;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to count prekeys for device {}: {}", device.id, e);
+                }
+            }
         }
+
+        let last_resort = one_time_prekey.is_none() && device.last_resort_prekey.is_some();
+        let one_time_prekey = match one_time_prekey {
+            Some(pk) => Some(b64(&pk.public_key)),
+            None => device.last_resort_prekey.as_deref().map(b64),
+        };
+
+        devices.push(DeviceKeyBundle {
+            device_id: device.id,
+            identity_key: b64(&device.identity_key),
+            signed_prekey: b64(&device.signed_prekey),
+            signed_prekey_sig: b64(&device.signed_prekey_sig),
+            signed_prekey_id: device.signed_prekey_id,
+            one_time_prekey,
+            last_resort,
+        });
     }
 
+    let (verified, verified_key_changed) = if requester_id != user_id {
+        crate::api::verification::verification_flags(&state, requester_id, user_id).await?
+    } else {
+        (false, false)
+    };
+
+    // Top-level fields mirror the primary device for single-device clients
+    let bundle = match devices.first() {
+        Some(primary) => KeyBundle {
+            identity_key: primary.identity_key.clone(),
+            signed_prekey: primary.signed_prekey.clone(),
+            signed_prekey_sig: primary.signed_prekey_sig.clone(),
+            signed_prekey_id: primary.signed_prekey_id,
+            one_time_prekey: primary.one_time_prekey.clone(),
+            last_resort: primary.last_resort,
+            devices,
+            verified,
+            verified_key_changed,
+        },
+        None => KeyBundle {
+            identity_key: b64(&user.identity_key),
+            signed_prekey: b64(&user.signed_prekey),
+            signed_prekey_sig: b64(&user.signed_prekey_sig),
+            signed_prekey_id: 1,
+            one_time_prekey: None,
+            last_resort: false,
+            devices,
+            verified,
+            verified_key_changed,
+        },
+    };
+
     Ok(Json(bundle))
 }
 
 /// POST /api/v1/keys/prekeys
-/// Upload new one-time prekeys (clients should call this when running low).
+/// Upload new one-time prekeys for one of the caller's devices
+/// (clients should call this when running low).
 pub async fn upload_prekeys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
@@ -76,100 +172,325 @@ pub async fn upload_prekeys(
         return Err(AppError::Validation("Maximum 100 prekeys per upload".into()));
     }
 
-    // Get current max key_id for this user to continue the sequence
-    let current_count = queries::count_unused_prekeys(state.db.read(), user_id).await?;
-    let start_id = current_count as i32;
+    let device = resolve_device(&state, user_id, req.device_id).await?;
 
-    let prekeys: Result<Vec<(i32, Vec<u8>)>, _> = req
-        .prekeys
-        .iter()
-        .enumerate()
-        .map(|(i, key_b64)| {
-            base64::Engine::decode(
-                &base64::engine::general_purpose::STANDARD,
-                key_b64,
-            )
-            .map(|bytes| (start_id + i as i32, bytes))
-            .map_err(|_| AppError::Validation(format!("Invalid prekey encoding at index {}", i)))
-        })
-        .collect();
+    // Get current max key_id for this device to continue the sequence
+    let current_count = queries::count_unused_prekeys(state.db.read(), device.id).await?;
+    let prekeys = decode_prekeys(&req.prekeys, current_count as i32)?;
 
-    queries::insert_prekeys(state.db.write(), user_id, &prekeys?).await?;
+    queries::insert_prekeys(state.db.write(), user_id, device.id, &prekeys).await?;
 
-    let total = queries::count_unused_prekeys(state.db.read(), user_id).await?;
+    let total = queries::count_unused_prekeys(state.db.read(), device.id).await?;
 
     Ok(Json(serde_json::json!({
         "message": "Prekeys uploaded",
+        "device_id": device.id,
         "total_available": total,
     })))
 }
 
 /// PUT /api/v1/keys/identity
-/// Update the authenticated user's identity key and signed prekey.
-/// Called after login when the client generates new ephemeral keys.
+/// Update the identity key and rotate the signed prekey of one of the
+/// caller's devices (the primary device unless `device_id` is given).
+/// Called after login when the client generates new ephemeral keys, and on
+/// the client's signed prekey rotation schedule. The replaced signed prekey
+/// stays retained for `signed_prekey_grace_hours` unless the identity key
+/// changed, since a prekey signed by the old identity is no longer usable.
+/// A new identity key is appended to the key transparency log and announced
+/// to DM peers with `IdentityKeyChanged`.
 pub async fn update_identity_keys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
     Json(req): Json<UpdateKeysRequest>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let identity_key = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.identity_key,
-    )
-    .map_err(|_| AppError::Validation("Invalid identity_key encoding".into()))?;
+    let identity_key = decode_b64(&req.identity_key, "identity_key")?;
+    let signed_prekey = decode_b64(&req.signed_prekey, "signed_prekey")?;
+    let signed_prekey_sig = decode_b64(&req.signed_prekey_signature, "signed_prekey_signature")?;
 
-    let signed_prekey = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.signed_prekey,
-    )
-    .map_err(|_| AppError::Validation("Invalid signed_prekey encoding".into()))?;
+    let device = resolve_device(&state, user_id, req.device_id).await?;
 
-    let signed_prekey_sig = base64::Engine::decode(
-        &base64::engine::general_purpose::STANDARD,
-        &req.signed_prekey_signature,
-    )
-    .map_err(|_| AppError::Validation("Invalid signed_prekey_signature encoding".into()))?;
+    let identity_changed = device.identity_key != identity_key;
+    if !identity_changed && device.signed_prekey == signed_prekey {
+        return Ok(Json(serde_json::json!({
+            "message": "Keys unchanged",
+            "device_id": device.id,
+            "signed_prekey_id": device.signed_prekey_id,
+        })));
+    }
 
-    // Check if the identity key actually changed — if so, old SKDMs are undecryptable
-    let user = queries::find_user_by_id(state.db.read(), user_id)
-        .await?
-        .ok_or(AppError::NotFound("User not found".into()))?;
+    let signed_prekey_id = match req.signed_prekey_id {
+        Some(id) => validate_signed_prekey_id(id)?,
+        None => device.signed_prekey_id.checked_add(1).unwrap_or(0),
+    };
+    if signed_prekey_id == device.signed_prekey_id {
+        return Err(AppError::Validation(
+            "signed_prekey_id must differ from the current signed prekey".into(),
+        ));
+    }
+
+    if identity_changed {
+        // Identity key changed: SKDMs encrypted to this device's old key are
+        // undecryptable. Other devices keep theirs.
+        queries::clear_sender_key_distributions_for_device(state.db.write(), device.id).await?;
+    }
+
+    let grace_hours = state.config.signed_prekey_grace_hours;
+    let retain_previous_until = (!identity_changed && grace_hours > 0)
+        .then(|| chrono::Utc::now() + chrono::Duration::hours(grace_hours as i64));
+
+    queries::update_device_keys(
+        state.db.write(),
+        &device,
+        &identity_key,
+        &signed_prekey,
+        &signed_prekey_sig,
+        signed_prekey_id,
+        retain_previous_until,
+    )
+    .await?;
 
-    if user.identity_key != identity_key {
-        // Identity key changed: clear all pending SKDMs encrypted with the old key
-        queries::clear_sender_key_distributions_for_user(state.db.write(), user_id).await?;
+    if identity_changed {
+        let entry = publish_identity_key(&state, user_id, device.id, &identity_key).await?;
+        notify_identity_key_changed(&state, user_id, &entry).await;
     }
 
-    queries::update_user_keys(state.db.write(), user_id, &identity_key, &signed_prekey, &signed_prekey_sig).await?;
+    Ok(Json(serde_json::json!({
+        "message": "Keys updated",
+        "device_id": device.id,
+        "signed_prekey_id": signed_prekey_id,
+    })))
+}
 
-    Ok(Json(serde_json::json!({ "message": "Keys updated" })))
+/// PUT /api/v1/keys/last-resort
+/// Set or replace the last-resort prekey of one of the caller's devices.
+/// It is handed out whenever the device has no one-time prekeys left.
+pub async fn upload_last_resort_prekey(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<UploadLastResortPreKeyRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let prekey = decode_b64(&req.prekey, "prekey")?;
+    let device = resolve_device(&state, user_id, req.device_id).await?;
+    queries::set_last_resort_prekey(state.db.write(), device.id, &prekey).await?;
+    Ok(Json(serde_json::json!({ "message": "Last-resort prekey set", "device_id": device.id })))
 }
 
-/// DELETE /api/v1/keys/prekeys
-/// Delete all unused one-time prekeys for the authenticated user.
+/// DELETE /api/v1/keys/prekeys?device_id=
+/// Delete all unused one-time prekeys of one of the caller's devices.
 /// Called on login before uploading fresh prekeys so the server only holds
 /// OTPs whose private keys exist in the client's current MemoryStore.
 pub async fn delete_prekeys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
+    Query(query): Query<DeviceQuery>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let deleted = queries::delete_unused_prekeys(state.db.write(), user_id).await?;
+    let device = resolve_device(&state, user_id, query.device_id).await?;
+    let deleted = queries::delete_unused_prekeys(state.db.write(), device.id).await?;
     Ok(Json(serde_json::json!({
         "message": "Unused prekeys cleared",
         "deleted": deleted,
     })))
 }
 
-/// GET /api/v1/keys/prekeys/count
-/// Check how many unused prekeys the authenticated user has remaining.
+/// GET /api/v1/keys/prekeys/count?device_id=
+/// Check how many unused prekeys one of the caller's devices has remaining,
+/// whether its signed prekey is due for rotation, and which replaced signed
+/// prekey (if any) is still within its grace window.
 pub async fn prekey_count(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
+    Query(query): Query<DeviceQuery>,
 ) -> AppResult<Json<serde_json::Value>> {
-    let count = queries::count_unused_prekeys(state.db.read(), user_id).await?;
+    let device = resolve_device(&state, user_id, query.device_id).await?;
+    let count = queries::count_unused_prekeys(state.db.read(), device.id).await?;
+
+    let now = chrono::Utc::now();
+    let max_age_days = state.config.signed_prekey_max_age_days;
+    let signed_prekey_stale = max_age_days > 0
+        && device.signed_prekey_created_at + chrono::Duration::days(max_age_days as i64) < now;
+    let previous = device
+        .previous_signed_prekey_id
+        .zip(device.previous_signed_prekey_expires_at)
+        .filter(|(_, expires_at)| *expires_at > now);
 
     Ok(Json(serde_json::json!({
         "count": count,
         "needs_replenishment": count < 20,
+        "has_last_resort": device.last_resort_prekey.is_some(),
+        "signed_prekey_id": device.signed_prekey_id,
+        "signed_prekey_created_at": device.signed_prekey_created_at,
+        "signed_prekey_stale": signed_prekey_stale,
+        "previous_signed_prekey_id": previous.map(|(id, _)| id),
+        "previous_signed_prekey_expires_at": previous.map(|(_, expires_at)| expires_at),
     })))
 }
+
+/// GET /api/v1/keys/sender-certificate?device_id=
+/// Issue a short-lived sender certificate for one of the caller's devices.
+/// Clients embed it in sealed-sender ciphertexts so recipients can
+/// authenticate the sender without the server storing who sent the message.
+pub async fn get_sender_certificate(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<DeviceQuery>,
+) -> AppResult<Json<SenderCertificateResponse>> {
+    let device = resolve_device(&state, user_id, query.device_id).await?;
+
+    // Signatures cover millisecond timestamps; don't serialize more precision than was signed
+    let expires_at = chrono::Utc::now()
+        + chrono::Duration::hours(crate::sealed_sender::CERTIFICATE_TTL_HOURS);
+    let expires_at =
+        chrono::DateTime::from_timestamp_millis(expires_at.timestamp_millis()).unwrap_or(expires_at);
+    let (signature, public_key) = crate::sealed_sender::sign_certificate(
+        &state.sender_cert_keys,
+        user_id,
+        device.id,
+        &device.identity_key,
+        expires_at,
+    )?;
+
+    Ok(Json(SenderCertificateResponse {
+        user_id,
+        device_id: device.id,
+        identity_key: b64(&device.identity_key),
+        expires_at,
+        signature: b64(&signature),
+        public_key: b64(&public_key),
+    }))
+}
+
+/// GET /api/v1/keys/devices
+/// List the caller's E2EE devices, primary first.
+pub async fn list_devices(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<DeviceResponse>>> {
+    let devices = queries::list_devices(state.db.read(), user_id).await?;
+    Ok(Json(devices.into_iter().map(device_response).collect()))
+}
+
+/// POST /api/v1/keys/devices
+/// Register a new E2EE device with its own identity key, signed prekey and
+/// one-time prekeys. Existing devices and their sender keys are untouched.
+pub async fn register_device(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<RegisterDeviceRequest>,
+) -> AppResult<Json<DeviceResponse>> {
+    if req.name.as_ref().is_some_and(|n| n.chars().count() > 64) {
+        return Err(AppError::Validation("Device name must be at most 64 characters".into()));
+    }
+    if req.one_time_prekeys.len() > 100 {
+        return Err(AppError::Validation("Maximum 100 prekeys per upload".into()));
+    }
+    if queries::list_devices(state.db.read(), user_id).await?.len() >= MAX_DEVICES {
+        return Err(AppError::Validation(format!(
+            "Maximum {} devices per account",
+            MAX_DEVICES
+        )));
+    }
+
+    let identity_key = decode_b64(&req.identity_key, "identity_key")?;
+    let signed_prekey = decode_b64(&req.signed_prekey, "signed_prekey")?;
+    let signed_prekey_sig = decode_b64(&req.signed_prekey_signature, "signed_prekey_signature")?;
+    let signed_prekey_id = validate_signed_prekey_id(req.signed_prekey_id.unwrap_or(1))?;
+    let prekeys = decode_prekeys(&req.one_time_prekeys, 0)?;
+    let last_resort_prekey = req
+        .last_resort_prekey
+        .as_deref()
+        .map(|k| decode_b64(k, "last_resort_prekey"))
+        .transpose()?;
+
+    let device = queries::create_device(
+        state.db.write(),
+        user_id,
+        req.name.as_deref(),
+        &identity_key,
+        &signed_prekey,
+        &signed_prekey_sig,
+        signed_prekey_id,
+    )
+    .await?;
+    publish_identity_key(&state, user_id, device.id, &identity_key).await?;
+    if !prekeys.is_empty() {
+        queries::insert_prekeys(state.db.write(), user_id, device.id, &prekeys).await?;
+    }
+    if let Some(ref key) = last_resort_prekey {
+        queries::set_last_resort_prekey(state.db.write(), device.id, key).await?;
+    }
+
+    notify_device_change(
+        &state,
+        user_id,
+        WsServerMessage::DeviceAdded { user_id, device_id: device.id },
+    )
+    .await;
+
+    Ok(Json(device_response(device)))
+}
+
+/// DELETE /api/v1/keys/devices/:device_id
+/// Remove one of the caller's devices, its prekeys and the SKDMs addressed to it.
+pub async fn remove_device(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(device_id): Path<Uuid>,
+) -> AppResult<Json<serde_json::Value>> {
+    let devices = queries::list_devices(state.db.read(), user_id).await?;
+    if !devices.iter().any(|d| d.id == device_id) {
+        return Err(AppError::NotFound("Device not found".into()));
+    }
+    if devices.len() == 1 {
+        return Err(AppError::Validation("Cannot remove your only device".into()));
+    }
+
+    queries::delete_device(state.db.write(), user_id, device_id).await?;
+
+    notify_device_change(&state, user_id, WsServerMessage::DeviceRemoved { user_id, device_id })
+        .await;
+
+    Ok(Json(serde_json::json!({ "message": "Device removed" })))
+}
+
+fn device_response(device: Device) -> DeviceResponse {
+    DeviceResponse {
+        id: device.id,
+        name: device.name,
+        identity_key: b64(&device.identity_key),
+        created_at: device.created_at,
+    }
+}
+
+/// Tell the user's other devices, friends and DM/group participants that the
+/// user's device list changed. Server channel senders pick up new devices from
+/// `GET /channels/:id/members/keys` when they next distribute.
+async fn notify_device_change(state: &AppState, user_id: Uuid, msg: WsServerMessage) {
+    let mut recipients = vec![user_id];
+    match queries::get_presence_audience(state.db.read(), user_id).await {
+        Ok(audience) => recipients.extend(audience.into_iter().map(|(id, _)| id)),
+        Err(e) => tracing::warn!("Failed to load device change audience: {}", e),
+    }
+    for recipient in recipients {
+        pubsub::send_to_user(state, recipient, &msg).await;
+    }
+}
+
+/// Tell the user's other sessions and everyone sharing a DM with them that a
+/// device's identity key changed, so verified contacts can re-verify.
+async fn notify_identity_key_changed(state: &AppState, user_id: Uuid, entry: &TransparencyLogEntry) {
+    let msg = WsServerMessage::IdentityKeyChanged {
+        user_id,
+        device_id: entry.device_id,
+        identity_key: b64(&entry.identity_key),
+        log_seq: entry.seq,
+    };
+    let mut recipients = vec![user_id];
+    match queries::get_dm_peer_ids(state.db.read(), user_id).await {
+        Ok(peers) => recipients.extend(peers),
+        Err(e) => tracing::warn!("Failed to load identity key change audience: {}", e),
+    }
+    for recipient in recipients {
+        pubsub::send_to_user(state, recipient, &msg).await;
+    }
+}
+
@@ -156,6 +156,17 @@ pub async fn send_message(
     )
     .map_err(|_| AppError::Validation("Invalid encrypted_body encoding".into()))?;
 
+    // Sealed sender: authorize later edits by capability, don't record the sender
+    let capability_hash = req
+        .capability_hash
+        .as_deref()
+        .map(|h| {
+            crate::sealed_sender::decode_capability_hash(h)
+                .ok_or(AppError::Validation("capability_hash must be 32 bytes of base64".into()))
+        })
+        .transpose()?;
+    let sender_id = if capability_hash.is_some() { None } else { Some(user_id) };
+
     let message = queries::insert_message(
         state.db.write(),
         channel_id,
@@ -163,8 +174,9 @@ pub async fn send_message(
         &encrypted_body,
         req.expires_at,
         req.has_attachments,
-        user_id,
+        sender_id,
         req.reply_to_id,
+        capability_hash.as_deref(),
     )
     .await?;
 
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use uuid::Uuid;
+
+use crate::api::{b64, decode_b64};
+use crate::api::keys::resolve_device;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::AuthUser;
+use crate::models::*;
+use crate::permissions;
+use crate::pubsub;
+use crate::AppState;
+
+/// Upper bound on KeyPackages per upload.
+const MAX_KEY_PACKAGES_PER_UPLOAD: usize = 100;
+
+/// Upper bound on unclaimed KeyPackages stored per device.
+const MAX_KEY_PACKAGES_PER_DEVICE: i64 = 500;
+
+async fn require_channel_access(state: &AppState, channel_id: Uuid, user_id: Uuid) -> AppResult<()> {
+    if !queries::can_access_channel(state.db.read(), channel_id, user_id).await? {
+        return Err(AppError::Forbidden("Not a member of this channel".into()));
+    }
+    Ok(())
+}
+
+/// POST /api/v1/keys/mls/key-packages
+/// Upload MLS KeyPackages for one of the caller's devices. Each is handed out
+/// once, so clients should keep a supply and top it up.
+pub async fn upload_key_packages(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Json(req): Json<UploadKeyPackagesRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    if req.key_packages.is_empty() {
+        return Err(AppError::Validation("No key packages provided".into()));
+    }
+    if req.key_packages.len() > MAX_KEY_PACKAGES_PER_UPLOAD {
+        return Err(AppError::Validation(format!(
+            "Maximum {} key packages per upload",
+            MAX_KEY_PACKAGES_PER_UPLOAD
+        )));
+    }
+
+    let device = resolve_device(&state, user_id, req.device_id).await?;
+    let key_packages = req
+        .key_packages
+        .iter()
+        .map(|kp| decode_b64(kp, "key_package"))
+        .collect::<AppResult<Vec<_>>>()?;
+
+    let stored = queries::count_mls_key_packages(state.db.read(), device.id).await?;
+    if stored + key_packages.len() as i64 > MAX_KEY_PACKAGES_PER_DEVICE {
+        return Err(AppError::Validation(format!(
+            "A device can hold at most {} unclaimed key packages ({} stored)",
+            MAX_KEY_PACKAGES_PER_DEVICE, stored
+        )));
+    }
+
+    queries::insert_mls_key_packages(state.db.write(), user_id, device.id, &key_packages).await?;
+    let total = queries::count_mls_key_packages(state.db.read(), device.id).await?;
+
+    Ok(Json(serde_json::json!({
+        "device_id": device.id,
+        "total_available": total,
+    })))
+}
+
+/// GET /api/v1/keys/mls/key-packages/count?device_id=
+pub async fn key_package_count(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<DeviceQuery>,
+) -> AppResult<Json<serde_json::Value>> {
+    let device = resolve_device(&state, user_id, query.device_id).await?;
+    let count = queries::count_mls_key_packages(state.db.read(), device.id).await?;
+    Ok(Json(serde_json::json!({ "device_id": device.id, "count": count })))
+}
+
+/// POST /api/v1/users/:user_id/mls/key-packages
+/// Claim one KeyPackage from each of a user's devices, to add them to an MLS
+/// group. Claimed KeyPackages are deleted; devices with none left are omitted.
+/// Claims count against the same per-requester/target limit as key bundles.
+pub async fn claim_key_packages(
+    State(state): State<AppState>,
+    AuthUser(requester_id): AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<Json<ClaimedKeyPackages>> {
+    if requester_id != user_id && !state.key_bundle_limiter.check((requester_id, user_id)) {
+        return Err(AppError::RateLimited);
+    }
+
+    let mut key_packages = Vec::new();
+    for device in queries::list_devices(state.db.read(), user_id).await? {
+        if let Some(kp) = queries::claim_mls_key_package(state.db.write(), device.id).await? {
+            key_packages.push(DeviceKeyPackage {
+                device_id: device.id,
+                key_package: b64(&kp),
+            });
+        }
+    }
+
+    Ok(Json(ClaimedKeyPackages { user_id, key_packages }))
+}
+
+/// POST /api/v1/channels/:channel_id/mls
+/// Switch a channel to MLS group messaging. The caller creates the group
+/// client-side at epoch 0 and adds members with commits. Server channels
+/// require MANAGE_CHANNELS; DMs and group DMs any participant.
+pub async fn create_group(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<Uuid>,
+) -> AppResult<Json<MlsGroupResponse>> {
+    let channel = queries::find_channel_by_id(state.db.read(), channel_id)
+        .await?
+        .ok_or(AppError::NotFound("Channel not found".into()))?;
+    match channel.server_id {
+        Some(server_id) => {
+            queries::require_server_permission(
+                state.db.read(),
+                server_id,
+                user_id,
+                permissions::MANAGE_CHANNELS,
+            )
+            .await?
+        }
+        None => require_channel_access(&state, channel_id, user_id).await?,
+    }
+
+    let group = queries::create_mls_group(state.db.write(), channel_id, user_id)
+        .await?
+        .ok_or(AppError::Conflict("Channel already uses MLS".into()))?;
+
+    Ok(Json(MlsGroupResponse {
+        channel_id,
+        epoch: group.epoch,
+        created_at: group.created_at,
+    }))
+}
+
+/// GET /api/v1/channels/:channel_id/mls
+/// The channel's MLS group state; 404 for channels using sender keys.
+pub async fn get_group(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<Uuid>,
+) -> AppResult<Json<MlsGroupResponse>> {
+    require_channel_access(&state, channel_id, user_id).await?;
+    let group = queries::find_mls_group(state.db.read(), channel_id)
+        .await?
+        .ok_or(AppError::NotFound("Channel is not an MLS group".into()))?;
+
+    Ok(Json(MlsGroupResponse {
+        channel_id,
+        epoch: group.epoch,
+        created_at: group.created_at,
+    }))
+}
+
+/// POST /api/v1/channels/:channel_id/mls/handshake
+/// Append a commit or proposal to the channel's ordered handshake stream.
+/// `epoch` must be the group's current epoch, otherwise 409: the client must
+/// catch up on the stream and retry. An accepted commit advances the epoch
+/// and delivers its Welcome to `welcome_device_ids`.
+pub async fn send_handshake(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<Uuid>,
+    Json(req): Json<SendMlsHandshakeRequest>,
+) -> AppResult<Json<MlsHandshakeResponse>> {
+    require_channel_access(&state, channel_id, user_id).await?;
+
+    if req.kind != "commit" && req.kind != "proposal" {
+        return Err(AppError::Validation("kind must be 'commit' or 'proposal'".into()));
+    }
+    let message = decode_b64(&req.message, "message")?;
+    let device = resolve_device(&state, user_id, req.device_id).await?;
+
+    let welcome = match req.welcome.as_deref() {
+        Some(_) if req.kind != "commit" => {
+            return Err(AppError::Validation("Only commits can carry a Welcome".into()));
+        }
+        Some(_) if req.welcome_device_ids.is_empty() => {
+            return Err(AppError::Validation("Welcome has no recipients".into()));
+        }
+        Some(w) => Some(decode_b64(w, "welcome")?),
+        None if !req.welcome_device_ids.is_empty() => {
+            return Err(AppError::Validation("welcome_device_ids given without a Welcome".into()));
+        }
+        None => None,
+    };
+
+    // Welcomes go to devices of users who can already read the channel
+    let owners: HashMap<Uuid, Uuid> =
+        queries::get_device_owners(state.db.read(), &req.welcome_device_ids)
+            .await?
+            .into_iter()
+            .collect();
+    let mut recipients = Vec::with_capacity(req.welcome_device_ids.len());
+    for device_id in &req.welcome_device_ids {
+        let owner = *owners
+            .get(device_id)
+            .ok_or(AppError::Validation("Unknown welcome device".into()))?;
+        if !queries::can_access_channel(state.db.read(), channel_id, owner).await? {
+            return Err(AppError::Validation("Welcome recipient is not a channel member".into()));
+        }
+        recipients.push((owner, *device_id));
+    }
+
+    let handshake = queries::append_mls_handshake(
+        state.db.write(),
+        channel_id,
+        user_id,
+        device.id,
+        req.epoch,
+        &req.kind,
+        &message,
+        welcome.as_deref().map(|welcome| NewMlsWelcome {
+            welcome,
+            recipients: &recipients,
+        }),
+    )
+    .await?;
+    let response = MlsHandshakeResponse::from(handshake);
+
+    let msg = WsServerMessage::MlsHandshake(response.clone());
+    if let Some(broadcaster) = state.channel_broadcasts.get(&channel_id) {
+        let _ = broadcaster.send(msg.clone());
+    }
+    crate::pubsub::publish_channel_event(state.redis.clone().as_mut(), channel_id, &msg).await;
+
+    if welcome.is_some() {
+        for (owner, device_id) in recipients {
+            pubsub::send_to_user(&state, owner, &WsServerMessage::MlsWelcome { channel_id, device_id })
+                .await;
+        }
+    }
+
+    Ok(Json(response))
+}
+
+/// GET /api/v1/channels/:channel_id/mls/handshake?after=&limit=
+/// The channel's handshake messages after sequence number `after`, in order.
+pub async fn get_handshakes(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(channel_id): Path<Uuid>,
+    Query(query): Query<MlsHandshakeQuery>,
+) -> AppResult<Json<Vec<MlsHandshakeResponse>>> {
+    require_channel_access(&state, channel_id, user_id).await?;
+    let after = query.after.unwrap_or(-1);
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+
+    let handshakes = queries::get_mls_handshakes(state.db.read(), channel_id, after, limit).await?;
+    Ok(Json(handshakes.into_iter().map(MlsHandshakeResponse::from).collect()))
+}
+
+/// GET /api/v1/keys/mls/welcomes?device_id=
+/// Welcomes waiting for one of the caller's devices (the primary by default).
+/// They stay until acknowledged with DELETE.
+pub async fn get_welcomes(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Query(query): Query<DeviceQuery>,
+) -> AppResult<Json<Vec<MlsWelcomeResponse>>> {
+    let device = resolve_device(&state, user_id, query.device_id).await?;
+    let welcomes = queries::get_mls_welcomes(state.db.read(), device.id).await?;
+
+    Ok(Json(
+        welcomes
+            .into_iter()
+            .map(|w| MlsWelcomeResponse {
+                id: w.id,
+                channel_id: w.channel_id,
+                to_device_id: w.to_device_id,
+                epoch: w.epoch,
+                welcome: b64(&w.welcome),
+                created_at: w.created_at,
+            })
+            .collect(),
+    ))
+}
+
+/// DELETE /api/v1/keys/mls/welcomes/:welcome_id
+/// Acknowledge a processed Welcome.
+pub async fn delete_welcome(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(welcome_id): Path<Uuid>,
+) -> AppResult<Json<serde_json::Value>> {
+    if !queries::delete_mls_welcome(state.db.write(), welcome_id, user_id).await? {
+        return Err(AppError::NotFound("Welcome not found".into()));
+    }
+    Ok(Json(serde_json::json!({ "message": "Welcome deleted" })))
+}
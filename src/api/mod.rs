@@ -3,20 +3,41 @@ pub mod auth_routes;
 pub mod bans;
 pub mod categories;
 pub mod channels;
+pub mod device_link;
+pub mod email;
 pub mod emojis;
 pub mod friends;
 pub mod invites;
 pub mod key_backup;
 pub mod keys;
 pub mod messages;
+pub mod mls;
+pub mod oidc;
 pub mod presence;
 pub mod roles;
 pub mod sender_keys;
+pub mod signing_keys;
+pub mod server_keys;
+pub mod transparency;
 pub mod servers;
 pub mod attachments;
 pub mod link_preview;
 pub mod reports;
 pub mod users;
+pub mod verification;
 pub mod registration_invites;
 pub mod voice;
 pub mod gifs;
+
+use crate::errors::{AppError, AppResult};
+
+/// Encode bytes as standard base64, the way API responses carry binary fields.
+pub(crate) fn b64(bytes: &[u8]) -> String {
+    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)
+}
+
+/// Decode a standard base64 request field, naming it in the error.
+pub(crate) fn decode_b64(value: &str, field: &str) -> AppResult<Vec<u8>> {
+    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, value)
+        .map_err(|_| AppError::Validation(format!("Invalid {} encoding", field)))
+}
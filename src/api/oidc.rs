@@ -0,0 +1,217 @@
+use axum::{extract::State, http::HeaderMap, Json};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::api::auth_routes::{create_account, start_session};
+use crate::auth;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::{AuthSession, AuthUser};
+use crate::models::*;
+use crate::oidc;
+use crate::AppState;
+
+fn require_oidc(state: &AppState) -> AppResult<()> {
+    if state.config.oidc_enabled() {
+        Ok(())
+    } else {
+        Err(AppError::NotFound(
+            "SSO is not configured on this instance".into(),
+        ))
+    }
+}
+
+/// Store a fresh state, nonce and PKCE verifier and build the issuer URL.
+async fn begin(state: &AppState, link_user_id: Option<Uuid>) -> AppResult<OidcAuthorizeResponse> {
+    require_oidc(state)?;
+    let metadata = oidc::discover(&state.config.oidc_issuer).await?;
+
+    let login_state = oidc::random_token();
+    let nonce = oidc::random_token();
+    let code_verifier = oidc::random_token();
+    queries::insert_oidc_login_state(
+        state.db.write(),
+        &login_state,
+        &code_verifier,
+        &nonce,
+        link_user_id,
+        Utc::now() + Duration::minutes(oidc::LOGIN_STATE_TTL_MINUTES),
+    )
+    .await?;
+
+    Ok(OidcAuthorizeResponse {
+        authorization_url: oidc::authorization_url(
+            &metadata,
+            &state.config,
+            &login_state,
+            &nonce,
+            &code_verifier,
+        ),
+        state: login_state,
+    })
+}
+
+/// GET /api/v1/auth/methods
+pub async fn login_methods(State(state): State<AppState>) -> Json<LoginMethodsResponse> {
+    Json(LoginMethodsResponse {
+        password: state.config.password_login_enabled,
+        oidc: state.config.oidc_enabled(),
+    })
+}
+
+/// GET /api/v1/auth/oidc/authorize
+/// Start an SSO login. The client sends the browser to `authorization_url`.
+pub async fn authorize(State(state): State<AppState>) -> AppResult<Json<OidcAuthorizeResponse>> {
+    Ok(Json(begin(&state, None).await?))
+}
+
+/// POST /api/v1/auth/oidc/link
+/// Start linking the caller's account to their SSO identity. The callback
+/// links instead of logging in.
+pub async fn start_link(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<OidcAuthorizeResponse>> {
+    Ok(Json(begin(&state, Some(user_id)).await?))
+}
+
+/// POST /api/v1/auth/oidc/callback
+/// Redeem the code the issuer redirected back with. A linked subject logs in;
+/// an unknown one gets a new account when auto-provisioning is on, named
+/// after `oidc_username_claim`. Registration invites and PoW don't apply:
+/// the issuer already vouched for the user.
+///
+/// A link started with `/auth/oidc/link` must be finished by the same
+/// account, signed in: otherwise anyone tricked into completing someone
+/// else's link flow would bind their SSO identity to that account.
+pub async fn callback(
+    State(state): State<AppState>,
+    session: Option<AuthSession>,
+    headers: HeaderMap,
+    Json(req): Json<OidcCallbackRequest>,
+) -> AppResult<Json<OidcCallbackResponse>> {
+    require_oidc(&state)?;
+    let pending = queries::take_oidc_login_state(state.db.write(), &req.state)
+        .await?
+        .filter(|p| p.expires_at > Utc::now())
+        .ok_or(AppError::AuthError(
+            "Invalid or expired SSO state — start again".into(),
+        ))?;
+    if let Some(link_user_id) = pending.link_user_id {
+        if session.map(|s| s.user_id) != Some(link_user_id) {
+            return Err(AppError::Forbidden(
+                "Finish linking while signed in to the account that started it".into(),
+            ));
+        }
+    }
+
+    let metadata = oidc::discover(&state.config.oidc_issuer).await?;
+    let issuer = oidc::normalize_issuer(&metadata.issuer);
+    let id_token =
+        oidc::exchange_code(&metadata, &state.config, &req.code, &pending.code_verifier).await?;
+    let token = oidc::verify_id_token(&metadata, &state.config, &id_token, &pending.nonce).await?;
+
+    if let Some(user_id) = pending.link_user_id {
+        let identity =
+            queries::link_oidc_identity(state.db.write(), user_id, issuer, &token.subject).await?;
+        return Ok(Json(OidcCallbackResponse::Linked {
+            linked: true,
+            issuer: identity.issuer,
+            subject: identity.subject,
+        }));
+    }
+
+    let (user, device_id) =
+        match queries::find_user_by_oidc_subject(state.db.read(), issuer, &token.subject).await?
+        {
+            Some(user) => (user, None),
+            None => {
+                if !state.config.oidc_auto_provision {
+                    return Err(AppError::Forbidden(
+                        "No account is linked to this SSO identity".into(),
+                    ));
+                }
+                let keys = req.keys.as_ref().ok_or(AppError::Validation(
+                    "Device keys are required to create an account".into(),
+                ))?;
+                let username =
+                    oidc::username_from_claims(&token.claims, &state.config.oidc_username_claim)
+                        .ok_or_else(|| {
+                            AppError::Validation(format!(
+                                "SSO identity has no usable {} claim",
+                                state.config.oidc_username_claim
+                            ))
+                        })?;
+                let display_name = token
+                    .claims
+                    .get("name")
+                    .and_then(|v| v.as_str())
+                    .map(|n| n.chars().take(32).collect::<String>());
+                let email = token.claims.get("email").and_then(|v| v.as_str());
+                // Nobody knows this password: the account signs in through
+                // SSO unless its owner sets one later.
+                let password_hash = auth::hash_password(&oidc::random_token())?;
+
+                let (user, device) = create_account(
+                    &state,
+                    &username,
+                    display_name.as_deref(),
+                    email,
+                    &password_hash,
+                    keys,
+                )
+                .await?;
+                queries::mark_password_unset(state.db.write(), user.id).await?;
+                queries::link_oidc_identity(state.db.write(), user.id, issuer, &token.subject)
+                    .await?;
+                tracing::info!("Provisioned account {} from SSO", user.username);
+                (user, Some(device.id))
+            }
+        };
+    let _ = queries::touch_oidc_identity(state.db.write(), issuer, &token.subject).await;
+
+    let (access_token, refresh_token) = start_session(&state, user.id, &headers).await?;
+    Ok(Json(OidcCallbackResponse::Login(Box::new(AuthResponse {
+        access_token,
+        refresh_token,
+        user: user.into(),
+        device_id,
+    }))))
+}
+
+/// GET /api/v1/auth/oidc/identities
+pub async fn list_identities(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<OidcIdentity>>> {
+    Ok(Json(
+        queries::get_oidc_identities(state.db.read(), user_id).await?,
+    ))
+}
+
+/// DELETE /api/v1/auth/oidc/link
+/// Unlink the caller's identity at the configured issuer. Refused when
+/// password login is disabled, or when it is the only identity of an account
+/// whose password nobody knows, since the account could no longer sign in.
+pub async fn unlink(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<serde_json::Value>> {
+    require_oidc(&state)?;
+    if !state.config.password_login_enabled {
+        return Err(AppError::Forbidden(
+            "Password login is disabled — unlinking would lock you out".into(),
+        ));
+    }
+    let identities = queries::get_oidc_identities(state.db.read(), user_id).await?;
+    if identities.len() == 1 && !queries::is_password_set(state.db.read(), user_id).await? {
+        return Err(AppError::Forbidden(
+            "Set a password before unlinking your only SSO identity".into(),
+        ));
+    }
+    let issuer = oidc::normalize_issuer(&state.config.oidc_issuer);
+    if !queries::delete_oidc_identity(state.db.write(), user_id, issuer).await? {
+        return Err(AppError::NotFound("No linked SSO identity".into()));
+    }
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
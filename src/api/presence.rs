@@ -1,14 +1,22 @@
+use std::collections::HashMap;
+
 use axum::{extract::{Query, State}, Json};
 use uuid::Uuid;
 
+use crate::db::queries;
 use crate::errors::AppError;
-use crate::models::{PresenceEntry, PresenceQuery};
+use crate::middleware::AuthUser;
+use crate::models::{PresenceDetails, PresenceEntry, PresenceQuery};
+use crate::presence;
 use crate::AppState;
 
-/// Bulk presence check: returns online/offline status for a list of user IDs.
+/// Bulk presence check: returns status, `since`, custom status and (when the
+/// instance records it) last-seen for a list of user IDs.
+/// Users with friends-only presence show as offline unless the caller is a friend.
 /// GET /api/v1/presence?user_ids=uuid1,uuid2,...
 pub async fn get_presence(
     State(state): State<AppState>,
+    viewer: Option<AuthUser>,
     Query(query): Query<PresenceQuery>,
 ) -> Result<Json<Vec<PresenceEntry>>, AppError> {
     let user_ids: Vec<Uuid> = query
@@ -21,50 +29,63 @@ pub async fn get_presence(
         return Ok(Json(vec![]));
     }
 
-    let entries = if let Some(mut redis) = state.redis.clone() {
-        // Bulk-fetch presence from Redis hash
-        let mut cmd = redis::cmd("HMGET");
-        cmd.arg("haven:presence");
-        for uid in &user_ids {
-            cmd.arg(uid.to_string());
-        }
-        let statuses: Vec<Option<String>> = cmd
-            .query_async(&mut redis)
-            .await
-            .unwrap_or_else(|_| vec![None; user_ids.len()]);
+    let viewer = viewer.map(|AuthUser(id)| id);
+    Ok(Json(fetch_presence(&state, &user_ids, viewer).await))
+}
 
-        user_ids
-            .iter()
-            .zip(statuses.iter())
-            .map(|(uid, status)| {
-                let s = match status.as_deref() {
-                    // Never leak "invisible" to other users
-                    Some("invisible") | None => "offline",
-                    Some(s) => s,
-                };
-                PresenceEntry {
-                    user_id: *uid,
-                    status: s.to_string(),
-                }
-            })
-            .collect()
-    } else {
-        // In-memory presence fallback
-        user_ids
-            .iter()
-            .map(|uid| {
-                let status = state.memory.presence
-                    .get(uid)
-                    .map(|v| v.value().clone())
-                    .unwrap_or_else(|| "offline".to_string());
-                let s = if status == "invisible" { "offline" } else { &status };
-                PresenceEntry {
-                    user_id: *uid,
-                    status: s.to_string(),
-                }
-            })
-            .collect()
-    };
+/// Look up each user's presence as `viewer` may see it: invisible is reported
+/// as offline, and so are friends-only users the viewer isn't friends with.
+/// Without a viewer, every friends-only user is reported as offline.
+pub(crate) async fn fetch_presence(
+    state: &AppState,
+    user_ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> Vec<PresenceEntry> {
+    let live = presence::get_many(state, user_ids).await;
+    let details: HashMap<Uuid, PresenceDetails> =
+        match queries::get_presence_details(state.db.read(), user_ids, viewer).await {
+            Ok(rows) => rows.into_iter().map(|d| (d.user_id, d)).collect(),
+            Err(e) => {
+                // Fail closed: everyone shows as offline
+                tracing::warn!("Failed to load presence details: {}", e);
+                HashMap::new()
+            }
+        };
 
-    Ok(Json(entries))
+    user_ids
+        .iter()
+        .zip(live)
+        .map(|(uid, live)| {
+            let Some(details) = details.get(uid).filter(|d| !d.hidden) else {
+                return PresenceEntry {
+                    user_id: *uid,
+                    status: "offline".into(),
+                    since: None,
+                    last_seen: None,
+                    custom_status: None,
+                    custom_status_emoji: None,
+                    custom_status_expires_at: None,
+                };
+            };
+            let (status, since) = match live {
+                // Never leak "invisible" to other users
+                Some(p) if p.status != "invisible" => (p.status, Some(p.since)),
+                _ => ("offline".to_string(), None),
+            };
+            let last_seen = if status == "offline" && state.config.presence_last_seen {
+                details.last_seen_at
+            } else {
+                None
+            };
+            PresenceEntry {
+                user_id: *uid,
+                status,
+                since,
+                last_seen,
+                custom_status: details.custom_status.clone(),
+                custom_status_emoji: details.custom_status_emoji.clone(),
+                custom_status_expires_at: details.custom_status_expires_at,
+            }
+        })
+        .collect()
 }
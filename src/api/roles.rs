@@ -9,6 +9,7 @@ use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
 use crate::models::*;
 use crate::permissions;
+use crate::rekey::ChannelAccess;
 use crate::AppState;
 
 /// GET /api/v1/servers/:server_id/roles
@@ -106,6 +107,7 @@ pub async fn update_role(
 
     let perms: Option<i64> = req.permissions.as_deref().and_then(|s| s.parse().ok());
 
+    let access = ChannelAccess::of_members(&state, server_id, None).await;
     let updated = queries::update_role(
         state.db.write(),
         role_id,
@@ -115,6 +117,7 @@ pub async fn update_role(
         req.position,
     )
     .await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     // Invalidate all permission caches for this server (role changed affects everyone)
     crate::cache::invalidate_pattern(
@@ -122,6 +125,7 @@ pub async fn update_role(
         &state.memory,
         &format!("haven:perms:{}:*", server_id),
     ).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -168,7 +172,9 @@ pub async fn delete_role(
         }
     }
 
+    let access = ChannelAccess::of_members(&state, server_id, None).await;
     queries::delete_role(state.db.write(), role_id).await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     // Invalidate all permission caches for this server
     crate::cache::invalidate_pattern(
@@ -176,6 +182,7 @@ pub async fn delete_role(
         &state.memory,
         &format!("haven:perms:{}:*", server_id),
     ).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -220,7 +227,9 @@ pub async fn assign_role(
         }
     }
 
+    let access = ChannelAccess::of_user(&state, server_id, target_user_id).await;
     queries::assign_role(state.db.write(), server_id, target_user_id, req.role_id).await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     // Invalidate permission cache for target user
     crate::cache::invalidate(
@@ -228,6 +237,7 @@ pub async fn assign_role(
         &state.memory,
         &format!("haven:perms:{}:{}", server_id, target_user_id),
     ).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -271,7 +281,9 @@ pub async fn unassign_role(
         }
     }
 
+    let access = ChannelAccess::of_user(&state, server_id, target_user_id).await;
     queries::remove_role(state.db.write(), server_id, target_user_id, role_id).await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     // Invalidate permission cache for target user
     crate::cache::invalidate(
@@ -279,6 +291,7 @@ pub async fn unassign_role(
         &state.memory,
         &format!("haven:perms:{}:{}", server_id, target_user_id),
     ).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -339,6 +352,7 @@ pub async fn set_overwrite(
     let allow: i64 = req.allow_bits.parse().map_err(|_| AppError::Validation("Invalid allow_bits".into()))?;
     let deny: i64 = req.deny_bits.parse().map_err(|_| AppError::Validation("Invalid deny_bits".into()))?;
 
+    let access = ChannelAccess::of_members(&state, server_id, Some(channel_id)).await;
     let overwrite = queries::set_channel_overwrite(
         state.db.write(),
         channel_id,
@@ -348,6 +362,7 @@ pub async fn set_overwrite(
         deny,
     )
     .await?;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -380,7 +395,9 @@ pub async fn delete_overwrite(
     )
     .await?;
 
+    let access = ChannelAccess::of_members(&state, server_id, Some(channel_id)).await;
     queries::delete_channel_overwrite(state.db.write(), channel_id, &target_type, target_id).await?;
+    access.rekey_lost(&state, SenderKeyRotationReason::PermissionsChanged).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
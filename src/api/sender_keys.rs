@@ -1,5 +1,7 @@
+use std::collections::{HashMap, HashSet};
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     Json,
 };
 use uuid::Uuid;
@@ -11,7 +13,8 @@ use crate::models::*;
 use crate::AppState;
 
 /// POST /api/v1/channels/:channel_id/sender-keys
-/// Distribute encrypted sender keys to channel members.
+/// Distribute encrypted sender keys to channel members. Not available in
+/// channels that switched to MLS.
 pub async fn distribute_sender_keys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
@@ -27,17 +30,52 @@ pub async fn distribute_sender_keys(
         return Err(AppError::Validation("No distributions provided".into()));
     }
 
+    if queries::find_mls_group(state.db.read(), channel_id).await?.is_some() {
+        return Err(AppError::Validation("Channel uses MLS, not sender keys".into()));
+    }
+
+    // Entries without a device go to the recipient's primary device
+    let untargeted: Vec<Uuid> = req
+        .distributions
+        .iter()
+        .filter(|d| d.to_device_id.is_none())
+        .map(|d| d.to_user_id)
+        .collect();
+    let primary: HashMap<Uuid, Uuid> = queries::get_primary_device_ids(state.db.read(), &untargeted)
+        .await?
+        .into_iter()
+        .collect();
+
+    // Explicit devices must belong to the addressed user
+    let targeted: Vec<Uuid> = req.distributions.iter().filter_map(|d| d.to_device_id).collect();
+    let owners: HashMap<Uuid, Uuid> = queries::get_device_owners(state.db.read(), &targeted)
+        .await?
+        .into_iter()
+        .collect();
+
     // Decode and prepare batch
-    let distributions: Result<Vec<(Uuid, Uuid, Vec<u8>)>, AppError> = req
+    #[allow(clippy::type_complexity)]
+    let distributions: Result<Vec<(Uuid, Uuid, Uuid, Vec<u8>)>, AppError> = req
         .distributions
         .iter()
         .map(|d| {
+            let to_device_id = match d.to_device_id {
+                Some(device_id) if owners.get(&device_id) == Some(&d.to_user_id) => device_id,
+                Some(_) => {
+                    return Err(AppError::Validation(
+                        "to_device_id does not belong to to_user_id".into(),
+                    ))
+                }
+                None => *primary
+                    .get(&d.to_user_id)
+                    .ok_or_else(|| AppError::Validation("Recipient has no devices".into()))?,
+            };
             let bytes = base64::Engine::decode(
                 &base64::engine::general_purpose::STANDARD,
                 &d.encrypted_skdm,
             )
             .map_err(|_| AppError::Validation("Invalid encrypted_skdm encoding".into()))?;
-            Ok((d.to_user_id, d.distribution_id, bytes))
+            Ok((d.to_user_id, to_device_id, d.distribution_id, bytes))
         })
         .collect();
 
@@ -54,20 +92,17 @@ pub async fn distribute_sender_keys(
 
     // Notify affected recipients via their WebSocket connections + Redis pub/sub
     let sk_msg = WsServerMessage::SenderKeysUpdated { channel_id };
-    for (to_user_id, _, _) in &distributions {
-        if let Some(conns) = state.connections.get(to_user_id) {
-            for sender in conns.iter() {
-                let _ = sender.send(sk_msg.clone());
-            }
-        }
-        crate::pubsub::publish_user_event(state.redis.clone().as_mut(), *to_user_id, &sk_msg).await;
+    let recipients: HashSet<Uuid> = distributions.iter().map(|(to_user_id, ..)| *to_user_id).collect();
+    for to_user_id in &recipients {
+        crate::pubsub::send_to_user(&state, *to_user_id, &sk_msg).await;
     }
 
     Ok(Json(serde_json::json!({ "distributed": count })))
 }
 
-/// GET /api/v1/channels/:channel_id/sender-keys
-/// Fetch all sender key distributions for the authenticated user in this channel.
+/// GET /api/v1/channels/:channel_id/sender-keys?device_id=
+/// Fetch all sender key distributions addressed to one of the authenticated
+/// user's devices (the primary device by default) in this channel.
 /// SKDMs are retained so clients can re-fetch after page reloads or on new devices.
 /// The INSERT uses ON CONFLICT ... DO UPDATE, so rows are bounded to one per
 /// (channel, sender, recipient, distributionId).
@@ -75,13 +110,22 @@ pub async fn get_sender_keys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
     Path(channel_id): Path<Uuid>,
+    Query(query): Query<DeviceQuery>,
 ) -> AppResult<Json<Vec<SenderKeyDistributionResponse>>> {
     // Verify membership
     if !queries::can_access_channel(state.db.read(), channel_id, user_id).await? {
         return Err(AppError::Forbidden("Not a member of this channel".into()));
     }
 
-    let skdms = queries::get_sender_key_distributions(state.db.read(), channel_id, user_id).await?;
+    let device = match query.device_id {
+        Some(id) => queries::find_device(state.db.read(), id)
+            .await?
+            .filter(|d| d.user_id == user_id),
+        None => queries::find_primary_device(state.db.read(), user_id).await?,
+    }
+    .ok_or(AppError::NotFound("Device not found".into()))?;
+
+    let skdms = queries::get_sender_key_distributions(state.db.read(), channel_id, device.id).await?;
 
     let responses: Vec<SenderKeyDistributionResponse> = skdms
         .iter()
@@ -89,6 +133,7 @@ pub async fn get_sender_keys(
             id: s.id,
             channel_id: s.channel_id,
             from_user_id: s.from_user_id,
+            to_device_id: s.to_device_id,
             distribution_id: s.distribution_id,
             encrypted_skdm: base64::Engine::encode(
                 &base64::engine::general_purpose::STANDARD,
@@ -102,8 +147,9 @@ pub async fn get_sender_keys(
 }
 
 /// GET /api/v1/channels/:channel_id/members/keys
-/// Fetch identity keys for all members of a channel (for encrypting SKDMs).
-/// Excludes the requesting user (they don't need to encrypt to themselves).
+/// Fetch identity keys for all members of a channel and each of their
+/// devices (for encrypting SKDMs). Excludes the requesting user; their own
+/// other devices get SKDMs via `GET /users/:id/keys`.
 pub async fn get_channel_member_keys(
     State(state): State<AppState>,
     AuthUser(user_id): AuthUser,
@@ -116,6 +162,16 @@ pub async fn get_channel_member_keys(
     let member_keys =
         queries::get_channel_member_identity_keys(state.db.read(), channel_id, user_id).await?;
 
+    let mut devices: HashMap<Uuid, Vec<DeviceIdentityKey>> = HashMap::new();
+    for (uid, device_id, key) in
+        queries::get_channel_member_device_keys(state.db.read(), channel_id, user_id).await?
+    {
+        devices.entry(uid).or_default().push(DeviceIdentityKey {
+            device_id,
+            identity_key: base64::Engine::encode(&base64::engine::general_purpose::STANDARD, key),
+        });
+    }
+
     let results: Vec<ChannelMemberKeyInfo> = member_keys
         .iter()
         .map(|(uid, key)| ChannelMemberKeyInfo {
@@ -124,6 +180,7 @@ pub async fn get_channel_member_keys(
                 &base64::engine::general_purpose::STANDARD,
                 key,
             ),
+            devices: devices.remove(uid).unwrap_or_default(),
         })
         .collect();
 
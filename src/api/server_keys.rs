@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use uuid::Uuid;
+
+use crate::api::{b64, decode_b64};
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::AuthUser;
+use crate::models::*;
+use crate::permissions;
+use crate::pubsub;
+use crate::AppState;
+
+const MAX_WRAP_SIZE: usize = 1024;
+
+/// Reject a metadata write encrypted under a stale server key. Clients send
+/// the epoch they encrypted under; a rotation in between is a 409 so the
+/// client fetches the new wrap and re-encrypts.
+pub(crate) async fn require_current_epoch(
+    state: &AppState,
+    server_id: Uuid,
+    meta_epoch: Option<i32>,
+) -> AppResult<()> {
+    let Some(meta_epoch) = meta_epoch else {
+        return Ok(());
+    };
+    let server = queries::find_server_by_id(state.db.read(), server_id)
+        .await?
+        .ok_or(AppError::NotFound("Server not found".into()))?;
+    if meta_epoch != server.key_epoch {
+        return Err(AppError::Conflict(format!(
+            "Metadata must be encrypted under server key epoch {}",
+            server.key_epoch
+        )));
+    }
+    Ok(())
+}
+
+/// GET /api/v1/servers/:server_id/key-epochs
+/// The current epoch and every server key wrapped for the caller, including
+/// older epochs so metadata encrypted under them stays readable.
+pub async fn get_server_key_epochs(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(server_id): Path<Uuid>,
+) -> AppResult<Json<ServerKeyEpochsResponse>> {
+    if !queries::is_server_member(state.db.read(), server_id, user_id).await? {
+        return Err(AppError::Forbidden("Not a member of this server".into()));
+    }
+    let server = queries::find_server_by_id(state.db.read(), server_id)
+        .await?
+        .ok_or(AppError::NotFound("Server not found".into()))?;
+
+    let wraps = queries::get_server_key_wraps(state.db.read(), server_id, user_id)
+        .await?
+        .into_iter()
+        .map(|w| ServerKeyWrapResponse {
+            epoch: w.epoch,
+            encrypted_key: b64(&w.encrypted_key),
+            distributed_by: w.distributed_by,
+            created_at: w.created_at,
+        })
+        .collect();
+
+    Ok(Json(ServerKeyEpochsResponse {
+        key_epoch: server.key_epoch,
+        wraps,
+    }))
+}
+
+/// PUT /api/v1/servers/:server_id/key-epochs
+/// Upload a server key wrapped per member. With `epoch` one past the current
+/// epoch this rotates the key (MANAGE_SERVER, and every current member needs
+/// a wrap); with the current epoch it adds wraps for members who lack one,
+/// which any member holding that epoch's key may do. Recipients get
+/// `ServerKeyRotated`.
+pub async fn distribute_server_keys(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(server_id): Path<Uuid>,
+    Json(req): Json<DistributeServerKeysRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    if !queries::is_server_member(state.db.read(), server_id, user_id).await? {
+        return Err(AppError::Forbidden("Not a member of this server".into()));
+    }
+    let server = queries::find_server_by_id(state.db.read(), server_id)
+        .await?
+        .ok_or(AppError::NotFound("Server not found".into()))?;
+
+    if req.wraps.is_empty() {
+        return Err(AppError::Validation("wraps is empty".into()));
+    }
+    let members: HashSet<Uuid> = queries::get_server_member_ids(state.db.read(), server_id)
+        .await?
+        .into_iter()
+        .collect();
+    let mut wraps = Vec::with_capacity(req.wraps.len());
+    let mut recipients = HashSet::with_capacity(req.wraps.len());
+    for entry in &req.wraps {
+        let key = decode_b64(&entry.encrypted_key, "encrypted_key")?;
+        if key.is_empty() || key.len() > MAX_WRAP_SIZE {
+            return Err(AppError::Validation(format!(
+                "encrypted_key must be between 1 and {} bytes",
+                MAX_WRAP_SIZE
+            )));
+        }
+        if !members.contains(&entry.user_id) {
+            return Err(AppError::Validation(format!(
+                "{} is not a member of this server",
+                entry.user_id
+            )));
+        }
+        if !recipients.insert(entry.user_id) {
+            return Err(AppError::Validation(format!(
+                "Duplicate wrap for {}",
+                entry.user_id
+            )));
+        }
+        wraps.push((entry.user_id, key));
+    }
+
+    if req.epoch == server.key_epoch + 1 {
+        let (is_owner, perms) =
+            queries::get_member_permissions(state.db.read(), server_id, user_id).await?;
+        if !is_owner && !permissions::has_permission(perms, permissions::MANAGE_SERVER) {
+            return Err(AppError::Forbidden(
+                "Missing MANAGE_SERVER permission".into(),
+            ));
+        }
+        let missing = members.iter().filter(|m| !recipients.contains(m)).count();
+        if missing > 0 {
+            return Err(AppError::Validation(format!(
+                "A new epoch needs a wrap for every member ({} missing)",
+                missing
+            )));
+        }
+        if !queries::rotate_server_key(state.db.write(), server_id, req.epoch, user_id, &wraps)
+            .await?
+        {
+            return Err(AppError::Conflict(
+                "Server key was rotated concurrently".into(),
+            ));
+        }
+        crate::cache::invalidate(
+            state.redis.clone().as_mut(),
+            &state.memory,
+            &format!("haven:server:{}", server_id),
+        )
+        .await;
+        let _ = queries::insert_audit_log(
+            state.db.write(),
+            server_id,
+            user_id,
+            "server_key_rotate",
+            Some("server"),
+            Some(server_id),
+            Some(&serde_json::json!({ "epoch": req.epoch })),
+            None,
+        )
+        .await;
+    } else if req.epoch == server.key_epoch && req.epoch > 0 {
+        if !queries::has_server_key_wrap(state.db.read(), server_id, req.epoch, user_id).await? {
+            return Err(AppError::Forbidden(
+                "You do not hold the key for this epoch".into(),
+            ));
+        }
+        queries::add_server_key_wraps(state.db.write(), server_id, req.epoch, user_id, &wraps)
+            .await?;
+    } else {
+        return Err(AppError::Conflict(format!(
+            "Current server key epoch is {}",
+            server.key_epoch
+        )));
+    }
+
+    let msg = WsServerMessage::ServerKeyRotated {
+        server_id,
+        epoch: req.epoch,
+    };
+    for &recipient in &recipients {
+        pubsub::send_to_user(&state, recipient, &msg).await;
+    }
+
+    Ok(Json(
+        serde_json::json!({ "epoch": req.epoch, "distributed": wraps.len() }),
+    ))
+}
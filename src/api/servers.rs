@@ -12,6 +12,7 @@ use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
 use crate::models::*;
 use crate::permissions;
+use crate::rekey::ChannelAccess;
 use crate::storage;
 use crate::AppState;
 
@@ -82,6 +83,8 @@ pub async fn create_server(
         my_permissions: Some(i64::MAX.to_string()),
         system_channel_id: Some(channel.id),
         icon_url: None,
+        key_epoch: server.key_epoch,
+        meta_epoch: server.meta_epoch,
     }))
 }
 
@@ -113,6 +116,8 @@ pub async fn get_server(
         my_permissions: Some(perms.to_string()),
         system_channel_id: server.system_channel_id,
         icon_url: server.icon_url.clone(),
+        key_epoch: server.key_epoch,
+        meta_epoch: server.meta_epoch,
     }))
 }
 
@@ -138,6 +143,8 @@ pub async fn list_servers(
             my_permissions: Some(perms.to_string()),
             system_channel_id: s.system_channel_id,
             icon_url: s.icon_url.clone(),
+            key_epoch: s.key_epoch,
+            meta_epoch: s.meta_epoch,
         });
     }
 
@@ -197,6 +204,7 @@ pub async fn list_server_channels(
             dm_status: c.dm_status,
             last_message_id: None,
             is_private: c.is_private,
+            meta_epoch: c.meta_epoch,
         });
     }
 
@@ -257,6 +265,7 @@ pub async fn update_server(
         if meta_bytes.is_empty() || meta_bytes.len() > 4096 {
             return Err(AppError::Validation("encrypted_meta must be between 1 and 4096 bytes".into()));
         }
+        crate::api::server_keys::require_current_epoch(&state, server_id, req.meta_epoch).await?;
         queries::update_server_meta(state.db.write(), server_id, &meta_bytes).await?;
     }
 
@@ -296,6 +305,7 @@ pub async fn set_nickname(
     }
 
     queries::update_member_nickname(state.db.write(), server_id, user_id, req.nickname.as_deref()).await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     Ok(Json(serde_json::json!({ "ok": true })))
 }
@@ -325,6 +335,7 @@ pub async fn set_member_nickname(
     }
 
     queries::update_member_nickname(state.db.write(), server_id, target_user_id, req.nickname.as_deref()).await?;
+    crate::member_list::refresh(&state, server_id).await;
 
     // Audit log
     let _ = queries::insert_audit_log(
@@ -369,7 +380,10 @@ pub async fn leave_server(
         .await?
         .ok_or(AppError::UserNotFound)?;
 
+    let access = ChannelAccess::of_user(&state, server_id, user_id).await;
     queries::remove_server_member(state.db.write(), server_id, user_id).await?;
+    crate::member_list::refresh(&state, server_id).await;
+    access.rekey_lost(&state, SenderKeyRotationReason::Left).await;
 
     // Post system message in system channel
     if let Some(system_channel_id) = server.system_channel_id {
@@ -395,6 +409,48 @@ pub async fn leave_server(
     Ok(Json(serde_json::json!({ "ok": true })))
 }
 
+/// PUT /api/v1/servers/:server_id/owner — hand the server to another member
+/// (owner only)
+pub async fn transfer_ownership(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(server_id): Path<Uuid>,
+    Json(req): Json<TransferOwnershipRequest>,
+) -> AppResult<Json<serde_json::Value>> {
+    let server = queries::find_server_by_id(state.db.read(), server_id)
+        .await?
+        .ok_or(AppError::NotFound("Server not found".into()))?;
+
+    if server.owner_id != user_id {
+        return Err(AppError::Forbidden(
+            "Only the server owner can transfer ownership".into(),
+        ));
+    }
+    if req.user_id == user_id {
+        return Err(AppError::Validation("You already own this server".into()));
+    }
+    if !queries::is_server_member(state.db.read(), server_id, req.user_id).await? {
+        return Err(AppError::Validation("The new owner must be a member of this server".into()));
+    }
+    let new_owner = queries::find_user_by_id(state.db.read(), req.user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    if new_owner.deactivated_at.is_some() {
+        return Err(AppError::Validation("The new owner's account is deactivated".into()));
+    }
+
+    queries::set_server_owner(state.db.write(), server_id, req.user_id).await?;
+    crate::cache::invalidate(state.redis.clone().as_mut(), &state.memory, &format!("haven:server:{}", server_id)).await;
+    crate::member_list::refresh(&state, server_id).await;
+
+    let _ = queries::insert_audit_log(
+        state.db.write(), server_id, user_id, "owner_transfer",
+        Some("member"), Some(req.user_id), None, None,
+    ).await;
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
 /// DELETE /api/v1/servers/:server_id — delete a server (owner only)
 pub async fn delete_server(
     State(state): State<AppState>,
@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Query, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use serde_json::json;
+
+use crate::auth::email_hash_key_id;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::jwt_keys::{self, KeyPurpose, Keyring};
+use crate::middleware::AdminUser;
+use crate::models::{EmailHashKeyUsage, RotateSigningKeyQuery, SigningKeyResponse};
+use crate::AppState;
+
+/// GET /.well-known/jwks.json
+/// Public keys that verify Haven access tokens, including the next key
+/// before it starts signing.
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=3600")],
+        Json(state.jwt_keys.jwks()),
+    )
+}
+
+/// GET /.well-known/haven-keys.json
+/// Public keys for Haven's signatures other than access tokens, per purpose,
+/// including the next key before it starts signing.
+pub async fn service_keys(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=3600")],
+        Json(json!({
+            (KeyPurpose::TreeHead.as_str()): state.tree_head_keys.jwks(),
+            (KeyPurpose::SenderCertificate.as_str()): state.sender_cert_keys.jwks(),
+        })),
+    )
+}
+
+fn keyring(state: &AppState, purpose: KeyPurpose) -> &Keyring {
+    match purpose {
+        KeyPurpose::AccessToken => &state.jwt_keys,
+        KeyPurpose::TreeHead => &state.tree_head_keys,
+        KeyPurpose::SenderCertificate => &state.sender_cert_keys,
+    }
+}
+
+/// GET /api/v1/admin/signing-keys
+pub async fn list_signing_keys(
+    AdminUser(_user_id): AdminUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<SigningKeyResponse>>> {
+    let mut keys = state.jwt_keys.keys_info();
+    keys.extend(state.tree_head_keys.keys_info());
+    keys.extend(state.sender_cert_keys.keys_info());
+    Ok(Json(keys))
+}
+
+/// POST /api/v1/admin/signing-keys/rotate?purpose=
+/// Sign with a new key from now on, for access tokens unless `purpose` says
+/// otherwise. Tokens signed by the previous key stay valid until they expire.
+pub async fn rotate_signing_key(
+    AdminUser(admin_id): AdminUser,
+    State(state): State<AppState>,
+    Query(query): Query<RotateSigningKeyQuery>,
+) -> AppResult<Json<Vec<SigningKeyResponse>>> {
+    let purpose = match query.purpose.as_deref() {
+        None => KeyPurpose::AccessToken,
+        Some(purpose) => KeyPurpose::parse(purpose)
+            .ok_or_else(|| AppError::Validation(format!("Unknown signing key purpose '{}'", purpose)))?,
+    };
+    let ring = keyring(&state, purpose);
+    jwt_keys::rotate_now(&state.db, &state.config, &state.storage_key, ring).await?;
+    tracing::warn!("Admin {} rotated the {} signing key", admin_id, purpose.as_str());
+    Ok(Json(ring.keys_info()))
+}
+
+/// GET /api/v1/admin/email-hash-keys
+/// Stored email hashes per key, so a previous key can be dropped from the
+/// config once no user's hash depends on it.
+pub async fn email_hash_key_usage(
+    AdminUser(_user_id): AdminUser,
+    State(state): State<AppState>,
+) -> AppResult<Json<Vec<EmailHashKeyUsage>>> {
+    let config = &state.config;
+    let current = email_hash_key_id(config.current_email_hash_key());
+    let configured: HashSet<String> = config
+        .email_hash_keys()
+        .into_iter()
+        .map(email_hash_key_id)
+        .collect();
+    // Hashes from before key ids were recorded were made with jwt_secret
+    let legacy = email_hash_key_id(&config.jwt_secret);
+
+    let mut usage: Vec<EmailHashKeyUsage> = queries::count_email_hashes_by_key(state.db.read())
+        .await?
+        .into_iter()
+        .map(|(key_id, users)| {
+            let effective = key_id.as_deref().unwrap_or(&legacy);
+            EmailHashKeyUsage {
+                current: effective == current,
+                configured: configured.contains(effective),
+                key_id,
+                users,
+            }
+        })
+        .collect();
+    if !usage.iter().any(|u| u.current) {
+        usage.push(EmailHashKeyUsage {
+            key_id: Some(current),
+            current: true,
+            configured: true,
+            users: 0,
+        });
+    }
+    Ok(Json(usage))
+}
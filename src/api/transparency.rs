@@ -0,0 +1,146 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::api::b64;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::{AdminUser, AuthUser};
+use crate::models::*;
+use crate::transparency::{self, Hash};
+use crate::AppState;
+
+/// Sign a tree head over the log as it is now, from the stored subtree nodes
+/// covering it. Proofs and exports are built against its size so they match
+/// the head they ship with.
+async fn signed_tree_head(state: &AppState) -> AppResult<SignedTreeHead> {
+    let tree_size = queries::get_transparency_tree_size(state.db.read()).await? as u64;
+    let ids = transparency::root_nodes(tree_size);
+    let nodes = queries::get_transparency_nodes(state.db.read(), &ids).await?;
+    let root = fold(&nodes, &ids)?;
+
+    // Signatures cover millisecond timestamps; don't serialize more precision than was signed
+    let now = Utc::now();
+    let timestamp = DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now);
+    let (signature, public_key) =
+        transparency::sign_tree_head(&state.tree_head_keys, tree_size, timestamp, &root)?;
+
+    Ok(SignedTreeHead {
+        tree_size: tree_size as i64,
+        root_hash: b64(&root),
+        timestamp,
+        signature: b64(&signature),
+        public_key: b64(&public_key),
+    })
+}
+
+/// Hash over `ids` (a range's covering nodes) from the fetched nodes.
+fn fold(nodes: &queries::TransparencyNodes, ids: &[transparency::NodeId]) -> AppResult<Hash> {
+    let hashes = ids
+        .iter()
+        .map(|id| nodes.get(id).copied())
+        .collect::<Option<Vec<Hash>>>()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Key transparency tree is missing nodes")))?;
+    Ok(transparency::fold_nodes(&hashes))
+}
+
+fn entry_response(entry: TransparencyLogEntry, audit_path: Option<Vec<Hash>>) -> TransparencyLogEntryResponse {
+    TransparencyLogEntryResponse {
+        seq: entry.seq,
+        user_id: entry.user_id,
+        device_id: entry.device_id,
+        identity_key: b64(&entry.identity_key),
+        prev_hash: entry.prev_hash.as_deref().map(b64),
+        leaf_hash: b64(&entry.leaf_hash),
+        published_at: entry.published_at,
+        audit_path: audit_path.map(|path| path.iter().map(|h| b64(h)).collect()),
+    }
+}
+
+/// Record a newly published identity key in the transparency log.
+pub(crate) async fn publish_identity_key(
+    state: &AppState,
+    user_id: Uuid,
+    device_id: Uuid,
+    identity_key: &[u8],
+) -> AppResult<TransparencyLogEntry> {
+    queries::append_transparency_log(state.db.write(), user_id, device_id, identity_key, false)
+        .await?
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Identity key was not logged")))
+}
+
+/// GET /api/v1/keys/transparency/head
+/// The current signed tree head of the key transparency log.
+pub async fn get_tree_head(
+    State(state): State<AppState>,
+    AuthUser(_user_id): AuthUser,
+) -> AppResult<Json<SignedTreeHead>> {
+    Ok(Json(signed_tree_head(&state).await?))
+}
+
+/// GET /api/v1/users/:user_id/keys/log
+/// A user's identity key history, oldest first, each entry with an inclusion
+/// proof against the returned signed tree head.
+pub async fn get_user_key_log(
+    State(state): State<AppState>,
+    AuthUser(_requester_id): AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> AppResult<Json<KeyTransparencyLog>> {
+    let tree_head = signed_tree_head(&state).await?;
+    let tree_size = tree_head.tree_size as u64;
+
+    let logged: Vec<_> = queries::get_user_transparency_entries(state.db.read(), user_id)
+        .await?
+        .into_iter()
+        // Entries appended after the head are proven by the next one
+        .filter(|entry| (entry.seq as u64) < tree_size)
+        .collect();
+    if logged.is_empty() {
+        return Err(AppError::UserNotFound);
+    }
+
+    // Every node the audit paths need, fetched in one query
+    let paths: Vec<Vec<Vec<transparency::NodeId>>> = logged
+        .iter()
+        .map(|entry| transparency::audit_path_nodes(entry.seq as u64, tree_size))
+        .collect();
+    let ids: Vec<transparency::NodeId> = paths.iter().flatten().flatten().copied().collect();
+    let nodes = queries::get_transparency_nodes(state.db.read(), &ids).await?;
+
+    let entries = logged
+        .into_iter()
+        .zip(&paths)
+        .map(|(entry, path)| {
+            let path = path.iter().map(|step| fold(&nodes, step)).collect::<AppResult<Vec<Hash>>>()?;
+            Ok(entry_response(entry, Some(path)))
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+
+    Ok(Json(KeyTransparencyLog { tree_head, entries }))
+}
+
+/// GET /api/v1/admin/key-transparency?after=&limit=
+/// Export log entries after sequence number `after` for third-party auditors,
+/// together with a signed tree head covering at least the returned entries.
+pub async fn admin_export_log(
+    AdminUser(_admin_id): AdminUser,
+    State(state): State<AppState>,
+    Query(params): Query<TransparencyLogQuery>,
+) -> AppResult<Json<KeyTransparencyLog>> {
+    let after = params.after.unwrap_or(-1);
+    let limit = params.limit.unwrap_or(1000).clamp(1, 10_000);
+
+    let tree_head = signed_tree_head(&state).await?;
+
+    let entries = queries::list_transparency_log(state.db.read(), after, limit)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.seq < tree_head.tree_size)
+        .map(|entry| entry_response(entry, None))
+        .collect();
+
+    Ok(Json(KeyTransparencyLog { tree_head, entries }))
+}
@@ -1,13 +1,14 @@
 use axum::{
     body::Bytes,
     extract::{Path, Query, State},
-    http::{header, StatusCode},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Redirect},
     Json,
 };
 use serde::Deserialize;
 use uuid::Uuid;
 
+use crate::data_export;
 use crate::db::queries;
 use crate::errors::{AppError, AppResult};
 use crate::middleware::AuthUser;
@@ -35,7 +36,8 @@ pub async fn get_user_by_username(
     Query(query): Query<UserSearchQuery>,
 ) -> AppResult<Json<UserPublic>> {
     let user = queries::find_user_by_username(state.db.read(), &query.username).await?;
-    let user = user.ok_or(AppError::UserNotFound)?;
+    // Deactivated accounts can't be found
+    let user = user.filter(|u| u.deactivated_at.is_none()).ok_or(AppError::UserNotFound)?;
     Ok(Json(UserPublic::from(user)))
 }
 
@@ -46,8 +48,10 @@ pub async fn get_profile(
     Path(user_id): Path<Uuid>,
     Query(query): Query<ProfileQuery>,
 ) -> AppResult<Json<UserProfileResponse>> {
+    // A deactivated profile is hidden from everyone but its owner
     let user = queries::find_user_by_id_cached(state.db.read(), &mut state.redis.clone(), &state.memory, user_id)
         .await?
+        .filter(|u| u.deactivated_at.is_none() || u.id == requester_id)
         .ok_or(AppError::UserNotFound)?;
 
     let is_blocked = queries::is_blocked(state.db.read(), requester_id, user_id).await?;
@@ -95,6 +99,15 @@ pub async fn get_profile(
         base64::Engine::encode(&base64::engine::general_purpose::STANDARD, v)
     });
 
+    let (custom_status, custom_status_emoji) = user.active_custom_status();
+    let has_status = custom_status.is_some();
+
+    let (verified, verified_key_changed) = if requester_id != user_id {
+        crate::api::verification::verification_flags(&state, requester_id, user_id).await?
+    } else {
+        (false, false)
+    };
+
     Ok(Json(UserProfileResponse {
         id: user.id,
         username: user.username,
@@ -102,8 +115,9 @@ pub async fn get_profile(
         about_me: user.about_me,
         avatar_url: user.avatar_url,
         banner_url: user.banner_url,
-        custom_status: user.custom_status,
-        custom_status_emoji: user.custom_status_emoji,
+        custom_status,
+        custom_status_emoji,
+        custom_status_expires_at: user.custom_status_expires_at.filter(|_| has_status),
         created_at: user.created_at,
         is_blocked,
         is_friend,
@@ -114,6 +128,8 @@ pub async fn get_profile(
         mutual_server_count,
         roles,
         encrypted_profile,
+        verified,
+        verified_key_changed,
     }))
 }
 
@@ -133,6 +149,18 @@ pub async fn update_profile(
         None
     };
 
+    // An expiry only makes sense alongside a status
+    let custom_status_expires_at = req.custom_status_expires_at.filter(|_| req.custom_status.is_some());
+    if custom_status_expires_at.is_some_and(|t| t <= chrono::Utc::now()) {
+        return Err(AppError::Validation(
+            "custom_status_expires_at must be in the future".into(),
+        ));
+    }
+
+    let previous = queries::find_user_by_id(state.db.read(), user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+
     let user = queries::update_user_profile(
         state.db.write(),
         user_id,
@@ -140,12 +168,27 @@ pub async fn update_profile(
         req.about_me.as_deref(),
         req.custom_status.as_deref(),
         req.custom_status_emoji.as_deref(),
+        custom_status_expires_at,
         encrypted_profile_bytes.as_deref(),
     )
     .await?;
 
     // Invalidate user cache
     crate::cache::invalidate(state.redis.clone().as_mut(), &state.memory, &format!("haven:user:{}", user_id)).await;
+    crate::member_list::refresh_user(&state, user_id).await;
+
+    if user.active_custom_status() != previous.active_custom_status()
+        || user.custom_status_expires_at != previous.custom_status_expires_at
+    {
+        crate::ws::broadcast_custom_status(
+            &state,
+            user_id,
+            user.custom_status.clone(),
+            user.custom_status_emoji.clone(),
+            user.custom_status_expires_at,
+        )
+        .await;
+    }
 
     Ok(Json(UserPublic::from(user)))
 }
@@ -455,3 +498,65 @@ pub async fn get_profile_key(
         ),
     }))
 }
+
+/// POST /api/v1/users/@me/export — start building an archive of everything
+/// the server holds about the caller. Poll `GET /users/@me/export` for it.
+pub async fn request_data_export(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    headers: HeaderMap,
+) -> AppResult<(StatusCode, Json<DataExportResponse>)> {
+    let export = data_export::start(&state, user_id, &headers).await?;
+    Ok((StatusCode::ACCEPTED, Json(data_export::to_response(&state, export))))
+}
+
+/// GET /api/v1/users/@me/export — the caller's latest export, with a download
+/// link once it is ready
+pub async fn get_data_export(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<DataExportResponse>> {
+    let export = queries::list_user_data_exports(state.db.read(), user_id)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(AppError::NotFound("No data export requested".into()))?;
+    Ok(Json(data_export::to_response(&state, export)))
+}
+
+/// GET /api/v1/exports/:export_id/download?sig= — download an export archive.
+/// The signed link stands in for auth, so it works from a plain browser tab.
+pub async fn download_data_export(
+    State(state): State<AppState>,
+    Path(export_id): Path<Uuid>,
+    Query(query): Query<DataExportDownloadQuery>,
+) -> AppResult<impl IntoResponse> {
+    if !data_export::verify_download_signature(&state.storage_key, export_id, &query.sig) {
+        return Err(AppError::Forbidden("Invalid download link".into()));
+    }
+    let export = queries::find_data_export(state.db.read(), export_id)
+        .await?
+        .filter(|e| e.status == "ready" && e.expires_at.is_some_and(|at| at > chrono::Utc::now()))
+        .ok_or(AppError::NotFound("Export not found or expired".into()))?;
+    let storage_key = export
+        .storage_key
+        .ok_or(AppError::NotFound("Export not found or expired".into()))?;
+
+    // Unsealed chunk by chunk on the way out
+    let chunks = state
+        .storage
+        .load_sealed(&storage_key)
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to load data export: {}", e)))?;
+
+    let filename = format!("haven-export-{}.tar", export.created_at.format("%Y-%m-%d"));
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename)),
+            (header::CONTENT_LENGTH, export.size_bytes.unwrap_or_default().to_string()),
+        ],
+        axum::body::Body::from_stream(chunks),
+    ))
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::api::{b64, decode_b64};
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::middleware::AuthUser;
+use crate::models::*;
+use crate::pubsub;
+use crate::AppState;
+
+const MAX_STATE_SIZE: usize = 64 * 1024; // 64 KB
+
+/// Order-independent fingerprint of a set of identity keys: SHA-256 over the
+/// sorted keys, each prefixed with its big-endian u32 length.
+fn keys_fingerprint(mut keys: Vec<Vec<u8>>) -> Vec<u8> {
+    keys.sort();
+    keys.dedup();
+    let mut hasher = Sha256::new();
+    for key in &keys {
+        hasher.update((key.len() as u32).to_be_bytes());
+        hasher.update(key);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Whether `viewer` verified `contact`, and whether the contact's identity
+/// keys have changed since (any device key added, removed or replaced).
+pub(crate) async fn verification_flags(
+    state: &AppState,
+    viewer: Uuid,
+    contact: Uuid,
+) -> AppResult<(bool, bool)> {
+    let Some(verified_hash) = queries::get_verified_keys_hash(state.db.read(), viewer, contact).await?
+    else {
+        return Ok((false, false));
+    };
+    let keys = queries::get_device_identity_keys(state.db.read(), &[contact])
+        .await?
+        .into_iter()
+        .map(|(_, key)| key)
+        .collect();
+    Ok((true, keys_fingerprint(keys) != verified_hash))
+}
+
+/// GET /api/v1/keys/verifications
+/// All of the caller's per-contact verification states.
+pub async fn list_verification_states(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+) -> AppResult<Json<Vec<VerificationStateResponse>>> {
+    let states = queries::get_verification_states(state.db.read(), user_id).await?;
+
+    let contacts: Vec<Uuid> = states
+        .iter()
+        .filter(|s| s.verified_keys_hash.is_some())
+        .map(|s| s.contact_id)
+        .collect();
+    let mut keys: HashMap<Uuid, Vec<Vec<u8>>> = HashMap::new();
+    for (contact_id, key) in queries::get_device_identity_keys(state.db.read(), &contacts).await? {
+        keys.entry(contact_id).or_default().push(key);
+    }
+
+    let responses = states
+        .into_iter()
+        .map(|s| {
+            let verified_key_changed = s.verified_keys_hash.as_ref().is_some_and(|hash| {
+                keys_fingerprint(keys.remove(&s.contact_id).unwrap_or_default()) != *hash
+            });
+            VerificationStateResponse {
+                contact_id: s.contact_id,
+                encrypted_state: b64(&s.encrypted_state),
+                nonce: b64(&s.nonce),
+                verified: s.verified_keys_hash.is_some(),
+                verified_key_changed,
+                updated_at: s.updated_at,
+            }
+        })
+        .collect();
+
+    Ok(Json(responses))
+}
+
+/// PUT /api/v1/keys/verifications/:contact_id
+/// Store the caller's encrypted verification state for a contact, and the
+/// contact keys it vouches for. The caller's other sessions get
+/// `VerificationStateUpdated`.
+pub async fn upload_verification_state(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(contact_id): Path<Uuid>,
+    Json(req): Json<UploadVerificationStateRequest>,
+) -> AppResult<Json<VerificationStateResponse>> {
+    let encrypted_state = decode_b64(&req.encrypted_state, "encrypted_state")?;
+    let nonce = decode_b64(&req.nonce, "nonce")?;
+    if nonce.len() != 24 {
+        return Err(AppError::Validation("Nonce must be 24 bytes".into()));
+    }
+    if encrypted_state.len() > MAX_STATE_SIZE {
+        return Err(AppError::Validation(format!(
+            "Verification state too large (max {}KB)",
+            MAX_STATE_SIZE / 1024
+        )));
+    }
+
+    if queries::find_user_by_id(state.db.read(), contact_id).await?.is_none() {
+        return Err(AppError::UserNotFound);
+    }
+
+    let verified_keys_hash = match req.verified_identity_keys {
+        Some(keys) if keys.is_empty() => {
+            return Err(AppError::Validation("verified_identity_keys is empty".into()));
+        }
+        Some(keys) => Some(keys_fingerprint(
+            keys.iter()
+                .map(|k| decode_b64(k, "verified_identity_keys"))
+                .collect::<AppResult<_>>()?,
+        )),
+        None => None,
+    };
+
+    let saved = queries::upsert_verification_state(
+        state.db.write(),
+        user_id,
+        contact_id,
+        &encrypted_state,
+        &nonce,
+        verified_keys_hash.as_deref(),
+    )
+    .await?;
+
+    let (verified, verified_key_changed) = verification_flags(&state, user_id, contact_id).await?;
+    pubsub::send_to_user(&state, user_id, &WsServerMessage::VerificationStateUpdated { contact_id }).await;
+
+    Ok(Json(VerificationStateResponse {
+        contact_id,
+        encrypted_state: b64(&saved.encrypted_state),
+        nonce: b64(&saved.nonce),
+        verified,
+        verified_key_changed,
+        updated_at: saved.updated_at,
+    }))
+}
+
+/// DELETE /api/v1/keys/verifications/:contact_id
+pub async fn delete_verification_state(
+    State(state): State<AppState>,
+    AuthUser(user_id): AuthUser,
+    Path(contact_id): Path<Uuid>,
+) -> AppResult<Json<serde_json::Value>> {
+    if !queries::delete_verification_state(state.db.write(), user_id, contact_id).await? {
+        return Err(AppError::NotFound("No verification state for this contact".into()));
+    }
+    pubsub::send_to_user(&state, user_id, &WsServerMessage::VerificationStateUpdated { contact_id }).await;
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
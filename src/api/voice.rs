@@ -338,12 +338,7 @@ async fn broadcast_voice_state(
             if let Ok(member_ids) = queries::get_channel_member_ids(state.db.read(), channel_id).await {
                 for mid in member_ids {
                     if mid == user_id { continue; }
-                    if let Some(conns) = state.connections.get(&mid) {
-                        for tx in conns.iter() {
-                            let _ = tx.send(msg.clone());
-                        }
-                    }
-                    pubsub::publish_user_event(state.redis.clone().as_mut(), mid, &msg).await;
+                    pubsub::send_to_user(state, mid, &msg).await;
                 }
             }
         }
@@ -0,0 +1,224 @@
+//! Personal API tokens for scripts and integrations.
+//!
+//! A user creates named tokens (`hvn_pat_…`) from `/auth/tokens`, each with
+//! scopes and an optional expiry. `AuthUser` accepts them in place of an
+//! access token, but only on the routes their scopes cover:
+//!
+//! - `messages:read` — GET on servers, channels, messages, DMs and attachments
+//! - `messages:send` — posting messages, sender keys and attachments
+//! - `server:<uuid>:manage` — every route under `/servers/<uuid>`
+//!
+//! Everything else — account, keys, sessions, tokens, admin and the
+//! WebSocket — needs a signed-in session. Only the SHA-256 hash of a token
+//! is stored.
+
+use std::fmt;
+
+use axum::extract::{FromRequestParts, MatchedPath, RawPathParams};
+use axum::http::{request::Parts, Method};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::api::auth_routes::extract_ip_from_headers;
+use crate::auth;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::AppState;
+
+/// Marks a bearer token as a personal API token rather than a JWT
+pub const TOKEN_PREFIX: &str = "hvn_pat_";
+
+/// How stale `last_used_at` may get before a use is written back (seconds)
+const LAST_USED_GRANULARITY_SECS: i64 = 60;
+
+/// What a token may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    MessagesRead,
+    MessagesSend,
+    ManageServer(Uuid),
+}
+
+impl Scope {
+    pub fn parse(s: &str) -> Option<Scope> {
+        match s {
+            "messages:read" => Some(Scope::MessagesRead),
+            "messages:send" => Some(Scope::MessagesSend),
+            _ => {
+                let id = s.strip_prefix("server:")?.strip_suffix(":manage")?;
+                Uuid::parse_str(id).ok().map(Scope::ManageServer)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::MessagesRead => f.write_str("messages:read"),
+            Scope::MessagesSend => f.write_str("messages:send"),
+            Scope::ManageServer(id) => write!(f, "server:{}:manage", id),
+        }
+    }
+}
+
+/// A new random token.
+pub fn generate_token() -> String {
+    format!("{}{}", TOKEN_PREFIX, auth::generate_refresh_token())
+}
+
+/// The scopes, any one of which lets a token call `route` (the matched route
+/// template, e.g. `/api/v1/channels/:channel_id/messages`). Empty when tokens
+/// can't call it at all.
+pub fn accepted_scopes(method: &Method, route: &str, server_id: Option<Uuid>) -> Vec<Scope> {
+    let route = route.strip_prefix("/api/v1").unwrap_or(route);
+    let under = |prefix: &str| route == prefix || route.starts_with(&format!("{}/", prefix));
+    let mut scopes = Vec::new();
+
+    if method == Method::GET
+        && (under("/servers")
+            || under("/channels")
+            || under("/messages")
+            || under("/dm")
+            || route == "/attachments/:attachment_id")
+    {
+        scopes.push(Scope::MessagesRead);
+    }
+    if method == Method::POST
+        && matches!(
+            route,
+            "/channels/:channel_id/messages" | "/channels/:channel_id/sender-keys" | "/attachments/upload"
+        )
+    {
+        scopes.push(Scope::MessagesSend);
+    }
+    if let Some(server_id) = server_id {
+        if under("/servers/:server_id") && !owner_only(method, route) {
+            scopes.push(Scope::ManageServer(server_id));
+        }
+    }
+    scopes
+}
+
+/// Server routes that delete the server or hand it over (leaving deletes it
+/// when the owner is the last member). Only the owner's own sessions may
+/// call these, whatever a token's scopes say.
+fn owner_only(method: &Method, route: &str) -> bool {
+    matches!(
+        (method.as_str(), route),
+        ("DELETE", "/servers/:server_id")
+            | ("PUT", "/servers/:server_id/owner")
+            | ("DELETE", "/servers/:server_id/members/@me")
+    )
+}
+
+/// The user a personal API token acts for, if it is valid and its scopes
+/// cover the request.
+pub async fn authenticate(state: &AppState, token: &str, parts: &mut Parts) -> AppResult<Uuid> {
+    let record = queries::find_api_token_by_hash(state.db.read(), &auth::hash_refresh_token(token))
+        .await?
+        .ok_or(AppError::AuthError("Invalid API token".into()))?;
+    let now = Utc::now();
+    if record.expires_at.is_some_and(|at| at <= now) {
+        return Err(AppError::AuthError("API token has expired".into()));
+    }
+
+    let route = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_owned())
+        .unwrap_or_default();
+    let server_id = RawPathParams::from_request_parts(parts, state)
+        .await
+        .ok()
+        .and_then(|params| {
+            params
+                .iter()
+                .find(|(name, _)| *name == "server_id")
+                .and_then(|(_, value)| Uuid::parse_str(value).ok())
+        });
+
+    let accepted = accepted_scopes(&parts.method, &route, server_id);
+    if accepted.is_empty() {
+        return Err(AppError::Forbidden("API tokens can't be used for this endpoint".into()));
+    }
+    let granted: Vec<Scope> = record.scopes.split_whitespace().filter_map(Scope::parse).collect();
+    if !accepted.iter().any(|scope| granted.contains(scope)) {
+        return Err(AppError::Forbidden(format!("API token lacks the {} scope", accepted[0])));
+    }
+
+    let fresh = record
+        .last_used_at
+        .is_some_and(|at| now - at <= Duration::seconds(LAST_USED_GRANULARITY_SECS));
+    if !fresh {
+        let ip = extract_ip_from_headers(&parts.headers);
+        queries::touch_api_token(state.db.write(), record.id, ip.as_deref()).await?;
+    }
+
+    Ok(record.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip() {
+        let server = Uuid::new_v4();
+        for scope in [Scope::MessagesRead, Scope::MessagesSend, Scope::ManageServer(server)] {
+            assert_eq!(Scope::parse(&scope.to_string()), Some(scope));
+        }
+        assert_eq!(Scope::parse("server:not-a-uuid:manage"), None);
+        assert_eq!(Scope::parse("admin"), None);
+    }
+
+    #[test]
+    fn message_routes_need_message_scopes() {
+        let read = accepted_scopes(&Method::GET, "/api/v1/channels/:channel_id/messages", None);
+        assert_eq!(read, vec![Scope::MessagesRead]);
+
+        let send = accepted_scopes(&Method::POST, "/api/v1/channels/:channel_id/messages", None);
+        assert_eq!(send, vec![Scope::MessagesSend]);
+
+        // Deleting a channel is neither
+        assert!(accepted_scopes(&Method::DELETE, "/api/v1/channels/:channel_id", None).is_empty());
+    }
+
+    #[test]
+    fn server_routes_need_that_servers_scope() {
+        let server = Uuid::new_v4();
+        let scopes = accepted_scopes(&Method::PATCH, "/api/v1/servers/:server_id", Some(server));
+        assert_eq!(scopes, vec![Scope::ManageServer(server)]);
+
+        let scopes = accepted_scopes(&Method::GET, "/api/v1/servers/:server_id/members", Some(server));
+        assert_eq!(scopes, vec![Scope::MessagesRead, Scope::ManageServer(server)]);
+
+        // Creating a server isn't managing one
+        assert!(accepted_scopes(&Method::POST, "/api/v1/servers", None).is_empty());
+    }
+
+    #[test]
+    fn owner_only_server_routes_are_off_limits() {
+        let server = Uuid::new_v4();
+        for (method, route) in [
+            (Method::DELETE, "/api/v1/servers/:server_id"),
+            (Method::PUT, "/api/v1/servers/:server_id/owner"),
+            (Method::DELETE, "/api/v1/servers/:server_id/members/@me"),
+        ] {
+            assert!(accepted_scopes(&method, route, Some(server)).is_empty(), "{} {}", method, route);
+        }
+    }
+
+    #[test]
+    fn account_routes_are_off_limits() {
+        for (method, route) in [
+            (Method::POST, "/api/v1/auth/tokens"),
+            (Method::GET, "/api/v1/auth/sessions"),
+            (Method::PUT, "/api/v1/keys/identity"),
+            (Method::POST, "/api/v1/ws/ticket"),
+            (Method::GET, "/api/v1/admin/stats"),
+        ] {
+            assert!(accepted_scopes(&method, route, None).is_empty(), "{} {}", method, route);
+        }
+    }
+}
@@ -3,7 +3,6 @@ use argon2::{
     Argon2,
 };
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -11,6 +10,7 @@ use uuid::Uuid;
 
 use crate::config::AppConfig;
 use crate::errors::{AppError, AppResult};
+use crate::jwt_keys::Keyring;
 
 // ─── JWT Claims ────────────────────────────────────────
 
@@ -20,6 +20,10 @@ pub struct Claims {
     pub exp: usize,  // expiry timestamp
     pub iat: usize,  // issued at
     pub jti: String, // unique token ID
+    /// Refresh-token family (session) this token was issued to. Revoking the
+    /// session denylists the family, which invalidates its access tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_id: Option<Uuid>,
 }
 
 // ─── Password Hashing (Argon2id) ───────────────────────
@@ -44,7 +48,7 @@ pub fn verify_password(password: &str, hash: &str) -> AppResult<bool> {
 }
 
 /// Hash an email for storage (one-way, for account recovery matching).
-/// Uses HMAC-SHA256 with the server's JWT secret as key to prevent rainbow table attacks.
+/// Uses HMAC-SHA256 with an email hash key to prevent rainbow table attacks.
 pub fn hash_email(email: &str, secret: &str) -> String {
     use hmac::{Hmac, Mac};
     type HmacSha256 = Hmac<Sha256>;
@@ -56,10 +60,39 @@ pub fn hash_email(email: &str, secret: &str) -> String {
     format!("{:x}", mac.finalize().into_bytes())
 }
 
+/// Short ID of an email hash key, stored next to each hash so a rotated-out
+/// key can be dropped once no hash references it.
+pub fn email_hash_key_id(key: &str) -> String {
+    let digest = Sha256::digest(format!("haven email hash key id\n{}", key).as_bytes());
+    hex::encode(&digest[..8])
+}
+
+/// Hash an email under the current email hash key. Returns `(hash, key_id)`.
+pub fn hash_email_current(email: &str, config: &AppConfig) -> (String, String) {
+    let key = config.current_email_hash_key();
+    (hash_email(email, key), email_hash_key_id(key))
+}
+
+/// The email's hash under every configured key, for lookups that must also
+/// match hashes made before a rotation.
+pub fn email_hash_candidates(email: &str, config: &AppConfig) -> Vec<String> {
+    config
+        .email_hash_keys()
+        .into_iter()
+        .map(|key| hash_email(email, key))
+        .collect()
+}
+
 // ─── JWT Token Generation ──────────────────────────────
 
-/// Generate a JWT access token for a user.
-pub fn generate_access_token(user_id: Uuid, config: &AppConfig) -> AppResult<String> {
+/// Generate a JWT access token for a user, signed with the keyring's
+/// current key.
+pub fn generate_access_token(
+    user_id: Uuid,
+    family_id: Option<Uuid>,
+    config: &AppConfig,
+    keys: &Keyring,
+) -> AppResult<String> {
     let now = Utc::now();
     let expiry = now + Duration::hours(config.jwt_expiry_hours);
 
@@ -68,31 +101,18 @@ pub fn generate_access_token(user_id: Uuid, config: &AppConfig) -> AppResult<Str
         exp: expiry.timestamp() as usize,
         iat: now.timestamp() as usize,
         jti: Uuid::new_v4().to_string(),
+        family_id,
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.jwt_secret.as_bytes()),
-    )
-    .map_err(|e| AppError::Internal(anyhow::anyhow!("JWT encoding failed: {}", e)))?;
-
-    Ok(token)
+    keys.sign(&claims)
 }
 
-/// Validate a JWT access token and extract claims.
-pub fn validate_access_token(token: &str, config: &AppConfig) -> AppResult<Claims> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(config.jwt_secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|e| match e.kind() {
+/// Validate a JWT access token against the keyring and extract claims.
+pub fn validate_access_token(token: &str, keys: &Keyring) -> AppResult<Claims> {
+    keys.verify::<Claims>(token).map_err(|e| match e.kind() {
         jsonwebtoken::errors::ErrorKind::ExpiredSignature => AppError::TokenExpired,
         _ => AppError::InvalidToken,
-    })?;
-
-    Ok(token_data.claims)
+    })
 }
 
 /// Extract the user ID from validated claims.
@@ -110,6 +130,13 @@ pub fn generate_refresh_token() -> String {
     base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, &bytes)
 }
 
+/// Generate an opaque, URL-safe WebSocket connect ticket.
+pub fn generate_ws_ticket() -> String {
+    let mut rng = rand::thread_rng();
+    let bytes: Vec<u8> = (0..32).map(|_| rng.gen()).collect();
+    base64::Engine::encode(&base64::engine::general_purpose::URL_SAFE_NO_PAD, &bytes)
+}
+
 /// Hash a refresh token for storage (we never store the raw token).
 pub fn hash_refresh_token(token: &str) -> String {
     let mut hasher = Sha256::new();
@@ -166,6 +193,7 @@ pub fn verify_totp(secret_b32: &str, code: &str) -> AppResult<bool> {
 mod tests {
     use super::*;
     use crate::config::AppConfig;
+    use crate::jwt_keys::SigningAlgorithm;
 
     fn test_config() -> AppConfig {
         AppConfig::test_default()
@@ -228,32 +256,61 @@ mod tests {
         assert_ne!(h1, h2);
     }
 
+    #[test]
+    fn email_hash_candidates_cover_previous_keys() {
+        let mut config = test_config();
+        config.email_hash_key = "new-key".into();
+        config.email_hash_previous_keys = "old-key, older-key".into();
+
+        let (hash, key_id) = hash_email_current("a@example.com", &config);
+        assert_eq!(hash, hash_email("a@example.com", "new-key"));
+        assert_eq!(key_id, email_hash_key_id("new-key"));
+        assert_eq!(
+            email_hash_candidates("a@example.com", &config),
+            vec![
+                hash,
+                hash_email("a@example.com", "old-key"),
+                hash_email("a@example.com", "older-key"),
+            ]
+        );
+
+        // Without a dedicated key, hashes keep matching the legacy jwt_secret ones
+        config.email_hash_key.clear();
+        assert_eq!(
+            hash_email_current("a@example.com", &config).0,
+            hash_email("a@example.com", &config.jwt_secret)
+        );
+    }
+
     // ─── JWT Tokens ─────────────────────────────────────
 
     #[test]
     fn generate_and_validate_access_token() {
         let config = test_config();
+        let keys = Keyring::ephemeral(SigningAlgorithm::EdDSA);
         let user_id = Uuid::new_v4();
-        let token = generate_access_token(user_id, &config).unwrap();
-        let claims = validate_access_token(&token, &config).unwrap();
+        let family_id = Uuid::new_v4();
+        let token = generate_access_token(user_id, Some(family_id), &config, &keys).unwrap();
+        let claims = validate_access_token(&token, &keys).unwrap();
         assert_eq!(claims.sub, user_id.to_string());
+        assert_eq!(claims.family_id, Some(family_id));
     }
 
     #[test]
-    fn validate_token_wrong_secret_fails() {
+    fn validate_token_wrong_keyring_fails() {
         let config = test_config();
+        let keys = Keyring::ephemeral(SigningAlgorithm::EdDSA);
         let user_id = Uuid::new_v4();
-        let token = generate_access_token(user_id, &config).unwrap();
+        let token = generate_access_token(user_id, None, &config, &keys).unwrap();
 
-        let mut bad_config = test_config();
-        bad_config.jwt_secret = "completely-different-secret-key-for-testing".into();
-        assert!(validate_access_token(&token, &bad_config).is_err());
+        let other_keys = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        assert!(validate_access_token(&token, &other_keys).is_err());
     }
 
     #[test]
     fn validate_token_garbage_fails() {
-        let config = test_config();
-        assert!(validate_access_token("not.a.jwt", &config).is_err());
+        let keys = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        assert!(validate_access_token("not.a.jwt", &keys).is_err());
     }
 
     #[test]
@@ -264,6 +321,7 @@ mod tests {
             exp: 99999999999,
             iat: 0,
             jti: Uuid::new_v4().to_string(),
+            family_id: None,
         };
         assert_eq!(user_id_from_claims(&claims).unwrap(), user_id);
     }
@@ -275,6 +333,7 @@ mod tests {
             exp: 99999999999,
             iat: 0,
             jti: Uuid::new_v4().to_string(),
+            family_id: None,
         };
         assert!(user_id_from_claims(&claims).is_err());
     }
@@ -0,0 +1,178 @@
+//! CAPTCHA verification for registration.
+//!
+//! Cloudflare Turnstile, hCaptcha and the self-hostable mCaptcha are
+//! supported. The verify URL can be overridden for each, which is required for
+//! mCaptcha and lets tests point at a local stand-in.
+
+use crate::config::AppConfig;
+use crate::errors::{AppError, AppResult};
+
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+const HCAPTCHA_VERIFY_URL: &str = "https://api.hcaptcha.com/siteverify";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptchaProvider {
+    Turnstile,
+    HCaptcha,
+    MCaptcha,
+}
+
+impl CaptchaProvider {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "turnstile" => Some(Self::Turnstile),
+            "hcaptcha" => Some(Self::HCaptcha),
+            "mcaptcha" => Some(Self::MCaptcha),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Turnstile => "turnstile",
+            Self::HCaptcha => "hcaptcha",
+            Self::MCaptcha => "mcaptcha",
+        }
+    }
+
+    fn default_verify_url(&self) -> Option<&'static str> {
+        match self {
+            Self::Turnstile => Some(TURNSTILE_VERIFY_URL),
+            Self::HCaptcha => Some(HCAPTCHA_VERIFY_URL),
+            // Self-hosted: there is no default instance
+            Self::MCaptcha => None,
+        }
+    }
+}
+
+/// A configured CAPTCHA provider.
+#[derive(Debug, Clone)]
+pub struct Captcha {
+    pub provider: CaptchaProvider,
+    pub site_key: String,
+    secret_key: String,
+    verify_url: String,
+}
+
+impl Captcha {
+    /// The CAPTCHA the config asks for, if any. The legacy `turnstile_*`
+    /// options still enable Turnstile when no provider is named.
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        let (provider, site_key, secret_key) = if config.captcha_provider.is_empty() {
+            if !config.turnstile_enabled() {
+                return None;
+            }
+            (
+                CaptchaProvider::Turnstile,
+                &config.turnstile_site_key,
+                &config.turnstile_secret_key,
+            )
+        } else {
+            let Some(provider) = CaptchaProvider::parse(&config.captcha_provider) else {
+                tracing::error!("Unknown captcha_provider '{}' — CAPTCHA disabled", config.captcha_provider);
+                return None;
+            };
+            (provider, &config.captcha_site_key, &config.captcha_secret_key)
+        };
+        if site_key.is_empty() || secret_key.is_empty() {
+            tracing::error!("CAPTCHA provider {:?} needs a site key and a secret key — CAPTCHA disabled", provider);
+            return None;
+        }
+
+        let verify_url = if config.captcha_verify_url.is_empty() {
+            match provider.default_verify_url() {
+                Some(url) => url.to_string(),
+                None => {
+                    tracing::error!("CAPTCHA provider {:?} needs captcha_verify_url — CAPTCHA disabled", provider);
+                    return None;
+                }
+            }
+        } else {
+            config.captcha_verify_url.clone()
+        };
+
+        Some(Captcha {
+            provider,
+            site_key: site_key.clone(),
+            secret_key: secret_key.clone(),
+            verify_url,
+        })
+    }
+
+    /// Check a token the client's widget produced. Returns Ok(()) on success.
+    pub async fn verify(&self, token: &str, remote_ip: Option<&str>) -> AppResult<()> {
+        let client = reqwest::Client::new();
+        let request = match self.provider {
+            CaptchaProvider::Turnstile | CaptchaProvider::HCaptcha => {
+                let mut form = vec![("secret", self.secret_key.as_str()), ("response", token)];
+                if self.provider == CaptchaProvider::HCaptcha {
+                    form.push(("sitekey", self.site_key.as_str()));
+                }
+                if let Some(ip) = remote_ip {
+                    form.push(("remoteip", ip));
+                }
+                client.post(&self.verify_url).form(&form)
+            }
+            CaptchaProvider::MCaptcha => client.post(&self.verify_url).json(&serde_json::json!({
+                "token": token,
+                "key": self.site_key,
+                "secret": self.secret_key,
+            })),
+        };
+
+        let res = request
+            .send()
+            .await
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("CAPTCHA verification request failed: {}", e)))?;
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("CAPTCHA response parse failed: {}", e)))?;
+
+        let field = match self.provider {
+            CaptchaProvider::MCaptcha => "valid",
+            _ => "success",
+        };
+        if body.get(field).and_then(|v| v.as_bool()) == Some(true) {
+            Ok(())
+        } else {
+            Err(AppError::Validation("CAPTCHA verification failed — please try again".into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_turnstile_options_still_work() {
+        let mut config = AppConfig::test_default();
+        assert!(Captcha::from_config(&config).is_none());
+
+        config.turnstile_site_key = "site".into();
+        config.turnstile_secret_key = "secret".into();
+        let captcha = Captcha::from_config(&config).unwrap();
+        assert_eq!(captcha.provider, CaptchaProvider::Turnstile);
+        assert_eq!(captcha.verify_url, TURNSTILE_VERIFY_URL);
+    }
+
+    #[test]
+    fn mcaptcha_requires_a_verify_url() {
+        let mut config = AppConfig::test_default();
+        config.captcha_provider = "mcaptcha".into();
+        config.captcha_site_key = "site".into();
+        config.captcha_secret_key = "secret".into();
+        assert!(Captcha::from_config(&config).is_none());
+
+        config.captcha_verify_url = "https://captcha.example/api/v1/pow/siteverify".into();
+        let captcha = Captcha::from_config(&config).unwrap();
+        assert_eq!(captcha.provider, CaptchaProvider::MCaptcha);
+    }
+
+    #[test]
+    fn provider_names_are_case_insensitive() {
+        assert_eq!(CaptchaProvider::parse("hCaptcha"), Some(CaptchaProvider::HCaptcha));
+        assert_eq!(CaptchaProvider::parse("recaptcha"), None);
+    }
+}
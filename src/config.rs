@@ -69,6 +69,33 @@ pub struct ConfigFile {
     #[serde(default = "default_ws_session_ttl_secs")]
     pub ws_session_ttl_secs: u64,
 
+    #[serde(default = "default_ws_allow_query_token")]
+    pub ws_allow_query_token: bool,
+
+    #[serde(default = "default_lazy_member_list_threshold")]
+    pub lazy_member_list_threshold: u32,
+
+    #[serde(default = "default_ws_drain_timeout_secs")]
+    pub ws_drain_timeout_secs: u64,
+
+    #[serde(default = "default_presence_idle_timeout_secs")]
+    pub presence_idle_timeout_secs: u64,
+
+    #[serde(default)]
+    pub presence_last_seen: bool,
+
+    #[serde(default = "default_signed_prekey_max_age_days")]
+    pub signed_prekey_max_age_days: u32,
+
+    #[serde(default = "default_signed_prekey_grace_hours")]
+    pub signed_prekey_grace_hours: u32,
+
+    #[serde(default = "default_key_backup_versions_retained")]
+    pub key_backup_versions_retained: u32,
+
+    #[serde(default = "default_ws_reconnect_jitter_ms")]
+    pub ws_reconnect_jitter_ms: u64,
+
     #[serde(default = "default_max_upload_size_bytes")]
     pub max_upload_size_bytes: u64,
 
@@ -118,6 +145,90 @@ pub struct ConfigFile {
     pub turnstile_site_key: String,
     #[serde(default)]
     pub turnstile_secret_key: String,
+
+    // Login methods
+    #[serde(default = "default_password_login_enabled")]
+    pub password_login_enabled: bool,
+    #[serde(default)]
+    pub oidc_issuer: String,
+    #[serde(default)]
+    pub oidc_client_id: String,
+    #[serde(default)]
+    pub oidc_client_secret: String,
+    #[serde(default)]
+    pub oidc_redirect_uri: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub oidc_scopes: String,
+    #[serde(default = "default_oidc_username_claim")]
+    pub oidc_username_claim: String,
+    #[serde(default = "default_oidc_auto_provision")]
+    pub oidc_auto_provision: bool,
+
+    // Signing keys
+    #[serde(default = "default_jwt_algorithm")]
+    pub jwt_algorithm: String,
+    #[serde(default = "default_jwt_key_rotation_days")]
+    pub jwt_key_rotation_days: i64,
+    #[serde(default)]
+    pub email_hash_key: String,
+    #[serde(default)]
+    pub email_hash_previous_keys: String,
+
+    // Anti-abuse
+    #[serde(default = "default_pow_base_difficulty")]
+    pub pow_base_difficulty: u32,
+    #[serde(default = "default_pow_max_difficulty")]
+    pub pow_max_difficulty: u32,
+    #[serde(default = "default_pow_registration_baseline")]
+    pub pow_registration_baseline: u32,
+    #[serde(default = "default_login_pow_failure_threshold")]
+    pub login_pow_failure_threshold: u32,
+    #[serde(default)]
+    pub captcha_provider: String,
+    #[serde(default)]
+    pub captcha_site_key: String,
+    #[serde(default)]
+    pub captcha_secret_key: String,
+    #[serde(default)]
+    pub captcha_verify_url: String,
+
+    // Login protection
+    #[serde(default = "default_login_lockout_free_attempts")]
+    pub login_lockout_free_attempts: u32,
+    #[serde(default = "default_login_lockout_base_secs")]
+    pub login_lockout_base_secs: u64,
+    #[serde(default = "default_login_lockout_max_secs")]
+    pub login_lockout_max_secs: u64,
+    #[serde(default = "default_security_event_retention_days")]
+    pub security_event_retention_days: u32,
+
+    // Email (verification and password reset)
+    #[serde(default)]
+    pub smtp_host: String,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub smtp_username: String,
+    #[serde(default)]
+    pub smtp_password: String,
+    #[serde(default = "default_smtp_tls")]
+    pub smtp_tls: String,
+    #[serde(default = "default_smtp_from")]
+    pub smtp_from: String,
+    #[serde(default)]
+    pub public_url: String,
+    #[serde(default = "default_email_verification_expiry_hours")]
+    pub email_verification_expiry_hours: i64,
+    #[serde(default = "default_password_reset_expiry_minutes")]
+    pub password_reset_expiry_minutes: i64,
+
+    // Account deletion
+    #[serde(default = "default_account_deletion_grace_days")]
+    pub account_deletion_grace_days: u32,
+
+    // Personal data export
+    #[serde(default = "default_data_export_expiry_hours")]
+    pub data_export_expiry_hours: i64,
 }
 
 // ─── TLS Config ───────────────────────────────────────
@@ -163,6 +274,11 @@ fn default_broadcast_channel_capacity() -> usize { 4096 }
 fn default_ws_heartbeat_timeout_secs() -> u64 { 90 }
 fn default_ws_session_buffer_size() -> usize { 500 }
 fn default_ws_session_ttl_secs() -> u64 { 300 }
+fn default_ws_allow_query_token() -> bool { false }
+fn default_lazy_member_list_threshold() -> u32 { 1000 }
+fn default_ws_drain_timeout_secs() -> u64 { 30 }
+fn default_presence_idle_timeout_secs() -> u64 { 300 }
+fn default_ws_reconnect_jitter_ms() -> u64 { 10_000 }
 fn default_max_upload_size_bytes() -> u64 { 524_288_000 }
 fn default_cdn_presign_expiry_secs() -> u64 { 3600 }
 fn default_livekit_bundled() -> bool { true }
@@ -172,10 +288,34 @@ fn default_tls_port() -> u16 { 8443 }
 fn default_tls_cert_path() -> String { "./data/certs/cert.pem".into() }
 fn default_tls_key_path() -> String { "./data/certs/key.pem".into() }
 fn default_tls_auto_generate() -> bool { true }
+fn default_signed_prekey_max_age_days() -> u32 { 30 }
+fn default_signed_prekey_grace_hours() -> u32 { 72 }
+fn default_key_backup_versions_retained() -> u32 { 5 }
 fn default_audit_log_retention_days() -> u32 { 90 }
 fn default_resolved_report_retention_days() -> u32 { 180 }
 fn default_expired_invite_cleanup() -> bool { true }
 fn default_registration_invites_per_user() -> u32 { 3 }
+fn default_password_login_enabled() -> bool { true }
+fn default_oidc_scopes() -> String { "openid profile email".into() }
+fn default_oidc_username_claim() -> String { "preferred_username".into() }
+fn default_oidc_auto_provision() -> bool { true }
+fn default_jwt_algorithm() -> String { "EdDSA".into() }
+fn default_jwt_key_rotation_days() -> i64 { 30 }
+fn default_pow_base_difficulty() -> u32 { 20 }
+fn default_pow_max_difficulty() -> u32 { 24 }
+fn default_pow_registration_baseline() -> u32 { 30 }
+fn default_login_pow_failure_threshold() -> u32 { 100 }
+fn default_login_lockout_free_attempts() -> u32 { 5 }
+fn default_login_lockout_base_secs() -> u64 { 30 }
+fn default_login_lockout_max_secs() -> u64 { 3600 }
+fn default_security_event_retention_days() -> u32 { 180 }
+fn default_smtp_port() -> u16 { 587 }
+fn default_smtp_tls() -> String { "starttls".into() }
+fn default_smtp_from() -> String { "Haven <noreply@localhost>".into() }
+fn default_email_verification_expiry_hours() -> i64 { 24 }
+fn default_password_reset_expiry_minutes() -> i64 { 30 }
+fn default_account_deletion_grace_days() -> u32 { 30 }
+fn default_data_export_expiry_hours() -> i64 { 48 }
 
 // ─── Application Config ───────────────────────────────
 
@@ -222,6 +362,25 @@ pub struct AppConfig {
     pub ws_heartbeat_timeout_secs: u64,
     pub ws_session_buffer_size: usize,
     pub ws_session_ttl_secs: u64,
+    /// Accept `?token=<access token>` on the WS upgrade in addition to tickets
+    pub ws_allow_query_token: bool,
+    /// Servers with at least this many members get no PresenceUpdate fan-out;
+    /// clients get presence there via member list subscriptions instead
+    pub lazy_member_list_threshold: u32,
+    /// How long shutdown waits for WebSocket clients to move off this instance
+    pub ws_drain_timeout_secs: u64,
+    /// Upper bound on the random delay handed out in `Reconnect` during a drain
+    pub ws_reconnect_jitter_ms: u64,
+    /// Input idle time (reported in heartbeats) after which an online user goes idle; 0 disables
+    pub presence_idle_timeout_secs: u64,
+    /// Persist users' last-seen time when they go offline (opt-in per instance)
+    pub presence_last_seen: bool,
+    /// `prekey_count` flags a signed prekey older than this as stale; 0 disables
+    pub signed_prekey_max_age_days: u32,
+    /// How long a replaced signed prekey stays listed for in-flight X3DH handshakes
+    pub signed_prekey_grace_hours: u32,
+    /// Key backup versions kept per user; older ones are pruned on upload
+    pub key_backup_versions_retained: u32,
 
     // File Upload
     pub max_upload_size_bytes: u64,
@@ -261,6 +420,86 @@ pub struct AppConfig {
     // Cloudflare Turnstile (CAPTCHA) — disabled when empty
     pub turnstile_site_key: String,
     pub turnstile_secret_key: String,
+
+    // Login methods
+    /// Allow username/password login and registration; disable to require SSO
+    pub password_login_enabled: bool,
+    /// OpenID Connect issuer URL — SSO is disabled when empty
+    pub oidc_issuer: String,
+    pub oidc_client_id: String,
+    /// Empty for a public client (PKCE only)
+    pub oidc_client_secret: String,
+    /// Client page the issuer redirects back to; it posts the code to /auth/oidc/callback
+    pub oidc_redirect_uri: String,
+    pub oidc_scopes: String,
+    /// ID token claim mapped to the Haven username of provisioned accounts
+    pub oidc_username_claim: String,
+    /// Create an account on first SSO login for subjects that aren't linked yet
+    pub oidc_auto_provision: bool,
+
+    // Signing keys
+    /// Algorithm for new access-token signing keys: EdDSA (Ed25519) or ES256
+    pub jwt_algorithm: String,
+    /// Age at which the signing key is replaced; the old key keeps verifying until its tokens expire
+    pub jwt_key_rotation_days: i64,
+    /// HMAC key for stored email hashes. Empty falls back to jwt_secret, which
+    /// hashed emails before this key existed
+    pub email_hash_key: String,
+    /// Comma-separated earlier email hash keys, still matched after a rotation
+    pub email_hash_previous_keys: String,
+
+    // Anti-abuse
+    /// PoW leading zero bits required when the instance is quiet (20 ≈ 1M hashes)
+    pub pow_base_difficulty: u32,
+    /// Ceiling for adaptive PoW difficulty
+    pub pow_max_difficulty: u32,
+    /// Registrations per hour the base difficulty is meant for; each doubling beyond it adds a bit
+    pub pow_registration_baseline: u32,
+    /// Failed logins across the instance within 5 minutes after which login needs a PoW solution (0 = never)
+    pub login_pow_failure_threshold: u32,
+    /// CAPTCHA checked at registration: "turnstile", "hcaptcha" or "mcaptcha" (self-hosted).
+    /// Empty: Turnstile when turnstile_site_key/secret_key are set, otherwise none
+    pub captcha_provider: String,
+    pub captcha_site_key: String,
+    pub captcha_secret_key: String,
+    /// Verification endpoint; empty uses the provider's own (required for mcaptcha)
+    pub captcha_verify_url: String,
+
+    // Login protection
+    /// Failed logins per username before lockouts start
+    pub login_lockout_free_attempts: u32,
+    /// First lockout; each further failure doubles it
+    pub login_lockout_base_secs: u64,
+    /// Longest lockout
+    pub login_lockout_max_secs: u64,
+    /// Days to keep users' security event history (0 = forever)
+    pub security_event_retention_days: u32,
+
+    // Email (verification and password reset)
+    /// SMTP relay for verification and password reset emails — email is disabled when empty.
+    /// Addresses are only held in memory while a message is sent; users store an HMAC hash
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    /// Empty: send without authenticating
+    pub smtp_username: String,
+    pub smtp_password: String,
+    /// "starttls", "tls" (implicit TLS, usually port 465) or "none" (local relays only)
+    pub smtp_tls: String,
+    pub smtp_from: String,
+    /// Base URL of the web client, for links in emails (e.g. https://haven.example).
+    /// Empty: http://localhost:<port>
+    pub public_url: String,
+    pub email_verification_expiry_hours: i64,
+    pub password_reset_expiry_minutes: i64,
+
+    // Account deletion
+    /// Days between a deletion request and the account being erased; signing in
+    /// during this window cancels it (0 = erase immediately)
+    pub account_deletion_grace_days: u32,
+
+    // Personal data export
+    /// Hours a personal data export archive can be downloaded before it is deleted
+    pub data_export_expiry_hours: i64,
 }
 
 impl AppConfig {
@@ -269,6 +508,48 @@ impl AppConfig {
         !self.turnstile_site_key.is_empty() && !self.turnstile_secret_key.is_empty()
     }
 
+    /// Returns true if OpenID Connect single sign-on is configured.
+    pub fn oidc_enabled(&self) -> bool {
+        !self.oidc_issuer.is_empty()
+            && !self.oidc_client_id.is_empty()
+            && !self.oidc_redirect_uri.is_empty()
+    }
+
+    /// Returns true if an SMTP relay is configured for outgoing email.
+    pub fn email_enabled(&self) -> bool {
+        !self.smtp_host.is_empty()
+    }
+
+    /// Base URL for links to the web client, without a trailing slash.
+    pub fn public_base_url(&self) -> String {
+        if self.public_url.is_empty() {
+            format!("http://localhost:{}", self.port)
+        } else {
+            self.public_url.trim_end_matches('/').to_string()
+        }
+    }
+
+    /// The key new email hashes are made with.
+    pub fn current_email_hash_key(&self) -> &str {
+        if self.email_hash_key.is_empty() {
+            &self.jwt_secret
+        } else {
+            &self.email_hash_key
+        }
+    }
+
+    /// Every email hash key a stored hash may have been made with, current first.
+    pub fn email_hash_keys(&self) -> Vec<&str> {
+        let mut keys = vec![self.current_email_hash_key()];
+        keys.extend(
+            self.email_hash_previous_keys
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty()),
+        );
+        keys
+    }
+
     /// Returns true if LiveKit voice is configured.
     pub fn livekit_enabled(&self) -> bool {
         !self.livekit_url.is_empty()
@@ -314,6 +595,15 @@ impl AppConfig {
             ws_heartbeat_timeout_secs: 90,
             ws_session_buffer_size: 500,
             ws_session_ttl_secs: 300,
+            ws_allow_query_token: false,
+            lazy_member_list_threshold: 1000,
+            ws_drain_timeout_secs: 30,
+            ws_reconnect_jitter_ms: 10_000,
+            presence_idle_timeout_secs: 300,
+            presence_last_seen: false,
+            signed_prekey_max_age_days: 30,
+            signed_prekey_grace_hours: 72,
+            key_backup_versions_retained: 5,
             max_upload_size_bytes: 10_000_000,
             cdn_enabled: false,
             cdn_base_url: String::new(),
@@ -341,6 +631,48 @@ impl AppConfig {
 
             turnstile_site_key: String::new(),
             turnstile_secret_key: String::new(),
+
+            password_login_enabled: true,
+            oidc_issuer: String::new(),
+            oidc_client_id: String::new(),
+            oidc_client_secret: String::new(),
+            oidc_redirect_uri: String::new(),
+            oidc_scopes: "openid profile email".into(),
+            oidc_username_claim: "preferred_username".into(),
+            oidc_auto_provision: true,
+
+            jwt_algorithm: "EdDSA".into(),
+            jwt_key_rotation_days: 30,
+            email_hash_key: "test-email-hash-key".into(),
+            email_hash_previous_keys: String::new(),
+
+            pow_base_difficulty: 20,
+            pow_max_difficulty: 24,
+            pow_registration_baseline: 30,
+            login_pow_failure_threshold: 0,
+            captcha_provider: String::new(),
+            captcha_site_key: String::new(),
+            captcha_secret_key: String::new(),
+            captcha_verify_url: String::new(),
+
+            login_lockout_free_attempts: 5,
+            login_lockout_base_secs: 30,
+            login_lockout_max_secs: 3600,
+            security_event_retention_days: 180,
+
+            smtp_host: String::new(),
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            smtp_tls: "none".into(),
+            smtp_from: "Haven <noreply@haven.test>".into(),
+            public_url: "http://haven.test".into(),
+            email_verification_expiry_hours: 24,
+            password_reset_expiry_minutes: 30,
+
+            account_deletion_grace_days: 30,
+
+            data_export_expiry_hours: 48,
         }
     }
 
@@ -407,6 +739,42 @@ impl AppConfig {
             ws_heartbeat_timeout_secs: default_ws_heartbeat_timeout_secs(),
             ws_session_buffer_size: default_ws_session_buffer_size(),
             ws_session_ttl_secs: default_ws_session_ttl_secs(),
+            ws_allow_query_token: env::var("WS_ALLOW_QUERY_TOKEN")
+                .unwrap_or_else(|_| "false".into())
+                .parse()
+                .unwrap_or(false),
+            lazy_member_list_threshold: env::var("LAZY_MEMBER_LIST_THRESHOLD")
+                .unwrap_or_else(|_| "1000".into())
+                .parse()
+                .unwrap_or(1000),
+            ws_drain_timeout_secs: env::var("WS_DRAIN_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+            ws_reconnect_jitter_ms: env::var("WS_RECONNECT_JITTER_MS")
+                .unwrap_or_else(|_| "10000".into())
+                .parse()
+                .unwrap_or(10_000),
+            presence_idle_timeout_secs: env::var("PRESENCE_IDLE_TIMEOUT_SECS")
+                .unwrap_or_else(|_| "300".into())
+                .parse()
+                .unwrap_or(300),
+            presence_last_seen: env::var("PRESENCE_LAST_SEEN")
+                .unwrap_or_else(|_| "false".into())
+                .parse()
+                .unwrap_or(false),
+            signed_prekey_max_age_days: env::var("SIGNED_PREKEY_MAX_AGE_DAYS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+            signed_prekey_grace_hours: env::var("SIGNED_PREKEY_GRACE_HOURS")
+                .unwrap_or_else(|_| "72".into())
+                .parse()
+                .unwrap_or(72),
+            key_backup_versions_retained: env::var("KEY_BACKUP_VERSIONS_RETAINED")
+                .unwrap_or_else(|_| "5".into())
+                .parse()
+                .unwrap_or(5),
 
             max_upload_size_bytes: env::var("MAX_UPLOAD_SIZE_BYTES")
                 .unwrap_or_else(|_| "524288000".into()) // 500MB
@@ -479,6 +847,96 @@ impl AppConfig {
 
             turnstile_site_key: env::var("TURNSTILE_SITE_KEY").unwrap_or_default(),
             turnstile_secret_key: env::var("TURNSTILE_SECRET_KEY").unwrap_or_default(),
+
+            password_login_enabled: env::var("PASSWORD_LOGIN_ENABLED")
+                .unwrap_or_else(|_| "true".into())
+                .parse()
+                .unwrap_or(true),
+            oidc_issuer: env::var("OIDC_ISSUER").unwrap_or_default(),
+            oidc_client_id: env::var("OIDC_CLIENT_ID").unwrap_or_default(),
+            oidc_client_secret: env::var("OIDC_CLIENT_SECRET").unwrap_or_default(),
+            oidc_redirect_uri: env::var("OIDC_REDIRECT_URI").unwrap_or_default(),
+            oidc_scopes: env::var("OIDC_SCOPES").unwrap_or_else(|_| default_oidc_scopes()),
+            oidc_username_claim: env::var("OIDC_USERNAME_CLAIM").unwrap_or_else(|_| default_oidc_username_claim()),
+            oidc_auto_provision: env::var("OIDC_AUTO_PROVISION")
+                .unwrap_or_else(|_| "true".into())
+                .parse()
+                .unwrap_or(true),
+
+            jwt_algorithm: env::var("JWT_ALGORITHM").unwrap_or_else(|_| default_jwt_algorithm()),
+            jwt_key_rotation_days: env::var("JWT_KEY_ROTATION_DAYS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+            email_hash_key: env::var("EMAIL_HASH_KEY").unwrap_or_default(),
+            email_hash_previous_keys: env::var("EMAIL_HASH_PREVIOUS_KEYS").unwrap_or_default(),
+
+            pow_base_difficulty: env::var("POW_BASE_DIFFICULTY")
+                .unwrap_or_else(|_| "20".into())
+                .parse()
+                .unwrap_or(20),
+            pow_max_difficulty: env::var("POW_MAX_DIFFICULTY")
+                .unwrap_or_else(|_| "24".into())
+                .parse()
+                .unwrap_or(24),
+            pow_registration_baseline: env::var("POW_REGISTRATION_BASELINE")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+            login_pow_failure_threshold: env::var("LOGIN_POW_FAILURE_THRESHOLD")
+                .unwrap_or_else(|_| "100".into())
+                .parse()
+                .unwrap_or(100),
+            captcha_provider: env::var("CAPTCHA_PROVIDER").unwrap_or_default(),
+            captcha_site_key: env::var("CAPTCHA_SITE_KEY").unwrap_or_default(),
+            captcha_secret_key: env::var("CAPTCHA_SECRET_KEY").unwrap_or_default(),
+            captcha_verify_url: env::var("CAPTCHA_VERIFY_URL").unwrap_or_default(),
+
+            login_lockout_free_attempts: env::var("LOGIN_LOCKOUT_FREE_ATTEMPTS")
+                .unwrap_or_else(|_| "5".into())
+                .parse()
+                .unwrap_or(5),
+            login_lockout_base_secs: env::var("LOGIN_LOCKOUT_BASE_SECS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+            login_lockout_max_secs: env::var("LOGIN_LOCKOUT_MAX_SECS")
+                .unwrap_or_else(|_| "3600".into())
+                .parse()
+                .unwrap_or(3600),
+            security_event_retention_days: env::var("SECURITY_EVENT_RETENTION_DAYS")
+                .unwrap_or_else(|_| "180".into())
+                .parse()
+                .unwrap_or(180),
+
+            smtp_host: env::var("SMTP_HOST").unwrap_or_default(),
+            smtp_port: env::var("SMTP_PORT")
+                .unwrap_or_else(|_| "587".into())
+                .parse()
+                .unwrap_or(587),
+            smtp_username: env::var("SMTP_USERNAME").unwrap_or_default(),
+            smtp_password: env::var("SMTP_PASSWORD").unwrap_or_default(),
+            smtp_tls: env::var("SMTP_TLS").unwrap_or_else(|_| default_smtp_tls()),
+            smtp_from: env::var("SMTP_FROM").unwrap_or_else(|_| default_smtp_from()),
+            public_url: env::var("PUBLIC_URL").unwrap_or_default(),
+            email_verification_expiry_hours: env::var("EMAIL_VERIFICATION_EXPIRY_HOURS")
+                .unwrap_or_else(|_| "24".into())
+                .parse()
+                .unwrap_or(24),
+            password_reset_expiry_minutes: env::var("PASSWORD_RESET_EXPIRY_MINUTES")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+
+            account_deletion_grace_days: env::var("ACCOUNT_DELETION_GRACE_DAYS")
+                .unwrap_or_else(|_| "30".into())
+                .parse()
+                .unwrap_or(30),
+
+            data_export_expiry_hours: env::var("DATA_EXPORT_EXPIRY_HOURS")
+                .unwrap_or_else(|_| "48".into())
+                .parse()
+                .unwrap_or(48),
         }
     }
 
@@ -527,6 +985,15 @@ impl AppConfig {
             ws_heartbeat_timeout_secs: file.ws_heartbeat_timeout_secs,
             ws_session_buffer_size: file.ws_session_buffer_size,
             ws_session_ttl_secs: file.ws_session_ttl_secs,
+            ws_allow_query_token: file.ws_allow_query_token,
+            lazy_member_list_threshold: file.lazy_member_list_threshold,
+            ws_drain_timeout_secs: file.ws_drain_timeout_secs,
+            ws_reconnect_jitter_ms: file.ws_reconnect_jitter_ms,
+            presence_idle_timeout_secs: file.presence_idle_timeout_secs,
+            presence_last_seen: file.presence_last_seen,
+            signed_prekey_max_age_days: file.signed_prekey_max_age_days,
+            signed_prekey_grace_hours: file.signed_prekey_grace_hours,
+            key_backup_versions_retained: file.key_backup_versions_retained,
             max_upload_size_bytes: file.max_upload_size_bytes,
             cdn_enabled: file.cdn_enabled,
             cdn_base_url: file.cdn_base_url,
@@ -554,6 +1021,48 @@ impl AppConfig {
 
             turnstile_site_key: file.turnstile_site_key,
             turnstile_secret_key: file.turnstile_secret_key,
+
+            password_login_enabled: file.password_login_enabled,
+            oidc_issuer: file.oidc_issuer,
+            oidc_client_id: file.oidc_client_id,
+            oidc_client_secret: file.oidc_client_secret,
+            oidc_redirect_uri: file.oidc_redirect_uri,
+            oidc_scopes: file.oidc_scopes,
+            oidc_username_claim: file.oidc_username_claim,
+            oidc_auto_provision: file.oidc_auto_provision,
+
+            jwt_algorithm: file.jwt_algorithm,
+            jwt_key_rotation_days: file.jwt_key_rotation_days,
+            email_hash_key: file.email_hash_key,
+            email_hash_previous_keys: file.email_hash_previous_keys,
+
+            pow_base_difficulty: file.pow_base_difficulty,
+            pow_max_difficulty: file.pow_max_difficulty,
+            pow_registration_baseline: file.pow_registration_baseline,
+            login_pow_failure_threshold: file.login_pow_failure_threshold,
+            captcha_provider: file.captcha_provider,
+            captcha_site_key: file.captcha_site_key,
+            captcha_secret_key: file.captcha_secret_key,
+            captcha_verify_url: file.captcha_verify_url,
+
+            login_lockout_free_attempts: file.login_lockout_free_attempts,
+            login_lockout_base_secs: file.login_lockout_base_secs,
+            login_lockout_max_secs: file.login_lockout_max_secs,
+            security_event_retention_days: file.security_event_retention_days,
+
+            smtp_host: file.smtp_host,
+            smtp_port: file.smtp_port,
+            smtp_username: file.smtp_username,
+            smtp_password: file.smtp_password,
+            smtp_tls: file.smtp_tls,
+            smtp_from: file.smtp_from,
+            public_url: file.public_url,
+            email_verification_expiry_hours: file.email_verification_expiry_hours,
+            password_reset_expiry_minutes: file.password_reset_expiry_minutes,
+
+            account_deletion_grace_days: file.account_deletion_grace_days,
+
+            data_export_expiry_hours: file.data_export_expiry_hours,
         }
     }
 
@@ -598,6 +1107,15 @@ impl AppConfig {
             ws_heartbeat_timeout_secs: default_ws_heartbeat_timeout_secs(),
             ws_session_buffer_size: default_ws_session_buffer_size(),
             ws_session_ttl_secs: default_ws_session_ttl_secs(),
+            ws_allow_query_token: default_ws_allow_query_token(),
+            lazy_member_list_threshold: default_lazy_member_list_threshold(),
+            ws_drain_timeout_secs: default_ws_drain_timeout_secs(),
+            ws_reconnect_jitter_ms: default_ws_reconnect_jitter_ms(),
+            presence_idle_timeout_secs: default_presence_idle_timeout_secs(),
+            presence_last_seen: false,
+            signed_prekey_max_age_days: default_signed_prekey_max_age_days(),
+            signed_prekey_grace_hours: default_signed_prekey_grace_hours(),
+            key_backup_versions_retained: default_key_backup_versions_retained(),
             max_upload_size_bytes: default_max_upload_size_bytes(),
             cdn_enabled: false,
             cdn_base_url: String::new(),
@@ -621,6 +1139,48 @@ impl AppConfig {
 
             turnstile_site_key: String::new(),
             turnstile_secret_key: String::new(),
+
+            password_login_enabled: default_password_login_enabled(),
+            oidc_issuer: String::new(),
+            oidc_client_id: String::new(),
+            oidc_client_secret: String::new(),
+            oidc_redirect_uri: String::new(),
+            oidc_scopes: default_oidc_scopes(),
+            oidc_username_claim: default_oidc_username_claim(),
+            oidc_auto_provision: default_oidc_auto_provision(),
+
+            jwt_algorithm: default_jwt_algorithm(),
+            jwt_key_rotation_days: default_jwt_key_rotation_days(),
+            email_hash_key: (0..32).map(|_| format!("{:02x}", rng.gen::<u8>())).collect(),
+            email_hash_previous_keys: String::new(),
+
+            pow_base_difficulty: default_pow_base_difficulty(),
+            pow_max_difficulty: default_pow_max_difficulty(),
+            pow_registration_baseline: default_pow_registration_baseline(),
+            login_pow_failure_threshold: default_login_pow_failure_threshold(),
+            captcha_provider: String::new(),
+            captcha_site_key: String::new(),
+            captcha_secret_key: String::new(),
+            captcha_verify_url: String::new(),
+
+            login_lockout_free_attempts: default_login_lockout_free_attempts(),
+            login_lockout_base_secs: default_login_lockout_base_secs(),
+            login_lockout_max_secs: default_login_lockout_max_secs(),
+            security_event_retention_days: default_security_event_retention_days(),
+
+            smtp_host: String::new(),
+            smtp_port: default_smtp_port(),
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            smtp_tls: default_smtp_tls(),
+            smtp_from: default_smtp_from(),
+            public_url: String::new(),
+            email_verification_expiry_hours: default_email_verification_expiry_hours(),
+            password_reset_expiry_minutes: default_password_reset_expiry_minutes(),
+
+            account_deletion_grace_days: default_account_deletion_grace_days(),
+
+            data_export_expiry_hours: default_data_export_expiry_hours(),
         };
 
         // Write the TOML file
@@ -658,6 +1218,15 @@ impl AppConfig {
             ws_heartbeat_timeout_secs: file.ws_heartbeat_timeout_secs,
             ws_session_buffer_size: file.ws_session_buffer_size,
             ws_session_ttl_secs: file.ws_session_ttl_secs,
+            ws_allow_query_token: file.ws_allow_query_token,
+            lazy_member_list_threshold: file.lazy_member_list_threshold,
+            ws_drain_timeout_secs: file.ws_drain_timeout_secs,
+            ws_reconnect_jitter_ms: file.ws_reconnect_jitter_ms,
+            presence_idle_timeout_secs: file.presence_idle_timeout_secs,
+            presence_last_seen: file.presence_last_seen,
+            signed_prekey_max_age_days: file.signed_prekey_max_age_days,
+            signed_prekey_grace_hours: file.signed_prekey_grace_hours,
+            key_backup_versions_retained: file.key_backup_versions_retained,
             max_upload_size_bytes: file.max_upload_size_bytes,
             cdn_enabled: file.cdn_enabled,
             cdn_base_url: file.cdn_base_url,
@@ -685,6 +1254,48 @@ impl AppConfig {
 
             turnstile_site_key: file.turnstile_site_key,
             turnstile_secret_key: file.turnstile_secret_key,
+
+            password_login_enabled: file.password_login_enabled,
+            oidc_issuer: file.oidc_issuer,
+            oidc_client_id: file.oidc_client_id,
+            oidc_client_secret: file.oidc_client_secret,
+            oidc_redirect_uri: file.oidc_redirect_uri,
+            oidc_scopes: file.oidc_scopes,
+            oidc_username_claim: file.oidc_username_claim,
+            oidc_auto_provision: file.oidc_auto_provision,
+
+            jwt_algorithm: file.jwt_algorithm,
+            jwt_key_rotation_days: file.jwt_key_rotation_days,
+            email_hash_key: file.email_hash_key,
+            email_hash_previous_keys: file.email_hash_previous_keys,
+
+            pow_base_difficulty: file.pow_base_difficulty,
+            pow_max_difficulty: file.pow_max_difficulty,
+            pow_registration_baseline: file.pow_registration_baseline,
+            login_pow_failure_threshold: file.login_pow_failure_threshold,
+            captcha_provider: file.captcha_provider,
+            captcha_site_key: file.captcha_site_key,
+            captcha_secret_key: file.captcha_secret_key,
+            captcha_verify_url: file.captcha_verify_url,
+
+            login_lockout_free_attempts: file.login_lockout_free_attempts,
+            login_lockout_base_secs: file.login_lockout_base_secs,
+            login_lockout_max_secs: file.login_lockout_max_secs,
+            security_event_retention_days: file.security_event_retention_days,
+
+            smtp_host: file.smtp_host,
+            smtp_port: file.smtp_port,
+            smtp_username: file.smtp_username,
+            smtp_password: file.smtp_password,
+            smtp_tls: file.smtp_tls,
+            smtp_from: file.smtp_from,
+            public_url: file.public_url,
+            email_verification_expiry_hours: file.email_verification_expiry_hours,
+            password_reset_expiry_minutes: file.password_reset_expiry_minutes,
+
+            account_deletion_grace_days: file.account_deletion_grace_days,
+
+            data_export_expiry_hours: file.data_export_expiry_hours,
         }
     }
 }
@@ -706,6 +1317,7 @@ mod tests {
         assert_eq!(config.max_ws_connections_per_user, 10);
         assert_eq!(config.broadcast_channel_capacity, 4096);
         assert!(!config.cdn_enabled);
+        assert!(!config.ws_allow_query_token);
     }
 
     #[test]
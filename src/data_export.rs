@@ -0,0 +1,488 @@
+//! Personal data export.
+//!
+//! `POST /users/@me/export` starts a background job that gathers everything
+//! the server holds about the caller into a tar archive:
+//!
+//! - `export.json` — the profile (with the `encrypted_profile` blob),
+//!   attachment metadata, friendships, blocks, server memberships and roles,
+//!   sessions, filed reports, key backup versions and audit log entries made
+//!   by or about the user
+//! - `messages/<n>.json` — sent messages (ciphertexts and metadata), a page
+//!   per file, so they are never loaded all at once
+//! - `attachments/<id>` — the files attached to those messages, still
+//!   encrypted by the client that uploaded them
+//! - `profile/avatar`, `profile/banner` — profile images, when set
+//!
+//! The archive is streamed into `Storage` as it is built, sealed in chunks
+//! so it is never held in memory whole, and streamed back out on download.
+//! Each user may request a few exports a day. While it is ready,
+//! its `download_url` carries a signature made with the server's storage
+//! key, so it works without signing in. An hourly worker deletes archives
+//! `data_export_expiry_hours` after they were built, which ends the link.
+
+use std::io;
+
+use axum::http::HeaderMap;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::api::b64;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::models::{DataExport, DataExportResponse};
+use crate::security_events;
+use crate::storage;
+use crate::AppState;
+
+/// Exports still pending after this long are assumed lost (e.g. to a restart)
+const STALE_AFTER_HOURS: i64 = 6;
+
+/// Messages per `messages/<n>.json` file
+const MESSAGE_PAGE_SIZE: i64 = 1000;
+
+const TAR_BLOCK: usize = 512;
+
+/// Largest entry the 11-digit octal size field can describe (8 GiB - 1)
+const TAR_MAX_ENTRY: u64 = 0o77777777777;
+
+/// Start building an export for `user_id`. Older exports are replaced; only
+/// one can be in progress at a time.
+pub async fn start(state: &AppState, user_id: Uuid, headers: &HeaderMap) -> AppResult<DataExport> {
+    if !state.data_export_limiter.check(user_id) {
+        return Err(AppError::RateLimited);
+    }
+
+    // The partial unique index on pending exports settles concurrent requests
+    let export = queries::insert_data_export(state.db.write(), Uuid::new_v4(), user_id)
+        .await?
+        .ok_or(AppError::Conflict("An export is already being prepared".into()))?;
+    for previous in queries::list_user_data_exports(state.db.read(), user_id).await? {
+        if previous.id != export.id {
+            remove(state, &previous).await?;
+        }
+    }
+
+    security_events::record(state, user_id, security_events::DATA_EXPORT_REQUESTED, headers).await?;
+    tokio::spawn(run(state.clone(), export.id, user_id));
+    tracing::info!("User {} requested a data export ({})", user_id, export.id);
+    Ok(export)
+}
+
+async fn run(state: AppState, export_id: Uuid, user_id: Uuid) {
+    let expires_at = Utc::now() + Duration::hours(state.config.data_export_expiry_hours);
+    if let Err(e) = build_and_store(&state, export_id, user_id, expires_at).await {
+        tracing::error!("Data export {} for user {} failed: {}", export_id, user_id, e);
+        if let Err(e) = queries::fail_data_export(state.db.write(), export_id, expires_at).await {
+            tracing::error!("Failed to mark data export {} as failed: {}", export_id, e);
+        }
+    }
+}
+
+async fn build_and_store(
+    state: &AppState,
+    export_id: Uuid,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    let storage_key = storage::obfuscated_key(&state.storage_key, &format!("export:{}", export_id));
+    let mut upload = state.storage.begin_sealed(&storage_key).await.map_err(store_error)?;
+    let size = match write_archive(state, user_id, &mut upload.writer).await {
+        Ok(size) => size,
+        Err(e) => {
+            state.storage.abort_sealed(upload).await;
+            return Err(e);
+        }
+    };
+    state.storage.finish_sealed(upload).await.map_err(store_error)?;
+    queries::complete_data_export(state.db.write(), export_id, &storage_key, size as i64, expires_at).await?;
+    tracing::info!("Data export {} is ready ({} bytes)", export_id, size);
+    Ok(())
+}
+
+fn store_error(e: io::Error) -> AppError {
+    AppError::Internal(anyhow::anyhow!("Failed to store data export: {}", e))
+}
+
+/// Load a file stored the way uploads are: raw when the CDN is enabled,
+/// encrypted at rest otherwise.
+async fn load_upload(state: &AppState, storage_key: &str) -> io::Result<Vec<u8>> {
+    if state.config.cdn_enabled {
+        state.storage.load_blob_raw(storage_key).await
+    } else {
+        state.storage.load_blob(storage_key).await
+    }
+}
+
+/// Write the archive to `out`, returning its size.
+async fn write_archive<W: AsyncWrite + Unpin>(state: &AppState, user_id: Uuid, out: W) -> AppResult<u64> {
+    let db = state.db.read();
+    let user = queries::find_user_by_id(db, user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+    let now = Utc::now();
+    let mut tar = TarBuilder::new(out, now.timestamp());
+
+    let attachments = queries::list_attachments_by_sender(db, user_id).await?;
+    let friendships = queries::list_friendships_for_user(db, user_id).await?;
+    let blocks = queries::get_blocked_users(db, user_id, i64::MAX, 0).await?;
+    let memberships = queries::list_server_memberships(db, user_id).await?;
+    let roles = queries::list_roles_for_user(db, user_id).await?;
+    let sessions = queries::list_all_refresh_tokens(db, user_id).await?;
+    let reports = queries::list_reports_by_reporter(db, user_id).await?;
+    let key_backups = queries::list_key_backup_versions(db, user_id).await?;
+    let audit_log = queries::list_audit_log_about_user(db, user_id).await?;
+
+    // Files first, so the JSON can say which ones made it into the archive
+    let mut attachment_entries = Vec::with_capacity(attachments.len());
+    for att in &attachments {
+        let path = format!("attachments/{}", att.id);
+        let file = match load_upload(state, &att.storage_key).await {
+            Ok(data) => match tar.append(&path, &data).await {
+                Ok(()) => Some(path),
+                Err(e) if e.kind() == io::ErrorKind::InvalidInput => {
+                    tracing::warn!("Data export: attachment {} left out: {}", att.id, e);
+                    None
+                }
+                Err(e) => return Err(store_error(e)),
+            },
+            Err(e) => {
+                tracing::warn!("Data export: attachment {} unreadable: {}", att.id, e);
+                None
+            }
+        };
+        attachment_entries.push(json!({
+            "id": att.id,
+            "message_id": att.message_id,
+            "encrypted_meta": b64(&att.encrypted_meta),
+            "size_bucket": att.size_bucket,
+            "created_at": att.created_at,
+            "file": file,
+        }));
+    }
+
+    let mut profile_files = serde_json::Map::new();
+    for (kind, set) in [("avatar", user.avatar_url.is_some()), ("banner", user.banner_url.is_some())] {
+        if !set {
+            continue;
+        }
+        let key = storage::obfuscated_key(&state.storage_key, &format!("{}:{}", kind, user_id));
+        if let Ok(data) = load_upload(state, &key).await {
+            let path = format!("profile/{}", kind);
+            tar.append(&path, &data).await.map_err(store_error)?;
+            profile_files.insert(kind.to_string(), json!(path));
+        }
+    }
+
+    // Messages go out a page at a time, keyed on the last one written
+    let mut message_files = Vec::new();
+    let mut after = None;
+    loop {
+        let page = queries::list_messages_by_sender(db, user_id, after, MESSAGE_PAGE_SIZE).await?;
+        let Some(last) = page.last() else { break };
+        after = Some((last.timestamp, last.id));
+        let entries: Vec<_> = page
+            .iter()
+            .map(|m| {
+                json!({
+                    "id": m.id,
+                    "channel_id": m.channel_id,
+                    "sender_token": b64(&m.sender_token),
+                    "encrypted_body": b64(&m.encrypted_body),
+                    "timestamp": m.timestamp,
+                    "edited_at": m.edited_at,
+                    "expires_at": m.expires_at,
+                    "reply_to_id": m.reply_to_id,
+                    "has_attachments": m.has_attachments,
+                    "message_type": m.message_type,
+                })
+            })
+            .collect();
+        let path = format!("messages/{:06}.json", message_files.len());
+        let data = serde_json::to_vec_pretty(&entries)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to serialize data export: {}", e)))?;
+        tar.append(&path, &data).await.map_err(store_error)?;
+        message_files.push(path);
+        if page.len() < MESSAGE_PAGE_SIZE as usize {
+            break;
+        }
+    }
+    let server_entries: Vec<_> = memberships
+        .iter()
+        .map(|m| {
+            let server_roles: Vec<_> = roles.iter().filter(|r| r.server_id == m.server_id).collect();
+            json!({
+                "server_id": m.server_id,
+                "joined_at": m.joined_at,
+                "nickname": m.nickname,
+                "encrypted_role": b64(&m.encrypted_role),
+                "roles": server_roles,
+            })
+        })
+        .collect();
+    // Every session ever started, not only the active ones; token hashes stay out
+    let session_entries: Vec<_> = sessions
+        .iter()
+        .map(|s| {
+            json!({
+                "id": s.id,
+                "family_id": s.family_id,
+                "device_name": s.device_name,
+                "ip_address": s.ip_address,
+                "created_at": s.created_at,
+                "last_activity": s.last_activity,
+                "expires_at": s.expires_at,
+                "revoked": s.revoked,
+            })
+        })
+        .collect();
+
+    let document = json!({
+        "exported_at": now,
+        "profile": {
+            "id": user.id,
+            "username": user.username,
+            "display_name": user.display_name,
+            "about_me": user.about_me,
+            "custom_status": user.custom_status,
+            "custom_status_emoji": user.custom_status_emoji,
+            "custom_status_expires_at": user.custom_status_expires_at,
+            "avatar_url": user.avatar_url,
+            "banner_url": user.banner_url,
+            "files": profile_files,
+            "encrypted_profile": user.encrypted_profile.as_deref().map(b64),
+            "identity_key": b64(&user.identity_key),
+            "dm_privacy": user.dm_privacy,
+            "has_email": user.email_hash.is_some(),
+            "email_verified_at": user.email_verified_at,
+            "totp_enabled": user.totp_secret.is_some(),
+            "is_instance_admin": user.is_instance_admin,
+            "created_at": user.created_at,
+            "updated_at": user.updated_at,
+            "deactivated_at": user.deactivated_at,
+            "deletion_scheduled_at": user.deletion_scheduled_at,
+        },
+        "messages": message_files,
+        "attachments": attachment_entries,
+        "friendships": friendships,
+        "blocked_users": blocks,
+        "servers": server_entries,
+        "sessions": session_entries,
+        "reports": reports,
+        "key_backups": key_backups,
+        "audit_log": audit_log,
+    });
+    let document = serde_json::to_vec_pretty(&document)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to serialize data export: {}", e)))?;
+    tar.append("export.json", &document).await.map_err(store_error)?;
+
+    tar.finish().await.map_err(store_error)
+}
+
+/// The API view of an export, with a signed download link while it is ready.
+pub fn to_response(state: &AppState, export: DataExport) -> DataExportResponse {
+    let download_url = (export.status == "ready").then(|| {
+        format!(
+            "{}/api/v1/exports/{}/download?sig={}",
+            state.config.public_base_url(),
+            export.id,
+            download_signature(&state.storage_key, export.id),
+        )
+    });
+    DataExportResponse {
+        id: export.id,
+        status: export.status,
+        size_bytes: export.size_bytes,
+        created_at: export.created_at,
+        completed_at: export.completed_at,
+        expires_at: export.expires_at,
+        download_url,
+    }
+}
+
+fn link_mac(key: &[u8; 32], export_id: Uuid) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC key length is always valid");
+    mac.update(b"data-export:");
+    mac.update(export_id.as_bytes());
+    mac
+}
+
+pub fn download_signature(key: &[u8; 32], export_id: Uuid) -> String {
+    let tag = link_mac(key, export_id).finalize().into_bytes();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(tag)
+}
+
+/// Check a download link's signature in constant time.
+pub fn verify_download_signature(key: &[u8; 32], export_id: Uuid, sig: &str) -> bool {
+    match base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(sig) {
+        Ok(tag) => link_mac(key, export_id).verify_slice(&tag).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Delete an export's archive and its record.
+pub async fn remove(state: &AppState, export: &DataExport) -> AppResult<()> {
+    if let Some(key) = &export.storage_key {
+        state
+            .storage
+            .delete_blob(key)
+            .await
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to delete data export: {}", e)))?;
+    }
+    queries::delete_data_export(state.db.write(), export.id).await
+}
+
+/// Delete every export of a user, before their account is erased.
+pub async fn remove_all(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    for export in queries::list_user_data_exports(state.db.read(), user_id).await? {
+        remove(state, &export).await?;
+    }
+    Ok(())
+}
+
+/// Fail exports that never finished and delete expired ones. Returns how
+/// many were deleted.
+pub async fn purge_expired(state: &AppState) -> AppResult<usize> {
+    let now = Utc::now();
+    let expires_at = now + Duration::hours(state.config.data_export_expiry_hours);
+    queries::fail_stale_data_exports(state.db.write(), now - Duration::hours(STALE_AFTER_HOURS), expires_at)
+        .await?;
+
+    let expired = queries::list_expired_data_exports(state.db.write()).await?;
+    let mut removed = 0;
+    for export in &expired {
+        match remove(state, export).await {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::error!("Failed to delete data export {}: {}", export.id, e),
+        }
+    }
+    Ok(removed)
+}
+
+// ─── Tar Archive ──────────────────────────────────────
+
+/// Writes an uncompressed ustar archive to `out` as entries are added.
+struct TarBuilder<W> {
+    out: W,
+    mtime: i64,
+    len: u64,
+}
+
+impl<W: AsyncWrite + Unpin> TarBuilder<W> {
+    fn new(out: W, mtime: i64) -> Self {
+        Self { out, mtime, len: 0 }
+    }
+
+    /// Add a regular file. `path` must be under 100 bytes. Files too large
+    /// for a ustar header are refused with `InvalidInput`.
+    async fn append(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let header = tar_header(path, data.len() as u64, self.mtime)?;
+        let padding = (TAR_BLOCK - data.len() % TAR_BLOCK) % TAR_BLOCK;
+        self.out.write_all(&header).await?;
+        self.out.write_all(data).await?;
+        self.out.write_all(&[0u8; TAR_BLOCK][..padding]).await?;
+        self.len += (TAR_BLOCK + data.len() + padding) as u64;
+        Ok(())
+    }
+
+    /// Close the archive with two empty blocks. Returns its size.
+    async fn finish(mut self) -> io::Result<u64> {
+        self.out.write_all(&[0u8; 2 * TAR_BLOCK]).await?;
+        self.out.flush().await?;
+        Ok(self.len + 2 * TAR_BLOCK as u64)
+    }
+}
+
+fn tar_header(path: &str, size: u64, mtime: i64) -> io::Result<[u8; TAR_BLOCK]> {
+    if size > TAR_MAX_ENTRY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is too large for a tar entry ({} bytes)", path, size),
+        ));
+    }
+
+    let mut header = [0u8; TAR_BLOCK];
+    let name = path.as_bytes();
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field set to spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal(field: &[u8]) -> u64 {
+        let s = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(s.trim_matches(|c| c == '\0' || c == ' '), 8).unwrap()
+    }
+
+    #[tokio::test]
+    async fn tar_entries_are_block_aligned() {
+        let mut archive = Vec::new();
+        let mut tar = TarBuilder::new(&mut archive, 1_700_000_000);
+        tar.append("export.json", b"{}").await.unwrap();
+        tar.append("attachments/a", &[7u8; 600]).await.unwrap();
+        let size = tar.finish().await.unwrap();
+
+        // header + 1 data block, header + 2 data blocks, 2 end blocks
+        assert_eq!(archive.len(), 7 * TAR_BLOCK);
+        assert_eq!(size, archive.len() as u64);
+        assert_eq!(&archive[..11], b"export.json");
+        assert_eq!(octal(&archive[124..136]), 2);
+        assert_eq!(&archive[TAR_BLOCK..TAR_BLOCK + 2], b"{}");
+        let second = 2 * TAR_BLOCK;
+        assert_eq!(&archive[second..second + 13], b"attachments/a");
+        assert_eq!(octal(&archive[second + 124..second + 136]), 600);
+        assert!(archive[5 * TAR_BLOCK..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn tar_header_checksum_matches() {
+        let header = tar_header("profile/avatar", 3, 0).unwrap();
+        let stored = octal(&header[148..156]);
+        let computed: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { u64::from(b' ') } else { u64::from(b) })
+            .sum();
+        assert_eq!(stored, computed);
+        assert_eq!(&header[257..263], b"ustar\0");
+    }
+
+    #[test]
+    fn tar_header_refuses_entries_of_8_gib() {
+        let header = tar_header("attachments/big", TAR_MAX_ENTRY, 0).unwrap();
+        assert_eq!(octal(&header[124..136]), TAR_MAX_ENTRY);
+        let err = tar_header("attachments/big", TAR_MAX_ENTRY + 1, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn download_signature_is_bound_to_the_export() {
+        let key = [3u8; 32];
+        let export_id = Uuid::new_v4();
+        let sig = download_signature(&key, export_id);
+
+        assert!(verify_download_signature(&key, export_id, &sig));
+        assert!(!verify_download_signature(&key, Uuid::new_v4(), &sig));
+        assert!(!verify_download_signature(&[4u8; 32], export_id, &sig));
+        assert!(!verify_download_signature(&key, export_id, "not base64!"));
+    }
+}
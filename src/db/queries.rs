@@ -12,7 +12,7 @@ pub async fn create_user(
     pool: &Pool,
     username: &str,
     display_name: Option<&str>,
-    email_hash: Option<&str>,
+    email_hash: Option<(&str, &str)>, // (hash, key id)
     password_hash: &str,
     identity_key: &[u8],
     signed_prekey: &[u8],
@@ -20,17 +20,18 @@ pub async fn create_user(
 ) -> AppResult<User> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        INSERT INTO users (id, username, display_name, email_hash, password_hash,
-                          identity_key, signed_prekey, signed_prekey_sig,
+        INSERT INTO users (id, username, display_name, email_hash, email_hash_key_id,
+                          password_hash, identity_key, signed_prekey, signed_prekey_sig,
                           created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
         RETURNING *
         "#,
     )
     .bind(Uuid::new_v4())
     .bind(username)
     .bind(display_name)
-    .bind(email_hash)
+    .bind(email_hash.map(|(hash, _)| hash))
+    .bind(email_hash.map(|(_, key_id)| key_id))
     .bind(password_hash)
     .bind(identity_key)
     .bind(signed_prekey)
@@ -81,25 +82,6 @@ pub async fn find_user_by_id_cached(
     Ok(user)
 }
 
-pub async fn update_user_keys(
-    pool: &Pool,
-    user_id: Uuid,
-    identity_key: &[u8],
-    signed_prekey: &[u8],
-    signed_prekey_sig: &[u8],
-) -> AppResult<()> {
-    sqlx::query(
-        "UPDATE users SET identity_key = $1, signed_prekey = $2, signed_prekey_sig = $3, updated_at = CURRENT_TIMESTAMP WHERE id = $4",
-    )
-    .bind(identity_key)
-    .bind(signed_prekey)
-    .bind(signed_prekey_sig)
-    .bind(user_id)
-    .execute(pool)
-    .await?;
-    Ok(())
-}
-
 pub async fn set_user_totp_secret(pool: &Pool, user_id: Uuid, secret: &str) -> AppResult<()> {
     sqlx::query("UPDATE users SET totp_secret = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
         .bind(secret)
@@ -139,7 +121,9 @@ pub async fn clear_user_totp_secret(pool: &Pool, user_id: Uuid) -> AppResult<()>
 }
 
 pub async fn update_user_password(pool: &Pool, user_id: Uuid, password_hash: &str) -> AppResult<()> {
-    sqlx::query("UPDATE users SET password_hash = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+    sqlx::query(
+        "UPDATE users SET password_hash = $1, password_set = TRUE, updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+    )
         .bind(password_hash)
         .bind(user_id)
         .execute(pool)
@@ -147,21 +131,239 @@ pub async fn update_user_password(pool: &Pool, user_id: Uuid, password_hash: &st
     Ok(())
 }
 
+/// Record that nobody knows the account's password (SSO-provisioned accounts).
+pub async fn mark_password_unset(pool: &Pool, user_id: Uuid) -> AppResult<()> {
+    sqlx::query("UPDATE users SET password_set = FALSE WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Whether the account's owner has ever chosen a password.
+pub async fn is_password_set(pool: &Pool, user_id: Uuid) -> AppResult<bool> {
+    let set: Option<bool> = sqlx::query_scalar("SELECT password_set FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(set.unwrap_or(false))
+}
+
+// ─── Devices ───────────────────────────────────────────
+
+pub async fn create_device(
+    pool: &Pool,
+    user_id: Uuid,
+    name: Option<&str>,
+    identity_key: &[u8],
+    signed_prekey: &[u8],
+    signed_prekey_sig: &[u8],
+    signed_prekey_id: i32,
+) -> AppResult<Device> {
+    let device = sqlx::query_as::<_, Device>(
+        r#"
+        INSERT INTO devices (id, user_id, name, identity_key, signed_prekey, signed_prekey_sig,
+                             signed_prekey_id, signed_prekey_created_at, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(name)
+    .bind(identity_key)
+    .bind(signed_prekey)
+    .bind(signed_prekey_sig)
+    .bind(signed_prekey_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(device)
+}
+
+/// All of a user's devices, primary (oldest) first.
+pub async fn list_devices(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Device>> {
+    let devices = sqlx::query_as::<_, Device>(
+        "SELECT * FROM devices WHERE user_id = $1 ORDER BY created_at ASC, id ASC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(devices)
+}
+
+pub async fn find_device(pool: &Pool, device_id: Uuid) -> AppResult<Option<Device>> {
+    let device = sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE id = $1")
+        .bind(device_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(device)
+}
+
+/// The user's oldest device, which legacy single-device clients act on.
+pub async fn find_primary_device(pool: &Pool, user_id: Uuid) -> AppResult<Option<Device>> {
+    let device = sqlx::query_as::<_, Device>(
+        "SELECT * FROM devices WHERE user_id = $1 ORDER BY created_at ASC, id ASC LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(device)
+}
+
+/// Map each of `user_ids` to its primary device.
+pub async fn get_primary_device_ids(pool: &Pool, user_ids: &[Uuid]) -> AppResult<Vec<(Uuid, Uuid)>> {
+    let rows: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT ON (user_id) user_id, id FROM devices
+        WHERE user_id = ANY($1)
+        ORDER BY user_id, created_at ASC, id ASC
+        "#,
+    )
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Owners of the given devices, as (device_id, user_id) pairs.
+pub async fn get_device_owners(pool: &Pool, device_ids: &[Uuid]) -> AppResult<Vec<(Uuid, Uuid)>> {
+    let rows: Vec<(Uuid, Uuid)> =
+        sqlx::query_as("SELECT id, user_id FROM devices WHERE id = ANY($1)")
+            .bind(device_ids)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows)
+}
+
+/// Replace a device's identity key and rotate its signed prekey. The outgoing
+/// signed prekey is retained until `retain_previous_until`, or dropped when
+/// that is None. When it is the user's primary device the legacy key columns
+/// on `users` are kept in step.
+pub async fn update_device_keys(
+    pool: &Pool,
+    device: &Device,
+    identity_key: &[u8],
+    signed_prekey: &[u8],
+    signed_prekey_sig: &[u8],
+    signed_prekey_id: i32,
+    retain_previous_until: Option<DateTime<Utc>>,
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"
+        UPDATE devices SET
+            previous_signed_prekey_id = CASE WHEN $5::TIMESTAMPTZ IS NULL THEN NULL ELSE signed_prekey_id END,
+            previous_signed_prekey = CASE WHEN $5::TIMESTAMPTZ IS NULL THEN NULL ELSE signed_prekey END,
+            previous_signed_prekey_sig = CASE WHEN $5::TIMESTAMPTZ IS NULL THEN NULL ELSE signed_prekey_sig END,
+            previous_signed_prekey_expires_at = $5,
+            identity_key = $1, signed_prekey = $2, signed_prekey_sig = $3, signed_prekey_id = $4,
+            signed_prekey_created_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+        WHERE id = $6
+        "#,
+    )
+    .bind(identity_key)
+    .bind(signed_prekey)
+    .bind(signed_prekey_sig)
+    .bind(signed_prekey_id)
+    .bind(retain_previous_until)
+    .bind(device.id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE users SET identity_key = $1, signed_prekey = $2, signed_prekey_sig = $3,
+                         updated_at = CURRENT_TIMESTAMP
+        WHERE id = $4 AND $5 = (
+            SELECT id FROM devices WHERE user_id = $4 ORDER BY created_at ASC, id ASC LIMIT 1
+        )
+        "#,
+    )
+    .bind(identity_key)
+    .bind(signed_prekey)
+    .bind(signed_prekey_sig)
+    .bind(device.user_id)
+    .bind(device.id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Drop retained signed prekeys whose grace window has passed.
+pub async fn purge_expired_previous_signed_prekeys(pool: &Pool) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE devices SET previous_signed_prekey_id = NULL, previous_signed_prekey = NULL,
+                           previous_signed_prekey_sig = NULL, previous_signed_prekey_expires_at = NULL
+        WHERE previous_signed_prekey_expires_at < CURRENT_TIMESTAMP
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete one of a user's devices along with its prekeys and pending SKDMs.
+/// If it was the primary device, the legacy key columns on `users` move to
+/// the next oldest device.
+pub async fn delete_device(pool: &Pool, user_id: Uuid, device_id: Uuid) -> AppResult<bool> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query("DELETE FROM devices WHERE id = $1 AND user_id = $2")
+        .bind(device_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() > 0 {
+        sqlx::query(
+            r#"
+            UPDATE users u SET identity_key = d.identity_key, signed_prekey = d.signed_prekey,
+                               signed_prekey_sig = d.signed_prekey_sig,
+                               updated_at = CURRENT_TIMESTAMP
+            FROM (
+                SELECT * FROM devices WHERE user_id = $1 ORDER BY created_at ASC, id ASC LIMIT 1
+            ) d
+            WHERE u.id = $1
+            "#,
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
 // ─── Pre-Keys ──────────────────────────────────────────
 
-pub async fn insert_prekeys(pool: &Pool, user_id: Uuid, keys: &[(i32, Vec<u8>)]) -> AppResult<()> {
+/// Set (or replace) a device's last-resort prekey.
+pub async fn set_last_resort_prekey(pool: &Pool, device_id: Uuid, prekey: &[u8]) -> AppResult<()> {
+    sqlx::query("UPDATE devices SET last_resort_prekey = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+        .bind(prekey)
+        .bind(device_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_prekeys(
+    pool: &Pool,
+    user_id: Uuid,
+    device_id: Uuid,
+    keys: &[(i32, Vec<u8>)],
+) -> AppResult<()> {
     // Batch insert using a transaction
     let mut tx = pool.begin().await?;
 
     for (key_id, public_key) in keys {
         sqlx::query(
             r#"
-            INSERT INTO prekeys (id, user_id, key_id, public_key, used, created_at)
-            VALUES ($1, $2, $3, $4, false, CURRENT_TIMESTAMP)
+            INSERT INTO prekeys (id, user_id, device_id, key_id, public_key, used, created_at)
+            VALUES ($1, $2, $3, $4, $5, false, CURRENT_TIMESTAMP)
             "#,
         )
         .bind(Uuid::new_v4())
         .bind(user_id)
+        .bind(device_id)
         .bind(key_id)
         .bind(public_key)
         .execute(&mut *tx)
@@ -172,14 +374,14 @@ pub async fn insert_prekeys(pool: &Pool, user_id: Uuid, keys: &[(i32, Vec<u8>)])
     Ok(())
 }
 
-/// Fetch and consume one unused one-time prekey (marks it as used atomically).
-pub async fn consume_prekey(pool: &Pool, user_id: Uuid) -> AppResult<Option<PreKey>> {
+/// Fetch and consume one unused one-time prekey of a device (marks it as used atomically).
+pub async fn consume_prekey(pool: &Pool, device_id: Uuid) -> AppResult<Option<PreKey>> {
     let prekey = sqlx::query_as::<_, PreKey>(
         r#"
         UPDATE prekeys SET used = true
         WHERE id = (
             SELECT id FROM prekeys
-            WHERE user_id = $1 AND used = false
+            WHERE device_id = $1 AND used = false
             ORDER BY created_at ASC
             LIMIT 1
             FOR UPDATE SKIP LOCKED
@@ -187,28 +389,28 @@ pub async fn consume_prekey(pool: &Pool, user_id: Uuid) -> AppResult<Option<PreK
         RETURNING *
         "#,
     )
-    .bind(user_id)
+    .bind(device_id)
     .fetch_optional(pool)
     .await?;
 
     Ok(prekey)
 }
 
-pub async fn count_unused_prekeys(pool: &Pool, user_id: Uuid) -> AppResult<i64> {
+pub async fn count_unused_prekeys(pool: &Pool, device_id: Uuid) -> AppResult<i64> {
     let row: (i64,) =
-        sqlx::query_as("SELECT COUNT(*) FROM prekeys WHERE user_id = $1 AND used = false")
-            .bind(user_id)
+        sqlx::query_as("SELECT COUNT(*) FROM prekeys WHERE device_id = $1 AND used = false")
+            .bind(device_id)
             .fetch_one(pool)
             .await?;
     Ok(row.0)
 }
 
-/// Delete all unused one-time prekeys for a user.
+/// Delete all unused one-time prekeys of a device.
 /// Called on login before uploading fresh prekeys so the server only has
 /// OTPs whose private keys exist in the client's current MemoryStore.
-pub async fn delete_unused_prekeys(pool: &Pool, user_id: Uuid) -> AppResult<i64> {
-    let result = sqlx::query("DELETE FROM prekeys WHERE user_id = $1 AND used = false")
-        .bind(user_id)
+pub async fn delete_unused_prekeys(pool: &Pool, device_id: Uuid) -> AppResult<i64> {
+    let result = sqlx::query("DELETE FROM prekeys WHERE device_id = $1 AND used = false")
+        .bind(device_id)
         .execute(pool)
         .await?;
     Ok(result.rows_affected() as i64)
@@ -291,6 +493,17 @@ pub async fn revoke_token_family(pool: &Pool, family_id: Uuid) -> AppResult<u64>
     Ok(result.rows_affected())
 }
 
+/// Every token family (session) the user holds refresh tokens in.
+pub async fn get_user_token_families(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        "SELECT DISTINCT family_id FROM refresh_tokens WHERE user_id = $1 AND family_id IS NOT NULL",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
 pub async fn revoke_all_user_refresh_tokens(pool: &Pool, user_id: Uuid) -> AppResult<()> {
     sqlx::query("DELETE FROM refresh_tokens WHERE user_id = $1")
         .bind(user_id)
@@ -345,1218 +558,2763 @@ pub async fn update_session_activity(pool: &Pool, family_id: Uuid) -> AppResult<
     Ok(())
 }
 
-// ─── Servers ───────────────────────────────────────────
+// ─── API Tokens ────────────────────────────────────────
 
-pub async fn create_server(
+pub async fn count_api_tokens(pool: &Pool, user_id: Uuid) -> AppResult<i64> {
+    let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM api_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.0)
+}
+
+pub async fn insert_api_token(
     pool: &Pool,
-    owner_id: Uuid,
-    encrypted_meta: &[u8],
-) -> AppResult<Server> {
-    let server = sqlx::query_as::<_, Server>(
+    user_id: Uuid,
+    name: &str,
+    token_hash: &str,
+    token_prefix: &str,
+    scopes: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> AppResult<ApiToken> {
+    let token = sqlx::query_as::<_, ApiToken>(
         r#"
-        INSERT INTO servers (id, encrypted_meta, owner_id, created_at)
-        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        INSERT INTO api_tokens (id, user_id, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         RETURNING *
         "#,
     )
     .bind(Uuid::new_v4())
-    .bind(encrypted_meta)
-    .bind(owner_id)
+    .bind(user_id)
+    .bind(name)
+    .bind(token_hash)
+    .bind(token_prefix)
+    .bind(scopes)
+    .bind(expires_at)
     .fetch_one(pool)
     .await?;
-    Ok(server)
-}
-
-pub async fn find_server_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Server>> {
-    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
-        .bind(id)
-        .fetch_optional(pool)
-        .await?;
-    Ok(server)
-}
-
-/// Cached variant — checks cache first, falls back to DB, caches for 5 min.
-pub async fn find_server_by_id_cached(
-    pool: &Pool,
-    redis: &mut Option<redis::aio::ConnectionManager>,
-    memory: &crate::memory_store::MemoryStore,
-    id: Uuid,
-) -> AppResult<Option<Server>> {
-    let key = format!("haven:server:{}", id);
-    if let Some(server) = crate::cache::get_cached::<Server>(redis.as_mut(), memory, &key).await {
-        return Ok(Some(server));
-    }
-    let server = find_server_by_id(pool, id).await?;
-    if let Some(ref s) = server {
-        crate::cache::set_cached(redis.as_mut(), memory, &key, s, 300).await;
-    }
-    Ok(server)
+    Ok(token)
 }
 
-pub async fn get_user_servers(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Server>> {
-    let servers = sqlx::query_as::<_, Server>(
+/// A token by hash, unless its account is deactivated.
+pub async fn find_api_token_by_hash(pool: &Pool, token_hash: &str) -> AppResult<Option<ApiToken>> {
+    let token = sqlx::query_as::<_, ApiToken>(
         r#"
-        SELECT s.* FROM servers s
-        INNER JOIN server_members sm ON s.id = sm.server_id
-        WHERE sm.user_id = $1
-        ORDER BY s.created_at DESC
+        SELECT t.* FROM api_tokens t
+        INNER JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1 AND u.deactivated_at IS NULL
         "#,
     )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+    Ok(token)
+}
+
+/// A user's API tokens, newest first, expired ones included.
+pub async fn list_api_tokens(pool: &Pool, user_id: Uuid) -> AppResult<Vec<ApiToken>> {
+    let tokens = sqlx::query_as::<_, ApiToken>(
+        "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+    )
     .bind(user_id)
     .fetch_all(pool)
     .await?;
-    Ok(servers)
+    Ok(tokens)
 }
 
-pub async fn update_system_channel(
-    pool: &Pool,
-    server_id: Uuid,
-    system_channel_id: Option<Uuid>,
-) -> AppResult<()> {
-    sqlx::query("UPDATE servers SET system_channel_id = $1 WHERE id = $2")
-        .bind(system_channel_id)
-        .bind(server_id)
+/// Revoke one token (only if it belongs to user_id).
+pub async fn delete_api_token(pool: &Pool, user_id: Uuid, id: Uuid) -> AppResult<bool> {
+    let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn update_server_meta(
-    pool: &Pool,
-    server_id: Uuid,
-    encrypted_meta: &[u8],
-) -> AppResult<()> {
-    sqlx::query("UPDATE servers SET encrypted_meta = $1 WHERE id = $2")
-        .bind(encrypted_meta)
-        .bind(server_id)
+pub async fn delete_user_api_tokens(pool: &Pool, user_id: Uuid) -> AppResult<u64> {
+    let result = sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+        .bind(user_id)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected())
 }
 
-pub async fn update_server_icon(
-    pool: &Pool,
-    server_id: Uuid,
-    icon_url: Option<&str>,
-) -> AppResult<()> {
-    sqlx::query("UPDATE servers SET icon_url = $1 WHERE id = $2")
-        .bind(icon_url)
-        .bind(server_id)
+/// Record a use of a token.
+pub async fn touch_api_token(pool: &Pool, id: Uuid, ip_address: Option<&str>) -> AppResult<()> {
+    sqlx::query("UPDATE api_tokens SET last_used_at = NOW(), last_used_ip = $2 WHERE id = $1")
+        .bind(id)
+        .bind(ip_address)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-// ─── Server Members ────────────────────────────────────
+// ─── Security Events ───────────────────────────────────
 
-pub async fn add_server_member(
+pub async fn insert_security_event(
     pool: &Pool,
-    server_id: Uuid,
     user_id: Uuid,
-    encrypted_role: &[u8],
-) -> AppResult<ServerMember> {
-    let member = sqlx::query_as::<_, ServerMember>(
-        r#"
-        INSERT INTO server_members (id, server_id, user_id, encrypted_role, joined_at)
-        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
-        ON CONFLICT (server_id, user_id) DO UPDATE SET encrypted_role = EXCLUDED.encrypted_role
+    kind: &str,
+    device_name: Option<&str>,
+    ip_address: Option<&str>,
+) -> AppResult<SecurityEvent> {
+    let event = sqlx::query_as::<_, SecurityEvent>(
+        r#"
+        INSERT INTO security_events (id, user_id, kind, device_name, ip_address)
+        VALUES ($1, $2, $3, $4, $5)
         RETURNING *
         "#,
     )
     .bind(Uuid::new_v4())
-    .bind(server_id)
     .bind(user_id)
-    .bind(encrypted_role)
+    .bind(kind)
+    .bind(device_name)
+    .bind(ip_address)
     .fetch_one(pool)
     .await?;
-    Ok(member)
+    Ok(event)
 }
 
-pub async fn is_server_member(pool: &Pool, server_id: Uuid, user_id: Uuid) -> AppResult<bool> {
-    let row: (bool,) = sqlx::query_as(
-        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)",
+/// A user's security events, newest first.
+pub async fn list_security_events(
+    pool: &Pool,
+    user_id: Uuid,
+    limit: i64,
+    before: Option<DateTime<Utc>>,
+) -> AppResult<Vec<SecurityEvent>> {
+    let events = sqlx::query_as::<_, SecurityEvent>(
+        r#"
+        SELECT * FROM security_events
+        WHERE user_id = $1 AND ($2::TIMESTAMPTZ IS NULL OR created_at < $2)
+        ORDER BY created_at DESC
+        LIMIT $3
+        "#,
     )
-    .bind(server_id)
     .bind(user_id)
-    .fetch_one(pool)
+    .bind(before)
+    .bind(limit)
+    .fetch_all(pool)
     .await?;
-    Ok(row.0)
+    Ok(events)
 }
 
-// ─── Channels ──────────────────────────────────────────
-
-/// Find an existing DM channel between exactly two users.
-pub async fn find_dm_channel(pool: &Pool, user_a: Uuid, user_b: Uuid) -> AppResult<Option<Channel>> {
-    let channel = sqlx::query_as::<_, Channel>(
+/// Record a sign-in from `device_name`. Returns true when the user had not
+/// signed in from that device before.
+pub async fn record_login_device(pool: &Pool, user_id: Uuid, device_name: &str) -> AppResult<bool> {
+    let inserted: (bool,) = sqlx::query_as(
         r#"
-        SELECT c.* FROM channels c
-        WHERE c.channel_type = 'dm'
-          AND (SELECT COUNT(*) FROM channel_members cm WHERE cm.channel_id = c.id) = 2
-          AND EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel_id = c.id AND cm.user_id = $1)
-          AND EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel_id = c.id AND cm.user_id = $2)
-        LIMIT 1
+        INSERT INTO login_devices (user_id, device_name)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id, device_name) DO UPDATE SET last_seen_at = NOW()
+        RETURNING (xmax = 0) AS inserted
         "#,
     )
-    .bind(user_a)
-    .bind(user_b)
-    .fetch_optional(pool)
+    .bind(user_id)
+    .bind(device_name)
+    .fetch_one(pool)
     .await?;
-    Ok(channel)
+    Ok(inserted.0)
 }
 
-pub async fn create_channel(
+pub async fn count_login_devices(pool: &Pool, user_id: Uuid) -> AppResult<i64> {
+    let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM login_devices WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(count.0)
+}
+
+// ─── Email ─────────────────────────────────────────────
+
+/// Store a new emailed token, replacing the user's earlier tokens for the
+/// same purpose and dropping any that expired.
+pub async fn replace_email_token(
     pool: &Pool,
-    server_id: Option<Uuid>,
-    encrypted_meta: &[u8],
-    channel_type: &str,
-    position: i32,
-    category_id: Option<Uuid>,
-    is_private: bool,
-) -> AppResult<Channel> {
-    let channel = sqlx::query_as::<_, Channel>(
+    user_id: Uuid,
+    purpose: &str,
+    token_hash: &str,
+    email_hash: Option<(&str, &str)>, // (hash, key id)
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        "DELETE FROM email_tokens WHERE (user_id = $1 AND purpose = $2) OR expires_at < CURRENT_TIMESTAMP",
+    )
+    .bind(user_id)
+    .bind(purpose)
+    .execute(pool)
+    .await?;
+    sqlx::query(
         r#"
-        INSERT INTO channels (id, server_id, encrypted_meta, channel_type, position, category_id, is_private, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP)
-        RETURNING *
+        INSERT INTO email_tokens (token_hash, user_id, purpose, email_hash, email_hash_key_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
         "#,
     )
-    .bind(Uuid::new_v4())
-    .bind(server_id)
-    .bind(encrypted_meta)
-    .bind(channel_type)
-    .bind(position)
-    .bind(category_id)
-    .bind(is_private)
-    .fetch_one(pool)
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(purpose)
+    .bind(email_hash.map(|(hash, _)| hash))
+    .bind(email_hash.map(|(_, key_id)| key_id))
+    .bind(expires_at)
+    .execute(pool)
     .await?;
-    Ok(channel)
+    Ok(())
 }
 
-pub async fn get_server_channels(pool: &Pool, server_id: Uuid) -> AppResult<Vec<Channel>> {
-    let channels = sqlx::query_as::<_, Channel>(
-        "SELECT * FROM channels WHERE server_id = $1 ORDER BY position ASC",
+/// Consume an emailed token (single-use). Expired ones are returned too; the
+/// caller checks `expires_at`.
+pub async fn take_email_token(pool: &Pool, token_hash: &str, purpose: &str) -> AppResult<Option<EmailToken>> {
+    let row = sqlx::query_as::<_, EmailToken>(
+        "DELETE FROM email_tokens WHERE token_hash = $1 AND purpose = $2 RETURNING *",
     )
-    .bind(server_id)
-    .fetch_all(pool)
+    .bind(token_hash)
+    .bind(purpose)
+    .fetch_optional(pool)
     .await?;
-    Ok(channels)
+    Ok(row)
 }
 
-pub async fn get_user_dm_channels(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Channel>> {
-    let channels = sqlx::query_as::<_, Channel>(
-        r#"
-        SELECT c.* FROM channels c
-        INNER JOIN channel_members cm ON c.id = cm.channel_id
-        WHERE cm.user_id = $1 AND c.channel_type IN ('dm', 'group')
-        ORDER BY c.created_at DESC
-        "#,
+pub async fn delete_email_tokens(pool: &Pool, user_id: Uuid, purpose: &str) -> AppResult<()> {
+    sqlx::query("DELETE FROM email_tokens WHERE user_id = $1 AND purpose = $2")
+        .bind(user_id)
+        .bind(purpose)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn has_pending_email_token(pool: &Pool, user_id: Uuid, purpose: &str) -> AppResult<bool> {
+    let row: (bool,) = sqlx::query_as(
+        "SELECT EXISTS(SELECT 1 FROM email_tokens WHERE user_id = $1 AND purpose = $2 AND expires_at > CURRENT_TIMESTAMP)",
     )
     .bind(user_id)
-    .fetch_all(pool)
+    .bind(purpose)
+    .fetch_one(pool)
     .await?;
-    Ok(channels)
+    Ok(row.0)
 }
 
-pub async fn find_channel_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Channel>> {
-    let ch = sqlx::query_as::<_, Channel>("SELECT * FROM channels WHERE id = $1")
-        .bind(id)
-        .fetch_optional(pool)
-        .await?;
-    Ok(ch)
+/// The user whose verified email hashes to one of `hashes` (one per
+/// configured email hash key).
+pub async fn find_user_by_verified_email(pool: &Pool, hashes: &[String]) -> AppResult<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT * FROM users
+        WHERE email_hash = ANY($1) AND email_verified_at IS NOT NULL
+        ORDER BY email_verified_at DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(hashes)
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
 }
 
-pub async fn update_channel_meta(
+/// Replace the user's email hash. `verified` marks it confirmed; otherwise
+/// it is stored unverified.
+pub async fn set_user_email_hash(
     pool: &Pool,
-    channel_id: Uuid,
-    encrypted_meta: &[u8],
-) -> AppResult<Channel> {
-    let ch = sqlx::query_as::<_, Channel>(
-        "UPDATE channels SET encrypted_meta = $1 WHERE id = $2 RETURNING *",
+    user_id: Uuid,
+    email_hash: Option<(&str, &str)>, // (hash, key id)
+    verified: bool,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET email_hash = $2, email_hash_key_id = $3,
+            email_verified_at = CASE WHEN $4 THEN NOW() ELSE NULL END,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
     )
-    .bind(encrypted_meta)
-    .bind(channel_id)
-    .fetch_one(pool)
+    .bind(user_id)
+    .bind(email_hash.map(|(hash, _)| hash))
+    .bind(email_hash.map(|(_, key_id)| key_id))
+    .bind(verified)
+    .execute(pool)
     .await?;
-    Ok(ch)
+    Ok(())
 }
 
-pub async fn delete_channel(pool: &Pool, channel_id: Uuid) -> AppResult<()> {
-    // Delete members first, then message children, then messages, then the channel
-    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1")
-        .bind(channel_id)
-        .execute(pool)
-        .await?;
-    // Clean up child rows before deleting messages (no FK cascade on partitioned table)
-    cleanup_channel_message_children(pool, channel_id).await?;
-    sqlx::query("DELETE FROM messages WHERE channel_id = $1")
-        .bind(channel_id)
-        .execute(pool)
-        .await?;
-    sqlx::query("DELETE FROM sender_key_distributions WHERE channel_id = $1")
-        .bind(channel_id)
-        .execute(pool)
-        .await?;
-    sqlx::query("DELETE FROM channels WHERE id = $1")
-        .bind(channel_id)
+/// Move a verified email hash to the current key, keeping it verified.
+pub async fn rehash_user_email(pool: &Pool, user_id: Uuid, email_hash: &str, key_id: &str) -> AppResult<()> {
+    sqlx::query("UPDATE users SET email_hash = $2, email_hash_key_id = $3 WHERE id = $1")
+        .bind(user_id)
+        .bind(email_hash)
+        .bind(key_id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-// ─── Channel Members ───────────────────────────────────
+// ─── Device Linking ────────────────────────────────────
 
-pub async fn add_channel_member(
+/// Store a new link request, dropping any that expired.
+pub async fn insert_device_link(
     pool: &Pool,
-    channel_id: Uuid,
-    user_id: Uuid,
-) -> AppResult<ChannelMember> {
-    let member = sqlx::query_as::<_, ChannelMember>(
+    id: Uuid,
+    token_hash: &str,
+    ephemeral_public_key: &[u8],
+    device_name: Option<&str>,
+    ip_address: Option<&str>,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query("DELETE FROM device_links WHERE expires_at < CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+    sqlx::query(
         r#"
-        INSERT INTO channel_members (id, channel_id, user_id, joined_at)
-        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
-        ON CONFLICT (channel_id, user_id) DO UPDATE SET joined_at = EXCLUDED.joined_at
-        RETURNING *
+        INSERT INTO device_links (id, token_hash, ephemeral_public_key, device_name, ip_address, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
         "#,
     )
-    .bind(Uuid::new_v4())
-    .bind(channel_id)
-    .bind(user_id)
-    .fetch_one(pool)
+    .bind(id)
+    .bind(token_hash)
+    .bind(ephemeral_public_key)
+    .bind(device_name)
+    .bind(ip_address)
+    .bind(expires_at)
+    .execute(pool)
     .await?;
-    Ok(member)
+    Ok(())
 }
 
-/// Bulk-insert a user into all channels belonging to a server (single query).
-pub async fn add_channel_members_bulk(
+/// A link request that is neither approved nor expired.
+pub async fn find_pending_device_link(pool: &Pool, id: Uuid) -> AppResult<Option<DeviceLink>> {
+    let link = sqlx::query_as::<_, DeviceLink>(
+        r#"
+        SELECT * FROM device_links
+        WHERE id = $1 AND approved_by IS NULL AND expires_at > CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(link)
+}
+
+/// Approve a pending link whose ephemeral key matches the scanned one.
+/// Returns false when there is no such link.
+pub async fn approve_device_link(
     pool: &Pool,
-    server_id: Uuid,
-    user_id: Uuid,
-) -> AppResult<u64> {
+    id: Uuid,
+    ephemeral_public_key: &[u8],
+    approved_by: Uuid,
+    approver_public_key: &[u8],
+    provisioning_message: &[u8],
+) -> AppResult<bool> {
     let result = sqlx::query(
         r#"
-        INSERT INTO channel_members (id, channel_id, user_id, joined_at)
-        SELECT gen_random_uuid(), c.id, $1, CURRENT_TIMESTAMP
-        FROM channels c
-        WHERE c.server_id = $2
-        ON CONFLICT (channel_id, user_id) DO UPDATE SET joined_at = EXCLUDED.joined_at
+        UPDATE device_links
+        SET approved_by = $3, approver_public_key = $4, provisioning_message = $5, approved_at = NOW()
+        WHERE id = $1 AND ephemeral_public_key = $2
+          AND approved_by IS NULL AND expires_at > CURRENT_TIMESTAMP
         "#,
     )
-    .bind(user_id)
-    .bind(server_id)
+    .bind(id)
+    .bind(ephemeral_public_key)
+    .bind(approved_by)
+    .bind(approver_public_key)
+    .bind(provisioning_message)
     .execute(pool)
     .await?;
-    Ok(result.rows_affected())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn is_channel_member(pool: &Pool, channel_id: Uuid, user_id: Uuid) -> AppResult<bool> {
-    let row: (bool,) = sqlx::query_as(
-        "SELECT EXISTS(SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2)",
+/// Consume an approved link (single-use) for the client holding its token.
+pub async fn take_approved_device_link(pool: &Pool, id: Uuid, token_hash: &str) -> AppResult<Option<DeviceLink>> {
+    let link = sqlx::query_as::<_, DeviceLink>(
+        r#"
+        DELETE FROM device_links
+        WHERE id = $1 AND token_hash = $2 AND approved_by IS NOT NULL AND expires_at > CURRENT_TIMESTAMP
+        RETURNING *
+        "#,
     )
-    .bind(channel_id)
-    .bind(user_id)
-    .fetch_one(pool)
+    .bind(id)
+    .bind(token_hash)
+    .fetch_optional(pool)
     .await?;
-    Ok(row.0)
+    Ok(link)
 }
 
-/// Check if a user can access a channel: either via channel_members (DM/group)
-/// or via server membership (server channels).
-pub async fn can_access_channel(pool: &Pool, channel_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+/// Whether the client holding `token_hash` has a link still waiting for approval.
+pub async fn device_link_is_pending(pool: &Pool, id: Uuid, token_hash: &str) -> AppResult<bool> {
     let row: (bool,) = sqlx::query_as(
         r#"
         SELECT EXISTS(
-            SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2
-            UNION ALL
-            SELECT 1 FROM channels c
-            JOIN server_members sm ON sm.server_id = c.server_id
-            WHERE c.id = $1 AND sm.user_id = $2 AND c.server_id IS NOT NULL
+            SELECT 1 FROM device_links
+            WHERE id = $1 AND token_hash = $2 AND approved_by IS NULL AND expires_at > CURRENT_TIMESTAMP
         )
         "#,
     )
-    .bind(channel_id)
-    .bind(user_id)
+    .bind(id)
+    .bind(token_hash)
     .fetch_one(pool)
     .await?;
     Ok(row.0)
 }
 
-pub async fn get_channel_member_ids(pool: &Pool, channel_id: Uuid) -> AppResult<Vec<Uuid>> {
-    let rows: Vec<(Uuid,)> =
-        sqlx::query_as("SELECT user_id FROM channel_members WHERE channel_id = $1")
-            .bind(channel_id)
-            .fetch_all(pool)
-            .await?;
-    Ok(rows.into_iter().map(|r| r.0).collect())
-}
+// ─── OIDC ──────────────────────────────────────────────
 
-/// Remove a user from a channel.
-pub async fn remove_channel_member(
+/// Store an in-flight authorization request, dropping any that expired.
+pub async fn insert_oidc_login_state(
     pool: &Pool,
-    channel_id: Uuid,
-    user_id: Uuid,
+    state: &str,
+    code_verifier: &str,
+    nonce: &str,
+    link_user_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
 ) -> AppResult<()> {
-    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-        .bind(channel_id)
-        .bind(user_id)
+    sqlx::query("DELETE FROM oidc_login_states WHERE expires_at < CURRENT_TIMESTAMP")
         .execute(pool)
         .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO oidc_login_states (state, code_verifier, nonce, link_user_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(state)
+    .bind(code_verifier)
+    .bind(nonce)
+    .bind(link_user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
-/// Get channel members with user info (for DM/group member sidebar).
-pub async fn get_channel_members_info(
-    pool: &Pool,
-    channel_id: Uuid,
-) -> AppResult<Vec<ChannelMemberInfo>> {
-    let members = sqlx::query_as::<_, ChannelMemberInfo>(
-        r#"
-        SELECT cm.user_id, u.username, u.display_name, u.avatar_url, cm.joined_at
-        FROM channel_members cm
-        INNER JOIN users u ON u.id = cm.user_id
-        WHERE cm.channel_id = $1
-        ORDER BY cm.joined_at ASC
-        "#,
+/// Consume an authorization request (single-use). Expired ones are returned
+/// too; the caller checks `expires_at`.
+pub async fn take_oidc_login_state(pool: &Pool, state: &str) -> AppResult<Option<OidcLoginState>> {
+    let row = sqlx::query_as::<_, OidcLoginState>(
+        "DELETE FROM oidc_login_states WHERE state = $1 RETURNING *",
     )
-    .bind(channel_id)
-    .fetch_all(pool)
+    .bind(state)
+    .fetch_optional(pool)
     .await?;
-    Ok(members)
+    Ok(row)
 }
 
-/// Get all channel IDs a user belongs to (for presence broadcast).
-/// Includes DM/group channels (via channel_members) and server channels (via server membership).
-pub async fn get_user_channel_ids(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Uuid>> {
-    let rows: Vec<(Uuid,)> = sqlx::query_as(
+pub async fn find_user_by_oidc_subject(
+    pool: &Pool,
+    issuer: &str,
+    subject: &str,
+) -> AppResult<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT channel_id FROM channel_members WHERE user_id = $1
-        UNION
-        SELECT c.id FROM channels c
-        JOIN server_members sm ON sm.server_id = c.server_id
-        WHERE sm.user_id = $1 AND c.server_id IS NOT NULL
+        SELECT u.* FROM users u
+        INNER JOIN oidc_identities oi ON oi.user_id = u.id
+        WHERE oi.issuer = $1 AND oi.subject = $2
         "#,
     )
-    .bind(user_id)
-    .fetch_all(pool)
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
     .await?;
-    Ok(rows.into_iter().map(|r| r.0).collect())
-}
-
-// ─── Messages ──────────────────────────────────────────
-
-pub async fn find_message_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Message>> {
-    let msg = sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE id = $1")
-        .bind(id)
-        .fetch_optional(pool)
-        .await?;
-    Ok(msg)
+    Ok(user)
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn insert_message(
+pub async fn link_oidc_identity(
     pool: &Pool,
-    channel_id: Uuid,
-    sender_token: &[u8],
-    encrypted_body: &[u8],
-    expires_at: Option<DateTime<Utc>>,
-    has_attachments: bool,
-    sender_id: Uuid,
-    reply_to_id: Option<Uuid>,
-) -> AppResult<Message> {
-    let msg = sqlx::query_as::<_, Message>(
+    user_id: Uuid,
+    issuer: &str,
+    subject: &str,
+) -> AppResult<OidcIdentity> {
+    let identity = sqlx::query_as::<_, OidcIdentity>(
         r#"
-        INSERT INTO messages (id, channel_id, sender_token, encrypted_body,
-                             timestamp, expires_at, has_attachments, sender_id, reply_to_id)
-        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5, $6, $7, $8)
+        INSERT INTO oidc_identities (id, user_id, issuer, subject, created_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
         RETURNING *
         "#,
     )
     .bind(Uuid::new_v4())
-    .bind(channel_id)
-    .bind(sender_token)
-    .bind(encrypted_body)
-    .bind(expires_at)
-    .bind(has_attachments)
-    .bind(sender_id)
-    .bind(reply_to_id)
+    .bind(user_id)
+    .bind(issuer)
+    .bind(subject)
     .fetch_one(pool)
-    .await?;
-    Ok(msg)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_err)
+            if db_err.constraint() == Some("oidc_identities_issuer_subject_key") =>
+        {
+            AppError::Conflict("This SSO identity is already linked to another account".into())
+        }
+        sqlx::Error::Database(ref db_err)
+            if db_err.constraint() == Some("oidc_identities_user_id_issuer_key") =>
+        {
+            AppError::Conflict("Your account is already linked to an SSO identity".into())
+        }
+        other => AppError::Database(other),
+    })?;
+    Ok(identity)
 }
 
-/// Update encrypted_body of a message (for editing). Only the original sender can edit.
-pub async fn update_message_body(
-    pool: &Pool,
-    message_id: Uuid,
-    sender_id: Uuid,
-    new_encrypted_body: &[u8],
-) -> AppResult<Message> {
-    let msg = sqlx::query_as::<_, Message>(
-        r#"
-        UPDATE messages
-        SET encrypted_body = $1, edited_at = CURRENT_TIMESTAMP
-        WHERE id = $2 AND sender_id = $3
-        RETURNING *
-        "#,
+pub async fn touch_oidc_identity(pool: &Pool, issuer: &str, subject: &str) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE oidc_identities SET last_login_at = CURRENT_TIMESTAMP WHERE issuer = $1 AND subject = $2",
     )
-    .bind(new_encrypted_body)
-    .bind(message_id)
-    .bind(sender_id)
-    .fetch_optional(pool)
+    .bind(issuer)
+    .bind(subject)
+    .execute(pool)
     .await?;
+    Ok(())
+}
 
-    msg.ok_or_else(|| AppError::Forbidden("Cannot edit this message".into()))
+pub async fn get_oidc_identities(pool: &Pool, user_id: Uuid) -> AppResult<Vec<OidcIdentity>> {
+    let identities = sqlx::query_as::<_, OidcIdentity>(
+        "SELECT * FROM oidc_identities WHERE user_id = $1 ORDER BY created_at ASC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(identities)
 }
 
-/// Clean up child rows that previously relied on FK CASCADE from messages.
-/// Must be called before deleting messages (partitioned tables can't have FK refs).
-async fn cleanup_message_children(pool: &Pool, message_id: Uuid) -> AppResult<()> {
-    sqlx::query("DELETE FROM attachments WHERE message_id = $1")
-        .bind(message_id)
-        .execute(pool)
-        .await?;
-    sqlx::query("DELETE FROM reactions WHERE message_id = $1")
-        .bind(message_id)
-        .execute(pool)
-        .await?;
-    sqlx::query("DELETE FROM pinned_messages WHERE message_id = $1")
-        .bind(message_id)
-        .execute(pool)
-        .await?;
-    sqlx::query("DELETE FROM reports WHERE message_id = $1")
-        .bind(message_id)
+/// Unlink `user_id` from `issuer`. Issuers are stored normalized, see
+/// `oidc::normalize_issuer`.
+pub async fn delete_oidc_identity(pool: &Pool, user_id: Uuid, issuer: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        "DELETE FROM oidc_identities WHERE user_id = $1 AND issuer = $2",
+    )
+        .bind(user_id)
+        .bind(issuer)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-/// Clean up child rows for all messages in a channel.
-/// Used when deleting a channel (bulk message delete).
-async fn cleanup_channel_message_children(pool: &Pool, channel_id: Uuid) -> AppResult<()> {
-    sqlx::query(
-        "DELETE FROM attachments WHERE message_id IN (SELECT id FROM messages WHERE channel_id = $1)"
-    )
-    .bind(channel_id)
-    .execute(pool)
-    .await?;
-    sqlx::query(
-        "DELETE FROM reactions WHERE message_id IN (SELECT id FROM messages WHERE channel_id = $1)"
-    )
-    .bind(channel_id)
-    .execute(pool)
-    .await?;
-    sqlx::query(
-        "DELETE FROM pinned_messages WHERE channel_id = $1"
+// ─── Signing Keys ──────────────────────────────────────
+
+/// Signing keys that haven't been retired yet, oldest activation first.
+pub async fn get_jwt_signing_keys(
+    pool: &Pool,
+    purpose: &str,
+    now: DateTime<Utc>,
+) -> AppResult<Vec<JwtSigningKey>> {
+    let keys = sqlx::query_as::<_, JwtSigningKey>(
+        r#"
+        SELECT * FROM jwt_signing_keys
+        WHERE purpose = $1 AND (retires_at IS NULL OR retires_at > $2)
+        ORDER BY activates_at
+        "#,
     )
-    .bind(channel_id)
-    .execute(pool)
+    .bind(purpose)
+    .bind(now)
+    .fetch_all(pool)
     .await?;
-    sqlx::query(
-        "DELETE FROM reports WHERE channel_id = $1"
+    Ok(keys)
+}
+
+/// Add a signing key. With `unless_pending_after`, nothing is inserted if some
+/// key for the same purpose already activates after that time, so instances
+/// rotating at the same moment add one key between them. Returns whether the
+/// key was inserted.
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_jwt_signing_key(
+    pool: &Pool,
+    purpose: &str,
+    kid: &str,
+    algorithm: &str,
+    private_key: &[u8],
+    public_key: &[u8],
+    activates_at: DateTime<Utc>,
+    unless_pending_after: Option<DateTime<Utc>>,
+) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO jwt_signing_keys (kid, purpose, algorithm, private_key, public_key,
+                                      created_at, activates_at)
+        SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP, $6
+        WHERE $7::TIMESTAMPTZ IS NULL
+           OR NOT EXISTS (SELECT 1 FROM jwt_signing_keys WHERE purpose = $2 AND activates_at > $7)
+        "#,
     )
-    .bind(channel_id)
+    .bind(kid)
+    .bind(purpose)
+    .bind(algorithm)
+    .bind(private_key)
+    .bind(public_key)
+    .bind(activates_at)
+    .bind(unless_pending_after)
     .execute(pool)
     .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-/// Delete a message. Only the original sender can delete.
-/// Returns the deleted message (for getting channel_id).
-pub async fn delete_message(
+/// Schedule retirement for every key of `purpose` activated before `activated_before`.
+pub async fn retire_jwt_signing_keys(
     pool: &Pool,
-    message_id: Uuid,
-    sender_id: Uuid,
-) -> AppResult<Message> {
-    // Clean up child rows (no FK cascade on partitioned table)
-    cleanup_message_children(pool, message_id).await?;
-
-    let msg = sqlx::query_as::<_, Message>(
+    purpose: &str,
+    activated_before: DateTime<Utc>,
+    retires_at: DateTime<Utc>,
+) -> AppResult<u64> {
+    let result = sqlx::query(
         r#"
-        DELETE FROM messages
-        WHERE id = $1 AND sender_id = $2
-        RETURNING *
+        UPDATE jwt_signing_keys SET retires_at = $3
+        WHERE purpose = $1 AND retires_at IS NULL AND activates_at < $2
         "#,
     )
-    .bind(message_id)
-    .bind(sender_id)
-    .fetch_optional(pool)
+    .bind(purpose)
+    .bind(activated_before)
+    .bind(retires_at)
+    .execute(pool)
     .await?;
+    Ok(result.rows_affected())
+}
 
-    msg.ok_or_else(|| AppError::Forbidden("Cannot delete this message".into()))
+pub async fn delete_retired_jwt_signing_keys(pool: &Pool, now: DateTime<Utc>) -> AppResult<u64> {
+    let result = sqlx::query("DELETE FROM jwt_signing_keys WHERE retires_at <= $1")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
 }
 
-/// Delete a message by ID (admin/owner — no sender check).
-pub async fn delete_message_admin(
-    pool: &Pool,
-    message_id: Uuid,
-) -> AppResult<Message> {
-    // Clean up child rows (no FK cascade on partitioned table)
-    cleanup_message_children(pool, message_id).await?;
+/// Stored email hashes per email hash key id (None: legacy jwt_secret hashes).
+pub async fn count_email_hashes_by_key(pool: &Pool) -> AppResult<Vec<(Option<String>, i64)>> {
+    let rows = sqlx::query_as(
+        "SELECT email_hash_key_id, COUNT(*) FROM users WHERE email_hash IS NOT NULL GROUP BY email_hash_key_id",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
 
-    let msg = sqlx::query_as::<_, Message>(
+// ─── Servers ───────────────────────────────────────────
+
+pub async fn create_server(
+    pool: &Pool,
+    owner_id: Uuid,
+    encrypted_meta: &[u8],
+) -> AppResult<Server> {
+    let server = sqlx::query_as::<_, Server>(
         r#"
-        DELETE FROM messages
-        WHERE id = $1
+        INSERT INTO servers (id, encrypted_meta, owner_id, created_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
         RETURNING *
         "#,
     )
-    .bind(message_id)
-    .fetch_optional(pool)
+    .bind(Uuid::new_v4())
+    .bind(encrypted_meta)
+    .bind(owner_id)
+    .fetch_one(pool)
     .await?;
+    Ok(server)
+}
 
-    msg.ok_or_else(|| AppError::NotFound("Message not found".into()))
+pub async fn find_server_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Server>> {
+    let server = sqlx::query_as::<_, Server>("SELECT * FROM servers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(server)
 }
 
-pub async fn get_channel_messages(
+/// Cached variant — checks cache first, falls back to DB, caches for 5 min.
+pub async fn find_server_by_id_cached(
     pool: &Pool,
-    channel_id: Uuid,
-    before: Option<DateTime<Utc>>,
-    limit: i64,
-) -> AppResult<Vec<Message>> {
-    let messages = if let Some(before_ts) = before {
-        sqlx::query_as::<_, Message>(
-            r#"
-            SELECT * FROM messages
-            WHERE channel_id = $1 AND timestamp < $2
-              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
-            ORDER BY timestamp DESC
-            LIMIT $3
-            "#,
-        )
+    redis: &mut Option<redis::aio::ConnectionManager>,
+    memory: &crate::memory_store::MemoryStore,
+    id: Uuid,
+) -> AppResult<Option<Server>> {
+    let key = format!("haven:server:{}", id);
+    if let Some(server) = crate::cache::get_cached::<Server>(redis.as_mut(), memory, &key).await {
+        return Ok(Some(server));
+    }
+    let server = find_server_by_id(pool, id).await?;
+    if let Some(ref s) = server {
+        crate::cache::set_cached(redis.as_mut(), memory, &key, s, 300).await;
+    }
+    Ok(server)
+}
+
+pub async fn get_user_servers(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Server>> {
+    let servers = sqlx::query_as::<_, Server>(
+        r#"
+        SELECT s.* FROM servers s
+        INNER JOIN server_members sm ON s.id = sm.server_id
+        WHERE sm.user_id = $1
+        ORDER BY s.created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(servers)
+}
+
+pub async fn update_system_channel(
+    pool: &Pool,
+    server_id: Uuid,
+    system_channel_id: Option<Uuid>,
+) -> AppResult<()> {
+    sqlx::query("UPDATE servers SET system_channel_id = $1 WHERE id = $2")
+        .bind(system_channel_id)
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn update_server_meta(
+    pool: &Pool,
+    server_id: Uuid,
+    encrypted_meta: &[u8],
+) -> AppResult<()> {
+    sqlx::query("UPDATE servers SET encrypted_meta = $1, meta_epoch = key_epoch WHERE id = $2")
+        .bind(encrypted_meta)
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn update_server_icon(
+    pool: &Pool,
+    server_id: Uuid,
+    icon_url: Option<&str>,
+) -> AppResult<()> {
+    sqlx::query("UPDATE servers SET icon_url = $1 WHERE id = $2")
+        .bind(icon_url)
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Server Members ────────────────────────────────────
+
+pub async fn add_server_member(
+    pool: &Pool,
+    server_id: Uuid,
+    user_id: Uuid,
+    encrypted_role: &[u8],
+) -> AppResult<ServerMember> {
+    let member = sqlx::query_as::<_, ServerMember>(
+        r#"
+        INSERT INTO server_members (id, server_id, user_id, encrypted_role, joined_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+        ON CONFLICT (server_id, user_id) DO UPDATE SET encrypted_role = EXCLUDED.encrypted_role
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(server_id)
+    .bind(user_id)
+    .bind(encrypted_role)
+    .fetch_one(pool)
+    .await?;
+    Ok(member)
+}
+
+pub async fn is_server_member(pool: &Pool, server_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    let row: (bool,) = sqlx::query_as(
+        "SELECT EXISTS(SELECT 1 FROM server_members WHERE server_id = $1 AND user_id = $2)",
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.0)
+}
+
+// ─── Channels ──────────────────────────────────────────
+
+/// Find an existing DM channel between exactly two users.
+pub async fn find_dm_channel(pool: &Pool, user_a: Uuid, user_b: Uuid) -> AppResult<Option<Channel>> {
+    let channel = sqlx::query_as::<_, Channel>(
+        r#"
+        SELECT c.* FROM channels c
+        WHERE c.channel_type = 'dm'
+          AND (SELECT COUNT(*) FROM channel_members cm WHERE cm.channel_id = c.id) = 2
+          AND EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel_id = c.id AND cm.user_id = $1)
+          AND EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel_id = c.id AND cm.user_id = $2)
+        LIMIT 1
+        "#,
+    )
+    .bind(user_a)
+    .bind(user_b)
+    .fetch_optional(pool)
+    .await?;
+    Ok(channel)
+}
+
+pub async fn create_channel(
+    pool: &Pool,
+    server_id: Option<Uuid>,
+    encrypted_meta: &[u8],
+    channel_type: &str,
+    position: i32,
+    category_id: Option<Uuid>,
+    is_private: bool,
+) -> AppResult<Channel> {
+    let channel = sqlx::query_as::<_, Channel>(
+        r#"
+        INSERT INTO channels (id, server_id, encrypted_meta, channel_type, position, category_id, is_private, meta_epoch, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7,
+                COALESCE((SELECT key_epoch FROM servers WHERE id = $2), 0), CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(server_id)
+    .bind(encrypted_meta)
+    .bind(channel_type)
+    .bind(position)
+    .bind(category_id)
+    .bind(is_private)
+    .fetch_one(pool)
+    .await?;
+    Ok(channel)
+}
+
+pub async fn get_server_channels(pool: &Pool, server_id: Uuid) -> AppResult<Vec<Channel>> {
+    let channels = sqlx::query_as::<_, Channel>(
+        "SELECT * FROM channels WHERE server_id = $1 ORDER BY position ASC",
+    )
+    .bind(server_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(channels)
+}
+
+pub async fn get_user_dm_channels(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Channel>> {
+    let channels = sqlx::query_as::<_, Channel>(
+        r#"
+        SELECT c.* FROM channels c
+        INNER JOIN channel_members cm ON c.id = cm.channel_id
+        WHERE cm.user_id = $1 AND c.channel_type IN ('dm', 'group')
+        ORDER BY c.created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(channels)
+}
+
+pub async fn find_channel_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Channel>> {
+    let ch = sqlx::query_as::<_, Channel>("SELECT * FROM channels WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(ch)
+}
+
+pub async fn update_channel_meta(
+    pool: &Pool,
+    channel_id: Uuid,
+    encrypted_meta: &[u8],
+) -> AppResult<Channel> {
+    let ch = sqlx::query_as::<_, Channel>(
+        r#"
+        UPDATE channels SET encrypted_meta = $1,
+            meta_epoch = COALESCE((SELECT key_epoch FROM servers WHERE id = channels.server_id), 0)
+        WHERE id = $2
+        RETURNING *
+        "#,
+    )
+    .bind(encrypted_meta)
+    .bind(channel_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(ch)
+}
+
+pub async fn delete_channel(pool: &Pool, channel_id: Uuid) -> AppResult<()> {
+    // Delete members first, then message children, then messages, then the channel
+    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1")
         .bind(channel_id)
-        .bind(before_ts)
-        .bind(limit)
-        .fetch_all(pool)
-        .await?
-    } else {
-        sqlx::query_as::<_, Message>(
+        .execute(pool)
+        .await?;
+    // Clean up child rows before deleting messages (no FK cascade on partitioned table)
+    cleanup_channel_message_children(pool, channel_id).await?;
+    sqlx::query("DELETE FROM messages WHERE channel_id = $1")
+        .bind(channel_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM sender_key_distributions WHERE channel_id = $1")
+        .bind(channel_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM channels WHERE id = $1")
+        .bind(channel_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Channel Members ───────────────────────────────────
+
+pub async fn add_channel_member(
+    pool: &Pool,
+    channel_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<ChannelMember> {
+    let member = sqlx::query_as::<_, ChannelMember>(
+        r#"
+        INSERT INTO channel_members (id, channel_id, user_id, joined_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        ON CONFLICT (channel_id, user_id) DO UPDATE SET joined_at = EXCLUDED.joined_at
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(member)
+}
+
+/// Bulk-insert a user into all channels belonging to a server (single query).
+pub async fn add_channel_members_bulk(
+    pool: &Pool,
+    server_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO channel_members (id, channel_id, user_id, joined_at)
+        SELECT gen_random_uuid(), c.id, $1, CURRENT_TIMESTAMP
+        FROM channels c
+        WHERE c.server_id = $2
+        ON CONFLICT (channel_id, user_id) DO UPDATE SET joined_at = EXCLUDED.joined_at
+        "#,
+    )
+    .bind(user_id)
+    .bind(server_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn is_channel_member(pool: &Pool, channel_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    let row: (bool,) = sqlx::query_as(
+        "SELECT EXISTS(SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2)",
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.0)
+}
+
+/// Check if a user can access a channel: either via channel_members (DM/group)
+/// or via server membership (server channels).
+pub async fn can_access_channel(pool: &Pool, channel_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    let row: (bool,) = sqlx::query_as(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2
+            UNION ALL
+            SELECT 1 FROM channels c
+            JOIN server_members sm ON sm.server_id = c.server_id
+            WHERE c.id = $1 AND sm.user_id = $2 AND c.server_id IS NOT NULL
+        )
+        "#,
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(row.0)
+}
+
+pub async fn get_channel_member_ids(pool: &Pool, channel_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> =
+        sqlx::query_as("SELECT user_id FROM channel_members WHERE channel_id = $1")
+            .bind(channel_id)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// Remove a user from a channel.
+pub async fn remove_channel_member(
+    pool: &Pool,
+    channel_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<()> {
+    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+        .bind(channel_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Get channel members with user info (for DM/group member sidebar).
+pub async fn get_channel_members_info(
+    pool: &Pool,
+    channel_id: Uuid,
+) -> AppResult<Vec<ChannelMemberInfo>> {
+    // Deactivated accounts show no display name or avatar, and accounts
+    // awaiting deletion show as a placeholder
+    let members = sqlx::query_as::<_, ChannelMemberInfo>(
+        r#"
+        SELECT cm.user_id,
+               CASE WHEN u.deletion_scheduled_at IS NULL THEN u.username ELSE $2 END AS username,
+               CASE WHEN u.deactivated_at IS NULL THEN u.display_name END AS display_name,
+               CASE WHEN u.deactivated_at IS NULL THEN u.avatar_url END AS avatar_url,
+               cm.joined_at,
+               u.deletion_scheduled_at IS NOT NULL AS deleted
+        FROM channel_members cm
+        INNER JOIN users u ON u.id = cm.user_id
+        WHERE cm.channel_id = $1
+        ORDER BY cm.joined_at ASC
+        "#,
+    )
+    .bind(channel_id)
+    .bind(crate::accounts::DELETED_ACCOUNT_NAME)
+    .fetch_all(pool)
+    .await?;
+    Ok(members)
+}
+
+/// Get all channel IDs a user belongs to (for presence broadcast).
+/// Includes DM/group channels (via channel_members) and server channels (via server membership).
+pub async fn get_user_channel_ids(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        r#"
+        SELECT channel_id FROM channel_members WHERE user_id = $1
+        UNION
+        SELECT c.id FROM channels c
+        JOIN server_members sm ON sm.server_id = c.server_id
+        WHERE sm.user_id = $1 AND c.server_id IS NOT NULL
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// Server channels whose subscribers see the user's presence. Skips servers with
+/// at least `large_server_threshold` members (those use lazy member lists), and
+/// DM/group channels (participants get presence as user-directed events).
+pub async fn get_user_presence_channel_ids(
+    pool: &Pool,
+    user_id: Uuid,
+    large_server_threshold: i64,
+) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        r#"
+        SELECT c.id FROM channels c
+        JOIN server_members sm ON sm.server_id = c.server_id
+        WHERE sm.user_id = $1 AND c.server_id IS NOT NULL
+          AND (SELECT COUNT(*) FROM server_members m WHERE m.server_id = c.server_id) < $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(large_server_threshold)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+// ─── Messages ──────────────────────────────────────────
+
+pub async fn find_message_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Message>> {
+    let msg = sqlx::query_as::<_, Message>("SELECT * FROM messages WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(msg)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_message(
+    pool: &Pool,
+    channel_id: Uuid,
+    sender_token: &[u8],
+    encrypted_body: &[u8],
+    expires_at: Option<DateTime<Utc>>,
+    has_attachments: bool,
+    sender_id: Option<Uuid>,
+    reply_to_id: Option<Uuid>,
+    capability_hash: Option<&[u8]>,
+) -> AppResult<Message> {
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        INSERT INTO messages (id, channel_id, sender_token, encrypted_body,
+                             timestamp, expires_at, has_attachments, sender_id, reply_to_id,
+                             capability_hash)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP, $5, $6, $7, $8, $9)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(channel_id)
+    .bind(sender_token)
+    .bind(encrypted_body)
+    .bind(expires_at)
+    .bind(has_attachments)
+    .bind(sender_id)
+    .bind(reply_to_id)
+    .bind(capability_hash)
+    .fetch_one(pool)
+    .await?;
+    Ok(msg)
+}
+
+/// Update encrypted_body of a message (for editing). Only the original sender can edit.
+pub async fn update_message_body(
+    pool: &Pool,
+    message_id: Uuid,
+    sender_id: Uuid,
+    new_encrypted_body: &[u8],
+) -> AppResult<Message> {
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        UPDATE messages
+        SET encrypted_body = $1, edited_at = CURRENT_TIMESTAMP
+        WHERE id = $2 AND sender_id = $3
+        RETURNING *
+        "#,
+    )
+    .bind(new_encrypted_body)
+    .bind(message_id)
+    .bind(sender_id)
+    .fetch_optional(pool)
+    .await?;
+
+    msg.ok_or_else(|| AppError::Forbidden("Cannot edit this message".into()))
+}
+
+/// Update encrypted_body of a sealed-sender message. The caller must present
+/// the capability token whose hash was stored with the message.
+pub async fn update_sealed_message_body(
+    pool: &Pool,
+    message_id: Uuid,
+    capability_hash: &[u8],
+    new_encrypted_body: &[u8],
+) -> AppResult<Message> {
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        UPDATE messages
+        SET encrypted_body = $1, edited_at = CURRENT_TIMESTAMP
+        WHERE id = $2 AND capability_hash = $3
+        RETURNING *
+        "#,
+    )
+    .bind(new_encrypted_body)
+    .bind(message_id)
+    .bind(capability_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    msg.ok_or_else(|| AppError::Forbidden("Cannot edit this message".into()))
+}
+
+/// Clean up child rows that previously relied on FK CASCADE from messages.
+/// Must be called before deleting messages (partitioned tables can't have FK refs).
+async fn cleanup_message_children(pool: &Pool, message_id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM attachments WHERE message_id = $1")
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM reactions WHERE message_id = $1")
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM pinned_messages WHERE message_id = $1")
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    sqlx::query("DELETE FROM reports WHERE message_id = $1")
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Clean up child rows for all messages in a channel.
+/// Used when deleting a channel (bulk message delete).
+async fn cleanup_channel_message_children(pool: &Pool, channel_id: Uuid) -> AppResult<()> {
+    sqlx::query(
+        "DELETE FROM attachments WHERE message_id IN (SELECT id FROM messages WHERE channel_id = $1)"
+    )
+    .bind(channel_id)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "DELETE FROM reactions WHERE message_id IN (SELECT id FROM messages WHERE channel_id = $1)"
+    )
+    .bind(channel_id)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "DELETE FROM pinned_messages WHERE channel_id = $1"
+    )
+    .bind(channel_id)
+    .execute(pool)
+    .await?;
+    sqlx::query(
+        "DELETE FROM reports WHERE channel_id = $1"
+    )
+    .bind(channel_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Delete a message. Only the original sender can delete.
+/// Returns the deleted message (for getting channel_id).
+pub async fn delete_message(
+    pool: &Pool,
+    message_id: Uuid,
+    sender_id: Uuid,
+) -> AppResult<Message> {
+    // Clean up child rows (no FK cascade on partitioned table)
+    cleanup_message_children(pool, message_id).await?;
+
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        DELETE FROM messages
+        WHERE id = $1 AND sender_id = $2
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .bind(sender_id)
+    .fetch_optional(pool)
+    .await?;
+
+    msg.ok_or_else(|| AppError::Forbidden("Cannot delete this message".into()))
+}
+
+/// Delete a sealed-sender message by its capability hash.
+pub async fn delete_sealed_message(
+    pool: &Pool,
+    message_id: Uuid,
+    capability_hash: &[u8],
+) -> AppResult<Message> {
+    let authorized: Option<(Uuid,)> =
+        sqlx::query_as("SELECT id FROM messages WHERE id = $1 AND capability_hash = $2")
+            .bind(message_id)
+            .bind(capability_hash)
+            .fetch_optional(pool)
+            .await?;
+    if authorized.is_none() {
+        return Err(AppError::Forbidden("Cannot delete this message".into()));
+    }
+
+    // Clean up child rows (no FK cascade on partitioned table)
+    cleanup_message_children(pool, message_id).await?;
+
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        DELETE FROM messages
+        WHERE id = $1 AND capability_hash = $2
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .bind(capability_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    msg.ok_or_else(|| AppError::Forbidden("Cannot delete this message".into()))
+}
+
+/// Delete a message by ID (admin/owner — no sender check).
+pub async fn delete_message_admin(
+    pool: &Pool,
+    message_id: Uuid,
+) -> AppResult<Message> {
+    // Clean up child rows (no FK cascade on partitioned table)
+    cleanup_message_children(pool, message_id).await?;
+
+    let msg = sqlx::query_as::<_, Message>(
+        r#"
+        DELETE FROM messages
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await?;
+
+    msg.ok_or_else(|| AppError::NotFound("Message not found".into()))
+}
+
+pub async fn get_channel_messages(
+    pool: &Pool,
+    channel_id: Uuid,
+    before: Option<DateTime<Utc>>,
+    limit: i64,
+) -> AppResult<Vec<Message>> {
+    let messages = if let Some(before_ts) = before {
+        sqlx::query_as::<_, Message>(
+            r#"
+            SELECT * FROM messages
+            WHERE channel_id = $1 AND timestamp < $2
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            ORDER BY timestamp DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(channel_id)
+        .bind(before_ts)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+    } else {
+        sqlx::query_as::<_, Message>(
+            r#"
+            SELECT * FROM messages
+            WHERE channel_id = $1
+              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            ORDER BY timestamp DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(channel_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await?
+    };
+    Ok(messages)
+}
+
+/// Purge expired messages (called by background worker).
+/// Cleans up child rows first since FK cascades were removed for partitioning.
+pub async fn purge_expired_messages(pool: &Pool) -> AppResult<u64> {
+    let expired_condition = "message_id IN (SELECT id FROM messages WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP)";
+    sqlx::query(&format!("DELETE FROM attachments WHERE {}", expired_condition))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!("DELETE FROM reactions WHERE {}", expired_condition))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!("DELETE FROM pinned_messages WHERE {}", expired_condition))
+        .execute(pool)
+        .await?;
+    sqlx::query(&format!("DELETE FROM reports WHERE {}", expired_condition))
+        .execute(pool)
+        .await?;
+    let result = sqlx::query("DELETE FROM messages WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Ensure monthly message partitions exist for the next 3 months.
+/// Called by a daily background worker. PostgreSQL only — SQLite doesn't support partitioning.
+#[cfg(feature = "postgres")]
+pub async fn ensure_future_partitions(pool: &Pool) -> AppResult<()> {
+    use chrono::Datelike;
+
+    let now = Utc::now();
+    for month_offset in 0..3 {
+        let target = now
+            .checked_add_months(chrono::Months::new(month_offset))
+            .unwrap_or(now);
+        let name = format!("messages_y{}m{:02}", target.year(), target.month());
+        let start = format!("{}-{:02}-01", target.year(), target.month());
+        let next = target
+            .checked_add_months(chrono::Months::new(1))
+            .unwrap_or(target);
+        let end = format!("{}-{:02}-01", next.year(), next.month());
+
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} PARTITION OF messages FOR VALUES FROM ('{}') TO ('{}')",
+            name, start, end
+        );
+        // Ignore errors — partition may already exist or overlap with default
+        if let Err(e) = sqlx::query(&sql).execute(pool).await {
+            tracing::debug!("Partition {} already exists or overlaps: {}", name, e);
+        }
+    }
+    Ok(())
+}
+
+/// No-op for SQLite — partitioning is not supported or needed.
+#[cfg(feature = "sqlite")]
+pub async fn ensure_future_partitions(_pool: &Pool) -> AppResult<()> {
+    Ok(())
+}
+
+// ─── Data Retention Purge ──────────────────────────────
+
+/// Delete audit log entries older than `retention_days` days.
+/// Called by a daily background worker when audit_log_retention_days > 0.
+pub async fn purge_old_audit_logs(pool: &Pool, retention_days: u32) -> AppResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM audit_log WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+    )
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete security events older than `retention_days` days.
+/// Called by a daily background worker when security_event_retention_days > 0.
+pub async fn purge_old_security_events(pool: &Pool, retention_days: u32) -> AppResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM security_events WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+    )
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete key backup access log entries older than `retention_days` days.
+/// Runs alongside the audit log purge.
+pub async fn purge_old_key_backup_access_logs(pool: &Pool, retention_days: u32) -> AppResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM key_backup_access_log WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+    )
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete resolved/dismissed reports older than `retention_days` days.
+/// Pending reports are never auto-deleted.
+pub async fn purge_old_resolved_reports(pool: &Pool, retention_days: u32) -> AppResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM reports WHERE status != 'pending' AND created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+    )
+    .bind(retention_days as i32)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete invites that have passed their expiration time.
+/// Invites with no expiry (expires_at IS NULL) are never deleted.
+pub async fn purge_expired_invites(pool: &Pool) -> AppResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM invites WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP"
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// ─── Attachments ───────────────────────────────────────
+
+/// Link an attachment (uploaded via presigned URL) to a message.
+pub async fn link_attachment(
+    pool: &Pool,
+    attachment_id: Uuid,
+    message_id: Uuid,
+    storage_key: &str,
+) -> AppResult<Attachment> {
+    let att = sqlx::query_as::<_, Attachment>(
+        r#"
+        INSERT INTO attachments (id, message_id, storage_key, encrypted_meta, size_bucket, created_at)
+        VALUES ($1, $2, $3, $4, 0, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(attachment_id)
+    .bind(message_id)
+    .bind(storage_key)
+    .bind(&[] as &[u8])
+    .fetch_one(pool)
+    .await?;
+    Ok(att)
+}
+
+pub async fn find_attachment_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Attachment>> {
+    let att = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(att)
+}
+
+pub async fn insert_attachment(
+    pool: &Pool,
+    message_id: Uuid,
+    storage_key: &str,
+    encrypted_meta: &[u8],
+    size_bucket: i32,
+) -> AppResult<Attachment> {
+    let att = sqlx::query_as::<_, Attachment>(
+        r#"
+        INSERT INTO attachments (id, message_id, storage_key, encrypted_meta, size_bucket, created_at)
+        VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(message_id)
+    .bind(storage_key)
+    .bind(encrypted_meta)
+    .bind(size_bucket)
+    .fetch_one(pool)
+    .await?;
+    Ok(att)
+}
+
+// ─── Invites ──────────────────────────────────────────
+
+pub async fn create_invite(
+    pool: &Pool,
+    server_id: Uuid,
+    created_by: Uuid,
+    code: &str,
+    max_uses: Option<i32>,
+    expires_at: Option<DateTime<Utc>>,
+) -> AppResult<Invite> {
+    let invite = sqlx::query_as::<_, Invite>(
+        r#"
+        INSERT INTO invites (id, server_id, created_by, code, max_uses, use_count, expires_at, created_at)
+        VALUES ($1, $2, $3, $4, $5, 0, $6, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(server_id)
+    .bind(created_by)
+    .bind(code)
+    .bind(max_uses)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+    Ok(invite)
+}
+
+pub async fn find_invite_by_code(pool: &Pool, code: &str) -> AppResult<Option<Invite>> {
+    let invite = sqlx::query_as::<_, Invite>("SELECT * FROM invites WHERE code = $1")
+        .bind(code)
+        .fetch_optional(pool)
+        .await?;
+    Ok(invite)
+}
+
+pub async fn get_server_invites(pool: &Pool, server_id: Uuid, limit: i64, offset: i64) -> AppResult<Vec<Invite>> {
+    let invites = sqlx::query_as::<_, Invite>(
+        "SELECT * FROM invites WHERE server_id = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+    )
+    .bind(server_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    Ok(invites)
+}
+
+pub async fn increment_invite_uses(pool: &Pool, invite_id: Uuid) -> AppResult<()> {
+    sqlx::query("UPDATE invites SET use_count = use_count + 1 WHERE id = $1")
+        .bind(invite_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_invite(pool: &Pool, invite_id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM invites WHERE id = $1")
+        .bind(invite_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Reactions ────────────────────────────────────────
+
+/// Add a reaction. Returns the reaction (upsert — ignores if already exists).
+pub async fn add_reaction(
+    pool: &Pool,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+    sender_token: Option<&str>,
+) -> AppResult<Reaction> {
+    let reaction = sqlx::query_as::<_, Reaction>(
+        r#"
+        INSERT INTO reactions (message_id, user_id, emoji, sender_token)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (message_id, user_id, emoji) DO UPDATE SET created_at = reactions.created_at
+        RETURNING *
+        "#,
+    )
+    .bind(message_id)
+    .bind(user_id)
+    .bind(emoji)
+    .bind(sender_token)
+    .fetch_one(pool)
+    .await?;
+    Ok(reaction)
+}
+
+/// Remove a reaction. Returns true if a row was deleted.
+pub async fn remove_reaction(
+    pool: &Pool,
+    message_id: Uuid,
+    user_id: Uuid,
+    emoji: &str,
+) -> AppResult<bool> {
+    let result = sqlx::query(
+        "DELETE FROM reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+    )
+    .bind(message_id)
+    .bind(user_id)
+    .bind(emoji)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get all reactions for a set of message IDs, grouped by emoji.
+pub async fn get_reactions_for_messages(
+    pool: &Pool,
+    message_ids: &[Uuid],
+) -> AppResult<Vec<Reaction>> {
+    if message_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // PostgreSQL supports ANY($1) with array binding; SQLite needs dynamic IN clause.
+    #[cfg(feature = "postgres")]
+    let reactions = sqlx::query_as::<_, Reaction>(
+        "SELECT * FROM reactions WHERE message_id = ANY($1) ORDER BY created_at ASC",
+    )
+    .bind(message_ids)
+    .fetch_all(pool)
+    .await?;
+
+    #[cfg(feature = "sqlite")]
+    let reactions = {
+        let placeholders: Vec<String> = (1..=message_ids.len()).map(|i| format!("${}", i)).collect();
+        let sql = format!(
+            "SELECT * FROM reactions WHERE message_id IN ({}) ORDER BY created_at ASC",
+            placeholders.join(", ")
+        );
+        let mut query = sqlx::query_as::<_, Reaction>(&sql);
+        for id in message_ids {
+            query = query.bind(id);
+        }
+        query.fetch_all(pool).await?
+    };
+
+    Ok(reactions)
+}
+
+/// Get all reactions for a specific message (for resolving who reacted).
+pub async fn get_reactions_for_message(
+    pool: &Pool,
+    message_id: Uuid,
+) -> AppResult<Vec<Reaction>> {
+    let reactions = sqlx::query_as::<_, Reaction>(
+        "SELECT * FROM reactions WHERE message_id = $1 ORDER BY created_at ASC",
+    )
+    .bind(message_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(reactions)
+}
+
+// ─── Sender Key Distributions ─────────────────────────
+
+/// Store a batch of encrypted SKDMs for a channel.
+pub async fn insert_sender_key_distributions(
+    pool: &Pool,
+    channel_id: Uuid,
+    from_user_id: Uuid,
+    distributions: &[(Uuid, Uuid, Uuid, Vec<u8>)], // (to_user_id, to_device_id, distribution_id, encrypted_skdm)
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+
+    for (to_user_id, to_device_id, distribution_id, encrypted_skdm) in distributions {
+        sqlx::query(
             r#"
-            SELECT * FROM messages
-            WHERE channel_id = $1
-              AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
-            ORDER BY timestamp DESC
-            LIMIT $2
+            INSERT INTO sender_key_distributions
+                (id, channel_id, from_user_id, to_user_id, to_device_id, distribution_id,
+                 encrypted_skdm, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP)
+            ON CONFLICT (channel_id, from_user_id, to_device_id, distribution_id)
+            DO UPDATE SET encrypted_skdm = EXCLUDED.encrypted_skdm, created_at = CURRENT_TIMESTAMP
             "#,
         )
+        .bind(Uuid::new_v4())
         .bind(channel_id)
-        .bind(limit)
-        .fetch_all(pool)
-        .await?
-    };
-    Ok(messages)
+        .bind(from_user_id)
+        .bind(to_user_id)
+        .bind(to_device_id)
+        .bind(distribution_id)
+        .bind(encrypted_skdm)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
 }
 
-/// Purge expired messages (called by background worker).
-/// Cleans up child rows first since FK cascades were removed for partitioning.
-pub async fn purge_expired_messages(pool: &Pool) -> AppResult<u64> {
-    let expired_condition = "message_id IN (SELECT id FROM messages WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP)";
-    sqlx::query(&format!("DELETE FROM attachments WHERE {}", expired_condition))
-        .execute(pool)
-        .await?;
-    sqlx::query(&format!("DELETE FROM reactions WHERE {}", expired_condition))
-        .execute(pool)
-        .await?;
-    sqlx::query(&format!("DELETE FROM pinned_messages WHERE {}", expired_condition))
-        .execute(pool)
-        .await?;
-    sqlx::query(&format!("DELETE FROM reports WHERE {}", expired_condition))
+/// Fetch all pending SKDMs for a device in a specific channel.
+pub async fn get_sender_key_distributions(
+    pool: &Pool,
+    channel_id: Uuid,
+    to_device_id: Uuid,
+) -> AppResult<Vec<SenderKeyDistribution>> {
+    let rows = sqlx::query_as::<_, SenderKeyDistribution>(
+        r#"
+        SELECT * FROM sender_key_distributions
+        WHERE channel_id = $1 AND to_device_id = $2
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(channel_id)
+    .bind(to_device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Delete all SKDMs targeting a device (used when its identity key changes).
+pub async fn clear_sender_key_distributions_for_device(
+    pool: &Pool,
+    device_id: Uuid,
+) -> AppResult<()> {
+    sqlx::query("DELETE FROM sender_key_distributions WHERE to_device_id = $1")
+        .bind(device_id)
         .execute(pool)
         .await?;
-    let result = sqlx::query("DELETE FROM messages WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP")
+    Ok(())
+}
+
+/// Delete all SKDMs addressed to a user in a channel (used when they lose access to it).
+pub async fn delete_sender_key_distributions_to_user(
+    pool: &Pool,
+    channel_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<()> {
+    sqlx::query("DELETE FROM sender_key_distributions WHERE channel_id = $1 AND to_user_id = $2")
+        .bind(channel_id)
+        .bind(user_id)
         .execute(pool)
         .await?;
-    Ok(result.rows_affected())
+    Ok(())
 }
 
-/// Ensure monthly message partitions exist for the next 3 months.
-/// Called by a daily background worker. PostgreSQL only — SQLite doesn't support partitioning.
-#[cfg(feature = "postgres")]
-pub async fn ensure_future_partitions(pool: &Pool) -> AppResult<()> {
-    use chrono::Datelike;
+/// Delete consumed SKDMs (after client has fetched them).
+pub async fn delete_sender_key_distributions(
+    pool: &Pool,
+    ids: &[Uuid],
+) -> AppResult<()> {
+    if ids.is_empty() {
+        return Ok(());
+    }
 
-    let now = Utc::now();
-    for month_offset in 0..3 {
-        let target = now
-            .checked_add_months(chrono::Months::new(month_offset))
-            .unwrap_or(now);
-        let name = format!("messages_y{}m{:02}", target.year(), target.month());
-        let start = format!("{}-{:02}-01", target.year(), target.month());
-        let next = target
-            .checked_add_months(chrono::Months::new(1))
-            .unwrap_or(target);
-        let end = format!("{}-{:02}-01", next.year(), next.month());
+    #[cfg(feature = "postgres")]
+    {
+        sqlx::query("DELETE FROM sender_key_distributions WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(pool)
+            .await?;
+    }
 
+    #[cfg(feature = "sqlite")]
+    {
+        let placeholders: Vec<String> = (1..=ids.len()).map(|i| format!("${}", i)).collect();
         let sql = format!(
-            "CREATE TABLE IF NOT EXISTS {} PARTITION OF messages FOR VALUES FROM ('{}') TO ('{}')",
-            name, start, end
+            "DELETE FROM sender_key_distributions WHERE id IN ({})",
+            placeholders.join(", ")
         );
-        // Ignore errors — partition may already exist or overlap with default
-        if let Err(e) = sqlx::query(&sql).execute(pool).await {
-            tracing::debug!("Partition {} already exists or overlaps: {}", name, e);
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id);
         }
+        query.execute(pool).await?;
     }
-    Ok(())
-}
 
-/// No-op for SQLite — partitioning is not supported or needed.
-#[cfg(feature = "sqlite")]
-pub async fn ensure_future_partitions(_pool: &Pool) -> AppResult<()> {
     Ok(())
 }
 
-// ─── Data Retention Purge ──────────────────────────────
-
-/// Delete audit log entries older than `retention_days` days.
-/// Called by a daily background worker when audit_log_retention_days > 0.
-pub async fn purge_old_audit_logs(pool: &Pool, retention_days: u32) -> AppResult<u64> {
-    let result = sqlx::query(
-        "DELETE FROM audit_log WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+/// Get all channel member identity keys (for SKDM encryption).
+/// Returns (user_id, identity_key) pairs for all members except the requester.
+/// For server channels, includes all server members (not just channel_members).
+pub async fn get_channel_member_identity_keys(
+    pool: &Pool,
+    channel_id: Uuid,
+    exclude_user_id: Uuid,
+) -> AppResult<Vec<(Uuid, Vec<u8>)>> {
+    let rows: Vec<(Uuid, Vec<u8>)> = sqlx::query_as(
+        r#"
+        SELECT DISTINCT u.id, u.identity_key FROM (
+            SELECT cm.user_id FROM channel_members cm WHERE cm.channel_id = $1
+            UNION
+            SELECT sm.user_id FROM server_members sm
+            JOIN channels c ON c.server_id = sm.server_id
+            WHERE c.id = $1 AND c.server_id IS NOT NULL
+        ) members
+        JOIN users u ON u.id = members.user_id
+        WHERE u.id != $2
+        "#,
     )
-    .bind(retention_days as i32)
-    .execute(pool)
+    .bind(channel_id)
+    .bind(exclude_user_id)
+    .fetch_all(pool)
     .await?;
-    Ok(result.rows_affected())
+    Ok(rows)
 }
 
-/// Delete resolved/dismissed reports older than `retention_days` days.
-/// Pending reports are never auto-deleted.
-pub async fn purge_old_resolved_reports(pool: &Pool, retention_days: u32) -> AppResult<u64> {
-    let result = sqlx::query(
-        "DELETE FROM reports WHERE status != 'pending' AND created_at < CURRENT_TIMESTAMP - make_interval(days => $1)"
+/// Device identity keys of all channel members except the requester, as
+/// (user_id, device_id, identity_key), primary device first per user.
+pub async fn get_channel_member_device_keys(
+    pool: &Pool,
+    channel_id: Uuid,
+    exclude_user_id: Uuid,
+) -> AppResult<Vec<(Uuid, Uuid, Vec<u8>)>> {
+    let rows: Vec<(Uuid, Uuid, Vec<u8>)> = sqlx::query_as(
+        r#"
+        SELECT d.user_id, d.id, d.identity_key FROM (
+            SELECT cm.user_id FROM channel_members cm WHERE cm.channel_id = $1
+            UNION
+            SELECT sm.user_id FROM server_members sm
+            JOIN channels c ON c.server_id = sm.server_id
+            WHERE c.id = $1 AND c.server_id IS NOT NULL
+        ) members
+        JOIN devices d ON d.user_id = members.user_id
+        WHERE d.user_id != $2
+        ORDER BY d.user_id, d.created_at ASC, d.id ASC
+        "#,
     )
-    .bind(retention_days as i32)
-    .execute(pool)
+    .bind(channel_id)
+    .bind(exclude_user_id)
+    .fetch_all(pool)
     .await?;
-    Ok(result.rows_affected())
+    Ok(rows)
 }
 
-/// Delete invites that have passed their expiration time.
-/// Invites with no expiry (expires_at IS NULL) are never deleted.
-pub async fn purge_expired_invites(pool: &Pool) -> AppResult<u64> {
-    let result = sqlx::query(
-        "DELETE FROM invites WHERE expires_at IS NOT NULL AND expires_at < CURRENT_TIMESTAMP"
+// ─── MLS ──────────────────────────────────────────────
+
+/// Store a batch of KeyPackages for a device.
+pub async fn insert_mls_key_packages(
+    pool: &Pool,
+    user_id: Uuid,
+    device_id: Uuid,
+    key_packages: &[Vec<u8>],
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    for key_package in key_packages {
+        sqlx::query(
+            r#"
+            INSERT INTO mls_key_packages (id, user_id, device_id, key_package, created_at)
+            VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(device_id)
+        .bind(key_package)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn count_mls_key_packages(pool: &Pool, device_id: Uuid) -> AppResult<i64> {
+    let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM mls_key_packages WHERE device_id = $1")
+        .bind(device_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.0)
+}
+
+/// Atomically take a device's oldest KeyPackage (each may be used only once).
+pub async fn claim_mls_key_package(pool: &Pool, device_id: Uuid) -> AppResult<Option<Vec<u8>>> {
+    let key_package: Option<Vec<u8>> = sqlx::query_scalar(
+        r#"
+        DELETE FROM mls_key_packages
+        WHERE id = (
+            SELECT id FROM mls_key_packages
+            WHERE device_id = $1
+            ORDER BY created_at ASC
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING key_package
+        "#,
     )
-    .execute(pool)
+    .bind(device_id)
+    .fetch_optional(pool)
     .await?;
-    Ok(result.rows_affected())
+    Ok(key_package)
 }
 
-// ─── Attachments ───────────────────────────────────────
-
-/// Link an attachment (uploaded via presigned URL) to a message.
-pub async fn link_attachment(
+/// Put a channel in MLS mode at epoch 0. Returns None if it already is.
+pub async fn create_mls_group(
     pool: &Pool,
-    attachment_id: Uuid,
-    message_id: Uuid,
-    storage_key: &str,
-) -> AppResult<Attachment> {
-    let att = sqlx::query_as::<_, Attachment>(
-        r#"
-        INSERT INTO attachments (id, message_id, storage_key, encrypted_meta, size_bucket, created_at)
-        VALUES ($1, $2, $3, $4, 0, CURRENT_TIMESTAMP)
+    channel_id: Uuid,
+    created_by: Uuid,
+) -> AppResult<Option<MlsGroup>> {
+    let group = sqlx::query_as::<_, MlsGroup>(
+        r#"
+        INSERT INTO mls_groups (channel_id, epoch, next_seq, created_by, created_at, updated_at)
+        VALUES ($1, 0, 0, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        ON CONFLICT (channel_id) DO NOTHING
         RETURNING *
         "#,
     )
-    .bind(attachment_id)
-    .bind(message_id)
-    .bind(storage_key)
-    .bind(&[] as &[u8])
-    .fetch_one(pool)
+    .bind(channel_id)
+    .bind(created_by)
+    .fetch_optional(pool)
     .await?;
-    Ok(att)
+    Ok(group)
 }
 
-pub async fn find_attachment_by_id(pool: &Pool, id: Uuid) -> AppResult<Option<Attachment>> {
-    let att = sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE id = $1")
-        .bind(id)
+pub async fn find_mls_group(pool: &Pool, channel_id: Uuid) -> AppResult<Option<MlsGroup>> {
+    let group = sqlx::query_as::<_, MlsGroup>("SELECT * FROM mls_groups WHERE channel_id = $1")
+        .bind(channel_id)
         .fetch_optional(pool)
         .await?;
-    Ok(att)
+    Ok(group)
 }
 
-pub async fn insert_attachment(
+/// Append a handshake message to a channel's MLS stream. `epoch` must be the
+/// group's current epoch; an accepted commit advances it. Concurrent commits
+/// serialize on the group row, so only the first for an epoch wins and the
+/// rest get a Conflict. A commit's Welcome is stored for each
+/// (to_user_id, to_device_id) in `welcome_to`.
+#[allow(clippy::too_many_arguments)]
+pub async fn append_mls_handshake(
     pool: &Pool,
-    message_id: Uuid,
-    storage_key: &str,
-    encrypted_meta: &[u8],
-    size_bucket: i32,
-) -> AppResult<Attachment> {
-    let att = sqlx::query_as::<_, Attachment>(
+    channel_id: Uuid,
+    sender_user_id: Uuid,
+    sender_device_id: Uuid,
+    epoch: i64,
+    kind: &str,
+    message: &[u8],
+    welcome: Option<NewMlsWelcome<'_>>,
+) -> AppResult<MlsHandshakeMessage> {
+    let mut tx = pool.begin().await?;
+    let group = sqlx::query_as::<_, MlsGroup>(
+        "SELECT * FROM mls_groups WHERE channel_id = $1 FOR UPDATE",
+    )
+    .bind(channel_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound("Channel is not an MLS group".into()))?;
+
+    if group.epoch != epoch {
+        return Err(AppError::Conflict(format!(
+            "Stale epoch {}: group is at epoch {}",
+            epoch, group.epoch
+        )));
+    }
+    let next_epoch = if kind == "commit" { epoch + 1 } else { epoch };
+
+    sqlx::query(
         r#"
-        INSERT INTO attachments (id, message_id, storage_key, encrypted_meta, size_bucket, created_at)
-        VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP)
+        UPDATE mls_groups SET epoch = $2, next_seq = next_seq + 1, updated_at = CURRENT_TIMESTAMP
+        WHERE channel_id = $1
+        "#,
+    )
+    .bind(channel_id)
+    .bind(next_epoch)
+    .execute(&mut *tx)
+    .await?;
+
+    let handshake = sqlx::query_as::<_, MlsHandshakeMessage>(
+        r#"
+        INSERT INTO mls_handshake_messages
+            (channel_id, seq, epoch, kind, sender_user_id, sender_device_id, message, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP)
         RETURNING *
         "#,
     )
-    .bind(Uuid::new_v4())
-    .bind(message_id)
-    .bind(storage_key)
-    .bind(encrypted_meta)
-    .bind(size_bucket)
-    .fetch_one(pool)
+    .bind(channel_id)
+    .bind(group.next_seq)
+    .bind(epoch)
+    .bind(kind)
+    .bind(sender_user_id)
+    .bind(sender_device_id)
+    .bind(message)
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(att)
-}
 
-// ─── Invites ──────────────────────────────────────────
+    if let Some(NewMlsWelcome { welcome, recipients }) = welcome {
+        for (to_user_id, to_device_id) in recipients {
+            sqlx::query(
+                r#"
+                INSERT INTO mls_welcomes
+                    (id, channel_id, to_user_id, to_device_id, epoch, welcome, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(channel_id)
+            .bind(to_user_id)
+            .bind(to_device_id)
+            .bind(next_epoch)
+            .bind(welcome)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
 
-pub async fn create_invite(
+    tx.commit().await?;
+    Ok(handshake)
+}
+
+/// A channel's handshake messages after sequence number `after`, in order.
+pub async fn get_mls_handshakes(
     pool: &Pool,
-    server_id: Uuid,
-    created_by: Uuid,
-    code: &str,
-    max_uses: Option<i32>,
-    expires_at: Option<DateTime<Utc>>,
-) -> AppResult<Invite> {
-    let invite = sqlx::query_as::<_, Invite>(
+    channel_id: Uuid,
+    after: i64,
+    limit: i64,
+) -> AppResult<Vec<MlsHandshakeMessage>> {
+    let rows = sqlx::query_as::<_, MlsHandshakeMessage>(
         r#"
-        INSERT INTO invites (id, server_id, created_by, code, max_uses, use_count, expires_at, created_at)
-        VALUES ($1, $2, $3, $4, $5, 0, $6, CURRENT_TIMESTAMP)
-        RETURNING *
+        SELECT * FROM mls_handshake_messages
+        WHERE channel_id = $1 AND seq > $2
+        ORDER BY seq ASC
+        LIMIT $3
         "#,
     )
-    .bind(Uuid::new_v4())
-    .bind(server_id)
-    .bind(created_by)
-    .bind(code)
-    .bind(max_uses)
-    .bind(expires_at)
-    .fetch_one(pool)
+    .bind(channel_id)
+    .bind(after)
+    .bind(limit)
+    .fetch_all(pool)
     .await?;
-    Ok(invite)
+    Ok(rows)
 }
 
-pub async fn find_invite_by_code(pool: &Pool, code: &str) -> AppResult<Option<Invite>> {
-    let invite = sqlx::query_as::<_, Invite>("SELECT * FROM invites WHERE code = $1")
-        .bind(code)
-        .fetch_optional(pool)
+/// Pending Welcomes for a device, oldest first.
+pub async fn get_mls_welcomes(pool: &Pool, device_id: Uuid) -> AppResult<Vec<MlsWelcome>> {
+    let rows = sqlx::query_as::<_, MlsWelcome>(
+        "SELECT * FROM mls_welcomes WHERE to_device_id = $1 ORDER BY created_at ASC",
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Delete a Welcome once its recipient has processed it. Returns false if no
+/// Welcome with that ID is addressed to the user.
+pub async fn delete_mls_welcome(pool: &Pool, welcome_id: Uuid, user_id: Uuid) -> AppResult<bool> {
+    let result = sqlx::query("DELETE FROM mls_welcomes WHERE id = $1 AND to_user_id = $2")
+        .bind(welcome_id)
+        .bind(user_id)
+        .execute(pool)
         .await?;
-    Ok(invite)
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_server_invites(pool: &Pool, server_id: Uuid, limit: i64, offset: i64) -> AppResult<Vec<Invite>> {
-    let invites = sqlx::query_as::<_, Invite>(
-        "SELECT * FROM invites WHERE server_id = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+// ─── Server Members (extended) ────────────────────────
+
+pub async fn get_server_members(
+    pool: &Pool,
+    server_id: Uuid,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<ServerMemberResponse>> {
+    // Step 1: Get members (paginated)
+    #[allow(clippy::type_complexity)]
+    let rows: Vec<(Uuid, String, Option<String>, Option<String>, DateTime<Utc>, Option<String>, Option<DateTime<Utc>>)> =
+        sqlx::query_as(
+            r#"
+            SELECT sm.user_id, u.username, u.display_name, u.avatar_url, sm.joined_at, sm.nickname, sm.timed_out_until
+            FROM server_members sm
+            INNER JOIN users u ON u.id = sm.user_id
+            WHERE sm.server_id = $1
+            ORDER BY sm.joined_at ASC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(server_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+    // Step 2: Get all role assignments for this server in one query
+    let role_assignments: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        "SELECT user_id, role_id FROM member_roles WHERE server_id = $1",
     )
     .bind(server_id)
-    .bind(limit)
-    .bind(offset)
     .fetch_all(pool)
     .await?;
-    Ok(invites)
+
+    // Build a map: user_id -> Vec<role_id>
+    let mut role_map: std::collections::HashMap<Uuid, Vec<Uuid>> =
+        std::collections::HashMap::new();
+    for (uid, rid) in role_assignments {
+        role_map.entry(uid).or_default().push(rid);
+    }
+
+    Ok(rows
+        .into_iter()
+        .map(
+            |(user_id, username, display_name, avatar_url, joined_at, nickname, timed_out_until)| {
+                // Only include timed_out_until if it's still in the future
+                let active_timeout = timed_out_until.filter(|t| *t > Utc::now());
+                ServerMemberResponse {
+                    user_id,
+                    username,
+                    display_name,
+                    avatar_url,
+                    joined_at,
+                    nickname,
+                    role_ids: role_map.remove(&user_id).unwrap_or_default(),
+                    timed_out_until: active_timeout,
+                }
+            },
+        )
+        .collect())
 }
 
-pub async fn increment_invite_uses(pool: &Pool, invite_id: Uuid) -> AppResult<()> {
-    sqlx::query("UPDATE invites SET use_count = use_count + 1 WHERE id = $1")
-        .bind(invite_id)
+pub async fn get_server_member_ids(pool: &Pool, server_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> =
+        sqlx::query_as("SELECT user_id FROM server_members WHERE server_id = $1")
+            .bind(server_id)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+pub async fn remove_server_member(
+    pool: &Pool,
+    server_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<()> {
+    // Remove from all server channels
+    sqlx::query(
+        r#"
+        DELETE FROM channel_members
+        WHERE user_id = $1
+          AND channel_id IN (SELECT id FROM channels WHERE server_id = $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(server_id)
+    .execute(pool)
+    .await?;
+
+    // Remove from server
+    sqlx::query("DELETE FROM server_members WHERE server_id = $1 AND user_id = $2")
+        .bind(server_id)
+        .bind(user_id)
         .execute(pool)
         .await?;
+
     Ok(())
 }
 
-pub async fn delete_invite(pool: &Pool, invite_id: Uuid) -> AppResult<()> {
-    sqlx::query("DELETE FROM invites WHERE id = $1")
-        .bind(invite_id)
+pub async fn count_server_members(pool: &Pool, server_id: Uuid) -> AppResult<i64> {
+    let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM server_members WHERE server_id = $1")
+        .bind(server_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.0)
+}
+
+pub async fn delete_server(pool: &Pool, server_id: Uuid) -> AppResult<()> {
+    // All child tables use ON DELETE CASCADE, so this single delete
+    // removes server_members, channels (→ messages, channel_members, etc.),
+    // roles, member_roles, invites, bans, categories, etc.
+    sqlx::query("DELETE FROM servers WHERE id = $1")
+        .bind(server_id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-// ─── Reactions ────────────────────────────────────────
+// ─── Key Transparency ────────────────────────────────
 
-/// Add a reaction. Returns the reaction (upsert — ignores if already exists).
-pub async fn add_reaction(
+/// Append an identity key publication to the key transparency log. The table
+/// lock serializes appends so sequence numbers stay dense and every entry
+/// chains to the user's latest one. With `unless_logged`, nothing is appended
+/// if this device key is already in the log; the check runs under the lock,
+/// so instances backfilling at the same time log each key once. Returns the
+/// new entry, if any.
+pub async fn append_transparency_log(
     pool: &Pool,
-    message_id: Uuid,
     user_id: Uuid,
-    emoji: &str,
-    sender_token: Option<&str>,
-) -> AppResult<Reaction> {
-    let reaction = sqlx::query_as::<_, Reaction>(
+    device_id: Uuid,
+    identity_key: &[u8],
+    unless_logged: bool,
+) -> AppResult<Option<TransparencyLogEntry>> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("LOCK TABLE key_transparency_log IN EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+    if unless_logged {
+        let logged: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM key_transparency_log WHERE device_id = $1 AND identity_key = $2)",
+        )
+        .bind(device_id)
+        .bind(identity_key)
+        .fetch_one(&mut *tx)
+        .await?;
+        if logged {
+            return Ok(None);
+        }
+    }
+    let seq: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq) + 1, 0) FROM key_transparency_log")
+        .fetch_one(&mut *tx)
+        .await?;
+    let prev_hash: Option<Vec<u8>> = sqlx::query_scalar(
+        "SELECT leaf_hash FROM key_transparency_log WHERE user_id = $1 ORDER BY seq DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // Stored with microsecond precision; hash what will be read back
+    let now = Utc::now();
+    let published_at = DateTime::from_timestamp_micros(now.timestamp_micros()).unwrap_or(now);
+    let leaf_hash = crate::transparency::leaf_hash(
+        seq,
+        user_id,
+        device_id,
+        published_at,
+        prev_hash.as_deref(),
+        identity_key,
+    );
+
+    let entry = sqlx::query_as::<_, TransparencyLogEntry>(
         r#"
-        INSERT INTO reactions (message_id, user_id, emoji, sender_token)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (message_id, user_id, emoji) DO UPDATE SET created_at = reactions.created_at
+        INSERT INTO key_transparency_log (seq, user_id, device_id, identity_key, prev_hash,
+                                          leaf_hash, published_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
         RETURNING *
         "#,
     )
-    .bind(message_id)
+    .bind(seq)
     .bind(user_id)
-    .bind(emoji)
-    .bind(sender_token)
-    .fetch_one(pool)
+    .bind(device_id)
+    .bind(identity_key)
+    .bind(prev_hash)
+    .bind(leaf_hash.as_slice())
+    .bind(published_at)
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(reaction)
+
+    // Store the leaf and the subtrees it completes
+    let siblings = crate::transparency::left_siblings(seq as u64);
+    let (levels, indexes) = transparency_node_keys(&siblings);
+    let stored: Vec<(i16, i64, Vec<u8>)> = sqlx::query_as(TRANSPARENCY_NODES_QUERY)
+        .bind(levels)
+        .bind(indexes)
+        .fetch_all(&mut *tx)
+        .await?;
+    let stored = transparency_node_map(stored)?;
+    let sibling_hashes = siblings
+        .iter()
+        .map(|id| stored.get(id).copied())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Key transparency tree is missing nodes")))?;
+    for (id, hash) in crate::transparency::completed_nodes(seq as u64, leaf_hash, &sibling_hashes) {
+        sqlx::query("INSERT INTO key_transparency_nodes (level, idx, hash) VALUES ($1, $2, $3)")
+            .bind(id.level as i16)
+            .bind(id.index as i64)
+            .bind(hash.as_slice())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some(entry))
+}
+
+/// Stored key transparency tree nodes by id.
+pub type TransparencyNodes = std::collections::HashMap<crate::transparency::NodeId, crate::transparency::Hash>;
+
+const TRANSPARENCY_NODES_QUERY: &str = r#"
+    SELECT n.level, n.idx, n.hash FROM key_transparency_nodes n
+    JOIN UNNEST($1::SMALLINT[], $2::BIGINT[]) AS w(level, idx) ON n.level = w.level AND n.idx = w.idx
+"#;
+
+fn transparency_node_keys(ids: &[crate::transparency::NodeId]) -> (Vec<i16>, Vec<i64>) {
+    ids.iter().map(|id| (id.level as i16, id.index as i64)).unzip()
+}
+
+fn transparency_node_map(rows: Vec<(i16, i64, Vec<u8>)>) -> AppResult<TransparencyNodes> {
+    rows.into_iter()
+        .map(|(level, idx, hash)| {
+            let hash = crate::transparency::Hash::try_from(hash.as_slice())
+                .map_err(|_| AppError::Internal(anyhow::anyhow!("Corrupt key transparency node hash")))?;
+            Ok((crate::transparency::NodeId { level: level as u32, index: idx as u64 }, hash))
+        })
+        .collect()
 }
 
-/// Remove a reaction. Returns true if a row was deleted.
-pub async fn remove_reaction(
+/// Number of entries in the log.
+pub async fn get_transparency_tree_size(pool: &Pool) -> AppResult<i64> {
+    let size: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq) + 1, 0) FROM key_transparency_log")
+        .fetch_one(pool)
+        .await?;
+    Ok(size)
+}
+
+/// Stored Merkle tree nodes by id. Nodes that don't exist yet are left out.
+pub async fn get_transparency_nodes(
+    pool: &Pool,
+    ids: &[crate::transparency::NodeId],
+) -> AppResult<TransparencyNodes> {
+    let (levels, indexes) = transparency_node_keys(ids);
+    let rows: Vec<(i16, i64, Vec<u8>)> = sqlx::query_as(TRANSPARENCY_NODES_QUERY)
+        .bind(levels)
+        .bind(indexes)
+        .fetch_all(pool)
+        .await?;
+    transparency_node_map(rows)
+}
+
+pub async fn get_user_transparency_entries(
     pool: &Pool,
-    message_id: Uuid,
     user_id: Uuid,
-    emoji: &str,
-) -> AppResult<bool> {
-    let result = sqlx::query(
-        "DELETE FROM reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+) -> AppResult<Vec<TransparencyLogEntry>> {
+    let entries = sqlx::query_as::<_, TransparencyLogEntry>(
+        "SELECT * FROM key_transparency_log WHERE user_id = $1 ORDER BY seq ASC",
     )
-    .bind(message_id)
     .bind(user_id)
-    .bind(emoji)
-    .execute(pool)
+    .fetch_all(pool)
     .await?;
-    Ok(result.rows_affected() > 0)
+    Ok(entries)
 }
 
-/// Get all reactions for a set of message IDs, grouped by emoji.
-pub async fn get_reactions_for_messages(
+pub async fn list_transparency_log(
     pool: &Pool,
-    message_ids: &[Uuid],
-) -> AppResult<Vec<Reaction>> {
-    if message_ids.is_empty() {
-        return Ok(Vec::new());
-    }
-
-    // PostgreSQL supports ANY($1) with array binding; SQLite needs dynamic IN clause.
-    #[cfg(feature = "postgres")]
-    let reactions = sqlx::query_as::<_, Reaction>(
-        "SELECT * FROM reactions WHERE message_id = ANY($1) ORDER BY created_at ASC",
+    after: i64,
+    limit: i64,
+) -> AppResult<Vec<TransparencyLogEntry>> {
+    let entries = sqlx::query_as::<_, TransparencyLogEntry>(
+        "SELECT * FROM key_transparency_log WHERE seq > $1 ORDER BY seq ASC LIMIT $2",
     )
-    .bind(message_ids)
+    .bind(after)
+    .bind(limit)
     .fetch_all(pool)
     .await?;
-
-    #[cfg(feature = "sqlite")]
-    let reactions = {
-        let placeholders: Vec<String> = (1..=message_ids.len()).map(|i| format!("${}", i)).collect();
-        let sql = format!(
-            "SELECT * FROM reactions WHERE message_id IN ({}) ORDER BY created_at ASC",
-            placeholders.join(", ")
-        );
-        let mut query = sqlx::query_as::<_, Reaction>(&sql);
-        for id in message_ids {
-            query = query.bind(id);
-        }
-        query.fetch_all(pool).await?
-    };
-
-    Ok(reactions)
+    Ok(entries)
 }
 
-/// Get all reactions for a specific message (for resolving who reacted).
-pub async fn get_reactions_for_message(
-    pool: &Pool,
-    message_id: Uuid,
-) -> AppResult<Vec<Reaction>> {
-    let reactions = sqlx::query_as::<_, Reaction>(
-        "SELECT * FROM reactions WHERE message_id = $1 ORDER BY created_at ASC",
+/// Devices whose current identity key has no log entry yet (keys published
+/// before the log existed), oldest first, as (user_id, device_id, identity_key).
+pub async fn list_unlogged_device_keys(pool: &Pool) -> AppResult<Vec<(Uuid, Uuid, Vec<u8>)>> {
+    let rows: Vec<(Uuid, Uuid, Vec<u8>)> = sqlx::query_as(
+        r#"
+        SELECT d.user_id, d.id, d.identity_key FROM devices d
+        WHERE NOT EXISTS (
+            SELECT 1 FROM key_transparency_log l
+            WHERE l.device_id = d.id AND l.identity_key = d.identity_key
+        )
+        ORDER BY d.created_at ASC, d.id ASC
+        "#,
     )
-    .bind(message_id)
     .fetch_all(pool)
     .await?;
-    Ok(reactions)
+    Ok(rows)
 }
 
-// ─── Sender Key Distributions ─────────────────────────
+// ─── Key Backups ─────────────────────────────────────
 
-/// Store a batch of encrypted SKDMs for a channel.
-pub async fn insert_sender_key_distributions(
+/// Store a new backup version (the user's latest version + 1) with its key
+/// slots.
+pub async fn create_key_backup_version(
     pool: &Pool,
-    channel_id: Uuid,
-    from_user_id: Uuid,
-    distributions: &[(Uuid, Uuid, Vec<u8>)], // (to_user_id, distribution_id, encrypted_skdm)
-) -> AppResult<()> {
+    user_id: Uuid,
+    encrypted_data: &[u8],
+    nonce: &[u8],
+    salt: Option<&[u8]>,
+    slots: &[NewKeyBackupSlot],
+) -> AppResult<KeyBackup> {
     let mut tx = pool.begin().await?;
-
-    for (to_user_id, distribution_id, encrypted_skdm) in distributions {
+    let backup = sqlx::query_as::<_, KeyBackup>(
+        r#"
+        INSERT INTO key_backups (id, user_id, encrypted_data, nonce, salt, version, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5,
+                (SELECT COALESCE(MAX(version), 0) + 1 FROM key_backups WHERE user_id = $2),
+                CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(encrypted_data)
+    .bind(nonce)
+    .bind(salt)
+    .fetch_one(&mut *tx)
+    .await?;
+    for slot in slots {
         sqlx::query(
             r#"
-            INSERT INTO sender_key_distributions
-                (id, channel_id, from_user_id, to_user_id, distribution_id, encrypted_skdm, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)
-            ON CONFLICT (channel_id, from_user_id, to_user_id, distribution_id)
-            DO UPDATE SET encrypted_skdm = EXCLUDED.encrypted_skdm, created_at = CURRENT_TIMESTAMP
+            INSERT INTO key_backup_slots (id, backup_id, kind, wrapped_key, nonce, salt, credential_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP)
             "#,
         )
         .bind(Uuid::new_v4())
-        .bind(channel_id)
-        .bind(from_user_id)
-        .bind(to_user_id)
-        .bind(distribution_id)
-        .bind(encrypted_skdm)
+        .bind(backup.id)
+        .bind(slot.kind.as_str())
+        .bind(&slot.wrapped_key)
+        .bind(&slot.nonce)
+        .bind(slot.salt.as_deref())
+        .bind(slot.credential_id.as_deref())
         .execute(&mut *tx)
         .await?;
     }
+    tx.commit().await?;
+    Ok(backup)
+}
 
+/// Copy `from` (slots and sessions included) into a new latest version.
+pub async fn restore_key_backup_version(pool: &Pool, from: &KeyBackup) -> AppResult<KeyBackup> {
+    let mut tx = pool.begin().await?;
+    let backup = sqlx::query_as::<_, KeyBackup>(
+        r#"
+        INSERT INTO key_backups (id, user_id, encrypted_data, nonce, salt, version, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5,
+                (SELECT COALESCE(MAX(version), 0) + 1 FROM key_backups WHERE user_id = $2),
+                CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(from.user_id)
+    .bind(&from.encrypted_data)
+    .bind(&from.nonce)
+    .bind(from.salt.as_deref())
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO key_backup_slots (id, backup_id, kind, wrapped_key, nonce, salt, credential_id, created_at)
+        SELECT gen_random_uuid(), $1, kind, wrapped_key, nonce, salt, credential_id, created_at
+        FROM key_backup_slots WHERE backup_id = $2
+        "#,
+    )
+    .bind(backup.id)
+    .bind(from.id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO key_backup_sessions (backup_id, session_id, encrypted_data, nonce, updated_at)
+        SELECT $1, session_id, encrypted_data, nonce, updated_at
+        FROM key_backup_sessions WHERE backup_id = $2
+        "#,
+    )
+    .bind(backup.id)
+    .bind(from.id)
+    .execute(&mut *tx)
+    .await?;
     tx.commit().await?;
-    Ok(())
+    Ok(backup)
 }
 
-/// Fetch all pending SKDMs for a user in a specific channel.
-pub async fn get_sender_key_distributions(
+/// The user's latest backup version.
+pub async fn get_key_backup(pool: &Pool, user_id: Uuid) -> AppResult<Option<KeyBackup>> {
+    let backup = sqlx::query_as::<_, KeyBackup>(
+        "SELECT * FROM key_backups WHERE user_id = $1 ORDER BY version DESC LIMIT 1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(backup)
+}
+
+pub async fn get_key_backup_version(
     pool: &Pool,
-    channel_id: Uuid,
-    to_user_id: Uuid,
-) -> AppResult<Vec<SenderKeyDistribution>> {
-    let rows = sqlx::query_as::<_, SenderKeyDistribution>(
+    user_id: Uuid,
+    version: i32,
+) -> AppResult<Option<KeyBackup>> {
+    let backup = sqlx::query_as::<_, KeyBackup>(
+        "SELECT * FROM key_backups WHERE user_id = $1 AND version = $2",
+    )
+    .bind(user_id)
+    .bind(version)
+    .fetch_optional(pool)
+    .await?;
+    Ok(backup)
+}
+
+/// Retained backup versions, newest first.
+pub async fn list_key_backup_versions(
+    pool: &Pool,
+    user_id: Uuid,
+) -> AppResult<Vec<KeyBackupVersionInfo>> {
+    let versions = sqlx::query_as::<_, KeyBackupVersionInfo>(
         r#"
-        SELECT * FROM sender_key_distributions
-        WHERE channel_id = $1 AND to_user_id = $2
-        ORDER BY created_at ASC
+        SELECT b.version, b.created_at, b.updated_at,
+               (SELECT COUNT(*) FROM key_backup_slots s WHERE s.backup_id = b.id) AS slot_count,
+               (SELECT COUNT(*) FROM key_backup_sessions s WHERE s.backup_id = b.id) AS session_count
+        FROM key_backups b
+        WHERE b.user_id = $1
+        ORDER BY b.version DESC
         "#,
     )
-    .bind(channel_id)
-    .bind(to_user_id)
+    .bind(user_id)
     .fetch_all(pool)
     .await?;
-    Ok(rows)
+    Ok(versions)
 }
 
-/// Delete all SKDMs targeting a user (used when their identity key changes).
-pub async fn clear_sender_key_distributions_for_user(
-    pool: &Pool,
-    user_id: Uuid,
-) -> AppResult<()> {
-    sqlx::query("DELETE FROM sender_key_distributions WHERE to_user_id = $1")
+/// Delete all but the newest `keep` versions of a user's backup.
+pub async fn prune_key_backup_versions(pool: &Pool, user_id: Uuid, keep: i64) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM key_backups
+        WHERE user_id = $1 AND id NOT IN (
+            SELECT id FROM key_backups WHERE user_id = $1 ORDER BY version DESC LIMIT $2
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(keep)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Delete every version of a user's backup.
+pub async fn delete_key_backup(pool: &Pool, user_id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM key_backups WHERE user_id = $1")
         .bind(user_id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-/// Delete consumed SKDMs (after client has fetched them).
-pub async fn delete_sender_key_distributions(
-    pool: &Pool,
-    ids: &[Uuid],
-) -> AppResult<()> {
-    if ids.is_empty() {
-        return Ok(());
-    }
-
-    #[cfg(feature = "postgres")]
-    {
-        sqlx::query("DELETE FROM sender_key_distributions WHERE id = ANY($1)")
-            .bind(ids)
-            .execute(pool)
-            .await?;
-    }
-
-    #[cfg(feature = "sqlite")]
-    {
-        let placeholders: Vec<String> = (1..=ids.len()).map(|i| format!("${}", i)).collect();
-        let sql = format!(
-            "DELETE FROM sender_key_distributions WHERE id IN ({})",
-            placeholders.join(", ")
-        );
-        let mut query = sqlx::query(&sql);
-        for id in ids {
-            query = query.bind(id);
-        }
-        query.execute(pool).await?;
-    }
-
-    Ok(())
+pub async fn get_key_backup_slots(pool: &Pool, backup_id: Uuid) -> AppResult<Vec<KeyBackupSlot>> {
+    let slots = sqlx::query_as::<_, KeyBackupSlot>(
+        "SELECT * FROM key_backup_slots WHERE backup_id = $1 ORDER BY created_at, id",
+    )
+    .bind(backup_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(slots)
 }
 
-/// Get all channel member identity keys (for SKDM encryption).
-/// Returns (user_id, identity_key) pairs for all members except the requester.
-/// For server channels, includes all server members (not just channel_members).
-pub async fn get_channel_member_identity_keys(
+pub async fn add_key_backup_slot(
     pool: &Pool,
-    channel_id: Uuid,
-    exclude_user_id: Uuid,
-) -> AppResult<Vec<(Uuid, Vec<u8>)>> {
-    let rows: Vec<(Uuid, Vec<u8>)> = sqlx::query_as(
+    backup_id: Uuid,
+    slot: &NewKeyBackupSlot,
+) -> AppResult<KeyBackupSlot> {
+    let slot = sqlx::query_as::<_, KeyBackupSlot>(
         r#"
-        SELECT DISTINCT u.id, u.identity_key FROM (
-            SELECT cm.user_id FROM channel_members cm WHERE cm.channel_id = $1
-            UNION
-            SELECT sm.user_id FROM server_members sm
-            JOIN channels c ON c.server_id = sm.server_id
-            WHERE c.id = $1 AND c.server_id IS NOT NULL
-        ) members
-        JOIN users u ON u.id = members.user_id
-        WHERE u.id != $2
+        INSERT INTO key_backup_slots (id, backup_id, kind, wrapped_key, nonce, salt, credential_id, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, CURRENT_TIMESTAMP)
+        RETURNING *
         "#,
     )
-    .bind(channel_id)
-    .bind(exclude_user_id)
-    .fetch_all(pool)
+    .bind(Uuid::new_v4())
+    .bind(backup_id)
+    .bind(slot.kind.as_str())
+    .bind(&slot.wrapped_key)
+    .bind(&slot.nonce)
+    .bind(slot.salt.as_deref())
+    .bind(slot.credential_id.as_deref())
+    .fetch_one(pool)
     .await?;
-    Ok(rows)
+    Ok(slot)
 }
 
-// ─── Server Members (extended) ────────────────────────
-
-pub async fn get_server_members(
-    pool: &Pool,
-    server_id: Uuid,
-    limit: i64,
-    offset: i64,
-) -> AppResult<Vec<ServerMemberResponse>> {
-    // Step 1: Get members (paginated)
-    #[allow(clippy::type_complexity)]
-    let rows: Vec<(Uuid, String, Option<String>, Option<String>, DateTime<Utc>, Option<String>, Option<DateTime<Utc>>)> =
-        sqlx::query_as(
-            r#"
-            SELECT sm.user_id, u.username, u.display_name, u.avatar_url, sm.joined_at, sm.nickname, sm.timed_out_until
-            FROM server_members sm
-            INNER JOIN users u ON u.id = sm.user_id
-            WHERE sm.server_id = $1
-            ORDER BY sm.joined_at ASC
-            LIMIT $2 OFFSET $3
-            "#,
-        )
-        .bind(server_id)
-        .bind(limit)
-        .bind(offset)
-        .fetch_all(pool)
+/// Returns true if the slot existed.
+pub async fn delete_key_backup_slot(pool: &Pool, backup_id: Uuid, slot_id: Uuid) -> AppResult<bool> {
+    let result = sqlx::query("DELETE FROM key_backup_slots WHERE backup_id = $1 AND id = $2")
+        .bind(backup_id)
+        .bind(slot_id)
+        .execute(pool)
         .await?;
+    Ok(result.rows_affected() > 0)
+}
 
-    // Step 2: Get all role assignments for this server in one query
-    let role_assignments: Vec<(Uuid, Uuid)> = sqlx::query_as(
-        "SELECT user_id, role_id FROM member_roles WHERE server_id = $1",
+/// Insert or replace one session entry of a backup version.
+pub async fn upsert_key_backup_session(
+    pool: &Pool,
+    backup_id: Uuid,
+    session_id: &str,
+    encrypted_data: &[u8],
+    nonce: &[u8],
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO key_backup_sessions (backup_id, session_id, encrypted_data, nonce, updated_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+        ON CONFLICT (backup_id, session_id) DO UPDATE SET
+            encrypted_data = EXCLUDED.encrypted_data,
+            nonce = EXCLUDED.nonce,
+            updated_at = CURRENT_TIMESTAMP
+        "#,
     )
-    .bind(server_id)
-    .fetch_all(pool)
+    .bind(backup_id)
+    .bind(session_id)
+    .bind(encrypted_data)
+    .bind(nonce)
+    .execute(pool)
     .await?;
-
-    // Build a map: user_id -> Vec<role_id>
-    let mut role_map: std::collections::HashMap<Uuid, Vec<Uuid>> =
-        std::collections::HashMap::new();
-    for (uid, rid) in role_assignments {
-        role_map.entry(uid).or_default().push(rid);
-    }
-
-    Ok(rows
-        .into_iter()
-        .map(
-            |(user_id, username, display_name, avatar_url, joined_at, nickname, timed_out_until)| {
-                // Only include timed_out_until if it's still in the future
-                let active_timeout = timed_out_until.filter(|t| *t > Utc::now());
-                ServerMemberResponse {
-                    user_id,
-                    username,
-                    display_name,
-                    avatar_url,
-                    joined_at,
-                    nickname,
-                    role_ids: role_map.remove(&user_id).unwrap_or_default(),
-                    timed_out_until: active_timeout,
-                }
-            },
-        )
-        .collect())
+    sqlx::query("UPDATE key_backups SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(backup_id)
+        .execute(pool)
+        .await?;
+    Ok(())
 }
 
-pub async fn get_server_member_ids(pool: &Pool, server_id: Uuid) -> AppResult<Vec<Uuid>> {
-    let rows: Vec<(Uuid,)> =
-        sqlx::query_as("SELECT user_id FROM server_members WHERE server_id = $1")
-            .bind(server_id)
-            .fetch_all(pool)
+pub async fn count_key_backup_sessions(pool: &Pool, backup_id: Uuid) -> AppResult<i64> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM key_backup_sessions WHERE backup_id = $1")
+            .bind(backup_id)
+            .fetch_one(pool)
             .await?;
-    Ok(rows.into_iter().map(|r| r.0).collect())
+    Ok(count)
 }
 
-pub async fn remove_server_member(
+pub async fn get_key_backup_sessions(
+    pool: &Pool,
+    backup_id: Uuid,
+) -> AppResult<Vec<KeyBackupSession>> {
+    let sessions = sqlx::query_as::<_, KeyBackupSession>(
+        "SELECT * FROM key_backup_sessions WHERE backup_id = $1 ORDER BY session_id",
+    )
+    .bind(backup_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(sessions)
+}
+
+pub async fn insert_key_backup_access(
     pool: &Pool,
-    server_id: Uuid,
     user_id: Uuid,
+    version: i32,
+    action: &str,
+    ip_address: Option<&str>,
+    device_name: Option<&str>,
 ) -> AppResult<()> {
-    // Remove from all server channels
     sqlx::query(
         r#"
-        DELETE FROM channel_members
-        WHERE user_id = $1
-          AND channel_id IN (SELECT id FROM channels WHERE server_id = $2)
+        INSERT INTO key_backup_access_log (id, user_id, version, action, ip_address, device_name, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP)
         "#,
     )
+    .bind(Uuid::new_v4())
     .bind(user_id)
-    .bind(server_id)
+    .bind(version)
+    .bind(action)
+    .bind(ip_address)
+    .bind(device_name)
     .execute(pool)
     .await?;
-
-    // Remove from server
-    sqlx::query("DELETE FROM server_members WHERE server_id = $1 AND user_id = $2")
-        .bind(server_id)
-        .bind(user_id)
-        .execute(pool)
-        .await?;
-
     Ok(())
 }
 
-pub async fn count_server_members(pool: &Pool, server_id: Uuid) -> AppResult<i64> {
-    let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM server_members WHERE server_id = $1")
-        .bind(server_id)
-        .fetch_one(pool)
-        .await?;
-    Ok(row.0)
-}
-
-pub async fn delete_server(pool: &Pool, server_id: Uuid) -> AppResult<()> {
-    // All child tables use ON DELETE CASCADE, so this single delete
-    // removes server_members, channels (→ messages, channel_members, etc.),
-    // roles, member_roles, invites, bans, categories, etc.
-    sqlx::query("DELETE FROM servers WHERE id = $1")
-        .bind(server_id)
-        .execute(pool)
-        .await?;
-    Ok(())
+pub async fn get_key_backup_access_log(
+    pool: &Pool,
+    user_id: Uuid,
+    limit: i64,
+    offset: i64,
+) -> AppResult<Vec<KeyBackupAccess>> {
+    let entries = sqlx::query_as::<_, KeyBackupAccess>(
+        r#"
+        SELECT id, version, action, ip_address, device_name, created_at
+        FROM key_backup_access_log
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    Ok(entries)
 }
 
-// ─── Key Backups ─────────────────────────────────────
+// ─── Verification States ─────────────────────────────
 
-pub async fn upsert_key_backup(
+pub async fn upsert_verification_state(
     pool: &Pool,
     user_id: Uuid,
-    encrypted_data: &[u8],
+    contact_id: Uuid,
+    encrypted_state: &[u8],
     nonce: &[u8],
-    salt: &[u8],
-    version: i32,
-) -> AppResult<KeyBackup> {
-    let backup = sqlx::query_as::<_, KeyBackup>(
+    verified_keys_hash: Option<&[u8]>,
+) -> AppResult<VerificationState> {
+    let state = sqlx::query_as::<_, VerificationState>(
         r#"
-        INSERT INTO key_backups (id, user_id, encrypted_data, nonce, salt, version, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
-        ON CONFLICT (user_id) DO UPDATE SET
-            encrypted_data = EXCLUDED.encrypted_data,
+        INSERT INTO verification_states
+            (user_id, contact_id, encrypted_state, nonce, verified_keys_hash, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)
+        ON CONFLICT (user_id, contact_id) DO UPDATE SET
+            encrypted_state = EXCLUDED.encrypted_state,
             nonce = EXCLUDED.nonce,
-            salt = EXCLUDED.salt,
-            version = EXCLUDED.version,
+            verified_keys_hash = EXCLUDED.verified_keys_hash,
             updated_at = CURRENT_TIMESTAMP
         RETURNING *
         "#,
     )
-    .bind(Uuid::new_v4())
     .bind(user_id)
-    .bind(encrypted_data)
+    .bind(contact_id)
+    .bind(encrypted_state)
     .bind(nonce)
-    .bind(salt)
-    .bind(version)
+    .bind(verified_keys_hash)
     .fetch_one(pool)
     .await?;
-    Ok(backup)
+    Ok(state)
 }
 
-pub async fn get_key_backup(pool: &Pool, user_id: Uuid) -> AppResult<Option<KeyBackup>> {
-    let backup = sqlx::query_as::<_, KeyBackup>(
-        "SELECT * FROM key_backups WHERE user_id = $1",
+pub async fn get_verification_states(pool: &Pool, user_id: Uuid) -> AppResult<Vec<VerificationState>> {
+    let states = sqlx::query_as::<_, VerificationState>(
+        "SELECT * FROM verification_states WHERE user_id = $1 ORDER BY updated_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(states)
+}
+
+/// The fingerprint of `contact_id`'s keys as `user_id` verified them, if they did.
+pub async fn get_verified_keys_hash(
+    pool: &Pool,
+    user_id: Uuid,
+    contact_id: Uuid,
+) -> AppResult<Option<Vec<u8>>> {
+    let hash: Option<Option<Vec<u8>>> = sqlx::query_scalar(
+        "SELECT verified_keys_hash FROM verification_states WHERE user_id = $1 AND contact_id = $2",
     )
     .bind(user_id)
+    .bind(contact_id)
     .fetch_optional(pool)
     .await?;
-    Ok(backup)
+    Ok(hash.flatten())
 }
 
-pub async fn delete_key_backup(pool: &Pool, user_id: Uuid) -> AppResult<()> {
-    sqlx::query("DELETE FROM key_backups WHERE user_id = $1")
-        .bind(user_id)
-        .execute(pool)
-        .await?;
-    Ok(())
+/// Returns false if there was no state for the contact.
+pub async fn delete_verification_state(
+    pool: &Pool,
+    user_id: Uuid,
+    contact_id: Uuid,
+) -> AppResult<bool> {
+    let result =
+        sqlx::query("DELETE FROM verification_states WHERE user_id = $1 AND contact_id = $2")
+            .bind(user_id)
+            .bind(contact_id)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Current device identity keys of `user_ids`, as (user_id, identity_key).
+pub async fn get_device_identity_keys(
+    pool: &Pool,
+    user_ids: &[Uuid],
+) -> AppResult<Vec<(Uuid, Vec<u8>)>> {
+    let rows: Vec<(Uuid, Vec<u8>)> =
+        sqlx::query_as("SELECT user_id, identity_key FROM devices WHERE user_id = ANY($1)")
+            .bind(user_ids)
+            .fetch_all(pool)
+            .await?;
+    Ok(rows)
 }
 
 // ─── User Profiles ───────────────────────────────────
 
+#[allow(clippy::too_many_arguments)]
 pub async fn update_user_profile(
     pool: &Pool,
     user_id: Uuid,
@@ -1564,6 +3322,7 @@ pub async fn update_user_profile(
     about_me: Option<&str>,
     custom_status: Option<&str>,
     custom_status_emoji: Option<&str>,
+    custom_status_expires_at: Option<DateTime<Utc>>,
     encrypted_profile: Option<&[u8]>,
 ) -> AppResult<User> {
     let user = sqlx::query_as::<_, User>(
@@ -1573,7 +3332,8 @@ pub async fn update_user_profile(
             about_me = $3,
             custom_status = $4,
             custom_status_emoji = $5,
-            encrypted_profile = COALESCE($6, encrypted_profile),
+            custom_status_expires_at = $6,
+            encrypted_profile = COALESCE($7, encrypted_profile),
             updated_at = CURRENT_TIMESTAMP
         WHERE id = $1
         RETURNING *
@@ -1584,12 +3344,31 @@ pub async fn update_user_profile(
     .bind(about_me)
     .bind(custom_status)
     .bind(custom_status_emoji)
+    .bind(custom_status_expires_at)
     .bind(encrypted_profile)
     .fetch_one(pool)
     .await?;
     Ok(user)
 }
 
+/// Clear custom statuses whose expiry has passed. Returns the affected users.
+pub async fn clear_expired_custom_statuses(pool: &Pool) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        r#"
+        UPDATE users SET
+            custom_status = NULL,
+            custom_status_emoji = NULL,
+            custom_status_expires_at = NULL,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE custom_status_expires_at <= NOW()
+        RETURNING id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
 pub async fn update_user_avatar(pool: &Pool, user_id: Uuid, avatar_url: &str) -> AppResult<User> {
     let user = sqlx::query_as::<_, User>(
         "UPDATE users SET avatar_url = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING *",
@@ -1959,6 +3738,30 @@ pub async fn get_member_role_ids(
     Ok(rows.into_iter().map(|r| r.0).collect())
 }
 
+/// Every member of `server_id` (only those in `user_ids` when given) with
+/// each role they hold, as (user_id, role_id, role permissions) rows. A
+/// member without roles has a single row with both left None.
+pub async fn get_member_role_grants(
+    pool: &Pool,
+    server_id: Uuid,
+    user_ids: Option<&[Uuid]>,
+) -> AppResult<Vec<(Uuid, Option<Uuid>, Option<i64>)>> {
+    let rows: Vec<(Uuid, Option<Uuid>, Option<i64>)> = sqlx::query_as(
+        r#"
+        SELECT sm.user_id, mr.role_id, r.permissions
+        FROM server_members sm
+        LEFT JOIN member_roles mr ON mr.server_id = sm.server_id AND mr.user_id = sm.user_id
+        LEFT JOIN roles r ON r.id = mr.role_id
+        WHERE sm.server_id = $1 AND ($2::uuid[] IS NULL OR sm.user_id = ANY($2))
+        "#,
+    )
+    .bind(server_id)
+    .bind(user_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
 pub async fn get_member_roles(
     pool: &Pool,
     server_id: Uuid,
@@ -2056,6 +3859,20 @@ pub async fn get_channel_overwrites(
     Ok(rows)
 }
 
+/// The overwrites of several channels at once.
+pub async fn get_overwrites_for_channels(
+    pool: &Pool,
+    channel_ids: &[Uuid],
+) -> AppResult<Vec<ChannelPermissionOverwrite>> {
+    let rows = sqlx::query_as::<_, ChannelPermissionOverwrite>(
+        "SELECT * FROM channel_permission_overwrites WHERE channel_id = ANY($1)",
+    )
+    .bind(channel_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
 pub async fn set_channel_overwrite(
     pool: &Pool,
     channel_id: Uuid,
@@ -2323,6 +4140,141 @@ pub async fn update_dm_privacy(pool: &Pool, user_id: Uuid, dm_privacy: &str) ->
     Ok(())
 }
 
+pub async fn update_presence_privacy(pool: &Pool, user_id: Uuid, presence_privacy: &str) -> AppResult<()> {
+    sqlx::query("UPDATE users SET presence_privacy = $2, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(user_id)
+        .bind(presence_privacy)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_presence_privacy(pool: &Pool, user_id: Uuid) -> AppResult<String> {
+    let row: Option<(String,)> = sqlx::query_as("SELECT presence_privacy FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|r| r.0).unwrap_or_else(|| "everyone".into()))
+}
+
+/// Returns (presence_privacy, hide_last_seen).
+pub async fn get_presence_settings(pool: &Pool, user_id: Uuid) -> AppResult<(String, bool)> {
+    let row: (String, bool) =
+        sqlx::query_as("SELECT presence_privacy, hide_last_seen FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+    Ok(row)
+}
+
+/// Hiding last-seen also forgets the stored value.
+pub async fn update_hide_last_seen(pool: &Pool, user_id: Uuid, hide: bool) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE users SET
+            hide_last_seen = $2,
+            last_seen_at = CASE WHEN $2 THEN NULL ELSE last_seen_at END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(hide)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Record that the user just went offline, unless they hide last-seen.
+pub async fn record_last_seen(pool: &Pool, user_id: Uuid) -> AppResult<Option<DateTime<Utc>>> {
+    let row: Option<(DateTime<Utc>,)> = sqlx::query_as(
+        "UPDATE users SET last_seen_at = NOW() WHERE id = $1 AND NOT hide_last_seen RETURNING last_seen_at",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| r.0))
+}
+
+/// Users who get a user's presence directly: accepted friends and participants
+/// of shared DM/group channels. The flag is true for friends.
+pub async fn get_presence_audience(pool: &Pool, user_id: Uuid) -> AppResult<Vec<(Uuid, bool)>> {
+    let rows: Vec<(Uuid, bool)> = sqlx::query_as(
+        r#"
+        WITH friends AS (
+            SELECT CASE WHEN requester_id = $1 THEN addressee_id ELSE requester_id END AS user_id
+            FROM friendships
+            WHERE status = 'accepted' AND (requester_id = $1 OR addressee_id = $1)
+        )
+        SELECT user_id, TRUE FROM friends
+        UNION
+        SELECT other.user_id, FALSE
+        FROM channel_members mine
+        JOIN channels c ON c.id = mine.channel_id AND c.server_id IS NULL
+        JOIN channel_members other ON other.channel_id = mine.channel_id
+        WHERE mine.user_id = $1 AND other.user_id <> $1
+          AND other.user_id NOT IN (SELECT user_id FROM friends)
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Users sharing a DM or group DM with `user_id`.
+pub async fn get_dm_peer_ids(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Uuid>> {
+    let ids: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT DISTINCT other.user_id
+        FROM channel_members mine
+        JOIN channels c ON c.id = mine.channel_id AND c.server_id IS NULL
+        JOIN channel_members other ON other.channel_id = mine.channel_id
+        WHERE mine.user_id = $1 AND other.user_id <> $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(ids)
+}
+
+/// Presence details for `user_ids` as `viewer` may see them. `hidden` is set
+/// for friends-only users the viewer isn't friends with (every friends-only
+/// user when there is no viewer). Expired custom statuses are left out.
+pub async fn get_presence_details(
+    pool: &Pool,
+    user_ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> AppResult<Vec<PresenceDetails>> {
+    let rows = sqlx::query_as::<_, PresenceDetails>(
+        r#"
+        SELECT
+            u.id AS user_id,
+            (u.presence_privacy = 'friends_only' AND ($2::uuid IS NULL OR (
+                u.id <> $2 AND NOT EXISTS (
+                    SELECT 1 FROM friendships f
+                    WHERE f.status = 'accepted'
+                      AND ((f.requester_id = u.id AND f.addressee_id = $2)
+                        OR (f.requester_id = $2 AND f.addressee_id = u.id))
+                )
+            ))) AS hidden,
+            CASE WHEN expired THEN NULL ELSE u.custom_status END AS custom_status,
+            CASE WHEN expired THEN NULL ELSE u.custom_status_emoji END AS custom_status_emoji,
+            CASE WHEN expired THEN NULL ELSE u.custom_status_expires_at END AS custom_status_expires_at,
+            CASE WHEN u.hide_last_seen THEN NULL ELSE u.last_seen_at END AS last_seen_at
+        FROM users u,
+             LATERAL (SELECT COALESCE(u.custom_status_expires_at <= NOW(), FALSE) AS expired) e
+        WHERE u.id = ANY($1)
+        "#,
+    )
+    .bind(user_ids)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
 // ─── Bans ──────────────────────────────────────────────
 
 pub async fn create_ban(
@@ -2599,6 +4551,111 @@ pub async fn get_profile_key(
     Ok(dist)
 }
 
+// ─── Server Key Epochs ───────────────────────────────
+
+/// Start server key epoch `epoch` with the given member wraps. Returns false
+/// (and stores nothing) if the server's current epoch isn't `epoch - 1`, i.e.
+/// someone else rotated first.
+pub async fn rotate_server_key(
+    pool: &Pool,
+    server_id: Uuid,
+    epoch: i32,
+    distributed_by: Uuid,
+    wraps: &[(Uuid, Vec<u8>)],
+) -> AppResult<bool> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query("UPDATE servers SET key_epoch = $1 WHERE id = $2 AND key_epoch = $3")
+        .bind(epoch)
+        .bind(server_id)
+        .bind(epoch - 1)
+        .execute(&mut *tx)
+        .await?;
+    if result.rows_affected() == 0 {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+    for (user_id, encrypted_key) in wraps {
+        sqlx::query(
+            r#"
+            INSERT INTO server_key_wraps (server_id, epoch, user_id, encrypted_key, distributed_by)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(server_id)
+        .bind(epoch)
+        .bind(user_id)
+        .bind(encrypted_key)
+        .bind(distributed_by)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Add or replace wraps for an existing epoch.
+pub async fn add_server_key_wraps(
+    pool: &Pool,
+    server_id: Uuid,
+    epoch: i32,
+    distributed_by: Uuid,
+    wraps: &[(Uuid, Vec<u8>)],
+) -> AppResult<()> {
+    for (user_id, encrypted_key) in wraps {
+        sqlx::query(
+            r#"
+            INSERT INTO server_key_wraps (server_id, epoch, user_id, encrypted_key, distributed_by)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (server_id, epoch, user_id)
+            DO UPDATE SET encrypted_key = EXCLUDED.encrypted_key,
+                          distributed_by = EXCLUDED.distributed_by,
+                          created_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(server_id)
+        .bind(epoch)
+        .bind(user_id)
+        .bind(encrypted_key)
+        .bind(distributed_by)
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Every wrap held by `user_id` in a server, oldest epoch first.
+pub async fn get_server_key_wraps(
+    pool: &Pool,
+    server_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<Vec<ServerKeyWrap>> {
+    let wraps = sqlx::query_as::<_, ServerKeyWrap>(
+        "SELECT * FROM server_key_wraps WHERE server_id = $1 AND user_id = $2 ORDER BY epoch ASC",
+    )
+    .bind(server_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(wraps)
+}
+
+pub async fn has_server_key_wrap(
+    pool: &Pool,
+    server_id: Uuid,
+    epoch: i32,
+    user_id: Uuid,
+) -> AppResult<bool> {
+    let row: Option<(i32,)> = sqlx::query_as(
+        "SELECT epoch FROM server_key_wraps WHERE server_id = $1 AND epoch = $2 AND user_id = $3",
+    )
+    .bind(server_id)
+    .bind(epoch)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
+
 // ─── Custom Emojis ───────────────────────────────────
 
 pub async fn list_server_emojis(pool: &Pool, server_id: Uuid) -> AppResult<Vec<CustomEmoji>> {
@@ -2706,6 +4763,15 @@ pub async fn get_servers_owned_by(pool: &Pool, user_id: Uuid) -> AppResult<Vec<S
     Ok(servers)
 }
 
+pub async fn set_server_owner(pool: &Pool, server_id: Uuid, owner_id: Uuid) -> AppResult<()> {
+    sqlx::query("UPDATE servers SET owner_id = $1 WHERE id = $2")
+        .bind(owner_id)
+        .bind(server_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
 // ─── Member Timeouts ─────────────────────────────────
 
 pub async fn set_member_timeout(
@@ -3052,6 +5118,293 @@ pub async fn is_first_user_precheck(pool: &Pool) -> AppResult<bool> {
     Ok(row.0 == 0)
 }
 
+// ─── Account Deactivation ─────────────────────────────
+
+/// Deactivate an account, scheduling its deletion when `deletion_at` is set.
+/// `by_admin` marks a deletion the owner can't cancel by signing in.
+pub async fn deactivate_user(
+    pool: &Pool,
+    user_id: Uuid,
+    deletion_at: Option<DateTime<Utc>>,
+    by_admin: bool,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET deactivated_at = COALESCE(deactivated_at, NOW()), deletion_scheduled_at = $2,
+            deletion_requested_by_admin = deletion_requested_by_admin OR $3
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .bind(deletion_at)
+    .bind(by_admin)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Reactivate a deactivated account, cancelling any scheduled deletion
+/// unless an admin scheduled it. Returns false when nothing changed.
+pub async fn reactivate_user(pool: &Pool, user_id: Uuid) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET deactivated_at = NULL, deletion_scheduled_at = NULL
+        WHERE id = $1 AND deactivated_at IS NOT NULL AND NOT deletion_requested_by_admin
+        "#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Accounts whose deletion grace period is over, oldest first.
+pub async fn list_users_due_for_deletion(pool: &Pool, limit: i64) -> AppResult<Vec<Uuid>> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        r#"
+        SELECT id FROM users
+        WHERE deletion_scheduled_at <= CURRENT_TIMESTAMP
+        ORDER BY deletion_scheduled_at ASC
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+// ─── Data Exports ────────────────────────────────────
+
+/// Returns None when the user already has an export pending.
+pub async fn insert_data_export(pool: &Pool, id: Uuid, user_id: Uuid) -> AppResult<Option<DataExport>> {
+    let export = sqlx::query_as::<_, DataExport>(
+        r#"
+        INSERT INTO data_exports (id, user_id) VALUES ($1, $2)
+        ON CONFLICT (user_id) WHERE status = 'pending' DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(export)
+}
+
+pub async fn find_data_export(pool: &Pool, id: Uuid) -> AppResult<Option<DataExport>> {
+    let export = sqlx::query_as::<_, DataExport>("SELECT * FROM data_exports WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(export)
+}
+
+/// A user's exports, newest first.
+pub async fn list_user_data_exports(pool: &Pool, user_id: Uuid) -> AppResult<Vec<DataExport>> {
+    let exports = sqlx::query_as::<_, DataExport>(
+        "SELECT * FROM data_exports WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(exports)
+}
+
+pub async fn complete_data_export(
+    pool: &Pool,
+    id: Uuid,
+    storage_key: &str,
+    size_bytes: i64,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE data_exports
+        SET status = 'ready', storage_key = $2, size_bytes = $3, completed_at = NOW(), expires_at = $4
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(storage_key)
+    .bind(size_bytes)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Mark an export failed. It is kept until `expires_at` so the user sees why
+/// their export never arrived.
+pub async fn fail_data_export(pool: &Pool, id: Uuid, expires_at: DateTime<Utc>) -> AppResult<()> {
+    sqlx::query(
+        "UPDATE data_exports SET status = 'failed', completed_at = NOW(), expires_at = $2 WHERE id = $1",
+    )
+    .bind(id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Fail exports still pending since before `cutoff`, e.g. because the
+/// server restarted while building them.
+pub async fn fail_stale_data_exports(
+    pool: &Pool,
+    cutoff: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE data_exports SET status = 'failed', completed_at = NOW(), expires_at = $2
+        WHERE status = 'pending' AND created_at < $1
+        "#,
+    )
+    .bind(cutoff)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn list_expired_data_exports(pool: &Pool) -> AppResult<Vec<DataExport>> {
+    let exports = sqlx::query_as::<_, DataExport>(
+        "SELECT * FROM data_exports WHERE expires_at <= CURRENT_TIMESTAMP",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(exports)
+}
+
+pub async fn delete_data_export(pool: &Pool, id: Uuid) -> AppResult<()> {
+    sqlx::query("DELETE FROM data_exports WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// A page of the messages a user sent under their own id, oldest first,
+/// resuming after the `(timestamp, id)` of the previous page's last message.
+/// Sealed-sender messages store no sender and can't be attributed.
+pub async fn list_messages_by_sender(
+    pool: &Pool,
+    user_id: Uuid,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    limit: i64,
+) -> AppResult<Vec<Message>> {
+    let messages = sqlx::query_as::<_, Message>(
+        r#"
+        SELECT * FROM messages
+        WHERE sender_id = $1
+          AND ($2::timestamptz IS NULL OR (timestamp, id) > ($2, $3))
+        ORDER BY timestamp ASC, id ASC
+        LIMIT $4
+        "#,
+    )
+    .bind(user_id)
+    .bind(after.map(|(ts, _)| ts))
+    .bind(after.map(|(_, id)| id))
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(messages)
+}
+
+/// Attachments on the messages a user sent.
+pub async fn list_attachments_by_sender(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Attachment>> {
+    let attachments = sqlx::query_as::<_, Attachment>(
+        r#"
+        SELECT * FROM attachments
+        WHERE message_id IN (SELECT id FROM messages WHERE sender_id = $1)
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(attachments)
+}
+
+/// Friendships and pending friend requests in either direction.
+pub async fn list_friendships_for_user(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Friendship>> {
+    let friendships = sqlx::query_as::<_, Friendship>(
+        r#"
+        SELECT * FROM friendships
+        WHERE requester_id = $1 OR addressee_id = $1
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(friendships)
+}
+
+pub async fn list_server_memberships(pool: &Pool, user_id: Uuid) -> AppResult<Vec<ServerMember>> {
+    let members = sqlx::query_as::<_, ServerMember>(
+        "SELECT * FROM server_members WHERE user_id = $1 ORDER BY joined_at ASC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(members)
+}
+
+/// The roles a user holds, across all their servers.
+pub async fn list_roles_for_user(pool: &Pool, user_id: Uuid) -> AppResult<Vec<Role>> {
+    let roles = sqlx::query_as::<_, Role>(
+        r#"
+        SELECT r.* FROM roles r
+        INNER JOIN member_roles mr ON mr.role_id = r.id
+        WHERE mr.user_id = $1
+        ORDER BY r.server_id, r.position DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(roles)
+}
+
+/// Every refresh token of a user, including rotated and expired ones not
+/// yet purged.
+pub async fn list_all_refresh_tokens(pool: &Pool, user_id: Uuid) -> AppResult<Vec<RefreshToken>> {
+    let tokens = sqlx::query_as::<_, RefreshToken>(
+        "SELECT * FROM refresh_tokens WHERE user_id = $1 ORDER BY created_at ASC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(tokens)
+}
+
+pub async fn list_reports_by_reporter(pool: &Pool, reporter_id: Uuid) -> AppResult<Vec<Report>> {
+    let reports = sqlx::query_as::<_, Report>(
+        "SELECT * FROM reports WHERE reporter_id = $1 ORDER BY created_at ASC",
+    )
+    .bind(reporter_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(reports)
+}
+
+/// Audit log entries a user made or that target them, in every server.
+pub async fn list_audit_log_about_user(pool: &Pool, user_id: Uuid) -> AppResult<Vec<AuditLogEntry>> {
+    let entries = sqlx::query_as::<_, AuditLogEntry>(
+        r#"
+        SELECT * FROM audit_log
+        WHERE actor_id = $1 OR target_id = $1
+        ORDER BY created_at ASC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(entries)
+}
+
 // ─── Registration Invites (instance-level) ────────────
 
 pub async fn find_registration_invite_by_code(
@@ -0,0 +1,222 @@
+//! Outgoing email over an SMTP relay: address verification and password reset.
+//!
+//! Haven stores only an HMAC hash of a user's address. The plaintext is used
+//! while a message is composed and sent, then dropped. A verification token
+//! carries the hash of the address it went to; password resets are only sent
+//! to verified addresses. Tokens are single-use and stored as SHA-256 hashes.
+
+use chrono::{Duration, Utc};
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use uuid::Uuid;
+
+use crate::auth;
+use crate::config::AppConfig;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::AppState;
+
+pub const VERIFY_EMAIL: &str = "verify_email";
+pub const PASSWORD_RESET: &str = "password_reset";
+
+/// A configured SMTP relay.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// The relay the config names, if any.
+    pub fn from_config(config: &AppConfig) -> Option<Self> {
+        if !config.email_enabled() {
+            return None;
+        }
+        let from: Mailbox = match config.smtp_from.parse() {
+            Ok(from) => from,
+            Err(e) => {
+                tracing::error!("Invalid smtp_from '{}': {} — email disabled", config.smtp_from, e);
+                return None;
+            }
+        };
+
+        let builder = match config.smtp_tls.to_ascii_lowercase().as_str() {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host),
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host),
+            "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)),
+            other => {
+                tracing::error!("Unknown smtp_tls '{}' — email disabled", other);
+                return None;
+            }
+        };
+        let mut builder = match builder {
+            Ok(builder) => builder.port(config.smtp_port),
+            Err(e) => {
+                tracing::error!("Invalid SMTP relay '{}': {} — email disabled", config.smtp_host, e);
+                return None;
+            }
+        };
+        if !config.smtp_username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                config.smtp_username.clone(),
+                config.smtp_password.clone(),
+            ));
+        }
+
+        Some(Mailer { transport: builder.build(), from })
+    }
+
+    pub async fn send(&self, to: Address, subject: &str, body: String) -> AppResult<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(Mailbox::new(None, to))
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to build email: {}", e)))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("SMTP send failed: {}", e)))?;
+        Ok(())
+    }
+
+    /// Send in the background, so the response doesn't wait on (or reveal
+    /// anything through) the relay.
+    fn spawn_send(&self, to: Address, subject: &'static str, body: String) {
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(to, subject, body).await {
+                tracing::error!("Failed to send '{}' email: {}", subject, e);
+            }
+        });
+    }
+}
+
+/// Parse a user-supplied address.
+pub fn parse_address(email: &str) -> AppResult<Address> {
+    email
+        .trim()
+        .parse()
+        .map_err(|_| AppError::Validation("Invalid email address".into()))
+}
+
+/// Store a new token for `purpose`, replacing the user's earlier one, and
+/// return it.
+async fn issue_token(
+    state: &AppState,
+    user_id: Uuid,
+    purpose: &str,
+    email_hash: Option<(&str, &str)>,
+    ttl: Duration,
+) -> AppResult<String> {
+    // Same shape as refresh tokens: 48 random bytes, stored as a SHA-256 hash
+    let token = auth::generate_refresh_token();
+    queries::replace_email_token(
+        state.db.write(),
+        user_id,
+        purpose,
+        &auth::hash_refresh_token(&token),
+        email_hash,
+        Utc::now() + ttl,
+    )
+    .await?;
+    Ok(token)
+}
+
+/// Consume a token for `purpose`. Unknown, used and expired tokens all fail
+/// the same way.
+pub async fn redeem_token(state: &AppState, token: &str, purpose: &str) -> AppResult<crate::models::EmailToken> {
+    queries::take_email_token(state.db.write(), &auth::hash_refresh_token(token), purpose)
+        .await?
+        .filter(|t| t.expires_at > Utc::now())
+        .ok_or_else(|| AppError::Validation("Invalid or expired link — request a new one".into()))
+}
+
+fn verification_body(config: &AppConfig, token: &str) -> String {
+    format!(
+        "Confirm this address for your Haven account by opening the link below. \
+         It expires in {} hours.\n\n{}/verify-email?token={}\n\n\
+         If you didn't ask for this, you can ignore this email.\n",
+        config.email_verification_expiry_hours,
+        config.public_base_url(),
+        token,
+    )
+}
+
+fn password_reset_body(config: &AppConfig, username: &str, token: &str) -> String {
+    format!(
+        "Someone asked to reset the password of the Haven account {}. \
+         To choose a new password, open the link below. It expires in {} minutes \
+         and signs out every session of the account.\n\n{}/reset-password?token={}\n\n\
+         If you didn't ask for this, you can ignore this email.\n",
+        username,
+        config.password_reset_expiry_minutes,
+        config.public_base_url(),
+        token,
+    )
+}
+
+/// Email a verification link for `address` to the user. The account's email
+/// hash changes only once the link is followed.
+pub async fn send_verification(state: &AppState, mailer: &Mailer, user_id: Uuid, address: Address) -> AppResult<()> {
+    let (hash, key_id) = auth::hash_email_current(address.as_ref(), &state.config);
+    let ttl = Duration::hours(state.config.email_verification_expiry_hours);
+    let token = issue_token(state, user_id, VERIFY_EMAIL, Some((&hash, &key_id)), ttl).await?;
+    mailer.spawn_send(address, "Confirm your email address", verification_body(&state.config, &token));
+    Ok(())
+}
+
+/// Email a password reset link to a user's verified address.
+pub async fn send_password_reset(
+    state: &AppState,
+    mailer: &Mailer,
+    user_id: Uuid,
+    username: &str,
+    address: Address,
+) -> AppResult<()> {
+    let ttl = Duration::minutes(state.config.password_reset_expiry_minutes);
+    let token = issue_token(state, user_id, PASSWORD_RESET, None, ttl).await?;
+    mailer.spawn_send(address, "Reset your password", password_reset_body(&state.config, username, &token));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_is_off_without_a_relay() {
+        let config = AppConfig::test_default();
+        assert!(Mailer::from_config(&config).is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_tls_mode_disables_email() {
+        let mut config = AppConfig::test_default();
+        config.smtp_host = "smtp.example.com".into();
+        assert!(Mailer::from_config(&config).is_some());
+
+        config.smtp_tls = "ssl3".into();
+        assert!(Mailer::from_config(&config).is_none());
+    }
+
+    #[test]
+    fn links_use_the_public_url() {
+        let mut config = AppConfig::test_default();
+        config.public_url = "https://haven.example/".into();
+        let body = verification_body(&config, "tok");
+        assert!(body.contains("https://haven.example/verify-email?token=tok"));
+
+        config.public_url = String::new();
+        let body = password_reset_body(&config, "alice", "tok");
+        assert!(body.contains(&format!("http://localhost:{}/reset-password?token=tok", config.port)));
+    }
+
+    #[test]
+    fn addresses_are_validated() {
+        assert!(parse_address(" alice@example.com ").is_ok());
+        assert!(parse_address("not-an-address").is_err());
+    }
+}
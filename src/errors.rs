@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -31,9 +31,15 @@ pub enum AppError {
     #[error("Forbidden: {0}")]
     Forbidden(String),
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     #[error("Rate limited")]
     RateLimited,
 
+    #[error("Login locked for {0} seconds")]
+    LoginLocked(u64),
+
     #[error("Bad request: {0}")]
     BadRequest(String),
 
@@ -61,7 +67,12 @@ impl IntoResponse for AppError {
             AppError::Validation(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
             AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg.clone()),
             AppError::RateLimited => (StatusCode::TOO_MANY_REQUESTS, "Rate limited".into()),
+            AppError::LoginLocked(secs) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Too many failed login attempts — try again in {secs} seconds"),
+            ),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
             AppError::PrekeyExhausted(id) => (
                 StatusCode::GONE,
@@ -86,6 +97,9 @@ impl IntoResponse for AppError {
             "status": status.as_u16(),
         }));
 
+        if let AppError::LoginLocked(secs) = self {
+            return (status, [(header::RETRY_AFTER, secs.to_string())], body).into_response();
+        }
         (status, body).into_response()
     }
 }
@@ -0,0 +1,621 @@
+//! Asymmetric signing keys for access tokens and the server's other
+//! signatures.
+//!
+//! Every instance signs and verifies with shared keyrings kept in the
+//! `jwt_signing_keys` table, one per `KeyPurpose`, private halves encrypted
+//! with the storage key. Tokens carry the signing key's `kid` so verifiers
+//! pick the right public key, and the public halves are served as a JWKS at
+//! `/.well-known/jwks.json` for other services. Key transparency tree heads
+//! and sealed-sender certificates are signed from their own Ed25519 rings,
+//! published at `/.well-known/haven-keys.json`.
+//!
+//! Rotation overlaps: a new key is published `PUBLISH_AHEAD_HOURS` before it
+//! starts signing, so every instance (and every JWKS consumer) knows it by
+//! then, and the key it replaces keeps verifying until the last token it
+//! signed has expired. An admin rotation signs with the new key at once;
+//! other instances load it when the first token naming it arrives.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::AppConfig;
+use crate::db::{queries, DbPools};
+use crate::errors::{AppError, AppResult};
+use crate::models::SigningKeyResponse;
+
+/// How long a new key is published before it signs.
+pub const PUBLISH_AHEAD_HOURS: i64 = 24;
+
+/// How often instances reload the keyring and rotate when due.
+const SYNC_INTERVAL_SECS: u64 = 300;
+
+/// Minimum time between reloads triggered by tokens naming an unknown key,
+/// so made-up `kid`s can't turn into a query per request.
+const UNKNOWN_KID_RELOAD_SECS: u64 = 10;
+
+/// What a keyring's keys sign. Each purpose has its own ring and rotates on
+/// its own schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyPurpose {
+    #[default]
+    AccessToken,
+    TreeHead,
+    SenderCertificate,
+}
+
+impl KeyPurpose {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "access_token" => Some(Self::AccessToken),
+            "tree_head" => Some(Self::TreeHead),
+            "sender_certificate" => Some(Self::SenderCertificate),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AccessToken => "access_token",
+            Self::TreeHead => "tree_head",
+            Self::SenderCertificate => "sender_certificate",
+        }
+    }
+
+    /// Access tokens use the configured algorithm; everything else is Ed25519,
+    /// which clients verify directly.
+    fn algorithm(&self, config: &AppConfig) -> AppResult<SigningAlgorithm> {
+        match self {
+            Self::AccessToken => configured_algorithm(config),
+            Self::TreeHead | Self::SenderCertificate => Ok(SigningAlgorithm::EdDSA),
+        }
+    }
+
+    /// How long a replaced key stays published: until the last access token
+    /// or sender certificate it signed expires, or for a publication period
+    /// for tree heads, which are signed fresh for every request.
+    fn retire_after(&self, config: &AppConfig) -> Duration {
+        match self {
+            Self::AccessToken => Duration::hours(config.jwt_expiry_hours),
+            Self::TreeHead => Duration::hours(PUBLISH_AHEAD_HOURS),
+            Self::SenderCertificate => Duration::hours(crate::sealed_sender::CERTIFICATE_TTL_HOURS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SigningAlgorithm {
+    EdDSA,
+    ES256,
+}
+
+impl SigningAlgorithm {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "EdDSA" | "Ed25519" => Some(Self::EdDSA),
+            "ES256" => Some(Self::ES256),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EdDSA => "EdDSA",
+            Self::ES256 => "ES256",
+        }
+    }
+
+    fn jwt_algorithm(&self) -> Algorithm {
+        match self {
+            Self::EdDSA => Algorithm::EdDSA,
+            Self::ES256 => Algorithm::ES256,
+        }
+    }
+}
+
+fn b64url(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// A new key pair: PKCS#8 private key and raw public key (the 32-byte
+/// Ed25519 key or the uncompressed P-256 point).
+fn generate(algorithm: SigningAlgorithm) -> AppResult<(Vec<u8>, Vec<u8>)> {
+    fn keygen_error<E>(_: E) -> AppError {
+        AppError::Internal(anyhow::anyhow!("Signing key generation failed"))
+    }
+
+    let rng = SystemRandom::new();
+    match algorithm {
+        SigningAlgorithm::EdDSA => {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(keygen_error)?;
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(keygen_error)?;
+            Ok((pkcs8.as_ref().to_vec(), pair.public_key().as_ref().to_vec()))
+        }
+        SigningAlgorithm::ES256 => {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .map_err(keygen_error)?;
+            let pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .map_err(keygen_error)?;
+            Ok((pkcs8.as_ref().to_vec(), pair.public_key().as_ref().to_vec()))
+        }
+    }
+}
+
+/// Key id: a truncated SHA-256 thumbprint of the public key.
+fn key_id(public_key: &[u8]) -> String {
+    b64url(&Sha256::digest(public_key)[..12])
+}
+
+struct LoadedKey {
+    kid: String,
+    algorithm: SigningAlgorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// For signing raw messages; Ed25519 keys only
+    ed25519: Option<Ed25519KeyPair>,
+    jwk: Value,
+    created_at: DateTime<Utc>,
+    activates_at: DateTime<Utc>,
+    retires_at: Option<DateTime<Utc>>,
+}
+
+impl LoadedKey {
+    fn new(
+        kid: String,
+        algorithm: SigningAlgorithm,
+        pkcs8: &[u8],
+        public_key: &[u8],
+        created_at: DateTime<Utc>,
+        activates_at: DateTime<Utc>,
+        retires_at: Option<DateTime<Utc>>,
+    ) -> AppResult<Self> {
+        let bad_key = || AppError::Internal(anyhow::anyhow!("Malformed signing key {}", kid));
+        let mut ed25519 = None;
+        let (encoding, decoding, jwk) = match algorithm {
+            SigningAlgorithm::EdDSA => {
+                if public_key.len() != 32 {
+                    return Err(bad_key());
+                }
+                ed25519 = Some(Ed25519KeyPair::from_pkcs8(pkcs8).map_err(|_| bad_key())?);
+                let x = b64url(public_key);
+                let decoding = DecodingKey::from_ed_components(&x).map_err(|_| bad_key())?;
+                let jwk = json!({ "kty": "OKP", "crv": "Ed25519", "x": x });
+                (EncodingKey::from_ed_der(pkcs8), decoding, jwk)
+            }
+            SigningAlgorithm::ES256 => {
+                if public_key.len() != 65 || public_key[0] != 0x04 {
+                    return Err(bad_key());
+                }
+                let x = b64url(&public_key[1..33]);
+                let y = b64url(&public_key[33..65]);
+                let decoding = DecodingKey::from_ec_components(&x, &y).map_err(|_| bad_key())?;
+                let jwk = json!({ "kty": "EC", "crv": "P-256", "x": x, "y": y });
+                (EncodingKey::from_ec_der(pkcs8), decoding, jwk)
+            }
+        };
+        let mut jwk = jwk;
+        jwk["kid"] = json!(kid);
+        jwk["alg"] = json!(algorithm.as_str());
+        jwk["use"] = json!("sig");
+
+        Ok(LoadedKey {
+            kid,
+            algorithm,
+            encoding,
+            decoding,
+            ed25519,
+            jwk,
+            created_at,
+            activates_at,
+            retires_at,
+        })
+    }
+}
+
+/// The loaded keyring for one purpose, ordered by activation. Cheap to
+/// clone; every clone sees reloads.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    purpose: KeyPurpose,
+    keys: Arc<RwLock<Vec<Arc<LoadedKey>>>>,
+    /// When a token with an unknown `kid` last triggered a reload
+    unknown_kid_reload: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Keyring {
+    /// An empty keyring; `sync` fills it.
+    pub fn new(purpose: KeyPurpose) -> Self {
+        Keyring {
+            purpose,
+            ..Default::default()
+        }
+    }
+
+    /// A keyring with one fresh key that exists only in memory.
+    #[cfg(test)]
+    pub(crate) fn ephemeral(algorithm: SigningAlgorithm) -> Self {
+        let (pkcs8, public_key) = generate(algorithm).unwrap();
+        let now = Utc::now();
+        let key = LoadedKey::new(key_id(&public_key), algorithm, &pkcs8, &public_key, now, now, None)
+            .unwrap();
+        Keyring {
+            keys: Arc::new(RwLock::new(vec![Arc::new(key)])),
+            ..Default::default()
+        }
+    }
+
+    fn snapshot(&self) -> Vec<Arc<LoadedKey>> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The newest key that has activated.
+    fn signing_key(&self, now: DateTime<Utc>) -> Option<Arc<LoadedKey>> {
+        self.snapshot()
+            .into_iter()
+            .rev()
+            .find(|k| k.activates_at <= now)
+    }
+
+    fn active_key(&self) -> AppResult<Arc<LoadedKey>> {
+        self.signing_key(Utc::now()).ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("No active {} signing key", self.purpose.as_str()))
+        })
+    }
+
+    /// Sign claims with the current key, naming it in the `kid` header.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> AppResult<String> {
+        let key = self.active_key()?;
+        let mut header = Header::new(key.algorithm.jwt_algorithm());
+        header.kid = Some(key.kid.clone());
+        jsonwebtoken::encode(&header, claims, &key.encoding)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("JWT encoding failed: {}", e)))
+    }
+
+    /// Sign a raw message with the current key. Returns `(signature, public_key)`.
+    pub fn sign_message(&self, message: &[u8]) -> AppResult<(Vec<u8>, Vec<u8>)> {
+        let key = self.active_key()?;
+        let pair = key.ed25519.as_ref().ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("Signing key {} can't sign raw messages", key.kid))
+        })?;
+        let signature = pair.sign(message);
+        Ok((signature.as_ref().to_vec(), pair.public_key().as_ref().to_vec()))
+    }
+
+    /// Whether `token` names a key that isn't loaded. Malformed tokens and
+    /// tokens without a `kid` don't.
+    fn names_unknown_key(&self, token: &str) -> bool {
+        let Some(kid) = jsonwebtoken::decode_header(token).ok().and_then(|h| h.kid) else {
+            return false;
+        };
+        !self.snapshot().iter().any(|k| k.kid == kid)
+    }
+
+    /// Claim the next unknown-`kid` reload, unless one ran too recently.
+    fn claim_unknown_kid_reload(&self) -> bool {
+        let mut last = self.unknown_kid_reload.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if last.is_some_and(|at| now.duration_since(at).as_secs() < UNKNOWN_KID_RELOAD_SECS) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+
+    /// Verify a token against the key its `kid` names. Tokens without a
+    /// `kid`, or naming a key that isn't (or is no longer) in the ring, fail.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header.kid.ok_or(ErrorKind::InvalidToken)?;
+        let key = self
+            .snapshot()
+            .into_iter()
+            .find(|k| k.kid == kid)
+            .ok_or(ErrorKind::InvalidSignature)?;
+        if key.retires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(ErrorKind::InvalidSignature.into());
+        }
+        let validation = Validation::new(key.algorithm.jwt_algorithm());
+        jsonwebtoken::decode::<T>(token, &key.decoding, &validation).map(|data| data.claims)
+    }
+
+    /// Public keys of the whole ring, pending and retiring keys included.
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<Value> = self.snapshot().iter().map(|k| k.jwk.clone()).collect();
+        json!({ "keys": keys })
+    }
+
+    /// The ring as the admin API lists it.
+    pub fn keys_info(&self) -> Vec<SigningKeyResponse> {
+        let signing = self.signing_key(Utc::now()).map(|k| k.kid.clone());
+        self.snapshot()
+            .iter()
+            .map(|k| SigningKeyResponse {
+                kid: k.kid.clone(),
+                purpose: self.purpose.as_str().to_string(),
+                algorithm: k.algorithm.as_str().to_string(),
+                created_at: k.created_at,
+                activates_at: k.activates_at,
+                retires_at: k.retires_at,
+                signing: signing.as_deref() == Some(k.kid.as_str()),
+            })
+            .collect()
+    }
+}
+
+fn configured_algorithm(config: &AppConfig) -> AppResult<SigningAlgorithm> {
+    SigningAlgorithm::parse(&config.jwt_algorithm).ok_or_else(|| {
+        AppError::Internal(anyhow::anyhow!(
+            "Unsupported jwt_algorithm '{}' (expected EdDSA or ES256)",
+            config.jwt_algorithm
+        ))
+    })
+}
+
+/// Generate a key for `purpose` and store it. See
+/// `queries::insert_jwt_signing_key` for `unless_pending_after`.
+async fn add_key(
+    db: &DbPools,
+    config: &AppConfig,
+    storage_key: &[u8; 32],
+    purpose: KeyPurpose,
+    activates_at: DateTime<Utc>,
+    unless_pending_after: Option<DateTime<Utc>>,
+) -> AppResult<()> {
+    let algorithm = purpose.algorithm(config)?;
+    let (pkcs8, public_key) = generate(algorithm)?;
+    let sealed = crate::storage::encrypt_blob(&pkcs8, storage_key)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!(e)))?;
+    let kid = key_id(&public_key);
+    if queries::insert_jwt_signing_key(
+        db.write(),
+        purpose.as_str(),
+        &kid,
+        algorithm.as_str(),
+        &sealed,
+        &public_key,
+        activates_at,
+        unless_pending_after,
+    )
+    .await?
+    {
+        tracing::info!(
+            "Added {} {} signing key {} (signs from {})",
+            algorithm.as_str(),
+            purpose.as_str(),
+            kid,
+            activates_at
+        );
+    }
+    Ok(())
+}
+
+/// Bring the stored keyring up to date and reload `ring` from it: create the
+/// first key, publish the next one when rotation is due, schedule keys that
+/// stopped signing for retirement and drop retired ones.
+pub async fn sync(
+    db: &DbPools,
+    config: &AppConfig,
+    storage_key: &[u8; 32],
+    ring: &Keyring,
+) -> AppResult<()> {
+    let now = Utc::now();
+    let purpose = ring.purpose;
+    let keys = queries::get_jwt_signing_keys(db.write(), purpose.as_str(), now).await?;
+    let active = keys.iter().rev().find(|k| k.activates_at <= now);
+    let pending = keys.iter().any(|k| k.activates_at > now);
+
+    let rotation = Duration::days(config.jwt_key_rotation_days.max(1));
+    let ahead = Duration::hours(PUBLISH_AHEAD_HOURS);
+    match active {
+        None => add_key(db, config, storage_key, purpose, now, None).await?,
+        Some(key) if !pending && key.activates_at + rotation - ahead <= now => {
+            add_key(db, config, storage_key, purpose, now + ahead, Some(now)).await?
+        }
+        _ => {}
+    }
+
+    reload(db, config, storage_key, ring).await
+}
+
+/// Start signing with a new key right away, e.g. after a suspected leak.
+/// Tokens signed by the previous key stay valid until they expire. Other
+/// instances load the new key as soon as a token signed with it reaches them
+/// (see `load_for_token`), rather than waiting for their next sync.
+pub async fn rotate_now(
+    db: &DbPools,
+    config: &AppConfig,
+    storage_key: &[u8; 32],
+    ring: &Keyring,
+) -> AppResult<()> {
+    add_key(db, config, storage_key, ring.purpose, Utc::now(), None).await?;
+    reload(db, config, storage_key, ring).await
+}
+
+async fn reload(
+    db: &DbPools,
+    config: &AppConfig,
+    storage_key: &[u8; 32],
+    ring: &Keyring,
+) -> AppResult<()> {
+    let now = Utc::now();
+    let purpose = ring.purpose;
+    let keys = queries::get_jwt_signing_keys(db.write(), purpose.as_str(), now).await?;
+
+    // Keys the active key replaced verify until their last signature expires
+    if let Some(active) = keys.iter().rev().find(|k| k.activates_at <= now) {
+        let retires_at = active.activates_at + purpose.retire_after(config);
+        queries::retire_jwt_signing_keys(db.write(), purpose.as_str(), active.activates_at, retires_at)
+            .await?;
+    }
+    let deleted = queries::delete_retired_jwt_signing_keys(db.write(), now).await?;
+    if deleted > 0 {
+        tracing::info!("Deleted {} retired signing key(s)", deleted);
+    }
+
+    load(db, storage_key, ring).await
+}
+
+/// Replace `ring`'s keys with the stored ones.
+async fn load(db: &DbPools, storage_key: &[u8; 32], ring: &Keyring) -> AppResult<()> {
+    let now = Utc::now();
+    let mut loaded = Vec::new();
+    for key in queries::get_jwt_signing_keys(db.write(), ring.purpose.as_str(), now).await? {
+        let Some(algorithm) = SigningAlgorithm::parse(&key.algorithm) else {
+            tracing::error!("Skipping signing key {} with unknown algorithm {}", key.kid, key.algorithm);
+            continue;
+        };
+        let pkcs8 = crate::storage::decrypt_blob(&key.private_key, storage_key)
+            .map_err(|e| AppError::Internal(anyhow::anyhow!("Signing key {}: {}", key.kid, e)))?;
+        loaded.push(Arc::new(LoadedKey::new(
+            key.kid,
+            algorithm,
+            &pkcs8,
+            &key.public_key,
+            key.created_at,
+            key.activates_at,
+            key.retires_at,
+        )?));
+    }
+    *ring.keys.write().unwrap_or_else(|e| e.into_inner()) = loaded;
+    Ok(())
+}
+
+/// Reload `ring` before verifying `token` if the token names a key it hasn't
+/// loaded, e.g. one another instance rotated in since this one last synced.
+/// Verification then decides; a failed reload is only logged.
+pub async fn load_for_token(db: &DbPools, storage_key: &[u8; 32], ring: &Keyring, token: &str) {
+    if !ring.names_unknown_key(token) || !ring.claim_unknown_kid_reload() {
+        return;
+    }
+    if let Err(e) = load(db, storage_key, ring).await {
+        tracing::error!("Reloading JWT signing keys for an unknown kid failed: {}", e);
+    }
+}
+
+/// Background task: sync the keyrings every few minutes.
+pub fn spawn_rotation_task(db: DbPools, config: AppConfig, storage_key: [u8; 32], rings: Vec<Keyring>) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(SYNC_INTERVAL_SECS));
+        // The first tick fires immediately; startup already synced
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for ring in &rings {
+                if let Err(e) = sync(&db, &config, &storage_key, ring).await {
+                    tracing::error!("{} signing key sync failed: {}", ring.purpose.as_str(), e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct TestClaims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> TestClaims {
+        TestClaims {
+            sub: "user".into(),
+            exp: (Utc::now().timestamp() + 600) as usize,
+        }
+    }
+
+    #[test]
+    fn eddsa_round_trip() {
+        let ring = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        let token = ring.sign(&claims()).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert!(header.kid.is_some());
+        let decoded: TestClaims = ring.verify(&token).unwrap();
+        assert_eq!(decoded.sub, "user");
+    }
+
+    #[test]
+    fn es256_round_trip() {
+        let ring = Keyring::ephemeral(SigningAlgorithm::ES256);
+        let token = ring.sign(&claims()).unwrap();
+        assert_eq!(jsonwebtoken::decode_header(&token).unwrap().alg, Algorithm::ES256);
+        let decoded: TestClaims = ring.verify(&token).unwrap();
+        assert_eq!(decoded.sub, "user");
+    }
+
+    #[test]
+    fn foreign_keyring_rejects() {
+        let token = Keyring::ephemeral(SigningAlgorithm::EdDSA).sign(&claims()).unwrap();
+        let other = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        assert!(other.verify::<TestClaims>(&token).is_err());
+    }
+
+    #[test]
+    fn jwks_publishes_signing_kid() {
+        let ring = Keyring::ephemeral(SigningAlgorithm::ES256);
+        let token = ring.sign(&claims()).unwrap();
+        let kid = jsonwebtoken::decode_header(&token).unwrap().kid.unwrap();
+
+        let jwks = ring.jwks();
+        let key = &jwks["keys"][0];
+        assert_eq!(key["kid"], kid);
+        assert_eq!(key["kty"], "EC");
+        assert_eq!(key["alg"], "ES256");
+
+        let info = ring.keys_info();
+        assert_eq!(info.len(), 1);
+        assert!(info[0].signing);
+    }
+
+    #[test]
+    fn unknown_kid_reloads_are_throttled() {
+        let ring = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        let own = ring.sign(&claims()).unwrap();
+        let foreign = Keyring::ephemeral(SigningAlgorithm::EdDSA).sign(&claims()).unwrap();
+        assert!(!ring.names_unknown_key(&own));
+        assert!(!ring.names_unknown_key("not.a.jwt"));
+        assert!(ring.names_unknown_key(&foreign));
+
+        assert!(ring.claim_unknown_kid_reload());
+        assert!(!ring.claim_unknown_kid_reload());
+    }
+
+    #[test]
+    fn raw_signatures_verify_with_the_published_key() {
+        use ring::signature::{UnparsedPublicKey, ED25519};
+
+        let ring = Keyring::ephemeral(SigningAlgorithm::EdDSA);
+        let (signature, public_key) = ring.sign_message(b"tree head").unwrap();
+        assert_eq!(ring.jwks()["keys"][0]["x"], b64url(&public_key));
+        let verifier = UnparsedPublicKey::new(&ED25519, &public_key);
+        assert!(verifier.verify(b"tree head", &signature).is_ok());
+
+        let es256 = Keyring::ephemeral(SigningAlgorithm::ES256);
+        assert!(es256.sign_message(b"tree head").is_err());
+    }
+
+    #[test]
+    fn unknown_algorithm_is_rejected() {
+        assert_eq!(SigningAlgorithm::parse("EdDSA"), Some(SigningAlgorithm::EdDSA));
+        assert_eq!(SigningAlgorithm::parse("ES256"), Some(SigningAlgorithm::ES256));
+        assert_eq!(SigningAlgorithm::parse("HS256"), None);
+    }
+}
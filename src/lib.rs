@@ -2,28 +2,44 @@
 // The binary crate (main.rs) uses these modules directly via `mod`.
 // Integration tests in tests/ import them from this lib crate.
 
+pub mod accounts;
 pub mod api;
+pub mod api_tokens;
 pub mod auth;
 pub mod cache;
+pub mod captcha;
 pub mod config;
 pub mod crypto;
+pub mod data_export;
 pub mod db;
+pub mod email;
 pub mod errors;
+pub mod jwt_keys;
+pub mod login_throttle;
+pub mod member_list;
 pub mod memory_store;
 pub mod middleware;
 pub mod models;
+pub mod oidc;
 pub mod permissions;
+pub mod pow;
+pub mod presence;
 pub mod pubsub;
+pub mod rekey;
+pub mod revocation;
+pub mod sealed_sender;
+pub mod security_events;
 pub mod storage;
 pub mod tls;
+pub mod transparency;
 pub mod livekit_proc;
 pub mod ws;
 #[cfg(feature = "embed-ui")]
 pub mod embedded_ui;
 
 use axum::{
-    extract::DefaultBodyLimit,
-    http::{header, HeaderValue, Method},
+    extract::{DefaultBodyLimit, State},
+    http::{header, HeaderValue, Method, StatusCode},
     middleware as axum_mw,
     routing::{delete, get, post, put},
     Router,
@@ -48,17 +64,40 @@ pub struct AppState {
     pub redis: Option<redis::aio::ConnectionManager>,
     pub config: AppConfig,
     pub storage_key: [u8; 32],
+    /// Access-token signing keys, synced from the database
+    pub jwt_keys: jwt_keys::Keyring,
+    /// Key transparency tree-head signing keys, synced from the database
+    pub tree_head_keys: jwt_keys::Keyring,
+    /// Sealed-sender certificate signing keys, synced from the database
+    pub sender_cert_keys: jwt_keys::Keyring,
     pub storage: storage::Storage,
     pub connections: ConnectionMap,
     pub channel_broadcasts: ChannelBroadcastMap,
     pub pubsub_subscriptions: pubsub::PubSubSubscriptions,
     pub memory: memory_store::MemoryStore,
+    /// Per-IP rate limiter for the unauthenticated auth endpoints (10 req / 60s);
+    /// its pressure also raises PoW difficulty
+    pub auth_limiter: RateLimiter,
     /// Per-user rate limiter for WebSocket message sending (30 msg / 10s)
     pub ws_rate_limiter: UserRateLimiter,
     /// Per-user rate limiter for write API endpoints (30 req / 60s)
     pub api_rate_limiter: UserRateLimiter,
+    /// Key bundle fetches per (requester, target) pair (10 / hour), so one
+    /// account can't drain another's one-time prekeys
+    pub key_bundle_limiter: UserRateLimiter<(uuid::Uuid, uuid::Uuid)>,
+    /// Per-user limit on data export requests (3 / day)
+    pub data_export_limiter: UserRateLimiter,
+    /// Cooldown on `PrekeysLow` notices per (device, exhausted) (1 / hour),
+    /// so every bundle fetch below the threshold doesn't re-notify
+    pub prekeys_low_cooldown: UserRateLimiter<(uuid::Uuid, bool)>,
     /// WebSocket sessions for resume support
     pub sessions: ws::SessionMap,
+    /// Cached member lists for lazy member list subscriptions
+    pub member_lists: member_list::MemberListMap,
+    /// Random per-process ID, so an instance can skip its own pub/sub events
+    pub instance_id: uuid::Uuid,
+    /// Shutdown drain state for WebSocket connections
+    pub ws_drain: ws::WsDrain,
 }
 
 // ─── Router ────────────────────────────────────────────
@@ -104,49 +143,108 @@ pub fn build_router(state: AppState) -> Router {
     let global_limiter = RateLimiter::new(state.config.max_requests_per_minute, 60);
     middleware::spawn_rate_limit_cleanup(global_limiter.clone());
 
-    // Stricter limit for auth endpoints (10 req/min per IP to resist brute-force)
-    let auth_limiter = RateLimiter::new(10, 60);
-
-    // Auth routes (no authentication required) — stricter rate limit
-    let auth_limiter_clone = auth_limiter.clone();
+    // Auth routes (no authentication required) — stricter per-IP limit from
+    // state.auth_limiter to resist brute-force
+    let auth_limiter_clone = state.auth_limiter.clone();
     let auth_routes = Router::new()
         .route("/challenge", get(api::auth_routes::pow_challenge))
         .route("/register", post(api::auth_routes::register))
         .route("/login", post(api::auth_routes::login))
         .route("/refresh", post(api::auth_routes::refresh_token))
         .route("/invite-required", get(api::registration_invites::invite_required))
+        .route("/methods", get(api::oidc::login_methods))
+        .route("/oidc/authorize", get(api::oidc::authorize))
+        .route("/oidc/callback", post(api::oidc::callback))
+        .route("/email/verify", post(api::email::verify_email))
+        .route("/password-reset", post(api::email::request_password_reset))
+        .route("/password-reset/confirm", post(api::email::confirm_password_reset))
+        .route("/link", post(api::device_link::start_device_link))
         .layer(axum_mw::from_fn(move |req, next| {
             let limiter = auth_limiter_clone.clone();
             rate_limit_middleware(limiter, req, next)
         }));
 
+    // Device link polling — a waiting client polls every few seconds for up
+    // to the link's 5 minutes, which would exhaust the auth limit; the
+    // unguessable link_token is what keeps it safe, so it gets its own
+    let link_poll_limiter = RateLimiter::new(60, 60);
+    middleware::spawn_rate_limit_cleanup(link_poll_limiter.clone());
+    let link_poll_routes = Router::new()
+        .route("/link/:link_id/poll", post(api::device_link::poll_device_link))
+        .layer(axum_mw::from_fn(move |req, next| {
+            let limiter = link_poll_limiter.clone();
+            rate_limit_middleware(limiter, req, next)
+        }));
+
     // Auth routes (authentication required)
     let auth_protected = Router::new()
         .route("/logout", post(api::auth_routes::logout))
         .route("/password", put(api::auth_routes::change_password))
         .route("/sessions", get(api::auth_routes::list_sessions))
         .route("/sessions/:family_id", delete(api::auth_routes::revoke_session))
+        .route("/tokens", get(api::auth_routes::list_api_tokens).post(api::auth_routes::create_api_token))
+        .route("/tokens/:token_id", delete(api::auth_routes::revoke_api_token))
+        .route("/security-events", get(api::auth_routes::list_security_events))
         .route("/totp/setup", post(api::auth_routes::totp_setup))
         .route("/totp/verify", post(api::auth_routes::totp_verify))
         .route("/totp", delete(api::auth_routes::totp_disable))
-        .route("/delete-account", post(api::auth_routes::delete_account));
+        .route(
+            "/email",
+            get(api::email::email_status)
+                .put(api::email::set_email)
+                .delete(api::email::remove_email),
+        )
+        .route("/link/:link_id", get(api::device_link::get_device_link))
+        .route("/link/:link_id/approve", post(api::device_link::approve_device_link))
+        .route("/deactivate", post(api::auth_routes::deactivate_account))
+        .route("/delete-account", post(api::auth_routes::delete_account))
+        .route("/oidc/link", post(api::oidc::start_link).delete(api::oidc::unlink))
+        .route("/oidc/identities", get(api::oidc::list_identities));
 
     // Key management routes
     let key_routes = Router::new()
         .route("/identity", put(api::keys::update_identity_keys))
         .route("/prekeys", post(api::keys::upload_prekeys).delete(api::keys::delete_prekeys))
         .route("/prekeys/count", get(api::keys::prekey_count))
+        .route("/last-resort", put(api::keys::upload_last_resort_prekey))
+        .route("/devices", get(api::keys::list_devices).post(api::keys::register_device))
+        .route("/devices/:device_id", delete(api::keys::remove_device))
+        .route("/transparency/head", get(api::transparency::get_tree_head))
+        .route("/sender-certificate", get(api::keys::get_sender_certificate))
+        .route("/mls/key-packages", post(api::mls::upload_key_packages))
+        .route("/mls/key-packages/count", get(api::mls::key_package_count))
+        .route("/mls/welcomes", get(api::mls::get_welcomes))
+        .route("/mls/welcomes/:welcome_id", delete(api::mls::delete_welcome))
         .route(
             "/backup",
             put(api::key_backup::upload_key_backup)
                 .get(api::key_backup::get_key_backup)
                 .delete(api::key_backup::delete_key_backup),
         )
-        .route("/backup/status", get(api::key_backup::get_key_backup_status));
+        .route("/backup/status", get(api::key_backup::get_key_backup_status))
+        .route("/backup/versions", get(api::key_backup::list_key_backup_versions))
+        .route("/backup/versions/:version", get(api::key_backup::get_key_backup_version))
+        .route(
+            "/backup/versions/:version/restore",
+            post(api::key_backup::restore_key_backup_version),
+        )
+        .route("/backup/versions/:version/sessions", get(api::key_backup::get_backup_sessions))
+        .route("/backup/sessions", put(api::key_backup::upload_backup_sessions))
+        .route("/backup/slots", post(api::key_backup::add_key_backup_slot))
+        .route("/backup/slots/:slot_id", delete(api::key_backup::delete_key_backup_slot))
+        .route("/backup/access-log", get(api::key_backup::get_key_backup_access_log))
+        .route("/verifications", get(api::verification::list_verification_states))
+        .route(
+            "/verifications/:contact_id",
+            put(api::verification::upload_verification_state)
+                .delete(api::verification::delete_verification_state),
+        );
 
     // User routes
     let user_routes = Router::new()
         .route("/:user_id/keys", get(api::keys::get_key_bundle))
+        .route("/:user_id/keys/log", get(api::transparency::get_user_key_log))
+        .route("/:user_id/mls/key-packages", post(api::mls::claim_key_packages))
         .route("/:user_id/profile", get(api::users::get_profile))
         .route("/:user_id/avatar", get(api::users::get_avatar))
         .route("/:user_id/banner", get(api::users::get_banner))
@@ -160,13 +258,18 @@ pub fn build_router(state: AppState) -> Router {
         .route("/banner", post(api::users::upload_banner))
         .route("/blocked", get(api::users::get_blocked_users))
         .route("/profile-keys", put(api::users::distribute_profile_keys))
-        .route("/:user_id/profile-key", get(api::users::get_profile_key));
+        .route("/:user_id/profile-key", get(api::users::get_profile_key))
+        .route(
+            "/@me/export",
+            get(api::users::get_data_export).post(api::users::request_data_export),
+        );
 
     // Server routes
     let server_routes = Router::new()
         .route("/", get(api::servers::list_servers))
         .route("/", post(api::servers::create_server))
         .route("/:server_id", get(api::servers::get_server).patch(api::servers::update_server).delete(api::servers::delete_server))
+        .route("/:server_id/owner", put(api::servers::transfer_ownership))
         .route(
             "/:server_id/channels",
             get(api::servers::list_server_channels),
@@ -205,6 +308,10 @@ pub fn build_router(state: AppState) -> Router {
             "/:server_id/invites/:invite_id",
             delete(api::invites::delete_invite),
         )
+        .route(
+            "/:server_id/key-epochs",
+            get(api::server_keys::get_server_key_epochs).put(api::server_keys::distribute_server_keys),
+        )
         .route(
             "/:server_id/members/@me/permissions",
             get(api::servers::get_my_permissions),
@@ -318,6 +425,14 @@ pub fn build_router(state: AppState) -> Router {
             "/:channel_id/members/keys",
             get(api::sender_keys::get_channel_member_keys),
         )
+        .route(
+            "/:channel_id/mls",
+            get(api::mls::get_group).post(api::mls::create_group),
+        )
+        .route(
+            "/:channel_id/mls/handshake",
+            get(api::mls::get_handshakes).post(api::mls::send_handshake),
+        )
         .route(
             "/:channel_id/reactions",
             get(api::messages::get_channel_reactions),
@@ -374,9 +489,10 @@ pub fn build_router(state: AppState) -> Router {
     let presence_routes = Router::new()
         .route("/presence", get(api::presence::get_presence));
 
-    // DM privacy route
+    // Privacy routes
     let dm_privacy_routes = Router::new()
-        .route("/users/dm-privacy", put(api::friends::update_dm_privacy));
+        .route("/users/dm-privacy", put(api::friends::update_dm_privacy))
+        .route("/users/presence-privacy", put(api::friends::update_presence_privacy));
 
     // Report routes
     let report_routes = Router::new()
@@ -446,6 +562,10 @@ pub fn build_router(state: AppState) -> Router {
         .route("/users", get(api::admin::list_users))
         .route("/users/:user_id/admin", put(api::admin::set_admin))
         .route("/users/:user_id", delete(api::admin::delete_user))
+        .route("/key-transparency", get(api::transparency::admin_export_log))
+        .route("/signing-keys", get(api::signing_keys::list_signing_keys))
+        .route("/signing-keys/rotate", post(api::signing_keys::rotate_signing_key))
+        .route("/email-hash-keys", get(api::signing_keys::email_hash_key_usage))
         .route(
             "/registration-invites",
             get(api::registration_invites::admin_list_invites)
@@ -461,12 +581,16 @@ pub fn build_router(state: AppState) -> Router {
         .route("/search", get(api::gifs::search_gifs))
         .route("/trending", get(api::gifs::trending_gifs));
 
+    // Data export download (the signed link stands in for auth)
+    let export_routes = Router::new()
+        .route("/:export_id/download", get(api::users::download_data_export));
+
     let message_routes = Router::new()
         .route("/:message_id/reactions", get(api::messages::get_message_reactions));
 
     // Assemble the full API
     let api = Router::new()
-        .nest("/auth", auth_routes.merge(auth_protected))
+        .nest("/auth", auth_routes.merge(link_poll_routes).merge(auth_protected))
         .nest("/admin", admin_routes)
         .nest("/keys", key_routes)
         .nest("/users", user_routes)
@@ -477,6 +601,7 @@ pub fn build_router(state: AppState) -> Router {
         .nest("/friends", friend_routes)
         .nest("/invites", invite_routes)
         .nest("/attachments", attachment_routes)
+        .nest("/exports", export_routes)
         .merge(link_preview_routes)
         .merge(presence_routes)
         .merge(dm_privacy_routes)
@@ -487,17 +612,21 @@ pub fn build_router(state: AppState) -> Router {
 
     Router::new()
         .route("/api/v1/ws", get(ws::ws_handler))
+        .route("/api/v1/ws/ticket", post(ws::ws_ticket))
         .nest("/api/v1", api)
+        .route("/.well-known/jwks.json", get(api::signing_keys::jwks))
+        .route("/.well-known/haven-keys.json", get(api::signing_keys::service_keys))
         .route("/health", get(health_check))
         .layer(CompressionLayer::new())
-        // TraceLayer: custom span excludes remote_addr (IP privacy)
+        // TraceLayer: custom span excludes remote_addr (IP privacy) and the
+        // query string (may carry WS tickets or tokens)
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|req: &axum::extract::Request| {
                     tracing::info_span!(
                         "http_request",
                         method = %req.method(),
-                        uri = %req.uri(),
+                        path = %req.uri().path(),
                         version = ?req.version(),
                     )
                 })
@@ -543,6 +672,11 @@ pub fn build_router(state: AppState) -> Router {
         .with_state(state)
 }
 
-async fn health_check() -> &'static str {
-    "ok"
+async fn health_check(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    // Report unhealthy while draining so load balancers stop routing here
+    if state.ws_drain.is_draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else {
+        (StatusCode::OK, "ok")
+    }
 }
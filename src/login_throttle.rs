@@ -0,0 +1,175 @@
+//! Per-account login throttling.
+//!
+//! Failed password (or TOTP) attempts are counted per username, whether or not
+//! the account exists. The first `login_lockout_free_attempts` failures cost
+//! nothing; each one after that locks the username for
+//! `login_lockout_base_secs`, doubling per failure up to
+//! `login_lockout_max_secs`. A successful login clears the counter, and a
+//! counter left alone for a day is forgotten.
+
+use std::time::{Duration, Instant};
+
+use crate::errors::{AppError, AppResult};
+use crate::AppState;
+
+/// How long a failure count survives without further failures (seconds)
+const FAILURE_MEMORY_SECS: u64 = 24 * 3600;
+
+/// Failed-attempt state for one username.
+#[derive(Debug, Clone, Copy)]
+pub struct LoginFailures {
+    pub failures: u32,
+    pub locked_until: Option<Instant>,
+    /// When the whole entry may be forgotten
+    pub expires_at: Instant,
+}
+
+/// Lockout after the `failures`-th consecutive failure: none while within
+/// `free`, then `base` seconds doubling per further failure, capped at `max`.
+pub fn lockout_secs(failures: u32, free: u32, base: u64, max: u64) -> u64 {
+    if failures <= free {
+        return 0;
+    }
+    let doublings = (failures - free - 1).min(32);
+    base.saturating_mul(1u64 << doublings).min(max.max(base))
+}
+
+fn throttle_key(username: &str) -> String {
+    format!("haven:login_throttle:{}", username.to_lowercase())
+}
+
+/// Fail with `LoginLocked` while the username is locked out.
+pub async fn check(state: &AppState, username: &str) -> AppResult<()> {
+    let remaining = if let Some(mut redis) = state.redis.clone() {
+        let locked_until: Option<i64> = redis::cmd("HGET")
+            .arg(throttle_key(username))
+            .arg("locked_until")
+            .query_async(&mut redis)
+            .await
+            .unwrap_or(None);
+        locked_until
+            .map(|until| until - chrono::Utc::now().timestamp())
+            .filter(|secs| *secs > 0)
+            .map(|secs| secs as u64)
+    } else {
+        let now = Instant::now();
+        state
+            .memory
+            .login_failures
+            .get(&username.to_lowercase())
+            .and_then(|entry| entry.locked_until)
+            .filter(|until| *until > now)
+            .map(|until| (until - now).as_secs().max(1))
+    };
+
+    match remaining {
+        Some(secs) => Err(AppError::LoginLocked(secs)),
+        None => Ok(()),
+    }
+}
+
+/// Count a failed attempt. Returns the new failure count and the lockout
+/// it triggered in seconds (0 for none).
+pub async fn record_failure(state: &AppState, username: &str) -> (u32, u64) {
+    let config = &state.config;
+
+    let failures = if let Some(mut redis) = state.redis.clone() {
+        let key = throttle_key(username);
+        let result: Result<(u32,), redis::RedisError> = redis::pipe()
+            .cmd("HINCRBY")
+            .arg(&key)
+            .arg("failures")
+            .arg(1)
+            .cmd("EXPIRE")
+            .arg(&key)
+            .arg(FAILURE_MEMORY_SECS)
+            .ignore()
+            .query_async(&mut redis)
+            .await;
+        match result {
+            Ok((failures,)) => failures,
+            Err(e) => {
+                tracing::warn!("Failed to count login failure: {}", e);
+                return (0, 0);
+            }
+        }
+    } else {
+        let expires_at = Instant::now() + Duration::from_secs(FAILURE_MEMORY_SECS);
+        let mut entry = state
+            .memory
+            .login_failures
+            .entry(username.to_lowercase())
+            .or_insert(LoginFailures { failures: 0, locked_until: None, expires_at });
+        entry.failures += 1;
+        entry.expires_at = expires_at;
+        entry.failures
+    };
+
+    let secs = lockout_secs(
+        failures,
+        config.login_lockout_free_attempts,
+        config.login_lockout_base_secs,
+        config.login_lockout_max_secs,
+    );
+    if secs > 0 {
+        if let Some(mut redis) = state.redis.clone() {
+            let until = chrono::Utc::now().timestamp() + secs as i64;
+            let result: Result<(), redis::RedisError> = redis::cmd("HSET")
+                .arg(throttle_key(username))
+                .arg("locked_until")
+                .arg(until)
+                .query_async(&mut redis)
+                .await;
+            if let Err(e) = result {
+                tracing::warn!("Failed to lock out username: {}", e);
+            }
+        } else if let Some(mut entry) = state.memory.login_failures.get_mut(&username.to_lowercase()) {
+            entry.locked_until = Some(Instant::now() + Duration::from_secs(secs));
+        }
+    }
+    (failures, secs)
+}
+
+/// Forget the failures of a username after a successful login.
+pub async fn clear(state: &AppState, username: &str) {
+    if let Some(mut redis) = state.redis.clone() {
+        let result: Result<(), redis::RedisError> = redis::cmd("DEL")
+            .arg(throttle_key(username))
+            .query_async(&mut redis)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to clear login failures: {}", e);
+        }
+    } else {
+        state.memory.login_failures.remove(&username.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_attempts_do_not_lock() {
+        for failures in 0..=5 {
+            assert_eq!(lockout_secs(failures, 5, 30, 3600), 0);
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_per_failure() {
+        assert_eq!(lockout_secs(6, 5, 30, 3600), 30);
+        assert_eq!(lockout_secs(7, 5, 30, 3600), 60);
+        assert_eq!(lockout_secs(8, 5, 30, 3600), 120);
+    }
+
+    #[test]
+    fn lockout_is_capped() {
+        // 30 * 2^6 is still under the cap; 30 * 2^7 passes it
+        assert_eq!(lockout_secs(12, 5, 30, 3600), 1920);
+        assert_eq!(lockout_secs(13, 5, 30, 3600), 3600);
+        assert_eq!(lockout_secs(u32::MAX, 5, 30, 3600), 3600);
+        // A max below the base never shortens the base lockout
+        assert_eq!(lockout_secs(6, 5, 30, 10), 30);
+    }
+}
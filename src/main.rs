@@ -3,14 +3,20 @@ use std::sync::Arc;
 use dashmap::DashMap;
 
 use haven_backend::{
+    accounts,
     build_router,
+    cache,
     config::AppConfig,
+    data_export,
     db::{self, DbPools},
+    jwt_keys::{self, KeyPurpose, Keyring},
     livekit_proc,
+    member_list,
     memory_store::MemoryStore,
-    middleware::{spawn_user_rate_limit_cleanup, UserRateLimiter},
+    middleware::{spawn_rate_limit_cleanup, spawn_user_rate_limit_cleanup, RateLimiter, UserRateLimiter},
     pubsub,
     storage::Storage,
+    ws,
     AppState,
 };
 
@@ -111,11 +117,38 @@ async fn main() {
     let storage = Storage::from_config(&config).await;
     let storage_key = *storage.encryption_key();
 
+    // Signing keys for access tokens, tree heads and sender certificates:
+    // create or rotate as needed, then keep in sync
+    let jwt_keys = Keyring::new(KeyPurpose::AccessToken);
+    let tree_head_keys = Keyring::new(KeyPurpose::TreeHead);
+    let sender_cert_keys = Keyring::new(KeyPurpose::SenderCertificate);
+    for ring in [&jwt_keys, &tree_head_keys, &sender_cert_keys] {
+        jwt_keys::sync(&db, &config, &storage_key, ring)
+            .await
+            .expect("Failed to load signing keys");
+    }
+    jwt_keys::spawn_rotation_task(
+        db.clone(),
+        config.clone(),
+        storage_key,
+        vec![jwt_keys.clone(), tree_head_keys.clone(), sender_cert_keys.clone()],
+    );
+
+    // Stricter per-IP limit for auth endpoints (10 req/min to resist brute-force)
+    let auth_limiter = RateLimiter::new(10, 60);
+    spawn_rate_limit_cleanup(auth_limiter.clone());
+
     // Per-user rate limiters
     let ws_rate_limiter = UserRateLimiter::new(30, 10); // 30 messages per 10 seconds
     let api_rate_limiter = UserRateLimiter::new(30, 60); // 30 write ops per minute
     spawn_user_rate_limit_cleanup(ws_rate_limiter.clone());
     spawn_user_rate_limit_cleanup(api_rate_limiter.clone());
+    let key_bundle_limiter = UserRateLimiter::new(10, 3600); // 10 bundle fetches per target per hour
+    spawn_user_rate_limit_cleanup(key_bundle_limiter.clone());
+    let data_export_limiter = UserRateLimiter::new(3, 86400); // 3 export requests per day
+    spawn_user_rate_limit_cleanup(data_export_limiter.clone());
+    let prekeys_low_cooldown = UserRateLimiter::new(1, 3600); // 1 low-prekey notice per device per hour
+    spawn_user_rate_limit_cleanup(prekeys_low_cooldown.clone());
 
     // Build application state (pubsub_subscriptions added after start_subscriber)
     let mut state = AppState {
@@ -123,14 +156,24 @@ async fn main() {
         redis,
         config: config.clone(),
         storage_key,
+        jwt_keys,
+        tree_head_keys,
+        sender_cert_keys,
         storage,
         connections: Arc::new(DashMap::new()),
         channel_broadcasts: Arc::new(DashMap::new()),
         pubsub_subscriptions: pubsub::empty_subscriptions(),
         memory,
+        auth_limiter,
         ws_rate_limiter,
         api_rate_limiter,
+        key_bundle_limiter,
+        data_export_limiter,
+        prekeys_low_cooldown,
         sessions: Arc::new(DashMap::new()),
+        member_lists: Arc::new(DashMap::new()),
+        instance_id: uuid::Uuid::new_v4(),
+        ws_drain: ws::WsDrain::default(),
     };
 
     // Start Redis pub/sub subscriber and store the subscriptions handle
@@ -189,7 +232,73 @@ async fn main() {
         });
     }
 
+    // Worker: Clear expired custom statuses every 60 seconds and tell
+    // friends, DM participants and member lists that they are gone.
+    {
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                let expired = match db::queries::clear_expired_custom_statuses(
+                    state_clone.db.write(),
+                )
+                .await
+                {
+                    Ok(ids) => ids,
+                    Err(e) => {
+                        tracing::warn!("Custom status expiry failed: {}", e);
+                        continue;
+                    }
+                };
+                for user_id in expired {
+                    cache::invalidate(
+                        state_clone.redis.clone().as_mut(),
+                        &state_clone.memory,
+                        &format!("haven:user:{}", user_id),
+                    )
+                    .await;
+                    ws::broadcast_custom_status(&state_clone, user_id, None, None, None).await;
+                    member_list::refresh_user(&state_clone, user_id).await;
+                }
+            }
+        });
+    }
+
+    // Worker: Erase accounts whose deletion grace period is over (hourly)
+    {
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match accounts::erase_due_accounts(&state_clone).await {
+                    Ok(count) if count > 0 => tracing::info!("Erased {} accounts scheduled for deletion", count),
+                    Err(e) => tracing::error!("Failed to erase accounts scheduled for deletion: {}", e),
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    // Worker: Delete expired personal data exports (hourly)
+    {
+        let state_clone = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match data_export::purge_expired(&state_clone).await {
+                    Ok(count) if count > 0 => tracing::info!("Deleted {} expired data exports", count),
+                    Err(e) => tracing::error!("Failed to delete expired data exports: {}", e),
+                    _ => {}
+                }
+            }
+        });
+    }
+
     // Build router
+    let drain_state = state.clone();
     let app = build_router(state);
 
     // ─── Embedded Web UI ──────────────────────────────────
@@ -240,24 +349,28 @@ async fn main() {
             .clone()
             .layer(axum::middleware::from_fn(inject_https_proto));
 
+        // Both listeners stop after the one drain; HTTPS gets the drain
+        // timeout again for in-flight requests to finish.
+        let https_handle = axum_server::Handle::new();
+        let https_grace = tokio::time::Duration::from_secs(config.ws_drain_timeout_secs);
         let http_server = axum::serve(listener, app.into_make_service())
-            .with_graceful_shutdown(shutdown_signal());
+            .with_graceful_shutdown({
+                let https_handle = https_handle.clone();
+                async move {
+                    shutdown_and_drain(drain_state).await;
+                    https_handle.graceful_shutdown(Some(https_grace));
+                }
+            });
 
         let https_server = axum_server::bind_rustls(tls_addr, rustls_config)
+            .handle(https_handle)
             .serve(app_https.into_make_service());
 
-        tokio::select! {
-            result = http_server => {
-                result.expect("HTTP server error");
-            }
-            result = https_server => {
-                result.expect("HTTPS server error");
-            }
-        }
+        tokio::try_join!(async { http_server.await }, https_server).expect("Server error");
     } else {
         // HTTP only
         axum::serve(listener, app.into_make_service())
-            .with_graceful_shutdown(shutdown_signal())
+            .with_graceful_shutdown(shutdown_and_drain(drain_state))
             .await
             .expect("Server error");
     }
@@ -265,6 +378,13 @@ async fn main() {
     tracing::info!("Haven backend shut down gracefully");
 }
 
+/// Wait for a shutdown signal, then move WebSocket clients off this instance
+/// before the listener stops accepting connections.
+async fn shutdown_and_drain(state: AppState) {
+    shutdown_signal().await;
+    ws::drain(&state).await;
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
@@ -359,6 +479,57 @@ fn spawn_background_workers(db: DbPools, config: &AppConfig) {
         }
     });
 
+    // One-shot: log identity keys published before the key transparency log existed
+    {
+        let pool = db.primary().clone();
+        tokio::spawn(async move {
+            let keys = match db::queries::list_unlogged_device_keys(&pool).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::error!("Failed to list identity keys missing from the transparency log: {}", e);
+                    return;
+                }
+            };
+            // Other instances may be backfilling too; appends re-check under the lock
+            let mut logged = 0;
+            for (user_id, device_id, identity_key) in &keys {
+                match db::queries::append_transparency_log(&pool, *user_id, *device_id, identity_key, true)
+                    .await
+                {
+                    Ok(Some(_)) => logged += 1,
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::error!("Failed to backfill transparency log: {}", e);
+                        return;
+                    }
+                }
+            }
+            if logged > 0 {
+                tracing::info!("Backfilled {} identity keys into the transparency log", logged);
+            }
+        });
+    }
+
+    // Worker: Drop signed prekeys past their rotation grace window (hourly)
+    {
+        let pool = db.primary().clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                match db::queries::purge_expired_previous_signed_prekeys(&pool).await {
+                    Ok(count) if count > 0 => {
+                        tracing::info!("Dropped {} retained signed prekeys", count);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to drop retained signed prekeys: {}", e);
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
     // Worker: Purge old audit log entries (daily, metadata minimization)
     if config.audit_log_retention_days > 0 {
         let pool = db.primary().clone();
@@ -372,6 +543,28 @@ fn spawn_background_workers(db: DbPools, config: &AppConfig) {
                     Err(e) => tracing::error!("Failed to purge audit logs: {}", e),
                     _ => {}
                 }
+                match db::queries::purge_old_key_backup_access_logs(&pool, days).await {
+                    Ok(count) if count > 0 => tracing::info!("Purged {} old key backup access entries", count),
+                    Err(e) => tracing::error!("Failed to purge key backup access log: {}", e),
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    // Worker: Purge old per-user security events (daily)
+    if config.security_event_retention_days > 0 {
+        let pool = db.primary().clone();
+        let days = config.security_event_retention_days;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(86400));
+            loop {
+                interval.tick().await;
+                match db::queries::purge_old_security_events(&pool, days).await {
+                    Ok(count) if count > 0 => tracing::info!("Purged {} old security events", count),
+                    Err(e) => tracing::error!("Failed to purge security events: {}", e),
+                    _ => {}
+                }
             }
         });
     }
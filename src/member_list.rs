@@ -0,0 +1,569 @@
+//! Lazy member list subscriptions.
+//!
+//! Instead of fetching every member and receiving presence for all of them,
+//! a client subscribes to a window of a server's sorted member list over the
+//! gateway. While anyone on this instance is subscribed to a server, its list
+//! is cached here; changes are diffed against each subscriber's window and
+//! only the resulting insert/update/delete ops are pushed. Changes are also
+//! relayed through Redis so other instances update their cached lists.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+use crate::api::presence::fetch_presence;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::models::{
+    MemberListGroup, MemberListGrouping, MemberListItem, MemberListOp, ServerMemberResponse,
+    WsServerMessage,
+};
+use crate::AppState;
+
+/// Maximum number of rows a single subscription window may span.
+pub const MAX_RANGE_SPAN: u32 = 100;
+
+/// Cached member lists for servers with local subscribers. Maps server_id -> list.
+pub type MemberListMap = Arc<DashMap<Uuid, Arc<Mutex<ServerMemberList>>>>;
+
+struct Subscriber {
+    user_id: Uuid,
+    tx: mpsc::UnboundedSender<WsServerMessage>,
+    group_by: MemberListGrouping,
+    range: [u32; 2],
+    /// The rows this subscriber currently holds for its window
+    window: Vec<MemberListItem>,
+}
+
+/// A server's member list, plus everyone on this instance watching it.
+pub struct ServerMemberList {
+    members: Vec<ServerMemberResponse>,
+    /// role_id -> position (default role excluded)
+    role_positions: HashMap<Uuid, i32>,
+    /// user_id -> public status ("offline" for invisible)
+    statuses: HashMap<Uuid, String>,
+    /// session_id -> subscriber
+    subscribers: HashMap<Uuid, Subscriber>,
+}
+
+/// A flattened list for one grouping mode.
+struct Snapshot {
+    items: Vec<MemberListItem>,
+    groups: Vec<MemberListGroup>,
+    online_count: u32,
+}
+
+impl ServerMemberList {
+    async fn load(state: &AppState, server_id: Uuid) -> AppResult<Self> {
+        let members = queries::get_server_members(state.db.read(), server_id, i64::MAX, 0).await?;
+        let role_positions = queries::get_server_roles(state.db.read(), server_id)
+            .await?
+            .into_iter()
+            .filter(|r| !r.is_default)
+            .map(|r| (r.id, r.position))
+            .collect();
+        let ids: Vec<Uuid> = members.iter().map(|m| m.user_id).collect();
+        let statuses = fetch_presence(state, &ids, None)
+            .await
+            .into_iter()
+            .map(|p| (p.user_id, p.status))
+            .collect();
+
+        Ok(Self {
+            members,
+            role_positions,
+            statuses,
+            subscribers: HashMap::new(),
+        })
+    }
+
+    fn status_of(&self, user_id: Uuid) -> &str {
+        self.statuses.get(&user_id).map(String::as_str).unwrap_or("offline")
+    }
+
+    fn build(&self, group_by: MemberListGrouping) -> Snapshot {
+        // Group order: roles by position (highest first), then "online", then "offline"
+        let mut buckets: BTreeMap<(u8, Reverse<i32>, String), Vec<&ServerMemberResponse>> =
+            BTreeMap::new();
+        let mut online_count = 0;
+
+        for m in &self.members {
+            let key = if self.status_of(m.user_id) == "offline" {
+                (2, Reverse(0), "offline".to_string())
+            } else {
+                online_count += 1;
+                let top_role = match group_by {
+                    MemberListGrouping::Status => None,
+                    MemberListGrouping::Role => m
+                        .role_ids
+                        .iter()
+                        .filter_map(|id| self.role_positions.get(id).map(|pos| (*pos, *id)))
+                        .max(),
+                };
+                match top_role {
+                    Some((pos, id)) => (0, Reverse(pos), id.to_string()),
+                    None => (1, Reverse(0), "online".to_string()),
+                }
+            };
+            buckets.entry(key).or_default().push(m);
+        }
+
+        let mut items = Vec::with_capacity(self.members.len() + buckets.len());
+        let mut groups = Vec::with_capacity(buckets.len());
+        for ((_, _, id), mut members) in buckets {
+            members.sort_by_cached_key(|m| (sort_name(m).to_lowercase(), m.user_id));
+            let group = MemberListGroup {
+                id,
+                count: members.len() as u32,
+            };
+            items.push(MemberListItem::Group(group.clone()));
+            groups.push(group);
+            for m in members {
+                items.push(MemberListItem::Member {
+                    member: m.clone(),
+                    status: self.status_of(m.user_id).to_string(),
+                });
+            }
+        }
+
+        Snapshot {
+            items,
+            groups,
+            online_count,
+        }
+    }
+
+    /// Recompute every subscriber's window and send the ops that changed.
+    /// Subscribers that disconnected or are no longer members are dropped.
+    fn push_updates(&mut self, server_id: Uuid) {
+        let member_ids: HashSet<Uuid> = self.members.iter().map(|m| m.user_id).collect();
+        self.subscribers
+            .retain(|_, s| !s.tx.is_closed() && member_ids.contains(&s.user_id));
+
+        let mut snapshots: HashMap<MemberListGrouping, Snapshot> = HashMap::new();
+        for sub in self.subscribers.values() {
+            snapshots
+                .entry(sub.group_by)
+                .or_insert_with(|| self.build(sub.group_by));
+        }
+
+        let member_count = self.members.len() as u32;
+        for sub in self.subscribers.values_mut() {
+            let snap = &snapshots[&sub.group_by];
+            let window = window_of(&snap.items, sub.range);
+            let ops = diff_window(sub.range[0], &sub.window, window);
+            if ops.is_empty() {
+                continue;
+            }
+            sub.window = window.to_vec();
+            let _ = sub.tx.send(update_message(server_id, sub.group_by, member_count, snap, ops));
+        }
+    }
+}
+
+fn sort_name(m: &ServerMemberResponse) -> &str {
+    m.nickname
+        .as_deref()
+        .or(m.display_name.as_deref())
+        .unwrap_or(&m.username)
+}
+
+fn window_of(items: &[MemberListItem], [start, end]: [u32; 2]) -> &[MemberListItem] {
+    let len = items.len();
+    &items[(start as usize).min(len)..(end as usize).min(len)]
+}
+
+fn update_message(
+    server_id: Uuid,
+    group_by: MemberListGrouping,
+    member_count: u32,
+    snap: &Snapshot,
+    ops: Vec<MemberListOp>,
+) -> WsServerMessage {
+    WsServerMessage::MemberListUpdate {
+        server_id,
+        group_by,
+        member_count,
+        online_count: snap.online_count,
+        groups: snap.groups.clone(),
+        ops,
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ItemKey<'a> {
+    Group(&'a str),
+    Member(Uuid),
+}
+
+fn item_key(item: &MemberListItem) -> ItemKey<'_> {
+    match item {
+        MemberListItem::Group(g) => ItemKey::Group(&g.id),
+        MemberListItem::Member { member, .. } => ItemKey::Member(member.user_id),
+    }
+}
+
+/// Compute the ops that turn `old` into `new`, where both are windows
+/// beginning at absolute index `start`. Ops are applied in order.
+fn diff_window(start: u32, old: &[MemberListItem], new: &[MemberListItem]) -> Vec<MemberListOp> {
+    // Keys still ahead of the cursor in `old`
+    let mut old_left: HashSet<ItemKey> = old.iter().map(item_key).collect();
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < new.len() || j < old.len() {
+        let index = start + i as u32;
+        match (new.get(i), old.get(j)) {
+            (Some(n), Some(o)) if item_key(n) == item_key(o) => {
+                if n != o {
+                    ops.push(MemberListOp::Update { index, item: n.clone() });
+                }
+                old_left.remove(&item_key(o));
+                i += 1;
+                j += 1;
+            }
+            (Some(n), _) if !old_left.contains(&item_key(n)) => {
+                ops.push(MemberListOp::Insert { index, item: n.clone() });
+                i += 1;
+            }
+            (_, Some(o)) => {
+                // Gone from the window, or moved further down (re-inserted later)
+                ops.push(MemberListOp::Delete { index });
+                old_left.remove(&item_key(o));
+                j += 1;
+            }
+            // `old` exhausted means `old_left` is empty, so the insert arm matches
+            (_, None) => unreachable!(),
+        }
+    }
+
+    ops
+}
+
+// ─── Gateway Entry Points ──────────────────────────────
+
+/// Subscribe a connection to `range` of a server's member list. Sends a
+/// `sync` op for the window immediately. Re-subscribing replaces the range.
+pub async fn subscribe(
+    state: &AppState,
+    server_id: Uuid,
+    session_id: Uuid,
+    user_id: Uuid,
+    tx: &mpsc::UnboundedSender<WsServerMessage>,
+    group_by: MemberListGrouping,
+    range: [u32; 2],
+) -> AppResult<()> {
+    let [start, end] = range;
+    if start >= end || end - start > MAX_RANGE_SPAN {
+        return Err(AppError::Validation(format!(
+            "Invalid range: must be non-empty and span at most {} rows",
+            MAX_RANGE_SPAN
+        )));
+    }
+
+    if !queries::is_server_member(state.db.read(), server_id, user_id).await? {
+        return Err(AppError::Forbidden("Not a member of this server".into()));
+    }
+
+    let list = match state.member_lists.get(&server_id) {
+        Some(l) => l.clone(),
+        None => {
+            let loaded = ServerMemberList::load(state, server_id).await?;
+            state
+                .member_lists
+                .entry(server_id)
+                .or_insert_with(|| Arc::new(Mutex::new(loaded)))
+                .clone()
+        }
+    };
+
+    {
+        let mut guard = list.lock().await;
+        let snap = guard.build(group_by);
+        let window = window_of(&snap.items, range).to_vec();
+        let sync = MemberListOp::Sync {
+            range,
+            items: window.clone(),
+        };
+        let _ = tx.send(update_message(
+            server_id,
+            group_by,
+            guard.members.len() as u32,
+            &snap,
+            vec![sync],
+        ));
+        guard.subscribers.insert(
+            session_id,
+            Subscriber {
+                user_id,
+                tx: tx.clone(),
+                group_by,
+                range,
+                window,
+            },
+        );
+    }
+
+    // The entry may have been dropped by a concurrent last unsubscribe
+    state.member_lists.entry(server_id).or_insert(list);
+    Ok(())
+}
+
+/// Remove a connection's subscription to a server's member list.
+pub async fn unsubscribe(state: &AppState, server_id: Uuid, session_id: Uuid) {
+    let Some(list) = state.member_lists.get(&server_id).map(|l| l.clone()) else {
+        return;
+    };
+    let now_empty = {
+        let mut guard = list.lock().await;
+        guard.subscribers.remove(&session_id);
+        guard.subscribers.is_empty()
+    };
+    if now_empty {
+        state.member_lists.remove_if(&server_id, |_, l| {
+            Arc::ptr_eq(l, &list)
+                && l.try_lock().map(|g| g.subscribers.is_empty()).unwrap_or(false)
+        });
+    }
+}
+
+/// Drop every member list subscription held by a closed connection.
+pub async fn unsubscribe_session(state: &AppState, session_id: Uuid) {
+    let server_ids: Vec<Uuid> = state.member_lists.iter().map(|e| *e.key()).collect();
+    for server_id in server_ids {
+        unsubscribe(state, server_id, session_id).await;
+    }
+}
+
+// ─── Change Notifications ──────────────────────────────
+
+/// Redis channel every instance listens on for member list changes.
+pub const REDIS_CHANNEL: &str = "haven:member_lists";
+
+/// A member list change, relayed so every instance can apply it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum Change {
+    Refresh { server_id: Uuid },
+    RefreshUser { user_id: Uuid },
+    Presence { user_id: Uuid, status: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RelayedChange {
+    /// Instance that published the change (and already applied it)
+    origin: Uuid,
+    #[serde(flatten)]
+    change: Change,
+}
+
+/// Publish a change for the other instances. No-op without Redis.
+async fn publish(state: &AppState, change: Change) {
+    let Some(mut redis) = state.redis.clone() else { return };
+    let relayed = RelayedChange {
+        origin: state.instance_id,
+        change,
+    };
+    if let Ok(payload) = serde_json::to_string(&relayed) {
+        let _: Result<(), _> = redis::cmd("PUBLISH")
+            .arg(REDIS_CHANNEL)
+            .arg(&payload)
+            .query_async(&mut redis)
+            .await;
+    }
+}
+
+/// Apply a change published by another instance (called by the pub/sub subscriber).
+pub async fn apply_relayed(state: &AppState, payload: &str) {
+    let Ok(relayed) = serde_json::from_str::<RelayedChange>(payload) else {
+        return;
+    };
+    if relayed.origin == state.instance_id {
+        return;
+    }
+    match relayed.change {
+        Change::Refresh { server_id } => reload(state, server_id).await,
+        Change::RefreshUser { user_id } => reload_user(state, user_id).await,
+        Change::Presence { user_id, status } => apply_presence(state, user_id, &status).await,
+    }
+}
+
+/// Reload a server's members and roles after a membership, role or nickname
+/// change, and push the resulting ops on every instance.
+pub async fn refresh(state: &AppState, server_id: Uuid) {
+    reload(state, server_id).await;
+    publish(state, Change::Refresh { server_id }).await;
+}
+
+/// Refresh every cached list the user appears in (profile edits, account
+/// deletion), on every instance.
+pub async fn refresh_user(state: &AppState, user_id: Uuid) {
+    reload_user(state, user_id).await;
+    publish(state, Change::RefreshUser { user_id }).await;
+}
+
+/// Apply a presence change to every cached list containing the user, on
+/// every instance.
+pub async fn presence_changed(state: &AppState, user_id: Uuid, status: &str) {
+    apply_presence(state, user_id, status).await;
+    publish(
+        state,
+        Change::Presence {
+            user_id,
+            status: status.to_string(),
+        },
+    )
+    .await;
+}
+
+/// Reload one server's cached list. No-op if nobody here is subscribed.
+async fn reload(state: &AppState, server_id: Uuid) {
+    let Some(list) = state.member_lists.get(&server_id).map(|l| l.clone()) else {
+        return;
+    };
+    let fresh = match ServerMemberList::load(state, server_id).await {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::warn!("Failed to reload member list for server {}: {}", server_id, e);
+            return;
+        }
+    };
+    let mut guard = list.lock().await;
+    guard.members = fresh.members;
+    guard.role_positions = fresh.role_positions;
+    guard.statuses = fresh.statuses;
+    guard.push_updates(server_id);
+}
+
+async fn reload_user(state: &AppState, user_id: Uuid) {
+    for (server_id, list) in cached_lists(state) {
+        if list.lock().await.statuses.contains_key(&user_id) {
+            reload(state, server_id).await;
+        }
+    }
+}
+
+async fn apply_presence(state: &AppState, user_id: Uuid, status: &str) {
+    for (server_id, list) in cached_lists(state) {
+        let mut guard = list.lock().await;
+        if let Some(current) = guard.statuses.get_mut(&user_id) {
+            if current != status {
+                *current = status.to_string();
+                guard.push_updates(server_id);
+            }
+        }
+    }
+}
+
+fn cached_lists(state: &AppState) -> Vec<(Uuid, Arc<Mutex<ServerMemberList>>)> {
+    state
+        .member_lists
+        .iter()
+        .map(|e| (*e.key(), e.value().clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn member(name: &str) -> MemberListItem {
+        MemberListItem::Member {
+            member: ServerMemberResponse {
+                user_id: Uuid::from_u128(name.bytes().fold(0, |acc, b| acc * 256 + b as u128)),
+                username: name.into(),
+                display_name: None,
+                avatar_url: None,
+                joined_at: chrono::DateTime::<Utc>::from_timestamp(0, 0).unwrap(),
+                nickname: None,
+                role_ids: vec![],
+                timed_out_until: None,
+            },
+            status: "online".into(),
+        }
+    }
+
+    fn group(id: &str, count: u32) -> MemberListItem {
+        MemberListItem::Group(MemberListGroup {
+            id: id.into(),
+            count,
+        })
+    }
+
+    /// Apply ops to a client-side copy of the list, as a client would.
+    fn apply(mut list: Vec<MemberListItem>, ops: &[MemberListOp]) -> Vec<MemberListItem> {
+        for op in ops {
+            match op {
+                MemberListOp::Insert { index, item } => list.insert(*index as usize, item.clone()),
+                MemberListOp::Update { index, item } => list[*index as usize] = item.clone(),
+                MemberListOp::Delete { index } => {
+                    list.remove(*index as usize);
+                }
+                MemberListOp::Sync { .. } => unreachable!(),
+            }
+        }
+        list
+    }
+
+    fn assert_diff(old: Vec<MemberListItem>, new: Vec<MemberListItem>) -> Vec<MemberListOp> {
+        let ops = diff_window(0, &old, &new);
+        assert_eq!(apply(old, &ops), new);
+        ops
+    }
+
+    #[test]
+    fn diff_identical_is_empty() {
+        let list = vec![group("online", 2), member("a"), member("b")];
+        assert!(assert_diff(list.clone(), list).is_empty());
+    }
+
+    #[test]
+    fn diff_insert_and_delete() {
+        let ops = assert_diff(
+            vec![group("online", 2), member("a"), member("c")],
+            vec![group("online", 2), member("b"), member("c")],
+        );
+        assert_eq!(ops.len(), 2);
+    }
+
+    #[test]
+    fn diff_group_count_change_is_update() {
+        let ops = assert_diff(
+            vec![group("online", 1), member("a")],
+            vec![group("online", 2), member("a"), member("b")],
+        );
+        assert!(matches!(ops[0], MemberListOp::Update { index: 0, .. }));
+        assert!(matches!(ops[1], MemberListOp::Insert { index: 2, .. }));
+    }
+
+    #[test]
+    fn diff_member_moves_between_groups() {
+        assert_diff(
+            vec![group("online", 2), member("a"), member("b"), group("offline", 1), member("c")],
+            vec![group("online", 1), member("b"), group("offline", 2), member("a"), member("c")],
+        );
+    }
+
+    #[test]
+    fn diff_reorder_and_shrink() {
+        assert_diff(
+            vec![member("a"), member("b"), member("c"), member("d")],
+            vec![member("d"), member("c")],
+        );
+        assert_diff(vec![], vec![member("a"), member("b")]);
+        assert_diff(vec![member("a"), member("b")], vec![]);
+    }
+
+    #[test]
+    fn diff_uses_absolute_indexes() {
+        let ops = diff_window(40, &[member("a")], &[member("b")]);
+        assert!(matches!(ops[0], MemberListOp::Insert { index: 40, .. }));
+        assert!(matches!(ops[1], MemberListOp::Delete { index: 41 }));
+    }
+}
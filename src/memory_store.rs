@@ -5,6 +5,9 @@ use std::time::{Duration, Instant};
 use dashmap::DashMap;
 use uuid::Uuid;
 
+use crate::login_throttle::LoginFailures;
+use crate::presence::PresenceState;
+
 /// Active DM/group call state (ephemeral, not persisted).
 pub struct ActiveCall {
     pub caller_id: Uuid,
@@ -16,18 +19,36 @@ pub struct ConnectedCall {
     pub started_at: Instant,
 }
 
+/// A pending WebSocket connect ticket (single-use, short-lived).
+pub struct WsTicket {
+    pub user_id: Uuid,
+    /// Session (refresh-token family) of the access token that minted it
+    pub family_id: Option<Uuid>,
+    /// Hash of the requesting client's IP + User-Agent
+    pub binding: String,
+    pub expires_at: Instant,
+}
+
 /// In-memory state stores for single-instance mode (no Redis).
 ///
 /// When Redis is configured, these still serve as a local cache layer.
 /// When Redis is absent, these are the sole source of truth for ephemeral state.
 #[derive(Clone)]
 pub struct MemoryStore {
-    /// User presence: user_id → status string (online, idle, dnd, invisible)
-    pub presence: Arc<DashMap<Uuid, String>>,
+    /// User presence: user_id → live presence (status, since)
+    pub presence: Arc<DashMap<Uuid, PresenceState>>,
     /// Generic cache: key → (JSON string, expiry instant)
     pub cache: Arc<DashMap<String, (String, Instant)>>,
-    /// PoW challenges: challenge string → expiry instant
-    pub pow_challenges: Arc<DashMap<String, Instant>>,
+    /// PoW challenges: challenge string → (difficulty, expiry instant)
+    pub pow_challenges: Arc<DashMap<String, (u32, Instant)>>,
+    /// Abuse signals (registrations, failed logins): (event, minute) → count
+    pub event_counters: Arc<DashMap<(&'static str, u64), u32>>,
+    /// Failed logins per lowercased username
+    pub login_failures: Arc<DashMap<String, LoginFailures>>,
+    /// WebSocket connect tickets: ticket string → pending ticket
+    pub ws_tickets: Arc<DashMap<String, WsTicket>>,
+    /// Revoked sessions: family_id → when its last access token expires
+    pub revoked_families: Arc<DashMap<Uuid, Instant>>,
     /// Voice channel participants: channel_id → set of user_ids
     pub voice_participants: Arc<DashMap<Uuid, HashSet<Uuid>>>,
     /// Server-muted users per voice channel
@@ -46,6 +67,10 @@ impl Default for MemoryStore {
             presence: Arc::new(DashMap::new()),
             cache: Arc::new(DashMap::new()),
             pow_challenges: Arc::new(DashMap::new()),
+            event_counters: Arc::new(DashMap::new()),
+            login_failures: Arc::new(DashMap::new()),
+            ws_tickets: Arc::new(DashMap::new()),
+            revoked_families: Arc::new(DashMap::new()),
             voice_participants: Arc::new(DashMap::new()),
             voice_muted: Arc::new(DashMap::new()),
             voice_deafened: Arc::new(DashMap::new()),
@@ -60,10 +85,15 @@ impl MemoryStore {
         Self::default()
     }
 
-    /// Spawn a background task that prunes expired cache and PoW entries every 60 seconds.
+    /// Spawn a background task that prunes expired cache, PoW, event counter,
+    /// login failure, WS ticket and revocation entries every 60 seconds.
     pub fn spawn_cleanup_task(&self) {
         let cache = self.cache.clone();
         let pow = self.pow_challenges.clone();
+        let counters = self.event_counters.clone();
+        let login_failures = self.login_failures.clone();
+        let tickets = self.ws_tickets.clone();
+        let revoked = self.revoked_families.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(60));
@@ -75,7 +105,20 @@ impl MemoryStore {
                 cache.retain(|_, (_, expiry)| *expiry > now);
 
                 // Prune expired PoW challenges
-                pow.retain(|_, expiry| *expiry > now);
+                pow.retain(|_, (_, expiry)| *expiry > now);
+
+                // Event counters older than the longest window (an hour)
+                let minute = chrono::Utc::now().timestamp().max(0) as u64 / 60;
+                counters.retain(|(_, m), _| *m + 60 >= minute);
+
+                // Forget login failures nobody has added to for a day
+                login_failures.retain(|_, f| f.expires_at > now);
+
+                // Prune expired WS tickets
+                tickets.retain(|_, t| t.expires_at > now);
+
+                // Forget revoked sessions whose access tokens have all expired
+                revoked.retain(|_, expiry| *expiry > now);
             }
         });
     }
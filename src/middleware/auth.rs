@@ -4,12 +4,55 @@ use axum::{
 };
 use uuid::Uuid;
 
-use crate::auth::{user_id_from_claims, validate_access_token};
+use crate::api_tokens;
+use crate::auth::{user_id_from_claims, validate_access_token, Claims};
 use crate::db::queries;
 use crate::errors::AppError;
+use crate::jwt_keys;
+use crate::revocation;
 use crate::AppState;
 
-/// Extractor that validates JWT and provides the authenticated user ID.
+fn bearer_token(parts: &Parts) -> Result<&str, AppError> {
+    let auth_header = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(AppError::AuthError("Missing authorization header".into()))?;
+
+    auth_header
+        .strip_prefix("Bearer ")
+        .ok_or(AppError::AuthError("Invalid authorization format".into()))
+}
+
+/// Validate the bearer token and reject it if its session was revoked.
+/// Personal API tokens are refused here.
+async fn bearer_claims(parts: &Parts, state: &AppState) -> Result<Claims, AppError> {
+    let token = bearer_token(parts)?;
+    if token.starts_with(api_tokens::TOKEN_PREFIX) {
+        return Err(AppError::Forbidden("API tokens can't be used for this endpoint".into()));
+    }
+
+    jwt_keys::load_for_token(&state.db, &state.storage_key, &state.jwt_keys, token).await;
+    let claims = validate_access_token(token, &state.jwt_keys)?;
+    revocation::check_claims(state, &claims).await?;
+    Ok(claims)
+}
+
+/// The user behind the bearer token: an access token, or a personal API
+/// token whose scopes cover this route.
+async fn bearer_user(parts: &mut Parts, state: &AppState) -> Result<Uuid, AppError> {
+    let token = bearer_token(parts)?;
+    if token.starts_with(api_tokens::TOKEN_PREFIX) {
+        let token = token.to_owned();
+        return api_tokens::authenticate(state, &token, parts).await;
+    }
+
+    let claims = bearer_claims(parts, state).await?;
+    user_id_from_claims(&claims)
+}
+
+/// Extractor that validates JWT (or a personal API token scoped for the
+/// route) and provides the authenticated user ID.
 /// Use in handler signatures: `AuthUser(user_id): AuthUser`
 #[derive(Debug, Clone)]
 pub struct AuthUser(pub Uuid);
@@ -19,20 +62,32 @@ impl FromRequestParts<AppState> for AuthUser {
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
-        let auth_header = parts
-            .headers
-            .get(AUTHORIZATION)
-            .and_then(|v| v.to_str().ok())
-            .ok_or(AppError::AuthError("Missing authorization header".into()))?;
+        let user_id = bearer_user(parts, state).await?;
+
+        Ok(AuthUser(user_id))
+    }
+}
+
+/// Like `AuthUser`, but also yields the session (token family) the access
+/// token belongs to, for handlers that act on the caller's own session.
+#[derive(Debug, Clone)]
+pub struct AuthSession {
+    pub user_id: Uuid,
+    pub family_id: Option<Uuid>,
+}
 
-        let token = auth_header
-            .strip_prefix("Bearer ")
-            .ok_or(AppError::AuthError("Invalid authorization format".into()))?;
+#[axum::async_trait]
+impl FromRequestParts<AppState> for AuthSession {
+    type Rejection = AppError;
 
-        let claims = validate_access_token(token, &state.config)?;
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let claims = bearer_claims(parts, state).await?;
         let user_id = user_id_from_claims(&claims)?;
 
-        Ok(AuthUser(user_id))
+        Ok(AuthSession {
+            user_id,
+            family_id: claims.family_id,
+        })
     }
 }
 
@@ -46,17 +101,7 @@ impl FromRequestParts<AppState> for AdminUser {
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
-        let auth_header = parts
-            .headers
-            .get(AUTHORIZATION)
-            .and_then(|v| v.to_str().ok())
-            .ok_or(AppError::AuthError("Missing authorization header".into()))?;
-
-        let token = auth_header
-            .strip_prefix("Bearer ")
-            .ok_or(AppError::AuthError("Invalid authorization format".into()))?;
-
-        let claims = validate_access_token(token, &state.config)?;
+        let claims = bearer_claims(parts, state).await?;
         let user_id = user_id_from_claims(&claims)?;
 
         // Verify user is an instance admin
@@ -82,13 +127,7 @@ impl FromRequestParts<AppState> for OptionalAuthUser {
     type Rejection = std::convert::Infallible;
 
     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
-        let user_id = parts
-            .headers
-            .get(AUTHORIZATION)
-            .and_then(|v| v.to_str().ok())
-            .and_then(|h| h.strip_prefix("Bearer "))
-            .and_then(|token| validate_access_token(token, &state.config).ok())
-            .and_then(|claims| user_id_from_claims(&claims).ok());
+        let user_id = bearer_user(parts, state).await.ok();
 
         Ok(OptionalAuthUser(user_id))
     }
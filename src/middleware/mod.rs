@@ -1,8 +1,8 @@
 pub mod auth;
 pub mod rate_limit;
 
-pub use auth::{AdminUser, AuthUser};
+pub use auth::{AdminUser, AuthSession, AuthUser};
 pub use rate_limit::{
-    rate_limit_middleware, spawn_rate_limit_cleanup, spawn_user_rate_limit_cleanup, RateLimiter,
-    UserRateLimiter,
+    rate_limit_middleware, spawn_rate_limit_cleanup, spawn_user_rate_limit_cleanup, ClientIp,
+    RateLimiter, UserRateLimiter,
 };
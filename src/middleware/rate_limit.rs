@@ -1,10 +1,11 @@
+use std::hash::Hash;
 use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Instant;
 
 use axum::{
-    extract::{ConnectInfo, Request},
-    http::StatusCode,
+    extract::{ConnectInfo, FromRequestParts, Request},
+    http::{request::Parts, Extensions, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
@@ -65,6 +66,18 @@ impl RateLimiter {
         *count <= self.max_requests
     }
 
+    /// How much of its allowance this IP has used in the current window
+    /// (0.0 = none, 1.0 = at the limit), without counting a request.
+    pub fn usage(&self, ip: IpAddr) -> f64 {
+        let hashed = hash_ip(ip, &*self.ip_hash_key);
+        match self.state.get(&hashed) {
+            Some(entry) if entry.1.elapsed().as_secs() < self.window_secs => {
+                entry.0 as f64 / self.max_requests.max(1) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
     /// Periodic cleanup of expired entries to prevent unbounded growth.
     pub fn cleanup(&self) {
         let now = Instant::now();
@@ -75,14 +88,15 @@ impl RateLimiter {
 }
 
 /// Per-user rate limiter keyed by user UUID (for authenticated endpoints).
+/// Other keys, e.g. (requester, target) pairs, work the same way.
 #[derive(Clone)]
-pub struct UserRateLimiter {
-    state: Arc<DashMap<Uuid, (u32, Instant)>>,
+pub struct UserRateLimiter<K = Uuid> {
+    state: Arc<DashMap<K, (u32, Instant)>>,
     max_requests: u32,
     window_secs: u64,
 }
 
-impl UserRateLimiter {
+impl<K: Eq + Hash> UserRateLimiter<K> {
     pub fn new(max_requests: u32, window_secs: u64) -> Self {
         Self {
             state: Arc::new(DashMap::new()),
@@ -92,9 +106,9 @@ impl UserRateLimiter {
     }
 
     /// Returns true if the request should be allowed for this user.
-    pub fn check(&self, user_id: Uuid) -> bool {
+    pub fn check(&self, key: K) -> bool {
         let now = Instant::now();
-        let mut entry = self.state.entry(user_id).or_insert((0, now));
+        let mut entry = self.state.entry(key).or_insert((0, now));
         let (count, window_start) = entry.value_mut();
 
         if now.duration_since(*window_start).as_secs() >= self.window_secs {
@@ -116,11 +130,15 @@ impl UserRateLimiter {
 
 /// Extract the client IP from the request (ConnectInfo or X-Forwarded-For).
 pub fn extract_ip(req: &Request) -> IpAddr {
-    req.extensions()
+    client_ip(req.extensions(), req.headers())
+}
+
+fn client_ip(extensions: &Extensions, headers: &HeaderMap) -> IpAddr {
+    extensions
         .get::<ConnectInfo<std::net::SocketAddr>>()
         .map(|ci| ci.0.ip())
         .or_else(|| {
-            req.headers()
+            headers
                 .get("x-forwarded-for")
                 .and_then(|v| v.to_str().ok())
                 .and_then(|s| s.split(',').next())
@@ -129,6 +147,19 @@ pub fn extract_ip(req: &Request) -> IpAddr {
         .unwrap_or(IpAddr::from([127, 0, 0, 1]))
 }
 
+/// Extractor for the client IP as the rate limiters see it.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+#[axum::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(&parts.extensions, &parts.headers)))
+    }
+}
+
 /// Middleware that enforces rate limits. Returns 429 if limit exceeded.
 pub async fn rate_limit_middleware(
     rate_limiter: RateLimiter,
@@ -156,7 +187,10 @@ pub fn spawn_rate_limit_cleanup(limiter: RateLimiter) {
 }
 
 /// Spawn cleanup for user rate limiter.
-pub fn spawn_user_rate_limit_cleanup(limiter: UserRateLimiter) {
+pub fn spawn_user_rate_limit_cleanup<K>(limiter: UserRateLimiter<K>)
+where
+    K: Eq + Hash + Send + Sync + 'static,
+{
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
         loop {
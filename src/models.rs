@@ -45,6 +45,33 @@ pub struct User {
     pub dm_privacy: String, // "everyone", "friends_only", "server_members"
     pub encrypted_profile: Option<Vec<u8>>,
     pub is_instance_admin: bool,
+    #[serde(default)]
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
+    /// Which email hash key made `email_hash`; None for the legacy jwt_secret
+    #[serde(default)]
+    pub email_hash_key_id: Option<String>,
+    /// When the user confirmed `email_hash` by following a verification link
+    #[serde(default)]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Set while the account is deactivated (profile hidden until next sign-in)
+    #[serde(default)]
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// When a requested deletion takes effect; signing in first cancels it
+    #[serde(default)]
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    /// Whether an admin scheduled the deletion, which signing in can't cancel
+    #[serde(default)]
+    pub deletion_requested_by_admin: bool,
+}
+
+impl User {
+    /// Custom status and emoji, unless the status has expired.
+    pub fn active_custom_status(&self) -> (Option<String>, Option<String>) {
+        match self.custom_status_expires_at {
+            Some(expires_at) if expires_at <= Utc::now() => (None, None),
+            _ => (self.custom_status.clone(), self.custom_status_emoji.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -57,6 +84,8 @@ pub struct UserPublic {
     pub banner_url: Option<String>,
     pub custom_status: Option<String>,
     pub custom_status_emoji: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encrypted_profile: Option<String>, // base64
@@ -69,6 +98,8 @@ impl From<User> for UserPublic {
     fn from(u: User) -> Self {
         let admin = if u.is_instance_admin { Some(true) } else { None };
         let totp = u.totp_secret.is_some();
+        let (custom_status, custom_status_emoji) = u.active_custom_status();
+        let custom_status_expires_at = u.custom_status_expires_at.filter(|_| custom_status.is_some());
         Self {
             id: u.id,
             username: u.username,
@@ -76,8 +107,9 @@ impl From<User> for UserPublic {
             about_me: u.about_me,
             avatar_url: u.avatar_url,
             banner_url: u.banner_url,
-            custom_status: u.custom_status,
-            custom_status_emoji: u.custom_status_emoji,
+            custom_status,
+            custom_status_emoji,
+            custom_status_expires_at,
             created_at: u.created_at,
             encrypted_profile: u.encrypted_profile.map(|v| {
                 base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &v)
@@ -106,7 +138,9 @@ pub struct RegisterRequest {
     pub identity_key: String,
     pub signed_prekey: String,
     pub signed_prekey_signature: String,
+    pub signed_prekey_id: Option<i32>, // defaults to 1
     pub one_time_prekeys: Vec<String>, // batch upload of OTPs
+    pub last_resort_prekey: Option<String>, // optional, never consumed
 
     // Proof-of-Work anti-bot challenge
     pub pow_challenge: String,
@@ -115,8 +149,10 @@ pub struct RegisterRequest {
     /// Registration invite code (required when REGISTRATION_INVITE_ONLY=true)
     pub invite_code: Option<String>,
 
-    /// Cloudflare Turnstile token (required when TURNSTILE_SECRET_KEY is set)
-    pub turnstile_token: Option<String>,
+    /// CAPTCHA token (required when a CAPTCHA provider is configured).
+    /// `turnstile_token` is the name older clients send.
+    #[serde(alias = "turnstile_token")]
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -124,6 +160,9 @@ pub struct LoginRequest {
     pub username: String,
     pub password: String,
     pub totp_code: Option<String>,
+    /// Required while failed logins spike (the server answers `pow_required`)
+    pub pow_challenge: Option<String>,
+    pub pow_nonce: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -131,14 +170,22 @@ pub struct AuthResponse {
     pub access_token: String,
     pub refresh_token: String,
     pub user: UserPublic,
+    /// Device created for the keys sent at registration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<Uuid>,
 }
 
-/// Login endpoint returns either full auth tokens or a TOTP challenge.
+/// Login endpoint returns either full auth tokens, a TOTP challenge, or a PoW
+/// challenge to solve and resend the login with.
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum LoginResponse {
     Success(Box<AuthResponse>),
     TotpRequired { totp_required: bool },
+    PowRequired {
+        pow_required: bool,
+        challenge: PowChallengeResponse,
+    },
 }
 
 impl axum::response::IntoResponse for LoginResponse {
@@ -152,6 +199,88 @@ pub struct RefreshRequest {
     pub refresh_token: String,
 }
 
+// ─── Device Linking ───────────────────────────────────
+
+#[derive(Debug, Clone, FromRow)]
+pub struct DeviceLink {
+    pub id: Uuid,
+    pub token_hash: String,
+    pub ephemeral_public_key: Vec<u8>,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub approved_by: Option<Uuid>,
+    pub approver_public_key: Option<Vec<u8>>,
+    pub provisioning_message: Option<Vec<u8>>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StartDeviceLinkRequest {
+    /// X25519 public key (base64) the provisioning message is encrypted to
+    pub ephemeral_public_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartDeviceLinkResponse {
+    pub link_id: Uuid,
+    /// Secret for polling; never put it in the QR code
+    pub link_token: String,
+    /// What the QR code shows: the link id and the ephemeral key, in the fragment
+    pub link_uri: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What the approving session is shown before it approves.
+#[derive(Debug, Serialize)]
+pub struct DeviceLinkInfoResponse {
+    pub link_id: Uuid,
+    pub device_name: Option<String>,
+    /// Partially masked, like session IPs
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApproveDeviceLinkRequest {
+    /// The key from the scanned QR code; must match the link's
+    pub ephemeral_public_key: String,
+    /// The approver's own ephemeral X25519 public key (base64)
+    pub approver_public_key: String,
+    /// Encrypted provisioning message (base64), e.g. the key backup unlock
+    pub provisioning_message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollDeviceLinkRequest {
+    pub link_token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceLinkResult {
+    #[serde(flatten)]
+    pub auth: AuthResponse,
+    pub approver_public_key: String,
+    pub provisioning_message: String,
+}
+
+/// Polling returns `pending` until a session approves the link, then the new
+/// session and provisioning message, once.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum DeviceLinkPollResponse {
+    Linked(Box<DeviceLinkResult>),
+    Pending { pending: bool },
+}
+
+impl axum::response::IntoResponse for DeviceLinkPollResponse {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json(self).into_response()
+    }
+}
+
 // ─── Proof-of-Work Challenge ──────────────────────────
 
 #[derive(Debug, Serialize)]
@@ -162,6 +291,79 @@ pub struct PowChallengeResponse {
     /// Cloudflare Turnstile site key (present when Turnstile is enabled)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub turnstile_site_key: Option<String>,
+    /// CAPTCHA widget registration needs a token from (present when enabled)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha: Option<CaptchaInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptchaInfo {
+    /// "turnstile", "hcaptcha" or "mcaptcha"
+    pub provider: String,
+    pub site_key: String,
+}
+
+// ─── Single Sign-On (OIDC) ─────────────────────────────
+
+/// Which login methods the instance offers, for rendering the login page.
+#[derive(Debug, Serialize)]
+pub struct LoginMethodsResponse {
+    pub password: bool,
+    pub oidc: bool,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct OidcLoginState {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub link_user_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct OidcIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OidcAuthorizeResponse {
+    /// Issuer URL to send the browser to
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+    /// Keys for the primary device of an auto-provisioned account. Ignored
+    /// when the subject is already linked; clients should always send them.
+    #[serde(default)]
+    pub keys: Option<RegisterDeviceRequest>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum OidcCallbackResponse {
+    Login(Box<AuthResponse>),
+    Linked { linked: bool, issuer: String, subject: String },
+}
+
+// ─── WebSocket Tickets ─────────────────────────────────
+
+#[derive(Debug, Serialize)]
+pub struct WsTicketResponse {
+    /// Single-use ticket to pass as `?ticket=` on the WebSocket upgrade
+    pub ticket: String,
+    /// Seconds until the ticket expires
+    pub expires_in: u64,
 }
 
 // ─── TOTP ──────────────────────────────────────────────
@@ -177,6 +379,71 @@ pub struct TotpVerifyRequest {
     pub code: String,
 }
 
+// ─── Devices ───────────────────────────────────────────
+
+/// One of a user's E2EE devices, with its own identity key and prekeys.
+/// The oldest device is the user's primary device.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Device {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: Option<String>,
+    pub identity_key: Vec<u8>,
+    pub signed_prekey: Vec<u8>,
+    pub signed_prekey_sig: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Handed out once one-time prekeys run out; never consumed
+    pub last_resort_prekey: Option<Vec<u8>>,
+    pub signed_prekey_id: i32,
+    pub signed_prekey_created_at: DateTime<Utc>,
+    /// The signed prekey replaced by the last rotation, kept until
+    /// `previous_signed_prekey_expires_at` for in-flight handshakes
+    pub previous_signed_prekey_id: Option<i32>,
+    pub previous_signed_prekey: Option<Vec<u8>>,
+    pub previous_signed_prekey_sig: Option<Vec<u8>>,
+    pub previous_signed_prekey_expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceResponse {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub identity_key: String, // base64
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterDeviceRequest {
+    pub name: Option<String>,
+    pub identity_key: String,            // base64
+    pub signed_prekey: String,           // base64
+    pub signed_prekey_signature: String, // base64
+    pub signed_prekey_id: Option<i32>,   // defaults to 1
+    #[serde(default)]
+    pub one_time_prekeys: Vec<String>,   // base64
+    pub last_resort_prekey: Option<String>, // base64
+}
+
+/// Selects one of the caller's devices; the primary device when omitted.
+#[derive(Debug, Deserialize)]
+pub struct DeviceQuery {
+    pub device_id: Option<Uuid>,
+}
+
+/// Server-signed binding of a device to its identity key, carried inside
+/// sealed-sender ciphertexts. The signature covers
+/// `sealed_sender::certificate_message` of these fields.
+#[derive(Debug, Serialize)]
+pub struct SenderCertificateResponse {
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub identity_key: String, // base64
+    pub expires_at: DateTime<Utc>,
+    pub signature: String,  // base64
+    pub public_key: String, // base64
+}
+
 // ─── Pre-Keys (X3DH) ──────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -187,19 +454,51 @@ pub struct PreKey {
     pub public_key: Vec<u8>,
     pub used: bool,
     pub created_at: DateTime<Utc>,
+    pub device_id: Uuid,
 }
 
+/// Key bundles for every device of a user. The top-level fields describe the
+/// primary device, for clients that predate multi-device.
 #[derive(Debug, Serialize)]
 pub struct KeyBundle {
     pub identity_key: String,       // base64
     pub signed_prekey: String,      // base64
     pub signed_prekey_sig: String,  // base64
+    pub signed_prekey_id: i32,
+    pub one_time_prekey: Option<String>, // base64, consumed on fetch
+    /// `one_time_prekey` is the last-resort prekey (not consumed)
+    pub last_resort: bool,
+    pub devices: Vec<DeviceKeyBundle>,
+    /// The requester verified this user's identity keys
+    pub verified: bool,
+    /// The user's identity keys changed since the requester verified them
+    pub verified_key_changed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceKeyBundle {
+    pub device_id: Uuid,
+    pub identity_key: String,       // base64
+    pub signed_prekey: String,      // base64
+    pub signed_prekey_sig: String,  // base64
+    pub signed_prekey_id: i32,
     pub one_time_prekey: Option<String>, // base64, consumed on fetch
+    /// `one_time_prekey` is the last-resort prekey (not consumed)
+    pub last_resort: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadLastResortPreKeyRequest {
+    pub prekey: String, // base64
+    #[serde(default)]
+    pub device_id: Option<Uuid>, // primary device when omitted
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UploadPreKeysRequest {
     pub prekeys: Vec<String>, // base64-encoded public keys
+    #[serde(default)]
+    pub device_id: Option<Uuid>, // primary device when omitted
 }
 
 #[derive(Debug, Deserialize)]
@@ -207,6 +506,11 @@ pub struct UpdateKeysRequest {
     pub identity_key: String,          // base64
     pub signed_prekey: String,         // base64
     pub signed_prekey_signature: String, // base64
+    /// ID of a new signed prekey; the current ID + 1 when omitted
+    #[serde(default)]
+    pub signed_prekey_id: Option<i32>,
+    #[serde(default)]
+    pub device_id: Option<Uuid>,       // primary device when omitted
 }
 
 // ─── Servers ───────────────────────────────────────────
@@ -219,6 +523,12 @@ pub struct Server {
     pub created_at: DateTime<Utc>,
     pub system_channel_id: Option<Uuid>,
     pub icon_url: Option<String>,
+    /// Current server key epoch; bumped by each key rotation
+    #[serde(default)]
+    pub key_epoch: i32,
+    /// Epoch of the key `encrypted_meta` is encrypted under
+    #[serde(default)]
+    pub meta_epoch: i32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -240,6 +550,8 @@ pub struct ServerResponse {
     pub system_channel_id: Option<Uuid>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub icon_url: Option<String>,
+    pub key_epoch: i32,
+    pub meta_epoch: i32,
 }
 
 // ─── Channels ──────────────────────────────────────────
@@ -255,6 +567,7 @@ pub struct Channel {
     pub category_id: Option<Uuid>,
     pub dm_status: Option<String>, // "active", "pending", "declined" — only for DM channels
     pub is_private: bool,
+    pub meta_epoch: i32, // server key epoch of encrypted_meta (0 for DMs)
 }
 
 #[derive(Debug, Deserialize)]
@@ -264,6 +577,9 @@ pub struct CreateChannelRequest {
     pub position: Option<i32>,
     pub category_id: Option<Uuid>,
     pub is_private: Option<bool>,
+    /// Server key epoch the client encrypted `encrypted_meta` under
+    #[serde(default)]
+    pub meta_epoch: Option<i32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -280,6 +596,7 @@ pub struct ChannelResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_message_id: Option<Uuid>,
     pub is_private: bool,
+    pub meta_epoch: i32,
 }
 
 // ─── Channel Categories ──────────────────────────────
@@ -385,10 +702,13 @@ pub struct Message {
     pub timestamp: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
     pub has_attachments: bool,
-    pub sender_id: Option<Uuid>,  // for edit authorization; null for legacy messages
+    pub sender_id: Option<Uuid>,  // for edit authorization; null for legacy and sealed messages
     pub edited_at: Option<DateTime<Utc>>,
     pub reply_to_id: Option<Uuid>,
     pub message_type: String,     // "user" or "system"
+    /// SHA-256 of the sender's capability token; set for sealed-sender messages
+    #[serde(default)]
+    pub capability_hash: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -399,6 +719,10 @@ pub struct SendMessageRequest {
     pub expires_at: Option<DateTime<Utc>>,
     pub has_attachments: bool,
     pub reply_to_id: Option<Uuid>,
+    /// Sealed sender: base64 SHA-256 of a capability token that authorizes
+    /// edits and deletes. When set, no sender is stored with the message.
+    #[serde(default)]
+    pub capability_hash: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -474,6 +798,7 @@ pub struct SenderKeyDistribution {
     pub distribution_id: Uuid,
     pub encrypted_skdm: Vec<u8>,
     pub created_at: DateTime<Utc>,
+    pub to_device_id: Uuid,
 }
 
 #[derive(Debug, Deserialize)]
@@ -484,6 +809,8 @@ pub struct DistributeSenderKeyRequest {
 #[derive(Debug, Deserialize)]
 pub struct SenderKeyDistributionEntry {
     pub to_user_id: Uuid,
+    #[serde(default)]
+    pub to_device_id: Option<Uuid>, // recipient's primary device when omitted
     pub distribution_id: Uuid,
     pub encrypted_skdm: String, // base64
 }
@@ -493,6 +820,7 @@ pub struct SenderKeyDistributionResponse {
     pub id: Uuid,
     pub channel_id: Uuid,
     pub from_user_id: Uuid,
+    pub to_device_id: Uuid,
     pub distribution_id: Uuid,
     pub encrypted_skdm: String, // base64
     pub created_at: DateTime<Utc>,
@@ -501,19 +829,206 @@ pub struct SenderKeyDistributionResponse {
 #[derive(Debug, Serialize)]
 pub struct ChannelMemberKeyInfo {
     pub user_id: Uuid,
+    pub identity_key: String, // base64, primary device
+    pub devices: Vec<DeviceIdentityKey>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceIdentityKey {
+    pub device_id: Uuid,
     pub identity_key: String, // base64
 }
 
+// ─── MLS ──────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub struct UploadKeyPackagesRequest {
+    #[serde(default)]
+    pub device_id: Option<Uuid>, // primary device when omitted
+    pub key_packages: Vec<String>, // base64 MLS KeyPackages
+}
+
+/// One claimed KeyPackage per device of the target user.
+#[derive(Debug, Serialize)]
+pub struct ClaimedKeyPackages {
+    pub user_id: Uuid,
+    pub key_packages: Vec<DeviceKeyPackage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceKeyPackage {
+    pub device_id: Uuid,
+    pub key_package: String, // base64
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MlsGroup {
+    pub channel_id: Uuid,
+    pub epoch: i64,
+    pub next_seq: i64,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MlsGroupResponse {
+    pub channel_id: Uuid,
+    pub epoch: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendMlsHandshakeRequest {
+    #[serde(default)]
+    pub device_id: Option<Uuid>, // sender's primary device when omitted
+    /// The group epoch the message was created in; must be the current epoch
+    pub epoch: i64,
+    pub kind: String,    // "commit" or "proposal"
+    pub message: String, // base64 MLSMessage
+    /// Welcome for members a commit adds (base64), delivered to `welcome_device_ids`
+    #[serde(default)]
+    pub welcome: Option<String>,
+    #[serde(default)]
+    pub welcome_device_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MlsHandshakeMessage {
+    pub channel_id: Uuid,
+    pub seq: i64,
+    pub epoch: i64,
+    pub kind: String,
+    /// None once the sender's account or device was deleted
+    pub sender_user_id: Option<Uuid>,
+    pub sender_device_id: Option<Uuid>,
+    pub message: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MlsHandshakeResponse {
+    pub channel_id: Uuid,
+    pub seq: i64,
+    pub epoch: i64,
+    pub kind: String,
+    pub sender_user_id: Option<Uuid>,
+    pub sender_device_id: Option<Uuid>,
+    pub message: String, // base64
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<MlsHandshakeMessage> for MlsHandshakeResponse {
+    fn from(m: MlsHandshakeMessage) -> Self {
+        Self {
+            channel_id: m.channel_id,
+            seq: m.seq,
+            epoch: m.epoch,
+            kind: m.kind,
+            sender_user_id: m.sender_user_id,
+            sender_device_id: m.sender_device_id,
+            message: base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &m.message),
+            created_at: m.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MlsHandshakeQuery {
+    pub after: Option<i64>, // exclusive sequence number
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MlsWelcome {
+    pub id: Uuid,
+    pub channel_id: Uuid,
+    pub to_user_id: Uuid,
+    pub to_device_id: Uuid,
+    pub epoch: i64,
+    pub welcome: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A commit's Welcome and the (user, device) pairs it is addressed to.
+pub struct NewMlsWelcome<'a> {
+    pub welcome: &'a [u8],
+    pub recipients: &'a [(Uuid, Uuid)],
+}
+
+#[derive(Debug, Serialize)]
+pub struct MlsWelcomeResponse {
+    pub id: Uuid,
+    pub channel_id: Uuid,
+    pub to_device_id: Uuid,
+    pub epoch: i64,
+    pub welcome: String, // base64
+    pub created_at: DateTime<Utc>,
+}
+
+// ─── Key Transparency ────────────────────────────────
+
+#[derive(Debug, Clone, FromRow)]
+pub struct TransparencyLogEntry {
+    pub seq: i64,
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub identity_key: Vec<u8>,
+    pub prev_hash: Option<Vec<u8>>, // leaf hash of the user's previous entry
+    pub leaf_hash: Vec<u8>,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransparencyLogEntryResponse {
+    pub seq: i64,
+    pub user_id: Uuid,
+    pub device_id: Uuid,
+    pub identity_key: String,      // base64
+    pub prev_hash: Option<String>, // base64
+    pub leaf_hash: String,         // base64
+    pub published_at: DateTime<Utc>,
+    /// Inclusion proof against the accompanying tree head (base64 hashes, leaf first)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit_path: Option<Vec<String>>,
+}
+
+/// Merkle root over the first `tree_size` log entries, signed with the
+/// server's Ed25519 transparency key.
+#[derive(Debug, Serialize)]
+pub struct SignedTreeHead {
+    pub tree_size: i64,
+    pub root_hash: String,  // base64
+    pub timestamp: DateTime<Utc>,
+    pub signature: String,  // base64
+    pub public_key: String, // base64
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyTransparencyLog {
+    pub tree_head: SignedTreeHead,
+    pub entries: Vec<TransparencyLogEntryResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransparencyLogQuery {
+    pub after: Option<i64>, // exclusive sequence number
+    pub limit: Option<i64>,
+}
+
 // ─── Key Backups ─────────────────────────────────────
 
+/// One version of a user's key backup. Either a passphrase-encrypted blob
+/// (`salt` set) or a manifest encrypted under a backup key that the
+/// version's key slots wrap.
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct KeyBackup {
     pub id: Uuid,
     pub user_id: Uuid,
     pub encrypted_data: Vec<u8>,
     pub nonce: Vec<u8>,
-    pub salt: Vec<u8>,
-    pub version: i32,
+    pub salt: Option<Vec<u8>>,
+    pub version: i32, // assigned by the server, increases per upload
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -522,16 +1037,87 @@ pub struct KeyBackup {
 pub struct UploadKeyBackupRequest {
     pub encrypted_data: String, // base64
     pub nonce: String,          // base64
-    pub salt: String,           // base64
-    pub version: Option<i32>,
+    /// Argon2id salt when `encrypted_data` is encrypted directly with a passphrase
+    #[serde(default)]
+    pub salt: Option<String>,   // base64
+    /// Slots wrapping the backup key; required when `salt` is omitted
+    #[serde(default)]
+    pub key_slots: Vec<KeyBackupSlotRequest>,
+}
+
+/// How a key slot's wrapping key is derived on the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyBackupSlotKind {
+    /// Argon2id over a passphrase, with `salt`
+    Passphrase,
+    /// A generated recovery key
+    RecoveryKey,
+    /// WebAuthn PRF output of `credential_id`, evaluated on `salt`
+    PasskeyPrf,
+}
+
+impl KeyBackupSlotKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Passphrase => "passphrase",
+            Self::RecoveryKey => "recovery_key",
+            Self::PasskeyPrf => "passkey_prf",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyBackupSlotRequest {
+    pub kind: KeyBackupSlotKind,
+    pub wrapped_key: String, // base64
+    pub nonce: String,       // base64
+    #[serde(default)]
+    pub salt: Option<String>,          // base64
+    #[serde(default)]
+    pub credential_id: Option<String>, // base64
+}
+
+/// A validated, decoded key slot ready to be stored.
+#[derive(Debug, Clone)]
+pub struct NewKeyBackupSlot {
+    pub kind: KeyBackupSlotKind,
+    pub wrapped_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub salt: Option<Vec<u8>>,
+    pub credential_id: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct KeyBackupSlot {
+    pub id: Uuid,
+    pub backup_id: Uuid,
+    pub kind: String,
+    pub wrapped_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub salt: Option<Vec<u8>>,
+    pub credential_id: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyBackupSlotResponse {
+    pub id: Uuid,
+    pub kind: String,
+    pub wrapped_key: String, // base64
+    pub nonce: String,       // base64
+    pub salt: Option<String>,          // base64
+    pub credential_id: Option<String>, // base64
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct KeyBackupResponse {
     pub encrypted_data: String, // base64
     pub nonce: String,          // base64
-    pub salt: String,           // base64
+    pub salt: Option<String>,   // base64
     pub version: i32,
+    pub key_slots: Vec<KeyBackupSlotResponse>,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -542,6 +1128,92 @@ pub struct KeyBackupStatusResponse {
     pub updated_at: Option<DateTime<Utc>>,
 }
 
+/// A retained backup version, without its key material.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct KeyBackupVersionInfo {
+    pub version: i32,
+    pub slot_count: i64,
+    pub session_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadBackupSessionsRequest {
+    /// The version the sessions belong to; must be the current version
+    pub version: i32,
+    pub sessions: Vec<BackupSessionEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BackupSessionEntry {
+    pub session_id: String,
+    pub encrypted_data: String, // base64
+    pub nonce: String,          // base64
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct KeyBackupSession {
+    pub backup_id: Uuid,
+    pub session_id: String,
+    pub encrypted_data: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupSessionResponse {
+    pub session_id: String,
+    pub encrypted_data: String, // base64
+    pub nonce: String,          // base64
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A download of backup material, recorded for the owner to review.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct KeyBackupAccess {
+    pub id: Uuid,
+    pub version: i32,
+    pub action: String, // "download" or "sessions"
+    pub ip_address: Option<String>,
+    pub device_name: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// ─── Verification States ─────────────────────────────
+
+#[derive(Debug, Clone, FromRow)]
+pub struct VerificationState {
+    pub user_id: Uuid,
+    pub contact_id: Uuid,
+    pub encrypted_state: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub verified_keys_hash: Option<Vec<u8>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadVerificationStateRequest {
+    pub encrypted_state: String, // base64
+    pub nonce: String,           // base64
+    /// Every device identity key of the contact that was verified (base64).
+    /// Omit to record an unverified contact.
+    #[serde(default)]
+    pub verified_identity_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerificationStateResponse {
+    pub contact_id: Uuid,
+    pub encrypted_state: String, // base64
+    pub nonce: String,           // base64
+    pub verified: bool,
+    /// The contact's identity keys changed since they were verified
+    pub verified_key_changed: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
 // ─── Reactions ────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -625,18 +1297,29 @@ pub enum WsClientMessage {
         expires_at: Option<DateTime<Utc>>,
         attachment_ids: Option<Vec<Uuid>>,
         reply_to_id: Option<Uuid>,
+        /// Sealed sender: base64 SHA-256 of the message's capability token
+        #[serde(default)]
+        capability_hash: Option<String>,
     },
     /// Edit a previously sent message
     EditMessage {
         message_id: Uuid,
         encrypted_body: String,
+        /// Capability token (base64) of a sealed-sender message
+        #[serde(default)]
+        capability: Option<String>,
     },
     /// Subscribe to channel events
     Subscribe { channel_id: Uuid },
     /// Unsubscribe from channel events
     Unsubscribe { channel_id: Uuid },
     /// Delete a previously sent message
-    DeleteMessage { message_id: Uuid },
+    DeleteMessage {
+        message_id: Uuid,
+        /// Capability token (base64) of a sealed-sender message
+        #[serde(default)]
+        capability: Option<String>,
+    },
     /// Add a reaction to a message
     AddReaction { message_id: Uuid, emoji: String },
     /// Remove a reaction from a message
@@ -657,12 +1340,21 @@ pub enum WsClientMessage {
     CallReject { channel_id: Uuid },
     /// End an active call
     CallEnd { channel_id: Uuid },
-    /// Ping (keepalive)
-    Ping,
+    /// Ping (keepalive), optionally reporting input idle time for idle detection
+    Ping(#[serde(default)] Option<HeartbeatInfo>),
     /// Mark a channel as read (up to latest message)
     MarkRead { channel_id: Uuid },
     /// Resume a previous session after reconnect
     Resume { session_id: Uuid },
+    /// Subscribe to a window `[start, end)` of a server's sorted member list
+    SubscribeMemberList {
+        server_id: Uuid,
+        #[serde(default)]
+        group_by: MemberListGrouping,
+        range: [u32; 2],
+    },
+    /// Stop receiving member list updates for a server
+    UnsubscribeMemberList { server_id: Uuid },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -692,6 +1384,35 @@ pub enum WsServerMessage {
     Subscribed { channel_id: Uuid },
     /// New sender key distributions are available for a channel
     SenderKeysUpdated { channel_id: Uuid },
+    /// A commit or proposal was accepted into a channel's MLS handshake stream
+    MlsHandshake(MlsHandshakeResponse),
+    /// A Welcome to a channel's MLS group is waiting for one of the user's devices
+    MlsWelcome { channel_id: Uuid, device_id: Uuid },
+    /// A user registered a new E2EE device; sessions and sender keys should
+    /// be extended to it
+    DeviceAdded { user_id: Uuid, device_id: Uuid },
+    /// A user removed one of their E2EE devices
+    DeviceRemoved { user_id: Uuid, device_id: Uuid },
+    /// A DM peer (or one of the user's own devices) published a new
+    /// identity key; `log_seq` locates it in the key transparency log
+    IdentityKeyChanged {
+        user_id: Uuid,
+        device_id: Uuid,
+        identity_key: String, // base64
+        log_seq: i64,
+    },
+    /// The user changed their verification state for a contact on another
+    /// device; other sessions should refetch it
+    VerificationStateUpdated { contact_id: Uuid },
+    /// A member lost access to the channel; distribute a fresh sender key
+    /// before sending again
+    SenderKeyRotationRequired {
+        channel_id: Uuid,
+        reason: SenderKeyRotationReason,
+    },
+    /// One of the user's devices fell below the one-time prekey threshold
+    /// (or ran out); the device should upload more
+    PrekeysLow { device_id: Uuid, remaining: i64 },
     /// A message was deleted
     MessageDeleted {
         message_id: Uuid,
@@ -712,7 +1433,24 @@ pub enum WsServerMessage {
         emoji: String,
     },
     /// User presence change (online/offline)
-    PresenceUpdate { user_id: Uuid, status: String },
+    PresenceUpdate {
+        user_id: Uuid,
+        status: String,
+        /// When the current status began (absent when offline)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<DateTime<Utc>>,
+        /// Set when going offline, if the instance records last-seen and the user allows it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_seen: Option<DateTime<Utc>>,
+    },
+    /// A friend or DM/group participant changed their custom status
+    CustomStatusUpdate {
+        user_id: Uuid,
+        custom_status: Option<String>,
+        custom_status_emoji: Option<String>,
+        #[serde(default)]
+        custom_status_expires_at: Option<DateTime<Utc>>,
+    },
     /// A friend request was received
     FriendRequestReceived { from_user_id: Uuid, from_username: String, friendship_id: Uuid },
     /// A friend request was accepted
@@ -797,8 +1535,86 @@ pub enum WsServerMessage {
     },
     /// Server structure changed (channels/categories created/updated/deleted)
     ServerUpdated { server_id: Uuid },
+    /// A server key was wrapped for this user at `epoch` (a rotation, or a
+    /// late wrap after joining); fetch it from /servers/:id/key-epochs
+    ServerKeyRotated { server_id: Uuid, epoch: i32 },
     /// Session expired or invalid — do a full reconnect
     InvalidSession,
+    /// The login session this socket belongs to was revoked; the server
+    /// closes the socket right after. Sign in again.
+    SessionRevoked { family_id: Uuid },
+    /// The account signed in from a device it had not used before
+    NewLoginDetected {
+        event_id: Uuid,
+        device_name: Option<String>,
+        ip_address: Option<String>,
+        created_at: DateTime<Utc>,
+    },
+    /// Server is draining — reconnect (and Resume) after `delay_ms`
+    Reconnect { delay_ms: u64 },
+    /// Incremental ops for a subscribed member list window
+    MemberListUpdate {
+        server_id: Uuid,
+        group_by: MemberListGrouping,
+        member_count: u32,
+        online_count: u32,
+        groups: Vec<MemberListGroup>,
+        ops: Vec<MemberListOp>,
+    },
+}
+
+/// Why members of a channel must rotate their sender keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SenderKeyRotationReason {
+    Kicked,
+    Banned,
+    Left,
+    /// A role or overwrite change took away VIEW_CHANNELS
+    PermissionsChanged,
+}
+
+// ─── Lazy Member List ─────────────────────────────────
+
+/// How a subscribed member list is partitioned into groups.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberListGrouping {
+    /// "online" and "offline"
+    #[default]
+    Status,
+    /// Online members by highest role, then "online" (no role), then "offline"
+    Role,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberListGroup {
+    /// Role ID, or "online" / "offline"
+    pub id: String,
+    pub count: u32,
+}
+
+/// A row in the flattened member list: a group header or a member.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MemberListItem {
+    Group(MemberListGroup),
+    Member {
+        member: ServerMemberResponse,
+        status: String,
+    },
+}
+
+/// An edit to apply, in order, to the client's copy of the list.
+/// Indexes are absolute positions in the full list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MemberListOp {
+    /// Replace the window `[start, end)` wholesale
+    Sync { range: [u32; 2], items: Vec<MemberListItem> },
+    Insert { index: u32, item: MemberListItem },
+    Update { index: u32, item: MemberListItem },
+    Delete { index: u32 },
 }
 
 // ─── Presence ─────────────────────────────────────────
@@ -812,6 +1628,38 @@ pub struct PresenceQuery {
 pub struct PresenceEntry {
     pub user_id: Uuid,
     pub status: String,
+    /// When the current status began (absent when offline)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Last time the user went offline (only if recorded and not hidden)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_status_emoji: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
+}
+
+/// What a viewer may see of a user's presence beyond the live status.
+#[derive(Debug, FromRow)]
+pub struct PresenceDetails {
+    pub user_id: Uuid,
+    /// Friends-only presence and the viewer isn't a friend
+    pub hidden: bool,
+    pub custom_status: Option<String>,
+    pub custom_status_emoji: Option<String>,
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
+    /// Null when the user hides last-seen
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Optional heartbeat payload. Clients that track user input send how long
+/// it has been idle so the server can flip them between online and idle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatInfo {
+    pub idle_ms: u64,
 }
 
 // ─── Refresh Tokens ────────────────────────────────────
@@ -848,6 +1696,95 @@ pub struct SessionResponse {
     pub is_current: bool,
 }
 
+// ─── API Tokens ───────────────────────────────────────
+
+/// A personal access token for scripts and integrations. Only the SHA-256
+/// hash of the token is stored.
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub token_hash: String,
+    /// The first characters of the token, to tell tokens apart in lists
+    pub token_prefix: String,
+    /// Space-separated: 'messages:read', 'messages:send',
+    /// 'server:<uuid>:manage'
+    pub scopes: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub last_used_ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// Days until the token expires; omit for a token that never does
+    pub expires_in_days: Option<i64>,
+}
+
+/// Response for the API token list endpoint.
+#[derive(Debug, Serialize)]
+pub struct ApiTokenResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// Partially masked, like session IPs
+    pub last_used_ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A new token. `token` is shown this once and can't be retrieved later.
+#[derive(Debug, Serialize)]
+pub struct CreateApiTokenResponse {
+    #[serde(flatten)]
+    pub info: ApiTokenResponse,
+    pub token: String,
+}
+
+// ─── Security Events ──────────────────────────────────
+
+/// Something security-relevant that happened to an account: a sign-in from
+/// a new device, a linked device, a login lockout, a password change or
+/// reset, a newly verified email address, a new API token, the account
+/// being deactivated, scheduled for deletion or reactivated, or a personal
+/// data export.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SecurityEvent {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// 'new_device_login', 'device_linked', 'account_locked',
+    /// 'password_changed', 'password_reset', 'email_verified',
+    /// 'api_token_created', 'account_deactivated',
+    /// 'account_deletion_scheduled', 'account_reactivated' or
+    /// 'data_export_requested'
+    pub kind: String,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SecurityEventResponse {
+    pub id: Uuid,
+    pub kind: String,
+    pub device_name: Option<String>,
+    /// Partially masked, like session IPs
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecurityEventQuery {
+    pub limit: Option<i64>,
+    pub before: Option<DateTime<Utc>>,
+}
+
 // ─── Invites ──────────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -936,7 +1873,7 @@ pub struct AdminCreateInvitesRequest {
     pub count: Option<u32>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServerMemberResponse {
     pub user_id: Uuid,
     pub username: String,
@@ -959,6 +1896,15 @@ pub struct UpdateNicknameRequest {
 pub struct UpdateServerRequest {
     pub system_channel_id: Option<Uuid>,
     pub encrypted_meta: Option<String>,
+    /// Server key epoch the client encrypted `encrypted_meta` under
+    #[serde(default)]
+    pub meta_epoch: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferOwnershipRequest {
+    /// The member who becomes owner
+    pub user_id: Uuid,
 }
 
 // ─── Channel Member Info ─────────────────────────────
@@ -966,10 +1912,14 @@ pub struct UpdateServerRequest {
 #[derive(Debug, Serialize, FromRow)]
 pub struct ChannelMemberInfo {
     pub user_id: Uuid,
+    /// "Deleted Account" while the user's account awaits deletion
     pub username: String,
+    /// Hidden while the account is deactivated
     pub display_name: Option<String>,
     pub avatar_url: Option<String>,
     pub joined_at: DateTime<Utc>,
+    /// The account is scheduled for deletion
+    pub deleted: bool,
 }
 
 // ─── Group DM ────────────────────────────────────────
@@ -988,6 +1938,54 @@ pub struct ChangePasswordRequest {
     pub new_password: String,
 }
 
+// ─── Email ───────────────────────────────────────────
+
+/// A single-use emailed token; only its SHA-256 hash is stored.
+#[derive(Debug, Clone, FromRow)]
+pub struct EmailToken {
+    pub token_hash: String,
+    pub user_id: Uuid,
+    /// 'verify_email' or 'password_reset'
+    pub purpose: String,
+    /// For 'verify_email': hash of the address the link was sent to
+    pub email_hash: Option<String>,
+    pub email_hash_key_id: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetEmailRequest {
+    pub email: String,
+    /// Current password — an address on file enables password resets
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetConfirmRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailStatusResponse {
+    /// Whether the server can send email at all
+    pub email_enabled: bool,
+    pub email_set: bool,
+    pub verified: bool,
+    pub verification_pending: bool,
+}
+
 // ─── User Profiles ───────────────────────────────────
 
 #[derive(Debug, Serialize)]
@@ -1000,6 +1998,8 @@ pub struct UserProfileResponse {
     pub banner_url: Option<String>,
     pub custom_status: Option<String>,
     pub custom_status_emoji: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub is_blocked: bool,
     pub is_friend: bool,
@@ -1012,6 +2012,10 @@ pub struct UserProfileResponse {
     pub roles: Option<Vec<RoleResponse>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encrypted_profile: Option<String>, // base64
+    /// The requester verified this user's identity keys
+    pub verified: bool,
+    /// The user's identity keys changed since the requester verified them
+    pub verified_key_changed: bool,
 }
 
 #[derive(Debug, Serialize, FromRow)]
@@ -1028,6 +2032,8 @@ pub struct UpdateProfileRequest {
     pub about_me: Option<String>,
     pub custom_status: Option<String>,
     pub custom_status_emoji: Option<String>,
+    /// When the custom status clears itself (must be in the future)
+    pub custom_status_expires_at: Option<DateTime<Utc>>,
     pub encrypted_profile: Option<String>, // base64-encoded encrypted blob
 }
 
@@ -1059,6 +2065,46 @@ pub struct ProfileKeyResponse {
     pub encrypted_profile_key: String, // base64
 }
 
+// ─── Server Key Epochs ───────────────────────────────
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ServerKeyWrap {
+    pub server_id: Uuid,
+    pub epoch: i32,
+    pub user_id: Uuid,
+    pub encrypted_key: Vec<u8>,
+    pub distributed_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ServerKeyWrapEntry {
+    pub user_id: Uuid,
+    pub encrypted_key: String, // base64
+}
+
+/// Wraps for a new epoch (`key_epoch + 1`, covering every member) or extra
+/// wraps for the current one, e.g. for members who joined since.
+#[derive(Debug, Deserialize)]
+pub struct DistributeServerKeysRequest {
+    pub epoch: i32,
+    pub wraps: Vec<ServerKeyWrapEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerKeyWrapResponse {
+    pub epoch: i32,
+    pub encrypted_key: String, // base64
+    pub distributed_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerKeyEpochsResponse {
+    pub key_epoch: i32,
+    pub wraps: Vec<ServerKeyWrapResponse>,
+}
+
 // ─── Blocked Users ───────────────────────────────────
 
 #[derive(Debug, Serialize, FromRow)]
@@ -1214,6 +2260,12 @@ pub struct UpdateDmPrivacyRequest {
     pub dm_privacy: String, // "everyone", "friends_only", "server_members"
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UpdatePresencePrivacyRequest {
+    pub presence_privacy: Option<String>, // "everyone", "friends_only"
+    pub hide_last_seen: Option<bool>,
+}
+
 // ─── Pinned Messages ────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
@@ -1336,6 +2388,57 @@ pub struct DeleteAccountRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct DeactivateAccountRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountDeletionResponse {
+    /// When the account will be erased, unless its owner signs in first
+    pub deletion_scheduled_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminDeleteUserQuery {
+    /// Erase now instead of after the grace period
+    #[serde(default)]
+    pub immediate: bool,
+}
+
+// ─── Data Exports ────────────────────────────────────
+
+/// A personal data export job and, once ready, its archive in storage.
+#[derive(Debug, Clone, FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: String, // "pending", "ready", "failed"
+    pub storage_key: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DataExportResponse {
+    pub id: Uuid,
+    pub status: String,
+    pub size_bytes: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Signed link to the archive, set while it is ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DataExportDownloadQuery {
+    pub sig: String,
+}
+
 // ─── Audit Log ───────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -1429,6 +2532,49 @@ pub struct AdminUserResponse {
     pub server_count: i64,
 }
 
+/// A row of a signing keyring (access tokens, tree heads, ...).
+#[derive(Debug, Clone, FromRow)]
+pub struct JwtSigningKey {
+    pub kid: String,
+    pub purpose: String, // jwt_keys::KeyPurpose
+    pub algorithm: String,
+    pub private_key: Vec<u8>, // encrypted PKCS#8
+    pub public_key: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    pub activates_at: DateTime<Utc>,
+    pub retires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SigningKeyResponse {
+    pub kid: String,
+    /// What the key signs: "access_token", "tree_head" or "sender_certificate"
+    pub purpose: String,
+    pub algorithm: String,
+    pub created_at: DateTime<Utc>,
+    pub activates_at: DateTime<Utc>,
+    pub retires_at: Option<DateTime<Utc>>,
+    /// Currently signing for its purpose
+    pub signing: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RotateSigningKeyQuery {
+    /// Which keyring to rotate; access tokens by default
+    pub purpose: Option<String>,
+}
+
+/// How many stored email hashes each email hash key made.
+#[derive(Debug, Serialize)]
+pub struct EmailHashKeyUsage {
+    /// None for hashes made with the legacy jwt_secret
+    pub key_id: Option<String>,
+    pub current: bool,
+    /// Still configured, so lookups match its hashes
+    pub configured: bool,
+    pub users: i64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SetAdminRequest {
     pub is_admin: bool,
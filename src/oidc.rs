@@ -0,0 +1,290 @@
+//! OpenID Connect single sign-on: authorization code flow with PKCE.
+//!
+//! Haven is the relying party. `/auth/oidc/authorize` stores a random state,
+//! nonce and PKCE verifier and hands the client the issuer's authorization
+//! URL. The issuer redirects the browser to `oidc_redirect_uri`, whose page
+//! posts the code and state to `/auth/oidc/callback`. The server then redeems
+//! the code with the verifier and checks the ID token's signature against the
+//! issuer's JWKS, along with its issuer, audience, expiry and nonce.
+
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::AppConfig;
+use crate::errors::{AppError, AppResult};
+
+/// How long an authorization request may take before its state expires.
+pub const LOGIN_STATE_TTL_MINUTES: i64 = 10;
+
+/// The parts of the issuer's discovery document the login flow needs.
+#[derive(Debug, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// Verified ID token: the issuer-scoped subject and every claim.
+#[derive(Debug)]
+pub struct IdToken {
+    pub subject: String,
+    pub claims: Map<String, Value>,
+}
+
+fn sso_error(detail: impl std::fmt::Display) -> AppError {
+    tracing::warn!("OIDC login failed: {}", detail);
+    AppError::AuthError("SSO login failed".into())
+}
+
+/// The form issuers are stored and looked up in: some providers publish
+/// theirs with a trailing slash, some without.
+pub fn normalize_issuer(issuer: &str) -> &str {
+    issuer.trim_end_matches('/')
+}
+
+/// Fetch `/.well-known/openid-configuration` and check that it describes the
+/// configured issuer.
+pub async fn discover(issuer: &str) -> AppResult<ProviderMetadata> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        normalize_issuer(issuer)
+    );
+    let metadata: ProviderMetadata = reqwest::get(&url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("OIDC discovery failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("OIDC discovery parse failed: {}", e)))?;
+    if normalize_issuer(&metadata.issuer) != normalize_issuer(issuer) {
+        return Err(AppError::Internal(anyhow::anyhow!(
+            "OIDC discovery returned issuer {} for {}",
+            metadata.issuer,
+            issuer
+        )));
+    }
+    Ok(metadata)
+}
+
+/// A random URL-safe value for `state`, `nonce` or the PKCE code verifier.
+pub fn random_token() -> String {
+    crate::auth::generate_refresh_token()
+}
+
+/// PKCE S256 code challenge: base64url(SHA-256(verifier)).
+pub fn pkce_challenge(verifier: &str) -> String {
+    base64::Engine::encode(
+        &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+        Sha256::digest(verifier.as_bytes()),
+    )
+}
+
+/// The issuer URL to send the user's browser to.
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    config: &AppConfig,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> String {
+    let separator = if metadata.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    format!(
+        "{}{}response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+        metadata.authorization_endpoint,
+        separator,
+        urlencoding::encode(&config.oidc_client_id),
+        urlencoding::encode(&config.oidc_redirect_uri),
+        urlencoding::encode(&config.oidc_scopes),
+        urlencoding::encode(state),
+        urlencoding::encode(nonce),
+        pkce_challenge(code_verifier),
+    )
+}
+
+/// Redeem an authorization code at the token endpoint and return the raw ID token.
+pub async fn exchange_code(
+    metadata: &ProviderMetadata,
+    config: &AppConfig,
+    code: &str,
+    code_verifier: &str,
+) -> AppResult<String> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", config.oidc_redirect_uri.as_str()),
+        ("client_id", config.oidc_client_id.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+    if !config.oidc_client_secret.is_empty() {
+        form.push(("client_secret", config.oidc_client_secret.as_str()));
+    }
+
+    let res = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("OIDC token request failed: {}", e)))?;
+    if !res.status().is_success() {
+        return Err(sso_error(format!(
+            "token endpoint returned {}",
+            res.status()
+        )));
+    }
+    let body: TokenResponse = res.json().await.map_err(sso_error)?;
+    body.id_token
+        .ok_or_else(|| sso_error("token response has no id_token"))
+}
+
+/// Verify an ID token's signature against the issuer's JWKS and check its
+/// issuer, audience, expiry and nonce.
+pub async fn verify_id_token(
+    metadata: &ProviderMetadata,
+    config: &AppConfig,
+    id_token: &str,
+    nonce: &str,
+) -> AppResult<IdToken> {
+    let header = jsonwebtoken::decode_header(id_token).map_err(sso_error)?;
+    // HMAC algorithms would make the client secret a signing key; only
+    // accept the issuer's published public keys.
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(sso_error("ID token uses a symmetric algorithm"));
+    }
+
+    let jwks: JwkSet = reqwest::get(&metadata.jwks_uri)
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("OIDC JWKS fetch failed: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("OIDC JWKS parse failed: {}", e)))?;
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| sso_error("no matching key in JWKS"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(sso_error)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.oidc_client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = jsonwebtoken::decode::<Map<String, Value>>(id_token, &key, &validation)
+        .map_err(sso_error)?
+        .claims;
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(sso_error("ID token nonce mismatch"));
+    }
+    let subject = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| sso_error("ID token has no subject"))?
+        .to_string();
+
+    Ok(IdToken { subject, claims })
+}
+
+/// Map the configured claim to a Haven username: an email-style value keeps
+/// its local part, characters outside `[A-Za-z0-9_-]` become `_`, and the
+/// result is cut to 32 characters. `None` when the claim is missing or too
+/// short.
+pub fn username_from_claims(claims: &Map<String, Value>, claim: &str) -> Option<String> {
+    let value = claims.get(claim)?.as_str()?;
+    let local = value.split('@').next().unwrap_or(value);
+    let username: String = local
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(32)
+        .collect();
+    (username.len() >= 3).then_some(username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pkce_challenge_matches_rfc7636_example() {
+        // RFC 7636 appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn username_mapping_sanitizes_claims() {
+        let claims = json!({
+            "preferred_username": "jane.doe",
+            "email": "j.smith@example.com",
+            "short": "ab",
+            "long": "x".repeat(40),
+            "number": 42,
+        });
+        let claims = claims.as_object().unwrap();
+        assert_eq!(
+            username_from_claims(claims, "preferred_username").as_deref(),
+            Some("jane_doe")
+        );
+        assert_eq!(
+            username_from_claims(claims, "email").as_deref(),
+            Some("j_smith")
+        );
+        assert_eq!(
+            username_from_claims(claims, "long").map(|u| u.len()),
+            Some(32)
+        );
+        assert!(username_from_claims(claims, "short").is_none());
+        assert!(username_from_claims(claims, "number").is_none());
+        assert!(username_from_claims(claims, "missing").is_none());
+    }
+
+    #[test]
+    fn authorization_url_carries_pkce_and_nonce() {
+        let metadata = ProviderMetadata {
+            issuer: "https://idp.example".into(),
+            authorization_endpoint: "https://idp.example/authorize".into(),
+            token_endpoint: "https://idp.example/token".into(),
+            jwks_uri: "https://idp.example/jwks".into(),
+        };
+        let mut config = AppConfig::test_default();
+        config.oidc_client_id = "haven".into();
+        config.oidc_redirect_uri = "https://chat.example/sso/callback".into();
+        let url = authorization_url(&metadata, &config, "st", "nn", "verifier");
+        assert!(
+            url.starts_with("https://idp.example/authorize?response_type=code&client_id=haven&")
+        );
+        assert!(url.contains("redirect_uri=https%3A%2F%2Fchat.example%2Fsso%2Fcallback"));
+        assert!(url.contains("scope=openid%20profile%20email"));
+        assert!(url.contains("&state=st&nonce=nn&"));
+        assert!(url.contains(&format!(
+            "code_challenge={}&code_challenge_method=S256",
+            pkce_challenge("verifier")
+        )));
+    }
+}
@@ -0,0 +1,243 @@
+//! Adaptive proof-of-work for registration and, under attack, login.
+//!
+//! A challenge's difficulty is fixed when it is issued and stored with it.
+//! It starts at `pow_base_difficulty` and gains a bit for every doubling of
+//! the last hour's registrations beyond `pow_registration_baseline`, plus up
+//! to two bits for a client IP close to its auth rate limit. Login asks for a
+//! solution only while failed logins across the instance exceed
+//! `login_pow_failure_threshold` in a 5-minute window.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppResult};
+use crate::AppState;
+
+/// How long an issued challenge can be redeemed (seconds)
+pub const CHALLENGE_TTL_SECS: u64 = 300;
+
+/// Window over which failed logins are counted (minutes)
+const LOGIN_FAILURE_WINDOW_MINUTES: u64 = 5;
+/// Window over which registrations are counted (minutes)
+const REGISTRATION_WINDOW_MINUTES: u64 = 60;
+
+pub const REGISTRATIONS: &str = "registrations";
+pub const LOGIN_FAILURES: &str = "login_failures";
+
+/// Verify a Proof-of-Work solution: SHA-256(challenge + nonce) must have `difficulty` leading zero bits.
+pub fn verify_solution(challenge: &str, nonce: &str, difficulty: u32) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(challenge.as_bytes());
+    hasher.update(nonce.as_bytes());
+    let hash = hasher.finalize();
+
+    // Check leading zero bits
+    let mut zero_bits = 0u32;
+    for &byte in hash.as_slice() {
+        if byte == 0 {
+            zero_bits += 8;
+        } else {
+            zero_bits += byte.leading_zeros();
+            break;
+        }
+        if zero_bits >= difficulty {
+            break;
+        }
+    }
+    zero_bits >= difficulty
+}
+
+/// Difficulty for the given load: one extra bit per doubling of
+/// `recent_registrations` beyond `baseline`, one at half and two at the full
+/// per-IP auth rate limit (`ip_usage`), capped at `max`.
+pub fn adaptive_difficulty(base: u32, max: u32, recent_registrations: u64, baseline: u32, ip_usage: f64) -> u32 {
+    let mut volume_bits = 0;
+    let mut threshold = baseline.max(1) as u64;
+    while recent_registrations > threshold && volume_bits < 32 {
+        volume_bits += 1;
+        threshold *= 2;
+    }
+    let ip_bits = if ip_usage >= 1.0 {
+        2
+    } else if ip_usage >= 0.5 {
+        1
+    } else {
+        0
+    };
+    (base + volume_bits + ip_bits).min(max.max(base))
+}
+
+/// Difficulty to issue a challenge at for this client right now.
+pub async fn current_difficulty(state: &AppState, ip: IpAddr) -> u32 {
+    let config = &state.config;
+    let registrations = window_count(state, REGISTRATIONS, REGISTRATION_WINDOW_MINUTES).await;
+    adaptive_difficulty(
+        config.pow_base_difficulty,
+        config.pow_max_difficulty,
+        registrations,
+        config.pow_registration_baseline,
+        state.auth_limiter.usage(ip),
+    )
+}
+
+/// Store a new single-use challenge at `difficulty` and return it.
+pub async fn issue_challenge(state: &AppState, difficulty: u32) -> String {
+    let challenge = Uuid::new_v4().to_string();
+
+    if let Some(mut redis) = state.redis.clone() {
+        let result: Result<(), redis::RedisError> = redis::cmd("SET")
+            .arg(format!("haven:pow:{}", challenge))
+            .arg(difficulty)
+            .arg("EX")
+            .arg(CHALLENGE_TTL_SECS)
+            .query_async(&mut redis)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to store PoW challenge: {}", e);
+        }
+    } else {
+        let expiry = Instant::now() + Duration::from_secs(CHALLENGE_TTL_SECS);
+        state.memory.pow_challenges.insert(challenge.clone(), (difficulty, expiry));
+    }
+
+    challenge
+}
+
+/// Consume a challenge (single-use) and check the nonce solves it at the
+/// difficulty it was issued with.
+pub async fn redeem(state: &AppState, challenge: &str, nonce: &str) -> AppResult<()> {
+    let difficulty = if let Some(mut redis) = state.redis.clone() {
+        redis::cmd("GETDEL")
+            .arg(format!("haven:pow:{}", challenge))
+            .query_async::<_, Option<u32>>(&mut redis)
+            .await
+            .unwrap_or(None)
+    } else {
+        state
+            .memory
+            .pow_challenges
+            .remove(challenge)
+            .filter(|(_, (_, expiry))| Instant::now() < *expiry)
+            .map(|(_, (difficulty, _))| difficulty)
+    };
+
+    let Some(difficulty) = difficulty else {
+        return Err(AppError::Validation(
+            "Invalid or expired PoW challenge — request a new one from /auth/challenge".into(),
+        ));
+    };
+    if !verify_solution(challenge, nonce, difficulty) {
+        return Err(AppError::Validation("Invalid Proof-of-Work solution".into()));
+    }
+    Ok(())
+}
+
+/// Whether login currently needs a PoW solution.
+pub async fn login_pow_required(state: &AppState) -> bool {
+    let threshold = state.config.login_pow_failure_threshold;
+    threshold > 0
+        && window_count(state, LOGIN_FAILURES, LOGIN_FAILURE_WINDOW_MINUTES).await >= threshold as u64
+}
+
+// ─── Event counters ────────────────────────────────────
+
+fn current_minute() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64 / 60
+}
+
+fn counter_key(event: &str, minute: u64) -> String {
+    format!("haven:abuse:{}:{}", event, minute)
+}
+
+/// Count one `event` (`REGISTRATIONS`, `LOGIN_FAILURES`) in its per-minute
+/// bucket, instance-wide through Redis when configured.
+pub async fn record(state: &AppState, event: &'static str) {
+    let minute = current_minute();
+    if let Some(mut redis) = state.redis.clone() {
+        let result: Result<(), redis::RedisError> = redis::pipe()
+            .cmd("INCR")
+            .arg(counter_key(event, minute))
+            .ignore()
+            .cmd("EXPIRE")
+            .arg(counter_key(event, minute))
+            .arg(REGISTRATION_WINDOW_MINUTES * 60 + 60)
+            .ignore()
+            .query_async(&mut redis)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to count {} event: {}", event, e);
+        }
+    } else {
+        *state.memory.event_counters.entry((event, minute)).or_insert(0) += 1;
+    }
+}
+
+/// Events of this kind in the last `minutes` minutes (current minute included).
+async fn window_count(state: &AppState, event: &'static str, minutes: u64) -> u64 {
+    let now = current_minute();
+    let first = now + 1 - minutes.max(1);
+    if let Some(mut redis) = state.redis.clone() {
+        let keys: Vec<String> = (first..=now).map(|m| counter_key(event, m)).collect();
+        match redis::cmd("MGET")
+            .arg(&keys)
+            .query_async::<_, Vec<Option<u64>>>(&mut redis)
+            .await
+        {
+            Ok(counts) => counts.into_iter().flatten().sum(),
+            Err(e) => {
+                tracing::warn!("Failed to read {} counters: {}", event, e);
+                0
+            }
+        }
+    } else {
+        (first..=now)
+            .filter_map(|m| state.memory.event_counters.get(&(event, m)).map(|c| *c as u64))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_instance_uses_base_difficulty() {
+        assert_eq!(adaptive_difficulty(20, 24, 0, 30, 0.0), 20);
+        assert_eq!(adaptive_difficulty(20, 24, 30, 30, 0.2), 20);
+    }
+
+    #[test]
+    fn registration_volume_adds_a_bit_per_doubling() {
+        assert_eq!(adaptive_difficulty(20, 30, 31, 30, 0.0), 21);
+        assert_eq!(adaptive_difficulty(20, 30, 60, 30, 0.0), 21);
+        assert_eq!(adaptive_difficulty(20, 30, 61, 30, 0.0), 22);
+        assert_eq!(adaptive_difficulty(20, 30, 240, 30, 0.0), 23);
+    }
+
+    #[test]
+    fn ip_pressure_adds_up_to_two_bits() {
+        assert_eq!(adaptive_difficulty(20, 30, 0, 30, 0.5), 21);
+        assert_eq!(adaptive_difficulty(20, 30, 0, 30, 1.3), 22);
+    }
+
+    #[test]
+    fn difficulty_is_capped() {
+        assert_eq!(adaptive_difficulty(20, 24, 1_000_000, 30, 1.0), 24);
+        // A max below the base never lowers it
+        assert_eq!(adaptive_difficulty(20, 16, 1_000_000, 30, 1.0), 20);
+    }
+
+    #[test]
+    fn solution_must_meet_difficulty() {
+        let challenge = "test-challenge";
+        let nonce = (0u64..)
+            .map(|n| n.to_string())
+            .find(|n| verify_solution(challenge, n, 8))
+            .unwrap();
+        assert!(verify_solution(challenge, &nonce, 8));
+        assert!(verify_solution(challenge, &nonce, 0));
+    }
+}
@@ -0,0 +1,146 @@
+//! Live presence store.
+//!
+//! Each connected user has a `PresenceState` in the Redis `haven:presence`
+//! hash (as JSON), or in `MemoryStore::presence` without Redis. The hash is
+//! wiped on startup; anything that must outlive a restart (last-seen) lives
+//! in Postgres instead.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::AppState;
+
+const PRESENCE_KEY: &str = "haven:presence";
+
+/// A user's live presence.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceState {
+    /// online, idle, dnd or invisible
+    pub status: String,
+    /// When `status` last changed
+    pub since: DateTime<Utc>,
+    /// Idle was set by heartbeat idle detection, not by the user
+    #[serde(default)]
+    pub auto_idle: bool,
+}
+
+impl PresenceState {
+    pub fn new(status: &str) -> Self {
+        Self {
+            status: status.to_string(),
+            since: Utc::now(),
+            auto_idle: false,
+        }
+    }
+
+    /// Status as other users see it (invisible is reported as offline).
+    pub fn visible_status(&self) -> &str {
+        if self.status == "invisible" {
+            "offline"
+        } else {
+            &self.status
+        }
+    }
+
+    fn decode(raw: &str) -> Self {
+        // Instances from before presence carried `since` stored bare strings
+        serde_json::from_str(raw).unwrap_or_else(|_| Self::new(raw))
+    }
+}
+
+pub async fn get(state: &AppState, user_id: Uuid) -> Option<PresenceState> {
+    if let Some(mut redis) = state.redis.clone() {
+        let raw: Option<String> = redis::cmd("HGET")
+            .arg(PRESENCE_KEY)
+            .arg(user_id.to_string())
+            .query_async(&mut redis)
+            .await
+            .ok()
+            .flatten();
+        raw.map(|r| PresenceState::decode(&r))
+    } else {
+        state.memory.presence.get(&user_id).map(|p| p.value().clone())
+    }
+}
+
+pub async fn get_many(state: &AppState, user_ids: &[Uuid]) -> Vec<Option<PresenceState>> {
+    if user_ids.is_empty() {
+        return vec![];
+    }
+    if let Some(mut redis) = state.redis.clone() {
+        let mut cmd = redis::cmd("HMGET");
+        cmd.arg(PRESENCE_KEY);
+        for uid in user_ids {
+            cmd.arg(uid.to_string());
+        }
+        let raw: Vec<Option<String>> = cmd
+            .query_async(&mut redis)
+            .await
+            .unwrap_or_else(|_| vec![None; user_ids.len()]);
+        raw.iter()
+            .map(|r| r.as_deref().map(PresenceState::decode))
+            .collect()
+    } else {
+        user_ids
+            .iter()
+            .map(|uid| state.memory.presence.get(uid).map(|p| p.value().clone()))
+            .collect()
+    }
+}
+
+pub async fn set(state: &AppState, user_id: Uuid, presence: &PresenceState) {
+    if let Some(mut redis) = state.redis.clone() {
+        let Ok(json) = serde_json::to_string(presence) else {
+            return;
+        };
+        let result: Result<(), redis::RedisError> = redis::cmd("HSET")
+            .arg(PRESENCE_KEY)
+            .arg(user_id.to_string())
+            .arg(json)
+            .query_async(&mut redis)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to update presence in Redis: {}", e);
+        }
+    }
+    // Always update in-memory presence
+    state.memory.presence.insert(user_id, presence.clone());
+}
+
+pub async fn remove(state: &AppState, user_id: Uuid) {
+    if let Some(mut redis) = state.redis.clone() {
+        let result: Result<(), redis::RedisError> = redis::cmd("HDEL")
+            .arg(PRESENCE_KEY)
+            .arg(user_id.to_string())
+            .query_async(&mut redis)
+            .await;
+        if let Err(e) = result {
+            tracing::warn!("Failed to update presence in Redis: {}", e);
+        }
+    }
+    state.memory.presence.remove(&user_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_json_and_legacy_values() {
+        let p = PresenceState::new("dnd");
+        let decoded = PresenceState::decode(&serde_json::to_string(&p).unwrap());
+        assert_eq!(decoded.status, "dnd");
+        assert_eq!(decoded.since, p.since);
+
+        let legacy = PresenceState::decode("idle");
+        assert_eq!(legacy.status, "idle");
+        assert!(!legacy.auto_idle);
+    }
+
+    #[test]
+    fn invisible_is_reported_offline() {
+        assert_eq!(PresenceState::new("invisible").visible_status(), "offline");
+        assert_eq!(PresenceState::new("idle").visible_status(), "idle");
+    }
+}
@@ -5,6 +5,7 @@ use futures::StreamExt;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
+use crate::member_list;
 use crate::models::WsServerMessage;
 use crate::AppState;
 
@@ -44,6 +45,17 @@ pub async fn publish_user_event(
     }
 }
 
+/// Send a WS event to every connection of `user_id`: the local ones
+/// directly, those on other instances through Redis.
+pub(crate) async fn send_to_user(state: &AppState, user_id: Uuid, msg: &WsServerMessage) {
+    if let Some(conns) = state.connections.get(&user_id) {
+        for tx in conns.iter() {
+            let _ = tx.send(msg.clone());
+        }
+    }
+    publish_user_event(state.redis.clone().as_mut(), user_id, msg).await;
+}
+
 /// Tracks which Redis channels this instance is subscribed to.
 pub type PubSubSubscriptions = Arc<Mutex<HashSet<String>>>;
 
@@ -89,6 +101,10 @@ pub fn start_subscriber(state: AppState) -> PubSubSubscriptions {
                             }
                         }
                     }
+                    // Member list changes concern every instance with cached lists
+                    if let Err(e) = pubsub.subscribe(member_list::REDIS_CHANNEL).await {
+                        tracing::error!("Failed to subscribe to {}: {}", member_list::REDIS_CHANNEL, e);
+                    }
 
                     // Process incoming messages
                     let mut msg_stream = pubsub.on_message();
@@ -98,13 +114,18 @@ pub fn start_subscriber(state: AppState) -> PubSubSubscriptions {
                             Err(_) => continue,
                         };
 
+                        let redis_channel: String = msg.get_channel_name().to_string();
+
+                        if redis_channel == member_list::REDIS_CHANNEL {
+                            member_list::apply_relayed(&state, &payload).await;
+                            continue;
+                        }
+
                         let ws_msg: WsServerMessage = match serde_json::from_str(&payload) {
                             Ok(m) => m,
                             Err(_) => continue,
                         };
 
-                        let redis_channel: String = msg.get_channel_name().to_string();
-
                         if let Some(channel_id_str) = redis_channel.strip_prefix("haven:ws:ch:") {
                             // Channel-scoped event — forward to local broadcast
                             if let Ok(channel_id) = Uuid::parse_str(channel_id_str) {
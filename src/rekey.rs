@@ -0,0 +1,233 @@
+//! Sender key rotation after channel membership changes.
+//!
+//! A user who loses read access to a channel still holds every sender key
+//! distributed to them there. Handlers that can take access away (kick, ban,
+//! leave, role and overwrite changes) snapshot who can read which channels
+//! before the change and compare afterwards. For every channel someone lost,
+//! SKDMs still pending for them are purged and the channel's remaining readers
+//! get `SenderKeyRotationRequired`, so senders distribute fresh keys before
+//! their next message.
+
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::models::{Channel, SenderKeyRotationReason, WsServerMessage};
+use crate::permissions;
+use crate::pubsub;
+use crate::AppState;
+
+/// Which server channels a set of users could read at snapshot time.
+pub struct ChannelAccess {
+    server_id: Uuid,
+    user_ids: Option<Vec<Uuid>>,
+    channel_id: Option<Uuid>,
+    readable: HashMap<Uuid, HashSet<Uuid>>,
+}
+
+impl ChannelAccess {
+    /// Every channel of `server_id` that `user_id` can read. Taken before
+    /// removing a member or changing their roles.
+    pub async fn of_user(state: &AppState, server_id: Uuid, user_id: Uuid) -> Self {
+        Self::snapshot(state, server_id, Some(vec![user_id]), None).await
+    }
+
+    /// Which members can read the private channels of `server_id` (or just
+    /// `channel_id`). Taken before editing roles or overwrites, which only
+    /// gate private channels.
+    pub async fn of_members(state: &AppState, server_id: Uuid, channel_id: Option<Uuid>) -> Self {
+        Self::snapshot(state, server_id, None, channel_id).await
+    }
+
+    async fn snapshot(
+        state: &AppState,
+        server_id: Uuid,
+        user_ids: Option<Vec<Uuid>>,
+        channel_id: Option<Uuid>,
+    ) -> Self {
+        let readable =
+            match readable_channels(state, server_id, user_ids.as_deref(), channel_id).await {
+                Ok(readable) => readable,
+                Err(e) => {
+                    tracing::warn!("Failed to snapshot channel access for server {}: {}", server_id, e);
+                    HashMap::new()
+                }
+            };
+        Self { server_id, user_ids, channel_id, readable }
+    }
+
+    /// Recompute access and rekey every channel a snapshotted user can no
+    /// longer read. Failures are logged, not returned: the membership change
+    /// itself has already happened.
+    pub async fn rekey_lost(self, state: &AppState, reason: SenderKeyRotationReason) {
+        if self.readable.is_empty() {
+            return;
+        }
+        let now = match readable_channels(
+            state,
+            self.server_id,
+            self.user_ids.as_deref(),
+            self.channel_id,
+        )
+        .await
+        {
+            Ok(now) => now,
+            Err(e) => {
+                tracing::warn!("Failed to recompute channel access for server {}: {}", self.server_id, e);
+                return;
+            }
+        };
+
+        let mut lost: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        for (user_id, before) in &self.readable {
+            let after = now.get(user_id);
+            for channel_id in before {
+                if !after.is_some_and(|a| a.contains(channel_id)) {
+                    lost.entry(*channel_id).or_default().push(*user_id);
+                }
+            }
+        }
+
+        for (channel_id, removed) in lost {
+            rekey_channel(state, channel_id, &removed, reason).await;
+        }
+    }
+}
+
+/// Purge SKDMs pending for `removed` in `channel_id` and tell the channel's
+/// remaining readers to rotate their sender keys.
+pub async fn rekey_channel(
+    state: &AppState,
+    channel_id: Uuid,
+    removed: &[Uuid],
+    reason: SenderKeyRotationReason,
+) {
+    if let Err(e) = try_rekey_channel(state, channel_id, removed, reason).await {
+        tracing::warn!("Failed to rekey channel {}: {}", channel_id, e);
+    }
+}
+
+async fn try_rekey_channel(
+    state: &AppState,
+    channel_id: Uuid,
+    removed: &[Uuid],
+    reason: SenderKeyRotationReason,
+) -> AppResult<()> {
+    for &user_id in removed {
+        queries::delete_sender_key_distributions_to_user(state.db.write(), channel_id, user_id)
+            .await?;
+    }
+
+    let Some(channel) = queries::find_channel_by_id(state.db.read(), channel_id).await? else {
+        return Ok(());
+    };
+    let audience = match channel.server_id {
+        Some(server_id) if !channel.is_private => {
+            queries::get_server_member_ids(state.db.read(), server_id).await?
+        }
+        Some(server_id) => readable_channels(state, server_id, None, Some(channel_id))
+            .await?
+            .into_iter()
+            .filter(|(_, channels)| channels.contains(&channel_id))
+            .map(|(user_id, _)| user_id)
+            .collect(),
+        None => queries::get_channel_member_ids(state.db.read(), channel_id).await?,
+    };
+
+    let msg = WsServerMessage::SenderKeyRotationRequired { channel_id, reason };
+    for user_id in audience {
+        if !removed.contains(&user_id) {
+            pubsub::send_to_user(state, user_id, &msg).await;
+        }
+    }
+    Ok(())
+}
+
+/// Map each of `user_ids` (every server member when `None`) to the channels
+/// of `server_id` they can read, applying the `list_server_channels` rule:
+/// members read public channels, and private ones only when VIEW_CHANNELS
+/// survives the channel's overwrites. With `user_ids` of `None` only private
+/// channels are considered, since role and overwrite edits can't affect the
+/// rest. `channel_id` narrows the result to that one channel. Members, their
+/// roles and the overwrites are each read in one query, however many members
+/// the server has.
+async fn readable_channels(
+    state: &AppState,
+    server_id: Uuid,
+    user_ids: Option<&[Uuid]>,
+    channel_id: Option<Uuid>,
+) -> AppResult<HashMap<Uuid, HashSet<Uuid>>> {
+    let db = state.db.read();
+    let channels: Vec<Channel> = queries::get_server_channels(db, server_id)
+        .await?
+        .into_iter()
+        .filter(|c| channel_id.is_none_or(|id| c.id == id))
+        .filter(|c| user_ids.is_some() || c.is_private)
+        .collect();
+    if channels.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // Members (only those still in the server) with their role ids and permissions
+    let mut members: HashMap<Uuid, (Vec<Uuid>, Vec<i64>)> = HashMap::new();
+    for (user_id, role_id, role_perms) in queries::get_member_role_grants(db, server_id, user_ids).await? {
+        let (role_ids, perms) = members.entry(user_id).or_default();
+        role_ids.extend(role_id);
+        perms.extend(role_perms);
+    }
+
+    // Without private channels every member reads everything
+    let private_ids: Vec<Uuid> = channels.iter().filter(|c| c.is_private).map(|c| c.id).collect();
+    if private_ids.is_empty() {
+        return Ok(members
+            .into_keys()
+            .map(|user_id| (user_id, channels.iter().map(|c| c.id).collect()))
+            .collect());
+    }
+
+    let mut overwrites: HashMap<Uuid, Vec<_>> = private_ids.iter().map(|&id| (id, Vec::new())).collect();
+    for o in queries::get_overwrites_for_channels(db, &private_ids).await? {
+        let target = if o.target_type == "role" {
+            permissions::OverwriteTarget::Role(o.target_id)
+        } else {
+            permissions::OverwriteTarget::Member(o.target_id)
+        };
+        if let Some(ow_tuples) = overwrites.get_mut(&o.channel_id) {
+            ow_tuples.push((target, o.allow_bits, o.deny_bits));
+        }
+    }
+
+    let owner_id = queries::find_server_by_id(db, server_id)
+        .await?
+        .ok_or(AppError::NotFound("Server not found".into()))?
+        .owner_id;
+    let everyone = queries::find_default_role(db, server_id).await?;
+    let everyone_role_id = everyone.as_ref().map(|r| r.id).unwrap_or(Uuid::nil());
+    let everyone_perms = everyone
+        .as_ref()
+        .map(|r| r.permissions)
+        .unwrap_or(permissions::DEFAULT_PERMISSIONS);
+
+    let mut readable = HashMap::with_capacity(members.len());
+    for (user_id, (member_role_ids, role_perms)) in members {
+        let base_perms =
+            permissions::compute_server_permissions(user_id == owner_id, everyone_perms, &role_perms);
+        let mut visible = HashSet::new();
+        for c in &channels {
+            let Some(ow_tuples) = overwrites.get(&c.id) else {
+                visible.insert(c.id);
+                continue;
+            };
+            let effective = permissions::apply_channel_overwrites(
+                base_perms, ow_tuples, &member_role_ids, user_id, everyone_role_id,
+            );
+            if permissions::has_permission(effective, permissions::VIEW_CHANNELS) {
+                visible.insert(c.id);
+            }
+        }
+        readable.insert(user_id, visible);
+    }
+    Ok(readable)
+}
@@ -0,0 +1,109 @@
+//! Access-token revocation.
+//!
+//! Access tokens carry the refresh-token family (`family_id`) they were
+//! issued to. Revoking a session puts its family on a denylist — in Redis
+//! when configured, and always in the in-memory store — for
+//! `jwt_expiry_hours`, after which every access token of that family has
+//! expired anyway. `AuthUser` and the WebSocket upgrade reject denylisted
+//! families, and live sockets of the family are told `SessionRevoked` and
+//! closed, on every instance via the user's pub/sub channel.
+
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::auth::Claims;
+use crate::db::queries;
+use crate::errors::{AppError, AppResult};
+use crate::models::WsServerMessage;
+use crate::AppState;
+
+fn denylist_key(family_id: Uuid) -> String {
+    format!("haven:revoked:family:{}", family_id)
+}
+
+/// Whether a session has been revoked. Redis errors fall back to the local
+/// denylist rather than locking every user out.
+pub async fn is_family_revoked(state: &AppState, family_id: Uuid) -> bool {
+    if let Some(expiry) = state.memory.revoked_families.get(&family_id) {
+        if *expiry > Instant::now() {
+            return true;
+        }
+    }
+    let Some(mut redis) = state.redis.clone() else {
+        return false;
+    };
+    match redis::cmd("EXISTS")
+        .arg(denylist_key(family_id))
+        .query_async::<_, bool>(&mut redis)
+        .await
+    {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            tracing::warn!("Revocation check failed for family {}: {}", family_id, e);
+            false
+        }
+    }
+}
+
+/// Reject claims whose session has been revoked.
+pub async fn check_claims(state: &AppState, claims: &Claims) -> AppResult<()> {
+    match claims.family_id {
+        Some(family_id) if is_family_revoked(state, family_id).await => Err(AppError::AuthError(
+            "Session has been revoked — please log in again".into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Denylist these sessions until their access tokens expire and disconnect
+/// their sockets.
+pub async fn revoke_families(state: &AppState, user_id: Uuid, family_ids: &[Uuid]) {
+    let ttl_secs = (state.config.jwt_expiry_hours.max(1) * 3600) as u64;
+    let expiry = Instant::now() + Duration::from_secs(ttl_secs);
+
+    for &family_id in family_ids {
+        state.memory.revoked_families.insert(family_id, expiry);
+        if let Some(mut redis) = state.redis.clone() {
+            let result: Result<(), redis::RedisError> = redis::cmd("SET")
+                .arg(denylist_key(family_id))
+                .arg(1)
+                .arg("EX")
+                .arg(ttl_secs)
+                .query_async(&mut redis)
+                .await;
+            if let Err(e) = result {
+                tracing::error!("Failed to denylist token family {}: {}", family_id, e);
+            }
+        }
+
+        crate::pubsub::send_to_user(state, user_id, &WsServerMessage::SessionRevoked { family_id }).await;
+    }
+}
+
+/// Revoke one session: delete its refresh tokens and denylist the family.
+/// Returns false when the user has no such session.
+pub async fn revoke_session(state: &AppState, user_id: Uuid, family_id: Uuid) -> AppResult<bool> {
+    if queries::revoke_session(state.db.write(), user_id, family_id).await? == 0 {
+        return Ok(false);
+    }
+    revoke_families(state, user_id, &[family_id]).await;
+    Ok(true)
+}
+
+/// Revoke every session of a user: delete all refresh tokens and denylist
+/// each family they belonged to.
+pub async fn revoke_all_sessions(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    let families = queries::get_user_token_families(state.db.write(), user_id).await?;
+    queries::revoke_all_user_refresh_tokens(state.db.write(), user_id).await?;
+    revoke_families(state, user_id, &families).await;
+    Ok(())
+}
+
+/// Revoke every session and personal API token of a user, for when their
+/// credentials may be compromised.
+pub async fn revoke_all_credentials(state: &AppState, user_id: Uuid) -> AppResult<()> {
+    revoke_all_sessions(state, user_id).await?;
+    queries::delete_user_api_tokens(state.db.write(), user_id).await?;
+    Ok(())
+}
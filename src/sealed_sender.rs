@@ -0,0 +1,117 @@
+//! Sealed-sender delivery certificates and message capabilities.
+//!
+//! A sender certificate is a short-lived statement, signed by the server,
+//! that binds a user and device to the device's identity key. Senders put it
+//! inside the ciphertext instead of identifying themselves in the stored
+//! message: recipients check the signature and expiry after decrypting, and
+//! the server keeps no sender for the message. To edit or delete a sealed
+//! message later, the sender presents a per-message capability token whose
+//! SHA-256 was stored alongside the message.
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::AppResult;
+use crate::jwt_keys::Keyring;
+
+/// Domain separator for sender certificates.
+const CERTIFICATE_CONTEXT: &[u8] = b"haven-sender-cert-v1";
+
+/// How long an issued sender certificate stays valid.
+pub const CERTIFICATE_TTL_HOURS: i64 = 24;
+
+/// Length of a capability hash (SHA-256).
+pub const CAPABILITY_HASH_LEN: usize = 32;
+
+/// The bytes a sender certificate signature covers:
+/// `"haven-sender-cert-v1" || user_id || device_id || expires_at_millis || identity_key`.
+pub fn certificate_message(
+    user_id: Uuid,
+    device_id: Uuid,
+    identity_key: &[u8],
+    expires_at: DateTime<Utc>,
+) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(CERTIFICATE_CONTEXT.len() + 40 + identity_key.len());
+    msg.extend_from_slice(CERTIFICATE_CONTEXT);
+    msg.extend_from_slice(user_id.as_bytes());
+    msg.extend_from_slice(device_id.as_bytes());
+    msg.extend_from_slice(&expires_at.timestamp_millis().to_be_bytes());
+    msg.extend_from_slice(identity_key);
+    msg
+}
+
+/// Sign a sender certificate with the current sender-certificate key
+/// (`jwt_keys::KeyPurpose::SenderCertificate`). Returns `(signature, public_key)`.
+pub fn sign_certificate(
+    keys: &Keyring,
+    user_id: Uuid,
+    device_id: Uuid,
+    identity_key: &[u8],
+    expires_at: DateTime<Utc>,
+) -> AppResult<(Vec<u8>, Vec<u8>)> {
+    keys.sign_message(&certificate_message(user_id, device_id, identity_key, expires_at))
+}
+
+/// The hash stored for a message's capability token. Edits and deletes look
+/// the message up by this hash, so the token itself never reaches the database.
+pub fn capability_hash(capability: &[u8]) -> [u8; CAPABILITY_HASH_LEN] {
+    Sha256::digest(capability).into()
+}
+
+/// Decode the base64 capability hash a sealed message is sent with.
+pub fn decode_capability_hash(value: &str) -> Option<Vec<u8>> {
+    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, value)
+        .ok()
+        .filter(|hash| hash.len() == CAPABILITY_HASH_LEN)
+}
+
+/// Decode a base64 capability token presented for an edit or delete and hash it.
+pub fn decode_capability(value: &str) -> Option<[u8; CAPABILITY_HASH_LEN]> {
+    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, value)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(|token| capability_hash(&token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    #[test]
+    fn certificate_signature_verifies() {
+        let keys = Keyring::ephemeral(crate::jwt_keys::SigningAlgorithm::EdDSA);
+        let (user, device) = (Uuid::new_v4(), Uuid::new_v4());
+        let expires = Utc::now();
+        let (signature, public_key) =
+            sign_certificate(&keys, user, device, b"identity", expires).unwrap();
+        let verifier = UnparsedPublicKey::new(&ED25519, &public_key);
+        assert!(verifier
+            .verify(&certificate_message(user, device, b"identity", expires), &signature)
+            .is_ok());
+        assert!(verifier
+            .verify(&certificate_message(user, device, b"other key", expires), &signature)
+            .is_err());
+        assert!(verifier
+            .verify(&certificate_message(device, user, b"identity", expires), &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn capability_hash_is_sha256_of_token() {
+        let hash = capability_hash(b"capability token");
+        assert_eq!(hash.as_slice(), Sha256::digest(b"capability token").as_slice());
+        assert_ne!(hash, capability_hash(b"another token"));
+    }
+
+    #[test]
+    fn capability_hash_must_be_32_bytes() {
+        let b64 = |bytes: &[u8]| base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes);
+        assert!(decode_capability_hash(&b64(&[7u8; 32])).is_some());
+        assert!(decode_capability_hash(&b64(&[7u8; 16])).is_none());
+        assert!(decode_capability_hash("not base64!").is_none());
+        assert_eq!(decode_capability(&b64(b"token")), Some(capability_hash(b"token")));
+        assert!(decode_capability("").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-user security event history.
+//!
+//! Every sign-in records its device name (parsed from the User-Agent) in
+//! `login_devices`. A sign-in from a device name the account has not used
+//! before — on an account that already has other devices — is recorded as a
+//! `new_device_login` event and pushed to the user's open sockets as
+//! `NewLoginDetected`. Devices linked by QR code, login lockouts, password
+//! changes and resets, newly verified email addresses, new API tokens,
+//! account deactivation, deletion requests and reactivation, and personal
+//! data exports are recorded too, so the user can review them from
+//! `/auth/security-events`.
+
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+use crate::api::auth_routes::{extract_ip_from_headers, mask_ip, parse_device_name};
+use crate::db::queries;
+use crate::errors::AppResult;
+use crate::models::{SecurityEvent, WsServerMessage};
+use crate::AppState;
+
+pub const NEW_DEVICE_LOGIN: &str = "new_device_login";
+pub const DEVICE_LINKED: &str = "device_linked";
+pub const ACCOUNT_LOCKED: &str = "account_locked";
+pub const PASSWORD_CHANGED: &str = "password_changed";
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const EMAIL_VERIFIED: &str = "email_verified";
+pub const API_TOKEN_CREATED: &str = "api_token_created";
+pub const ACCOUNT_DEACTIVATED: &str = "account_deactivated";
+pub const ACCOUNT_DELETION_SCHEDULED: &str = "account_deletion_scheduled";
+pub const ACCOUNT_REACTIVATED: &str = "account_reactivated";
+pub const DATA_EXPORT_REQUESTED: &str = "data_export_requested";
+
+/// Record an event for `user_id` with the device and IP of the request.
+pub async fn record(state: &AppState, user_id: Uuid, kind: &str, headers: &HeaderMap) -> AppResult<SecurityEvent> {
+    let device = device_name(headers);
+    let ip = extract_ip_from_headers(headers);
+    queries::insert_security_event(state.db.write(), user_id, kind, Some(&device), ip.as_deref()).await
+}
+
+/// Note a successful sign-in, and tell the user's other sessions when it
+/// came from a new device. The account's first device is not news.
+pub async fn note_login(state: &AppState, user_id: Uuid, headers: &HeaderMap) -> AppResult<()> {
+    let device = device_name(headers);
+    let known_before = queries::count_login_devices(state.db.read(), user_id).await?;
+    let is_new = queries::record_login_device(state.db.write(), user_id, &device).await?;
+    if !is_new || known_before == 0 {
+        return Ok(());
+    }
+
+    let event = record(state, user_id, NEW_DEVICE_LOGIN, headers).await?;
+    tracing::info!("User {} signed in from a new device ({})", user_id, device);
+
+    let msg = WsServerMessage::NewLoginDetected {
+        event_id: event.id,
+        device_name: event.device_name,
+        ip_address: event.ip_address.map(|ip| mask_ip(&ip)),
+        created_at: event.created_at,
+    };
+    crate::pubsub::send_to_user(state, user_id, &msg).await;
+    Ok(())
+}
+
+fn device_name(headers: &HeaderMap) -> String {
+    let ua = headers.get("user-agent").and_then(|v| v.to_str().ok()).unwrap_or("");
+    parse_device_name(ua)
+}
@@ -1,10 +1,14 @@
 use std::io;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 
 use aes_gcm::aead::{Aead, OsRng};
 use aes_gcm::{AeadCore, Aes256Gcm, Key, KeyInit, Nonce};
+use futures::Stream;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::config::AppConfig;
 
@@ -24,7 +28,7 @@ pub fn obfuscated_key(server_key: &[u8; 32], attachment_id: &str) -> String {
 
 // ─── Encryption helpers ──────────────────────────────────
 
-fn encrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
+pub(crate) fn encrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
     let key = Key::<Aes256Gcm>::from_slice(server_key);
     let cipher = Aes256Gcm::new(key);
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
@@ -39,7 +43,7 @@ fn encrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
     Ok(output)
 }
 
-fn decrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
+pub(crate) fn decrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
     if data.len() < 12 {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
@@ -57,6 +61,175 @@ fn decrypt_blob(data: &[u8], server_key: &[u8; 32]) -> io::Result<Vec<u8>> {
         .map_err(|e| io::Error::other(format!("Decryption failed: {}", e)))
 }
 
+// ─── Sealed streams ──────────────────────────────────────
+//
+// Blobs too large to hold in memory are sealed in chunks rather than as one
+// AES-GCM message. Each stream has a random salt and its own key derived
+// from the server key, and chunk i is sealed under the nonce (i, last-chunk
+// flag), so chunks can't be reordered, dropped or cut off at the end.
+// Layout: [32-byte salt || sealed chunks], every chunk but the last holding
+// exactly SEAL_CHUNK plaintext bytes.
+
+const SEAL_CHUNK: usize = 64 * 1024;
+const SEAL_TAG: usize = 16;
+const SEAL_SALT: usize = 32;
+
+fn stream_cipher(server_key: &[u8; 32], salt: &[u8]) -> Aes256Gcm {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(server_key)
+        .expect("HMAC key length is always valid");
+    mac.update(b"sealed-stream:");
+    mac.update(salt);
+    let key = mac.finalize().into_bytes();
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+}
+
+fn chunk_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+/// An `AsyncWrite` that seals everything written through it. Shutting it
+/// down seals the final chunk; a stream that was never shut down won't open.
+pub struct SealedWriter<W> {
+    inner: W,
+    cipher: Aes256Gcm,
+    index: u64,
+    plain: Vec<u8>,
+    sealed: Vec<u8>,
+    sealed_pos: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> SealedWriter<W> {
+    pub fn new(inner: W, server_key: &[u8; 32]) -> Self {
+        let salt: [u8; SEAL_SALT] = rand::random();
+        Self {
+            inner,
+            cipher: stream_cipher(server_key, &salt),
+            index: 0,
+            plain: Vec::with_capacity(SEAL_CHUNK),
+            sealed: salt.to_vec(),
+            sealed_pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Seal the buffered plaintext. Only called once `sealed` is written out.
+    fn seal(&mut self, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(self.index, last);
+        self.sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), self.plain.as_slice())
+            .map_err(|e| io::Error::other(format!("Encryption failed: {}", e)))?;
+        self.sealed_pos = 0;
+        self.plain.clear();
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Write out whatever sealed bytes are still pending.
+    fn poll_write_sealed(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.sealed_pos < self.sealed.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.sealed[self.sealed_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.sealed_pos += n;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for SealedWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_sealed(cx))?;
+        let n = buf.len().min(SEAL_CHUNK - this.plain.len());
+        this.plain.extend_from_slice(&buf[..n]);
+        if this.plain.len() == SEAL_CHUNK {
+            this.seal(false)?;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_sealed(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_sealed(cx))?;
+        if !this.finished {
+            // The final chunk is always short (possibly empty), which is
+            // how the reader tells it apart
+            this.seal(true)?;
+            this.finished = true;
+            ready!(this.poll_write_sealed(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Fill `buf` from `reader`, stopping early only at end of stream.
+async fn read_full<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Plaintext chunks of an opened sealed blob.
+pub type SealedStream = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>>;
+
+/// Open a stream written by `SealedWriter`, yielding its plaintext chunk by
+/// chunk. Tampering or truncation surfaces as an `InvalidData` error.
+pub fn open_sealed<R>(reader: R, server_key: &[u8; 32]) -> SealedStream
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let server_key = *server_key;
+    Box::pin(futures::stream::try_unfold(
+        (reader, None::<Aes256Gcm>, 0u64, false),
+        move |(mut reader, cipher, index, done)| async move {
+            if done {
+                return Ok(None);
+            }
+            let cipher = match cipher {
+                Some(cipher) => cipher,
+                None => {
+                    let mut salt = [0u8; SEAL_SALT];
+                    reader.read_exact(&mut salt).await?;
+                    stream_cipher(&server_key, &salt)
+                }
+            };
+            let mut chunk = vec![0u8; SEAL_CHUNK + SEAL_TAG];
+            let len = read_full(&mut reader, &mut chunk).await?;
+            let last = len < chunk.len();
+            let nonce = chunk_nonce(index, last);
+            let plain = cipher
+                .decrypt(Nonce::from_slice(&nonce), &chunk[..len])
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Sealed blob is corrupt or truncated"))?;
+            Ok(Some((plain, (reader, Some(cipher), index + 1, last))))
+        },
+    ))
+}
+
+/// A sealed blob being written: write it through `writer`, then hand it to
+/// `Storage::finish_sealed` (or `abort_sealed`).
+pub struct SealedUpload {
+    pub writer: SealedWriter<tokio::fs::File>,
+    storage_key: String,
+    path: PathBuf,
+}
+
 // ─── Storage Backend ──────────────────────────────────────
 
 /// Abstraction over local filesystem and S3 storage.
@@ -286,6 +459,82 @@ impl Storage {
         }
     }
 
+    /// Start writing a sealed blob. Local blobs are written next to their
+    /// final path and renamed into place; S3 uploads are staged in the
+    /// system temp directory.
+    pub async fn begin_sealed(&self, storage_key: &str) -> io::Result<SealedUpload> {
+        let path = match self {
+            Storage::Local { dir, .. } => {
+                let path = dir.join(format!("{}.partial", storage_key));
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                path
+            }
+            Storage::S3 { .. } => std::env::temp_dir().join(format!("haven-upload-{}", uuid::Uuid::new_v4())),
+        };
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(SealedUpload {
+            writer: SealedWriter::new(file, self.encryption_key()),
+            storage_key: storage_key.to_string(),
+            path,
+        })
+    }
+
+    /// Seal the last chunk of an upload and store it under its key.
+    pub async fn finish_sealed(&self, mut upload: SealedUpload) -> io::Result<()> {
+        let result = match upload.writer.shutdown().await {
+            Ok(()) => match self {
+                Storage::Local { dir, .. } => tokio::fs::rename(&upload.path, dir.join(&upload.storage_key)).await,
+                Storage::S3 { client, bucket, .. } => {
+                    match aws_sdk_s3::primitives::ByteStream::from_path(&upload.path).await {
+                        Ok(body) => client
+                            .put_object()
+                            .bucket(bucket)
+                            .key(&upload.storage_key)
+                            .body(body)
+                            .send()
+                            .await
+                            .map(|_| ())
+                            .map_err(|e| io::Error::other(format!("S3 put failed: {}", e))),
+                        Err(e) => Err(io::Error::other(format!("S3 upload staging failed: {}", e))),
+                    }
+                }
+            },
+            Err(e) => Err(e),
+        };
+        // Already moved into place when storing locally succeeded
+        let _ = tokio::fs::remove_file(&upload.path).await;
+        result
+    }
+
+    /// Throw away an unfinished upload.
+    pub async fn abort_sealed(&self, upload: SealedUpload) {
+        let SealedUpload { writer, path, .. } = upload;
+        drop(writer);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    /// Open a sealed blob as a stream of plaintext chunks.
+    pub async fn load_sealed(&self, storage_key: &str) -> io::Result<SealedStream> {
+        let reader: Pin<Box<dyn AsyncRead + Send>> = match self {
+            Storage::Local { dir, .. } => Box::pin(tokio::fs::File::open(dir.join(storage_key)).await?),
+            Storage::S3 { client, bucket, .. } => {
+                let output = client
+                    .get_object()
+                    .bucket(bucket)
+                    .key(storage_key)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        io::Error::other(format!("S3 get failed: {}", e))
+                    })?;
+                Box::pin(output.body.into_async_read())
+            }
+        };
+        Ok(open_sealed(reader, self.encryption_key()))
+    }
+
     /// Load and decrypt data.
     pub async fn load_blob(&self, storage_key: &str) -> io::Result<Vec<u8>> {
         let encrypted = match self {
@@ -514,6 +763,62 @@ mod tests {
         assert_eq!(loaded, data.to_vec());
     }
 
+    // ─── Sealed streams ──────────────────────────────────
+
+    async fn seal(data: &[u8], key: &[u8; 32]) -> Vec<u8> {
+        let mut writer = SealedWriter::new(Vec::new(), key);
+        writer.write_all(data).await.unwrap();
+        writer.shutdown().await.unwrap();
+        writer.inner
+    }
+
+    async fn open(sealed: Vec<u8>, key: &[u8; 32]) -> io::Result<Vec<u8>> {
+        use futures::TryStreamExt;
+        let chunks: Vec<Vec<u8>> = open_sealed(io::Cursor::new(sealed), key).try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    #[tokio::test]
+    async fn sealed_stream_roundtrip() {
+        let key = [9u8; 32];
+        for len in [0, 1, SEAL_CHUNK - 1, SEAL_CHUNK, 2 * SEAL_CHUNK + 7] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let sealed = seal(&data, &key).await;
+            assert_eq!(open(sealed, &key).await.unwrap(), data, "length {}", len);
+        }
+    }
+
+    #[tokio::test]
+    async fn sealed_stream_rejects_truncation_and_wrong_key() {
+        let key = [9u8; 32];
+        let data = vec![5u8; 2 * SEAL_CHUNK];
+        let sealed = seal(&data, &key).await;
+
+        // Cut at a chunk boundary: every remaining chunk still authenticates
+        let cut = SEAL_SALT + 2 * (SEAL_CHUNK + SEAL_TAG);
+        assert!(open(sealed[..cut].to_vec(), &key).await.is_err());
+        assert!(open(sealed.clone(), &[8u8; 32]).await.is_err());
+        assert!(open(sealed, &key).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn storage_local_sealed_roundtrip() {
+        use futures::TryStreamExt;
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Storage::Local {
+            dir: dir.path().to_path_buf(),
+            encryption_key: [0u8; 32],
+        };
+
+        let mut upload = storage.begin_sealed("ab/export").await.unwrap();
+        upload.writer.write_all(b"streamed archive").await.unwrap();
+        storage.finish_sealed(upload).await.unwrap();
+        assert!(!dir.path().join("ab/export.partial").exists());
+
+        let chunks: Vec<Vec<u8>> = storage.load_sealed("ab/export").await.unwrap().try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"streamed archive");
+    }
+
     #[tokio::test]
     async fn storage_local_presign_returns_none() {
         let dir = tempfile::tempdir().unwrap();
@@ -0,0 +1,312 @@
+//! Key transparency log for identity key publications.
+//!
+//! Every identity key a device publishes is appended to a single global log.
+//! Each entry commits to the same user's previous entry (a per-user hash
+//! chain), and the entries' leaf hashes form an RFC 6962-style Merkle tree
+//! whose root the server signs as the tree head, with the tree-head keyring
+//! (`jwt_keys::KeyPurpose::TreeHead`). Clients check that the keys
+//! they are served appear in the signed tree via inclusion proofs; auditors
+//! replay the whole log from the admin export and compare tree heads.
+
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::AppResult;
+use crate::jwt_keys::Keyring;
+
+pub type Hash = [u8; 32];
+
+/// Domain separator for signed tree heads.
+const TREE_HEAD_CONTEXT: &[u8] = b"haven-kt-tree-head-v1";
+
+/// Leaf hash of a log entry:
+/// `SHA-256(0x00 || seq || user_id || device_id || published_at_micros || prev_hash || identity_key)`
+/// with integers big-endian and `prev_hash` all zeroes for a user's first entry.
+pub fn leaf_hash(
+    seq: i64,
+    user_id: Uuid,
+    device_id: Uuid,
+    published_at: DateTime<Utc>,
+    prev_hash: Option<&[u8]>,
+    identity_key: &[u8],
+) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(seq.to_be_bytes());
+    hasher.update(user_id.as_bytes());
+    hasher.update(device_id.as_bytes());
+    hasher.update(published_at.timestamp_micros().to_be_bytes());
+    hasher.update(prev_hash.unwrap_or(&[0u8; 32]));
+    hasher.update(identity_key);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of two strictly smaller than `n` (n > 1).
+fn split_point(n: u64) -> u64 {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// A complete subtree of the log: the `2^level` leaves starting at leaf
+/// `index << level`. Level 0 nodes are the leaves. A node never changes once
+/// the log has grown past it, so nodes are stored as entries are appended
+/// and tree heads and audit paths are assembled from O(log n) of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    pub level: u32,
+    pub index: u64,
+}
+
+/// The nodes whose hashes `completed_nodes` needs to append leaf `seq`:
+/// the left siblings of the subtrees that leaf completes, lowest first.
+pub fn left_siblings(seq: u64) -> Vec<NodeId> {
+    let mut siblings = Vec::new();
+    let (mut level, mut index) = (0, seq);
+    while index & 1 == 1 {
+        siblings.push(NodeId { level, index: index - 1 });
+        level += 1;
+        index >>= 1;
+    }
+    siblings
+}
+
+/// The nodes to store when appending leaf `seq`: the leaf itself and every
+/// subtree it completes. `siblings` are the hashes of `left_siblings(seq)`.
+pub fn completed_nodes(seq: u64, leaf: Hash, siblings: &[Hash]) -> Vec<(NodeId, Hash)> {
+    let mut nodes = vec![(NodeId { level: 0, index: seq }, leaf)];
+    let mut hash = leaf;
+    for (sibling_id, sibling) in left_siblings(seq).into_iter().zip(siblings) {
+        hash = node_hash(sibling, &hash);
+        nodes.push((NodeId { level: sibling_id.level + 1, index: sibling_id.index >> 1 }, hash));
+    }
+    nodes
+}
+
+/// Complete subtrees covering leaves `start..end`, left to right. Every range
+/// RFC 6962's recursive split produces starts on a boundary of its largest
+/// subtree, so this matches the split.
+fn covering_nodes(mut start: u64, end: u64) -> Vec<NodeId> {
+    let mut nodes = Vec::new();
+    while start < end {
+        let mut level = 0;
+        while start.is_multiple_of(2 << level) && start + (2 << level) <= end {
+            level += 1;
+        }
+        nodes.push(NodeId { level, index: start >> level });
+        start += 1 << level;
+    }
+    nodes
+}
+
+/// Hash of a range from the hashes of its `covering_nodes`, in order
+/// (RFC 6962 §2.1, with the left subtrees already hashed).
+pub fn fold_nodes(hashes: &[Hash]) -> Hash {
+    match hashes.split_last() {
+        None => Sha256::digest([]).into(),
+        Some((last, rest)) => rest.iter().rev().fold(*last, |acc, hash| node_hash(hash, &acc)),
+    }
+}
+
+/// The nodes whose `fold_nodes` is the root of a tree of `tree_size` leaves.
+pub fn root_nodes(tree_size: u64) -> Vec<NodeId> {
+    covering_nodes(0, tree_size)
+}
+
+/// Audit path for leaf `index` in a tree of `tree_size` leaves (RFC 6962
+/// §2.1.1), ordered from the leaf up. Each step is given as the nodes whose
+/// `fold_nodes` is that sibling's hash.
+pub fn audit_path_nodes(index: u64, tree_size: u64) -> Vec<Vec<NodeId>> {
+    let mut path = Vec::new();
+    if index >= tree_size {
+        return path;
+    }
+    let (mut start, mut end) = (0, tree_size);
+    while end - start > 1 {
+        let k = split_point(end - start);
+        if index < start + k {
+            path.push(covering_nodes(start + k, end));
+            end = start + k;
+        } else {
+            path.push(covering_nodes(start, start + k));
+            start += k;
+        }
+    }
+    path.reverse();
+    path
+}
+
+/// Check an audit path against a tree root (RFC 9162 §2.1.3.2). This is the
+/// check clients run; it lives here so the server's proofs are tested with it.
+pub fn verify_inclusion(
+    leaf: &Hash,
+    index: u64,
+    tree_size: u64,
+    proof: &[Hash],
+    root: &Hash,
+) -> bool {
+    if index >= tree_size {
+        return false;
+    }
+    let (mut fnode, mut snode) = (index, tree_size - 1);
+    let mut hash = *leaf;
+    for sibling in proof {
+        if snode == 0 {
+            return false;
+        }
+        if fnode & 1 == 1 || fnode == snode {
+            hash = node_hash(sibling, &hash);
+            while fnode & 1 == 0 && fnode != 0 {
+                fnode >>= 1;
+                snode >>= 1;
+            }
+        } else {
+            hash = node_hash(&hash, sibling);
+        }
+        fnode >>= 1;
+        snode >>= 1;
+    }
+    snode == 0 && hash == *root
+}
+
+/// The bytes a tree head signature covers:
+/// `"haven-kt-tree-head-v1" || tree_size || timestamp_millis || root_hash`.
+pub fn tree_head_message(tree_size: u64, timestamp: DateTime<Utc>, root: &Hash) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(TREE_HEAD_CONTEXT.len() + 16 + 32);
+    msg.extend_from_slice(TREE_HEAD_CONTEXT);
+    msg.extend_from_slice(&tree_size.to_be_bytes());
+    msg.extend_from_slice(&timestamp.timestamp_millis().to_be_bytes());
+    msg.extend_from_slice(root);
+    msg
+}
+
+/// Sign a tree head with the current tree-head key. Returns
+/// `(signature, public_key)`.
+pub fn sign_tree_head(
+    keys: &Keyring,
+    tree_size: u64,
+    timestamp: DateTime<Utc>,
+    root: &Hash,
+) -> AppResult<(Vec<u8>, Vec<u8>)> {
+    keys.sign_message(&tree_head_message(tree_size, timestamp, root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+    use std::collections::HashMap;
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n)
+            .map(|i| leaf_hash(i as i64, Uuid::nil(), Uuid::nil(), DateTime::UNIX_EPOCH, None, &[i as u8]))
+            .collect()
+    }
+
+    /// RFC 6962 tree hash straight from the leaves, to check the stored nodes against.
+    fn root_hash(leaves: &[Hash]) -> Hash {
+        match leaves.len() {
+            0 => Sha256::digest([]).into(),
+            1 => leaves[0],
+            n => {
+                let k = split_point(n as u64) as usize;
+                node_hash(&root_hash(&leaves[..k]), &root_hash(&leaves[k..]))
+            }
+        }
+    }
+
+    /// Append `leaves` the way the log does, keeping every stored node.
+    fn store(leaves: &[Hash]) -> HashMap<NodeId, Hash> {
+        let mut nodes = HashMap::new();
+        for (seq, leaf) in leaves.iter().enumerate() {
+            let siblings: Vec<Hash> = left_siblings(seq as u64).iter().map(|id| nodes[id]).collect();
+            nodes.extend(completed_nodes(seq as u64, *leaf, &siblings));
+        }
+        nodes
+    }
+
+    fn fold(nodes: &HashMap<NodeId, Hash>, ids: &[NodeId]) -> Hash {
+        fold_nodes(&ids.iter().map(|id| nodes[id]).collect::<Vec<_>>())
+    }
+
+    fn inclusion_proof(nodes: &HashMap<NodeId, Hash>, index: usize, tree_size: usize) -> Vec<Hash> {
+        audit_path_nodes(index as u64, tree_size as u64)
+            .iter()
+            .map(|step| fold(nodes, step))
+            .collect()
+    }
+
+    #[test]
+    fn root_of_two_leaves_is_their_node_hash() {
+        let l = leaves(2);
+        assert_eq!(root_hash(&l), node_hash(&l[0], &l[1]));
+    }
+
+    #[test]
+    fn root_of_three_leaves_splits_at_two() {
+        let l = leaves(3);
+        assert_eq!(root_hash(&l), node_hash(&node_hash(&l[0], &l[1]), &l[2]));
+    }
+
+    #[test]
+    fn stored_nodes_give_the_tree_root_at_every_size() {
+        let l = leaves(33);
+        let nodes = store(&l);
+        for n in 0..=l.len() {
+            assert_eq!(fold(&nodes, &root_nodes(n as u64)), root_hash(&l[..n]), "n={}", n);
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_verify_for_every_leaf() {
+        let all = leaves(17);
+        let nodes = store(&all);
+        for n in 1..=all.len() {
+            let l = &all[..n];
+            let root = root_hash(l);
+            for (i, leaf) in l.iter().enumerate() {
+                let proof = inclusion_proof(&nodes, i, n);
+                assert!(verify_inclusion(leaf, i as u64, n as u64, &proof, &root), "n={} i={}", n, i);
+            }
+        }
+    }
+
+    #[test]
+    fn inclusion_proof_rejects_wrong_leaf() {
+        let l = leaves(5);
+        let root = root_hash(&l);
+        let proof = inclusion_proof(&store(&l), 2, 5);
+        assert!(!verify_inclusion(&l[3], 2, 5, &proof, &root));
+        assert!(!verify_inclusion(&l[2], 3, 5, &proof, &root));
+        assert!(!verify_inclusion(&l[2], 5, 5, &proof, &root));
+    }
+
+    #[test]
+    fn leaf_hash_commits_to_previous_entry() {
+        let first = leaf_hash(0, Uuid::nil(), Uuid::nil(), DateTime::UNIX_EPOCH, None, b"key");
+        let chained = leaf_hash(0, Uuid::nil(), Uuid::nil(), DateTime::UNIX_EPOCH, Some(&first), b"key");
+        assert_ne!(first, chained);
+    }
+
+    #[test]
+    fn tree_head_signature_verifies() {
+        let keys = Keyring::ephemeral(crate::jwt_keys::SigningAlgorithm::EdDSA);
+        let root = root_hash(&leaves(4));
+        let now = Utc::now();
+        let (signature, public_key) = sign_tree_head(&keys, 4, now, &root).unwrap();
+        let verifier = UnparsedPublicKey::new(&ED25519, &public_key);
+        assert!(verifier.verify(&tree_head_message(4, now, &root), &signature).is_ok());
+        assert!(verifier.verify(&tree_head_message(5, now, &root), &signature).is_err());
+    }
+}
@@ -1,27 +1,42 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
         Query, State,
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use futures::{SinkExt, StreamExt};
-use serde::Deserialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use tokio::sync::{broadcast, mpsc};
 use tokio::task::JoinHandle;
 use uuid::Uuid;
 
-use crate::auth::{validate_access_token, user_id_from_claims};
+use crate::api::auth_routes::extract_ip_from_headers;
+use crate::auth::{generate_ws_ticket, validate_access_token, user_id_from_claims};
 use crate::db::queries;
-use crate::errors::AppError;
-use crate::memory_store::{ActiveCall, ConnectedCall};
-use crate::models::{MessageResponse, WsClientMessage, WsServerMessage};
+use crate::errors::{AppError, AppResult};
+use crate::member_list;
+use crate::memory_store::{ActiveCall, ConnectedCall, WsTicket};
+use crate::middleware::AuthSession;
+use crate::models::{
+    HeartbeatInfo, MessageResponse, WsClientMessage, WsServerMessage, WsTicketResponse,
+};
+use crate::presence::{self, PresenceState};
 use crate::pubsub;
+use crate::revocation;
+use crate::sealed_sender;
 use crate::AppState;
 
 /// Tracks all connected clients. Maps user_id -> list of sender channels.
@@ -40,27 +55,200 @@ pub struct WsSession {
     pub created_at: Instant,
     pub last_active: tokio::sync::Mutex<Instant>,
     pub subscribed_channels: tokio::sync::Mutex<HashSet<Uuid>>,
+    /// Last heartbeat reported the user as idle (always set once disconnected)
+    pub idle: AtomicBool,
+}
+
+impl WsSession {
+    /// Append an event to the resume buffer, dropping the oldest when full.
+    async fn buffer_event(&self, msg: WsServerMessage) {
+        let mut buf = self.event_buffer.lock().await;
+        if buf.len() >= self.buffer_capacity {
+            buf.pop_front();
+        }
+        buf.push_back(msg);
+    }
 }
 
 /// Maps session_id -> Session for resume support.
 pub type SessionMap = Arc<DashMap<Uuid, Arc<WsSession>>>;
 
-/// Query params for WebSocket upgrade — token passed as query param
-/// since WebSocket doesn't support custom headers in browsers.
+/// Shutdown drain state. Once draining, new upgrades are refused and the
+/// live socket count tells `drain` when every client has moved elsewhere.
+#[derive(Clone, Default)]
+pub struct WsDrain {
+    draining: Arc<AtomicBool>,
+    live: Arc<AtomicUsize>,
+}
+
+impl WsDrain {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Number of WebSocket connections currently open on this instance.
+    pub fn live_connections(&self) -> usize {
+        self.live.load(Ordering::SeqCst)
+    }
+
+    fn track(&self) -> LiveSocket {
+        self.live.fetch_add(1, Ordering::SeqCst);
+        LiveSocket(self.live.clone())
+    }
+}
+
+/// Decrements the live socket count when a connection handler exits.
+struct LiveSocket(Arc<AtomicUsize>);
+
+impl Drop for LiveSocket {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Session state handed off through Redis so another instance can resume it.
+#[derive(Serialize, Deserialize)]
+struct PersistedSession {
+    user_id: Uuid,
+    events: Vec<WsServerMessage>,
+}
+
+/// Query params for WebSocket upgrade. Browsers can't set headers on a
+/// WebSocket handshake, so clients first exchange their access token for a
+/// short-lived ticket via `POST /api/v1/ws/ticket` and pass that instead.
+/// The raw `token` param is deprecated and only accepted when `ws_allow_query_token` is on.
 #[derive(Debug, Deserialize)]
 pub struct WsAuthQuery {
-    pub token: String,
+    pub ticket: Option<String>,
+    pub token: Option<String>,
+}
+
+/// How long a WS connect ticket stays valid (seconds).
+const WS_TICKET_TTL_SECS: u64 = 30;
+
+/// Close code sent when the socket's login session is revoked.
+const WS_CLOSE_SESSION_REVOKED: u16 = 4001;
+
+/// Fingerprint of the client a ticket was issued to (IP + User-Agent),
+/// so a leaked ticket can't be redeemed from a different client.
+fn ticket_binding(headers: &HeaderMap) -> String {
+    let ip = extract_ip_from_headers(headers).unwrap_or_default();
+    let ua = headers
+        .get("user-agent")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let mut hasher = Sha256::new();
+    hasher.update(ip.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(ua.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// POST /api/v1/ws/ticket — mint a single-use ticket for the WebSocket upgrade.
+pub async fn ws_ticket(
+    State(state): State<AppState>,
+    AuthSession { user_id, family_id }: AuthSession,
+    headers: HeaderMap,
+) -> AppResult<Json<WsTicketResponse>> {
+    let ticket = generate_ws_ticket();
+    let binding = ticket_binding(&headers);
+
+    if let Some(mut redis) = state.redis.clone() {
+        let family = family_id.map(|f| f.to_string()).unwrap_or_default();
+        redis::cmd("SET")
+            .arg(format!("haven:ws_ticket:{}", ticket))
+            .arg(format!("{}:{}:{}", user_id, family, binding))
+            .arg("EX")
+            .arg(WS_TICKET_TTL_SECS)
+            .query_async::<_, ()>(&mut redis)
+            .await?;
+    } else {
+        state.memory.ws_tickets.insert(
+            ticket.clone(),
+            WsTicket {
+                user_id,
+                family_id,
+                binding,
+                expires_at: Instant::now() + Duration::from_secs(WS_TICKET_TTL_SECS),
+            },
+        );
+    }
+
+    Ok(Json(WsTicketResponse {
+        ticket,
+        expires_in: WS_TICKET_TTL_SECS,
+    }))
+}
+
+/// Consume a WS ticket. Returns the owning user and session if the ticket
+/// exists, hasn't expired, and was issued to the same client that is now
+/// redeeming it.
+async fn redeem_ws_ticket(
+    state: &AppState,
+    ticket: &str,
+    headers: &HeaderMap,
+) -> AppResult<(Uuid, Option<Uuid>)> {
+    let binding = ticket_binding(headers);
+
+    let owner = if let Some(mut redis) = state.redis.clone() {
+        let value: Option<String> = redis::cmd("GETDEL")
+            .arg(format!("haven:ws_ticket:{}", ticket))
+            .query_async(&mut redis)
+            .await?;
+        value.and_then(|v| {
+            let mut parts = v.splitn(3, ':');
+            let (uid, family, bound) = (parts.next()?, parts.next()?, parts.next()?);
+            if bound != binding {
+                return None;
+            }
+            let family_id = match family {
+                "" => None,
+                f => Some(f.parse::<Uuid>().ok()?),
+            };
+            Some((uid.parse::<Uuid>().ok()?, family_id))
+        })
+    } else {
+        state
+            .memory
+            .ws_tickets
+            .remove(ticket)
+            .filter(|(_, t)| t.expires_at > Instant::now() && t.binding == binding)
+            .map(|(_, t)| (t.user_id, t.family_id))
+    };
+
+    owner.ok_or(AppError::InvalidToken)
 }
 
 /// WebSocket upgrade handler.
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     Query(auth): Query<WsAuthQuery>,
+    headers: HeaderMap,
     State(state): State<AppState>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
+    // Refuse new connections while draining so clients land on another instance
+    if state.ws_drain.is_draining() {
+        let status = StatusCode::SERVICE_UNAVAILABLE;
+        let body = Json(json!({ "error": "Server is shutting down", "status": status.as_u16() }));
+        return Ok((status, body).into_response());
+    }
+
     // Authenticate before upgrading
-    let claims = validate_access_token(&auth.token, &state.config)?;
-    let user_id = user_id_from_claims(&claims)?;
+    let (user_id, family_id) = match (auth.ticket, auth.token) {
+        (Some(ticket), _) => redeem_ws_ticket(&state, &ticket, &headers).await?,
+        (None, Some(token)) if state.config.ws_allow_query_token => {
+            crate::jwt_keys::load_for_token(&state.db, &state.storage_key, &state.jwt_keys, &token).await;
+            let claims = validate_access_token(&token, &state.jwt_keys)?;
+            (user_id_from_claims(&claims)?, claims.family_id)
+        }
+        _ => return Err(AppError::AuthError("Missing WebSocket ticket".into())),
+    };
+    // The session may have been revoked since the ticket was minted
+    if let Some(family_id) = family_id {
+        if revocation::is_family_revoked(&state, family_id).await {
+            return Err(AppError::AuthError("Session has been revoked".into()));
+        }
+    }
 
     // Check connection limit
     let conn_count = state
@@ -76,11 +264,15 @@ pub async fn ws_handler(
         )));
     }
 
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, user_id, state)))
+    Ok(ws
+        .on_upgrade(move |socket| handle_socket(socket, user_id, family_id, state))
+        .into_response())
 }
 
-/// Handles an individual WebSocket connection.
-async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
+/// Handles an individual WebSocket connection. `family_id` is the login
+/// session it was opened under; revoking that session closes the socket.
+async fn handle_socket(socket: WebSocket, user_id: Uuid, family_id: Option<Uuid>, state: AppState) {
+    let _live = state.ws_drain.track();
     let (mut ws_sink, mut ws_stream) = socket.split();
 
     // Create a channel for sending messages to this specific connection
@@ -101,6 +293,7 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
         created_at: Instant::now(),
         last_active: tokio::sync::Mutex::new(Instant::now()),
         subscribed_channels: tokio::sync::Mutex::new(HashSet::new()),
+        idle: AtomicBool::new(false),
     });
     state.sessions.insert(session_id, session.clone());
 
@@ -127,17 +320,35 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
     broadcast_presence(user_id, "online", &state).await;
 
     // Task: forward messages from our channel to the WebSocket sink,
-    // and buffer events in the session for resume support.
+    // and buffer events in the session for resume support. Hands the
+    // receiver back when stopped so a draining instance can keep buffering;
+    // a revoked session gets nothing back.
     let session_for_send = session.clone();
-    let send_task = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
+    let (stop_send, mut stop_send_rx) = tokio::sync::oneshot::channel::<()>();
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            let msg = tokio::select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+                _ = &mut stop_send_rx => break,
+            };
+
+            // Revocations reach every connection of the user; only act on our own
+            let revoked = match &msg {
+                WsServerMessage::SessionRevoked { family_id: revoked } => {
+                    if family_id != Some(*revoked) {
+                        continue;
+                    }
+                    true
+                }
+                _ => false,
+            };
+
             // Buffer the event for resume (skip Hello/Resumed/InvalidSession/Pong)
             if should_buffer_event(&msg) {
-                let mut buf = session_for_send.event_buffer.lock().await;
-                if buf.len() >= session_for_send.buffer_capacity {
-                    buf.pop_front();
-                }
-                buf.push_back(msg.clone());
+                session_for_send.buffer_event(msg.clone()).await;
             }
 
             let text = match serde_json::to_string(&msg) {
@@ -150,7 +361,17 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
             if ws_sink.send(Message::Text(text)).await.is_err() {
                 break;
             }
+            if revoked {
+                let _ = ws_sink
+                    .send(Message::Close(Some(CloseFrame {
+                        code: WS_CLOSE_SESSION_REVOKED,
+                        reason: "Session revoked".into(),
+                    })))
+                    .await;
+                return None;
+            }
         }
+        Some(rx)
     });
 
     // Task: read messages from the WebSocket and process them, with heartbeat timeout.
@@ -159,7 +380,7 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
     let tx_clone = tx.clone();
     let subs_clone = subscriptions.clone();
     let session_for_recv = session.clone();
-    let recv_task = tokio::spawn(async move {
+    let mut recv_task = tokio::spawn(async move {
         loop {
             match tokio::time::timeout(heartbeat_timeout, ws_stream.next()).await {
                 Ok(Some(Ok(msg))) => {
@@ -167,7 +388,7 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
                     *session_for_recv.last_active.lock().await = Instant::now();
                     match msg {
                         Message::Text(text) => {
-                            handle_client_message(&text, user_id, &state_clone, &tx_clone, &subs_clone).await;
+                            handle_client_message(&text, user_id, session_id, &state_clone, &tx_clone, &subs_clone).await;
                         }
                         Message::Close(_) => break,
                         Message::Ping(_) => {} // axum auto-responds with pong
@@ -185,11 +406,27 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
         }
     });
 
-    // Wait for either task to finish (connection closed)
-    tokio::select! {
-        _ = send_task => {},
-        _ = recv_task => {},
-    }
+    // Wait for either task to finish (connection closed), then stop the other.
+    // Awaiting the stopped task makes sure the receiver is back in hand before
+    // the connection map is checked for live senders below.
+    let send_finished = tokio::select! {
+        rx = &mut send_task => Some(rx),
+        _ = &mut recv_task => None,
+    };
+    let rx = match send_finished {
+        Some(rx) => {
+            recv_task.abort();
+            let _ = recv_task.await;
+            rx.ok().flatten()
+        }
+        None => {
+            let _ = stop_send.send(());
+            send_task.await.ok().flatten()
+        }
+    };
+
+    // Member list subscriptions are per-connection; clients re-subscribe after resume
+    member_list::unsubscribe_session(&state, session_id).await;
 
     // Snapshot subscribed channels into the session for resume
     {
@@ -198,6 +435,20 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
         *session_subs = subs.keys().copied().collect();
     }
 
+    // This instance is going away — hand the session off so the client can
+    // resume it on whichever instance it reconnects to, and keep its channel
+    // subscriptions feeding the handed-off buffer until the process exits so
+    // events sent during the reconnect gap are not lost.
+    let draining = state.ws_drain.is_draining();
+    if let Some(rx) = rx {
+        if draining && persist_session(&state, &session, false).await {
+            state.sessions.remove(&session_id);
+            tokio::spawn(buffer_until_exit(state.clone(), session.clone(), rx));
+            tracing::info!("WebSocket handed off: user={}, session={}", user_id, session_id);
+            return;
+        }
+    }
+
     // Cleanup: abort all subscription tasks and prune empty broadcasts
     let subscribed_channels: Vec<Uuid> = {
         let mut subs = subscriptions.lock().await;
@@ -227,19 +478,132 @@ async fn handle_socket(socket: WebSocket, user_id: Uuid, state: AppState) {
         is_last
     };
 
+    // A closed session no longer counts as activity for idle detection
+    session.idle.store(true, Ordering::SeqCst);
+
     if was_last_connection {
-        broadcast_presence(user_id, "offline", &state).await;
-        // Clean up voice state — remove from any voice channel
-        crate::api::voice::cleanup_voice_state(&state, user_id).await;
-        // Clean up any active calls this user initiated
-        cleanup_call_state(&state, user_id).await;
-        // Unsubscribe from Redis user channel
-        pubsub::unsubscribe_redis_user(&state, user_id).await;
+        // Clients of a draining instance are reconnecting elsewhere, not leaving
+        if !draining {
+            broadcast_presence(user_id, "offline", &state).await;
+            // Clean up voice state — remove from any voice channel
+            crate::api::voice::cleanup_voice_state(&state, user_id).await;
+            // Clean up any active calls this user initiated
+            cleanup_call_state(&state, user_id).await;
+            // Unsubscribe from Redis user channel
+            pubsub::unsubscribe_redis_user(&state, user_id).await;
+        }
+    } else {
+        refresh_idle(user_id, &state).await;
     }
 
     tracing::info!("WebSocket disconnected: user={}, session={}", user_id, session_id);
 }
 
+/// Drain this instance's WebSocket connections ahead of shutdown: refuse new
+/// upgrades, tell every client to reconnect after a jittered delay, wait for
+/// them to leave (up to `ws_drain_timeout_secs`), then persist whatever
+/// sessions remain so they can be resumed elsewhere.
+pub async fn drain(state: &AppState) {
+    state.ws_drain.draining.store(true, Ordering::SeqCst);
+
+    let jitter_ms = state.config.ws_reconnect_jitter_ms;
+    let mut notified = 0usize;
+    {
+        let mut rng = rand::thread_rng();
+        for entry in state.connections.iter() {
+            for tx in entry.value() {
+                let delay_ms = rng.gen_range(0..=jitter_ms);
+                if tx.send(WsServerMessage::Reconnect { delay_ms }).is_ok() {
+                    notified += 1;
+                }
+            }
+        }
+    }
+    tracing::info!("Draining {} WebSocket connections", notified);
+
+    let deadline = Instant::now() + Duration::from_secs(state.config.ws_drain_timeout_secs);
+    while state.ws_drain.live_connections() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let remaining = state.ws_drain.live_connections();
+    if remaining > 0 {
+        tracing::warn!("WebSocket drain deadline reached with {} connections open", remaining);
+    }
+
+    let sessions: Vec<Arc<WsSession>> = state.sessions.iter().map(|e| e.value().clone()).collect();
+    let mut persisted = 0u32;
+    for session in sessions {
+        if persist_session(state, &session, false).await {
+            persisted += 1;
+        }
+    }
+    tracing::info!("WebSocket drain complete, persisted {} sessions", persisted);
+}
+
+/// Keep buffering a handed-off session's events after its client has left,
+/// re-persisting on each one, until the process exits or another instance
+/// has resumed the session.
+async fn buffer_until_exit(
+    state: AppState,
+    session: Arc<WsSession>,
+    mut rx: mpsc::UnboundedReceiver<WsServerMessage>,
+) {
+    while let Some(msg) = rx.recv().await {
+        if !should_buffer_event(&msg) {
+            continue;
+        }
+        session.buffer_event(msg).await;
+        if !persist_session(&state, &session, true).await {
+            break;
+        }
+    }
+}
+
+/// Write a session's buffered events to Redis for cross-instance resume.
+/// With `only_existing`, a session already taken by another instance is not
+/// written back. Returns false when there is no Redis or nothing was written.
+async fn persist_session(state: &AppState, session: &WsSession, only_existing: bool) -> bool {
+    let Some(mut redis) = state.redis.clone() else {
+        return false;
+    };
+    let persisted = PersistedSession {
+        user_id: session.user_id,
+        events: session.event_buffer.lock().await.iter().cloned().collect(),
+    };
+    let Ok(json) = serde_json::to_string(&persisted) else {
+        return false;
+    };
+    let mut cmd = redis::cmd("SET");
+    cmd.arg(format!("haven:ws:session:{}", session.session_id))
+        .arg(json)
+        .arg("EX")
+        .arg(state.config.ws_session_ttl_secs);
+    if only_existing {
+        cmd.arg("XX");
+    }
+    // SET replies nil when XX finds no key
+    let result: Result<Option<String>, redis::RedisError> = cmd.query_async(&mut redis).await;
+    match result {
+        Ok(reply) => reply.is_some(),
+        Err(e) => {
+            tracing::warn!("Failed to persist WS session {}: {}", session.session_id, e);
+            false
+        }
+    }
+}
+
+/// Take a session persisted by a draining instance (single use).
+async fn take_persisted_session(state: &AppState, session_id: Uuid) -> Option<PersistedSession> {
+    let mut redis = state.redis.clone()?;
+    let value: Option<String> = redis::cmd("GETDEL")
+        .arg(format!("haven:ws:session:{}", session_id))
+        .query_async(&mut redis)
+        .await
+        .ok()?;
+    serde_json::from_str(&value?).ok()
+}
+
 /// Returns true if this event type should be buffered for resume support.
 /// Transient control messages (Hello, Pong, Resumed, InvalidSession) are not buffered.
 fn should_buffer_event(msg: &WsServerMessage) -> bool {
@@ -249,6 +613,8 @@ fn should_buffer_event(msg: &WsServerMessage) -> bool {
             | WsServerMessage::Pong
             | WsServerMessage::Resumed { .. }
             | WsServerMessage::InvalidSession
+            | WsServerMessage::SessionRevoked { .. }
+            | WsServerMessage::Reconnect { .. }
             | WsServerMessage::Subscribed { .. }
             | WsServerMessage::Error { .. }
             | WsServerMessage::CallRinging { .. }
@@ -259,6 +625,7 @@ fn should_buffer_event(msg: &WsServerMessage) -> bool {
 async fn handle_client_message(
     text: &str,
     user_id: Uuid,
+    session_id: Uuid,
     state: &AppState,
     reply_tx: &mpsc::UnboundedSender<WsServerMessage>,
     subscriptions: &Arc<tokio::sync::Mutex<HashMap<Uuid, JoinHandle<()>>>>,
@@ -281,6 +648,7 @@ async fn handle_client_message(
             expires_at,
             attachment_ids,
             reply_to_id,
+            capability_hash,
         } => {
             // Per-user rate limit on message sending
             if !state.ws_rate_limiter.check(user_id) {
@@ -297,6 +665,7 @@ async fn handle_client_message(
                 expires_at,
                 attachment_ids,
                 reply_to_id,
+                capability_hash.as_deref(),
                 state,
                 reply_tx,
             )
@@ -306,11 +675,13 @@ async fn handle_client_message(
         WsClientMessage::EditMessage {
             message_id,
             encrypted_body,
+            capability,
         } => {
             handle_edit_message(
                 user_id,
                 message_id,
                 &encrypted_body,
+                capability.as_deref(),
                 state,
                 reply_tx,
             )
@@ -325,8 +696,8 @@ async fn handle_client_message(
             handle_unsubscribe(channel_id, subscriptions).await;
         }
 
-        WsClientMessage::DeleteMessage { message_id } => {
-            handle_delete_message(user_id, message_id, state, reply_tx).await;
+        WsClientMessage::DeleteMessage { message_id, capability } => {
+            handle_delete_message(user_id, message_id, capability.as_deref(), state, reply_tx).await;
         }
 
         WsClientMessage::AddReaction { message_id, emoji } => {
@@ -377,26 +748,25 @@ async fn handle_client_message(
             handle_resume(session_id, user_id, state, reply_tx).await;
         }
 
-        WsClientMessage::Ping => {
-            let _ = reply_tx.send(WsServerMessage::Pong);
-            // Refresh presence on each ping to handle stale entries
-            if let Some(mut redis) = state.redis.clone() {
-                let current: Option<String> = redis::cmd("HGET")
-                    .arg("haven:presence")
-                    .arg(user_id.to_string())
-                    .query_async(&mut redis)
-                    .await
-                    .unwrap_or(None);
-                if let Some(status) = current {
-                    let _: Result<(), _> = redis::cmd("HSET")
-                        .arg("haven:presence")
-                        .arg(user_id.to_string())
-                        .arg(status)
-                        .query_async(&mut redis)
-                        .await;
-                }
+        WsClientMessage::SubscribeMemberList { server_id, group_by, range } => {
+            if let Err(e) = member_list::subscribe(
+                state, server_id, session_id, user_id, reply_tx, group_by, range,
+            )
+            .await
+            {
+                let _ = reply_tx.send(WsServerMessage::Error {
+                    message: e.to_string(),
+                });
             }
-            // In-memory presence is always kept up-to-date via broadcast_presence
+        }
+
+        WsClientMessage::UnsubscribeMemberList { server_id } => {
+            member_list::unsubscribe(state, server_id, session_id).await;
+        }
+
+        WsClientMessage::Ping(heartbeat) => {
+            let _ = reply_tx.send(WsServerMessage::Pong);
+            handle_heartbeat(user_id, session_id, heartbeat, state).await;
         }
     }
 }
@@ -427,13 +797,8 @@ async fn handle_mark_read(
                 channel_id,
                 last_read_at: read_state.last_read_at,
             };
-            if let Some(conns) = state.connections.get(&user_id) {
-                for conn in conns.iter() {
-                    // Send to all connections (including this one for confirmation)
-                    let _ = conn.send(sync_msg.clone());
-                }
-            }
-            pubsub::publish_user_event(state.redis.clone().as_mut(), user_id, &sync_msg).await;
+            // Sent to all connections, including this one for confirmation
+            pubsub::send_to_user(state, user_id, &sync_msg).await;
         }
         Err(e) => {
             tracing::warn!("Failed to upsert read state: {}", e);
@@ -442,48 +807,51 @@ async fn handle_mark_read(
 }
 
 /// Handle a Resume command: replay buffered events from a previous session.
+/// Sessions not held locally may have been handed off by a draining instance.
 async fn handle_resume(
     session_id: Uuid,
     user_id: Uuid,
     state: &AppState,
     reply_tx: &mpsc::UnboundedSender<WsServerMessage>,
 ) {
-    let session = match state.sessions.get(&session_id) {
-        Some(s) => s.clone(),
-        None => {
-            let _ = reply_tx.send(WsServerMessage::InvalidSession);
-            return;
-        }
-    };
+    let local = state.sessions.get(&session_id).map(|s| s.clone());
+    let events: Vec<WsServerMessage> = match local {
+        Some(session) => {
+            // Verify session belongs to this user
+            if session.user_id != user_id {
+                let _ = reply_tx.send(WsServerMessage::InvalidSession);
+                return;
+            }
 
-    // Verify session belongs to this user
-    if session.user_id != user_id {
-        let _ = reply_tx.send(WsServerMessage::InvalidSession);
-        return;
-    }
+            // Check if session has expired
+            let ttl = Duration::from_secs(state.config.ws_session_ttl_secs);
+            if session.last_active.lock().await.elapsed() > ttl {
+                state.sessions.remove(&session_id);
+                let _ = reply_tx.send(WsServerMessage::InvalidSession);
+                return;
+            }
 
-    // Check if session has expired
-    let ttl = Duration::from_secs(state.config.ws_session_ttl_secs);
-    if session.last_active.lock().await.elapsed() > ttl {
-        state.sessions.remove(&session_id);
-        let _ = reply_tx.send(WsServerMessage::InvalidSession);
-        return;
-    }
+            // Update last_active
+            *session.last_active.lock().await = Instant::now();
 
-    // Replay buffered events
-    let events: Vec<WsServerMessage> = {
-        let mut buf = session.event_buffer.lock().await;
-        buf.drain(..).collect()
+            let mut buf = session.event_buffer.lock().await;
+            buf.drain(..).collect()
+        }
+        None => match take_persisted_session(state, session_id).await {
+            Some(persisted) if persisted.user_id == user_id => persisted.events,
+            _ => {
+                let _ = reply_tx.send(WsServerMessage::InvalidSession);
+                return;
+            }
+        },
     };
-    let replayed_count = events.len() as u32;
 
+    // Replay buffered events
+    let replayed_count = events.len() as u32;
     for event in events {
         let _ = reply_tx.send(event);
     }
 
-    // Update last_active
-    *session.last_active.lock().await = Instant::now();
-
     let _ = reply_tx.send(WsServerMessage::Resumed { replayed_count });
     tracing::info!(
         "WebSocket session resumed: user={}, session={}, replayed={}",
@@ -501,6 +869,7 @@ async fn handle_send_message(
     expires_at: Option<chrono::DateTime<chrono::Utc>>,
     attachment_ids: Option<Vec<Uuid>>,
     reply_to_id: Option<Uuid>,
+    capability_hash: Option<&str>,
     state: &AppState,
     reply_tx: &mpsc::UnboundedSender<WsServerMessage>,
 ) {
@@ -572,6 +941,19 @@ async fn handle_send_message(
         return;
     }
 
+    // Sealed sender: edits are authorized by capability and no sender is stored
+    let capability_hash_bytes = match capability_hash.map(sealed_sender::decode_capability_hash) {
+        None => None,
+        Some(Some(hash)) => Some(hash),
+        Some(None) => {
+            let _ = reply_tx.send(WsServerMessage::Error {
+                message: "capability_hash must be 32 bytes of base64".into(),
+            });
+            return;
+        }
+    };
+    let sender_id = if capability_hash_bytes.is_some() { None } else { Some(user_id) };
+
     let has_attachments = attachment_ids.as_ref().is_some_and(|ids| !ids.is_empty());
 
     // Persist message
@@ -582,8 +964,9 @@ async fn handle_send_message(
         &encrypted_body_bytes,
         expires_at,
         has_attachments,
-        user_id,
+        sender_id,
         reply_to_id,
+        capability_hash_bytes.as_deref(),
     )
     .await
     {
@@ -727,43 +1110,23 @@ async fn handle_set_status(
         return;
     }
 
-    // For invisible, we store it in Redis but broadcast as "offline"
-    let broadcast_status = if status == "invisible" { "offline" } else { status };
-
-    // Update presence store
-    if let Some(mut redis) = state.redis.clone() {
-        let _: Result<(), _> = redis::cmd("HSET")
-            .arg("haven:presence")
-            .arg(user_id.to_string())
-            .arg(status)
-            .query_async(&mut redis)
-            .await;
-    }
-    state.memory.presence.insert(user_id, status.to_string());
-
-    // Broadcast to all channels this user belongs to
-    let channel_ids = match queries::get_user_channel_ids(state.db.read(), user_id).await {
-        Ok(ids) => ids,
-        Err(_) => return,
-    };
-
-    let msg = WsServerMessage::PresenceUpdate {
-        user_id,
-        status: broadcast_status.to_string(),
+    // Keep `since` when re-setting the same status; an explicit choice is never auto-idle
+    let presence = match presence::get(state, user_id).await {
+        Some(p) if p.status == status => PresenceState { auto_idle: false, ..p },
+        _ => PresenceState::new(status),
     };
+    presence::set(state, user_id, &presence).await;
 
-    for channel_id in &channel_ids {
-        if let Some(broadcaster) = state.channel_broadcasts.get(channel_id) {
-            let _ = broadcaster.send(msg.clone());
-        }
-    }
-    for channel_id in channel_ids {
-        pubsub::publish_channel_event(state.redis.clone().as_mut(), channel_id, &msg).await;
-    }
+    fan_out_presence(user_id, Some(&presence), None, state).await;
 }
 
 /// Handle typing indicator — ephemeral, no persistence.
 async fn handle_typing(user_id: Uuid, channel_id: Uuid, state: &AppState) {
+    let channel = match queries::find_channel_by_id(state.db.read(), channel_id).await {
+        Ok(Some(c)) => c,
+        _ => return,
+    };
+
     // Look up username for display (cached)
     let username = match queries::find_user_by_id_cached(state.db.read(), &mut state.redis.clone(), &state.memory, user_id).await {
         Ok(Some(user)) => user.display_name.unwrap_or(user.username),
@@ -775,17 +1138,35 @@ async fn handle_typing(user_id: Uuid, channel_id: Uuid, state: &AppState) {
         user_id,
         username,
     };
+
+    // DM/group: deliver to the other participants directly, so they see it
+    // without having subscribed to the channel
+    if channel.server_id.is_none() {
+        let Ok(member_ids) = queries::get_channel_member_ids(state.db.read(), channel_id).await else {
+            return;
+        };
+        if !member_ids.contains(&user_id) {
+            return;
+        }
+        for mid in member_ids.into_iter().filter(|&mid| mid != user_id) {
+            pubsub::send_to_user(state, mid, &typing_msg).await;
+        }
+        return;
+    }
+
     if let Some(broadcaster) = state.channel_broadcasts.get(&channel_id) {
         let _ = broadcaster.send(typing_msg.clone());
     }
     pubsub::publish_channel_event(state.redis.clone().as_mut(), channel_id, &typing_msg).await;
 }
 
-/// Handle an EditMessage command: verify ownership, update DB, broadcast.
+/// Handle an EditMessage command: verify ownership (sender, or capability for
+/// sealed-sender messages), update DB, broadcast.
 async fn handle_edit_message(
     user_id: Uuid,
     message_id: Uuid,
     encrypted_body: &str,
+    capability: Option<&str>,
     state: &AppState,
     reply_tx: &mpsc::UnboundedSender<WsServerMessage>,
 ) {
@@ -802,14 +1183,25 @@ async fn handle_edit_message(
         }
     };
 
-    let message = match queries::update_message_body(
-        state.db.write(),
-        message_id,
-        user_id,
-        &encrypted_body_bytes,
-    )
-    .await
-    {
+    let updated = match capability {
+        Some(capability) => match sealed_sender::decode_capability(capability) {
+            Some(hash) => {
+                queries::update_sealed_message_body(
+                    state.db.write(),
+                    message_id,
+                    &hash,
+                    &encrypted_body_bytes,
+                )
+                .await
+            }
+            None => Err(AppError::Validation("Invalid capability encoding".into())),
+        },
+        None => {
+            queries::update_message_body(state.db.write(), message_id, user_id, &encrypted_body_bytes)
+                .await
+        }
+    };
+    let message = match updated {
         Ok(m) => m,
         Err(e) => {
             let _ = reply_tx.send(WsServerMessage::Error {
@@ -836,15 +1228,22 @@ async fn handle_edit_message(
     pubsub::publish_channel_event(state.redis.clone().as_mut(), message.channel_id, &edit_msg).await;
 }
 
-/// Handle a DeleteMessage command: verify ownership or server admin, delete from DB, broadcast.
+/// Handle a DeleteMessage command: verify ownership (sender, or capability for
+/// sealed-sender messages) or server admin, delete from DB, broadcast.
 async fn handle_delete_message(
     user_id: Uuid,
     message_id: Uuid,
+    capability: Option<&str>,
     state: &AppState,
     reply_tx: &mpsc::UnboundedSender<WsServerMessage>,
 ) {
     // Try deleting as sender first (fast path)
-    let message = match queries::delete_message(state.db.write(), message_id, user_id).await {
+    let deleted = match capability.map(sealed_sender::decode_capability) {
+        Some(Some(hash)) => queries::delete_sealed_message(state.db.write(), message_id, &hash).await,
+        Some(None) => Err(AppError::Validation("Invalid capability encoding".into())),
+        None => queries::delete_message(state.db.write(), message_id, user_id).await,
+    };
+    let message = match deleted {
         Ok(m) => m,
         Err(_) => {
             // Sender check failed — check if user is the server owner
@@ -1053,56 +1452,200 @@ async fn handle_remove_reaction(
 /// Broadcast a presence update (online/offline) to all channels the user belongs to,
 /// and track the state in Redis for multi-instance queries.
 pub(crate) async fn broadcast_presence(user_id: Uuid, status: &str, state: &AppState) {
-    // Update presence stores
-    if let Some(mut redis) = state.redis.clone() {
-        let redis_result: Result<(), _> = if status == "offline" {
-            redis::cmd("HDEL")
-                .arg("haven:presence")
-                .arg(user_id.to_string())
-                .query_async(&mut redis)
-                .await
-        } else {
-            redis::cmd("HSET")
-                .arg("haven:presence")
-                .arg(user_id.to_string())
-                .arg(status)
-                .query_async(&mut redis)
-                .await
+    if status == "offline" {
+        let previous = presence::get(state, user_id).await;
+        presence::remove(state, user_id).await;
+
+        // Invisible users already looked offline; don't reveal when they left
+        let last_seen = match previous {
+            Some(p) if p.status != "invisible" && state.config.presence_last_seen => {
+                queries::record_last_seen(state.db.write(), user_id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Failed to record last-seen: {}", e);
+                        None
+                    })
+            }
+            _ => None,
         };
-        if let Err(e) = redis_result {
-            tracing::warn!("Failed to update presence in Redis: {}", e);
-        }
+        fan_out_presence(user_id, None, last_seen, state).await;
+        return;
     }
-    // Always update in-memory presence
-    if status == "offline" {
-        state.memory.presence.remove(&user_id);
+
+    // Another device coming online keeps the user's chosen status (dnd, invisible, ...)
+    let presence = match presence::get(state, user_id).await {
+        Some(p) if !p.auto_idle && (status == "online" || p.status == status) => p,
+        _ => PresenceState::new(status),
+    };
+    presence::set(state, user_id, &presence).await;
+
+    fan_out_presence(user_id, Some(&presence), None, state).await;
+}
+
+/// Send a status change to the user's server channels and member list
+/// subscribers, and directly to friends and DM/group participants.
+/// Channels of large servers are skipped — member list subscriptions cover those.
+/// Users with friends-only presence appear offline to everyone but friends.
+/// `presence` is None when the user went offline.
+async fn fan_out_presence(
+    user_id: Uuid,
+    presence: Option<&PresenceState>,
+    last_seen: Option<DateTime<Utc>>,
+    state: &AppState,
+) {
+    let (status, since) = match presence {
+        Some(p) if p.status != "invisible" => (p.visible_status(), Some(p.since)),
+        _ => ("offline", None),
+    };
+    let full = WsServerMessage::PresenceUpdate {
+        user_id,
+        status: status.to_string(),
+        since,
+        last_seen,
+    };
+    let friends_only = presence_is_friends_only(state, user_id).await;
+    let public = if friends_only {
+        WsServerMessage::PresenceUpdate {
+            user_id,
+            status: "offline".into(),
+            since: None,
+            last_seen: None,
+        }
     } else {
-        state.memory.presence.insert(user_id, status.to_string());
-    }
+        full.clone()
+    };
+    let public_status = if friends_only { "offline" } else { status };
+
+    member_list::presence_changed(state, user_id, public_status).await;
 
-    // Get all channels this user belongs to and broadcast
-    let channel_ids = match queries::get_user_channel_ids(state.db.read(), user_id).await {
-        Ok(ids) => ids,
+    match queries::get_user_presence_channel_ids(
+        state.db.read(),
+        user_id,
+        state.config.lazy_member_list_threshold as i64,
+    )
+    .await
+    {
+        Ok(channel_ids) => {
+            for ch_id in &channel_ids {
+                if let Some(broadcaster) = state.channel_broadcasts.get(ch_id) {
+                    let _ = broadcaster.send(public.clone());
+                }
+            }
+            // Publish presence to all subscribed channels via Redis
+            for ch_id in channel_ids {
+                pubsub::publish_channel_event(state.redis.clone().as_mut(), ch_id, &public).await;
+            }
+        }
         Err(e) => {
             tracing::warn!("Failed to get user channels for presence: {}", e);
+        }
+    }
+
+    match queries::get_presence_audience(state.db.read(), user_id).await {
+        Ok(audience) => {
+            for (recipient, is_friend) in audience {
+                let msg = if is_friend { &full } else { &public };
+                pubsub::send_to_user(state, recipient, msg).await;
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to get presence audience: {}", e);
+        }
+    }
+}
+
+/// Re-send a user's current status, e.g. after their presence privacy changed.
+pub(crate) async fn republish_presence(state: &AppState, user_id: Uuid) {
+    let current = presence::get(state, user_id).await;
+    fan_out_presence(user_id, current.as_ref(), None, state).await;
+}
+
+/// Heartbeat-driven idle detection. Clients report how long the user has been
+/// idle; once every session of the user on this instance is past
+/// `presence_idle_timeout_secs`, an online user becomes idle, and the next
+/// activity brings them back. Statuses the user picked are never touched.
+async fn handle_heartbeat(
+    user_id: Uuid,
+    session_id: Uuid,
+    heartbeat: Option<HeartbeatInfo>,
+    state: &AppState,
+) {
+    let timeout_secs = state.config.presence_idle_timeout_secs;
+    let Some(heartbeat) = heartbeat.filter(|_| timeout_secs > 0) else {
+        return;
+    };
+    let Some(session) = state.sessions.get(&session_id).map(|s| s.clone()) else {
+        return;
+    };
+    let idle = heartbeat.idle_ms >= timeout_secs * 1000;
+    if session.idle.swap(idle, Ordering::SeqCst) != idle {
+        refresh_idle(user_id, state).await;
+    }
+}
+
+/// Apply auto-idle transitions from the idle flags of the user's sessions.
+async fn refresh_idle(user_id: Uuid, state: &AppState) {
+    let user_idle = state
+        .sessions
+        .iter()
+        .filter(|s| s.user_id == user_id)
+        .all(|s| s.idle.load(Ordering::SeqCst));
+
+    let Some(current) = presence::get(state, user_id).await else {
+        return;
+    };
+    let next = if user_idle && current.status == "online" {
+        PresenceState {
+            status: "idle".into(),
+            since: Utc::now(),
+            auto_idle: true,
+        }
+    } else if !user_idle && current.auto_idle {
+        PresenceState::new("online")
+    } else {
+        return;
+    };
+    presence::set(state, user_id, &next).await;
+    fan_out_presence(user_id, Some(&next), None, state).await;
+}
+
+/// Send a custom status change to the user's friends and DM/group participants
+/// (friends only, if the user limits presence to friends).
+pub async fn broadcast_custom_status(
+    state: &AppState,
+    user_id: Uuid,
+    custom_status: Option<String>,
+    custom_status_emoji: Option<String>,
+    custom_status_expires_at: Option<DateTime<Utc>>,
+) {
+    let friends_only = presence_is_friends_only(state, user_id).await;
+    let audience = match queries::get_presence_audience(state.db.read(), user_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::warn!("Failed to get presence audience: {}", e);
             return;
         }
     };
 
-    let msg = WsServerMessage::PresenceUpdate {
+    let msg = WsServerMessage::CustomStatusUpdate {
         user_id,
-        status: status.to_string(),
+        custom_status,
+        custom_status_emoji,
+        custom_status_expires_at,
     };
-
-    for ch_id in &channel_ids {
-        if let Some(broadcaster) = state.channel_broadcasts.get(ch_id) {
-            let _ = broadcaster.send(msg.clone());
+    for (recipient, is_friend) in audience {
+        if is_friend || !friends_only {
+            pubsub::send_to_user(state, recipient, &msg).await;
         }
     }
-    // Publish presence to all subscribed channels via Redis
-    for ch_id in channel_ids {
-        pubsub::publish_channel_event(state.redis.clone().as_mut(), ch_id, &msg).await;
-    }
+}
+
+/// Whether the user only shares presence with friends. Fails closed.
+async fn presence_is_friends_only(state: &AppState, user_id: Uuid) -> bool {
+    queries::get_presence_privacy(state.db.read(), user_id)
+        .await
+        .map(|p| p == "friends_only")
+        .unwrap_or(true)
 }
 
 /// Handle PinMessage: verify permissions and broadcast.
@@ -1267,12 +1810,7 @@ async fn send_to_channel_members(
             if Some(mid) == exclude_user_id {
                 continue;
             }
-            if let Some(conns) = state.connections.get(&mid) {
-                for tx in conns.iter() {
-                    let _ = tx.send(msg.clone());
-                }
-            }
-            pubsub::publish_user_event(state.redis.clone().as_mut(), mid, &msg).await;
+            pubsub::send_to_user(state, mid, &msg).await;
         }
     }
 }
@@ -939,6 +939,49 @@ async fn update_profile(pool: Pool) {
     assert_eq!(value["about_me"].as_str(), Some("Hello world"));
 }
 
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn custom_status_expires(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, user_id) = app.register_user("status_expiry").await;
+
+    let past = chrono::Utc::now() - chrono::Duration::minutes(1);
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/profile",
+            Some(&token),
+            Some(json!({ "custom_status": "lunch", "custom_status_expires_at": past })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let soon = chrono::Utc::now() + chrono::Duration::seconds(2);
+    let (status, value) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/profile",
+            Some(&token),
+            Some(json!({ "custom_status": "lunch", "custom_status_expires_at": soon })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["custom_status"].as_str(), Some("lunch"));
+    assert!(value["custom_status_expires_at"].is_string());
+
+    let presence_uri = format!("/api/v1/presence?user_ids={}", user_id);
+    let (_, value) = app.request(Method::GET, &presence_uri, Some(&token), None).await;
+    assert_eq!(value[0]["custom_status"].as_str(), Some("lunch"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+
+    let profile_uri = format!("/api/v1/users/{}/profile", user_id);
+    let (_, value) = app.request(Method::GET, &profile_uri, Some(&token), None).await;
+    assert!(value["custom_status"].is_null());
+    let (_, value) = app.request(Method::GET, &presence_uri, Some(&token), None).await;
+    assert!(value[0].get("custom_status").is_none());
+}
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
 async fn search_user_by_username(pool: Pool) {
@@ -1145,7 +1188,7 @@ async fn list_channel_members(pool: Pool) {
     let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
     assert_eq!(status, StatusCode::OK);
     let members = value.as_array().unwrap();
-    assert!(members.len() >= 1);
+    assert!(!members.is_empty());
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
@@ -1739,1382 +1782,3164 @@ async fn update_identity_keys(pool: Pool) {
     assert_eq!(status, StatusCode::OK);
 }
 
-// ─── Attachments ────────────────────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn upload_attachment(pool: Pool) {
+async fn register_and_remove_device(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("attach_user").await;
+    let (token_a, user_a) = app.register_user("device_owner").await;
+    let (token_b, _) = app.register_user("device_peer").await;
 
-    let data = vec![0u8; 1024]; // 1KB of zeros
-    let (status, value) = app
-        .request_bytes(Method::POST, "/api/v1/attachments/upload", Some(&token), data)
+    let body = json!({
+        "name": "Laptop",
+        "identity_key": B64.encode([7u8; 32]),
+        "signed_prekey": B64.encode([8u8; 32]),
+        "signed_prekey_signature": B64.encode([9u8; 64]),
+        "one_time_prekeys": [B64.encode([10u8; 32])]
+    });
+    let (status, device) = app
+        .request(Method::POST, "/api/v1/keys/devices", Some(&token_a), Some(body))
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert!(value["attachment_id"].as_str().is_some());
-    assert!(value["storage_key"].as_str().is_some());
+    let device_id = device["id"].as_str().unwrap().to_string();
+
+    let (_, devices) = app
+        .request(Method::GET, "/api/v1/keys/devices", Some(&token_a), None)
+        .await;
+    assert_eq!(devices.as_array().unwrap().len(), 2);
+    assert_eq!(devices[1]["name"].as_str(), Some("Laptop"));
+
+    // Bundles cover every device, each consuming its own one-time prekey
+    let uri = format!("/api/v1/users/{}/keys", user_a);
+    let (status, bundle) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let bundles = bundle["devices"].as_array().unwrap();
+    assert_eq!(bundles.len(), 2);
+    assert_eq!(bundle["identity_key"], bundles[0]["identity_key"]);
+    assert_eq!(bundles[1]["device_id"].as_str(), Some(device_id.as_str()));
+    assert_eq!(bundles[1]["identity_key"].as_str(), Some(B64.encode([7u8; 32]).as_str()));
+    assert!(bundles[1]["one_time_prekey"].is_string());
+
+    // Someone else's device can't be removed
+    let remove_uri = format!("/api/v1/keys/devices/{}", device_id);
+    let (status, _) = app.request(Method::DELETE, &remove_uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app.request(Method::DELETE, &remove_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, bundle) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+    assert_eq!(bundle["devices"].as_array().unwrap().len(), 1);
+
+    // The last device stays
+    let primary_uri = format!(
+        "/api/v1/keys/devices/{}",
+        bundle["devices"][0]["device_id"].as_str().unwrap()
+    );
+    let (status, _) = app.request(Method::DELETE, &primary_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn upload_empty_attachment_fails(pool: Pool) {
+async fn sender_keys_are_addressed_per_device(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("empty_attach").await;
+    let (token_a, _) = app.register_user("skd_sender").await;
+    let (token_b, user_b) = app.register_user("skd_receiver").await;
+    let server_id = app.create_server(&token_a, "SKD Test").await;
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let channel_id = app.create_channel(&token_a, server_id, "encrypted-ch").await;
 
-    let (status, _) = app
-        .request_bytes(Method::POST, "/api/v1/attachments/upload", Some(&token), vec![])
+    let body = json!({
+        "identity_key": B64.encode([7u8; 32]),
+        "signed_prekey": B64.encode([8u8; 32]),
+        "signed_prekey_signature": B64.encode([9u8; 64])
+    });
+    let (_, device) = app
+        .request(Method::POST, "/api/v1/keys/devices", Some(&token_b), Some(body))
         .await;
-    assert_ne!(status, StatusCode::OK);
-}
+    let second = device["id"].as_str().unwrap().to_string();
 
-// ─── DM Privacy ─────────────────────────────────────────
+    // Member keys list both of B's devices
+    let keys_uri = format!("/api/v1/channels/{}/members/keys", channel_id);
+    let (_, members) = app.request(Method::GET, &keys_uri, Some(&token_a), None).await;
+    let b = members
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["user_id"].as_str() == Some(user_b.to_string().as_str()))
+        .unwrap();
+    assert_eq!(b["devices"].as_array().unwrap().len(), 2);
+    let primary = b["devices"][0]["device_id"].as_str().unwrap().to_string();
 
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn update_dm_privacy(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("dm_priv_user").await;
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let dist_id = Uuid::new_v4();
+    let body = json!({
+        "distributions": [
+            { "to_user_id": user_b, "distribution_id": dist_id, "encrypted_skdm": B64.encode(b"for-primary") },
+            { "to_user_id": user_b, "to_device_id": second, "distribution_id": dist_id, "encrypted_skdm": B64.encode(b"for-second") }
+        ]
+    });
+    let (status, value) = app.request(Method::POST, &uri, Some(&token_a), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["distributed"].as_i64(), Some(2));
 
-    let body = json!({ "dm_privacy": "friends_only" });
-    let (status, value) = app
-        .request(
-            Method::PUT,
-            "/api/v1/users/dm-privacy",
-            Some(&token),
-            Some(body),
-        )
+    // A device of another user is rejected
+    let body = json!({
+        "distributions": [
+            { "to_user_id": user_b, "to_device_id": Uuid::new_v4(), "distribution_id": dist_id, "encrypted_skdm": B64.encode(b"x") }
+        ]
+    });
+    let (status, _) = app.request(Method::POST, &uri, Some(&token_a), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let second_uri = format!("{}?device_id={}", uri, second);
+    let (_, skdms) = app.request(Method::GET, &second_uri, Some(&token_b), None).await;
+    assert_eq!(skdms.as_array().unwrap().len(), 1);
+    assert_eq!(skdms[0]["encrypted_skdm"].as_str(), Some(B64.encode(b"for-second").as_str()));
+
+    // New keys on the second device drop only its own SKDMs
+    let body = json!({
+        "device_id": second,
+        "identity_key": B64.encode([11u8; 32]),
+        "signed_prekey": B64.encode([12u8; 32]),
+        "signed_prekey_signature": B64.encode([13u8; 64])
+    });
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(body))
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["dm_privacy"].as_str(), Some("friends_only"));
+
+    let (_, skdms) = app.request(Method::GET, &second_uri, Some(&token_b), None).await;
+    assert!(skdms.as_array().unwrap().is_empty());
+    let primary_uri = format!("{}?device_id={}", uri, primary);
+    let (_, skdms) = app.request(Method::GET, &primary_uri, Some(&token_b), None).await;
+    assert_eq!(skdms.as_array().unwrap().len(), 1);
+    assert_eq!(skdms[0]["encrypted_skdm"].as_str(), Some(B64.encode(b"for-primary").as_str()));
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn update_dm_privacy_invalid_value(pool: Pool) {
+async fn last_resort_prekey_served_when_exhausted(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("dm_priv_bad").await;
+    let (token_a, _) = app.register_user("lr_requester").await;
+    let (token_b, user_b) = app.register_user("lr_target").await;
 
-    let body = json!({ "dm_privacy": "invalid_value" });
+    let uri = format!("/api/v1/users/{}/keys", user_b);
+    let (_, bundle) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert!(bundle["one_time_prekey"].is_null());
+    assert_eq!(bundle["last_resort"].as_bool(), Some(false));
+
+    let last_resort = B64.encode([42u8; 32]);
     let (status, _) = app
         .request(
             Method::PUT,
-            "/api/v1/users/dm-privacy",
-            Some(&token),
-            Some(body),
+            "/api/v1/keys/last-resort",
+            Some(&token_b),
+            Some(json!({ "prekey": last_resort })),
         )
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+
+    // Handed out on every fetch, never consumed
+    for _ in 0..2 {
+        let (_, bundle) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+        assert_eq!(bundle["one_time_prekey"].as_str(), Some(last_resort.as_str()));
+        assert_eq!(bundle["last_resort"].as_bool(), Some(true));
+        assert_eq!(bundle["devices"][0]["last_resort"].as_bool(), Some(true));
+    }
+
+    let (_, value) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token_b), None)
+        .await;
+    assert_eq!(value["has_last_resort"].as_bool(), Some(true));
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_friends_only_creates_pending_dm(pool: Pool) {
+async fn signed_prekey_rotation_retains_previous_key(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("dm_fo_a").await;
-    let (token_b, user_b) = app.register_user("dm_fo_b").await;
+    let (token_a, _) = app.register_user("spk_requester").await;
+    let (token_b, user_b) = app.register_user("spk_owner").await;
 
-    // B sets friends_only
-    app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "friends_only" })),
+    let uri = format!("/api/v1/users/{}/keys", user_b);
+    let (_, bundle) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(bundle["signed_prekey_id"].as_i64(), Some(1));
+    let identity_key = bundle["identity_key"].as_str().unwrap().to_string();
+
+    let (_, count) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token_b), None)
+        .await;
+    assert_eq!(count["signed_prekey_id"].as_i64(), Some(1));
+    assert_eq!(count["signed_prekey_stale"].as_bool(), Some(false));
+    assert!(count["previous_signed_prekey_id"].is_null());
+
+    // Backdate the key past the 30-day rotation schedule
+    sqlx::query(
+        "UPDATE devices SET signed_prekey_created_at = NOW() - INTERVAL '31 days' WHERE user_id = $1",
     )
-    .await;
+    .bind(user_b)
+    .execute(app.state().db.write())
+    .await
+    .unwrap();
+    let (_, count) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token_b), None)
+        .await;
+    assert_eq!(count["signed_prekey_stale"].as_bool(), Some(true));
 
-    // A (not a friend) creates DM with B — should be pending
-    use base64::Engine;
-    let b64 = &base64::engine::general_purpose::STANDARD;
+    // Rotating under the same identity key retains the old signed prekey
+    let new_spk = B64.encode([77u8; 32]);
     let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": b64.encode(b"dm-meta")
+        "identity_key": identity_key,
+        "signed_prekey": new_spk,
+        "signed_prekey_signature": B64.encode([78u8; 64]),
     });
     let (status, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(body.clone()))
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["dm_status"].as_str(), Some("pending"));
-}
+    assert_eq!(value["signed_prekey_id"].as_i64(), Some(2));
 
-// ─── Friends Extended ───────────────────────────────────
+    let (_, bundle) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(bundle["signed_prekey"].as_str(), Some(new_spk.as_str()));
+    assert_eq!(bundle["signed_prekey_id"].as_i64(), Some(2));
+    assert_eq!(bundle["devices"][0]["signed_prekey_id"].as_i64(), Some(2));
 
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn remove_friend(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("remove_a").await;
-    let (token_b, _) = app.register_user("remove_b").await;
+    let (_, count) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token_b), None)
+        .await;
+    assert_eq!(count["signed_prekey_stale"].as_bool(), Some(false));
+    assert_eq!(count["previous_signed_prekey_id"].as_i64(), Some(1));
+    assert!(count["previous_signed_prekey_expires_at"].is_string());
 
-    let friendship_id = app.make_friends(&token_a, &token_b, "remove_b").await;
+    // Re-sending the current key is a no-op; reusing its ID is rejected
+    let (_, value) = app
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(body))
+        .await;
+    assert_eq!(value["signed_prekey_id"].as_i64(), Some(2));
+    let reused = json!({
+        "identity_key": identity_key,
+        "signed_prekey": B64.encode([79u8; 32]),
+        "signed_prekey_signature": B64.encode([80u8; 64]),
+        "signed_prekey_id": 2,
+    });
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(reused))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
 
-    let uri = format!("/api/v1/friends/{}", friendship_id);
+    // A new identity key invalidates everything it signed, so nothing is retained
+    let body = json!({
+        "identity_key": B64.encode([81u8; 32]),
+        "signed_prekey": B64.encode([82u8; 32]),
+        "signed_prekey_signature": B64.encode([83u8; 64]),
+        "signed_prekey_id": 10,
+    });
     let (status, _) = app
-        .request(Method::DELETE, &uri, Some(&token_a), None)
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(body))
         .await;
     assert_eq!(status, StatusCode::OK);
-
-    // Friends list should be empty
-    let (_, value) = app
-        .request(Method::GET, "/api/v1/friends", Some(&token_a), None)
+    let (_, count) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token_b), None)
         .await;
-    let friends = value.as_array().unwrap();
-    assert!(friends.is_empty());
+    assert_eq!(count["signed_prekey_id"].as_i64(), Some(10));
+    assert!(count["previous_signed_prekey_id"].is_null());
 }
 
-// ─── Reactions ──────────────────────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_channel_reactions_empty(pool: Pool) {
+async fn key_transparency_log_proves_identity_key_history(pool: Pool) {
+    use haven_backend::transparency::{tree_head_message, verify_inclusion, Hash};
+
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("react_user").await;
-    let server_id = app.create_server(&token, "React Server").await;
-    let channel_id = app.create_channel(&token, server_id, "general").await;
+    let (admin_token, _) = app.register_user("kt_admin").await;
+    let (token, user_id) = app.register_user("kt_user").await;
 
-    let uri = format!("/api/v1/channels/{}/reactions", channel_id);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    let body = json!({
+        "identity_key": B64.encode([91u8; 32]),
+        "signed_prekey": B64.encode([92u8; 32]),
+        "signed_prekey_signature": B64.encode([93u8; 64]),
+    });
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token), Some(body))
+        .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value.as_array().unwrap().len(), 0);
-}
 
-// ─── Presence ───────────────────────────────────────────
+    let uri = format!("/api/v1/users/{}/keys/log", user_id);
+    let (status, log) = app.request(Method::GET, &uri, Some(&admin_token), None).await;
+    assert_eq!(status, StatusCode::OK);
 
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_presence_returns_offline_for_unknown(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let random_id = Uuid::new_v4();
-    let uri = format!("/api/v1/presence?user_ids={}", random_id);
-    let (status, value) = app.request(Method::GET, &uri, None, None).await;
+    // Registration key, then the replacement, chained together
+    let entries = log["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0]["prev_hash"].is_null());
+    assert_eq!(entries[1]["prev_hash"], entries[0]["leaf_hash"]);
+    assert_eq!(entries[1]["identity_key"].as_str(), Some(B64.encode([91u8; 32]).as_str()));
+
+    let decode = |v: &serde_json::Value| B64.decode(v.as_str().unwrap()).unwrap();
+    let hash = |v: &serde_json::Value| -> Hash { decode(v).try_into().unwrap() };
+
+    let head = &log["tree_head"];
+    let tree_size = head["tree_size"].as_u64().unwrap();
+    assert_eq!(tree_size, 3);
+    let root = hash(&head["root_hash"]);
+    for entry in entries {
+        let proof: Vec<Hash> = entry["audit_path"].as_array().unwrap().iter().map(hash).collect();
+        let index = entry["seq"].as_u64().unwrap();
+        assert!(verify_inclusion(&hash(&entry["leaf_hash"]), index, tree_size, &proof, &root));
+    }
+
+    // The tree head is signed with the published Ed25519 key
+    let timestamp: chrono::DateTime<chrono::Utc> =
+        serde_json::from_value(head["timestamp"].clone()).unwrap();
+    let public_key = decode(&head["public_key"]);
+    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &public_key)
+        .verify(&tree_head_message(tree_size, timestamp, &root), &decode(&head["signature"]))
+        .expect("tree head signature");
+    let (status, keys) = app.request(Method::GET, "/.well-known/haven-keys.json", None, None).await;
     assert_eq!(status, StatusCode::OK);
-    let entries = value.as_array().unwrap();
-    assert_eq!(entries.len(), 1);
-    assert_eq!(entries[0]["status"].as_str(), Some("offline"));
-}
+    let published = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&public_key);
+    assert!(keys["tree_head"]["keys"].as_array().unwrap().iter().any(|k| k["x"] == published));
 
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_presence_empty_ids_returns_empty(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (status, value) = app
-        .request(Method::GET, "/api/v1/presence?user_ids=", None, None)
+    let (status, same_head) = app
+        .request(Method::GET, "/api/v1/keys/transparency/head", Some(&token), None)
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value.as_array().unwrap().len(), 0);
-}
+    assert_eq!(same_head["root_hash"], head["root_hash"]);
 
-// ─── TOTP (2FA) ──────────────────────────────────────
+    // Auditors export the whole log; regular users cannot
+    let (status, _) = app
+        .request(Method::GET, "/api/v1/admin/key-transparency", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, export) = app
+        .request(Method::GET, "/api/v1/admin/key-transparency?after=0", Some(&admin_token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let exported = export["entries"].as_array().unwrap();
+    assert_eq!(exported.len(), 2);
+    assert_eq!(exported[0]["seq"].as_i64(), Some(1));
+    assert!(exported[0].get("audit_path").is_none());
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn totp_setup_returns_secret(pool: Pool) {
+async fn key_transparency_backfill_logs_each_key_once(pool: Pool) {
+    use haven_backend::db::queries;
+
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_setup").await;
+    let (_, user_id) = app.register_user("kt_backfill").await;
+    let db = app.state().db.write();
+    let device = queries::list_devices(db, user_id).await.unwrap().remove(0);
 
-    let (status, value) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
-        .await;
-    assert_eq!(status, StatusCode::OK);
-    assert!(value["secret"].as_str().is_some());
-    assert!(value["qr_code_uri"].as_str().is_some());
+    // Already logged at registration
+    let entry = queries::append_transparency_log(db, user_id, device.id, &device.identity_key, true)
+        .await
+        .unwrap();
+    assert!(entry.is_none());
+
+    // Two instances backfilling the same key at once log it once
+    let key = [44u8; 32];
+    let (first, second) = tokio::join!(
+        queries::append_transparency_log(db, user_id, device.id, &key, true),
+        queries::append_transparency_log(db, user_id, device.id, &key, true),
+    );
+    assert_eq!(first.unwrap().is_some() as u8 + second.unwrap().is_some() as u8, 1);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn totp_setup_twice_fails(pool: Pool) {
+async fn verified_contact_key_change_is_flagged(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_dup").await;
+    let (token_a, _) = app.register_user("verify_a").await;
+    let (token_b, user_b) = app.register_user("verify_b").await;
 
-    // Setup TOTP
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
-        .await;
-    let secret_b32 = value["secret"].as_str().unwrap().to_string();
+    let bundle_uri = format!("/api/v1/users/{}/keys", user_b);
+    let (_, bundle) = app.request(Method::GET, &bundle_uri, Some(&token_a), None).await;
+    assert_eq!(bundle["verified"].as_bool(), Some(false));
+    let keys: Vec<_> = bundle["devices"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| d["identity_key"].clone())
+        .collect();
 
-    // Verify with a valid code to activate it
-    let code = generate_totp_code(&secret_b32);
+    // A records that it verified B's current keys
+    let state_uri = format!("/api/v1/keys/verifications/{}", user_b);
+    let body = json!({
+        "encrypted_state": B64.encode(b"verified-safety-number"),
+        "nonce": B64.encode([0u8; 24]),
+        "verified_identity_keys": keys,
+    });
+    let (status, saved) = app.request(Method::PUT, &state_uri, Some(&token_a), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(saved["verified"].as_bool(), Some(true));
+    assert_eq!(saved["verified_key_changed"].as_bool(), Some(false));
+
+    let profile_uri = format!("/api/v1/users/{}/profile", user_b);
+    let (_, profile) = app.request(Method::GET, &profile_uri, Some(&token_a), None).await;
+    assert_eq!(profile["verified"].as_bool(), Some(true));
+    assert_eq!(profile["verified_key_changed"].as_bool(), Some(false));
+
+    // B replaces their identity key
+    let body = json!({
+        "identity_key": B64.encode([61u8; 32]),
+        "signed_prekey": B64.encode([62u8; 32]),
+        "signed_prekey_signature": B64.encode([63u8; 64]),
+    });
     let (status, _) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/totp/verify",
-            Some(&token),
-            Some(json!({ "code": code })),
-        )
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_b), Some(body))
         .await;
     assert_eq!(status, StatusCode::OK);
 
-    // Second setup should fail (TOTP already enabled)
-    let (status, _) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+    let (_, profile) = app.request(Method::GET, &profile_uri, Some(&token_a), None).await;
+    assert_eq!(profile["verified_key_changed"].as_bool(), Some(true));
+    let (_, bundle) = app.request(Method::GET, &bundle_uri, Some(&token_a), None).await;
+    assert_eq!(bundle["verified_key_changed"].as_bool(), Some(true));
+
+    // The state syncs to A's other devices with the same flag
+    let (status, states) = app
+        .request(Method::GET, "/api/v1/keys/verifications", Some(&token_a), None)
         .await;
-    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(status, StatusCode::OK);
+    let states = states.as_array().unwrap();
+    assert_eq!(states.len(), 1);
+    assert_eq!(states[0]["contact_id"].as_str(), Some(user_b.to_string().as_str()));
+    assert_eq!(states[0]["verified_key_changed"].as_bool(), Some(true));
+
+    let (status, _) = app.request(Method::DELETE, &state_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, profile) = app.request(Method::GET, &profile_uri, Some(&token_a), None).await;
+    assert_eq!(profile["verified"].as_bool(), Some(false));
+    assert_eq!(profile["verified_key_changed"].as_bool(), Some(false));
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn totp_verify_activates_2fa(pool: Pool) {
+async fn key_backup_versions_roll_back_and_audit_downloads(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_verify").await;
+    let (token, _) = app.register_user("backup_owner").await;
 
-    // Setup
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
-        .await;
-    let secret_b32 = value["secret"].as_str().unwrap().to_string();
+    // Version 1: passphrase-encrypted blob
+    let body = json!({
+        "encrypted_data": B64.encode(b"monolithic"),
+        "nonce": B64.encode([1u8; 24]),
+        "salt": B64.encode([2u8; 16]),
+    });
+    let (status, res) = app.request(Method::PUT, "/api/v1/keys/backup", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["version"].as_i64(), Some(1));
 
-    // Verify with correct code
-    let code = generate_totp_code(&secret_b32);
-    let (status, value) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/totp/verify",
-            Some(&token),
-            Some(json!({ "code": code })),
-        )
+    // Version 2: backup key wrapped by a recovery key and a passkey
+    let body = json!({
+        "encrypted_data": B64.encode(b"manifest"),
+        "nonce": B64.encode([3u8; 24]),
+        "key_slots": [
+            { "kind": "recovery_key", "wrapped_key": B64.encode([4u8; 48]), "nonce": B64.encode([5u8; 24]) },
+            {
+                "kind": "passkey_prf",
+                "wrapped_key": B64.encode([6u8; 48]),
+                "nonce": B64.encode([7u8; 24]),
+                "salt": B64.encode([8u8; 32]),
+                "credential_id": B64.encode(b"credential"),
+            },
+        ],
+    });
+    let (status, res) = app.request(Method::PUT, "/api/v1/keys/backup", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["version"].as_i64(), Some(2));
+
+    // Sessions are backed up incrementally, only into the current version
+    let sessions = |version: i64| {
+        json!({
+            "version": version,
+            "sessions": [
+                { "session_id": "peer-a", "encrypted_data": B64.encode(b"a"), "nonce": B64.encode([9u8; 24]) },
+                { "session_id": "peer-b", "encrypted_data": B64.encode(b"b"), "nonce": B64.encode([9u8; 24]) },
+            ],
+        })
+    };
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/backup/sessions", Some(&token), Some(sessions(1)))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/backup/sessions", Some(&token), Some(sessions(2)))
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert!(value["message"].as_str().unwrap().contains("enabled"));
-}
 
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn totp_verify_wrong_code_fails(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_bad").await;
+    // A bad upload becomes version 3 without destroying version 2
+    let body = json!({
+        "encrypted_data": B64.encode(b"corrupt"),
+        "nonce": B64.encode([0u8; 24]),
+        "salt": B64.encode([0u8; 16]),
+    });
+    let (status, _) = app.request(Method::PUT, "/api/v1/keys/backup", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
 
-    // Setup
-    app.request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+    let (_, versions) = app
+        .request(Method::GET, "/api/v1/keys/backup/versions", Some(&token), None)
         .await;
+    let versions = versions.as_array().unwrap();
+    assert_eq!(versions.len(), 3);
+    assert_eq!(versions[1]["version"].as_i64(), Some(2));
+    assert_eq!(versions[1]["slot_count"].as_i64(), Some(2));
+    assert_eq!(versions[1]["session_count"].as_i64(), Some(2));
 
-    // Verify with wrong code
-    let (status, _) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/totp/verify",
-            Some(&token),
-            Some(json!({ "code": "000000" })),
-        )
+    // Roll back to version 2
+    let (status, res) = app
+        .request(Method::POST, "/api/v1/keys/backup/versions/2/restore", Some(&token), None)
         .await;
-    assert_eq!(status, StatusCode::UNAUTHORIZED);
-}
-
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn totp_disable_removes_2fa(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_disable").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(res["version"].as_i64(), Some(4));
 
-    // Setup and activate
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
-        .await;
-    let secret_b32 = value["secret"].as_str().unwrap().to_string();
-    let code = generate_totp_code(&secret_b32);
-    app.request(
-        Method::POST,
-        "/api/v1/auth/totp/verify",
-        Some(&token),
-        Some(json!({ "code": code })),
-    )
-    .await;
+    let (status, backup) = app.request(Method::GET, "/api/v1/keys/backup", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(backup["version"].as_i64(), Some(4));
+    assert_eq!(backup["encrypted_data"].as_str(), Some(B64.encode(b"manifest").as_str()));
+    assert!(backup["salt"].is_null());
+    assert_eq!(backup["key_slots"].as_array().unwrap().len(), 2);
 
-    // Disable
-    let (status, value) = app
-        .request(Method::DELETE, "/api/v1/auth/totp", Some(&token), None)
+    let (status, restored) = app
+        .request(Method::GET, "/api/v1/keys/backup/versions/4/sessions", Some(&token), None)
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert!(value["message"].as_str().unwrap().contains("disabled"));
+    assert_eq!(restored.as_array().unwrap().len(), 2);
 
-    // Should be able to login without TOTP code now
-    let (status, _) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/login",
-            None,
-            Some(json!({ "username": "totp_disable", "password": "testpassword123" })),
-        )
+    // Both downloads were recorded
+    let (status, log) = app
+        .request(Method::GET, "/api/v1/keys/backup/access-log", Some(&token), None)
         .await;
     assert_eq!(status, StatusCode::OK);
+    let actions: Vec<_> = log.as_array().unwrap().iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions.len(), 2);
+    assert!(actions.contains(&"download"));
+    assert!(actions.contains(&"sessions"));
+
+    // Only the newest versions are retained (5 in the test config)
+    for _ in 0..3 {
+        let body = json!({
+            "encrypted_data": B64.encode(b"more"),
+            "nonce": B64.encode([1u8; 24]),
+            "salt": B64.encode([2u8; 16]),
+        });
+        app.request(Method::PUT, "/api/v1/keys/backup", Some(&token), Some(body)).await;
+    }
+    let (_, versions) = app
+        .request(Method::GET, "/api/v1/keys/backup/versions", Some(&token), None)
+        .await;
+    let versions = versions.as_array().unwrap();
+    assert_eq!(versions.len(), 5);
+    assert_eq!(versions[0]["version"].as_i64(), Some(7));
+    assert_eq!(versions[4]["version"].as_i64(), Some(3));
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn login_requires_totp_when_enabled(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("totp_login").await;
-
-    // Setup and activate TOTP
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
-        .await;
-    let secret_b32 = value["secret"].as_str().unwrap().to_string();
-    let code = generate_totp_code(&secret_b32);
-    app.request(
-        Method::POST,
-        "/api/v1/auth/totp/verify",
-        Some(&token),
-        Some(json!({ "code": code })),
-    )
-    .await;
+async fn sender_certificate_is_signed_for_device_key(pool: Pool) {
+    use ring::signature::{UnparsedPublicKey, ED25519};
 
-    // Login without TOTP code should return totp_required challenge
-    let (status, value) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/login",
-            None,
-            Some(json!({ "username": "totp_login", "password": "testpassword123" })),
-        )
-        .await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["totp_required"], json!(true));
-    assert!(value.get("access_token").is_none());
+    let app = TestApp::new(pool).await;
+    let (token, user_id) = app.register_user("cert_user").await;
 
-    // Login with valid TOTP code should succeed
-    let code = generate_totp_code(&secret_b32);
-    let (status, _) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/login",
-            None,
-            Some(json!({
-                "username": "totp_login",
-                "password": "testpassword123",
-                "totp_code": code,
-            })),
-        )
+    let (status, cert) = app
+        .request(Method::GET, "/api/v1/keys/sender-certificate", Some(&token), None)
         .await;
     assert_eq!(status, StatusCode::OK);
+    assert_eq!(cert["user_id"].as_str(), Some(user_id.to_string().as_str()));
+
+    let device_id: Uuid = cert["device_id"].as_str().unwrap().parse().unwrap();
+    let identity_key = B64.decode(cert["identity_key"].as_str().unwrap()).unwrap();
+    let expires_at: chrono::DateTime<chrono::Utc> =
+        cert["expires_at"].as_str().unwrap().parse().unwrap();
+    assert!(expires_at > chrono::Utc::now());
+
+    let signed = haven_backend::sealed_sender::certificate_message(
+        user_id,
+        device_id,
+        &identity_key,
+        expires_at,
+    );
+    let public_key = B64.decode(cert["public_key"].as_str().unwrap()).unwrap();
+    let signature = B64.decode(cert["signature"].as_str().unwrap()).unwrap();
+    assert!(UnparsedPublicKey::new(&ED25519, &public_key).verify(&signed, &signature).is_ok());
+
+    // The key comes from its own published keyring
+    let (_, keys) = app.request(Method::GET, "/.well-known/haven-keys.json", None, None).await;
+    let published = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&public_key);
+    let listed = |purpose: &str| {
+        keys[purpose]["keys"].as_array().unwrap().iter().any(|k| k["x"] == published)
+    };
+    assert!(listed("sender_certificate"));
+    assert!(!listed("tree_head"));
 }
 
-/// Generate a valid TOTP code from a base32-encoded secret.
-fn generate_totp_code(secret_b32: &str) -> String {
-    use totp_rs::{Algorithm, Secret, TOTP};
-    let secret = Secret::Encoded(secret_b32.into());
-    let totp = TOTP::new(
-        Algorithm::SHA1,
-        6,
-        1,
-        30,
-        secret.to_bytes().unwrap(),
-        Some("Haven".into()),
-        "test".into(),
-    )
-    .unwrap();
-    totp.generate_current().unwrap()
-}
-
-// ─── Server Update & Nickname ────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn update_server_system_channel(pool: Pool) {
+async fn key_bundle_fetches_are_throttled_per_requester(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("srv_update").await;
-    let server_id = app.create_server(&token, "Update Test").await;
-    let channel_id = app.create_channel(&token, server_id, "announcements").await;
+    let (token_a, _) = app.register_user("drain_attacker").await;
+    let (token_c, _) = app.register_user("drain_bystander").await;
+    let (token_b, user_b) = app.register_user("drain_target").await;
 
-    // Update system channel
-    let uri = format!("/api/v1/servers/{}", server_id);
-    let body = json!({ "system_channel_id": channel_id });
-    let (status, value) = app
-        .request(Method::PATCH, &uri, Some(&token), Some(body))
-        .await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["ok"].as_bool(), Some(true));
+    // The test app allows 5 fetches per requester/target pair
+    let uri = format!("/api/v1/users/{}/keys", user_b);
+    for _ in 0..5 {
+        let (status, _) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
 
-    // Verify via get_server
-    let (_, server) = app.request(Method::GET, &uri, Some(&token), None).await;
-    assert_eq!(
-        server["system_channel_id"].as_str().unwrap(),
-        channel_id.to_string()
-    );
+    // Other requesters and the owner are unaffected
+    let (status, _) = app.request(Method::GET, &uri, Some(&token_c), None).await;
+    assert_eq!(status, StatusCode::OK);
+    for _ in 0..6 {
+        let (status, _) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
 
+// ─── Attachments ────────────────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn update_server_invalid_channel_fails(pool: Pool) {
+async fn upload_attachment(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("srv_bad_ch").await;
-    let server_id = app.create_server(&token, "Bad Channel").await;
+    let (token, _) = app.register_user("attach_user").await;
 
-    // Try setting system channel to a non-existent channel
-    let uri = format!("/api/v1/servers/{}", server_id);
-    let body = json!({ "system_channel_id": Uuid::new_v4() });
-    let (status, _) = app
-        .request(Method::PATCH, &uri, Some(&token), Some(body))
+    let data = vec![0u8; 1024]; // 1KB of zeros
+    let (status, value) = app
+        .request_bytes(Method::POST, "/api/v1/attachments/upload", Some(&token), data)
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert!(value["attachment_id"].as_str().is_some());
+    assert!(value["storage_key"].as_str().is_some());
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn update_server_requires_permission(pool: Pool) {
+async fn upload_empty_attachment_fails(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_owner, _) = app.register_user("srv_perm_own").await;
-    let (token_member, _) = app.register_user("srv_perm_mem").await;
-    let server_id = app.create_server(&token_owner, "Perm Test").await;
-
-    app.invite_and_join(&token_owner, &token_member, server_id).await;
-
-    let channel_id = app.create_channel(&token_owner, server_id, "new-ch").await;
-    let uri = format!("/api/v1/servers/{}", server_id);
-    let body = json!({ "system_channel_id": channel_id });
+    let (token, _) = app.register_user("empty_attach").await;
 
-    // Member without MANAGE_SERVER should be forbidden
     let (status, _) = app
-        .request(Method::PATCH, &uri, Some(&token_member), Some(body))
+        .request_bytes(Method::POST, "/api/v1/attachments/upload", Some(&token), vec![])
         .await;
-    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_ne!(status, StatusCode::OK);
 }
 
+// ─── DM Privacy ─────────────────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn set_and_clear_nickname(pool: Pool) {
+async fn update_dm_privacy(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("nick_user").await;
-    let server_id = app.create_server(&token, "Nick Test").await;
-
-    let uri = format!("/api/v1/servers/{}/nickname", server_id);
+    let (token, _) = app.register_user("dm_priv_user").await;
 
-    // Set nickname
+    let body = json!({ "dm_privacy": "friends_only" });
     let (status, value) = app
         .request(
             Method::PUT,
-            &uri,
+            "/api/v1/users/dm-privacy",
             Some(&token),
-            Some(json!({ "nickname": "CoolNick" })),
+            Some(body),
         )
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["ok"].as_bool(), Some(true));
+    assert_eq!(value["dm_privacy"].as_str(), Some("friends_only"));
+}
 
-    // Verify via member list
-    let mem_uri = format!("/api/v1/servers/{}/members", server_id);
-    let (_, value) = app.request(Method::GET, &mem_uri, Some(&token), None).await;
-    let members = value.as_array().unwrap();
-    assert!(members.iter().any(|m| m["nickname"].as_str() == Some("CoolNick")));
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn update_dm_privacy_invalid_value(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("dm_priv_bad").await;
 
-    // Clear nickname
+    let body = json!({ "dm_privacy": "invalid_value" });
     let (status, _) = app
         .request(
             Method::PUT,
-            &uri,
+            "/api/v1/users/dm-privacy",
             Some(&token),
-            Some(json!({ "nickname": null })),
+            Some(body),
         )
         .await;
-    assert_eq!(status, StatusCode::OK);
+    assert_ne!(status, StatusCode::OK);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn nickname_too_long_fails(pool: Pool) {
+async fn update_presence_privacy(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("nick_long").await;
-    let server_id = app.create_server(&token, "Nick Long").await;
+    let (token, _) = app.register_user("presence_priv_user").await;
+
+    let (status, value) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/presence-privacy",
+            Some(&token),
+            Some(json!({ "presence_privacy": "friends_only" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["presence_privacy"].as_str(), Some("friends_only"));
 
-    let uri = format!("/api/v1/servers/{}/nickname", server_id);
-    let long_nick = "a".repeat(33);
     let (status, _) = app
         .request(
             Method::PUT,
-            &uri,
+            "/api/v1/users/presence-privacy",
             Some(&token),
-            Some(json!({ "nickname": long_nick })),
+            Some(json!({ "presence_privacy": "server_members" })),
         )
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_my_permissions(pool: Pool) {
+async fn dm_friends_only_creates_pending_dm(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("perm_me").await;
-    let server_id = app.create_server(&token, "Perm Check").await;
+    let (token_a, _) = app.register_user("dm_fo_a").await;
+    let (token_b, user_b) = app.register_user("dm_fo_b").await;
 
-    let uri = format!("/api/v1/servers/{}/members/@me/permissions", server_id);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["is_owner"].as_bool(), Some(true));
-    assert!(value["permissions"].as_str().is_some());
+    // B sets friends_only
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "friends_only" })),
+    )
+    .await;
+
+    // A (not a friend) creates DM with B — should be pending
+    use base64::Engine;
+    let b64 = &base64::engine::general_purpose::STANDARD;
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": b64.encode(b"dm-meta")
+    });
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["dm_status"].as_str(), Some("pending"));
 }
 
-// ─── Channel Reorder ─────────────────────────────────
+// ─── Friends Extended ───────────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn reorder_channels(pool: Pool) {
+async fn remove_friend(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("ch_reorder").await;
-    let server_id = app.create_server(&token, "Reorder Ch").await;
+    let (token_a, _) = app.register_user("remove_a").await;
+    let (token_b, _) = app.register_user("remove_b").await;
 
-    let ch1 = app.create_channel(&token, server_id, "alpha").await;
-    let ch2 = app.create_channel(&token, server_id, "beta").await;
+    let friendship_id = app.make_friends(&token_a, &token_b, "remove_b").await;
 
-    let uri = format!("/api/v1/servers/{}/channels/reorder", server_id);
-    let body = json!({
-        "order": [
-            { "id": ch1, "position": 2, "category_id": null },
-            { "id": ch2, "position": 1, "category_id": null },
-        ]
-    });
+    let uri = format!("/api/v1/friends/{}", friendship_id);
     let (status, _) = app
-        .request(Method::PUT, &uri, Some(&token), Some(body))
+        .request(Method::DELETE, &uri, Some(&token_a), None)
         .await;
     assert_eq!(status, StatusCode::OK);
 
-    // Verify positions changed
-    let list_uri = format!("/api/v1/servers/{}/channels", server_id);
-    let (_, value) = app.request(Method::GET, &list_uri, Some(&token), None).await;
-    let channels = value.as_array().unwrap();
-    let ch1_entry = channels.iter().find(|c| c["id"].as_str().unwrap() == ch1.to_string()).unwrap();
-    assert_eq!(ch1_entry["position"].as_i64(), Some(2));
+    // Friends list should be empty
+    let (_, value) = app
+        .request(Method::GET, "/api/v1/friends", Some(&token_a), None)
+        .await;
+    let friends = value.as_array().unwrap();
+    assert!(friends.is_empty());
 }
 
+// ─── Reactions ──────────────────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn reorder_channels_requires_permission(pool: Pool) {
+async fn get_channel_reactions_empty(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_owner, _) = app.register_user("reord_own").await;
-    let (token_member, _) = app.register_user("reord_mem").await;
-    let server_id = app.create_server(&token_owner, "Reord Perm").await;
-
-    app.invite_and_join(&token_owner, &token_member, server_id).await;
-
-    let ch = app.create_channel(&token_owner, server_id, "ch").await;
+    let (token, _) = app.register_user("react_user").await;
+    let server_id = app.create_server(&token, "React Server").await;
+    let channel_id = app.create_channel(&token, server_id, "general").await;
 
-    let uri = format!("/api/v1/servers/{}/channels/reorder", server_id);
-    let body = json!({ "order": [{ "id": ch, "position": 5, "category_id": null }] });
-    let (status, _) = app
-        .request(Method::PUT, &uri, Some(&token_member), Some(body))
-        .await;
-    assert_eq!(status, StatusCode::FORBIDDEN);
+    let uri = format!("/api/v1/channels/{}/reactions", channel_id);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value.as_array().unwrap().len(), 0);
 }
 
-// ─── Avatar Upload/Download ──────────────────────────
+// ─── Presence ───────────────────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn upload_and_download_avatar(pool: Pool) {
+async fn get_presence_returns_offline_for_unknown(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, user_id) = app.register_user("avatar_user").await;
+    let random_id = Uuid::new_v4();
+    let uri = format!("/api/v1/presence?user_ids={}", random_id);
+    let (status, value) = app.request(Method::GET, &uri, None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let entries = value.as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["status"].as_str(), Some("offline"));
+}
 
-    // Upload a fake PNG (starts with PNG magic bytes)
-    let mut png_data = vec![0x89, 0x50, 0x4E, 0x47]; // PNG magic
-    png_data.extend_from_slice(&[0u8; 100]);
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn get_presence_empty_ids_returns_empty(pool: Pool) {
+    let app = TestApp::new(pool).await;
     let (status, value) = app
-        .request_bytes(Method::POST, "/api/v1/users/avatar", Some(&token), png_data)
+        .request(Method::GET, "/api/v1/presence?user_ids=", None, None)
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert!(value["avatar_url"].as_str().is_some());
-
-    // Download avatar (no auth required)
-    let download_uri = format!("/api/v1/users/{}/avatar", user_id);
-    let (status, _) = app.request(Method::GET, &download_uri, None, None).await;
-    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value.as_array().unwrap().len(), 0);
 }
 
+// ─── TOTP (2FA) ──────────────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn upload_empty_avatar_fails(pool: Pool) {
+async fn totp_setup_returns_secret(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("avatar_empty").await;
+    let (token, _) = app.register_user("totp_setup").await;
 
-    let (status, _) = app
-        .request_bytes(Method::POST, "/api/v1/users/avatar", Some(&token), vec![])
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert!(value["secret"].as_str().is_some());
+    assert!(value["qr_code_uri"].as_str().is_some());
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_avatar_no_avatar_returns_404(pool: Pool) {
+async fn totp_setup_twice_fails(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (_, user_id) = app.register_user("no_avatar").await;
+    let (token, _) = app.register_user("totp_dup").await;
 
-    let uri = format!("/api/v1/users/{}/avatar", user_id);
-    let (status, _) = app.request(Method::GET, &uri, None, None).await;
-    assert_eq!(status, StatusCode::NOT_FOUND);
-}
+    // Setup TOTP
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
+    let secret_b32 = value["secret"].as_str().unwrap().to_string();
 
-// ─── Profile Keys ────────────────────────────────────
+    // Verify with a valid code to activate it
+    let code = generate_totp_code(&secret_b32);
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/totp/verify",
+            Some(&token),
+            Some(json!({ "code": code })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Second setup should fail (TOTP already enabled)
+    let (status, _) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn distribute_and_get_profile_keys(pool: Pool) {
+async fn totp_verify_activates_2fa(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, user_a) = app.register_user("pk_sender").await;
-    let (token_b, user_b) = app.register_user("pk_receiver").await;
+    let (token, _) = app.register_user("totp_verify").await;
 
-    // A distributes a profile key to B
-    let body = json!({
-        "distributions": [{
-            "to_user_id": user_b,
-            "encrypted_profile_key": B64.encode(b"fake-encrypted-profile-key-data")
-        }]
-    });
+    // Setup
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
+    let secret_b32 = value["secret"].as_str().unwrap().to_string();
+
+    // Verify with correct code
+    let code = generate_totp_code(&secret_b32);
     let (status, value) = app
         .request(
-            Method::PUT,
-            "/api/v1/users/profile-keys",
-            Some(&token_a),
-            Some(body),
+            Method::POST,
+            "/api/v1/auth/totp/verify",
+            Some(&token),
+            Some(json!({ "code": code })),
         )
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["distributed"].as_i64(), Some(1));
-
-    // B fetches A's profile key
-    let uri = format!("/api/v1/users/{}/profile-key", user_a);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token_b), None).await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["from_user_id"].as_str().unwrap(), user_a.to_string());
-    assert!(value["encrypted_profile_key"].as_str().is_some());
+    assert!(value["message"].as_str().unwrap().contains("enabled"));
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_profile_key_not_found(pool: Pool) {
+async fn totp_verify_wrong_code_fails(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("pk_miss").await;
+    let (token, _) = app.register_user("totp_bad").await;
 
-    // No profile key distributed — should be 404
-    let uri = format!("/api/v1/users/{}/profile-key", Uuid::new_v4());
-    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
-    assert_eq!(status, StatusCode::NOT_FOUND);
-}
+    // Setup
+    app.request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
 
-// ─── DM Requests ─────────────────────────────────────
+    // Verify with wrong code
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/totp/verify",
+            Some(&token),
+            Some(json!({ "code": "000000" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_request_accept_flow(pool: Pool) {
+async fn totp_disable_removes_2fa(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("dmr_a").await;
-    let (token_b, user_b) = app.register_user("dmr_b").await;
+    let (token, _) = app.register_user("totp_disable").await;
 
-    // B sets friends_only
+    // Setup and activate
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
+    let secret_b32 = value["secret"].as_str().unwrap().to_string();
+    let code = generate_totp_code(&secret_b32);
     app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "friends_only" })),
+        Method::POST,
+        "/api/v1/auth/totp/verify",
+        Some(&token),
+        Some(json!({ "code": code })),
     )
     .await;
 
-    // A creates DM with B (not friends) → pending
-    let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": B64.encode(b"dm-meta")
-    });
+    // Disable
     let (status, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .request(Method::DELETE, "/api/v1/auth/totp", Some(&token), None)
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["dm_status"].as_str(), Some("pending"));
-    let channel_id = value["id"].as_str().unwrap();
+    assert!(value["message"].as_str().unwrap().contains("disabled"));
 
-    // B lists DM requests
-    let (status, value) = app
-        .request(Method::GET, "/api/v1/dm/requests", Some(&token_b), None)
+    // Should be able to login without TOTP code now
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({ "username": "totp_disable", "password": "testpassword123" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn login_requires_totp_when_enabled(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("totp_login").await;
+
+    // Setup and activate TOTP
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/auth/totp/setup", Some(&token), None)
+        .await;
+    let secret_b32 = value["secret"].as_str().unwrap().to_string();
+    let code = generate_totp_code(&secret_b32);
+    app.request(
+        Method::POST,
+        "/api/v1/auth/totp/verify",
+        Some(&token),
+        Some(json!({ "code": code })),
+    )
+    .await;
+
+    // Login without TOTP code should return totp_required challenge
+    let (status, value) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({ "username": "totp_login", "password": "testpassword123" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["totp_required"], json!(true));
+    assert!(value.get("access_token").is_none());
+
+    // Login with valid TOTP code should succeed
+    let code = generate_totp_code(&secret_b32);
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({
+                "username": "totp_login",
+                "password": "testpassword123",
+                "totp_code": code,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+/// Generate a valid TOTP code from a base32-encoded secret.
+fn generate_totp_code(secret_b32: &str) -> String {
+    use totp_rs::{Algorithm, Secret, TOTP};
+    let secret = Secret::Encoded(secret_b32.into());
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret.to_bytes().unwrap(),
+        Some("Haven".into()),
+        "test".into(),
+    )
+    .unwrap();
+    totp.generate_current().unwrap()
+}
+
+// ─── Server Update & Nickname ────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn update_server_system_channel(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("srv_update").await;
+    let server_id = app.create_server(&token, "Update Test").await;
+    let channel_id = app.create_channel(&token, server_id, "announcements").await;
+
+    // Update system channel
+    let uri = format!("/api/v1/servers/{}", server_id);
+    let body = json!({ "system_channel_id": channel_id });
+    let (status, value) = app
+        .request(Method::PATCH, &uri, Some(&token), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["ok"].as_bool(), Some(true));
+
+    // Verify via get_server
+    let (_, server) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(
+        server["system_channel_id"].as_str().unwrap(),
+        channel_id.to_string()
+    );
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn update_server_invalid_channel_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("srv_bad_ch").await;
+    let server_id = app.create_server(&token, "Bad Channel").await;
+
+    // Try setting system channel to a non-existent channel
+    let uri = format!("/api/v1/servers/{}", server_id);
+    let body = json!({ "system_channel_id": Uuid::new_v4() });
+    let (status, _) = app
+        .request(Method::PATCH, &uri, Some(&token), Some(body))
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn update_server_requires_permission(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_owner, _) = app.register_user("srv_perm_own").await;
+    let (token_member, _) = app.register_user("srv_perm_mem").await;
+    let server_id = app.create_server(&token_owner, "Perm Test").await;
+
+    app.invite_and_join(&token_owner, &token_member, server_id).await;
+
+    let channel_id = app.create_channel(&token_owner, server_id, "new-ch").await;
+    let uri = format!("/api/v1/servers/{}", server_id);
+    let body = json!({ "system_channel_id": channel_id });
+
+    // Member without MANAGE_SERVER should be forbidden
+    let (status, _) = app
+        .request(Method::PATCH, &uri, Some(&token_member), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn server_key_rotation_keeps_old_epochs(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let b64 = &base64::engine::general_purpose::STANDARD;
+    let (token_owner, owner_id) = app.register_user("skey_owner").await;
+    let (token_member, member_id) = app.register_user("skey_member").await;
+    let server_id = app.create_server(&token_owner, "Key Epochs").await;
+    app.invite_and_join(&token_owner, &token_member, server_id).await;
+
+    let uri = format!("/api/v1/servers/{}/key-epochs", server_id);
+    let wraps = |epoch: u8| {
+        json!([
+            { "user_id": owner_id, "encrypted_key": b64.encode([epoch; 48]) },
+            { "user_id": member_id, "encrypted_key": b64.encode([epoch + 100; 48]) },
+        ])
+    };
+
+    // A new epoch must cover every member and needs MANAGE_SERVER
+    let partial = json!({ "epoch": 1, "wraps": [{ "user_id": owner_id, "encrypted_key": b64.encode([1u8; 48]) }] });
+    let (status, _) = app.request(Method::PUT, &uri, Some(&token_owner), Some(partial)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = app
+        .request(Method::PUT, &uri, Some(&token_member), Some(json!({ "epoch": 1, "wraps": wraps(1) })))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    for epoch in 1..=2u8 {
+        let (status, value) = app
+            .request(Method::PUT, &uri, Some(&token_owner), Some(json!({ "epoch": epoch, "wraps": wraps(epoch) })))
+            .await;
+        assert_eq!(status, StatusCode::OK, "Rotation failed: {}", value);
+    }
+
+    // Skipping or replaying an epoch conflicts
+    let (status, _) = app
+        .request(Method::PUT, &uri, Some(&token_owner), Some(json!({ "epoch": 4, "wraps": wraps(4) })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // The member still reads every epoch's wrap
+    let (status, value) = app.request(Method::GET, &uri, Some(&token_member), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["key_epoch"], 2);
+    let epochs: Vec<i64> = value["wraps"].as_array().unwrap().iter().map(|w| w["epoch"].as_i64().unwrap()).collect();
+    assert_eq!(epochs, vec![1, 2]);
+    assert_eq!(value["wraps"][0]["encrypted_key"], b64.encode([101u8; 48]));
+
+    // Metadata encrypted under a stale epoch is rejected and stamped otherwise
+    let server_uri = format!("/api/v1/servers/{}", server_id);
+    let meta = b64.encode(b"renamed");
+    let (status, _) = app
+        .request(Method::PATCH, &server_uri, Some(&token_owner), Some(json!({ "encrypted_meta": meta, "meta_epoch": 1 })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = app
+        .request(Method::PATCH, &server_uri, Some(&token_owner), Some(json!({ "encrypted_meta": meta, "meta_epoch": 2 })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, server) = app.request(Method::GET, &server_uri, Some(&token_owner), None).await;
+    assert_eq!(server["key_epoch"], 2);
+    assert_eq!(server["meta_epoch"], 2);
+
+    let channel_id = app.create_channel(&token_owner, server_id, "after-rotation").await;
+    let (_, channels) = app
+        .request(Method::GET, &format!("/api/v1/servers/{}/channels", server_id), Some(&token_owner), None)
+        .await;
+    let channel = channels.as_array().unwrap().iter().find(|c| c["id"] == channel_id.to_string()).unwrap();
+    assert_eq!(channel["meta_epoch"], 2);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn set_and_clear_nickname(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("nick_user").await;
+    let server_id = app.create_server(&token, "Nick Test").await;
+
+    let uri = format!("/api/v1/servers/{}/nickname", server_id);
+
+    // Set nickname
+    let (status, value) = app
+        .request(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            Some(json!({ "nickname": "CoolNick" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["ok"].as_bool(), Some(true));
+
+    // Verify via member list
+    let mem_uri = format!("/api/v1/servers/{}/members", server_id);
+    let (_, value) = app.request(Method::GET, &mem_uri, Some(&token), None).await;
+    let members = value.as_array().unwrap();
+    assert!(members.iter().any(|m| m["nickname"].as_str() == Some("CoolNick")));
+
+    // Clear nickname
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            Some(json!({ "nickname": null })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn nickname_too_long_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("nick_long").await;
+    let server_id = app.create_server(&token, "Nick Long").await;
+
+    let uri = format!("/api/v1/servers/{}/nickname", server_id);
+    let long_nick = "a".repeat(33);
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            Some(json!({ "nickname": long_nick })),
+        )
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn get_my_permissions(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("perm_me").await;
+    let server_id = app.create_server(&token, "Perm Check").await;
+
+    let uri = format!("/api/v1/servers/{}/members/@me/permissions", server_id);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["is_owner"].as_bool(), Some(true));
+    assert!(value["permissions"].as_str().is_some());
+}
+
+// ─── Channel Reorder ─────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn reorder_channels(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("ch_reorder").await;
+    let server_id = app.create_server(&token, "Reorder Ch").await;
+
+    let ch1 = app.create_channel(&token, server_id, "alpha").await;
+    let ch2 = app.create_channel(&token, server_id, "beta").await;
+
+    let uri = format!("/api/v1/servers/{}/channels/reorder", server_id);
+    let body = json!({
+        "order": [
+            { "id": ch1, "position": 2, "category_id": null },
+            { "id": ch2, "position": 1, "category_id": null },
+        ]
+    });
+    let (status, _) = app
+        .request(Method::PUT, &uri, Some(&token), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Verify positions changed
+    let list_uri = format!("/api/v1/servers/{}/channels", server_id);
+    let (_, value) = app.request(Method::GET, &list_uri, Some(&token), None).await;
+    let channels = value.as_array().unwrap();
+    let ch1_entry = channels.iter().find(|c| c["id"].as_str().unwrap() == ch1.to_string()).unwrap();
+    assert_eq!(ch1_entry["position"].as_i64(), Some(2));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn reorder_channels_requires_permission(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_owner, _) = app.register_user("reord_own").await;
+    let (token_member, _) = app.register_user("reord_mem").await;
+    let server_id = app.create_server(&token_owner, "Reord Perm").await;
+
+    app.invite_and_join(&token_owner, &token_member, server_id).await;
+
+    let ch = app.create_channel(&token_owner, server_id, "ch").await;
+
+    let uri = format!("/api/v1/servers/{}/channels/reorder", server_id);
+    let body = json!({ "order": [{ "id": ch, "position": 5, "category_id": null }] });
+    let (status, _) = app
+        .request(Method::PUT, &uri, Some(&token_member), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// ─── Avatar Upload/Download ──────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn upload_and_download_avatar(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, user_id) = app.register_user("avatar_user").await;
+
+    // Upload a fake PNG (starts with PNG magic bytes)
+    let mut png_data = vec![0x89, 0x50, 0x4E, 0x47]; // PNG magic
+    png_data.extend_from_slice(&[0u8; 100]);
+    let (status, value) = app
+        .request_bytes(Method::POST, "/api/v1/users/avatar", Some(&token), png_data)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(value["avatar_url"].as_str().is_some());
+
+    // Download avatar (no auth required)
+    let download_uri = format!("/api/v1/users/{}/avatar", user_id);
+    let (status, _) = app.request(Method::GET, &download_uri, None, None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn upload_empty_avatar_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("avatar_empty").await;
+
+    let (status, _) = app
+        .request_bytes(Method::POST, "/api/v1/users/avatar", Some(&token), vec![])
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn get_avatar_no_avatar_returns_404(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (_, user_id) = app.register_user("no_avatar").await;
+
+    let uri = format!("/api/v1/users/{}/avatar", user_id);
+    let (status, _) = app.request(Method::GET, &uri, None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// ─── Profile Keys ────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn distribute_and_get_profile_keys(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("pk_sender").await;
+    let (token_b, user_b) = app.register_user("pk_receiver").await;
+
+    // A distributes a profile key to B
+    let body = json!({
+        "distributions": [{
+            "to_user_id": user_b,
+            "encrypted_profile_key": B64.encode(b"fake-encrypted-profile-key-data")
+        }]
+    });
+    let (status, value) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/profile-keys",
+            Some(&token_a),
+            Some(body),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["distributed"].as_i64(), Some(1));
+
+    // B fetches A's profile key
+    let uri = format!("/api/v1/users/{}/profile-key", user_a);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["from_user_id"].as_str().unwrap(), user_a.to_string());
+    assert!(value["encrypted_profile_key"].as_str().is_some());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn get_profile_key_not_found(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("pk_miss").await;
+
+    // No profile key distributed — should be 404
+    let uri = format!("/api/v1/users/{}/profile-key", Uuid::new_v4());
+    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// ─── DM Requests ─────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn dm_request_accept_flow(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("dmr_a").await;
+    let (token_b, user_b) = app.register_user("dmr_b").await;
+
+    // B sets friends_only
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "friends_only" })),
+    )
+    .await;
+
+    // A creates DM with B (not friends) → pending
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": B64.encode(b"dm-meta")
+    });
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["dm_status"].as_str(), Some("pending"));
+    let channel_id = value["id"].as_str().unwrap();
+
+    // B lists DM requests
+    let (status, value) = app
+        .request(Method::GET, "/api/v1/dm/requests", Some(&token_b), None)
         .await;
     assert_eq!(status, StatusCode::OK);
     let requests = value.as_array().unwrap();
     assert_eq!(requests.len(), 1);
     assert_eq!(requests[0]["id"].as_str().unwrap(), channel_id);
 
-    // B accepts the DM request
-    let uri = format!("/api/v1/dm/{}/request", channel_id);
+    // B accepts the DM request
+    let uri = format!("/api/v1/dm/{}/request", channel_id);
+    let (status, value) = app
+        .request(
+            Method::POST,
+            &uri,
+            Some(&token_b),
+            Some(json!({ "action": "accept" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(value["message"].as_str().unwrap().contains("accepted"));
+
+    // DM requests should be empty now
+    let (_, value) = app
+        .request(Method::GET, "/api/v1/dm/requests", Some(&token_b), None)
+        .await;
+    assert_eq!(value.as_array().unwrap().len(), 0);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn dm_request_decline_flow(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("dmrd_a").await;
+    let (token_b, user_b) = app.register_user("dmrd_b").await;
+
+    // B sets friends_only
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "friends_only" })),
+    )
+    .await;
+
+    // A creates pending DM
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": B64.encode(b"dm-meta")
+    });
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    let channel_id = value["id"].as_str().unwrap();
+
+    // B declines
+    let uri = format!("/api/v1/dm/{}/request", channel_id);
+    let (status, value) = app
+        .request(
+            Method::POST,
+            &uri,
+            Some(&token_b),
+            Some(json!({ "action": "decline" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(value["message"].as_str().unwrap().contains("declined"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn dm_request_invalid_action(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("dmri_a").await;
+    let (token_b, user_b) = app.register_user("dmri_b").await;
+
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "friends_only" })),
+    )
+    .await;
+
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": B64.encode(b"dm-meta")
+    });
+    let (_, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    let channel_id = value["id"].as_str().unwrap();
+
+    let uri = format!("/api/v1/dm/{}/request", channel_id);
+    let (status, _) = app
+        .request(
+            Method::POST,
+            &uri,
+            Some(&token_b),
+            Some(json!({ "action": "invalid" })),
+        )
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+// ─── Sender Keys ─────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn distribute_and_get_sender_keys(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("sk_sender").await;
+    let (token_b, user_b) = app.register_user("sk_receiver").await;
+    let server_id = app.create_server(&token_a, "SK Test").await;
+
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+
+    let channel_id = app.create_channel(&token_a, server_id, "encrypted-ch").await;
+
+    // B joins the channel
+    let join_uri = format!("/api/v1/channels/{}/join", channel_id);
+    app.request(Method::POST, &join_uri, Some(&token_b), None).await;
+
+    let dist_id = Uuid::new_v4();
+
+    // A distributes sender keys to B
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let body = json!({
+        "distributions": [{
+            "to_user_id": user_b,
+            "distribution_id": dist_id,
+            "encrypted_skdm": B64.encode(b"fake-sender-key-distribution-message")
+        }]
+    });
+    let (status, value) = app
+        .request(Method::POST, &uri, Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["distributed"].as_i64(), Some(1));
+
+    // B fetches sender keys for the channel
+    let (status, value) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let keys = value.as_array().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0]["from_user_id"].as_str().unwrap(), user_a.to_string());
+    assert_eq!(keys[0]["distribution_id"].as_str().unwrap(), dist_id.to_string());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn distribute_sender_keys_empty_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("sk_empty").await;
+    let server_id = app.create_server(&token, "SK Empty").await;
+    let channel_id = app.create_channel(&token, server_id, "ch").await;
+
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let body = json!({ "distributions": [] });
+    let (status, _) = app
+        .request(Method::POST, &uri, Some(&token), Some(body))
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn get_channel_member_keys(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("mk_a").await;
+    let (token_b, user_b) = app.register_user("mk_b").await;
+    let server_id = app.create_server(&token_a, "MK Test").await;
+
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+
+    let channel_id = app.create_channel(&token_a, server_id, "keys-ch").await;
+
+    // B joins the channel
+    let join_uri = format!("/api/v1/channels/{}/join", channel_id);
+    app.request(Method::POST, &join_uri, Some(&token_b), None).await;
+
+    // A fetches member keys (should exclude A, include B)
+    let uri = format!("/api/v1/channels/{}/members/keys", channel_id);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let keys = value.as_array().unwrap();
+    assert!(keys.iter().any(|k| k["user_id"].as_str().unwrap() == user_b.to_string()));
+    assert!(keys.iter().all(|k| k["identity_key"].as_str().is_some()));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn sender_keys_non_member_forbidden(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("sk_own").await;
+    let (token_b, _) = app.register_user("sk_outsider").await;
+    let server_id = app.create_server(&token_a, "SK Forbid").await;
+    let channel_id = app.create_channel(&token_a, server_id, "ch").await;
+
+    // B is not a member — should be forbidden
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let (status, _) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn mls_group_orders_commits_and_delivers_welcomes(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("mls_a").await;
+    let (token_b, user_b) = app.register_user("mls_b").await;
+    let server_id = app.create_server(&token_a, "MLS").await;
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let channel_id = app.create_channel(&token_a, server_id, "mls-ch").await;
+
+    // B publishes KeyPackages; A claims one to add B
+    let body = json!({ "key_packages": [B64.encode(b"kp-1"), B64.encode(b"kp-2")] });
+    let (status, uploaded) = app
+        .request(Method::POST, "/api/v1/keys/mls/key-packages", Some(&token_b), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(uploaded["total_available"].as_i64(), Some(2));
+
+    let claim_uri = format!("/api/v1/users/{}/mls/key-packages", user_b);
+    let (status, claimed) = app.request(Method::POST, &claim_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let key_packages = claimed["key_packages"].as_array().unwrap();
+    assert_eq!(key_packages.len(), 1);
+    assert_eq!(key_packages[0]["key_package"].as_str(), Some(B64.encode(b"kp-1").as_str()));
+    let device_b = key_packages[0]["device_id"].as_str().unwrap().to_string();
+
+    // Only members with MANAGE_CHANNELS can switch a server channel to MLS
+    let group_uri = format!("/api/v1/channels/{}/mls", channel_id);
+    let (status, _) = app.request(Method::POST, &group_uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, group) = app.request(Method::POST, &group_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(group["epoch"].as_i64(), Some(0));
+
+    // A's commit at epoch 0 adds B; a concurrent commit for the same epoch loses
+    let handshake_uri = format!("/api/v1/channels/{}/mls/handshake", channel_id);
+    let commit = json!({
+        "epoch": 0,
+        "kind": "commit",
+        "message": B64.encode(b"commit-add-b"),
+        "welcome": B64.encode(b"welcome-b"),
+        "welcome_device_ids": [device_b],
+    });
+    let (status, accepted) = app
+        .request(Method::POST, &handshake_uri, Some(&token_a), Some(commit))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(accepted["seq"].as_i64(), Some(0));
+
+    let stale = json!({ "epoch": 0, "kind": "commit", "message": B64.encode(b"late") });
+    let (status, _) = app
+        .request(Method::POST, &handshake_uri, Some(&token_b), Some(stale))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (_, group) = app.request(Method::GET, &group_uri, Some(&token_b), None).await;
+    assert_eq!(group["epoch"].as_i64(), Some(1));
+
+    let (status, stream) = app
+        .request(Method::GET, &format!("{}?after=-1", handshake_uri), Some(&token_b), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let stream = stream.as_array().unwrap();
+    assert_eq!(stream.len(), 1);
+    assert_eq!(stream[0]["kind"], "commit");
+
+    // B picks up the Welcome and acknowledges it
+    let (status, welcomes) = app
+        .request(Method::GET, "/api/v1/keys/mls/welcomes", Some(&token_b), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let welcomes = welcomes.as_array().unwrap();
+    assert_eq!(welcomes.len(), 1);
+    assert_eq!(welcomes[0]["epoch"].as_i64(), Some(1));
+    let ack_uri = format!("/api/v1/keys/mls/welcomes/{}", welcomes[0]["id"].as_str().unwrap());
+    let (status, _) = app.request(Method::DELETE, &ack_uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, welcomes) = app
+        .request(Method::GET, "/api/v1/keys/mls/welcomes", Some(&token_b), None)
+        .await;
+    assert!(welcomes.as_array().unwrap().is_empty());
+
+    // Sender keys are off once the channel uses MLS
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let body = json!({
+        "distributions": [{
+            "to_user_id": user_b,
+            "distribution_id": Uuid::new_v4(),
+            "encrypted_skdm": B64.encode(b"skdm")
+        }]
+    });
+    let (status, _) = app.request(Method::POST, &uri, Some(&token_a), Some(body)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// ─── User Profile Extended ───────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn profile_shows_friendship_status(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("prof_a").await;
+    let (token_b, user_b) = app.register_user("prof_b").await;
+
+    // Before friendship: not friends
+    let uri = format!("/api/v1/users/{}/profile", user_b);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(value["is_friend"].as_bool(), Some(false));
+
+    // Send friend request
+    app.request(
+        Method::POST,
+        "/api/v1/friends/request",
+        Some(&token_a),
+        Some(json!({ "username": "prof_b" })),
+    )
+    .await;
+
+    // Should show pending outgoing
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(
+        value["friend_request_status"].as_str(),
+        Some("pending_outgoing")
+    );
+
+    // After accept: is_friend = true
+    let friendship_id = value["friendship_id"].as_str().unwrap();
+    let accept_uri = format!("/api/v1/friends/{}/accept", friendship_id);
+    app.request(Method::POST, &accept_uri, Some(&token_b), None).await;
+
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(value["is_friend"].as_bool(), Some(true));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn profile_shows_blocked_status(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("prof_blk_a").await;
+    let (_, user_b) = app.register_user("prof_blk_b").await;
+
+    // Block B
+    let block_uri = format!("/api/v1/users/{}/block", user_b);
+    app.request(Method::POST, &block_uri, Some(&token_a), None).await;
+
+    // Profile should show blocked
+    let uri = format!("/api/v1/users/{}/profile", user_b);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(value["is_blocked"].as_bool(), Some(true));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn profile_shows_mutual_friends(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("mut_a").await;
+    let (token_b, user_b) = app.register_user("mut_b").await;
+    let (token_c, _) = app.register_user("mut_c").await;
+
+    // A and C are friends, B and C are friends
+    app.make_friends(&token_a, &token_c, "mut_c").await;
+    app.make_friends(&token_b, &token_c, "mut_c").await;
+
+    // A views B's profile — mutual friend should be C
+    let uri = format!("/api/v1/users/{}/profile", user_b);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(value["mutual_friend_count"].as_i64(), Some(1));
+    let mutuals = value["mutual_friends"].as_array().unwrap();
+    assert_eq!(mutuals.len(), 1);
+    assert_eq!(mutuals[0]["username"].as_str(), Some("mut_c"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn profile_with_server_roles(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_owner, _) = app.register_user("role_prof_own").await;
+    let (token_member, member_id) = app.register_user("role_prof_mem").await;
+    let server_id = app.create_server(&token_owner, "Role Prof").await;
+
+    app.invite_and_join(&token_owner, &token_member, server_id).await;
+
+    // Create and assign a role
+    let roles_uri = format!("/api/v1/servers/{}/roles", server_id);
+    let (_, role_val) = app
+        .request(
+            Method::POST,
+            &roles_uri,
+            Some(&token_owner),
+            Some(json!({ "name": "Tester", "color": "#0000ff", "position": 1 })),
+        )
+        .await;
+    let role_id = role_val["id"].as_str().unwrap();
+
+    let assign_uri = format!("/api/v1/servers/{}/members/{}/roles", server_id, member_id);
+    app.request(
+        Method::PUT,
+        &assign_uri,
+        Some(&token_owner),
+        Some(json!({ "role_id": role_id })),
+    )
+    .await;
+
+    // Get profile with server_id — should include roles
+    let uri = format!("/api/v1/users/{}/profile?server_id={}", member_id, server_id);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token_owner), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let roles = value["roles"].as_array().unwrap();
+    assert!(roles.iter().any(|r| r["name"].as_str() == Some("Tester")));
+}
+
+// ─── Auth Edge Cases ─────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn change_password_revokes_refresh_tokens(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("pw_revoke").await;
+
+    // Get a refresh token
+    let (_, refresh, _) = app.login_user("pw_revoke").await;
+
+    // Change password
+    app.request(
+        Method::PUT,
+        "/api/v1/auth/password",
+        Some(&token),
+        Some(json!({
+            "current_password": "testpassword123",
+            "new_password": "brandnewpass456"
+        })),
+    )
+    .await;
+
+    // Old refresh token should be invalid
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/refresh",
+            None,
+            Some(json!({ "refresh_token": refresh })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn change_password_too_short_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("pw_short").await;
+
+    let body = json!({
+        "current_password": "testpassword123",
+        "new_password": "short"
+    });
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/auth/password", Some(&token), Some(body))
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+// ─── Group DM Extended ───────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn add_member_to_group_dm(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("grp_add_a").await;
+    let (token_b, user_b) = app.register_user("grp_add_b").await;
+    let (token_c, user_c) = app.register_user("grp_add_c").await;
+    let (token_d, user_d) = app.register_user("grp_add_d").await;
+
+    app.make_friends(&token_a, &token_b, "grp_add_b").await;
+    app.make_friends(&token_a, &token_c, "grp_add_c").await;
+    app.make_friends(&token_a, &token_d, "grp_add_d").await;
+
+    // Create group DM with B and C
+    let body = json!({
+        "member_ids": [user_b, user_c],
+        "encrypted_meta": B64.encode(b"group-meta")
+    });
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/dm/group", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let channel_id = value["id"].as_str().unwrap();
+
+    // Add D to the group
+    let uri = format!("/api/v1/channels/{}/members", channel_id);
+    let (status, value) = app
+        .request(
+            Method::POST,
+            &uri,
+            Some(&token_a),
+            Some(json!({ "user_id": user_d })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["added"].as_bool(), Some(true));
+
+    // Verify 4 members
+    let mem_uri = format!("/api/v1/channels/{}/members", channel_id);
+    let (_, value) = app.request(Method::GET, &mem_uri, Some(&token_a), None).await;
+    assert_eq!(value.as_array().unwrap().len(), 4);
+}
+
+// ─── Message Search ──────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn search_messages(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("search_user").await;
+    let server_id = app.create_server(&token, "Search Server").await;
+    let channel_id = app.create_channel(&token, server_id, "general").await;
+
+    // Send a few messages
+    app.send_message(&token, channel_id).await;
+    app.send_message(&token, channel_id).await;
+
+    // Get messages with limit
+    let uri = format!("/api/v1/channels/{}/messages?limit=1", channel_id);
+    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let messages = value.as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+}
+
+// ─── DM with Server Members Privacy ─────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn dm_server_members_privacy(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("sm_a").await;
+    let (token_b, user_b) = app.register_user("sm_b").await;
+
+    // B sets server_members privacy
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "server_members" })),
+    )
+    .await;
+
+    // A and B are not in a shared server and not friends → pending
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": B64.encode(b"dm-meta")
+    });
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["dm_status"].as_str(), Some("pending"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn dm_server_members_shared_server_is_active(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("sms_a").await;
+    let (token_b, user_b) = app.register_user("sms_b").await;
+
+    // B sets server_members privacy
+    app.request(
+        Method::PUT,
+        "/api/v1/users/dm-privacy",
+        Some(&token_b),
+        Some(json!({ "dm_privacy": "server_members" })),
+    )
+    .await;
+
+    // Put both in a server
+    let server_id = app.create_server(&token_a, "Shared").await;
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+
+    // Now DM should be active (shared server)
+    let body = json!({
+        "target_user_id": user_b,
+        "encrypted_meta": B64.encode(b"dm-meta")
+    });
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["dm_status"].as_str(), Some("active"));
+}
+
+// ─── Attachment Download ─────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn download_attachment_not_found(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("att_404").await;
+
+    let uri = format!("/api/v1/attachments/{}", Uuid::new_v4());
+    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// ─── Prekey Count ────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn prekey_count_zero_initially(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("pk_zero").await;
+
     let (status, value) = app
+        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["count"].as_i64(), Some(0));
+}
+
+// ─── Friend Request Edge Cases ───────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn friend_request_to_self_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("self_friend").await;
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/friends/request",
+            Some(&token),
+            Some(json!({ "username": "self_friend" })),
+        )
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn friend_request_when_already_friends_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("alr_a").await;
+    let (token_b, _) = app.register_user("alr_b").await;
+
+    app.make_friends(&token_a, &token_b, "alr_b").await;
+
+    let (status, _) = app
         .request(
             Method::POST,
-            &uri,
-            Some(&token_b),
-            Some(json!({ "action": "accept" })),
+            "/api/v1/friends/request",
+            Some(&token_a),
+            Some(json!({ "username": "alr_b" })),
         )
         .await;
-    assert_eq!(status, StatusCode::OK);
-    assert!(value["message"].as_str().unwrap().contains("accepted"));
+    assert_ne!(status, StatusCode::OK);
+}
 
-    // DM requests should be empty now
-    let (_, value) = app
-        .request(Method::GET, "/api/v1/dm/requests", Some(&token_b), None)
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn friend_request_unknown_user_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("fr_unknown").await;
+
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/friends/request",
+            Some(&token),
+            Some(json!({ "username": "nobody_exists_here" })),
+        )
         .await;
-    assert_eq!(value.as_array().unwrap().len(), 0);
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
+// ─── Invite Edge Cases ───────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_request_decline_flow(pool: Pool) {
+async fn join_invite_already_member(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("dmrd_a").await;
-    let (token_b, user_b) = app.register_user("dmrd_b").await;
+    let (token_owner, _) = app.register_user("inv_alr_own").await;
+    let (token_member, _) = app.register_user("inv_alr_mem").await;
+    let server_id = app.create_server(&token_owner, "Already Member").await;
 
-    // B sets friends_only
-    app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "friends_only" })),
-    )
-    .await;
+    let code = app.invite_and_join(&token_owner, &token_member, server_id).await;
 
-    // A creates pending DM
-    let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": B64.encode(b"dm-meta")
-    });
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+    // Try to join again
+    let join_uri = format!("/api/v1/invites/{}/join", code);
+    let (status, _) = app
+        .request(Method::POST, &join_uri, Some(&token_member), None)
         .await;
-    let channel_id = value["id"].as_str().unwrap();
+    assert_ne!(status, StatusCode::OK);
+}
 
-    // B declines
-    let uri = format!("/api/v1/dm/{}/request", channel_id);
-    let (status, value) = app
-        .request(
-            Method::POST,
-            &uri,
-            Some(&token_b),
-            Some(json!({ "action": "decline" })),
-        )
+// ─── Kick / Ban Edge Cases ───────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn kick_member_by_non_owner_fails(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_owner, _) = app.register_user("kick_p_own").await;
+    let (token_member, _) = app.register_user("kick_p_mem").await;
+    let (_, target_id) = app.register_user("kick_p_tgt").await;
+    let server_id = app.create_server(&token_owner, "Kick Perm").await;
+
+    app.invite_and_join(&token_owner, &token_member, server_id).await;
+
+    // Member (without KICK_MEMBERS) tries to kick — should be forbidden
+    let kick_uri = format!("/api/v1/servers/{}/members/{}", server_id, target_id);
+    let (status, _) = app
+        .request(Method::DELETE, &kick_uri, Some(&token_member), None)
         .await;
-    assert_eq!(status, StatusCode::OK);
-    assert!(value["message"].as_str().unwrap().contains("declined"));
+    assert_eq!(status, StatusCode::FORBIDDEN);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_request_invalid_action(pool: Pool) {
+async fn cannot_ban_self(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("dmri_a").await;
-    let (token_b, user_b) = app.register_user("dmri_b").await;
+    let (token, user_id) = app.register_user("ban_self").await;
+    let server_id = app.create_server(&token, "Ban Self").await;
 
-    app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "friends_only" })),
-    )
-    .await;
+    let ban_uri = format!("/api/v1/servers/{}/bans/{}", server_id, user_id);
+    let (status, _) = app
+        .request(Method::POST, &ban_uri, Some(&token), Some(json!({})))
+        .await;
+    assert_ne!(status, StatusCode::OK);
+}
+
+// ─── DM Edge Cases ───────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn create_dm_returns_existing_if_exists(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("dup_dm_a").await;
+    let (_, user_b) = app.register_user("dup_dm_b").await;
 
     let body = json!({
         "target_user_id": user_b,
         "encrypted_meta": B64.encode(b"dm-meta")
     });
-    let (_, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
+
+    let (status, value1) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body.clone()))
         .await;
-    let channel_id = value["id"].as_str().unwrap();
+    assert_eq!(status, StatusCode::OK);
+    let dm_id_1 = value1["id"].as_str().unwrap().to_string();
 
-    let uri = format!("/api/v1/dm/{}/request", channel_id);
-    let (status, _) = app
-        .request(
-            Method::POST,
-            &uri,
-            Some(&token_b),
-            Some(json!({ "action": "invalid" })),
-        )
+    let (status, value2) = app
+        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value2["id"].as_str().unwrap(), dm_id_1);
 }
 
-// ─── Sender Keys ─────────────────────────────────────
+// ─── Message Pagination ──────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn distribute_and_get_sender_keys(pool: Pool) {
+async fn get_messages_with_limit_and_pagination(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, user_a) = app.register_user("sk_sender").await;
-    let (token_b, user_b) = app.register_user("sk_receiver").await;
-    let server_id = app.create_server(&token_a, "SK Test").await;
-
-    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let (token, _) = app.register_user("msg_page").await;
+    let server_id = app.create_server(&token, "Page Test").await;
+    let channel_id = app.create_channel(&token, server_id, "ch").await;
 
-    let channel_id = app.create_channel(&token_a, server_id, "encrypted-ch").await;
+    // Send 5 messages
+    for _ in 0..5 {
+        app.send_message(&token, channel_id).await;
+    }
 
-    // B joins the channel
-    let join_uri = format!("/api/v1/channels/{}/join", channel_id);
-    app.request(Method::POST, &join_uri, Some(&token_b), None).await;
+    // Default: all 5
+    let uri = format!("/api/v1/channels/{}/messages", channel_id);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    assert_eq!(value.as_array().unwrap().len(), 5);
 
-    let dist_id = Uuid::new_v4();
+    // Limit to 2
+    let uri2 = format!("/api/v1/channels/{}/messages?limit=2", channel_id);
+    let (status, value) = app.request(Method::GET, &uri2, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value.as_array().unwrap().len(), 2);
 
-    // A distributes sender keys to B
-    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
-    let body = json!({
-        "distributions": [{
-            "to_user_id": user_b,
-            "distribution_id": dist_id,
-            "encrypted_skdm": B64.encode(b"fake-sender-key-distribution-message")
-        }]
-    });
-    let (status, value) = app
-        .request(Method::POST, &uri, Some(&token_a), Some(body))
-        .await;
+    // Limit capped at 100 — requesting 200 should still work
+    let uri3 = format!("/api/v1/channels/{}/messages?limit=200", channel_id);
+    let (status, value) = app.request(Method::GET, &uri3, Some(&token), None).await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["distributed"].as_i64(), Some(1));
+    assert_eq!(value.as_array().unwrap().len(), 5); // only 5 messages exist
+}
 
-    // B fetches sender keys for the channel
-    let (status, value) = app.request(Method::GET, &uri, Some(&token_b), None).await;
+// ─── Access Token Revocation ─────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn logout_revokes_access_tokens_immediately(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("revoke_logout").await;
+    let (other_token, _, _) = app.login_user("revoke_logout").await;
+
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/logout", Some(&token), Some(json!({}))).await;
     assert_eq!(status, StatusCode::OK);
-    let keys = value.as_array().unwrap();
-    assert_eq!(keys.len(), 1);
-    assert_eq!(keys[0]["from_user_id"].as_str().unwrap(), user_a.to_string());
-    assert_eq!(keys[0]["distribution_id"].as_str().unwrap(), dist_id.to_string());
+
+    // Both sessions' unexpired access tokens stop working at once
+    for t in [&token, &other_token] {
+        let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(t), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn distribute_sender_keys_empty_fails(pool: Pool) {
+async fn revoking_a_session_only_invalidates_its_tokens(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("sk_empty").await;
-    let server_id = app.create_server(&token, "SK Empty").await;
-    let channel_id = app.create_channel(&token, server_id, "ch").await;
+    let (token_a, _) = app.register_user("revoke_one").await;
+    let (token_b, refresh_b, _) = app.login_user("revoke_one").await;
+    let family_b = common::token_family(&token_b);
+    assert_ne!(common::token_family(&token_a), family_b);
 
-    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
-    let body = json!({ "distributions": [] });
-    let (status, _) = app
-        .request(Method::POST, &uri, Some(&token), Some(body))
+    // A refreshed token stays in its session
+    let (status, refreshed) = app
+        .request(Method::POST, "/api/v1/auth/refresh", None, Some(json!({ "refresh_token": refresh_b })))
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    let token_b2 = refreshed["access_token"].as_str().unwrap().to_string();
+    assert_eq!(common::token_family(&token_b2), family_b);
+
+    let uri = format!("/api/v1/auth/sessions/{}", family_b);
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    for t in [&token_b, &token_b2] {
+        let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(t), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+// ─── Anti-Abuse ──────────────────────────────────────
+
+fn registration_body(username: &str, challenge: &serde_json::Value) -> serde_json::Value {
+    let challenge_str = challenge["challenge"].as_str().unwrap();
+    let nonce = common::solve_pow(challenge_str, challenge["difficulty"].as_u64().unwrap() as u32);
+    json!({
+        "username": username,
+        "password": "testpassword123",
+        "identity_key": B64.encode([0u8; 32]),
+        "signed_prekey": B64.encode([0u8; 32]),
+        "signed_prekey_signature": B64.encode([0u8; 64]),
+        "one_time_prekeys": [],
+        "pow_challenge": challenge_str,
+        "pow_nonce": nonce,
+    })
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_channel_member_keys(pool: Pool) {
+async fn registration_checks_configured_captcha(pool: Pool) {
+    let verify_url = common::mock_captcha::start().await;
+    let app = TestApp::with_config(pool, move |config| {
+        config.captcha_provider = "hcaptcha".into();
+        config.captcha_site_key = common::mock_captcha::SITE_KEY.into();
+        config.captcha_secret_key = common::mock_captcha::SECRET_KEY.into();
+        config.captcha_verify_url = verify_url;
+    })
+    .await;
+
+    let (status, challenge) = app.request(Method::GET, "/api/v1/auth/challenge", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        challenge["captcha"],
+        json!({ "provider": "hcaptcha", "site_key": common::mock_captcha::SITE_KEY })
+    );
+    assert!(challenge.get("turnstile_site_key").is_none());
+
+    for token in [None, Some("captcha-fail")] {
+        let (_, challenge) = app.request(Method::GET, "/api/v1/auth/challenge", None, None).await;
+        let mut body = registration_body("captcha_user", &challenge);
+        if let Some(token) = token {
+            body["captcha_token"] = json!(token);
+        }
+        let (status, _) = app.request(Method::POST, "/api/v1/auth/register", None, Some(body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    let (_, challenge) = app.request(Method::GET, "/api/v1/auth/challenge", None, None).await;
+    let mut body = registration_body("captcha_user", &challenge);
+    body["captcha_token"] = json!(common::mock_captcha::PASS_TOKEN);
+    let (status, value) = app.request(Method::POST, "/api/v1/auth/register", None, Some(body)).await;
+    assert_eq!(status, StatusCode::OK, "{}", value);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn pow_challenge_is_single_use(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("mk_a").await;
-    let (token_b, user_b) = app.register_user("mk_b").await;
-    let server_id = app.create_server(&token_a, "MK Test").await;
+    let (_, challenge) = app.request(Method::GET, "/api/v1/auth/challenge", None, None).await;
+    assert_eq!(challenge["difficulty"], 20);
+    let body = registration_body("pow_once", &challenge);
 
-    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/register", None, Some(body.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut replay = body;
+    replay["username"] = json!("pow_twice");
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/register", None, Some(replay)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
 
-    let channel_id = app.create_channel(&token_a, server_id, "keys-ch").await;
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn login_requires_pow_when_failures_spike(pool: Pool) {
+    let app = TestApp::with_config(pool, |config| config.login_pow_failure_threshold = 3).await;
+    app.register_user("pow_login").await;
+
+    let wrong = json!({ "username": "pow_login", "password": "wrong-password" });
+    for _ in 0..3 {
+        let (status, value) = app.request(Method::POST, "/api/v1/auth/login", None, Some(wrong.clone())).await;
+        // Other tests' failures share the counter and may already have tripped it
+        if value.get("pow_required").is_none() {
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
 
-    // B joins the channel
-    let join_uri = format!("/api/v1/channels/{}/join", channel_id);
-    app.request(Method::POST, &join_uri, Some(&token_b), None).await;
+    let login = json!({ "username": "pow_login", "password": "testpassword123" });
+    let (status, value) = app.request(Method::POST, "/api/v1/auth/login", None, Some(login.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["pow_required"], true);
+    assert!(value.get("access_token").is_none());
 
-    // A fetches member keys (should exclude A, include B)
-    let uri = format!("/api/v1/channels/{}/members/keys", channel_id);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    let challenge = value["challenge"]["challenge"].as_str().unwrap();
+    let difficulty = value["challenge"]["difficulty"].as_u64().unwrap() as u32;
+    let mut solved = login;
+    solved["pow_challenge"] = json!(challenge);
+    solved["pow_nonce"] = json!(common::solve_pow(challenge, difficulty));
+    let (status, value) = app.request(Method::POST, "/api/v1/auth/login", None, Some(solved)).await;
     assert_eq!(status, StatusCode::OK);
-    let keys = value.as_array().unwrap();
-    assert!(keys.iter().any(|k| k["user_id"].as_str().unwrap() == user_b.to_string()));
-    assert!(keys.iter().all(|k| k["identity_key"].as_str().is_some()));
+    assert!(value["access_token"].is_string());
+}
+
+// ─── Signing Keys ────────────────────────────────────
+
+fn token_kid(token: &str) -> String {
+    jsonwebtoken::decode_header(token).unwrap().kid.expect("access token without kid")
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn sender_keys_non_member_forbidden(pool: Pool) {
+async fn jwks_verifies_access_tokens(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("sk_own").await;
-    let (token_b, _) = app.register_user("sk_outsider").await;
-    let server_id = app.create_server(&token_a, "SK Forbid").await;
-    let channel_id = app.create_channel(&token_a, server_id, "ch").await;
+    let (token, user_id) = app.register_user("jwks_user").await;
 
-    // B is not a member — should be forbidden
-    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
-    let (status, _) = app.request(Method::GET, &uri, Some(&token_b), None).await;
-    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, jwks) = app.request(Method::GET, "/.well-known/jwks.json", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let jwks: jsonwebtoken::jwk::JwkSet = serde_json::from_value(jwks).unwrap();
+    let jwk = jwks.find(&token_kid(&token)).expect("signing key not published");
+
+    // Anyone holding the JWKS can verify Haven's access tokens
+    let key = jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap();
+    let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::EdDSA);
+    let claims = jsonwebtoken::decode::<serde_json::Value>(&token, &key, &validation).unwrap().claims;
+    assert_eq!(claims["sub"], user_id.to_string());
 }
 
-// ─── User Profile Extended ───────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn profile_shows_friendship_status(pool: Pool) {
+async fn signing_key_rotation_keeps_old_tokens_valid(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("prof_a").await;
-    let (token_b, user_b) = app.register_user("prof_b").await;
-
-    // Before friendship: not friends
-    let uri = format!("/api/v1/users/{}/profile", user_b);
-    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
-    assert_eq!(value["is_friend"].as_bool(), Some(false));
-
-    // Send friend request
-    app.request(
-        Method::POST,
-        "/api/v1/friends/request",
-        Some(&token_a),
-        Some(json!({ "username": "prof_b" })),
-    )
-    .await;
+    let (admin_token, _) = app.register_user("rotate_admin").await;
+    let (user_token, _) = app.register_user("rotate_user").await;
 
-    // Should show pending outgoing
-    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
-    assert_eq!(
-        value["friend_request_status"].as_str(),
-        Some("pending_outgoing")
-    );
+    let (status, _) = app
+        .request(Method::POST, "/api/v1/admin/signing-keys/rotate", Some(&user_token), None)
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
 
-    // After accept: is_friend = true
-    let friendship_id = value["friendship_id"].as_str().unwrap();
-    let accept_uri = format!("/api/v1/friends/{}/accept", friendship_id);
-    app.request(Method::POST, &accept_uri, Some(&token_b), None).await;
+    let (status, keys) = app
+        .request(Method::POST, "/api/v1/admin/signing-keys/rotate", Some(&admin_token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let keys = keys.as_array().unwrap();
+    assert_eq!(keys.len(), 2);
+    // The replaced key verifies until its last token expires
+    let old = keys.iter().find(|k| k["kid"] == token_kid(&admin_token)).unwrap();
+    assert_eq!(old["signing"], false);
+    assert!(old["retires_at"].is_string());
+
+    let (new_token, _, _) = app.login_user("rotate_user").await;
+    assert_ne!(token_kid(&new_token), token_kid(&user_token));
+    for t in [&user_token, &new_token] {
+        let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(t), None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
 
-    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
-    assert_eq!(value["is_friend"].as_bool(), Some(true));
+    let (_, jwks) = app.request(Method::GET, "/.well-known/jwks.json", None, None).await;
+    assert_eq!(jwks["keys"].as_array().unwrap().len(), 2);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn profile_shows_blocked_status(pool: Pool) {
+async fn key_rotated_on_another_instance_verifies_at_once(pool: Pool) {
+    use haven_backend::jwt_keys::{self, KeyPurpose, Keyring};
+
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("prof_blk_a").await;
-    let (_, user_b) = app.register_user("prof_blk_b").await;
+    let (_, user_id) = app.register_user("rotate_elsewhere").await;
 
-    // Block B
-    let block_uri = format!("/api/v1/users/{}/block", user_b);
-    app.request(Method::POST, &block_uri, Some(&token_a), None).await;
+    // Another instance rotates and signs with the new key before this one syncs
+    let state = app.state();
+    let other = Keyring::new(KeyPurpose::AccessToken);
+    jwt_keys::rotate_now(&state.db, &state.config, &state.storage_key, &other).await.unwrap();
+    let token = haven_backend::auth::generate_access_token(user_id, None, &state.config, &other).unwrap();
 
-    // Profile should show blocked
-    let uri = format!("/api/v1/users/{}/profile", user_b);
-    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
-    assert_eq!(value["is_blocked"].as_bool(), Some(true));
+    let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn profile_shows_mutual_friends(pool: Pool) {
+async fn email_hash_key_usage_counts_users(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("mut_a").await;
-    let (token_b, user_b) = app.register_user("mut_b").await;
-    let (token_c, _) = app.register_user("mut_c").await;
+    let (admin_token, _) = app.register_user("hash_admin").await;
 
-    // A and C are friends, B and C are friends
-    app.make_friends(&token_a, &token_c, "mut_c").await;
-    app.make_friends(&token_b, &token_c, "mut_c").await;
+    let (_, challenge) = app.request(Method::GET, "/api/v1/auth/challenge", None, None).await;
+    let mut body = registration_body("hash_user", &challenge);
+    body["email"] = json!("Hash.User@example.com");
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/register", None, Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
 
-    // A views B's profile — mutual friend should be C
-    let uri = format!("/api/v1/users/{}/profile", user_b);
-    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
-    assert_eq!(value["mutual_friend_count"].as_i64(), Some(1));
-    let mutuals = value["mutual_friends"].as_array().unwrap();
-    assert_eq!(mutuals.len(), 1);
-    assert_eq!(mutuals[0]["username"].as_str(), Some("mut_c"));
+    let (status, usage) = app
+        .request(Method::GET, "/api/v1/admin/email-hash-keys", Some(&admin_token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        usage,
+        json!([{
+            "key_id": haven_backend::auth::email_hash_key_id("test-email-hash-key"),
+            "current": true,
+            "configured": true,
+            "users": 1,
+        }])
+    );
+}
+
+// ─── Single Sign-On ──────────────────────────────────
+
+async fn sso_app(pool: Pool, issuer: &common::mock_oidc::MockOidcIssuer) -> TestApp {
+    let issuer = issuer.issuer().to_string();
+    TestApp::with_config(pool, move |config| {
+        config.oidc_issuer = issuer;
+        config.oidc_client_id = common::mock_oidc::CLIENT_ID.into();
+        config.oidc_redirect_uri = common::mock_oidc::REDIRECT_URI.into();
+    })
+    .await
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn profile_with_server_roles(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token_owner, _) = app.register_user("role_prof_own").await;
-    let (token_member, member_id) = app.register_user("role_prof_mem").await;
-    let server_id = app.create_server(&token_owner, "Role Prof").await;
+async fn oidc_login_provisions_then_signs_in(pool: Pool) {
+    let issuer = common::mock_oidc::MockOidcIssuer::start().await;
+    let app = sso_app(pool, &issuer).await;
 
-    app.invite_and_join(&token_owner, &token_member, server_id).await;
+    let (status, methods) = app.request(Method::GET, "/api/v1/auth/methods", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(methods, json!({ "password": true, "oidc": true }));
 
-    // Create and assign a role
-    let roles_uri = format!("/api/v1/servers/{}/roles", server_id);
-    let (_, role_val) = app
-        .request(
-            Method::POST,
-            &roles_uri,
-            Some(&token_owner),
-            Some(json!({ "name": "Tester", "color": "#0000ff", "position": 1 })),
-        )
+    let claims = json!({ "preferred_username": "sso.alice", "name": "Alice" });
+    let keys = json!({
+        "identity_key": B64.encode([0u8; 32]),
+        "signed_prekey": B64.encode([0u8; 32]),
+        "signed_prekey_signature": B64.encode([0u8; 64]),
+    });
+
+    // First login creates the account from the username claim
+    let (_, start) = app.request(Method::GET, "/api/v1/auth/oidc/authorize", None, None).await;
+    let (code, state) = issuer.authorize(start["authorization_url"].as_str().unwrap(), "sub-alice", claims.clone());
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/auth/oidc/callback", None, Some(json!({ "code": code, "state": state, "keys": keys })))
         .await;
-    let role_id = role_val["id"].as_str().unwrap();
+    assert_eq!(status, StatusCode::OK, "SSO provisioning failed: {}", value);
+    assert_eq!(value["user"]["username"], "sso_alice");
+    assert_eq!(value["user"]["display_name"], "Alice");
+    assert!(value["device_id"].is_string());
+    let user_id = value["user"]["id"].clone();
 
-    let assign_uri = format!("/api/v1/servers/{}/members/{}/roles", server_id, member_id);
-    app.request(
-        Method::PUT,
-        &assign_uri,
-        Some(&token_owner),
-        Some(json!({ "role_id": role_id })),
-    )
-    .await;
+    // A state is single use
+    let (status, _) = app
+        .request(Method::POST, "/api/v1/auth/oidc/callback", None, Some(json!({ "code": code, "state": state })))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
 
-    // Get profile with server_id — should include roles
-    let uri = format!("/api/v1/users/{}/profile?server_id={}", member_id, server_id);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token_owner), None).await;
+    // The same subject signs back in without keys
+    let (_, start) = app.request(Method::GET, "/api/v1/auth/oidc/authorize", None, None).await;
+    let (code, state) = issuer.authorize(start["authorization_url"].as_str().unwrap(), "sub-alice", claims);
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/auth/oidc/callback", None, Some(json!({ "code": code, "state": state })))
+        .await;
+    assert_eq!(status, StatusCode::OK, "SSO login failed: {}", value);
+    assert_eq!(value["user"]["id"], user_id);
+    assert!(value["device_id"].is_null());
+    let token = value["access_token"].as_str().unwrap();
+    let (status, _) = app.request(Method::GET, "/api/v1/servers", Some(token), None).await;
     assert_eq!(status, StatusCode::OK);
-    let roles = value["roles"].as_array().unwrap();
-    assert!(roles.iter().any(|r| r["name"].as_str() == Some("Tester")));
-}
 
-// ─── Auth Edge Cases ─────────────────────────────────
+    // Nobody knows the provisioned account's password, so its only identity stays
+    let (status, _) = app.request(Method::DELETE, "/api/v1/auth/oidc/link", Some(token), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (_, identities) = app.request(Method::GET, "/api/v1/auth/oidc/identities", Some(token), None).await;
+    assert_eq!(identities.as_array().unwrap().len(), 1);
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn change_password_revokes_refresh_tokens(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("pw_revoke").await;
+async fn oidc_link_existing_account(pool: Pool) {
+    let issuer = common::mock_oidc::MockOidcIssuer::start().await;
+    let app = sso_app(pool, &issuer).await;
+    let (token, user_id) = app.register_user("sso_linker").await;
+    let (other_token, _) = app.register_user("sso_bystander").await;
+
+    // A link flow can only be finished by the account that started it
+    for finisher in [None, Some(other_token.as_str())] {
+        let (_, start) = app.request(Method::POST, "/api/v1/auth/oidc/link", Some(&token), None).await;
+        let (code, state) = issuer.authorize(start["authorization_url"].as_str().unwrap(), "sub-victim", json!({}));
+        let (status, _) = app
+            .request(Method::POST, "/api/v1/auth/oidc/callback", finisher, Some(json!({ "code": code, "state": state })))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let (_, identities) = app.request(Method::GET, "/api/v1/auth/oidc/identities", Some(&token), None).await;
+    assert!(identities.as_array().unwrap().is_empty());
 
-    // Get a refresh token
-    let (_, refresh, _) = app.login_user("pw_revoke").await;
+    let (status, start) = app.request(Method::POST, "/api/v1/auth/oidc/link", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (code, state) = issuer.authorize(start["authorization_url"].as_str().unwrap(), "sub-linker", json!({}));
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/auth/oidc/callback", Some(&token), Some(json!({ "code": code, "state": state })))
+        .await;
+    assert_eq!(status, StatusCode::OK, "Link failed: {}", value);
+    assert_eq!(value["linked"], true);
+    assert_eq!(value["subject"], "sub-linker");
 
-    // Change password
-    app.request(
-        Method::PUT,
-        "/api/v1/auth/password",
-        Some(&token),
-        Some(json!({
-            "current_password": "testpassword123",
-            "new_password": "brandnewpass456"
-        })),
-    )
-    .await;
+    let (_, identities) = app.request(Method::GET, "/api/v1/auth/oidc/identities", Some(&token), None).await;
+    assert_eq!(identities.as_array().unwrap().len(), 1);
 
-    // Old refresh token should be invalid
-    let (status, _) = app
-        .request(
-            Method::POST,
-            "/api/v1/auth/refresh",
-            None,
-            Some(json!({ "refresh_token": refresh })),
-        )
+    // SSO now signs into the existing account, even with no username claim
+    let (_, start) = app.request(Method::GET, "/api/v1/auth/oidc/authorize", None, None).await;
+    let (code, state) = issuer.authorize(start["authorization_url"].as_str().unwrap(), "sub-linker", json!({}));
+    let (status, value) = app
+        .request(Method::POST, "/api/v1/auth/oidc/callback", None, Some(json!({ "code": code, "state": state })))
         .await;
-    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value["user"]["id"], user_id.to_string());
+
+    let (status, _) = app.request(Method::DELETE, "/api/v1/auth/oidc/link", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::DELETE, "/api/v1/auth/oidc/link", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn change_password_too_short_fails(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("pw_short").await;
+async fn password_login_can_be_disabled(pool: Pool) {
+    let app = TestApp::with_config(pool, |config| config.password_login_enabled = false).await;
+
+    let (_, methods) = app.request(Method::GET, "/api/v1/auth/methods", None, None).await;
+    assert_eq!(methods, json!({ "password": false, "oidc": false }));
 
     let body = json!({
-        "current_password": "testpassword123",
-        "new_password": "short"
+        "username": "no_passwords",
+        "password": "testpassword123",
+        "identity_key": B64.encode([0u8; 32]),
+        "signed_prekey": B64.encode([0u8; 32]),
+        "signed_prekey_signature": B64.encode([0u8; 64]),
+        "one_time_prekeys": []
     });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/register", None, Some(body)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
     let (status, _) = app
-        .request(Method::PUT, "/api/v1/auth/password", Some(&token), Some(body))
+        .request(Method::POST, "/api/v1/auth/login", None, Some(json!({ "username": "no_passwords", "password": "testpassword123" })))
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // SSO isn't configured here either
+    let (status, _) = app.request(Method::GET, "/api/v1/auth/oidc/authorize", None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
-// ─── Group DM Extended ───────────────────────────────
+// ─── Login Protection ────────────────────────────────
+
+const FIREFOX_UA: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn add_member_to_group_dm(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("grp_add_a").await;
-    let (token_b, user_b) = app.register_user("grp_add_b").await;
-    let (token_c, user_c) = app.register_user("grp_add_c").await;
-    let (token_d, user_d) = app.register_user("grp_add_d").await;
-
-    app.make_friends(&token_a, &token_b, "grp_add_b").await;
-    app.make_friends(&token_a, &token_c, "grp_add_c").await;
-    app.make_friends(&token_a, &token_d, "grp_add_d").await;
+async fn repeated_login_failures_lock_the_account(pool: Pool) {
+    let app = TestApp::with_config(pool, |config| {
+        config.login_lockout_free_attempts = 2;
+        config.login_lockout_base_secs = 30;
+    })
+    .await;
+    // Lockouts live in the shared Redis, so don't reuse a username across runs
+    let username = format!("locked_{}", &Uuid::new_v4().simple().to_string()[..8]);
+    let (token, _) = app.register_user(&username).await;
+
+    let wrong = json!({ "username": username, "password": "wrong-password" });
+    for _ in 0..3 {
+        let (status, _) = app.request(Method::POST, "/api/v1/auth/login", None, Some(wrong.clone())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
 
-    // Create group DM with B and C
-    let body = json!({
-        "member_ids": [user_b, user_c],
-        "encrypted_meta": B64.encode(b"group-meta")
-    });
-    let (status, value) = app
-        .request(Method::POST, "/api/v1/dm/group", Some(&token_a), Some(body))
-        .await;
-    assert_eq!(status, StatusCode::OK);
-    let channel_id = value["id"].as_str().unwrap();
+    // Even the right password is refused while locked out
+    let login = json!({ "username": username.to_uppercase(), "password": "testpassword123" });
+    let (status, value) = app.request(Method::POST, "/api/v1/auth/login", None, Some(login)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(value["error"].as_str().unwrap().contains("try again"));
 
-    // Add D to the group
-    let uri = format!("/api/v1/channels/{}/members", channel_id);
-    let (status, value) = app
-        .request(
-            Method::POST,
-            &uri,
-            Some(&token_a),
-            Some(json!({ "user_id": user_d })),
-        )
-        .await;
+    let (status, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(&token), None).await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["added"].as_bool(), Some(true));
-
-    // Verify 4 members
-    let mem_uri = format!("/api/v1/channels/{}/members", channel_id);
-    let (_, value) = app.request(Method::GET, &mem_uri, Some(&token_a), None).await;
-    assert_eq!(value.as_array().unwrap().len(), 4);
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, vec!["account_locked"]);
 }
 
-// ─── Message Search ──────────────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn search_messages(pool: Pool) {
+async fn login_from_new_device_is_recorded(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("search_user").await;
-    let server_id = app.create_server(&token, "Search Server").await;
-    let channel_id = app.create_channel(&token, server_id, "general").await;
+    let (token, _) = app.register_user("new_device").await;
+    let login = json!({ "username": "new_device", "password": "testpassword123" });
 
-    // Send a few messages
-    app.send_message(&token, channel_id).await;
-    app.send_message(&token, channel_id).await;
+    // Same (absent) User-Agent as registration: nothing to report
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/login", None, Some(login.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(&token), None).await;
+    assert_eq!(events, json!([]));
+
+    for _ in 0..2 {
+        let (status, _) = app
+            .request_with_headers(Method::POST, "/api/v1/auth/login", None, Some(login.clone()), &[("user-agent", FIREFOX_UA), ("x-forwarded-for", "203.0.113.7")])
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
 
-    // Get messages with limit
-    let uri = format!("/api/v1/channels/{}/messages?limit=1", channel_id);
-    let (status, value) = app.request(Method::GET, &uri, Some(&token), None).await;
+    // Only the first sign-in from Firefox is new
+    let (status, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(&token), None).await;
     assert_eq!(status, StatusCode::OK);
-    let messages = value.as_array().unwrap();
-    assert_eq!(messages.len(), 1);
+    let events = events.as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["kind"], "new_device_login");
+    assert_eq!(events[0]["device_name"], "Firefox on Linux");
+    assert_eq!(events[0]["ip_address"], "203.0.x.x");
 }
 
-// ─── DM with Server Members Privacy ─────────────────
+// ─── Email ───────────────────────────────────────────
+
+async fn email_app(pool: Pool) -> (TestApp, common::mock_smtp::Inbox) {
+    let (port, inbox) = common::mock_smtp::start().await;
+    let app = TestApp::with_config(pool, |config| {
+        config.smtp_host = "127.0.0.1".into();
+        config.smtp_port = port;
+    })
+    .await;
+    (app, inbox)
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_server_members_privacy(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("sm_a").await;
-    let (token_b, user_b) = app.register_user("sm_b").await;
+async fn email_verification_link_confirms_address(pool: Pool) {
+    let (app, mut inbox) = email_app(pool).await;
+    let (token, _) = app.register_user("verify_me").await;
 
-    // B sets server_members privacy
-    app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "server_members" })),
-    )
-    .await;
+    let body = json!({ "email": "verify_me@example.com", "password": "wrong-password" });
+    let (status, _) = app.request(Method::PUT, "/api/v1/auth/email", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
 
-    // A and B are not in a shared server and not friends → pending
-    let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": B64.encode(b"dm-meta")
-    });
-    let (status, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
-        .await;
+    let body = json!({ "email": "verify_me@example.com", "password": "testpassword123" });
+    let (status, _) = app.request(Method::PUT, "/api/v1/auth/email", Some(&token), Some(body)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let link_token = inbox.next_token().await;
+
+    let (_, status_before) = app.request(Method::GET, "/api/v1/auth/email", Some(&token), None).await;
+    assert_eq!(status_before["email_set"], false);
+    assert_eq!(status_before["verification_pending"], true);
+
+    let verify = json!({ "token": link_token });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/email/verify", None, Some(verify.clone())).await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["dm_status"].as_str(), Some("pending"));
+    let (_, status_after) = app.request(Method::GET, "/api/v1/auth/email", Some(&token), None).await;
+    assert_eq!(status_after["email_set"], true);
+    assert_eq!(status_after["verified"], true);
+    assert_eq!(status_after["verification_pending"], false);
+
+    // Links are single-use
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/email/verify", None, Some(verify)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn dm_server_members_shared_server_is_active(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("sms_a").await;
-    let (token_b, user_b) = app.register_user("sms_b").await;
+async fn password_reset_sets_password_and_revokes_sessions(pool: Pool) {
+    let (app, mut inbox) = email_app(pool).await;
+    let (token, _) = app.register_user("forgetful").await;
+
+    // Unverified addresses get no reset link
+    let reset = json!({ "email": "Forgetful@Example.com" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/password-reset", None, Some(reset.clone())).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    let body = json!({ "email": "forgetful@example.com", "password": "testpassword123" });
+    app.request(Method::PUT, "/api/v1/auth/email", Some(&token), Some(body)).await;
+    let verify = json!({ "token": inbox.next_token().await });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/email/verify", None, Some(verify)).await;
+    assert_eq!(status, StatusCode::OK);
 
-    // B sets server_members privacy
-    app.request(
-        Method::PUT,
-        "/api/v1/users/dm-privacy",
-        Some(&token_b),
-        Some(json!({ "dm_privacy": "server_members" })),
-    )
-    .await;
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/password-reset", None, Some(reset)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let message = inbox.next().await;
+    assert!(message.contains("forgetful"), "reset email should name the account");
+    assert!(!message.contains("Confirm this address"), "got the verification email instead");
 
-    // Put both in a server
-    let server_id = app.create_server(&token_a, "Shared").await;
-    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let confirm = json!({ "token": common::mock_smtp::link_token(&message), "new_password": "brand-new-password" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/password-reset/confirm", None, Some(confirm.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/password-reset/confirm", None, Some(confirm)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
 
-    // Now DM should be active (shared server)
-    let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": B64.encode(b"dm-meta")
-    });
-    let (status, value) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
-        .await;
+    // The old session is gone and only the new password works
+    let (status, _) = app.request(Method::GET, "/api/v1/auth/sessions", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let old = json!({ "username": "forgetful", "password": "testpassword123" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/login", None, Some(old)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let new = json!({ "username": "forgetful", "password": "brand-new-password" });
+    let (status, value) = app.request(Method::POST, "/api/v1/auth/login", None, Some(new)).await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["dm_status"].as_str(), Some("active"));
-}
 
-// ─── Attachment Download ─────────────────────────────
+    let new_token = value["access_token"].as_str().unwrap();
+    let (_, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(new_token), None).await;
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, vec!["password_reset", "email_verified"]);
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn download_attachment_not_found(pool: Pool) {
+async fn password_reset_needs_smtp(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("att_404").await;
-
-    let uri = format!("/api/v1/attachments/{}", Uuid::new_v4());
-    let (status, _) = app.request(Method::GET, &uri, Some(&token), None).await;
+    let reset = json!({ "email": "someone@example.com" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/password-reset", None, Some(reset)).await;
     assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
-// ─── Prekey Count ────────────────────────────────────
+// ─── Device Linking ──────────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn prekey_count_zero_initially(pool: Pool) {
+async fn qr_device_link_signs_in_the_new_client(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("pk_zero").await;
+    let (token, user_id) = app.register_user("linker").await;
 
-    let (status, value) = app
-        .request(Method::GET, "/api/v1/keys/prekeys/count", Some(&token), None)
+    // The new client starts a link without signing in
+    let ephemeral = B64.encode([7u8; 32]);
+    let (status, start) = app
+        .request_with_headers(
+            Method::POST,
+            "/api/v1/auth/link",
+            None,
+            Some(json!({ "ephemeral_public_key": ephemeral })),
+            &[("user-agent", FIREFOX_UA)],
+        )
         .await;
     assert_eq!(status, StatusCode::OK);
-    assert_eq!(value["count"].as_i64(), Some(0));
-}
+    let link_id = start["link_id"].as_str().unwrap();
+    assert!(start["link_uri"].as_str().unwrap().contains(&format!("#id={}&key=", link_id)));
+    let poll_uri = format!("/api/v1/auth/link/{}/poll", link_id);
+    let poll = json!({ "link_token": start["link_token"] });
 
-// ─── Friend Request Edge Cases ───────────────────────
+    let (status, value) = app.request(Method::POST, &poll_uri, None, Some(poll.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(value, json!({ "pending": true }));
+    let (status, _) = app
+        .request(Method::POST, &poll_uri, None, Some(json!({ "link_token": "not-the-token" })))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The signed-in session sees who is asking, then approves with the QR's key
+    let (status, info) = app.request(Method::GET, &format!("/api/v1/auth/link/{}", link_id), Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(info["device_name"], "Firefox on Linux");
+
+    let approve_uri = format!("/api/v1/auth/link/{}/approve", link_id);
+    let provisioning = B64.encode(b"encrypted provisioning message");
+    let wrong_key = json!({
+        "ephemeral_public_key": B64.encode([8u8; 32]),
+        "approver_public_key": B64.encode([9u8; 32]),
+        "provisioning_message": provisioning,
+    });
+    let (status, _) = app.request(Method::POST, &approve_uri, Some(&token), Some(wrong_key)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let approve = json!({
+        "ephemeral_public_key": ephemeral,
+        "approver_public_key": B64.encode([9u8; 32]),
+        "provisioning_message": provisioning,
+    });
+    let (status, _) = app.request(Method::POST, &approve_uri, Some(&token), Some(approve.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::POST, &approve_uri, Some(&token), Some(approve)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // The new client collects its own session and the message, once
+    let (status, linked) = app
+        .request_with_headers(Method::POST, &poll_uri, None, Some(poll.clone()), &[("user-agent", FIREFOX_UA)])
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(linked["user"]["id"], user_id.to_string());
+    assert_eq!(linked["provisioning_message"], provisioning);
+    assert_eq!(linked["approver_public_key"], B64.encode([9u8; 32]));
+    let (status, _) = app.request(Method::POST, &poll_uri, None, Some(poll)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let new_token = linked["access_token"].as_str().unwrap();
+    let (status, sessions) = app.request(Method::GET, "/api/v1/auth/sessions", Some(new_token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sessions.as_array().unwrap().len(), 2);
+
+    let (_, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(&token), None).await;
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert!(kinds.contains(&"device_linked"));
+    assert!(kinds.contains(&"new_device_login"));
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn friend_request_to_self_fails(pool: Pool) {
+async fn device_link_polling_has_its_own_limit(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("self_friend").await;
-
-    let (status, _) = app
+    let (_, start) = app
         .request(
             Method::POST,
-            "/api/v1/friends/request",
-            Some(&token),
-            Some(json!({ "username": "self_friend" })),
+            "/api/v1/auth/link",
+            None,
+            Some(json!({ "ephemeral_public_key": B64.encode([7u8; 32]) })),
         )
         .await;
-    assert_ne!(status, StatusCode::OK);
+    let poll_uri = format!("/api/v1/auth/link/{}/poll", start["link_id"].as_str().unwrap());
+    let poll = json!({ "link_token": start["link_token"] });
+
+    // A minute of polling every second fits; the auth limit alone would not
+    for _ in 0..60 {
+        let (status, _) = app.request(Method::POST, &poll_uri, None, Some(poll.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = app.request(Method::POST, &poll_uri, None, Some(poll)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
 }
 
+// ─── API Tokens ──────────────────────────────────────
+
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn friend_request_when_already_friends_fails(pool: Pool) {
+async fn api_tokens_are_limited_to_their_scopes(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("alr_a").await;
-    let (token_b, _) = app.register_user("alr_b").await;
+    let (token, _) = app.register_user("scripter").await;
+    let server_id = app.create_server(&token, "Bot Server").await;
+    let channel_id = app.create_channel(&token, server_id, "general").await;
+    let messages_uri = format!("/api/v1/channels/{}/messages", channel_id);
 
-    app.make_friends(&token_a, &token_b, "alr_b").await;
+    let (status, created) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/tokens",
+            Some(&token),
+            Some(json!({ "name": "reader", "scopes": ["messages:read"], "expires_in_days": 30 })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", created);
+    let reader = created["token"].as_str().unwrap().to_string();
+    assert!(reader.starts_with("hvn_pat_"));
+    assert!(reader.starts_with(created["token_prefix"].as_str().unwrap()));
+
+    // Reading works; sending, account routes and minting more tokens don't
+    let (status, _) = app.request(Method::GET, &messages_uri, Some(&reader), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = json!({
+        "channel_id": channel_id,
+        "sender_token": B64.encode(b"sender"),
+        "encrypted_body": B64.encode(b"hi"),
+        "has_attachments": false
+    });
+    let (status, _) = app.request(Method::POST, &messages_uri, Some(&reader), Some(body)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app.request(Method::GET, "/api/v1/auth/sessions", Some(&reader), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let mint = json!({ "name": "more", "scopes": ["messages:read"] });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/tokens", Some(&reader), Some(mint)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
 
+    let (_, created) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/tokens",
+            Some(&token),
+            Some(json!({
+                "name": "bot",
+                "scopes": ["messages:send", format!("server:{}:manage", server_id)],
+            })),
+        )
+        .await;
+    let bot = created["token"].as_str().unwrap().to_string();
+    app.send_message(&bot, channel_id).await;
+    app.create_channel(&bot, server_id, "bot-log").await;
+    let other_server = app.create_server(&token, "Other Server").await;
     let (status, _) = app
         .request(
             Method::POST,
-            "/api/v1/friends/request",
-            Some(&token_a),
-            Some(json!({ "username": "alr_b" })),
+            &format!("/api/v1/servers/{}/channels", other_server),
+            Some(&bot),
+            Some(json!({ "encrypted_meta": B64.encode(b"nope") })),
         )
         .await;
-    assert_ne!(status, StatusCode::OK);
-}
-
-#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
-#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn friend_request_unknown_user_fails(pool: Pool) {
-    let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("fr_unknown").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
 
     let (status, _) = app
         .request(
             Method::POST,
-            "/api/v1/friends/request",
+            "/api/v1/auth/tokens",
             Some(&token),
-            Some(json!({ "username": "nobody_exists_here" })),
+            Some(json!({ "name": "bad", "scopes": ["admin"] })),
         )
         .await;
-    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // Listed with last use, never with the token itself; revocable
+    let (status, tokens) = app.request(Method::GET, "/api/v1/auth/tokens", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(tokens.len(), 2);
+    let listed = tokens.iter().find(|t| t["name"] == "reader").unwrap();
+    assert!(listed.get("token").is_none());
+    assert!(listed["last_used_at"].is_string());
+    assert!(listed["expires_at"].is_string());
+
+    let uri = format!("/api/v1/auth/tokens/{}", listed["id"].as_str().unwrap());
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::GET, &messages_uri, Some(&reader), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Managing a server doesn't extend to deleting it or giving it away
+    let server_uri = format!("/api/v1/servers/{}", server_id);
+    let (status, _) = app.request(Method::DELETE, &server_uri, Some(&bot), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let transfer = json!({ "user_id": Uuid::new_v4() });
+    let (status, _) = app
+        .request(Method::PUT, &format!("{}/owner", server_uri), Some(&bot), Some(transfer))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Changing the password revokes the remaining tokens
+    let change = json!({ "current_password": "testpassword123", "new_password": "newpassword456" });
+    let (status, _) = app.request(Method::PUT, "/api/v1/auth/password", Some(&token), Some(change)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request(Method::GET, &messages_uri, Some(&bot), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
-// ─── Invite Edge Cases ───────────────────────────────
+// ─── Account Deactivation ────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn join_invite_already_member(pool: Pool) {
+async fn deactivated_account_is_hidden_until_next_login(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_owner, _) = app.register_user("inv_alr_own").await;
-    let (token_member, _) = app.register_user("inv_alr_mem").await;
-    let server_id = app.create_server(&token_owner, "Already Member").await;
+    let (token_a, _) = app.register_user("deact_viewer").await;
+    let (token_b, user_b) = app.register_user("deact_sleeper").await;
+    let profile_uri = format!("/api/v1/users/{}/profile", user_b);
 
-    let code = app.invite_and_join(&token_owner, &token_member, server_id).await;
+    let (status, _) = app
+        .request(Method::POST, "/api/v1/auth/deactivate", Some(&token_b), Some(json!({ "password": "wrong" })))
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/auth/deactivate",
+            Some(&token_b),
+            Some(json!({ "password": "testpassword123" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
 
-    // Try to join again
-    let join_uri = format!("/api/v1/invites/{}/join", code);
+    // Signed out, and hidden from others
+    let (status, _) = app.request(Method::GET, "/api/v1/auth/sessions", Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::GET, &profile_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
     let (status, _) = app
-        .request(Method::POST, &join_uri, Some(&token_member), None)
+        .request(Method::GET, "/api/v1/users/search?username=deact_sleeper", Some(&token_a), None)
         .await;
-    assert_ne!(status, StatusCode::OK);
-}
+    assert_eq!(status, StatusCode::NOT_FOUND);
 
-// ─── Kick / Ban Edge Cases ───────────────────────────
+    // Signing in reactivates the account
+    let (token_b, _, _) = app.login_user("deact_sleeper").await;
+    let (status, _) = app.request(Method::GET, &profile_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, events) = app.request(Method::GET, "/api/v1/auth/security-events", Some(&token_b), None).await;
+    let kinds: Vec<&str> = events.as_array().unwrap().iter().map(|e| e["kind"].as_str().unwrap()).collect();
+    assert!(kinds.contains(&"account_deactivated"));
+    assert!(kinds.contains(&"account_reactivated"));
+}
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn kick_member_by_non_owner_fails(pool: Pool) {
+async fn account_deletion_waits_for_ownership_transfer_and_grace_period(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_owner, _) = app.register_user("kick_p_own").await;
-    let (token_member, _) = app.register_user("kick_p_mem").await;
-    let (_, target_id) = app.register_user("kick_p_tgt").await;
-    let server_id = app.create_server(&token_owner, "Kick Perm").await;
-
-    app.invite_and_join(&token_owner, &token_member, server_id).await;
+    let (token_a, user_a) = app.register_user("del_leaver").await;
+    let (token_b, user_b) = app.register_user("del_stayer").await;
+    let server_id = app.create_server(&token_a, "Inherited").await;
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+    let (_, dm) = app
+        .request(
+            Method::POST,
+            "/api/v1/dm",
+            Some(&token_a),
+            Some(json!({ "target_user_id": user_b, "encrypted_meta": B64.encode(b"dm-meta") })),
+        )
+        .await;
+    let dm_members_uri = format!("/api/v1/channels/{}/members", dm["id"].as_str().unwrap());
+    let delete = json!({ "password": "testpassword123" });
 
-    // Member (without KICK_MEMBERS) tries to kick — should be forbidden
-    let kick_uri = format!("/api/v1/servers/{}/members/{}", server_id, target_id);
+    // An owned server blocks deletion until it has a new owner
     let (status, _) = app
-        .request(Method::DELETE, &kick_uri, Some(&token_member), None)
+        .request(Method::POST, "/api/v1/auth/delete-account", Some(&token_a), Some(delete.clone()))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let owner_uri = format!("/api/v1/servers/{}/owner", server_id);
+    let (status, _) = app
+        .request(Method::PUT, &owner_uri, Some(&token_b), Some(json!({ "user_id": user_b })))
         .await;
     assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = app
+        .request(Method::PUT, &owner_uri, Some(&token_a), Some(json!({ "user_id": user_b })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, scheduled) = app
+        .request(Method::POST, "/api/v1/auth/delete-account", Some(&token_a), Some(delete))
+        .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(scheduled["deletion_scheduled_at"].is_string());
+
+    // The DM shows a placeholder during the grace window
+    let (status, members) = app.request(Method::GET, &dm_members_uri, Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let leaver = members
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["user_id"] == user_a.to_string())
+        .unwrap();
+    assert_eq!(leaver["username"], "Deleted Account");
+    assert_eq!(leaver["deleted"], true);
+
+    // Once the grace period is over the worker erases the account
+    sqlx::query("UPDATE users SET deletion_scheduled_at = NOW() - INTERVAL '1 minute' WHERE id = $1")
+        .bind(user_a)
+        .execute(app.state().db.write())
+        .await
+        .unwrap();
+    let erased = haven_backend::accounts::erase_due_accounts(app.state()).await.unwrap();
+    assert_eq!(erased, 1);
+    let body = json!({ "username": "del_leaver", "password": "testpassword123" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/login", None, Some(body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.request(Method::GET, &format!("/api/v1/servers/{}", server_id), Some(&token_b), None).await;
+    assert_eq!(status, StatusCode::OK);
 }
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn cannot_ban_self(pool: Pool) {
+async fn admin_scheduled_deletion_is_not_cancelled_by_signing_in(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, user_id) = app.register_user("ban_self").await;
-    let server_id = app.create_server(&token, "Ban Self").await;
+    let (admin_token, _) = app.register_user("del_admin").await;
+    let (_, target) = app.register_user("del_target").await;
 
-    let ban_uri = format!("/api/v1/servers/{}/bans/{}", server_id, user_id);
-    let (status, _) = app
-        .request(Method::POST, &ban_uri, Some(&token), Some(json!({})))
+    let (status, scheduled) = app
+        .request(Method::DELETE, &format!("/api/v1/admin/users/{}", target), Some(&admin_token), None)
         .await;
-    assert_ne!(status, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(scheduled["deleted"], false);
+
+    // The target can't sign in to undo it
+    let body = json!({ "username": "del_target", "password": "testpassword123" });
+    let (status, _) = app.request(Method::POST, "/api/v1/auth/login", None, Some(body)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let user = haven_backend::db::queries::find_user_by_id(app.state().db.read(), target).await.unwrap().unwrap();
+    assert!(user.deletion_scheduled_at.is_some());
+    assert!(user.deletion_requested_by_admin);
 }
 
-// ─── DM Edge Cases ───────────────────────────────────
+// ─── Data Export ─────────────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn create_dm_returns_existing_if_exists(pool: Pool) {
+async fn data_export_builds_an_archive_behind_a_signed_link(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token_a, _) = app.register_user("dup_dm_a").await;
-    let (_, user_b) = app.register_user("dup_dm_b").await;
+    let (token, _) = app.register_user("export_owner").await;
+    let (other_token, _) = app.register_user("export_other").await;
+    let server_id = app.create_server(&token, "Exported").await;
+    let channel_id = app.create_channel(&token, server_id, "general").await;
+    let (message_id, _) = app.send_message(&token, channel_id).await;
 
-    let body = json!({
-        "target_user_id": user_b,
-        "encrypted_meta": B64.encode(b"dm-meta")
-    });
+    let (status, _) = app.request(Method::GET, "/api/v1/users/@me/export", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, export) = app.request(Method::POST, "/api/v1/users/@me/export", Some(&token), None).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let export_id = export["id"].as_str().unwrap().to_string();
+
+    // The archive is built in the background
+    let mut export = export;
+    for _ in 0..50 {
+        if export["status"] != "pending" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        export = app.request(Method::GET, "/api/v1/users/@me/export", Some(&token), None).await.1;
+    }
+    assert_eq!(export["status"], "ready");
+    assert!(export["expires_at"].is_string());
 
-    let (status, value1) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body.clone()))
-        .await;
+    // The signed link works without signing in, for this export only
+    let path = export["download_url"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("http://haven.test")
+        .unwrap()
+        .to_string();
+    let (status, archive) = app.request(Method::GET, &path, None, None).await;
     assert_eq!(status, StatusCode::OK);
-    let dm_id_1 = value1["id"].as_str().unwrap().to_string();
+    let archive = archive.as_str().unwrap();
+    assert!(archive.contains("export.json"));
+    assert!(archive.contains("messages/000000.json"));
+    assert!(archive.contains("\"username\": \"export_owner\""));
+    assert!(archive.contains(&message_id.to_string()));
+    let forged = path.replace(&export_id, &Uuid::new_v4().to_string());
+    let (status, _) = app.request(Method::GET, &forged, None, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
 
-    let (status, value2) = app
-        .request(Method::POST, "/api/v1/dm", Some(&token_a), Some(body))
-        .await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value2["id"].as_str().unwrap(), dm_id_1);
+    // Other users don't see it
+    let (status, _) = app.request(Method::GET, "/api/v1/users/@me/export", Some(&other_token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
-// ─── Message Pagination ──────────────────────────────
-
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
 #[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
-async fn get_messages_with_limit_and_pagination(pool: Pool) {
+async fn data_export_requests_are_rate_limited(pool: Pool) {
     let app = TestApp::new(pool).await;
-    let (token, _) = app.register_user("msg_page").await;
-    let server_id = app.create_server(&token, "Page Test").await;
-    let channel_id = app.create_channel(&token, server_id, "ch").await;
+    let (token, _) = app.register_user("export_spammer").await;
 
-    // Send 5 messages
-    for _ in 0..5 {
-        app.send_message(&token, channel_id).await;
+    // Accepted or refused as already pending, every request counts
+    for _ in 0..3 {
+        let (status, _) = app.request(Method::POST, "/api/v1/users/@me/export", Some(&token), None).await;
+        assert!(status == StatusCode::ACCEPTED || status == StatusCode::CONFLICT, "{}", status);
     }
-
-    // Default: all 5
-    let uri = format!("/api/v1/channels/{}/messages", channel_id);
-    let (_, value) = app.request(Method::GET, &uri, Some(&token), None).await;
-    assert_eq!(value.as_array().unwrap().len(), 5);
-
-    // Limit to 2
-    let uri2 = format!("/api/v1/channels/{}/messages?limit=2", channel_id);
-    let (status, value) = app.request(Method::GET, &uri2, Some(&token), None).await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value.as_array().unwrap().len(), 2);
-
-    // Limit capped at 100 — requesting 200 should still work
-    let uri3 = format!("/api/v1/channels/{}/messages?limit=200", channel_id);
-    let (status, value) = app.request(Method::GET, &uri3, Some(&token), None).await;
-    assert_eq!(status, StatusCode::OK);
-    assert_eq!(value.as_array().unwrap().len(), 5); // only 5 messages exist
+    let (status, _) = app.request(Method::POST, "/api/v1/users/@me/export", Some(&token), None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
 }
 
 // ─── Registration Validation ─────────────────────────
@@ -0,0 +1,31 @@
+//! A stand-in CAPTCHA verify endpoint for registration tests.
+//!
+//! Speaks the Turnstile/hCaptcha siteverify form protocol. The token
+//! `PASS_TOKEN` verifies; anything else fails.
+
+use std::collections::HashMap;
+
+use axum::{routing::post, Form, Json, Router};
+use serde_json::{json, Value};
+
+pub const SITE_KEY: &str = "test-site-key";
+pub const SECRET_KEY: &str = "test-secret-key";
+pub const PASS_TOKEN: &str = "captcha-pass";
+
+/// Bind to an ephemeral port and start serving. Returns the verify URL.
+pub async fn start() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/siteverify", listener.local_addr().unwrap());
+
+    let app = Router::new().route("/siteverify", post(siteverify));
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    url
+}
+
+async fn siteverify(Form(form): Form<HashMap<String, String>>) -> Json<Value> {
+    let success = form.get("secret").map(String::as_str) == Some(SECRET_KEY)
+        && form.get("response").map(String::as_str) == Some(PASS_TOKEN);
+    Json(json!({ "success": success }))
+}
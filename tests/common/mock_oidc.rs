@@ -0,0 +1,185 @@
+//! A minimal OpenID Connect issuer for SSO integration tests.
+//!
+//! Serves discovery, a JWKS with one ES256 key and a token endpoint that
+//! checks the PKCE verifier. Tests play the browser with `authorize`, which
+//! reads the authorization URL Haven built and mints a code for it.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::Engine;
+use jsonwebtoken::{EncodingKey, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+pub const CLIENT_ID: &str = "haven-test";
+pub const REDIRECT_URI: &str = "http://haven.test/sso/callback";
+const KID: &str = "mock-key-1";
+
+struct PendingCode {
+    subject: String,
+    nonce: String,
+    code_challenge: String,
+    redirect_uri: String,
+    claims: Map<String, Value>,
+}
+
+struct Inner {
+    issuer: String,
+    encoding_key: EncodingKey,
+    jwk: Value,
+    codes: Mutex<HashMap<String, PendingCode>>,
+}
+
+pub struct MockOidcIssuer {
+    inner: Arc<Inner>,
+}
+
+fn b64url(bytes: &[u8]) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl MockOidcIssuer {
+    /// Bind to an ephemeral port and start serving.
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+        // Uncompressed point: 0x04 || x || y
+        let point = key_pair.public_key().as_ref();
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "use": "sig",
+            "alg": "ES256",
+            "kid": KID,
+            "x": b64url(&point[1..33]),
+            "y": b64url(&point[33..65]),
+        });
+
+        let inner = Arc::new(Inner {
+            issuer,
+            encoding_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            jwk,
+            codes: Mutex::new(HashMap::new()),
+        });
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(inner.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        MockOidcIssuer { inner }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.inner.issuer
+    }
+
+    /// Act as the user's browser at the issuer: accept the authorization URL
+    /// Haven produced and sign `subject` in. Returns `(code, state)` as the
+    /// redirect to `REDIRECT_URI` would carry them.
+    pub fn authorize(&self, authorization_url: &str, subject: &str, claims: Value) -> (String, String) {
+        let (endpoint, query) = authorization_url.split_once('?').expect("no query string");
+        assert_eq!(endpoint, format!("{}/authorize", self.inner.issuer));
+        let params: HashMap<String, String> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.to_string(), urlencoding::decode(v).unwrap().into_owned()))
+            .collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["code_challenge_method"], "S256");
+
+        let code = format!("code-{}", uuid::Uuid::new_v4());
+        self.inner.codes.lock().unwrap().insert(
+            code.clone(),
+            PendingCode {
+                subject: subject.to_string(),
+                nonce: params["nonce"].clone(),
+                code_challenge: params["code_challenge"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+                claims: claims.as_object().cloned().unwrap_or_default(),
+            },
+        );
+        (code, params["state"].clone())
+    }
+}
+
+async fn discovery(State(inner): State<Arc<Inner>>) -> Json<Value> {
+    Json(json!({
+        "issuer": inner.issuer,
+        "authorization_endpoint": format!("{}/authorize", inner.issuer),
+        "token_endpoint": format!("{}/token", inner.issuer),
+        "jwks_uri": format!("{}/jwks", inner.issuer),
+        "response_types_supported": ["code"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+    }))
+}
+
+async fn jwks(State(inner): State<Arc<Inner>>) -> Json<Value> {
+    Json(json!({ "keys": [inner.jwk] }))
+}
+
+async fn token(
+    State(inner): State<Arc<Inner>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let invalid = |error: &str| (StatusCode::BAD_REQUEST, Json(json!({ "error": error })));
+
+    if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+        || form.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+    {
+        return Err(invalid("invalid_client"));
+    }
+    let code = form.get("code").ok_or_else(|| invalid("invalid_request"))?;
+    let pending = inner
+        .codes
+        .lock()
+        .unwrap()
+        .remove(code)
+        .ok_or_else(|| invalid("invalid_grant"))?;
+    if form.get("redirect_uri") != Some(&pending.redirect_uri) {
+        return Err(invalid("invalid_grant"));
+    }
+    let verifier = form.get("code_verifier").ok_or_else(|| invalid("invalid_grant"))?;
+    if b64url(&Sha256::digest(verifier.as_bytes())) != pending.code_challenge {
+        return Err(invalid("invalid_grant"));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut claims = pending.claims;
+    claims.insert("iss".into(), json!(inner.issuer));
+    claims.insert("sub".into(), json!(pending.subject));
+    claims.insert("aud".into(), json!(CLIENT_ID));
+    claims.insert("iat".into(), json!(now));
+    claims.insert("exp".into(), json!(now + 300));
+    claims.insert("nonce".into(), json!(pending.nonce));
+
+    let mut header = Header::new(jsonwebtoken::Algorithm::ES256);
+    header.kid = Some(KID.into());
+    let id_token = jsonwebtoken::encode(&header, &claims, &inner.encoding_key).unwrap();
+
+    Ok(Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    })))
+}
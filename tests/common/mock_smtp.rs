@@ -0,0 +1,90 @@
+//! A local SMTP sink for email tests.
+//!
+//! Accepts every message without TLS or authentication and hands the DATA of
+//! each one to the test through an `Inbox`.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+pub struct Inbox {
+    messages: mpsc::UnboundedReceiver<String>,
+}
+
+impl Inbox {
+    /// The next message delivered, as its raw headers and body.
+    pub async fn next(&mut self) -> String {
+        tokio::time::timeout(Duration::from_secs(10), self.messages.recv())
+            .await
+            .expect("no email delivered within 10s")
+            .expect("SMTP sink stopped")
+    }
+
+    /// The `token` query parameter of the link in the next message.
+    pub async fn next_token(&mut self) -> String {
+        link_token(&self.next().await)
+    }
+}
+
+/// The `token` query parameter of the link in a message.
+pub fn link_token(message: &str) -> String {
+    // Undo quoted-printable soft line breaks and the escaped '='
+    let message = message.replace("=\n", "").replace("=3D", "=");
+    let start = message.find("?token=").expect("no link in email") + "?token=".len();
+    message[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect()
+}
+
+/// Bind to an ephemeral port and start accepting mail. Returns the port.
+pub async fn start() -> (u16, Inbox) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _ = session(stream, tx).await;
+            });
+        }
+    });
+    (port, Inbox { messages: rx })
+}
+
+async fn session(stream: TcpStream, tx: mpsc::UnboundedSender<String>) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    write.write_all(b"220 mock-smtp ESMTP\r\n").await?;
+
+    while let Some(line) = lines.next_line().await? {
+        let verb = line.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+        match verb.as_str() {
+            "EHLO" | "HELO" => write.write_all(b"250 mock-smtp\r\n").await?,
+            "DATA" => {
+                write.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await?;
+                let mut data = String::new();
+                while let Some(line) = lines.next_line().await? {
+                    if line == "." {
+                        break;
+                    }
+                    // Dot-unstuffing
+                    data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                    data.push('\n');
+                }
+                let _ = tx.send(data);
+                write.write_all(b"250 OK\r\n").await?;
+            }
+            "QUIT" => {
+                write.write_all(b"221 Bye\r\n").await?;
+                break;
+            }
+            _ => write.write_all(b"250 OK\r\n").await?,
+        }
+    }
+    Ok(())
+}
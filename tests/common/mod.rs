@@ -1,3 +1,9 @@
+#![allow(dead_code)]
+
+pub mod mock_captcha;
+pub mod mock_oidc;
+pub mod mock_smtp;
+
 use std::sync::Arc;
 
 use axum::{
@@ -14,11 +20,11 @@ use uuid::Uuid;
 
 use base64::Engine;
 use sha2::{Digest, Sha256};
-use haven_backend::{build_router, config::AppConfig, memory_store::MemoryStore, middleware::UserRateLimiter, AppState};
+use haven_backend::{build_router, config::AppConfig, jwt_keys::{KeyPurpose, Keyring}, memory_store::MemoryStore, middleware::{RateLimiter, UserRateLimiter}, AppState};
 
 /// Solve a PoW challenge by brute-forcing a nonce until SHA-256(challenge + nonce)
 /// has the required number of leading zero bits.
-fn solve_pow(challenge: &str, difficulty: u32) -> String {
+pub fn solve_pow(challenge: &str, difficulty: u32) -> String {
     for nonce in 0u64.. {
         let nonce_str = nonce.to_string();
         let mut hasher = Sha256::new();
@@ -45,6 +51,17 @@ fn solve_pow(challenge: &str, difficulty: u32) -> String {
     unreachable!()
 }
 
+/// Read the session (token family) an access token was issued to from its
+/// payload, without verifying it.
+pub fn token_family(token: &str) -> Uuid {
+    let payload = token.split('.').nth(1).expect("Malformed JWT");
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload)
+        .expect("Malformed JWT payload");
+    let claims: Value = serde_json::from_slice(&bytes).unwrap();
+    Uuid::parse_str(claims["family_id"].as_str().expect("Token has no family_id")).unwrap()
+}
+
 /// Test helper that wraps a fully-built Haven router.
 ///
 /// Each test gets a fresh database via `#[sqlx::test]`, so no data leaks between tests.
@@ -56,7 +73,12 @@ pub struct TestApp {
 impl TestApp {
     /// Build a TestApp from the pool provided by `#[sqlx::test]`.
     pub async fn new(pool: Pool) -> Self {
-        let config = AppConfig {
+        Self::with_config(pool, |_| {}).await
+    }
+
+    /// Like `new`, but lets the test adjust the config before the app is built.
+    pub async fn with_config(pool: Pool, configure: impl FnOnce(&mut AppConfig)) -> Self {
+        let mut config = AppConfig {
             host: "127.0.0.1".into(),
             port: 0,
             database_url: String::new(),
@@ -81,11 +103,22 @@ impl TestApp {
             ws_heartbeat_timeout_secs: 30,
             ws_session_buffer_size: 500,
             ws_session_ttl_secs: 300,
+            // The WS test helpers still connect with `?token=`
+            ws_allow_query_token: true,
+            lazy_member_list_threshold: 1000,
+            ws_drain_timeout_secs: 5,
+            ws_reconnect_jitter_ms: 100,
+            presence_idle_timeout_secs: 300,
+            presence_last_seen: true,
+            signed_prekey_max_age_days: 30,
+            signed_prekey_grace_hours: 72,
+            key_backup_versions_retained: 5,
             max_upload_size_bytes: 10_000_000,
             cdn_enabled: false,
             cdn_base_url: String::new(),
             cdn_presign_expiry_secs: 3600,
             livekit_url: String::new(),
+            livekit_client_url: String::new(),
             livekit_api_key: String::new(),
             livekit_api_secret: String::new(),
             livekit_bundled: false,
@@ -101,7 +134,46 @@ impl TestApp {
             registration_invite_only: false,
             registration_invites_per_user: 3,
             giphy_api_key: String::new(),
+            turnstile_site_key: String::new(),
+            turnstile_secret_key: String::new(),
+            password_login_enabled: true,
+            oidc_issuer: String::new(),
+            oidc_client_id: String::new(),
+            oidc_client_secret: String::new(),
+            oidc_redirect_uri: String::new(),
+            oidc_scopes: "openid profile email".into(),
+            oidc_username_claim: "preferred_username".into(),
+            oidc_auto_provision: true,
+            jwt_algorithm: "EdDSA".into(),
+            jwt_key_rotation_days: 30,
+            email_hash_key: "test-email-hash-key".into(),
+            email_hash_previous_keys: String::new(),
+            pow_base_difficulty: 20,
+            pow_max_difficulty: 24,
+            // Tests share one Redis; keep their registrations from raising the difficulty
+            pow_registration_baseline: u32::MAX,
+            login_pow_failure_threshold: 0,
+            captcha_provider: String::new(),
+            captcha_site_key: String::new(),
+            captcha_secret_key: String::new(),
+            captcha_verify_url: String::new(),
+            login_lockout_free_attempts: u32::MAX,
+            login_lockout_base_secs: 30,
+            login_lockout_max_secs: 3600,
+            security_event_retention_days: 180,
+            smtp_host: String::new(),
+            smtp_port: 587,
+            smtp_username: String::new(),
+            smtp_password: String::new(),
+            smtp_tls: "none".into(),
+            smtp_from: "Haven <noreply@haven.test>".into(),
+            public_url: "http://haven.test".into(),
+            email_verification_expiry_hours: 24,
+            password_reset_expiry_minutes: 30,
+            account_deletion_grace_days: 30,
+            data_export_expiry_hours: 48,
         };
+        configure(&mut config);
 
         std::fs::create_dir_all(&config.storage_dir).ok();
 
@@ -118,19 +190,39 @@ impl TestApp {
             encryption_key: storage_key,
         };
 
+        let db = haven_backend::db::DbPools::from_single(pool);
+        let jwt_keys = Keyring::new(KeyPurpose::AccessToken);
+        let tree_head_keys = Keyring::new(KeyPurpose::TreeHead);
+        let sender_cert_keys = Keyring::new(KeyPurpose::SenderCertificate);
+        for ring in [&jwt_keys, &tree_head_keys, &sender_cert_keys] {
+            haven_backend::jwt_keys::sync(&db, &config, &storage_key, ring)
+                .await
+                .expect("Failed to create signing keys");
+        }
+
         let state = AppState {
-            db: haven_backend::db::DbPools::from_single(pool),
+            db,
             redis: Some(redis),
             config,
             storage_key,
+            jwt_keys,
+            tree_head_keys,
+            sender_cert_keys,
             storage,
             connections: Arc::new(DashMap::new()),
             channel_broadcasts: Arc::new(DashMap::new()),
             pubsub_subscriptions: haven_backend::pubsub::empty_subscriptions(),
             memory: MemoryStore::new(),
+            auth_limiter: RateLimiter::new(1000, 60),
             ws_rate_limiter: UserRateLimiter::new(1000, 10),
             api_rate_limiter: UserRateLimiter::new(1000, 60),
+            key_bundle_limiter: UserRateLimiter::new(5, 3600),
+            data_export_limiter: UserRateLimiter::new(3, 86400),
+            prekeys_low_cooldown: UserRateLimiter::new(1, 3600),
             sessions: Arc::new(DashMap::new()),
+            member_lists: Arc::new(DashMap::new()),
+            instance_id: Uuid::new_v4(),
+            ws_drain: haven_backend::ws::WsDrain::default(),
         };
 
         TestApp { state }
@@ -142,6 +234,10 @@ impl TestApp {
     }
 
     /// Get a router suitable for `axum::serve` (WS integration tests).
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
     pub fn router_clone(&self) -> Router {
         build_router(self.state.clone())
     }
@@ -155,12 +251,27 @@ impl TestApp {
         uri: &str,
         token: Option<&str>,
         body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        self.request_with_headers(method, uri, token, body, &[]).await
+    }
+
+    /// Like `request`, with extra headers (e.g. a User-Agent).
+    pub async fn request_with_headers(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+        headers: &[(&str, &str)],
     ) -> (StatusCode, Value) {
         let body_bytes = body
             .map(|v| serde_json::to_vec(&v).unwrap())
             .unwrap_or_default();
 
         let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
 
         if let Some(t) = token {
             builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", t));
@@ -5,6 +5,7 @@ use futures::{SinkExt, StreamExt};
 use serde_json::{json, Value};
 use haven_backend::db::Pool;
 use tokio::net::TcpListener;
+use axum::http::{Method, StatusCode};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
 use common::TestApp;
@@ -56,7 +57,7 @@ async fn ws_send(
     >,
     msg: Value,
 ) {
-    sink.send(Message::Text(msg.to_string().into()))
+    sink.send(Message::Text(msg.to_string()))
         .await
         .unwrap();
 }
@@ -123,6 +124,67 @@ async fn ws_ping_returns_pong(pool: Pool) {
     assert_eq!(msg["type"].as_str(), Some("Pong"));
 }
 
+// ─── Connect Tickets ────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_connect_with_ticket_sends_hello(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("ws_ticket").await;
+    let addr = start_server(&app).await;
+
+    let (status, body) = app
+        .request(Method::POST, "/api/v1/ws/ticket", Some(&token), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["expires_in"].as_u64(), Some(30));
+    let ticket = body["ticket"].as_str().unwrap();
+
+    let url = format!("ws://{}/api/v1/ws?ticket={}", addr, ticket);
+    let (ws_stream, _) = connect_async(&url).await.expect("WS connect failed");
+    let (_sink, mut stream) = ws_stream.split();
+
+    let hello = ws_recv(&mut stream).await;
+    assert_eq!(hello["type"].as_str(), Some("Hello"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_ticket_is_single_use(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("ws_ticket_reuse").await;
+    let addr = start_server(&app).await;
+
+    let (_, body) = app
+        .request(Method::POST, "/api/v1/ws/ticket", Some(&token), None)
+        .await;
+    let url = format!("ws://{}/api/v1/ws?ticket={}", addr, body["ticket"].as_str().unwrap());
+
+    let _first = connect_async(&url).await.expect("WS connect failed");
+    assert!(connect_async(&url).await.is_err());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_invalid_ticket_rejected(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let addr = start_server(&app).await;
+
+    let url = format!("ws://{}/api/v1/ws?ticket=bogus", addr);
+    assert!(connect_async(&url).await.is_err());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_query_token_rejected_when_disabled(pool: Pool) {
+    let app = TestApp::with_config(pool, |c| c.ws_allow_query_token = false).await;
+    let (token, _) = app.register_user("ws_query_token").await;
+    let addr = start_server(&app).await;
+
+    let url = format!("ws://{}/api/v1/ws?token={}", addr, token);
+    assert!(connect_async(&url).await.is_err());
+}
+
 // ─── Subscribe / Subscribed ─────────────────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
@@ -146,6 +208,109 @@ async fn ws_subscribe_returns_subscribed(pool: Pool) {
     assert_eq!(msg["payload"]["channel_id"].as_str().unwrap(), channel_id.to_string());
 }
 
+// ─── Lazy Member List ───────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_member_list_sync_then_insert(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("ws_ml_owner").await;
+    let (token_b, _) = app.register_user("ws_ml_joiner").await;
+    let server_id = app.create_server(&token_a, "Member List").await;
+    let addr = start_server(&app).await;
+
+    let (mut sink, mut stream) = ws_connect(&addr, &token_a).await;
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "SubscribeMemberList",
+            "payload": {"server_id": server_id, "group_by": "status", "range": [0, 50]}
+        }),
+    )
+    .await;
+
+    let sync = ws_recv_matching(&mut stream, |v| v["type"] == "MemberListUpdate").await;
+    let op = &sync["payload"]["ops"][0];
+    assert_eq!(op["op"], "sync");
+    assert_eq!(op["items"][0]["kind"], "group");
+    assert_eq!(op["items"][0]["id"], "online");
+    assert_eq!(op["items"][1]["member"]["username"], "ws_ml_owner");
+    assert_eq!(sync["payload"]["member_count"], 1);
+
+    // A new (offline) member joins — only the new rows are pushed
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+
+    let update = ws_recv_matching(&mut stream, |v| v["type"] == "MemberListUpdate").await;
+    assert_eq!(update["payload"]["member_count"], 2);
+    let ops = update["payload"]["ops"].as_array().unwrap();
+    assert!(ops.iter().all(|o| o["op"] == "insert"));
+    assert!(ops
+        .iter()
+        .any(|o| o["item"]["member"]["username"] == "ws_ml_joiner" && o["item"]["status"] == "offline"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_member_list_update_crosses_instances(pool: Pool) {
+    let api_app = TestApp::new(pool.clone()).await;
+    let ws_app = TestApp::new(pool).await;
+    let (token_a, _) = api_app.register_user("ws_ml_relay_owner").await;
+    let (token_b, _) = api_app.register_user("ws_ml_relay_joiner").await;
+    let server_id = api_app.create_server(&token_a, "Relayed List").await;
+    haven_backend::pubsub::start_subscriber(ws_app.state().clone());
+    let addr = start_server(&ws_app).await;
+
+    let (mut sink, mut stream) = ws_connect(&addr, &token_a).await;
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "SubscribeMemberList",
+            "payload": {"server_id": server_id, "group_by": "status", "range": [0, 50]}
+        }),
+    )
+    .await;
+    ws_recv_matching(&mut stream, |v| v["type"] == "MemberListUpdate").await;
+    // Let the subscriber connect to Redis
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    // The join lands on the other instance, which has no cached list
+    api_app.invite_and_join(&token_a, &token_b, server_id).await;
+
+    let update = ws_recv_matching(&mut stream, |v| v["type"] == "MemberListUpdate").await;
+    assert_eq!(update["payload"]["member_count"], 2);
+    assert!(update["payload"]["ops"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|o| o["item"]["member"]["username"] == "ws_ml_relay_joiner"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_member_list_requires_membership(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_owner, _) = app.register_user("ws_ml_own").await;
+    let (token_other, _) = app.register_user("ws_ml_other").await;
+    let server_id = app.create_server(&token_owner, "Private List").await;
+    let addr = start_server(&app).await;
+
+    let (mut sink, mut stream) = ws_connect(&addr, &token_other).await;
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "SubscribeMemberList",
+            "payload": {"server_id": server_id, "range": [0, 50]}
+        }),
+    )
+    .await;
+
+    let msg = ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("Error")).await;
+    assert!(msg["payload"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Not a member"));
+}
+
 // ─── Subscribe to unauthorized channel ──────────────────
 
 #[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
@@ -431,3 +596,564 @@ async fn ws_send_message_invalid_base64(pool: Pool) {
         .unwrap()
         .contains("Invalid sender_token"));
 }
+
+// ─── Graceful Drain ─────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_drain_hands_session_to_other_instance(pool: Pool) {
+    // Two instances sharing one database and Redis, like a rolling deploy
+    let old_app = TestApp::new(pool.clone()).await;
+    let new_app = TestApp::new(pool).await;
+    let (token, _) = old_app.register_user("ws_drain").await;
+    let server_id = old_app.create_server(&token, "Drain Server").await;
+    let channel_id = old_app.create_channel(&token, server_id, "general").await;
+    let old_addr = start_server(&old_app).await;
+    let new_addr = start_server(&new_app).await;
+
+    let (mut sink, mut stream) = ws_connect(&old_addr, &token).await;
+    let hello = ws_recv(&mut stream).await;
+    let session_id = hello["payload"]["session_id"].as_str().unwrap().to_string();
+
+    ws_send(
+        &mut sink,
+        json!({"type": "Subscribe", "payload": {"channel_id": channel_id}}),
+    )
+    .await;
+    ws_recv_matching(&mut stream, |v| v["type"] == "Subscribed").await;
+    old_app.send_message(&token, channel_id).await;
+    ws_recv_matching(&mut stream, |v| v["type"] == "NewMessage").await;
+
+    let state = old_app.state().clone();
+    let drain = tokio::spawn(async move { haven_backend::ws::drain(&state).await });
+
+    let reconnect = ws_recv_matching(&mut stream, |v| v["type"] == "Reconnect").await;
+    assert!(reconnect["payload"]["delay_ms"].as_u64().unwrap() <= 100);
+
+    // The draining instance refuses new upgrades
+    let url = format!("ws://{}/api/v1/ws?token={}", old_addr, token);
+    match connect_async(&url).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(resp)) => {
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        other => panic!("Expected 503 from draining instance, got {:?}", other.map(|_| ())),
+    }
+
+    sink.close().await.unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(5), drain)
+        .await
+        .expect("drain did not finish")
+        .unwrap();
+
+    // Events sent while the client is between instances still reach the buffer
+    old_app.send_message(&token, channel_id).await;
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    // Resume on the other instance replays the buffered events
+    let (mut sink, mut stream) = ws_connect(&new_addr, &token).await;
+    ws_recv(&mut stream).await; // Hello
+    ws_send(
+        &mut sink,
+        json!({"type": "Resume", "payload": {"session_id": session_id}}),
+    )
+    .await;
+    let replayed = ws_recv(&mut stream).await;
+    assert_eq!(replayed["type"].as_str(), Some("NewMessage"));
+    let resumed = ws_recv_matching(&mut stream, |v| v["type"] == "Resumed").await;
+    assert!(resumed["payload"]["replayed_count"].as_u64().unwrap() >= 2);
+}
+
+// ─── DM / Friend Presence ───────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_dm_typing_reaches_unsubscribed_participant(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("ws_dm_type_a").await;
+    let (token_b, user_b) = app.register_user("ws_dm_type_b").await;
+    app.make_friends(&token_a, &token_b, "ws_dm_type_b").await;
+
+    let (status, dm) = app
+        .request(
+            Method::POST,
+            "/api/v1/dm",
+            Some(&token_a),
+            Some(json!({ "target_user_id": user_b, "encrypted_meta": B64.encode(b"dm-meta") })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let dm_id = dm["id"].as_str().unwrap().to_string();
+    let addr = start_server(&app).await;
+
+    // B is connected but never subscribes to the DM channel
+    let (_sink_b, mut stream_b) = ws_connect(&addr, &token_b).await;
+    ws_recv(&mut stream_b).await; // Hello
+
+    let (mut sink_a, _stream_a) = ws_connect(&addr, &token_a).await;
+    ws_send(&mut sink_a, json!({"type": "Typing", "payload": {"channel_id": dm_id}})).await;
+
+    let msg = ws_recv_matching(&mut stream_b, |v| v["type"] == "UserTyping").await;
+    assert_eq!(msg["payload"]["channel_id"].as_str(), Some(dm_id.as_str()));
+    assert_eq!(msg["payload"]["username"].as_str(), Some("ws_dm_type_a"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_friends_only_presence_hidden_from_non_friends(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_pp_a").await;
+    let (token_f, _) = app.register_user("ws_pp_friend").await;
+    let (token_n, _) = app.register_user("ws_pp_stranger").await;
+    app.make_friends(&token_a, &token_f, "ws_pp_friend").await;
+
+    // The stranger shares a DM with A
+    app.request(
+        Method::POST,
+        "/api/v1/dm",
+        Some(&token_n),
+        Some(json!({ "target_user_id": user_a, "encrypted_meta": B64.encode(b"dm-meta") })),
+    )
+    .await;
+
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/presence-privacy",
+            Some(&token_a),
+            Some(json!({ "presence_privacy": "friends_only" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let addr = start_server(&app).await;
+    let (_sink_f, mut stream_f) = ws_connect(&addr, &token_f).await;
+    ws_recv(&mut stream_f).await; // Hello
+    let (_sink_n, mut stream_n) = ws_connect(&addr, &token_n).await;
+    ws_recv(&mut stream_n).await; // Hello
+
+    let (_sink_a, _stream_a) = ws_connect(&addr, &token_a).await;
+    let a = user_a.to_string();
+
+    let to_friend = ws_recv_matching(&mut stream_f, |v| {
+        v["type"] == "PresenceUpdate" && v["payload"]["user_id"] == a.as_str()
+    })
+    .await;
+    assert_eq!(to_friend["payload"]["status"].as_str(), Some("online"));
+
+    let to_stranger = ws_recv_matching(&mut stream_n, |v| {
+        v["type"] == "PresenceUpdate" && v["payload"]["user_id"] == a.as_str()
+    })
+    .await;
+    assert_eq!(to_stranger["payload"]["status"].as_str(), Some("offline"));
+
+    let uri = format!("/api/v1/presence?user_ids={}", user_a);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_f), None).await;
+    assert_eq!(value[0]["status"].as_str(), Some("online"));
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_n), None).await;
+    assert_eq!(value[0]["status"].as_str(), Some("offline"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_custom_status_reaches_friends(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_cs_a").await;
+    let (token_f, _) = app.register_user("ws_cs_friend").await;
+    app.make_friends(&token_a, &token_f, "ws_cs_friend").await;
+    let addr = start_server(&app).await;
+
+    let (_sink_f, mut stream_f) = ws_connect(&addr, &token_f).await;
+    ws_recv(&mut stream_f).await; // Hello
+
+    let (status, _) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/profile",
+            Some(&token_a),
+            Some(json!({ "custom_status": "heads down", "custom_status_emoji": "🎧" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let msg = ws_recv_matching(&mut stream_f, |v| v["type"] == "CustomStatusUpdate").await;
+    assert_eq!(msg["payload"]["user_id"].as_str(), Some(user_a.to_string().as_str()));
+    assert_eq!(msg["payload"]["custom_status"].as_str(), Some("heads down"));
+    assert_eq!(msg["payload"]["custom_status_emoji"].as_str(), Some("🎧"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_heartbeat_idle_detection(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_idle_a").await;
+    let (token_f, _) = app.register_user("ws_idle_friend").await;
+    app.make_friends(&token_a, &token_f, "ws_idle_friend").await;
+    let addr = start_server(&app).await;
+
+    let (_sink_f, mut stream_f) = ws_connect(&addr, &token_f).await;
+    ws_recv(&mut stream_f).await; // Hello
+    let (mut sink_a, mut stream_a) = ws_connect(&addr, &token_a).await;
+    ws_recv(&mut stream_a).await; // Hello
+
+    let a = user_a.to_string();
+    let is_a = |v: &Value| v["type"] == "PresenceUpdate" && v["payload"]["user_id"] == a.as_str();
+    let online = ws_recv_matching(&mut stream_f, is_a).await;
+    assert_eq!(online["payload"]["status"].as_str(), Some("online"));
+
+    // Past the 300s idle timeout in the test config
+    ws_send(&mut sink_a, json!({"type": "Ping", "payload": {"idle_ms": 400_000}})).await;
+    let idle = ws_recv_matching(&mut stream_f, is_a).await;
+    assert_eq!(idle["payload"]["status"].as_str(), Some("idle"));
+    assert!(idle["payload"]["since"].is_string());
+
+    let uri = format!("/api/v1/presence?user_ids={}", user_a);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_f), None).await;
+    assert_eq!(value[0]["status"].as_str(), Some("idle"));
+
+    ws_send(&mut sink_a, json!({"type": "Ping", "payload": {"idle_ms": 0}})).await;
+    let back = ws_recv_matching(&mut stream_f, is_a).await;
+    assert_eq!(back["payload"]["status"].as_str(), Some("online"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_heartbeat_does_not_override_chosen_status(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_dnd_a").await;
+    let addr = start_server(&app).await;
+
+    let (mut sink_a, mut stream_a) = ws_connect(&addr, &token_a).await;
+    ws_recv(&mut stream_a).await; // Hello
+    ws_send(&mut sink_a, json!({"type": "SetStatus", "payload": {"status": "dnd"}})).await;
+    ws_send(&mut sink_a, json!({"type": "Ping", "payload": {"idle_ms": 400_000}})).await;
+    ws_recv_matching(&mut stream_a, |v| v["type"] == "Pong").await;
+
+    let uri = format!("/api/v1/presence?user_ids={}", user_a);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(value[0]["status"].as_str(), Some("dnd"));
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_last_seen_recorded_on_disconnect(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_seen_a").await;
+    let (token_f, _) = app.register_user("ws_seen_friend").await;
+    app.make_friends(&token_a, &token_f, "ws_seen_friend").await;
+    let addr = start_server(&app).await;
+
+    let (_sink_f, mut stream_f) = ws_connect(&addr, &token_f).await;
+    ws_recv(&mut stream_f).await; // Hello
+
+    let a = user_a.to_string();
+    let is_a = |v: &Value| v["type"] == "PresenceUpdate" && v["payload"]["user_id"] == a.as_str();
+    let (mut sink_a, _stream_a) = ws_connect(&addr, &token_a).await;
+    ws_recv_matching(&mut stream_f, is_a).await; // online
+    sink_a.close().await.unwrap();
+
+    let offline = ws_recv_matching(&mut stream_f, |v| {
+        is_a(v) && v["payload"]["status"] == "offline"
+    })
+    .await;
+    assert!(offline["payload"]["last_seen"].is_string());
+
+    let uri = format!("/api/v1/presence?user_ids={}", user_a);
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_f), None).await;
+    assert_eq!(value[0]["status"].as_str(), Some("offline"));
+    assert!(value[0]["last_seen"].is_string());
+
+    let (status, body) = app
+        .request(
+            Method::PUT,
+            "/api/v1/users/presence-privacy",
+            Some(&token_a),
+            Some(json!({ "hide_last_seen": true })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["hide_last_seen"].as_bool(), Some(true));
+
+    let (_, value) = app.request(Method::GET, &uri, Some(&token_f), None).await;
+    assert!(value[0].get("last_seen").is_none());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_device_added_reaches_friends(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_dev_a").await;
+    let (token_f, _) = app.register_user("ws_dev_friend").await;
+    app.make_friends(&token_a, &token_f, "ws_dev_friend").await;
+    let addr = start_server(&app).await;
+
+    let (_sink_f, mut stream_f) = ws_connect(&addr, &token_f).await;
+    ws_recv(&mut stream_f).await; // Hello
+
+    let body = json!({
+        "identity_key": B64.encode([7u8; 32]),
+        "signed_prekey": B64.encode([8u8; 32]),
+        "signed_prekey_signature": B64.encode([9u8; 64])
+    });
+    let (status, device) = app
+        .request(Method::POST, "/api/v1/keys/devices", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let msg = ws_recv_matching(&mut stream_f, |v| v["type"] == "DeviceAdded").await;
+    assert_eq!(msg["payload"]["user_id"].as_str(), Some(user_a.to_string().as_str()));
+    assert_eq!(msg["payload"]["device_id"], device["id"]);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_identity_key_changed_reaches_dm_peers(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, user_a) = app.register_user("ws_ik_a").await;
+    let (token_b, user_b) = app.register_user("ws_ik_b").await;
+    app.make_friends(&token_a, &token_b, "ws_ik_b").await;
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/dm",
+            Some(&token_a),
+            Some(json!({ "target_user_id": user_b, "encrypted_meta": B64.encode(b"dm-meta") })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let addr = start_server(&app).await;
+
+    let (_sink_b, mut stream_b) = ws_connect(&addr, &token_b).await;
+    ws_recv(&mut stream_b).await; // Hello
+
+    let new_key = B64.encode([21u8; 32]);
+    let body = json!({
+        "identity_key": new_key,
+        "signed_prekey": B64.encode([22u8; 32]),
+        "signed_prekey_signature": B64.encode([23u8; 64])
+    });
+    let (status, _) = app
+        .request(Method::PUT, "/api/v1/keys/identity", Some(&token_a), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let msg = ws_recv_matching(&mut stream_b, |v| v["type"] == "IdentityKeyChanged").await;
+    assert_eq!(msg["payload"]["user_id"].as_str(), Some(user_a.to_string().as_str()));
+    assert_eq!(msg["payload"]["identity_key"].as_str(), Some(new_key.as_str()));
+    assert!(msg["payload"]["log_seq"].is_i64());
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_kick_requires_sender_key_rotation(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("ws_rk_owner").await;
+    let (token_b, user_b) = app.register_user("ws_rk_kicked").await;
+    let (token_c, _) = app.register_user("ws_rk_stays").await;
+    let server_id = app.create_server(&token_a, "Rekey").await;
+    app.invite_and_join(&token_a, &token_b, server_id).await;
+    app.invite_and_join(&token_a, &token_c, server_id).await;
+    let channel_id = app.create_channel(&token_a, server_id, "secret").await;
+
+    // A distributes a sender key that B has not fetched yet
+    let uri = format!("/api/v1/channels/{}/sender-keys", channel_id);
+    let body = json!({
+        "distributions": [{
+            "to_user_id": user_b,
+            "distribution_id": uuid::Uuid::new_v4(),
+            "encrypted_skdm": B64.encode(b"skdm-for-b")
+        }]
+    });
+    let (status, _) = app.request(Method::POST, &uri, Some(&token_a), Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let addr = start_server(&app).await;
+    let (_sink_c, mut stream_c) = ws_connect(&addr, &token_c).await;
+    ws_recv(&mut stream_c).await; // Hello
+
+    let kick_uri = format!("/api/v1/servers/{}/members/{}", server_id, user_b);
+    let (status, _) = app.request(Method::DELETE, &kick_uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let msg = ws_recv_matching(&mut stream_c, |v| {
+        v["type"] == "SenderKeyRotationRequired"
+            && v["payload"]["channel_id"].as_str() == Some(channel_id.to_string().as_str())
+    })
+    .await;
+    assert_eq!(msg["payload"]["reason"], "kicked");
+
+    let pending: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sender_key_distributions WHERE channel_id = $1 AND to_user_id = $2",
+    )
+    .bind(channel_id)
+    .bind(user_b)
+    .fetch_one(app.state().db.read())
+    .await
+    .unwrap();
+    assert_eq!(pending, 0);
+}
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_prekeys_low_notifies_owner(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("ws_pk_requester").await;
+    let (token_b, user_b) = app.register_user("ws_pk_owner").await;
+    let addr = start_server(&app).await;
+
+    let prekeys: Vec<String> = (0..10u8).map(|i| B64.encode([i; 32])).collect();
+    let (status, _) = app
+        .request(
+            Method::POST,
+            "/api/v1/keys/prekeys",
+            Some(&token_b),
+            Some(json!({ "prekeys": prekeys })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_sink_b, mut stream_b) = ws_connect(&addr, &token_b).await;
+    ws_recv(&mut stream_b).await; // Hello
+
+    let uri = format!("/api/v1/users/{}/keys", user_b);
+    let (status, bundle) = app.request(Method::GET, &uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let msg = ws_recv_matching(&mut stream_b, |v| v["type"] == "PrekeysLow").await;
+    assert_eq!(msg["payload"]["remaining"].as_i64(), Some(9));
+    assert_eq!(msg["payload"]["device_id"], bundle["devices"][0]["device_id"]);
+}
+
+// ─── Sealed Sender ──────────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_sealed_message_edit_requires_capability(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token, _) = app.register_user("ws_sealed").await;
+    let server_id = app.create_server(&token, "Sealed").await;
+    let channel_id = app.create_channel(&token, server_id, "general").await;
+    let addr = start_server(&app).await;
+
+    let (mut sink, mut stream) = ws_connect(&addr, &token).await;
+    ws_send(
+        &mut sink,
+        json!({"type": "Subscribe", "payload": {"channel_id": channel_id}}),
+    )
+    .await;
+    ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("Subscribed")).await;
+
+    let capability = b"per-message capability token";
+    let capability_hash = haven_backend::sealed_sender::capability_hash(capability);
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "SendMessage",
+            "payload": {
+                "channel_id": channel_id,
+                "sender_token": B64.encode(b"certificate inside"),
+                "encrypted_body": B64.encode(b"sealed body"),
+                "expires_at": null,
+                "attachment_ids": null,
+                "reply_to_id": null,
+                "capability_hash": B64.encode(capability_hash),
+            }
+        }),
+    )
+    .await;
+    let ack = ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("MessageAck")).await;
+    let message_id = ack["payload"]["message_id"].as_str().unwrap().to_string();
+
+    // No sender is stored, so the sender_id path can't edit it
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "EditMessage",
+            "payload": { "message_id": message_id, "encrypted_body": B64.encode(b"edit") }
+        }),
+    )
+    .await;
+    let err = ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("Error")).await;
+    assert!(err["payload"]["message"].as_str().unwrap().contains("Cannot edit"));
+
+    // Neither can a wrong capability
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "EditMessage",
+            "payload": {
+                "message_id": message_id,
+                "encrypted_body": B64.encode(b"edit"),
+                "capability": B64.encode(b"wrong token"),
+            }
+        }),
+    )
+    .await;
+    ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("Error")).await;
+
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "EditMessage",
+            "payload": {
+                "message_id": message_id,
+                "encrypted_body": B64.encode(b"edited"),
+                "capability": B64.encode(capability),
+            }
+        }),
+    )
+    .await;
+    let edited = ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("MessageEdited")).await;
+    assert_eq!(edited["payload"]["message_id"].as_str(), Some(message_id.as_str()));
+
+    ws_send(
+        &mut sink,
+        json!({
+            "type": "DeleteMessage",
+            "payload": { "message_id": message_id, "capability": B64.encode(capability) }
+        }),
+    )
+    .await;
+    let deleted = ws_recv_matching(&mut stream, |v| v["type"].as_str() == Some("MessageDeleted")).await;
+    assert_eq!(deleted["payload"]["message_id"].as_str(), Some(message_id.as_str()));
+}
+
+// ─── Session Revocation ─────────────────────────────────
+
+#[cfg_attr(feature = "postgres", sqlx::test(migrations = "./migrations"))]
+#[cfg_attr(feature = "sqlite", sqlx::test(migrations = "./migrations_sqlite"))]
+async fn ws_revoked_session_is_disconnected(pool: Pool) {
+    let app = TestApp::new(pool).await;
+    let (token_a, _) = app.register_user("ws_revoke").await;
+    let (token_b, _, _) = app.login_user("ws_revoke").await;
+    let family_b = common::token_family(&token_b);
+    let addr = start_server(&app).await;
+
+    let (mut sink_a, mut stream_a) = ws_connect(&addr, &token_a).await;
+    let (_sink_b, mut stream_b) = ws_connect(&addr, &token_b).await;
+    ws_recv_matching(&mut stream_a, |v| v["type"] == "Hello").await;
+    ws_recv_matching(&mut stream_b, |v| v["type"] == "Hello").await;
+
+    let uri = format!("/api/v1/auth/sessions/{}", family_b);
+    let (status, _) = app.request(Method::DELETE, &uri, Some(&token_a), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Session B is told why and then closed
+    let msg = ws_recv_matching(&mut stream_b, |v| v["type"] == "SessionRevoked").await;
+    assert_eq!(msg["payload"]["family_id"], family_b.to_string());
+    let close = tokio::time::timeout(std::time::Duration::from_secs(5), stream_b.next())
+        .await
+        .expect("WS close timed out");
+    match close {
+        Some(Ok(Message::Close(Some(frame)))) => assert_eq!(u16::from(frame.code), 4001),
+        other => panic!("Expected close frame, got {:?}", other),
+    }
+
+    // Session A stays connected
+    ws_send(&mut sink_a, json!({"type": "Ping"})).await;
+    let msg = ws_recv_matching(&mut stream_a, |v| v["type"] == "Pong" || v["type"] == "SessionRevoked").await;
+    assert_eq!(msg["type"], "Pong");
+
+    // And the revoked token can't open a new socket
+    let url = format!("ws://{}/api/v1/ws?token={}", addr, token_b);
+    assert!(connect_async(&url).await.is_err());
+}